@@ -0,0 +1,934 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, File},
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use color_eyre::{
+    eyre::{bail, eyre, Context},
+    Report, Result, Section,
+};
+use winlock::{
+    backend::{self, BackendUnavailable, Launcher, Restart},
+    branch::{self, BranchName},
+    config::{Config, Profile},
+    devcontainer,
+    events::Events,
+    forge::{Issue, IssueReference, Repository},
+    multiplexer::MultiplexerKind,
+    notify::Notification,
+    prompt::PromptTemplate,
+    remote::Remote,
+    session::{AlreadyRunning, Session, Sessions},
+    vcs::VcsKind,
+    workspace::{
+        self, ByteSize, Cancel, CopyFailed, CopyReport, CopyTooLarge, Fingerprint,
+        InsufficientSpace, ProjectModified,
+    },
+    Agent, AgentSessionStatus, CreateOptions, CreationReport, RunOptions, RunOutcome,
+    UnsuitableProject,
+};
+
+use crate::{
+    daemon, editor, forge,
+    output::{self, narrate},
+    queue, session,
+};
+
+/// How many commits the project may move on from a session's base
+/// before resuming the session warns that it's stale.
+const STALE_COMMITS: u64 = 10;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// The branch the agent works on; derived from the issue when using --from-issue,
+    /// or else from the prompt when using --prompt, after confirming it.
+    #[arg(required_unless_present_any = ["from_issue", "prompt"])]
+    branch: Option<String>,
+
+    /// Convert the branch argument into a valid branch name,
+    /// for example `"Fix: crash on load"` into `fix-crash-on-load`.
+    #[arg(long)]
+    sanitize: bool,
+
+    /// The initial prompt for the agent.
+    #[arg(long, conflicts_with = "prompt_template", group = "initial_prompt")]
+    prompt: Option<String>,
+
+    /// Start from an issue: its text becomes the initial prompt.
+    ///
+    /// Accepts the URL of a GitHub, GitLab, or Gitea issue,
+    /// or the number of an issue in the repository of the project's origin remote.
+    #[arg(
+        long,
+        value_name = "URL|NUMBER",
+        conflicts_with_all = ["prompt", "prompt_template"],
+        group = "initial_prompt"
+    )]
+    from_issue: Option<IssueReference>,
+
+    /// Render the initial prompt from a template in the project's `.anna/prompts`
+    /// directory or the `prompts` config directory.
+    #[arg(long, value_name = "NAME", group = "initial_prompt")]
+    prompt_template: Option<String>,
+
+    /// Set a variable used by the prompt template.
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var, requires = "prompt_template")]
+    vars: Vec<(String, String)>,
+
+    /// The backend profile to use; defaults to the configured profile.
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Once the backend exits successfully, run another in the same workspace, such as
+    /// one reviewing its work: a configured profile, or else a command written as
+    /// a profile's is, such as `"aider --yes --message {prompt}"`. May be repeated for
+    /// a pipeline of several, which stops at the first that fails.
+    ///
+    /// Each starts a new conversation with the same prompt, and verification runs
+    /// after the last. Every run's exit status is recorded on the session.
+    #[arg(long, value_name = "PROFILE|COMMAND")]
+    then: Vec<String>,
+
+    /// Commit any changes the backend leaves uncommitted when it exits.
+    #[arg(long)]
+    auto_commit: bool,
+
+    /// The model the backend uses, such as `sonnet`, `opus`, or a full model ID.
+    ///
+    /// Remembered for the session, so resuming it keeps using the same model.
+    /// Defaults to the model last used in the session, or else the profile's.
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Start a new backend conversation instead of continuing the session's previous one.
+    #[arg(long)]
+    new_conversation: bool,
+
+    /// Don't record a transcript of the backend run.
+    #[arg(long)]
+    no_transcript: bool,
+
+    /// Give the backend the terminal directly instead of running it under a pseudo-terminal.
+    /// Implies --no-transcript.
+    #[arg(long)]
+    no_pty: bool,
+
+    /// Run the backend in a window named after the session of the configured terminal
+    /// multiplexer, tmux unless set otherwise, which can be detached from and reattached to,
+    /// rather than in this terminal. Outside a multiplexer the window is opened in its
+    /// `anna` session. Transcripts aren't recorded in multiplexers.
+    #[arg(long, conflicts_with = "no_network")]
+    mux: bool,
+
+    /// Run the backend in tmux, as with --mux, whichever multiplexer is configured.
+    #[arg(long, conflicts_with_all = ["no_network", "mux"])]
+    tmux: bool,
+
+    /// If a backend is already running in the session, attach to it as
+    /// `anna session attach` does, rather than failing; otherwise run one as usual.
+    #[arg(long)]
+    attach: bool,
+
+    /// Open the workspace in your editor before the backend starts, as `anna session code` does.
+    #[arg(long)]
+    code: bool,
+
+    /// Run the backend and the verify command in the project's development container,
+    /// as configured by `.devcontainer/devcontainer.json`, with the workspace mounted.
+    /// Starts the container with the Dev Container CLI if it isn't running.
+    #[arg(long, conflicts_with_all = ["no_network", "remote"])]
+    devcontainer: bool,
+
+    /// A shell command to run in the workspace after the backend exits, such as `cargo test`;
+    /// whether it passed is shown by `anna session list`. Defaults to the configured command.
+    #[arg(long, value_name = "COMMAND")]
+    verify: Option<String>,
+
+    /// Relaunch the backend if it exits unsuccessfully, such as on a crash or a rate limit:
+    /// `on-failure` relaunches it indefinitely and `on-failure:N` at most N times,
+    /// waiting longer before each attempt. It isn't relaunched after being interrupted.
+    #[arg(long, value_name = "POLICY", default_value = "no")]
+    restart: Restart,
+
+    /// Cut the backend off from the network, except for the hosts its profile
+    /// and the `[network]` config allow. Only supported on Linux.
+    #[arg(long)]
+    no_network: bool,
+
+    /// Check that nothing in the project changed while the agent worked on its copy,
+    /// failing if anything did. Every file in the project is checked, ignored ones included.
+    #[arg(long)]
+    paranoid: bool,
+
+    /// Work on a project that isn't under version control.
+    #[arg(long)]
+    no_vcs: bool,
+
+    /// Copy submodules as they are in the project rather than checking out
+    /// those that aren't checked out there, which may need the network.
+    #[arg(long)]
+    no_submodules: bool,
+
+    /// Copy the pointers Git LFS committed rather than the files they point to,
+    /// pulling only the project's configured patterns; pull others in the
+    /// workspace with `git lfs pull --include`.
+    #[arg(long)]
+    lfs_pointers: bool,
+
+    /// Write events describing the agent's progress as newline-delimited JSON,
+    /// for editor plugins and other integrations, to stdout or file descriptor FD.
+    ///
+    /// Under a pseudo-terminal the backend's output also goes to stdout,
+    /// so pass a separate descriptor, such as `--json-events=3 3>events.json`.
+    #[arg(long, value_name = "FD", num_args = 0..=1, require_equals = true, default_missing_value = "1")]
+    json_events: Option<u32>,
+
+    /// Create the workspace even if there doesn't seem to be enough free disk space
+    /// to copy the project.
+    #[arg(long)]
+    force: bool,
+
+    /// Copy the project without asking, however large it is; otherwise copies larger
+    /// than the configured `confirm_copy_size` are confirmed first.
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Skip files larger than SIZE, such as "500 MiB", when copying the project,
+    /// listing them rather than copying them. Skipped files git tracks don't show as
+    /// deleted in the workspace, so they aren't committed as such.
+    #[arg(long, value_name = "SIZE")]
+    max_file_size: Option<ByteSize>,
+
+    /// When resuming a session, first rebase its branch onto the revision
+    /// now checked out in the project.
+    #[arg(long)]
+    rebase: bool,
+
+    /// The revision a new branch starts from; `default` selects the project's default branch.
+    ///
+    /// Defaults to the revision currently checked out in the project.
+    #[arg(long, value_name = "REV")]
+    base: Option<String>,
+
+    /// Check out the branch where it already exists, locally or on a remote as of the
+    /// project's last fetch, rather than creating it, to continue work started elsewhere.
+    #[arg(long, conflicts_with = "base")]
+    existing: bool,
+
+    /// Also work on another project in the same session, in a linked workspace
+    /// with the same branch, for changes spanning repositories. May be repeated.
+    ///
+    /// The backend runs in the project's workspace, and finds linked ones
+    /// in the `ANNA_LINKED_WORKSPACES` environment variable.
+    #[arg(long, value_name = "PATH")]
+    also: Vec<PathBuf>,
+
+    /// Run the backend on another machine over SSH, such as a build server.
+    ///
+    /// The workspace is mirrored to `~/.anna/workspaces` there with rsync before the
+    /// backend runs and brought back after; setup and verify commands run there too.
+    /// Files git ignores, such as build output, aren't mirrored.
+    /// Only applies when the session is created.
+    #[arg(long, value_name = "USER@HOST", conflicts_with_all = ["no_network", "also"])]
+    remote: Option<Remote>,
+
+    /// Put the session in a group of related sessions, such as the agents working on
+    /// parts of one larger change, to list and act on them together with `anna session group`.
+    /// Moves a resumed session into the group.
+    #[arg(long, value_name = "NAME")]
+    group: Option<String>,
+
+    /// Queue the run rather than starting it, to be run with the rest of the queue
+    /// by `anna queue run`, which runs it as this command line without `--queue`.
+    ///
+    /// Queued runs need a prompt, from `--prompt`, `--prompt-template`, or `--from-issue`.
+    /// They have no terminal to ask questions on, so pass `--yes` to copy large projects,
+    /// and a branch unless the one named for the prompt will do. They run with the
+    /// environment of `anna queue run`, not this one's.
+    #[arg(
+        long,
+        requires = "initial_prompt",
+        conflicts_with_all = ["attach", "code", "json_events"]
+    )]
+    queue: bool,
+
+    /// Have the daemon run the agent detached, as this command line without
+    /// `--via-daemon`, rather than running it here, with this environment; see
+    /// `anna daemon`. Its output goes to a log, and `anna daemon status` shows how it's doing.
+    #[arg(long, conflicts_with_all = ["queue", "attach", "code", "json_events"])]
+    via_daemon: bool,
+}
+
+pub fn main(
+    mut config: Config,
+    sessions: &Sessions,
+    project: &Path,
+    args: Args,
+) -> Result<ExitCode> {
+    crate::config::load_project(&mut config, project, true)?;
+    // Relative to the project, as they would be if anna were run from there.
+    let also = args
+        .also
+        .iter()
+        .map(|path| {
+            let path = project.join(path);
+            fs::canonicalize(&path)
+                .with_context(|| format!("canonicalize linked project: {}", path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // Only what's copied from them is used, so the commands they checked in never
+    // run, and needn't be trusted.
+    for other in &also {
+        config.load_project(other)?;
+    }
+    let config = &config;
+    let profile = config.profile(args.profile.as_deref())?;
+    let then = args
+        .then
+        .iter()
+        .map(|stage| match config.profiles.get(stage) {
+            Some(profile) => Ok(profile.clone()),
+            None => stage
+                .parse()
+                .map(Profile::with_command)
+                .with_context(|| format!("parse --then command: {stage}"))
+                .suggestion(format!(
+                    "pass the name of a configured profile, or a command; profiles: {}",
+                    config
+                        .profiles
+                        .keys()
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // Taken before anything else, so that nothing anna does goes unchecked.
+    // Runs queued or left to the daemon take their own when they're run.
+    let fingerprint = ((args.paranoid || config.paranoid) && !args.queue && !args.via_daemon)
+        .then(|| Fingerprint::take(project))
+        .transpose()
+        .context("record the state of the project")?;
+
+    let project_config = config.project(project);
+    let vcs = match (args.no_vcs, project_config.vcs) {
+        (true, _) => VcsKind::None,
+        (false, Some(vcs)) => vcs,
+        (false, None) => VcsKind::detect(project),
+    };
+    if !args.no_vcs && vcs == VcsKind::None {
+        return Err(eyre!(
+            "project is not under version control: {}",
+            project.display()
+        ))
+        .suggestion("pass --no-vcs to work on it without version control");
+    }
+    if args.queue {
+        return queue::push(project, invocation_without("--queue")?);
+    }
+    if args.via_daemon {
+        return daemon::start_agent(&invocation_without("--via-daemon")?);
+    }
+
+    // Fetched before anything else, since the branch may be derived from it.
+    let issue = args
+        .from_issue
+        .map(|reference| fetch_issue(config, project, vcs, reference))
+        .transpose()?;
+    let branch = match (&args.branch, &issue) {
+        (Some(branch), _) => parse_branch(branch, args.sanitize)?,
+        (None, Some(issue)) => {
+            let branch = issue.branch()?;
+            narrate!("Using branch '{branch}' for issue #{}", issue.number);
+            branch
+        }
+        (None, None) => {
+            let prompt = args
+                .prompt
+                .as_deref()
+                .expect("clap requires a branch without --from-issue or --prompt");
+            branch_for_prompt(sessions, project, vcs, profile, prompt)?
+        }
+    };
+    // Checked before the session is resumed, which may change the workspace
+    // under the backend; running another would be refused anyway.
+    if let Some(backend) = sessions.running_backend(project, &branch)? {
+        if args.attach {
+            narrate!("Attaching to the backend already running in session '{branch}'");
+            session::attach(sessions, project, &branch)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        return Err(explain_already_running(
+            AlreadyRunning { branch, backend }.into(),
+        ));
+    }
+
+    let base = match args.base.as_deref() {
+        Some("default") => Some(
+            vcs.vcs()
+                .default_branch(project)
+                .context("determine default branch")?,
+        ),
+        base => base.map(String::from),
+    };
+
+    // Rendered before the workspace is created so that a broken template
+    // doesn't waste an expensive copy.
+    let prompt = match &args.prompt_template {
+        Some(name) => {
+            let vars = args.vars.into_iter().collect::<BTreeMap<_, _>>();
+            let template = PromptTemplate::load_for_project(project, name)?;
+            let prompt = template
+                .render(project, &branch, &vars)
+                .with_context(|| format!("render prompt template '{name}'"))?;
+            Some(prompt)
+        }
+        None => args.prompt.or_else(|| issue.as_ref().map(Issue::prompt)),
+    };
+
+    let defaults = config.create_options_with_vcs(project, vcs);
+    let options = CreateOptions {
+        base: base.clone(),
+        existing: args.existing,
+        issue: issue.map(|issue| issue.url),
+        also: also
+            .iter()
+            .map(|other| config.linked_project(other))
+            .collect(),
+        // The backend only needs to be installed where it runs.
+        backend: args
+            .remote
+            .is_none()
+            .then(|| String::from(profile.command.program())),
+        remote: args.remote.clone(),
+        group: args.group.clone(),
+        check_space: !args.force,
+        copy_limit: defaults.copy_limit.filter(|_| !args.yes),
+        max_file_size: args.max_file_size.map(|size| size.0),
+        cancel: Cancel::default(),
+        submodules: !args.no_submodules,
+        lfs: match args.lfs_pointers {
+            true => Some(defaults.lfs.clone().unwrap_or_default()),
+            false => defaults.lfs.clone(),
+        },
+        events: output::events(
+            args.json_events
+                .map(open_events)
+                .transpose()?
+                .unwrap_or_default(),
+        ),
+        ..defaults
+    };
+    // So that what the store waits for while the session is created is reported too.
+    let sessions = &sessions.clone().with_events(options.events.clone());
+    // Interrupting the copy cancels it, which deletes what was copied, rather than
+    // leaving a partial workspace behind. Only while copying: not while asking to.
+    let create = |branch: BranchName, options: &CreateOptions| {
+        let _interrupt = options.cancel.on_interrupt()?;
+        Agent::new(sessions, project, branch, options)
+    };
+    let created = match create(branch.clone(), &options) {
+        Err(err) if err.downcast_ref::<CopyTooLarge>().is_some() && confirm_copy(&err)? => {
+            let options = CreateOptions {
+                copy_limit: None,
+                ..options
+            };
+            create(branch, &options)
+        }
+        created => created,
+    };
+    let mut agent = created.map_err(|err| {
+        if err.downcast_ref::<CopyTooLarge>().is_some() {
+            return err.suggestion(
+                "pass --yes to copy it anyway, list what's large in the project's .annaignore, \
+                 or raise `confirm_copy_size` in the config",
+            );
+        }
+        if err.downcast_ref::<CopyFailed>().is_some() {
+            return explain_copy_failure(err);
+        }
+        if err.downcast_ref::<InsufficientSpace>().is_some() {
+            return err.suggestion("free up some space, or pass --force to try anyway");
+        }
+        if let Some(unavailable) = err.downcast_ref::<BackendUnavailable>() {
+            let suggestion = match &profile.install {
+                Some(install) => format!("install it with: {install}"),
+                None => format!(
+                    "install '{}', or change the profile's command in the config",
+                    unavailable.program
+                ),
+            };
+            return err.suggestion(suggestion);
+        }
+        match err.downcast_ref::<UnsuitableProject>() {
+            Some(UnsuitableProject::Workspace { project, .. }) => {
+                let suggestion =
+                    format!("run anna from the project instead: {}", project.display());
+                err.suggestion(suggestion)
+            }
+            Some(UnsuitableProject::Store { .. }) => {
+                err.suggestion("run anna from the project you want the agent to work on")
+            }
+            None => err,
+        }
+    })?;
+    if let Some(report) = agent.creation() {
+        report_creation(report, agent.session());
+    }
+    match (agent.status(), agent.base()) {
+        (AgentSessionStatus::Created, Some(revision)) => {
+            let from = base.as_deref().unwrap_or("the current checkout");
+            let short = revision.get(..12).unwrap_or(revision);
+            narrate!("Created session '{}' from {from} ({short})", agent.branch());
+        }
+        (AgentSessionStatus::Created, None) => {
+            narrate!("Created session '{}'", agent.branch())
+        }
+        (AgentSessionStatus::Resumed, _) => {
+            narrate!("Resumed session '{}'", agent.branch())
+        }
+    }
+    if agent.status() == AgentSessionStatus::Resumed {
+        if args.rebase {
+            let base = agent.rebase().map_err(session::explain_conflict)?;
+            let short = base.get(..12).unwrap_or(&base);
+            narrate!("Rebased '{}' onto {short}", agent.branch());
+        } else {
+            warn_if_stale(&agent);
+        }
+    }
+    if agent.status() == AgentSessionStatus::Resumed && !args.also.is_empty() {
+        eprintln!("warning: --also only applies to new sessions, so it was ignored");
+    }
+    if agent.status() == AgentSessionStatus::Resumed
+        && args.remote.is_some()
+        && args.remote != agent.session().remote
+    {
+        eprintln!("warning: --remote only applies to new sessions, so it was ignored");
+    }
+    if let Some(group) = &args.group {
+        if agent.session().group.as_ref() != Some(group) {
+            sessions.set_group(agent.project(), agent.branch(), Some(group))?;
+            narrate!("Moved '{}' into group '{group}'", agent.branch());
+        }
+    }
+    narrate!("Workspace: {}", agent.workspace().display());
+    if let Some(remote) = &agent.session().remote {
+        narrate!("Backend runs on: {remote}");
+    }
+    for linked in &agent.session().linked {
+        narrate!(
+            "Linked workspace for {}: {}",
+            linked.project.display(),
+            linked.workspace.display()
+        );
+    }
+
+    if args.code {
+        editor::open(config, agent.workspace())?;
+    }
+    if args.devcontainer && devcontainer::config(agent.workspace()).is_none() {
+        return Err(eyre!(
+            "the project has no development container configuration"
+        ))
+        .suggestion(format!(
+            "add one at {}, or run without --devcontainer",
+            devcontainer::CONFIG_PATHS[0]
+        ));
+    }
+
+    let pty = config.pty && !args.no_pty;
+    let multiplexer = match (args.tmux, args.mux) {
+        (true, _) => Some(MultiplexerKind::Tmux),
+        (false, true) => Some(config.multiplexer),
+        (false, false) => None,
+    };
+    let options = RunOptions {
+        prompt,
+        auto_commit: args.auto_commit || config.auto_commit,
+        new_conversation: args.new_conversation,
+        model: args.model,
+        pty,
+        multiplexer,
+        devcontainer: args.devcontainer,
+        transcript: pty && multiplexer.is_none() && config.transcripts && !args.no_transcript,
+        verify: args.verify.or_else(|| config.verify(project)),
+        network: (args.no_network || config.network.isolate)
+            .then(|| config.network_policy(profile)),
+        restart: args.restart,
+        launcher: Launcher::default(),
+    };
+    let stages = [profile].into_iter().chain(&then).collect::<Vec<_>>();
+    let mut outcome = None;
+    for (i, stage) in stages.iter().enumerate() {
+        let last = i + 1 == stages.len();
+        let program = stage.command.program();
+        if i > 0 {
+            narrate!("Running {program}, stage {} of {}", i + 1, stages.len());
+        }
+        let options = RunOptions {
+            new_conversation: options.new_conversation || i > 0,
+            // The model is named for the first backend, and may mean nothing to the others.
+            model: options.model.clone().filter(|_| i == 0),
+            verify: options.verify.clone().filter(|_| last),
+            network: options
+                .network
+                .as_ref()
+                .map(|_| config.network_policy(stage)),
+            ..options.clone()
+        };
+        let stage_outcome = agent
+            .run(stage, &options)
+            .map_err(explain_already_running)?;
+        report_outcome(&stage_outcome);
+        let status = stage_outcome.status;
+        outcome = Some(stage_outcome);
+        if !status.success() && !last {
+            narrate!("Stopped the pipeline, since {program} exited with {status}");
+            break;
+        }
+    }
+    let outcome = outcome.expect("there's always at least one stage");
+
+    let notify = config.notify(profile);
+    if notify.enabled() {
+        for err in Notification::new(agent.session(), &outcome).send(&notify) {
+            eprintln!("warning: failed to notify: {err:#}");
+        }
+    }
+
+    if let Some(fingerprint) = fingerprint {
+        let changes = fingerprint
+            .changes(&Fingerprint::take(project).context("check the state of the project")?);
+        if !changes.is_empty() {
+            return Err(ProjectModified {
+                project: project.to_path_buf(),
+                changes,
+            })
+            .suggestion(
+                "check whether something else was writing to the project, \
+                 or the backend reached it through an absolute path",
+            );
+        }
+    }
+
+    // Last, after anything the backend printed, so that scripts can find it.
+    if output::porcelain() {
+        println!("{}", agent.workspace().display());
+    }
+
+    let code = outcome.status.code().unwrap_or(1);
+    Ok(ExitCode::from(u8::try_from(code).unwrap_or(1)))
+}
+
+/// The arguments anna was run with, not including the program, without `flag`,
+/// to run again later or elsewhere.
+fn invocation_without(flag: &str) -> Result<Vec<String>> {
+    env::args_os()
+        .skip(1)
+        .filter(|arg| arg != flag)
+        .map(|arg| {
+            arg.into_string()
+                .map_err(|arg| eyre!("argument isn't valid UTF-8: {}", arg.to_string_lossy()))
+        })
+        .collect()
+}
+
+/// Suggest what to do about the backend already running, if `err` is [`AlreadyRunning`].
+fn explain_already_running(err: Report) -> Report {
+    let Some(running) = err.downcast_ref::<AlreadyRunning>() else {
+        return err;
+    };
+    let suggestion = match running.backend.multiplexer {
+        Some(_) => format!(
+            "wait for it to finish, or pass --attach to attach to it as `anna session attach {}` does",
+            running.branch
+        ),
+        None => String::from(
+            "wait for it to finish or stop it; backends run with --mux can be attached to instead",
+        ),
+    };
+    err.suggestion(suggestion)
+}
+
+/// Report what happened in a run of a backend, beyond its own output.
+fn report_outcome(outcome: &RunOutcome) {
+    if outcome.restarts > 0 {
+        narrate!(
+            "Backend was restarted {} times; its last run exited with {}",
+            outcome.restarts,
+            outcome.status
+        );
+    }
+    if let Some(commit) = &outcome.auto_commit {
+        narrate!("Committed remaining changes: {commit}");
+    }
+    for (project, commit) in &outcome.linked_auto_commits {
+        narrate!(
+            "Committed remaining changes in {}: {commit}",
+            project.display()
+        );
+    }
+    if !outcome.denied_hosts.is_empty() {
+        eprintln!(
+            "Blocked network access to: {}",
+            outcome.denied_hosts.join(", ")
+        );
+    }
+    match &outcome.verification {
+        Some(verification) if verification.passed => {
+            narrate!("Verification passed: {}", verification.command)
+        }
+        Some(verification) => eprintln!("Verification failed: {}", verification.command),
+        None => {}
+    }
+}
+
+/// Fetch the referenced issue from the forge hosting it; bare issue numbers
+/// refer to the repository of the project's origin remote.
+fn fetch_issue(
+    config: &Config,
+    project: &Path,
+    vcs: VcsKind,
+    reference: IssueReference,
+) -> Result<Issue> {
+    let repository = match reference.repository {
+        Some(repository) => repository,
+        None => vcs
+            .vcs()
+            .remote_url(project, "origin")
+            .context("get URL of remote 'origin'")
+            .suggestion("pass the issue's URL instead of its number")?
+            .parse::<Repository>()?,
+    };
+    forge::connect(config, &repository)?.issue(&repository, reference.number)
+}
+
+/// Name the branch for a session started with only a prompt: by asking the backend
+/// if its profile says how, else from the prompt's words. The name is made unique
+/// among the project's sessions and branches, then confirmed when there's someone to ask.
+fn branch_for_prompt(
+    sessions: &Sessions,
+    project: &Path,
+    vcs: VcsKind,
+    profile: &Profile,
+    prompt: &str,
+) -> Result<BranchName> {
+    let suggested = match &profile.name_branch {
+        Some(template) => backend::suggest_branch(template, prompt).or_else(|err| {
+            eprintln!("warning: the backend could not name the branch: {err:#}");
+            branch::from_prompt(prompt)
+        }),
+        None => branch::from_prompt(prompt),
+    }
+    .suggestion("pass the branch name as an argument")?;
+
+    let taken = |branch: &BranchName| -> Result<bool> {
+        Ok(sessions.get(project, branch)?.is_some() || vcs.vcs().branch_exists(project, branch)?)
+    };
+    let mut branch = suggested.clone();
+    let mut n = 2;
+    while taken(&branch)? {
+        branch = format!("{suggested}-{n}").parse()?;
+        n += 1;
+    }
+
+    if !io::stdin().is_terminal() {
+        narrate!("Using branch '{branch}' for the prompt");
+        return Ok(branch);
+    }
+    loop {
+        eprint!("Use branch '{branch}'? [Y/n, or another name] ");
+        io::stderr().flush().context("write to stderr")?;
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer).context("read answer")? == 0 {
+            bail!("no branch name confirmed");
+        }
+        match answer.trim() {
+            "" | "y" | "Y" | "yes" => return Ok(branch),
+            "n" | "N" | "no" => {
+                return Err(eyre!("no branch name confirmed"))
+                    .suggestion("pass the branch name as an argument");
+            }
+            name => match parse_branch(name, true) {
+                Ok(name) if taken(&name)? => {
+                    eprintln!("Branch '{name}' is already used in this project")
+                }
+                Ok(name) => return Ok(name),
+                Err(err) => eprintln!("{err}"),
+            },
+        }
+    }
+}
+
+/// Ask whether to go ahead with the copy `err`, a [`CopyTooLarge`], refused.
+/// Nobody can be asked unless stdin is a terminal.
+fn confirm_copy(err: &Report) -> Result<bool> {
+    let Some(too_large) = err.downcast_ref::<CopyTooLarge>() else {
+        return Ok(false);
+    };
+    if !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    eprint!(
+        "Copy {} files ({}) into a new workspace? [y/N] ",
+        too_large.estimate.files,
+        workspace::format_size(too_large.estimate.bytes)
+    );
+    io::stderr().flush().context("write to stderr")?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).context("read answer")?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Parse the branch argument, sanitizing it if requested.
+/// When a name is invalid, the sanitized alternative is suggested.
+fn parse_branch(name: &str, sanitize: bool) -> Result<BranchName> {
+    if sanitize {
+        let branch = branch::sanitize(name)?;
+        if branch.as_str() != name {
+            narrate!("Using branch '{branch}'");
+        }
+        return Ok(branch);
+    }
+
+    name.parse::<BranchName>()
+        .map_err(|err| match branch::sanitize(name) {
+            Ok(sanitized) => {
+                err.suggestion(format!("pass --sanitize to use '{sanitized}' instead"))
+            }
+            Err(_) => err,
+        })
+}
+
+fn parse_var(var: &str) -> Result<(String, String)> {
+    var.split_once('=')
+        .map(|(name, value)| (String::from(name.trim()), String::from(value)))
+        .ok_or_else(|| eyre!("expected NAME=VALUE, got '{var}'"))
+}
+
+/// Warn when the project has moved on far enough since the session's branch
+/// was created that the agent is likely working against stale code.
+///
+/// Only a warning, and skipped if it can't be determined: the agent can still run.
+fn warn_if_stale(agent: &Agent) {
+    let Ok(Some(behind)) = agent.commits_behind() else {
+        return;
+    };
+    if behind >= STALE_COMMITS {
+        eprintln!(
+            "warning: the project is {behind} commits ahead of where '{}' was created; \
+             pass --rebase to rebase onto it",
+            agent.branch()
+        );
+    }
+}
+
+fn report_creation(report: &CreationReport, session: &Session) {
+    let duration = format!("in {:.1}s", report.duration.as_secs_f64());
+    report_copy(&report.copy, "the workspace", &duration);
+    for (linked, copy) in session.linked.iter().zip(&report.linked) {
+        let project = linked.project.display();
+        let workspace = format!("the linked workspace for {project}");
+        report_copy(copy, &workspace, &format!("from {project}"));
+    }
+
+    let caches = &report.caches;
+    if !caches.seeded.is_empty() {
+        let seeded = caches
+            .seeded
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        narrate!(
+            "Seeded caches: {seeded} ({})",
+            workspace::format_size(caches.bytes)
+        );
+    }
+}
+
+/// Report what was copied into `workspace`, summarized with `detail`,
+/// and what failed to copy or was withheld.
+fn report_copy(copy: &CopyReport, workspace: &str, detail: &str) {
+    for warning in &copy.warnings {
+        eprintln!("warning: {warning}");
+    }
+    if !copy.withheld.is_empty() {
+        let withheld = copy
+            .withheld
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        narrate!("Withheld possible secrets from {workspace}: {withheld}");
+    }
+    if !copy.oversized.is_empty() {
+        let oversized = copy
+            .oversized
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        narrate!("Skipped files too large to copy into {workspace}: {oversized}");
+    }
+    for path in &copy.deleted {
+        eprintln!(
+            "warning: {} is tracked but was left out of {workspace}, so it reads as deleted there; \
+             take care not to commit its deletion",
+            path.display()
+        );
+    }
+
+    let mut summary = format!(
+        "Copied {} files ({}) {detail}",
+        copy.files,
+        workspace::format_size(copy.bytes)
+    );
+    if !copy.warnings.is_empty() {
+        summary.push_str(&format!("; {} failed to copy", copy.warnings.len()));
+    }
+    narrate!("{summary}");
+}
+
+/// List the entries a [`CopyFailed`] couldn't copy, and suggest how to make them available.
+pub fn explain_copy_failure(err: Report) -> Report {
+    /// How many failures are listed.
+    const LISTED: usize = 20;
+
+    let Some(failed) = err.downcast_ref::<CopyFailed>() else {
+        return err;
+    };
+    for failure in failed.report.failures.iter().take(LISTED) {
+        eprintln!("  {failure}");
+    }
+    if failed.report.failures.len() > LISTED {
+        eprintln!("  and {} more", failed.report.failures.len() - LISTED);
+    }
+    err.suggestion(
+        "if the project is in a synced folder or on a network drive, make sure its files \
+         are available on this machine, such as by pausing syncing, then try again",
+    )
+}
+
+fn open_events(fd: u32) -> Result<Events> {
+    if fd == 1 {
+        return Ok(Events::new(io::stdout()));
+    }
+    // Opened by path, rather than adopting the raw descriptor,
+    // so that one which isn't open is reported instead of misused.
+    let path = format!("/dev/fd/{fd}");
+    let file = File::options()
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("open file descriptor {fd} for events"))?;
+    Ok(Events::new(file))
+}
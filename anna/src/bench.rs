@@ -0,0 +1,107 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{path::Path, process::ExitCode, time::Instant};
+
+use clap::Subcommand;
+use color_eyre::{eyre::Context, Result};
+use winlock::{
+    config::{self, Config},
+    workspace::{self, CopyEngineKind, CopyOptions},
+};
+
+use crate::output::{self, narrate};
+
+#[derive(Debug, Subcommand)]
+pub enum Commands {
+    /// Time copying the current project into a workspace with each copy engine,
+    /// and use the fastest for the project's workspaces from now on.
+    ///
+    /// Prints each engine's time in seconds and what it copied. Engines whose
+    /// workspaces differ from a plain copy, such as hardlink, are measured
+    /// but never chosen; set `copy_engine` in the project's settings to use one.
+    Copy {
+        /// Only measure the engines, without recording the fastest.
+        #[arg(long)]
+        no_save: bool,
+    },
+}
+
+pub fn main(mut config: Config, project: &Path, command: Commands) -> Result<ExitCode> {
+    config.load_project(project)?;
+    match command {
+        Commands::Copy { no_save } => copy(&config, project, no_save),
+    }
+}
+
+fn copy(config: &Config, project: &Path, no_save: bool) -> Result<ExitCode> {
+    let exclude = config.secret_patterns(project);
+    // Measured where workspaces are really made, since the filesystem matters most.
+    let root = workspace::root();
+    let mut fastest = None;
+    for kind in CopyEngineKind::ALL {
+        let engine = kind.engine();
+        let dir = workspace::create_dir(&root, project, &format!("bench-{kind}"))?;
+        let started = Instant::now();
+        let copied = engine.copy(
+            project,
+            &dir,
+            &exclude,
+            &CopyOptions::default(),
+            &mut |_| {},
+        );
+        let elapsed = started.elapsed();
+        workspace::delete_dir(&dir)
+            .with_context(|| format!("delete benchmark workspace: {}", dir.display()))?;
+        let report = match copied {
+            Ok(report) => report,
+            Err(err) => {
+                eprintln!("warning: {kind} failed: {err:#}");
+                continue;
+            }
+        };
+
+        let seconds = format!("{:.3}", elapsed.as_secs_f64());
+        match output::porcelain() {
+            true => println!(
+                "{}",
+                output::row([
+                    kind.to_string(),
+                    seconds,
+                    report.files.to_string(),
+                    report.bytes.to_string()
+                ])
+            ),
+            false => println!(
+                "{kind}\t{seconds}\t{} files\t{}",
+                report.files,
+                workspace::format_size(report.bytes)
+            ),
+        }
+        match engine.caveat() {
+            Some(caveat) => narrate!("note: with {kind}, {caveat}"),
+            None if fastest.is_none_or(|(_, best)| elapsed < best) => {
+                fastest = Some((kind, elapsed));
+            }
+            None => {}
+        }
+    }
+
+    let Some((kind, _)) = fastest else {
+        eprintln!("No copy engine without caveats succeeded");
+        return Ok(ExitCode::FAILURE);
+    };
+    if no_save {
+        narrate!("Fastest: {kind}");
+        return Ok(ExitCode::SUCCESS);
+    }
+    let path = config::config_file()?;
+    config::set_project_value(&path, project, "copy_engine", kind.to_string())?;
+    narrate!(
+        "Fastest: {kind}; recorded for {} in {}",
+        project.display(),
+        path.display()
+    );
+    Ok(ExitCode::SUCCESS)
+}
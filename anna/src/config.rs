@@ -0,0 +1,262 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs,
+    io::{self, ErrorKind, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{Args, Subcommand, ValueEnum};
+use color_eyre::{eyre::Context, Result};
+use winlock::config::{self, CheckedInCommands, Config, ConfigFile, Severity, PROJECT_CONFIG};
+
+use crate::output::narrate;
+
+/// Config commands read the user's `config.toml` and the current project's
+/// checked-in `.anna.toml`, if it has one.
+///
+/// Settings are named by TOML dotted keys, such as `network.isolate`,
+/// `profiles.aider.command`, or `projects."/home/me/src/project".verify`.
+#[derive(Debug, Subcommand)]
+pub enum Commands {
+    /// Print the value of a setting, or its default if it isn't set.
+    ///
+    /// Strings are printed as they are, and other values as TOML.
+    /// Fails if the setting has no value.
+    Get {
+        /// The setting.
+        key: String,
+
+        #[command(flatten)]
+        scope: Scope,
+    },
+
+    /// Set a setting, keeping the rest of the file as it was.
+    Set {
+        /// The setting.
+        key: String,
+
+        /// The value: TOML such as `true`, `3`, or `["a", "b"]`, or otherwise a string.
+        value: String,
+
+        #[command(flatten)]
+        scope: Scope,
+    },
+
+    /// Remove a setting, so that it takes its default.
+    Unset {
+        /// The setting.
+        key: String,
+
+        #[command(flatten)]
+        scope: Scope,
+    },
+
+    /// Report every problem in the configuration files, such as unknown settings,
+    /// values of the wrong type, and deprecated settings.
+    ///
+    /// Prints each problem as `file:line:column: severity: message`,
+    /// and fails if any of them keep a file from loading.
+    Check,
+
+    /// Trust the setup and verification commands the project's checked-in `.anna.toml`
+    /// would have anna run, printing them. Until they're trusted they're skipped,
+    /// and trusting them lasts until they change.
+    Trust,
+
+    /// Print the configuration files.
+    Show {
+        /// Print the configuration in effect for the current project instead:
+        /// the files merged, with every default filled in.
+        #[arg(long)]
+        resolved: bool,
+    },
+}
+
+/// Which file a setting is read from or written to.
+#[derive(Debug, Args)]
+pub struct Scope {
+    /// Which file the setting is in.
+    #[arg(long, value_enum, default_value_t)]
+    scope: ScopeFile,
+}
+
+/// The files settings can be in, from `--scope`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum ScopeFile {
+    /// The user's `config.toml`.
+    #[default]
+    Global,
+
+    /// The project's checked-in `.anna.toml`, whose keys are those of
+    /// `projects."..."` in `config.toml`.
+    Project,
+}
+
+impl Scope {
+    fn file(&self, project: &Path) -> Result<(PathBuf, ConfigFile)> {
+        match self.scope {
+            ScopeFile::Project => Ok((project.join(PROJECT_CONFIG), ConfigFile::Project)),
+            ScopeFile::Global => Ok((config::config_file()?, ConfigFile::User)),
+        }
+    }
+}
+
+pub fn main(project: &Path, command: Commands) -> Result<ExitCode> {
+    match command {
+        Commands::Get { key, scope } => get(project, &key, &scope),
+        Commands::Set { key, value, scope } => set(project, &key, &value, &scope),
+        Commands::Unset { key, scope } => unset(project, &key, &scope),
+        Commands::Check => check(project),
+        Commands::Trust => trust(project),
+        Commands::Show { resolved: false } => show(project),
+        Commands::Show { resolved: true } => resolved(project),
+    }
+}
+
+fn get(project: &Path, key: &str, scope: &Scope) -> Result<ExitCode> {
+    let (path, file) = scope.file(project)?;
+    match config::get_value(&path, file, key)? {
+        Some(toml::Value::String(value)) => println!("{value}"),
+        Some(value) => println!("{value}"),
+        None => {
+            eprintln!("'{key}' has no value in {}", path.display());
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn set(project: &Path, key: &str, value: &str, scope: &Scope) -> Result<ExitCode> {
+    let (path, file) = scope.file(project)?;
+    config::set_value(&path, file, key, config::parse_value(value))?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn unset(project: &Path, key: &str, scope: &Scope) -> Result<ExitCode> {
+    let (path, file) = scope.file(project)?;
+    if !config::unset_value(&path, file, key)? {
+        narrate!("'{key}' was not set in {}", path.display());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn check(project: &Path) -> Result<ExitCode> {
+    let mut errors = false;
+    for (path, file) in files(project)? {
+        let Some(content) = read(&path)? else {
+            continue;
+        };
+        let problems = config::check(&content, file);
+        if problems.is_empty() {
+            narrate!("{}: no problems", path.display());
+        }
+        for problem in problems {
+            errors |= problem.severity == Severity::Error;
+            println!("{}:{problem}", path.display());
+        }
+    }
+    Ok(match errors {
+        true => ExitCode::FAILURE,
+        false => ExitCode::SUCCESS,
+    })
+}
+
+fn trust(project: &Path) -> Result<ExitCode> {
+    let mut config = Config::load()?;
+    let Some(commands) = config.load_project(project)? else {
+        narrate!("{} runs no commands that aren't trusted", project.display());
+        return Ok(ExitCode::SUCCESS);
+    };
+    print_commands(&commands);
+    config.trust_project(&config::config_file()?, project, &commands)?;
+    narrate!("Trusted them until they change");
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Merge the settings the project checked in into `config`, as [`Config::load_project`]
+/// does. If they'd run commands the user hasn't trusted, they're listed, and when `ask`
+/// and there's a terminal to ask on the user is asked whether to trust them;
+/// otherwise they're skipped.
+pub fn load_project(config: &mut Config, project: &Path, ask: bool) -> Result<()> {
+    let Some(commands) = config.load_project(project)? else {
+        return Ok(());
+    };
+    eprintln!(
+        "{} would have anna run commands you haven't trusted:",
+        commands.file.display()
+    );
+    print_commands(&commands);
+    if ask && io::stdin().is_terminal() {
+        eprint!("Trust them, until they change? [y/N] ");
+        io::stderr().flush().context("write to stderr")?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer).context("read answer")?;
+        if matches!(answer.trim(), "y" | "Y" | "yes") {
+            return config.trust_project(&config::config_file()?, project, &commands);
+        }
+    }
+    eprintln!("warning: skipping them; run `anna config trust` once you've reviewed them");
+    Ok(())
+}
+
+fn print_commands(commands: &CheckedInCommands) {
+    for command in &commands.setup {
+        eprintln!("  setup: {command}");
+    }
+    if let Some(command) = &commands.verify {
+        eprintln!("  verify: {command}");
+    }
+}
+
+fn show(project: &Path) -> Result<ExitCode> {
+    let mut first = true;
+    for (path, _) in files(project)? {
+        let Some(content) = read(&path)? else {
+            continue;
+        };
+        if !first {
+            println!();
+        }
+        first = false;
+        println!("# {}", path.display());
+        print!("{content}");
+        if !content.ends_with('\n') {
+            println!();
+        }
+    }
+    if first {
+        narrate!("No configuration files; everything has its default");
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn resolved(project: &Path) -> Result<ExitCode> {
+    let mut config = Config::load()?;
+    config.load_project(project)?;
+    // Other projects' settings have no effect here.
+    config.projects.retain(|path, _| path == project);
+    let content = toml::to_string_pretty(&config).context("serialize config")?;
+    print!("{content}");
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The configuration files that apply to the project, whether or not they exist.
+fn files(project: &Path) -> Result<[(PathBuf, ConfigFile); 2]> {
+    Ok([
+        (config::config_file()?, ConfigFile::User),
+        (project.join(PROJECT_CONFIG), ConfigFile::Project),
+    ])
+}
+
+/// Read a configuration file, or `None` if there isn't one.
+fn read(path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).context(format!("read config: {}", path.display())),
+    }
+}
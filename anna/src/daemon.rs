@@ -0,0 +1,131 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+#[cfg(unix)]
+use std::env;
+use std::{path::Path, process::ExitCode};
+
+use clap::Subcommand;
+#[cfg(not(unix))]
+use color_eyre::eyre::bail;
+use color_eyre::Result;
+#[cfg(unix)]
+use color_eyre::{eyre::Context, Section};
+use winlock::session::Session;
+#[cfg(unix)]
+use winlock::{
+    config::state_dir,
+    daemon::{self, Client, Daemon},
+};
+
+#[cfg(unix)]
+use crate::output::{self, narrate};
+
+/// Commands for the daemon, which keeps the session store in memory for fast queries
+/// and supervises agents started with `anna agent --via-daemon`. Editors and other
+/// frontends talk to it too: it answers JSON-RPC requests, one per line, on the
+/// Unix socket `daemon.sock` in anna's state directory.
+#[derive(Debug, Subcommand)]
+pub enum Commands {
+    /// Run the daemon in this terminal until it's stopped.
+    Run,
+
+    /// Print whether the daemon is running, then the agents it started: a tab-separated
+    /// line for each with its number, process ID, state, command line, and log.
+    Status,
+
+    /// Stop the daemon, leaving the agents it started running.
+    Stop,
+}
+
+#[cfg(unix)]
+pub fn main(command: Commands) -> Result<ExitCode> {
+    let socket = daemon::socket_path()?;
+    match command {
+        Commands::Run => {
+            let program = env::current_exe().context("find the anna executable")?;
+            let daemon = Daemon::new(&state_dir()?, &program)?;
+            narrate!("Listening on {}", socket.display());
+            daemon.serve(&socket)?;
+            narrate!("Stopped the daemon");
+        }
+        Commands::Status => {
+            let Ok(mut client) = Client::connect_to(&socket) else {
+                narrate!("The daemon isn't running");
+                return Ok(ExitCode::FAILURE);
+            };
+            let ping = client.call::<serde_json::Value>("ping", serde_json::Value::Null)?;
+            narrate!("The daemon is running as PID {}", ping["pid"]);
+            for agent in client.agents()? {
+                let state = match (agent.finished_at, agent.code) {
+                    (None, _) => String::from("running"),
+                    (Some(_), Some(code)) => format!("exited ({code})"),
+                    (Some(_), None) => String::from("killed"),
+                };
+                println!(
+                    "{}",
+                    output::row([
+                        agent.id.to_string(),
+                        agent.pid.to_string(),
+                        state,
+                        shell_words::join(&agent.args),
+                        agent.log.display().to_string(),
+                    ])
+                );
+            }
+        }
+        Commands::Stop => {
+            connect()?.shutdown()?;
+            narrate!("Stopped the daemon");
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(not(unix))]
+pub fn main(_command: Commands) -> Result<ExitCode> {
+    bail!("the daemon is only supported on Unix-like systems")
+}
+
+/// Connect to the daemon, suggesting how to start it if it isn't running.
+#[cfg(unix)]
+fn connect() -> Result<Client> {
+    Client::connect().suggestion("start it with `anna daemon run`")
+}
+
+/// The sessions, or only those of `project`, as the daemon has them.
+#[cfg(unix)]
+pub fn sessions(project: Option<&Path>) -> Result<Vec<Session>> {
+    connect()?.sessions(project)
+}
+
+#[cfg(not(unix))]
+pub fn sessions(_project: Option<&Path>) -> Result<Vec<Session>> {
+    bail!("the daemon is only supported on Unix-like systems")
+}
+
+/// Have the daemon run `anna` with `args` detached, from the current directory,
+/// printing the agent's number.
+#[cfg(unix)]
+pub fn start_agent(args: &[String]) -> Result<ExitCode> {
+    let dir = env::current_dir().context("get current directory")?;
+    // Its own, rather than the daemon's, which was captured whenever the daemon started.
+    let env = env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .collect();
+    let agent = connect()?.start_agent(&dir, args, &env)?;
+    narrate!(
+        "Started agent {} as PID {}; its output goes to {}",
+        agent.id,
+        agent.pid,
+        agent.log.display()
+    );
+    println!("{}", agent.id);
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(not(unix))]
+pub fn start_agent(_args: &[String]) -> Result<ExitCode> {
+    bail!("the daemon is only supported on Unix-like systems")
+}
@@ -0,0 +1,118 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::process::ExitCode;
+
+use color_eyre::Result;
+use winlock::{config::Config, network, session::Sessions};
+
+use crate::output;
+
+/// Check anna's configuration and session store, reporting each problem found.
+///
+/// Unlike other commands this doesn't stop at the first error,
+/// since the point is to see everything that's wrong at once.
+pub fn main() -> Result<ExitCode> {
+    let mut report = Report::default();
+
+    match Config::load() {
+        Ok(_) => report.ok("config loads"),
+        Err(err) => report.error(format!("config: {err:#}")),
+    }
+
+    let sessions = match Sessions::open() {
+        Ok(sessions) => sessions,
+        Err(err) => {
+            report.error(format!("session store: {err:#}"));
+            return Ok(report.finish());
+        }
+    };
+
+    match sessions.iter() {
+        Ok(iter) => {
+            // Read one by one rather than with `list_all`, so that damaged sessions
+            // are reported as problems instead of being skipped with a warning.
+            let mut count = 0;
+            let mut missing = Vec::new();
+            for session in iter {
+                match session {
+                    Ok(session) => {
+                        count += 1;
+                        if !session.archived && !session.workspace.is_dir() {
+                            missing.push(session);
+                        }
+                    }
+                    Err(err) => report.error(format!("session store: {err:#}")),
+                }
+            }
+            report.ok(format!("session store has {count} sessions"));
+            missing.sort_by(|a, b| (&a.project, &a.branch).cmp(&(&b.project, &b.branch)));
+            for session in missing {
+                report.warning(format!(
+                    "session '{}' of {} has no workspace: {}",
+                    session.branch,
+                    session.project.display(),
+                    session.workspace.display()
+                ));
+            }
+        }
+        Err(err) => report.error(format!("session store: {err:#}")),
+    }
+
+    match sessions.lock_owners() {
+        Ok(owners) if owners.is_empty() => report.ok("no session locks are held"),
+        Ok(owners) => {
+            for (lock, owner) in owners {
+                let owner =
+                    owner.map_or_else(|| String::from("an unknown process"), |o| o.to_string());
+                report.warning(format!("{} is locked by {owner}", lock.display()));
+            }
+        }
+        Err(err) => report.error(format!("session locks: {err:#}")),
+    }
+
+    // Only a warning: isolation is opt-in, and anna works fine without it.
+    match network::check() {
+        Ok(()) => report.ok("network isolation is available"),
+        Err(err) => report.warning(format!("network isolation is unavailable: {err:#}")),
+    }
+
+    Ok(report.finish())
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    errors: usize,
+}
+
+impl Report {
+    fn ok(&mut self, message: impl AsRef<str>) {
+        print_result("ok", message.as_ref());
+    }
+
+    fn warning(&mut self, message: impl AsRef<str>) {
+        print_result("warning", message.as_ref());
+    }
+
+    fn error(&mut self, message: impl AsRef<str>) {
+        self.errors += 1;
+        print_result("error", message.as_ref());
+    }
+
+    fn finish(self) -> ExitCode {
+        if self.errors == 0 {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Print the result of a check, with its level as a field of its own for scripts.
+fn print_result(level: &str, message: &str) {
+    match output::porcelain() {
+        true => println!("{}", output::row([level, message])),
+        false => println!("{level}: {message}"),
+    }
+}
@@ -0,0 +1,58 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    env,
+    io::{self, ErrorKind},
+    path::Path,
+    process::{Command, ExitStatus},
+};
+
+use color_eyre::{
+    eyre::{bail, eyre, Context},
+    Result, Section,
+};
+use winlock::config::Config;
+
+/// Editors tried in order when none is configured, all of which return
+/// once the directory is open rather than when the editor is closed.
+const FALLBACK_EDITORS: [&str; 2] = ["code", "zed"];
+
+/// Open `dir` in the user's editor: the configured one, else `$VISUAL`, else
+/// the first of the [fallbacks](FALLBACK_EDITORS) that's installed.
+///
+/// Terminal editors take over the terminal, so this returns once they exit.
+pub fn open(config: &Config, dir: &Path) -> Result<()> {
+    let configured = config
+        .editor
+        .clone()
+        .or_else(|| env::var("VISUAL").ok().filter(|visual| !visual.is_empty()));
+    if let Some(editor) = configured {
+        let words = shell_words::split(&editor)
+            .with_context(|| format!("parse editor command: {editor}"))?;
+        let Some((program, args)) = words.split_first() else {
+            bail!("the editor command is empty");
+        };
+        return check(Command::new(program).args(args).arg(dir).status(), &editor);
+    }
+
+    for editor in FALLBACK_EDITORS {
+        match Command::new(editor).arg(dir).status() {
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            result => return check(result, editor),
+        }
+    }
+    Err(eyre!("no editor found")).suggestion(format!(
+        "set one with `anna config set editor <COMMAND>` or $VISUAL, or install one of: {}",
+        FALLBACK_EDITORS.join(", ")
+    ))
+}
+
+fn check(result: io::Result<ExitStatus>, editor: &str) -> Result<()> {
+    let status = result.with_context(|| format!("run editor: {editor}"))?;
+    if !status.success() {
+        bail!("editor exited with {status}: {editor}");
+    }
+    Ok(())
+}
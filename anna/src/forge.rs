@@ -0,0 +1,20 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use color_eyre::{eyre::eyre, Result, Section};
+use winlock::{
+    config::Config,
+    forge::{Forge, Repository},
+};
+
+/// Connect to the forge hosting `repository`, as configured or detected from its host.
+pub fn connect(config: &Config, repository: &Repository) -> Result<Box<dyn Forge>> {
+    let host = &repository.host;
+    match config.forge(host) {
+        Some(kind) => kind.connect(host),
+        None => Err(eyre!("unable to tell which forge hosts {host}")).suggestion(format!(
+            "configure it in config.toml, for example: [forges] \"{host}\" = \"gitlab\""
+        )),
+    }
+}
@@ -0,0 +1,70 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{fs, path::Path, process::ExitCode};
+
+use color_eyre::{
+    eyre::{eyre, Context},
+    Result, Section,
+};
+use winlock::{
+    config::{self, Config, PROJECT_CONFIG},
+    prompt::PROJECT_PROMPTS,
+};
+
+use crate::output::narrate;
+
+/// An example template written by `--prompts`, showing the available variables.
+const EXAMPLE_PROMPT: &str = "\
+Fix the bug described below in {{project.name}}, a {{project.language}} project.
+Add a test that fails without the fix.
+
+{{description}}
+";
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Also create a `.anna/prompts` directory for the project's prompt templates,
+    /// with an example template.
+    #[arg(long)]
+    prompts: bool,
+
+    /// Overwrite an existing project configuration.
+    #[arg(long)]
+    force: bool,
+}
+
+/// Write a commented `.anna.toml` for the project, with defaults detected from it.
+pub fn main(project: &Path, args: Args) -> Result<ExitCode> {
+    let path = project.join(PROJECT_CONFIG);
+    if path.exists() && !args.force {
+        return Err(eyre!("{} already exists", path.display()))
+            .suggestion("pass --force to overwrite it");
+    }
+    fs::write(&path, config::project_template(project))
+        .with_context(|| format!("write {}", path.display()))?;
+    narrate!("Wrote {}", path.display());
+    // The user asked for these commands, so they needn't be asked whether to trust them.
+    let mut user = Config::load()?;
+    if let Some(commands) = user.load_project(project)? {
+        user.trust_project(&config::config_file()?, project, &commands)?;
+    }
+
+    if args.prompts {
+        let dir = project.join(PROJECT_PROMPTS);
+        fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+        // An existing template is the user's, even with --force.
+        let example = dir.join("bugfix.md");
+        if !example.exists() {
+            fs::write(&example, EXAMPLE_PROMPT)
+                .with_context(|| format!("write {}", example.display()))?;
+            narrate!(
+                "Wrote {}; use it with --prompt-template bugfix --var description=...",
+                example.display()
+            );
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
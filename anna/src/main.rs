@@ -2,13 +2,131 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use color_eyre::Result;
-use winlock::Agent;
+use std::{env, fs, path::PathBuf, process::ExitCode};
 
-fn main() -> Result<()> {
+use clap::{Parser, Subcommand};
+use color_eyre::{eyre::Context, Result, Section};
+use winlock::{config::Config, events::Events, session::Sessions};
+
+mod agent;
+mod bench;
+mod config;
+mod daemon;
+mod doctor;
+mod editor;
+mod forge;
+mod init;
+mod mcp;
+mod output;
+mod queue;
+mod serve;
+mod session;
+
+/// Anna is an agentic coding assistant.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Act on the project at PATH instead of the current directory, like `git -C`.
+    #[arg(short = 'C', long, global = true, value_name = "PATH")]
+    project: Option<PathBuf>,
+
+    /// Leave out narration of what's happening, printing only data, warnings, and errors.
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Keep output stable for scripts: no headers, and raw values such as sizes in bytes
+    /// and RFC 3339 timestamps, each line's fields separated by tabs with tabs, line
+    /// breaks, and backslashes escaped; `anna agent` prints its workspace last.
+    #[arg(long, global = true)]
+    porcelain: bool,
+
+    /// When to color output; `auto` colors it on a terminal unless `NO_COLOR` is set.
+    #[arg(long, global = true, value_name = "WHEN", default_value = "auto")]
+    color: output::ColorChoice,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Start or resume an agent working on a branch in a copy of the project.
+    Agent(Box<agent::Args>),
+
+    /// Manage agent sessions.
+    #[command(subcommand)]
+    Session(session::Commands),
+
+    /// Run the agents queued with `anna agent --queue`.
+    #[command(subcommand)]
+    Queue(queue::Commands),
+
+    /// Run or talk to the daemon, which answers queries quickly and supervises
+    /// agents started with `anna agent --via-daemon`.
+    #[command(subcommand)]
+    Daemon(daemon::Commands),
+
+    /// Serve a read-only view of every session over HTTP, as a page and a JSON API,
+    /// for a team dashboard or a browser tab to monitor agents with.
+    Serve(serve::Args),
+
+    /// Serve sessions to other agents over the Model Context Protocol.
+    #[command(subcommand)]
+    Mcp(mcp::Commands),
+
+    /// Measure how anna performs on the current project, and tune it to match.
+    #[command(subcommand)]
+    Bench(bench::Commands),
+
+    /// Inspect and change the configuration.
+    #[command(subcommand)]
+    Config(config::Commands),
+
+    /// Check the configuration and session store for problems.
+    Doctor,
+
+    /// Write a commented `.anna.toml` configuring agents for the project.
+    Init(init::Args),
+}
+
+fn main() -> Result<ExitCode> {
     color_eyre::install()?;
 
-    let _agent = Agent::new();
+    let cli = Cli::parse();
+    output::init(cli.quiet, cli.porcelain, cli.color);
+    let project = match &cli.project {
+        Some(path) => {
+            fs::canonicalize(path).with_context(|| format!("find project: {}", path.display()))?
+        }
+        None => env::current_dir().context("get current directory")?,
+    };
+
+    // Run before anything is loaded, so that it can report broken configuration.
+    if let Commands::Doctor = cli.command {
+        return doctor::main();
+    }
+    // Needs neither, and shouldn't be stopped by a broken config.
+    if let Commands::Init(args) = cli.command {
+        return init::main(&project, args);
+    }
+    // Reads the configuration itself, since it's how a broken one gets fixed.
+    if let Commands::Config(command) = cli.command {
+        return config::main(&project, command);
+    }
+
+    let config = Config::load().suggestion("run `anna config check` to see every problem")?;
+    let sessions = Sessions::open()?.with_events(output::events(Events::default()));
 
-    Ok(())
+    match cli.command {
+        Commands::Agent(args) => agent::main(config, &sessions, &project, *args),
+        Commands::Session(command) => session::main(&config, &sessions, &project, command),
+        Commands::Queue(command) => queue::main(command),
+        Commands::Daemon(command) => daemon::main(command),
+        Commands::Serve(args) => serve::main(args),
+        Commands::Mcp(command) => mcp::main(config, &sessions, command),
+        Commands::Bench(command) => bench::main(config, &project, command),
+        Commands::Config(_) | Commands::Doctor | Commands::Init(_) => {
+            unreachable!("handled before loading configuration")
+        }
+    }
 }
@@ -0,0 +1,45 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{io, path::Path, process::ExitCode};
+
+use clap::Subcommand;
+use color_eyre::Result;
+use winlock::{config::Config, mcp::Server, session::Sessions, CreateOptions};
+
+use crate::output::narrate;
+
+/// Commands for serving anna's sessions to other agents over the
+/// Model Context Protocol, as tools to list, create, diff, and merge them.
+#[derive(Debug, Subcommand)]
+pub enum Commands {
+    /// Serve MCP on standard input and output until the client hangs up;
+    /// configure it in an MCP client as the command `anna mcp serve`.
+    ///
+    /// Sessions are created as `anna agent` creates them, from the configuration,
+    /// without running a backend in them. Projects too large to copy without
+    /// confirmation are refused.
+    Serve,
+}
+
+pub fn main(config: Config, sessions: &Sessions, command: Commands) -> Result<ExitCode> {
+    match command {
+        Commands::Serve => {
+            narrate!("Serving MCP on standard input and output");
+            let server = Server::new(sessions.clone())
+                .create_options(move |project| create_options(&config, project));
+            server.serve(io::stdin().lock(), io::stdout().lock())?;
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The options `anna agent` would create a session of `project` with by default.
+fn create_options(config: &Config, project: &Path) -> Result<CreateOptions> {
+    let mut config = config.clone();
+    // Standard input is the client's requests, so nobody can be asked.
+    crate::config::load_project(&mut config, project, false)?;
+    // There's no one to confirm large copies with, so the limit stands.
+    Ok(config.create_options(project))
+}
@@ -0,0 +1,200 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! How anna talks to whoever runs it.
+//!
+//! Data goes to stdout and narration of what's happening to stderr. `--quiet` drops
+//! the narration, leaving warnings, questions, and errors; `--porcelain` keeps stdout
+//! stable for scripts: no headers, and raw values rather than ones formatted for people.
+//!
+//! Output for people is colored where `--color` says to; by default, when stdout is a
+//! terminal and `NO_COLOR` isn't set. Output for scripts never is.
+
+use std::{
+    env,
+    io::{self, IsTerminal},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use clap::ValueEnum;
+use jiff::Timestamp;
+use winlock::{
+    events::{Event, Events},
+    multiplexer::{self, SESSION},
+};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+static PORCELAIN: AtomicBool = AtomicBool::new(false);
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// When to color output, from `--color`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Color output written to a terminal, unless `NO_COLOR` is set.
+    #[default]
+    Auto,
+
+    /// Always color output, even when it isn't written to a terminal.
+    Always,
+
+    /// Never color output.
+    Never,
+}
+
+/// A color for part of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Green,
+
+    Red,
+
+    Yellow,
+
+    /// Dimmed, for what's less important.
+    Dim,
+}
+
+impl Color {
+    fn code(self) -> &'static str {
+        match self {
+            Self::Green => "32",
+            Self::Red => "31",
+            Self::Yellow => "33",
+            Self::Dim => "2",
+        }
+    }
+}
+
+/// Set how output is shaped for the rest of the process.
+pub fn init(quiet: bool, porcelain: bool, color: ColorChoice) {
+    QUIET.store(quiet, Ordering::Relaxed);
+    PORCELAIN.store(porcelain, Ordering::Relaxed);
+    // See https://no-color.org: only a non-empty value counts.
+    let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let color = match color {
+        ColorChoice::Auto => io::stdout().is_terminal() && !no_color,
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    };
+    COLOR.store(color && !porcelain, Ordering::Relaxed);
+}
+
+/// Whether narration is dropped.
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Whether stdout is meant for scripts rather than people.
+pub fn porcelain() -> bool {
+    PORCELAIN.load(Ordering::Relaxed)
+}
+
+/// Join `columns` into a line of tab-separated fields. Under `--porcelain`, backslashes,
+/// tabs, and line breaks in them are written as `\\`, `\t`, `\n`, and `\r`, so that
+/// every field stays in its place.
+pub fn row(columns: impl IntoIterator<Item = impl AsRef<str>>) -> String {
+    let porcelain = porcelain();
+    columns
+        .into_iter()
+        .map(|column| match porcelain {
+            true => field(column.as_ref()),
+            false => String::from(column.as_ref()),
+        })
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
+/// `text` in `color`, if output is colored.
+pub fn paint(text: &str, color: Color) -> String {
+    match COLOR.load(Ordering::Relaxed) {
+        true => format!("\x1b[{}m{text}\x1b[0m", color.code()),
+        false => String::from(text),
+    }
+}
+
+/// When `then` was: for people, how long ago, such as "3h ago";
+/// for scripts, an RFC 3339 timestamp.
+pub fn time(then: Timestamp) -> String {
+    match porcelain() {
+        true => then.to_string(),
+        false => format!("{} ago", age(Timestamp::now(), then)),
+    }
+}
+
+/// How long before `now` `then` was, to the largest whole unit, such as "3h".
+pub fn age(now: Timestamp, then: Timestamp) -> String {
+    let seconds = now.duration_since(then).as_secs().max(0);
+    match seconds {
+        0..60 => format!("{seconds}s"),
+        60..3600 => format!("{}m", seconds / 60),
+        3600..86400 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+fn field(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Narrate what's happening on stderr, like `eprintln!`, unless `--quiet` was given.
+macro_rules! narrate {
+    ($($arg:tt)*) => {
+        if !$crate::output::quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use narrate;
+
+/// Events that tell the user what winlock is waiting for and what went wrong along
+/// the way, then are passed on to `also`, such as to write them for `--json-events`.
+pub fn events(also: Events) -> Events {
+    Events::from_fn(move |event| {
+        tell(event);
+        also.emit(event);
+    })
+}
+
+/// Tell the user about `event`, if it's one they aren't otherwise told about.
+fn tell(event: &Event) {
+    match event {
+        Event::Waiting { on } => narrate!("Waiting for {on}"),
+        Event::Warning { message } => eprintln!("warning: {message}"),
+        Event::BackendRestarting {
+            attempt,
+            max_attempts,
+            delay_ms,
+        } => {
+            let of = max_attempts
+                .map(|max| format!(" of {max}"))
+                .unwrap_or_default();
+            narrate!(
+                "Backend exited unsuccessfully; restarting in {}s (attempt {attempt}{of})",
+                *delay_ms as f64 / 1000.0
+            );
+        }
+        Event::BackendDetached {
+            multiplexer: kind,
+            window,
+        } => narrate!(
+            "The backend is running in {kind} window '{window}' of session '{SESSION}'; \
+             reattach with {}",
+            multiplexer::attach_hint(*kind)
+        ),
+        _ => {}
+    }
+}
@@ -0,0 +1,155 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    env,
+    fs::File,
+    num::NonZero,
+    path::Path,
+    process::{Command, ExitCode, Stdio},
+};
+
+use clap::Subcommand;
+use color_eyre::{
+    eyre::{bail, Context},
+    Result,
+};
+use winlock::queue::{Queue, QueuedRunState};
+
+use crate::output::{self, narrate, Color};
+
+/// Commands for runs queued with `anna agent --queue`, which belong to every project.
+#[derive(Debug, Subcommand)]
+pub enum Commands {
+    /// List the queued runs, oldest first: each one's number, state, project,
+    /// when it was queued, the command it runs, and the log of its output.
+    List,
+
+    /// Run the queued runs, oldest first, a few at a time, until none are left,
+    /// including any queued meanwhile. Each one's output goes to its log.
+    ///
+    /// Runs left unfinished by an earlier `anna queue run` that was stopped are run again.
+    /// Exits unsuccessfully if any run failed.
+    Run {
+        /// How many runs may run at once.
+        #[arg(long, value_name = "N", default_value = "1")]
+        max_parallel: NonZero<usize>,
+    },
+
+    /// Take a run out of the queue, so that it isn't run, or forget a finished one.
+    Remove {
+        /// The run's number.
+        id: u64,
+    },
+}
+
+pub fn main(command: Commands) -> Result<ExitCode> {
+    let queue = Queue::open()?;
+    match command {
+        Commands::List => {
+            for run in queue.list()? {
+                println!(
+                    "{}",
+                    output::row([
+                        run.id.to_string(),
+                        state(&run.state),
+                        run.project.display().to_string(),
+                        output::time(run.queued_at),
+                        shell_words::join(&run.args),
+                        queue.log_path(run.id).display().to_string(),
+                    ])
+                );
+            }
+        }
+        Commands::Run { max_parallel } => {
+            let program = env::current_exe().context("find the anna executable")?;
+            let finished = queue.run(max_parallel, |run| {
+                narrate!(
+                    "Starting run {}: anna {}",
+                    run.id,
+                    shell_words::join(&run.args)
+                );
+                let path = queue.log_path(run.id);
+                let log = File::create(&path)
+                    .with_context(|| format!("create log: {}", path.display()))?;
+                let status = Command::new(&program)
+                    .args(&run.args)
+                    .current_dir(&run.dir)
+                    .stdin(Stdio::null())
+                    .stdout(log.try_clone().context("open log for stderr")?)
+                    .stderr(log)
+                    .status()
+                    .with_context(|| format!("run {}", program.display()))?;
+                narrate!(
+                    "Run {} exited with {status}; its log: {}",
+                    run.id,
+                    path.display()
+                );
+                Ok(status)
+            })?;
+            if finished.is_empty() {
+                narrate!("Nothing is queued");
+                return Ok(ExitCode::SUCCESS);
+            }
+            let failed = finished
+                .iter()
+                .filter(|run| !run.state.succeeded())
+                .collect::<Vec<_>>();
+            for run in &failed {
+                if let QueuedRunState::Finished {
+                    error: Some(error), ..
+                } = &run.state
+                {
+                    eprintln!("Run {} failed to start: {error}", run.id);
+                }
+            }
+            narrate!(
+                "Ran {} queued runs, {} failed",
+                finished.len(),
+                failed.len()
+            );
+            if !failed.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Commands::Remove { id } => {
+            let Some(run) = queue.remove(id)? else {
+                bail!("no queued run {id}");
+            };
+            narrate!(
+                "Took run {id} out of the queue: anna {}",
+                shell_words::join(&run.args)
+            );
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Queue a run of `anna` with `args`, from the current directory, working on `project`,
+/// printing the run's number.
+pub fn push(project: &Path, args: Vec<String>) -> Result<ExitCode> {
+    let dir = env::current_dir().context("get current directory")?;
+    let run = Queue::open()?.push(project, &dir, args)?;
+    narrate!(
+        "Queued run {}; start the queue with `anna queue run`",
+        run.id
+    );
+    println!("{}", run.id);
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The state of a queued run, colored for people.
+fn state(state: &QueuedRunState) -> String {
+    let color = match state {
+        QueuedRunState::Pending => None,
+        QueuedRunState::Running { .. } => Some(Color::Yellow),
+        state if state.succeeded() => Some(Color::Green),
+        QueuedRunState::Finished { .. } => Some(Color::Red),
+    };
+    let text = state.to_string();
+    match color {
+        Some(color) => output::paint(&text, color),
+        None => text,
+    }
+}
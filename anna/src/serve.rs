@@ -0,0 +1,34 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{net::IpAddr, process::ExitCode};
+
+use color_eyre::Result;
+use winlock::{dashboard::Dashboard, session::Sessions};
+
+use crate::output::narrate;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// The port to listen on.
+    #[arg(long, default_value = "7777")]
+    port: u16,
+
+    /// The address to listen on; only this machine can connect by default.
+    /// Pass `0.0.0.0` to let the team's dashboard connect, bearing in mind
+    /// that anyone who can reach the port can see every session.
+    #[arg(long, value_name = "ADDR", default_value = "127.0.0.1")]
+    bind: IpAddr,
+}
+
+/// Serve the dashboard until interrupted.
+pub fn main(args: Args) -> Result<ExitCode> {
+    let dashboard = Dashboard::bind(Sessions::open_read_only()?, (args.bind, args.port))?;
+    let addr = dashboard
+        .local_addr()
+        .unwrap_or((args.bind, args.port).into());
+    narrate!("Serving the dashboard at http://{addr}/, and its API under /api");
+    dashboard.serve();
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,1783 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    num::NonZero,
+    path::{self, Path, PathBuf},
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Subcommand;
+use color_eyre::{
+    eyre::{bail, eyre, Context},
+    Report, Result, Section,
+};
+use jiff::Timestamp;
+use winlock::{
+    branch::BranchName,
+    config::Config,
+    forge::{PullRequest, PullRequestDraft, Repository},
+    history::HistoryEvent,
+    review,
+    session::{ProjectStats, Removal, Session, SessionDetails, Sessions, WorkspaceState},
+    snapshot::Snapshots,
+    transcript::{self, Transcript},
+    usage,
+    vcs::{ConflictOperation, ConflictReport, UnsavedWork, VcsKind},
+    workspace, Agent,
+};
+
+use crate::{
+    agent, daemon, editor, forge,
+    output::{self, narrate, Color},
+};
+
+/// Commands for groups of sessions, which belong to the current project
+/// as [`Commands`] do.
+#[derive(Debug, Subcommand)]
+pub enum GroupCommands {
+    /// List the project's groups: each one's name, number of sessions, and description.
+    List,
+
+    /// List the sessions in a group with what's changed in each: their branch,
+    /// workspace, state as in `list`, and a summary of their commits.
+    Show {
+        /// The group.
+        group: String,
+    },
+
+    /// Describe what a group's sessions are working towards.
+    Describe {
+        /// The group.
+        group: String,
+
+        /// The description.
+        description: String,
+    },
+
+    /// Put sessions in a group, taking them out of any other.
+    Add {
+        /// The group.
+        group: String,
+
+        /// The branches of the sessions.
+        #[arg(required = true)]
+        branches: Vec<BranchName>,
+    },
+
+    /// Take sessions out of their group.
+    Leave {
+        /// The branches of the sessions.
+        #[arg(required = true)]
+        branches: Vec<BranchName>,
+    },
+
+    /// Remove every session in a group, deleting their workspaces, and forget the group.
+    ///
+    /// Removal is refused if any of the workspaces has work the project doesn't,
+    /// listing it, unless `--force` is passed.
+    Remove {
+        /// The group.
+        group: String,
+
+        /// Return once the sessions are removed, deleting their workspaces in the background.
+        #[arg(long)]
+        no_wait: bool,
+
+        /// Remove the sessions even if that loses work.
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Remove the sessions in a group whose work the project already has,
+    /// such as ones merged back, keeping the rest.
+    Prune {
+        /// The group.
+        group: String,
+
+        /// Return once the sessions are removed, deleting their workspaces in the background.
+        #[arg(long)]
+        no_wait: bool,
+    },
+}
+
+/// Session commands act on the current project: the current directory, or the one
+/// passed with `-C`. When that is inside a session's workspace, the project is
+/// the one the workspace was copied from.
+///
+/// Commands taking a session's branch also take its short ID, as `list` shows it,
+/// such as `anna session remove a1b2c3`; that finds the session whichever project
+/// it belongs to, unless the current project has a session with that branch.
+#[derive(Debug, Subcommand)]
+pub enum Commands {
+    /// List sessions for the current project.
+    ///
+    /// After the workspace, a column shows the workspace's state: verified, failing,
+    /// unverified, archived, or missing. With `--porcelain`, it shows whether it passed
+    /// its most recent verification instead: ✅ if it passed, ❌ if it failed, or - if it
+    /// hasn't been verified. The next shows how the branch and the project's default
+    /// branch diverged, such as +5/-2: five commits the default branch doesn't have yet,
+    /// and two it gained since the branch was created. With `--porcelain`, those are two
+    /// columns. Then come when the session was created and last active, such as
+    /// "created 3h ago"; with `--porcelain`, as timestamps. The last is the session's
+    /// short ID, which tells apart sessions of different projects with the same branch.
+    List {
+        /// List sessions for all projects.
+        #[arg(long)]
+        all: bool,
+
+        /// Also show the tokens each session's backends used and their estimated cost,
+        /// followed by the total on stderr.
+        ///
+        /// Costs are estimated from list prices, and shown as ? if a model's price is unknown.
+        #[arg(long)]
+        costs: bool,
+
+        /// List only the sessions in this group.
+        #[arg(long, value_name = "NAME")]
+        group: Option<String>,
+
+        /// Ask the daemon for the sessions, which it has at hand, rather than reading
+        /// the session store; see `anna daemon`. Its list may lag by up to a second.
+        #[arg(long)]
+        via_daemon: bool,
+    },
+
+    /// Remove a session of the current project, deleting its workspace.
+    ///
+    /// Adopted workspaces are not deleted. Removal is refused if the workspace has
+    /// uncommitted changes or commits the project doesn't have, listing them,
+    /// unless `--force` is passed.
+    Remove {
+        /// The branch of the session to remove.
+        branch: BranchName,
+
+        /// Return once the session is removed, deleting its workspace in the background.
+        #[arg(long)]
+        no_wait: bool,
+
+        /// Remove the session even if that loses work.
+        #[arg(long)]
+        force: bool,
+
+        /// First fetch the branch into the project as `refs/anna/<branch>`,
+        /// as `anna session fetch` does, so that its commits are kept.
+        #[arg(long)]
+        fetch: bool,
+    },
+
+    /// Manage groups of related sessions, such as the agents working on parts of
+    /// one larger change. Sessions join groups with `anna agent --group`.
+    #[command(subcommand)]
+    Group(GroupCommands),
+
+    /// Find workspaces no session tracks anymore, of any project, and delete them.
+    ///
+    /// These are directories in the workspace root marked as belonging to a session
+    /// the store doesn't have, such as ones left behind by a removal that failed.
+    /// Prints each one; without `--force`, nothing is deleted.
+    Clean {
+        /// Delete the workspaces rather than only listing them.
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Bring files that are new or changed in the project into a session's workspace.
+    ///
+    /// Only files that differ from the project are copied, and nothing is deleted.
+    /// Files the agent changed are left alone and reported, even if they changed
+    /// in the project too. The branch itself isn't touched; to bring in new commits,
+    /// resume the session with `anna agent --rebase`.
+    Refresh {
+        /// The branch of the session.
+        branch: BranchName,
+    },
+
+    /// Compress a session's workspace into anna's state directory and delete it,
+    /// to save disk space while the session is idle.
+    ///
+    /// The workspace is restored where it was when the session is next resumed.
+    Archive {
+        /// The branch of the session.
+        branch: BranchName,
+    },
+
+    /// Print the path of a session's workspace, for use in shell scripts.
+    ///
+    /// For example: `cd "$(anna session path my-feature)"`.
+    Path {
+        /// The branch of the session.
+        branch: BranchName,
+
+        /// Print the directory of the project the session belongs to instead.
+        #[arg(long)]
+        project_dir: bool,
+
+        /// Print every workspace of the session, its linked workspaces included,
+        /// one per line; with --project-dir, every project.
+        #[arg(long)]
+        all: bool,
+    },
+
+    /// Open a session's workspace in your editor, to review the agent's work there.
+    ///
+    /// Uses the `editor` setting, else $VISUAL, else VS Code or Zed, whichever is
+    /// installed. To open it while starting the agent, use `anna agent --code`.
+    Code {
+        /// The branch of the session.
+        branch: BranchName,
+    },
+
+    /// Ask a backend to review a session's changes, for a second opinion before merging them,
+    /// and write its review to `REVIEW.md` in the workspace, which is kept out of version
+    /// control. Prints the review's path.
+    ///
+    /// The backend is given the session's diff and prompt, and runs the command
+    /// configured as its profile's `review`; the built-in profile uses `claude --print`.
+    Review {
+        /// The branch of the session.
+        branch: BranchName,
+
+        /// The profile of the reviewing backend; defaults to the configured profile.
+        #[arg(long)]
+        profile: Option<String>,
+    },
+
+    /// Fetch a session's branch into the project as `refs/anna/<branch>`, without
+    /// merging it or changing what's checked out, to inspect the agent's commits
+    /// with the usual tools while it keeps working: `git log refs/anna/<branch>`.
+    ///
+    /// Prints the fetched reference and the commit it points to.
+    Fetch {
+        /// The branch of the session.
+        branch: BranchName,
+    },
+
+    /// Merge a session's branch back into the project's branch of the same name,
+    /// creating or fast-forwarding it; a branch that moved on has to be merged by hand.
+    ///
+    /// With `--and-remove`, or `remove_merged = true` in the configuration, the session
+    /// is then removed along with its workspace, refusing if the workspace has uncommitted
+    /// changes unless `--force` is passed. Nothing can resume it in between.
+    Merge {
+        /// The branch of the session.
+        branch: BranchName,
+
+        /// Remove the session once its branch is merged.
+        #[arg(long, conflicts_with = "keep")]
+        and_remove: bool,
+
+        /// Keep the session even if `remove_merged` is configured.
+        #[arg(long)]
+        keep: bool,
+
+        /// Remove the session even if its uncommitted changes are lost.
+        #[arg(long)]
+        force: bool,
+
+        /// Return once the session is removed, deleting its workspace in the background.
+        #[arg(long)]
+        no_wait: bool,
+    },
+
+    /// Run the verify command in every session of the current project, recording whether
+    /// each passed as `anna agent --verify` does, and print a scoreboard: a tab-separated
+    /// line per session with its branch, whether it passed, failed, or was skipped, how long
+    /// verification took, and the log of its output, or else why it was skipped.
+    ///
+    /// Sessions are skipped while a backend runs in them, or while they're archived.
+    /// Exits unsuccessfully if any session failed verification.
+    RunVerifyAll {
+        /// The command to run, instead of the configured `verify` command.
+        #[arg(long, value_name = "CMD")]
+        command: Option<String>,
+
+        /// How many sessions to verify at once.
+        #[arg(short, long, value_name = "N", default_value_t = NonZero::<usize>::MIN)]
+        jobs: NonZero<usize>,
+
+        /// Verify only the sessions in this group.
+        #[arg(long, value_name = "NAME")]
+        group: Option<String>,
+    },
+
+    /// Attach this terminal to the backend running in a session, to watch or take over
+    /// from it. Only backends run in a terminal multiplexer, with `anna agent --mux`,
+    /// can be attached to; detaching leaves them running.
+    Attach {
+        /// The branch of the session.
+        branch: BranchName,
+    },
+
+    /// Play back a recorded transcript of a session's backend runs.
+    Replay {
+        /// The branch of the session.
+        branch: BranchName,
+
+        /// Which transcript to play, as numbered by `--list`; defaults to the latest.
+        #[arg(long)]
+        index: Option<usize>,
+
+        /// List the session's transcripts instead of playing one.
+        #[arg(long)]
+        list: bool,
+
+        /// Playback speed multiplier.
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+
+        /// Shorten pauses longer than this many seconds.
+        #[arg(long, default_value_t = 2.0)]
+        max_idle: f64,
+    },
+
+    /// Snapshot a session's workspace, including uncommitted changes.
+    Snapshot {
+        /// The branch of the session.
+        branch: BranchName,
+
+        /// A description of the snapshot.
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// List the session's snapshots instead of taking one.
+        #[arg(long, conflicts_with = "message")]
+        list: bool,
+    },
+
+    /// Return a session's workspace to a snapshot, discarding everything that changed since.
+    Rollback {
+        /// The branch of the session.
+        branch: BranchName,
+
+        /// The snapshot to return to, as numbered by `snapshot --list`.
+        snapshot: u32,
+    },
+
+    /// Push a session's branch and open a pull request for it.
+    ///
+    /// The title and description are drafted from the session's prompt
+    /// and a summary of its changes. GitHub, GitLab, and Gitea are supported;
+    /// which one hosts the remote is detected from its URL.
+    ///
+    /// Authenticates with `GITHUB_TOKEN` or `GH_TOKEN` (or the GitHub CLI's login),
+    /// `GITLAB_TOKEN` (or the GitLab CLI's), or `GITEA_TOKEN` respectively.
+    Pr {
+        /// The branch of the session.
+        branch: BranchName,
+
+        /// The branch the changes are proposed for; defaults to the project's default branch.
+        #[arg(long)]
+        base: Option<String>,
+
+        /// The title of the pull request, instead of one drafted from the prompt.
+        #[arg(long)]
+        title: Option<String>,
+
+        /// The remote to push to.
+        #[arg(long, default_value = "origin")]
+        remote: String,
+    },
+
+    /// Make a session's branch track the branch of the same name on a remote, so that
+    /// plain `git push` and `git pull` in its workspace go there. The remote's branch
+    /// needn't exist yet. Set `upstream` in the configuration to do this for every new
+    /// session.
+    SetUpstream {
+        /// The branch of the session.
+        branch: BranchName,
+
+        /// The remote to track.
+        #[arg(long, default_value = "origin")]
+        remote: String,
+    },
+
+    /// Show the details of a session, one tab-separated field per line:
+    /// its workspace, base revision, backends, prompt, changes, disk usage,
+    /// and the environment it was created in.
+    Info {
+        /// The branch of the session.
+        branch: BranchName,
+
+        /// Print the details as JSON instead, including the full prompt and diff summary.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print every prompt given to a session, oldest first: the one it started with,
+    /// then any given when it was resumed. Each is preceded by a tab-separated line
+    /// with its number and when it was given, and followed by a blank line.
+    PromptHistory {
+        /// The branch of the session.
+        branch: BranchName,
+
+        /// Print the prompts as JSON instead.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print what happened to the sessions of a branch, oldest first, including ones since
+    /// removed: when each was created, resumed, verified, merged, or removed, and each exit
+    /// of a backend. One tab-separated line per event, with when it happened, its kind,
+    /// and a description.
+    History {
+        /// The branch.
+        branch: BranchName,
+
+        /// Print the events as JSON instead, with each one's session ID.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Summarize the sessions of every project: how many are in each state,
+    /// how many have a backend running, the disk space they take up, and how old
+    /// the oldest is, as a tab-separated table with a header and a total row.
+    Stat {
+        /// Print the summary as JSON instead, with the branches of running sessions.
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Report which process holds the lock on the current project's sessions.
+    WhyLocked,
+
+    /// Register an existing checkout of a branch as a session of the current project.
+    Adopt {
+        /// The branch checked out in the workspace.
+        branch: BranchName,
+
+        /// The directory containing the checkout, such as a git worktree.
+        workspace: PathBuf,
+    },
+
+    /// Move the sessions recorded for the project's previous directory to the current
+    /// project, after the project was moved, so that they're found again.
+    ///
+    /// Sessions record an ID for their project: its `.anna/project-id` file if it has one,
+    /// else its first commit. Relinking is refused if it doesn't match the current
+    /// project's, or if the previous directory still exists, unless `--force` is passed.
+    Relink {
+        /// The directory the project was in.
+        old: PathBuf,
+
+        /// Relink the sessions even if they don't seem to belong to the current project.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+impl Commands {
+    /// The branch naming the session the command acts on, if it acts on one.
+    fn branch_mut(&mut self) -> Option<&mut BranchName> {
+        match self {
+            Commands::Remove { branch, .. }
+            | Commands::Refresh { branch, .. }
+            | Commands::Archive { branch, .. }
+            | Commands::Path { branch, .. }
+            | Commands::Code { branch, .. }
+            | Commands::Review { branch, .. }
+            | Commands::Fetch { branch, .. }
+            | Commands::Merge { branch, .. }
+            | Commands::Attach { branch, .. }
+            | Commands::Replay { branch, .. }
+            | Commands::Snapshot { branch, .. }
+            | Commands::Rollback { branch, .. }
+            | Commands::Pr { branch, .. }
+            | Commands::SetUpstream { branch, .. }
+            | Commands::Info { branch, .. }
+            | Commands::PromptHistory { branch, .. }
+            | Commands::History { branch, .. } => Some(branch),
+            Commands::List { .. }
+            | Commands::Group(_)
+            | Commands::Clean { .. }
+            | Commands::RunVerifyAll { .. }
+            | Commands::Stat { .. }
+            | Commands::WhyLocked
+            | Commands::Adopt { .. }
+            | Commands::Relink { .. } => None,
+        }
+    }
+}
+
+pub fn main(
+    config: &Config,
+    sessions: &Sessions,
+    dir: &Path,
+    command: Commands,
+) -> Result<ExitCode> {
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("canonicalize {}", dir.display()))?;
+    let project = match sessions.containing(&dir)? {
+        Some(session) => session.project,
+        None => dir,
+    };
+    let mut command = command;
+    let project = match command.branch_mut() {
+        Some(branch) => resolve_id(sessions, project, branch)?,
+        None => project,
+    };
+
+    match command {
+        Commands::List {
+            all,
+            costs,
+            group,
+            via_daemon,
+        } => {
+            let mut listed: Vec<_> = match (all, via_daemon) {
+                (true, false) => sessions.iter()?.skip_unreadable().collect(),
+                (false, false) => sessions.list(&project)?,
+                (true, true) => daemon::sessions(None)?,
+                (false, true) => daemon::sessions(Some(&project))?,
+            };
+            if let Some(group) = &group {
+                listed.retain(|session| session.group.as_ref() == Some(group));
+            }
+            listed.sort_by(|a, b| (&a.project, &a.branch).cmp(&(&b.project, &b.branch)));
+            let short_ids = sessions.short_ids()?;
+            for session in &listed {
+                let mut columns = Vec::new();
+                if all {
+                    columns.push(session.project.display().to_string());
+                }
+                columns.push(session.branch.to_string());
+                columns.push(session.workspace.display().to_string());
+                columns.push(status(session));
+                let divergence = session.divergence().ok().flatten();
+                // Scripts get the counts on their own, rather than as one field to pick apart.
+                match (divergence, output::porcelain()) {
+                    (Some(divergence), true) => columns
+                        .extend([divergence.ahead.to_string(), divergence.behind.to_string()]),
+                    (None, true) => columns.extend([String::from("-"), String::from("-")]),
+                    (Some(divergence), false) => columns.push(divergence.to_string()),
+                    (None, false) => columns.push(String::from("-")),
+                }
+                if costs {
+                    let (tokens, cost) = usage::total(session.usage.values());
+                    columns.push(tokens.total().to_string());
+                    columns.push(format_cost(cost));
+                }
+                let (created, active) = (session.created_at, session.last_active());
+                match output::porcelain() {
+                    true => columns.extend([created.to_string(), active.to_string()]),
+                    false => columns.extend([
+                        format!("created {}", output::time(created)),
+                        format!("last active {}", output::time(active)),
+                    ]),
+                }
+                columns.push(
+                    short_ids
+                        .get(&session.id)
+                        .cloned()
+                        .unwrap_or_else(|| session.short_id()),
+                );
+                println!("{}", output::row(&columns));
+            }
+            if listed.is_empty() && !all {
+                hint_moved(config, sessions, &project)?;
+            }
+            if costs {
+                let (tokens, cost) =
+                    usage::total(listed.iter().flat_map(|session| session.usage.values()));
+                narrate!(
+                    "Total: {} tokens, {} across {} sessions",
+                    tokens.total(),
+                    format_cost(cost),
+                    listed.len()
+                );
+            }
+        }
+        Commands::Remove {
+            branch,
+            no_wait,
+            force,
+            fetch,
+        } => {
+            let session = get(sessions, &project, &branch)?;
+            if fetch {
+                session.fetch_into_project()?;
+                narrate!(
+                    "Fetched '{branch}' into {} as {}",
+                    project.display(),
+                    session.fetched_ref()
+                );
+            }
+            remove(
+                sessions,
+                &project,
+                &branch,
+                no_wait,
+                |session| match force {
+                    true => Ok(()),
+                    false => ensure_nothing_unsaved(session),
+                },
+            )?;
+        }
+        Commands::Group(command) => return group(sessions, &project, command),
+        Commands::Clean { force } => {
+            let untracked = sessions.untracked_workspaces(&workspace::root())?;
+            for (dir, marker) in &untracked {
+                println!(
+                    "{}",
+                    output::row([
+                        dir.display().to_string(),
+                        marker.project.display().to_string(),
+                        marker.branch.to_string(),
+                    ])
+                );
+                if force {
+                    workspace::delete_dir(dir)?;
+                }
+            }
+            match (untracked.len(), force) {
+                (0, _) => narrate!("No untracked workspaces"),
+                (n, true) => narrate!("Deleted {n} untracked workspaces"),
+                (n, false) => {
+                    narrate!("Found {n} untracked workspaces; pass --force to delete them")
+                }
+            }
+        }
+        Commands::Refresh { branch } => {
+            // Linked projects withhold what their own settings say to.
+            let mut config = config.clone();
+            for linked in &get(sessions, &project, &branch)?.linked {
+                config.load_project(&linked.project)?;
+            }
+            let Some(reports) = sessions
+                .refresh(&project, &branch, |project| config.secret_patterns(project))
+                .map_err(agent::explain_copy_failure)?
+            else {
+                bail!("no session for '{branch}' in {}", project.display());
+            };
+            for (index, (workspace, report)) in reports.iter().enumerate() {
+                for warning in &report.copy.warnings {
+                    eprintln!("warning: {warning}");
+                }
+                for path in &report.conflicts {
+                    narrate!("Kept the workspace's changes to {}", path.display());
+                }
+                let refreshed = match index {
+                    0 => format!("'{branch}'"),
+                    _ => format!("'{branch}' in linked workspace {}", workspace.display()),
+                };
+                narrate!(
+                    "Refreshed {refreshed}: copied {} files ({}), {} unchanged, {} changed in the workspace",
+                    report.copy.files,
+                    workspace::format_size(report.copy.bytes),
+                    report.unchanged,
+                    report.conflicts.len()
+                );
+            }
+        }
+        Commands::Archive { branch } => {
+            let session = get(sessions, &project, &branch)?;
+            let usage = workspace::disk_usage(&session.workspace).ok();
+            let Some(path) = sessions.archive(&project, &branch)? else {
+                bail!("no session for '{branch}' in {}", project.display());
+            };
+            let size = fs::metadata(&path)
+                .with_context(|| format!("read metadata: {}", path.display()))?
+                .len();
+            match usage {
+                Some(usage) => narrate!(
+                    "Archived '{branch}', shrinking its workspace from {} to {}",
+                    workspace::format_size(usage),
+                    workspace::format_size(size)
+                ),
+                None => narrate!("Archived '{branch}' ({})", workspace::format_size(size)),
+            }
+            println!("{}", path.display());
+        }
+        Commands::Path {
+            branch,
+            project_dir: print_project,
+            all,
+        } => {
+            let session = get(sessions, &project, &branch)?;
+            let (path, linked) = if print_project {
+                let linked = session.linked.into_iter().map(|linked| linked.project);
+                (session.project, linked.collect::<Vec<_>>())
+            } else {
+                let linked = session.linked.into_iter().map(|linked| linked.workspace);
+                (session.workspace, linked.collect())
+            };
+            println!("{}", path.display());
+            if all {
+                for path in linked {
+                    println!("{}", path.display());
+                }
+            }
+        }
+        Commands::Code { branch } => {
+            let session = get(sessions, &project, &branch)?;
+            if session.archived {
+                return Err(eyre!("session '{branch}' is archived")).suggestion(format!(
+                    "resume it with `anna agent {branch}` to restore its workspace"
+                ));
+            }
+            editor::open(config, &session.workspace)?;
+        }
+        Commands::Review { branch, profile } => {
+            let session = get(sessions, &project, &branch)?;
+            if session.archived {
+                return Err(eyre!("session '{branch}' is archived")).suggestion(format!(
+                    "resume it with `anna agent {branch}` to restore its workspace"
+                ));
+            }
+            let name = profile.as_deref().unwrap_or(&config.profile);
+            let Some(template) = &config.profile(Some(name))?.review else {
+                return Err(eyre!("profile '{name}' has no review command")).suggestion(format!(
+                    "set `review` in [profiles.{name}], such as `review = \"claude --print\"`"
+                ));
+            };
+            narrate!("Reviewing '{branch}' with {}", template.program());
+            let path = review::review(&session, template)?;
+            narrate!("Wrote the review of '{branch}'");
+            println!("{}", path.display());
+        }
+        Commands::Fetch { branch } => {
+            let session = get(sessions, &project, &branch)?;
+            session.fetch_into_project()?;
+            let reference = session.fetched_ref();
+            let commit = session.vcs.vcs().resolve(&project, &reference)?;
+            narrate!("Fetched '{branch}' into {}", project.display());
+            println!("{}", output::row([reference.as_str(), commit.as_str()]));
+        }
+        Commands::Merge {
+            branch,
+            and_remove,
+            keep,
+            force,
+            no_wait,
+        } => {
+            let session = get(sessions, &project, &branch)?;
+            if !(and_remove || config.remove_merged && !keep) {
+                session.merge_back().map_err(explain_conflict)?;
+                sessions.record(&session, HistoryEvent::Merged);
+                narrate!("Merged '{branch}' into {}", project.display());
+                return Ok(ExitCode::SUCCESS);
+            }
+            // Commits are merged, so only uncommitted changes would be lost.
+            let check = |session: &Session| {
+                if force || session.adopted {
+                    return Ok(());
+                }
+                let uncommitted = session
+                    .unsaved_work()
+                    .suggestion("pass --force to remove the session without checking")?
+                    .into_iter()
+                    .map(|(workspace, work)| (workspace, UnsavedWork { commits: 0, ..work }))
+                    .filter(|(_, work)| !work.is_empty())
+                    .collect::<Vec<_>>();
+                if uncommitted.is_empty() {
+                    return Ok(());
+                }
+                list_unsaved(&uncommitted);
+                Err(eyre!(
+                    "removing '{branch}' would lose the changes listed above"
+                ))
+                .suggestion(
+                    "commit or discard them, merge with --keep, or pass --force \
+                     to remove the session anyway",
+                )
+            };
+            let Some((session, removal)) = sessions
+                .merge_and_remove(&project, &branch, check)
+                .map_err(explain_conflict)?
+            else {
+                bail!("no session for '{branch}' in {}", project.display());
+            };
+            narrate!("Merged '{branch}' into {}", project.display());
+            finish_removal(&session, removal, no_wait)?;
+        }
+        Commands::RunVerifyAll {
+            command,
+            jobs,
+            group,
+        } => {
+            let mut config = config.clone();
+            if command.is_none() {
+                crate::config::load_project(&mut config, &project, true)?;
+            }
+            let Some(command) = command.or_else(|| config.verify(&project)) else {
+                return Err(eyre!(
+                    "no verify command is configured for {}",
+                    project.display()
+                ))
+                .suggestion("set `verify` in the configuration, or pass --command");
+            };
+            return verify_all(sessions, &project, &command, jobs, group.as_deref());
+        }
+        Commands::Attach { branch } => attach(sessions, &project, &branch)?,
+        Commands::Replay {
+            branch,
+            index,
+            list,
+            speed,
+            max_idle,
+        } => {
+            get(sessions, &project, &branch)?;
+            let dir = transcript::dir(&sessions.data_dir(&project, &branch));
+            let transcripts = transcript::list(&dir)?;
+
+            if list {
+                for (i, path) in transcripts.iter().enumerate() {
+                    println!(
+                        "{}",
+                        output::row([(i + 1).to_string(), path.display().to_string()])
+                    );
+                }
+                return Ok(ExitCode::SUCCESS);
+            }
+
+            let path = match index {
+                Some(index) => index.checked_sub(1).and_then(|i| transcripts.get(i)),
+                None => transcripts.last(),
+            };
+            let Some(path) = path else {
+                bail!("no such transcript for '{branch}'; see --list");
+            };
+
+            let max_idle = Duration::try_from_secs_f64(max_idle).context("parse --max-idle")?;
+            Transcript::read(path)?.replay(&mut io::stdout(), speed, max_idle)?;
+        }
+        Commands::Snapshot {
+            branch,
+            message: _,
+            list: true,
+        } => {
+            let snapshots = Snapshots::new(&sessions.data_dir(&project, &branch));
+            for snapshot in snapshots.list()? {
+                println!(
+                    "{}",
+                    output::row([
+                        snapshot.id.to_string(),
+                        output::time(snapshot.created_at),
+                        snapshot.message.unwrap_or_default(),
+                    ])
+                );
+            }
+        }
+        Commands::Snapshot {
+            branch,
+            message,
+            list: false,
+        } => {
+            let snapshots = Snapshots::new(&sessions.data_dir(&project, &branch));
+            let snapshot = snapshots.create(sessions, &project, &branch, message.as_deref())?;
+            narrate!("Created snapshot {} of '{branch}'", snapshot.id);
+            println!("{}", snapshot.id);
+        }
+        Commands::Rollback { branch, snapshot } => {
+            let snapshots = Snapshots::new(&sessions.data_dir(&project, &branch));
+            snapshots.rollback(sessions, &project, &branch, snapshot)?;
+            narrate!("Rolled '{branch}' back to snapshot {snapshot}");
+        }
+        Commands::Pr {
+            branch,
+            base,
+            title,
+            remote,
+        } => {
+            let session = get(sessions, &project, &branch)?;
+            let pr = pull_request(config, &session, base, title, &remote)?;
+            narrate!("Opened pull request #{} for '{branch}'", pr.number);
+            println!("{}", pr.url);
+        }
+        Commands::SetUpstream { branch, remote } => {
+            get(sessions, &project, &branch)?.set_upstream(&remote)?;
+            narrate!("'{branch}' now tracks {remote}/{branch}");
+        }
+        Commands::Info { branch, json } => {
+            let details = get(sessions, &project, &branch)?.details();
+            if json {
+                let json =
+                    serde_json::to_string_pretty(&details).context("serialize session details")?;
+                println!("{json}");
+            } else {
+                for (field, value) in info(&details) {
+                    println!("{}", output::row([field, value]));
+                }
+            }
+        }
+        Commands::PromptHistory { branch, json } => {
+            let prompts = get(sessions, &project, &branch)?.prompt_history();
+            if json {
+                let json = serde_json::to_string_pretty(&prompts).context("serialize prompts")?;
+                println!("{json}");
+            } else {
+                if prompts.is_empty() {
+                    narrate!("No prompts have been given to '{branch}'");
+                }
+                for (i, record) in prompts.iter().enumerate() {
+                    let (number, given_at) = ((i + 1).to_string(), output::time(record.given_at));
+                    // Prompts span lines, so people get them in full, and scripts escaped.
+                    match output::porcelain() {
+                        true => println!("{}", output::row([&number, &given_at, &record.prompt])),
+                        false => {
+                            println!("{number}\t{given_at}");
+                            println!("{}\n", record.prompt.trim_end());
+                        }
+                    }
+                }
+            }
+        }
+        Commands::History { branch, json } => {
+            let history = sessions.history(&project, &branch)?;
+            if json {
+                let json = serde_json::to_string_pretty(&history).context("serialize history")?;
+                println!("{json}");
+            } else {
+                if history.is_empty() {
+                    narrate!(
+                        "No history is recorded for '{branch}' in {}",
+                        project.display()
+                    );
+                }
+                for entry in &history {
+                    println!(
+                        "{}",
+                        output::row([
+                            entry.at.to_string(),
+                            String::from(entry.event.name()),
+                            entry.event.to_string(),
+                        ])
+                    );
+                }
+            }
+        }
+        Commands::Stat { json } => {
+            let stats = sessions.stats()?;
+            if json {
+                let json = serde_json::to_string_pretty(&stats).context("serialize stats")?;
+                println!("{json}");
+            } else {
+                let states = WorkspaceState::ALL.map(|state| state.to_string());
+                let header = ["project", "sessions", "running"]
+                    .into_iter()
+                    .chain(states.iter().map(String::as_str))
+                    .chain(["disk", "oldest"])
+                    .collect::<Vec<_>>();
+                if !output::porcelain() {
+                    println!("{}", header.join("\t"));
+                }
+
+                let now = Timestamp::now();
+                let mut total = ProjectStats {
+                    disk_usage: stats.disk_usage,
+                    oldest: stats.oldest,
+                    ..ProjectStats::default()
+                };
+                for (project, stat) in &stats.projects {
+                    println!("{}", stat_row(&project.display().to_string(), stat, now));
+                    for (state, count) in &stat.states {
+                        *total.states.entry(*state).or_default() += count;
+                    }
+                    total.running.extend(stat.running.iter().cloned());
+                }
+                println!("{}", stat_row("total", &total, now));
+            }
+        }
+        Commands::WhyLocked => {
+            if !sessions.is_locked(&project)? {
+                narrate!("Sessions for {} are not locked", project.display());
+                return Ok(ExitCode::SUCCESS);
+            }
+            match sessions.lock_owner(&project)? {
+                Some(owner) => println!("{owner}"),
+                None if output::porcelain() => println!("-"),
+                None => println!("an unknown process"),
+            }
+        }
+        Commands::Relink { old, force } => {
+            // The old directory is usually gone, so it can't be canonicalized.
+            let old = old
+                .canonicalize()
+                .or_else(|_| path::absolute(&old))
+                .with_context(|| format!("resolve {}", old.display()))?;
+            let moving = sessions.list(&old)?;
+            if moving.is_empty() {
+                bail!("no sessions are recorded for {}", old.display());
+            }
+            if !force {
+                if old.exists() {
+                    return Err(eyre!("the project still exists: {}", old.display()))
+                        .suggestion("pass --force to relink its sessions anyway");
+                }
+                let id = project_id(config, &project)?;
+                let foreign = moving.iter().find(|session| {
+                    matches!((&session.project_id, &id), (Some(recorded), Some(id)) if recorded != id)
+                });
+                if let Some(session) = foreign {
+                    return Err(eyre!(
+                        "the session for '{}' was created in a different project",
+                        session.branch
+                    ))
+                    .suggestion("pass --force to relink its sessions anyway");
+                }
+            }
+            for session in sessions.relink(&old, &project)? {
+                narrate!("Relinked '{}' to {}", session.branch, project.display());
+            }
+        }
+        Commands::Adopt { branch, workspace } => {
+            let session = sessions.adopt(&project, &branch, &workspace)?;
+            narrate!(
+                "Adopted '{branch}' ({}) as a session: {}",
+                session.vcs,
+                session.workspace.display()
+            );
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The [stable ID](winlock::session::project_id) of the project at `project`.
+fn project_id(config: &Config, project: &Path) -> Result<Option<String>> {
+    let vcs = config
+        .project(project)
+        .vcs
+        .unwrap_or_else(|| VcsKind::detect(project));
+    winlock::session::project_id(project, vcs)
+}
+
+/// Point out sessions recorded for the project before it was moved,
+/// since otherwise they silently disappear.
+fn hint_moved(config: &Config, sessions: &Sessions, project: &Path) -> Result<()> {
+    let Some(id) = project_id(config, project)? else {
+        return Ok(());
+    };
+    for old in sessions.moved(&id)? {
+        narrate!(
+            "Sessions are recorded for this project at {}, which no longer exists; \
+             run `anna session relink {}` to move them here",
+            old.display(),
+            shell_words::quote(&old.to_string_lossy())
+        );
+    }
+    Ok(())
+}
+
+fn group(sessions: &Sessions, project: &Path, command: GroupCommands) -> Result<ExitCode> {
+    let listed = sessions.list(project)?;
+    let members = |group: &str| {
+        let mut members = listed
+            .iter()
+            .filter(|session| session.group.as_deref() == Some(group))
+            .collect::<Vec<_>>();
+        members.sort_by(|a, b| a.branch.cmp(&b.branch));
+        members
+    };
+    let recorded = sessions.groups(project)?;
+    let ensure_exists =
+        |group: &str| match recorded.contains_key(group) || !members(group).is_empty() {
+            true => Ok(()),
+            false => Err(eyre!("no group '{group}' in {}", project.display()))
+                .suggestion("see the project's groups with `anna session group list`"),
+        };
+
+    match command {
+        GroupCommands::List => {
+            let names = recorded
+                .keys()
+                .cloned()
+                .chain(listed.iter().filter_map(|session| session.group.clone()))
+                .collect::<BTreeSet<_>>();
+            for name in names {
+                let description = recorded
+                    .get(&name)
+                    .and_then(|info| info.description.as_deref())
+                    .unwrap_or_default();
+                println!(
+                    "{}",
+                    output::row([
+                        name.as_str(),
+                        &members(&name).len().to_string(),
+                        description
+                    ])
+                );
+            }
+        }
+        GroupCommands::Show { group } => {
+            ensure_exists(&group)?;
+            if let Some(description) = recorded
+                .get(&group)
+                .and_then(|info| info.description.as_ref())
+            {
+                narrate!("{group}: {description}");
+            }
+            for session in members(&group) {
+                // The last line of the summary totals the changes.
+                let changes = session
+                    .diff_stat()
+                    .ok()
+                    .and_then(|stat| stat.lines().last().map(|line| String::from(line.trim())))
+                    .filter(|changes| !changes.is_empty())
+                    .unwrap_or_else(|| String::from("-"));
+                println!(
+                    "{}",
+                    output::row([
+                        session.branch.to_string(),
+                        session.workspace.display().to_string(),
+                        status(session),
+                        changes,
+                    ])
+                );
+            }
+        }
+        GroupCommands::Describe { group, description } => {
+            sessions.describe_group(project, &group, &description)?;
+        }
+        GroupCommands::Add { group, branches } => {
+            for branch in branches {
+                if sessions
+                    .set_group(project, &branch, Some(&group))?
+                    .is_none()
+                {
+                    bail!("no session for '{branch}' in {}", project.display());
+                }
+                narrate!("Moved '{branch}' into group '{group}'");
+            }
+        }
+        GroupCommands::Leave { branches } => {
+            for branch in branches {
+                if sessions.set_group(project, &branch, None)?.is_none() {
+                    bail!("no session for '{branch}' in {}", project.display());
+                }
+                narrate!("Took '{branch}' out of its group");
+            }
+        }
+        GroupCommands::Remove {
+            group,
+            no_wait,
+            force,
+        } => {
+            ensure_exists(&group)?;
+            let members = members(&group);
+            // Checked for every session before removing any, so that the group
+            // isn't left half removed.
+            if !force {
+                let mut unsaved = false;
+                for session in members.iter().filter(|session| !session.adopted) {
+                    unsaved |= check_unsaved(session)?;
+                }
+                if unsaved {
+                    return Err(eyre!(
+                        "removing group '{group}' would lose the work listed above"
+                    ))
+                    .suggestion(format!(
+                        "remove only the sessions without unsaved work with \
+                         `anna session group prune {group}`, or pass --force to remove them all anyway"
+                    ));
+                }
+            }
+            // Checked again as each is removed, in case one was resumed in between.
+            for session in &members {
+                remove(
+                    sessions,
+                    project,
+                    &session.branch,
+                    no_wait,
+                    |session| match force {
+                        true => Ok(()),
+                        false => ensure_nothing_unsaved(session),
+                    },
+                )?;
+            }
+            sessions.forget_group(project, &group)?;
+            narrate!("Removed group '{group}'");
+        }
+        GroupCommands::Prune { group, no_wait } => {
+            ensure_exists(&group)?;
+            let mut kept = 0;
+            for session in members(&group) {
+                // Checked under the session's lock, so that it can't gain work before
+                // it's removed. Kept with the reason, if any: a failed check is warned about.
+                let mut keep: Option<Option<Report>> = None;
+                let check = |session: &Session| {
+                    keep = match session.unsaved_work() {
+                        Ok(unsaved) if unsaved.is_empty() => return Ok(()),
+                        Ok(_) => Some(None),
+                        Err(err) => Some(Some(err)),
+                    };
+                    bail!("kept '{}'", session.branch)
+                };
+                let removed = remove(sessions, project, &session.branch, no_wait, check);
+                match keep {
+                    None => removed?,
+                    Some(err) => {
+                        if let Some(err) = err {
+                            eprintln!("warning: kept '{}': {err:#}", session.branch);
+                        }
+                        kept += 1;
+                    }
+                }
+            }
+            narrate!("Kept {kept} sessions in group '{group}' with work the project doesn't have");
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Remove the session for `branch`, deleting its workspace unless it was adopted,
+/// unless `check` fails under the session's lock.
+fn remove(
+    sessions: &Sessions,
+    project: &Path,
+    branch: &BranchName,
+    no_wait: bool,
+    check: impl FnOnce(&Session) -> Result<()>,
+) -> Result<()> {
+    let Some((session, removal)) = sessions.remove_deferred(project, branch, check)? else {
+        bail!("no session for '{branch}' in {}", project.display());
+    };
+    finish_removal(&session, removal, no_wait)
+}
+
+/// Delete what's left of `session`, removed by `removal`, reporting where its workspace went.
+fn finish_removal(session: &Session, removal: Removal, no_wait: bool) -> Result<()> {
+    let branch = &session.branch;
+    let workspace = session.workspace.display();
+    match (session.adopted, no_wait) {
+        (true, _) => {
+            removal.finish()?;
+            narrate!("Removed session '{branch}'; its adopted workspace was kept: {workspace}");
+        }
+        (false, true) => {
+            removal.spawn()?;
+            narrate!(
+                "Removed session '{branch}'; deleting its workspace in the background: {workspace}"
+            );
+        }
+        (false, false) => {
+            removal.finish()?;
+            narrate!("Removed session '{branch}' and its workspace: {workspace}");
+        }
+    }
+    Ok(())
+}
+
+/// If `branch` names no session of `project` but is the short ID of a session,
+/// of any project, replace it with that session's branch and return its project.
+fn resolve_id(sessions: &Sessions, project: PathBuf, branch: &mut BranchName) -> Result<PathBuf> {
+    if sessions.get(&project, branch)?.is_some() {
+        return Ok(project);
+    }
+    match sessions.find_by_id(branch.as_str())? {
+        Some(session) => {
+            *branch = session.branch;
+            Ok(session.project)
+        }
+        None => Ok(project),
+    }
+}
+
+fn get(sessions: &Sessions, project: &Path, branch: &BranchName) -> Result<Session> {
+    match sessions.get(project, branch)? {
+        Some(session) => Ok(session),
+        None => bail!("no session for '{branch}' in {}", project.display()),
+    }
+}
+
+/// How verifying a session in `run-verify-all` went.
+enum VerifyOutcome {
+    Verified {
+        passed: bool,
+
+        duration: Duration,
+
+        log: PathBuf,
+    },
+
+    Skipped(&'static str),
+
+    Failed(Report),
+}
+
+/// Verify the sessions of `project` with `command`, `jobs` at a time, printing a scoreboard.
+fn verify_all(
+    sessions: &Sessions,
+    project: &Path,
+    command: &str,
+    jobs: NonZero<usize>,
+    group: Option<&str>,
+) -> Result<ExitCode> {
+    let mut listed = sessions.list(project)?;
+    if let Some(group) = group {
+        listed.retain(|session| session.group.as_deref() == Some(group));
+    }
+    listed.sort_by(|a, b| a.branch.cmp(&b.branch));
+    if listed.is_empty() {
+        narrate!("No sessions to verify in {}", project.display());
+        return Ok(ExitCode::SUCCESS);
+    }
+    let jobs = jobs.get().min(listed.len());
+    narrate!(
+        "Verifying {} sessions with `{command}`, {jobs} at a time",
+        listed.len()
+    );
+
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    let mut outcomes = thread::scope(|scope| {
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let (listed, next) = (&listed, &next);
+            scope.spawn(move || {
+                while let Some(session) = listed.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let outcome = verify_session(sessions, session, command);
+                    if sender.send((session.branch.clone(), outcome)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+        // Reported as they finish, since verifying every session can take a while.
+        receiver
+            .into_iter()
+            .inspect(|(branch, outcome)| match outcome {
+                VerifyOutcome::Verified { passed: true, .. } => narrate!("'{branch}' passed"),
+                VerifyOutcome::Verified { passed: false, .. } => narrate!("'{branch}' failed"),
+                VerifyOutcome::Skipped(why) => narrate!("Skipped '{branch}': {why}"),
+                VerifyOutcome::Failed(err) => eprintln!("Couldn't verify '{branch}': {err:#}"),
+            })
+            .collect::<Vec<_>>()
+    });
+    outcomes.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for (branch, outcome) in &outcomes {
+        let (result, duration, detail) = match outcome {
+            VerifyOutcome::Verified {
+                passed: ok,
+                duration,
+                log,
+            } => {
+                match ok {
+                    true => passed += 1,
+                    false => failed += 1,
+                }
+                let duration = match output::porcelain() {
+                    true => duration.as_millis().to_string(),
+                    false => format!("{:.1}s", duration.as_secs_f64()),
+                };
+                let result = if *ok { "passed" } else { "failed" };
+                (result, duration, log.display().to_string())
+            }
+            VerifyOutcome::Skipped(why) => {
+                skipped += 1;
+                ("skipped", String::from("-"), String::from(*why))
+            }
+            VerifyOutcome::Failed(err) => {
+                failed += 1;
+                ("error", String::from("-"), format!("{err:#}"))
+            }
+        };
+        println!(
+            "{}",
+            output::row([branch.as_str(), result, &duration, &detail])
+        );
+    }
+    narrate!("{passed} passed, {failed} failed, {skipped} skipped");
+    Ok(match failed {
+        0 => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    })
+}
+
+/// Verify `session` with `command`, logging the output to its data directory.
+fn verify_session(sessions: &Sessions, session: &Session, command: &str) -> VerifyOutcome {
+    if session.archived {
+        return VerifyOutcome::Skipped("archived");
+    }
+    let verify = || -> Result<VerifyOutcome> {
+        // What a backend is in the middle of changing isn't worth verifying.
+        if sessions.is_running(&session.project, &session.branch)? {
+            return Ok(VerifyOutcome::Skipped("a backend is running"));
+        }
+        let Some(mut agent) = Agent::open(sessions, &session.project, &session.branch)? else {
+            return Ok(VerifyOutcome::Skipped("removed"));
+        };
+        let log = agent.data_dir().join(VERIFY_LOG);
+        fs::create_dir_all(agent.data_dir()).context("create session data dir")?;
+        let started = Instant::now();
+        let verification = agent.verify_logged(command, &log)?;
+        Ok(VerifyOutcome::Verified {
+            passed: verification.passed,
+            duration: started.elapsed(),
+            log,
+        })
+    };
+    verify().unwrap_or_else(VerifyOutcome::Failed)
+}
+
+/// Where `run-verify-all` writes the output of a session's verification,
+/// in its data directory.
+const VERIFY_LOG: &str = "verify.log";
+
+/// Attach to the backend running in the session for `branch`, explaining why not if it can't be.
+pub fn attach(sessions: &Sessions, project: &Path, branch: &BranchName) -> Result<()> {
+    get(sessions, project, branch)?;
+    let Some(backend) = sessions.running_backend(project, branch)? else {
+        let dir = transcript::dir(&sessions.data_dir(project, branch));
+        let err = Err(eyre!("no backend is running in session '{branch}'"));
+        return match transcript::list(&dir)?.is_empty() {
+            true => err.suggestion(format!("see what it did with `anna session info {branch}`")),
+            false => err.suggestion(format!(
+                "replay its last run with `anna session replay {branch}`"
+            )),
+        };
+    };
+    match (backend.multiplexer, &backend.window, backend.pid) {
+        (Some(kind), Some(window), _) => kind.multiplexer().attach(window),
+        (Some(kind), None, _) => bail!(
+            "the backend of session '{branch}' is still starting in {kind}; try again in a moment"
+        ),
+        (None, _, pid) => {
+            let pid = pid.map(|pid| format!(" (PID {pid})")).unwrap_or_default();
+            Err(eyre!(
+                "the backend of session '{branch}' is running in another terminal{pid}, outside a multiplexer, so it can't be attached to"
+            ))
+            .suggestion("run agents with `anna agent --mux` to be able to attach to them")
+        }
+    }
+}
+
+/// Fail, listing what would be lost, if removing `session` would lose work.
+/// Adopted sessions' workspaces are kept, so they never would.
+fn ensure_nothing_unsaved(session: &Session) -> Result<()> {
+    if session.adopted || !check_unsaved(session)? {
+        return Ok(());
+    }
+    Err(eyre!(
+        "removing '{}' would lose the work listed above",
+        session.branch
+    ))
+    .suggestion(
+        "pass --fetch to keep its commits in the project, commit or discard its changes, \
+         or pass --force to remove it anyway",
+    )
+}
+
+/// List the files a [`ConflictReport`] is about, if `err` is one, and suggest what to do.
+pub fn explain_conflict(err: Report) -> Report {
+    /// How many conflicting files are listed.
+    const LISTED: usize = 20;
+
+    let Some(report) = err.downcast_ref::<ConflictReport>() else {
+        return err;
+    };
+    if let Some(commit) = &report.commit {
+        let id = commit.id.get(..12).unwrap_or(&commit.id);
+        eprintln!("Stopped at {id} {}", commit.subject);
+    }
+    for file in report.files.iter().take(LISTED) {
+        match file.hunks {
+            0 => eprintln!("  {}", file.path.display()),
+            1 => eprintln!("  {} (1 conflict)", file.path.display()),
+            n => eprintln!("  {} ({n} conflicts)", file.path.display()),
+        }
+    }
+    if report.files.len() > LISTED {
+        eprintln!("  and {} more", report.files.len() - LISTED);
+    }
+    let branch = &report.branch;
+    let suggestion = match report.operation {
+        ConflictOperation::Rebase => format!(
+            "resume without --rebase and ask the agent to rebase, or rebase by hand in \
+             `anna session path {branch}` with `git rebase {}`",
+            report.onto
+        ),
+        ConflictOperation::Merge => format!(
+            "rebase the session with `anna agent {branch} --rebase` first, or fetch it with \
+             `anna session fetch {branch}` and merge `refs/anna/{branch}` by hand"
+        ),
+    };
+    err.suggestion(suggestion)
+}
+
+/// List what removing `session` would lose, returning whether it would lose anything.
+fn check_unsaved(session: &Session) -> Result<bool> {
+    let unsaved = session
+        .unsaved_work()
+        .suggestion("pass --force to remove the session without checking")?;
+    list_unsaved(&unsaved);
+    Ok(!unsaved.is_empty())
+}
+
+/// List the work in each of `unsaved`'s workspaces.
+fn list_unsaved(unsaved: &[(PathBuf, UnsavedWork)]) {
+    /// How many changed files are listed for each workspace.
+    const LISTED: usize = 10;
+
+    for (workspace, work) in unsaved {
+        eprintln!("{}: {work}", workspace.display());
+        for path in work.changed.iter().take(LISTED) {
+            eprintln!("  {}", path.display());
+        }
+        if work.changed.len() > LISTED {
+            eprintln!("  and {} more", work.changed.len() - LISTED);
+        }
+    }
+}
+
+fn pull_request(
+    config: &Config,
+    session: &Session,
+    base: Option<String>,
+    title: Option<String>,
+    remote: &str,
+) -> Result<PullRequest> {
+    let vcs = session.vcs.vcs();
+    let url = vcs
+        .remote_url(&session.workspace, remote)
+        .with_context(|| format!("get URL of remote '{remote}'"))?;
+    let repository = url.parse::<Repository>()?;
+    let base = match base {
+        Some(base) => base,
+        None => {
+            let base = vcs
+                .default_branch(&session.project)
+                .context("determine default branch")?;
+            // The forge only knows branches by their names on the remote.
+            match base.strip_prefix(&format!("{remote}/")) {
+                Some(name) => String::from(name),
+                None => base,
+            }
+        }
+    };
+
+    // Connected before pushing so that missing credentials are reported
+    // before anything is published.
+    let forge = forge::connect(config, &repository)?;
+
+    let mut draft = PullRequestDraft::new(session, &base)?;
+    if let Some(title) = title {
+        draft.title = title;
+    }
+
+    narrate!("Pushing '{}' to {remote}", session.branch);
+    vcs.push(&session.workspace, remote, &session.branch)
+        .with_context(|| format!("push '{}' to {remote}", session.branch))?;
+    forge.create_pull_request(&repository, &draft)
+}
+
+/// The state of the session's workspace, as shown by `list`. Scripts get whether its
+/// most recent verification passed, as they always have; people get the state, in color.
+fn status(session: &Session) -> String {
+    if output::porcelain() {
+        let verified = match &session.verification {
+            Some(verification) if verification.passed => "✅",
+            Some(_) => "❌",
+            None => "-",
+        };
+        return String::from(verified);
+    }
+    let state = session.state();
+    let color = match state {
+        WorkspaceState::Verified => Some(Color::Green),
+        WorkspaceState::Failing => Some(Color::Red),
+        WorkspaceState::Unverified => None,
+        WorkspaceState::Archived => Some(Color::Dim),
+        WorkspaceState::Missing => Some(Color::Yellow),
+    };
+    match color {
+        Some(color) => output::paint(&state.to_string(), color),
+        None => state.to_string(),
+    }
+}
+
+/// An estimated cost in US dollars, as shown by `list`.
+fn format_cost(cost: Option<f64>) -> String {
+    match (cost, output::porcelain()) {
+        (Some(cost), true) => cost.to_string(),
+        (Some(cost), false) => format!("${cost:.2}"),
+        (None, true) => String::from("-"),
+        (None, false) => String::from("?"),
+    }
+}
+
+/// A row of the table shown by `stat`.
+fn stat_row(name: &str, stat: &ProjectStats, now: Timestamp) -> String {
+    let mut columns = vec![
+        String::from(name),
+        stat.sessions().to_string(),
+        stat.running.len().to_string(),
+    ];
+    columns.extend(WorkspaceState::ALL.map(|state| {
+        stat.states
+            .get(&state)
+            .copied()
+            .unwrap_or_default()
+            .to_string()
+    }));
+    columns.push(match output::porcelain() {
+        true => stat.disk_usage.to_string(),
+        false => workspace::format_size(stat.disk_usage),
+    });
+    columns.push(match (stat.oldest, output::porcelain()) {
+        (Some(oldest), true) => oldest.to_string(),
+        (Some(oldest), false) => output::age(now, oldest),
+        (None, _) => String::from("-"),
+    });
+    output::row(columns)
+}
+
+/// A duration in milliseconds, to the second, such as "1h 2m 5s".
+fn format_duration(millis: u64) -> String {
+    let seconds = millis / 1000;
+    match seconds {
+        0..60 => format!("{seconds}s"),
+        60..3600 => format!("{}m {}s", seconds / 60, seconds % 60),
+        _ => format!(
+            "{}h {}m {}s",
+            seconds / 3600,
+            seconds % 3600 / 60,
+            seconds % 60
+        ),
+    }
+}
+
+/// The fields shown by `info`, in order.
+fn info(details: &SessionDetails) -> Vec<(String, String)> {
+    let session = &details.session;
+    let mut fields = vec![
+        ("project", session.project.display().to_string()),
+        ("branch", session.branch.to_string()),
+        ("id", session.id.to_string()),
+        ("workspace", session.workspace.display().to_string()),
+        ("status", details.state().to_string()),
+        ("vcs", session.vcs.to_string()),
+        (
+            "base",
+            session.base.clone().unwrap_or_else(|| String::from("-")),
+        ),
+        ("created_at", session.created_at.to_string()),
+        ("adopted", session.adopted.to_string()),
+    ]
+    .into_iter()
+    .map(|(field, value)| (String::from(field), value))
+    .collect::<Vec<_>>();
+    let mut push = |field: String, value: String| fields.push((field, value));
+
+    if let Some(refreshed_at) = session.refreshed_at {
+        push(String::from("refreshed_at"), refreshed_at.to_string());
+    }
+    if let Some(divergence) = details.divergence {
+        push(String::from("ahead"), divergence.ahead.to_string());
+        push(String::from("behind"), divergence.behind.to_string());
+    }
+    for linked in &session.linked {
+        push(
+            format!("linked.{}", linked.project.display()),
+            linked.workspace.display().to_string(),
+        );
+    }
+    if let Some(remote) = &session.remote {
+        push(String::from("remote"), remote.to_string());
+    }
+    if let Some(group) = &session.group {
+        push(String::from("group"), group.clone());
+    }
+    for toolchain in &session.toolchains {
+        push(String::from("toolchain"), toolchain.to_string());
+    }
+
+    for backend in session.resume_tokens.keys() {
+        push(String::from("backend"), backend.clone());
+    }
+    if let Some(model) = &session.model {
+        push(String::from("model"), model.clone());
+    }
+    if let Some(prompt) = &session.prompt {
+        // Only the first line, to keep to one line per field; `--json` has all of it.
+        let first = prompt.lines().next().unwrap_or_default();
+        push(String::from("prompt"), String::from(first));
+    }
+    if let Some(issue) = &session.issue {
+        push(String::from("issue"), issue.clone());
+    }
+    if let Some(verification) = &session.verification {
+        let result = if verification.passed {
+            "passed"
+        } else {
+            "failed"
+        };
+        push(
+            String::from("verification"),
+            format!("{result}: {}", verification.command),
+        );
+        push(
+            String::from("verified_at"),
+            verification.finished_at.to_string(),
+        );
+    }
+    // The last line of the summary totals the changes.
+    if let Some(changes) = details
+        .diff_stat
+        .as_deref()
+        .and_then(|stat| stat.lines().last())
+    {
+        push(String::from("changes"), String::from(changes.trim()));
+    }
+    if !session.usage.is_empty() {
+        let (tokens, cost) = usage::total(session.usage.values());
+        push(String::from("tokens"), tokens.total().to_string());
+        push(String::from("cost"), format_cost(cost));
+    }
+    if let Some(usage) = details.disk_usage {
+        push(String::from("disk_usage"), workspace::format_size(usage));
+    }
+    for (i, run) in session.resources.iter().enumerate() {
+        push(
+            format!("run.{}", i + 1),
+            format!(
+                "{}: {} wall, {} CPU ({:.1} CPUs), {} peak memory",
+                run.started_at,
+                format_duration(run.duration_ms),
+                format_duration(run.cpu_ms),
+                run.cpus(),
+                workspace::format_size(run.peak_rss)
+            ),
+        );
+    }
+    for (i, run) in session.runs.iter().enumerate() {
+        let status = match (run.success, run.code) {
+            (true, _) => String::from("succeeded"),
+            (false, Some(code)) => format!("failed with exit code {code}"),
+            (false, None) => String::from("was killed by a signal"),
+        };
+        push(
+            format!("exit.{}", i + 1),
+            format!("{}: {} {status}", run.started_at, run.backend),
+        );
+    }
+
+    if let Some(environment) = &session.environment {
+        push(
+            String::from("anna_version"),
+            environment.anna_version.clone(),
+        );
+        push(String::from("os"), environment.os.clone());
+        push(String::from("arch"), environment.arch.clone());
+        for (backend, version) in &environment.backends {
+            push(format!("backend.{backend}"), version.clone());
+        }
+        for (name, value) in &environment.variables {
+            push(format!("env.{name}"), value.clone());
+        }
+    }
+    fields
+}
@@ -0,0 +1,73 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{path::Path, process::Command};
+
+const ANNA: &str = env!("CARGO_BIN_EXE_anna");
+
+/// Every command under `path` that has no subcommands of its own, followed by
+/// placeholders for its positional arguments, as read from its `--help`.
+fn leaf_commands(path: Vec<String>) -> Vec<Vec<String>> {
+    let output = Command::new(ANNA)
+        .args(&path)
+        .arg("--help")
+        .output()
+        .expect("run anna --help");
+    let help = String::from_utf8(output.stdout).expect("help is utf-8");
+    match help.split_once("Commands:\n") {
+        Some((_, commands)) => commands
+            .lines()
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split_whitespace().next())
+            .filter(|name| *name != "help")
+            .flat_map(|name| leaf_commands([path.clone(), vec![String::from(name)]].concat()))
+            .collect(),
+        None => {
+            let usage = help
+                .lines()
+                .find_map(|line| line.strip_prefix("Usage: "))
+                .expect("help has a usage line");
+            // Optional ones too, since some are only optional given other options.
+            let positional = usage
+                .split_whitespace()
+                .filter(|word| word.starts_with('<') || word.starts_with('['))
+                .filter(|word| *word != "[OPTIONS]")
+                .map(|_| String::from("1"));
+            vec![path.into_iter().chain(positional).collect()]
+        }
+    }
+}
+
+#[test]
+fn takes_the_project_before_or_after_any_subcommand() {
+    let missing = Path::new(env!("CARGO_TARGET_TMPDIR")).join("missing-project");
+    let missing = missing.to_str().expect("utf-8 path");
+    let commands = leaf_commands(Vec::new());
+    assert!(commands.len() > 10, "{commands:?}");
+
+    for command in commands {
+        let command = command.iter().map(String::as_str).collect::<Vec<_>>();
+        for args in [
+            [&["-C", missing], &command[..]].concat(),
+            [&command[..], &["-C", missing]].concat(),
+            [&command[..], &["--project", missing]].concat(),
+        ] {
+            // Capturing a backtrace for each error would take most of the test's time.
+            let output = Command::new(ANNA)
+                .args(&args)
+                .env("RUST_BACKTRACE", "0")
+                .env("RUST_LIB_BACKTRACE", "0")
+                .output()
+                .expect("run anna");
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            // The project is looked for as soon as the command line is parsed,
+            // so a missing one stops every command before it does anything.
+            assert!(
+                !output.status.success() && stderr.contains("find project"),
+                "anna {}: {stderr}",
+                args.join(" ")
+            );
+        }
+    }
+}
@@ -0,0 +1,710 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Backend invocation templates.
+//!
+//! A backend is the program that actually talks to the LLM (for example `claude`).
+//! Rather than hardcoding how it is invoked, profiles describe the invocation
+//! as a command template such as:
+//!
+//! ```text
+//! claude --model {model} --append-system-prompt {prompt_file}
+//! ```
+//!
+//! The template is split into arguments using shell quoting rules *before*
+//! placeholders are substituted, so values containing spaces or quotes
+//! (prompts in particular) always arrive at the backend as a single argument.
+
+use std::{
+    ffi::OsString,
+    fmt,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    str::FromStr,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+#[cfg(feature = "cli-backends")]
+use std::{fs, io::Write};
+
+use color_eyre::eyre::{bail, eyre, Context, Error, Result};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "cli-backends")]
+use crate::branch;
+use crate::branch::BranchName;
+
+/// A variable that can be referenced in a [`CommandTemplate`] as `{name}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Placeholder {
+    /// The branch the agent is working on.
+    Branch,
+
+    /// The workspace directory the backend runs in.
+    Workspace,
+
+    /// The original project directory the workspace was copied from.
+    Project,
+
+    /// The prompt text.
+    Prompt,
+
+    /// A file containing the prompt text.
+    PromptFile,
+
+    /// The model the backend should use.
+    Model,
+
+    /// An identifier for the backend's conversation in this session,
+    /// so that it can be [resumed](crate::config::Profile::resume).
+    ResumeToken,
+}
+
+impl Placeholder {
+    /// All supported placeholders.
+    pub const ALL: [Placeholder; 7] = [
+        Placeholder::Branch,
+        Placeholder::Workspace,
+        Placeholder::Project,
+        Placeholder::Prompt,
+        Placeholder::PromptFile,
+        Placeholder::Model,
+        Placeholder::ResumeToken,
+    ];
+
+    /// The name used to reference this placeholder in a template.
+    pub fn name(self) -> &'static str {
+        match self {
+            Placeholder::Branch => "branch",
+            Placeholder::Workspace => "workspace",
+            Placeholder::Project => "project",
+            Placeholder::Prompt => "prompt",
+            Placeholder::PromptFile => "prompt_file",
+            Placeholder::Model => "model",
+            Placeholder::ResumeToken => "resume_token",
+        }
+    }
+}
+
+impl FromStr for Placeholder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|placeholder| placeholder.name() == s)
+            .ok_or_else(|| {
+                let known = Self::ALL.map(|p| format!("{{{}}}", p.name())).join(", ");
+                eyre!("unknown placeholder '{{{s}}}'; expected one of: {known}")
+            })
+    }
+}
+
+/// The values substituted into a [`CommandTemplate`] for a single run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TemplateValues {
+    /// Value for `{branch}`.
+    pub branch: Option<String>,
+
+    /// Value for `{workspace}`.
+    pub workspace: Option<PathBuf>,
+
+    /// Value for `{project}`.
+    pub project: Option<PathBuf>,
+
+    /// Value for `{prompt}`.
+    pub prompt: Option<String>,
+
+    /// Value for `{prompt_file}`.
+    pub prompt_file: Option<PathBuf>,
+
+    /// Value for `{model}`.
+    pub model: Option<String>,
+
+    /// Value for `{resume_token}`.
+    pub resume_token: Option<String>,
+}
+
+impl TemplateValues {
+    fn get(&self, placeholder: Placeholder) -> Option<String> {
+        let path = |p: &Option<PathBuf>| p.as_deref().map(Path::to_string_lossy).map(String::from);
+        match placeholder {
+            Placeholder::Branch => self.branch.clone(),
+            Placeholder::Workspace => path(&self.workspace),
+            Placeholder::Project => path(&self.project),
+            Placeholder::Prompt => self.prompt.clone(),
+            Placeholder::PromptFile => path(&self.prompt_file),
+            Placeholder::Model => self.model.clone(),
+            Placeholder::ResumeToken => self.resume_token.clone(),
+        }
+    }
+}
+
+/// A templated backend command line.
+///
+/// An argument consisting solely of a placeholder that has no value
+/// (for example `{prompt}` when the agent was started without a prompt) is omitted;
+/// a placeholder without a value embedded in a larger argument is an error.
+/// Literal braces are written as `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CommandTemplate {
+    source: String,
+    args: Vec<Argument>,
+}
+
+impl CommandTemplate {
+    /// The program the template runs.
+    pub fn program(&self) -> &str {
+        match self.args.first().map(|arg| arg.0.as_slice()) {
+            Some([Segment::Literal(program)]) => program,
+            _ => unreachable!("templates are validated to start with a literal program"),
+        }
+    }
+
+    /// The placeholders referenced by this template.
+    pub fn placeholders(&self) -> impl Iterator<Item = Placeholder> + '_ {
+        self.args
+            .iter()
+            .flat_map(|arg| arg.0.iter())
+            .filter_map(|segment| match segment {
+                Segment::Placeholder(placeholder) => Some(*placeholder),
+                Segment::Literal(_) => None,
+            })
+    }
+
+    /// Resolve the template into the program and its arguments.
+    pub fn resolve(&self, values: &TemplateValues) -> Result<Vec<String>> {
+        let args = self
+            .args
+            .iter()
+            .filter_map(|arg| arg.resolve(values).transpose())
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("resolve command template: {}", self.source))?;
+        if args.is_empty() {
+            bail!(
+                "command template resolved to an empty command: {}",
+                self.source
+            );
+        }
+        Ok(args)
+    }
+}
+
+impl FromStr for CommandTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let args = shell_words::split(s)
+            .with_context(|| format!("split command template: {s}"))?
+            .iter()
+            .map(|arg| Argument::parse(arg))
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("parse command template: {s}"))?;
+        match args.first() {
+            None => bail!("command template must not be empty"),
+            Some(Argument(segments)) if segments.iter().any(Segment::is_placeholder) => {
+                bail!("the program in a command template must not use placeholders: {s}")
+            }
+            Some(_) => Ok(Self {
+                source: String::from(s),
+                args,
+            }),
+        }
+    }
+}
+
+impl TryFrom<String> for CommandTemplate {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<CommandTemplate> for String {
+    fn from(value: CommandTemplate) -> Self {
+        value.source
+    }
+}
+
+impl fmt::Display for CommandTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Templated arguments added to a [`CommandTemplate`], one per element,
+/// with placeholders working as they do there.
+///
+/// Unlike a command template, arguments aren't split on whitespace:
+/// `["--append-system-prompt", "{prompt}"]` is two arguments however long the prompt.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct ArgsTemplate {
+    source: Vec<String>,
+    args: Vec<Argument>,
+}
+
+impl ArgsTemplate {
+    /// Whether there are no arguments.
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    /// Resolve the template into arguments, leaving out any consisting solely
+    /// of a placeholder that has no value.
+    pub fn resolve(&self, values: &TemplateValues) -> Result<Vec<String>> {
+        self.args
+            .iter()
+            .filter_map(|arg| arg.resolve(values).transpose())
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("resolve arguments: {:?}", self.source))
+    }
+}
+
+impl TryFrom<Vec<String>> for ArgsTemplate {
+    type Error = Error;
+
+    fn try_from(source: Vec<String>) -> Result<Self, Self::Error> {
+        let args = source
+            .iter()
+            .map(|arg| Argument::parse(arg))
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("parse arguments: {source:?}"))?;
+        Ok(Self { source, args })
+    }
+}
+
+impl From<ArgsTemplate> for Vec<String> {
+    fn from(value: ArgsTemplate) -> Self {
+        value.source
+    }
+}
+
+/// How long [`probe`] waits for a backend to report its version.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Check that `program` is installed and works by running `program --version` in `dir`,
+/// where version managers choose which version of it runs, returning the first line it prints.
+///
+/// This is much cheaper than discovering a missing backend
+/// only once a workspace has been copied for it.
+pub fn probe(program: &str, dir: &Path) -> Result<String, BackendUnavailable> {
+    let unavailable = |problem: String| BackendUnavailable {
+        program: String::from(program),
+        problem,
+    };
+    // Version output is small enough to fit in the pipes' buffers,
+    // so the child can't block on writing it while it's polled.
+    let mut child = Command::new(program)
+        .arg("--version")
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| match err.kind() {
+            ErrorKind::NotFound => unavailable(String::from("is not installed")),
+            _ => unavailable(format!("could not be run: {err}")),
+        })?;
+
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() < PROBE_TIMEOUT => {
+                thread::sleep(Duration::from_millis(20))
+            }
+            Ok(None) => {
+                // Best effort: it's reported as unresponsive either way.
+                let _ = child.kill();
+                let _ = child.wait();
+                let timeout = PROBE_TIMEOUT.as_secs();
+                return Err(unavailable(format!(
+                    "did not respond to --version within {timeout}s"
+                )));
+            }
+            Err(err) => return Err(unavailable(format!("could not be waited on: {err}"))),
+        }
+    };
+    let output = child
+        .wait_with_output()
+        .map_err(|err| unavailable(format!("output could not be read: {err}")))?;
+    if !status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim();
+        let problem = if stderr.is_empty() {
+            format!("failed to report its version ({status})")
+        } else {
+            format!("failed to report its version ({status}): {stderr}")
+        };
+        return Err(unavailable(problem));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(String::from(
+        stdout.lines().next().unwrap_or_default().trim(),
+    ))
+}
+
+/// Run `template` non-interactively in `dir`, giving it `prompt` and returning what it prints.
+///
+/// The prompt is substituted for `{prompt}` or written to `{prompt_file}` if the
+/// template references either, and otherwise written to the backend's standard input,
+/// which suits long prompts that would exceed the limits on arguments' length.
+/// `values` provides the template's other placeholders.
+#[cfg(feature = "cli-backends")]
+pub fn run_batch(
+    template: &CommandTemplate,
+    mut values: TemplateValues,
+    prompt: &str,
+    dir: Option<&Path>,
+) -> Result<String> {
+    let placeholders = template.placeholders().collect::<Vec<_>>();
+    let mut stdin = None;
+    // Kept alive until the backend exits, since it may read the file at any point.
+    let mut prompt_file = None;
+    if placeholders.contains(&Placeholder::PromptFile) {
+        let file = tempfile::NamedTempFile::new().context("create prompt file")?;
+        fs::write(file.path(), prompt).context("write prompt file")?;
+        values.prompt_file = Some(file.path().to_path_buf());
+        prompt_file = Some(file);
+    }
+    if placeholders.contains(&Placeholder::Prompt) {
+        values.prompt = Some(String::from(prompt));
+    } else if prompt_file.is_none() {
+        stdin = Some(prompt);
+    }
+
+    let command_line = template.resolve(&values)?;
+    let program = &command_line[0];
+    let mut command = Command::new(program);
+    command
+        .args(&command_line[1..])
+        .stdin(match stdin {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+    let mut child = command.spawn().with_context(|| format!("run {program}"))?;
+    let output = thread::scope(|scope| {
+        // Written from another thread, since the backend may not read all of its input
+        // before writing output, which would fill the pipe and block both.
+        if let (Some(prompt), Some(mut input)) = (stdin, child.stdin.take()) {
+            scope.spawn(move || {
+                // Best effort: a backend that exits without reading it all fails on its own.
+                let _ = input.write_all(prompt.as_bytes());
+            });
+        }
+        child.wait_with_output()
+    })
+    .with_context(|| format!("wait for {program}"))?;
+    drop(prompt_file);
+    if !output.status.success() {
+        bail!("{program} exited with {}", output.status);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(not(feature = "cli-backends"))]
+pub fn run_batch(
+    _template: &CommandTemplate,
+    _values: TemplateValues,
+    _prompt: &str,
+    _dir: Option<&Path>,
+) -> Result<String> {
+    bail!("running backends requires winlock's `cli-backends` feature")
+}
+
+/// What the backend is asked when [naming a branch](suggest_branch), followed by the prompt.
+#[cfg(feature = "cli-backends")]
+const NAME_BRANCH_REQUEST: &str = "Reply with only a short git branch name, \
+    a few lowercase words separated by hyphens, for this task:\n\n";
+
+/// Ask the backend to name the branch for `prompt` by running `template`
+/// [in batch mode](run_batch), with a request to do so as its prompt,
+/// and using the first line it prints. It runs in an empty directory,
+/// since naming a branch needs nothing from the project.
+///
+/// The name is [sanitized](branch::sanitize), since backends don't reliably
+/// follow instructions about its format.
+#[cfg(feature = "cli-backends")]
+pub fn suggest_branch(template: &CommandTemplate, prompt: &str) -> Result<BranchName> {
+    let request = format!("{NAME_BRANCH_REQUEST}{prompt}");
+    let dir = tempfile::tempdir().context("create directory to name the branch in")?;
+    let output = run_batch(
+        template,
+        TemplateValues::default(),
+        &request,
+        Some(dir.path()),
+    )?;
+    let name = output
+        .lines()
+        .map(|line| line.trim().trim_matches('`'))
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+    branch::sanitize(name)
+}
+
+#[cfg(not(feature = "cli-backends"))]
+pub fn suggest_branch(_template: &CommandTemplate, _prompt: &str) -> Result<BranchName> {
+    bail!("running backends requires winlock's `cli-backends` feature")
+}
+
+/// The backend checked by [`probe`] is missing or broken.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendUnavailable {
+    /// The backend's program.
+    pub program: String,
+
+    /// What's wrong with it.
+    pub problem: String,
+}
+
+impl fmt::Display for BackendUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "backend '{}' {}", self.program, self.problem)
+    }
+}
+
+impl std::error::Error for BackendUnavailable {}
+
+/// How long to wait before the first restart of a failed backend.
+pub const RESTART_BACKOFF: Duration = Duration::from_secs(5);
+
+/// The longest wait between restarts, however many there have been.
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Whether a backend that exits unsuccessfully is relaunched,
+/// written as `no`, `on-failure`, or `on-failure:N`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Restart {
+    /// Never relaunch the backend.
+    #[default]
+    No,
+
+    /// Relaunch the backend whenever it fails, unless it was interrupted,
+    /// waiting twice as long as the last time before each attempt.
+    OnFailure {
+        /// The most times to relaunch it, or `None` for no limit.
+        max: Option<u32>,
+
+        /// How long to wait before the first attempt.
+        backoff: Duration,
+    },
+}
+
+impl Restart {
+    /// How long to wait before relaunching a backend that exited with `status`,
+    /// having already been relaunched `restarts` times; `None` if it shouldn't be.
+    pub fn delay(&self, restarts: u32, status: &ExitStatus) -> Option<Duration> {
+        let Self::OnFailure { max, backoff } = *self else {
+            return None;
+        };
+        if status.success() || interrupted(status) || max.is_some_and(|max| restarts >= max) {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(restarts);
+        Some(backoff.saturating_mul(factor).min(MAX_RESTART_BACKOFF))
+    }
+}
+
+impl FromStr for Restart {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let max = match s.split_once(':') {
+            None if s == "no" => return Ok(Self::No),
+            None if s == "on-failure" => None,
+            Some(("on-failure", max)) => Some(
+                max.parse()
+                    .with_context(|| format!("parse restart limit '{max}'"))?,
+            ),
+            _ => bail!("unknown restart policy '{s}'; expected no, on-failure, or on-failure:N"),
+        };
+        Ok(Self::OnFailure {
+            max,
+            backoff: RESTART_BACKOFF,
+        })
+    }
+}
+
+impl fmt::Display for Restart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::No => f.write_str("no"),
+            Self::OnFailure { max: None, .. } => f.write_str("on-failure"),
+            Self::OnFailure { max: Some(max), .. } => write!(f, "on-failure:{max}"),
+        }
+    }
+}
+
+/// Whether the backend was stopped deliberately, such as with Ctrl-C,
+/// in which case relaunching it would defeat the purpose.
+fn interrupted(status: &ExitStatus) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+
+        const STOPPING: [i32; 4] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT];
+        if status
+            .signal()
+            .is_some_and(|signal| STOPPING.contains(&signal))
+        {
+            return true;
+        }
+        // Shells, and the pseudo-terminal, report death by signal as 128 plus the signal.
+        status
+            .code()
+            .is_some_and(|code| STOPPING.iter().any(|signal| code == 128 + signal))
+    }
+    #[cfg(not(unix))]
+    {
+        // STATUS_CONTROL_C_EXIT
+        status.code() == Some(0xC000013Au32 as i32)
+    }
+}
+
+/// A backend about to be started by [`Agent::run`](crate::Agent::run).
+#[derive(Debug, Clone, Copy)]
+pub struct Invocation<'a> {
+    /// The program to run.
+    pub program: &'a str,
+
+    /// Its arguments.
+    pub args: &'a [String],
+
+    /// Environment variables set for it, on top of the ones anna inherited.
+    pub env: &'a [(&'a str, OsString)],
+
+    /// The directory it runs in.
+    pub workspace: &'a Path,
+
+    /// The prompt it was given, if any.
+    pub prompt: Option<&'a str>,
+}
+
+/// Starts backends in place of spawning their process, such as to stand in
+/// for them in tests; see [`Launcher`].
+pub trait Launch: Send + Sync {
+    /// Run the backend `invocation` describes, waiting for it to exit.
+    fn launch(&self, invocation: &Invocation<'_>) -> Result<ExitStatus>;
+}
+
+/// How [`Agent::run`](crate::Agent::run) starts backends: by spawning their
+/// process, by default, or with a [`Launch`] implementation.
+///
+/// Cheap to clone: clones start backends the same way.
+#[derive(Clone, Default)]
+pub struct Launcher(Option<Arc<dyn Launch>>);
+
+impl Launcher {
+    /// Start backends with `launch`.
+    pub fn new(launch: impl Launch + 'static) -> Self {
+        Self(Some(Arc::new(launch)))
+    }
+
+    /// What starts backends in place of spawning them, if anything does.
+    pub fn custom(&self) -> Option<&dyn Launch> {
+        self.0.as_deref()
+    }
+}
+
+impl fmt::Debug for Launcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Launcher")
+            .field(&self.0.as_ref().map_or("spawn", |_| "custom"))
+            .finish()
+    }
+}
+
+/// Launchers are equal if they start backends the same way.
+impl PartialEq for Launcher {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Launcher {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Argument(Vec<Segment>);
+
+impl Argument {
+    fn parse(arg: &str) -> Result<Self> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = arg.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    literal.push(c);
+                }
+                ('{', _) => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => bail!("unclosed '{{' in argument '{arg}'"),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Placeholder(name.parse()?));
+                }
+                ('}', _) => {
+                    bail!("unmatched '}}' in argument '{arg}'; use '}}}}' for a literal brace")
+                }
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() || segments.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self(segments))
+    }
+
+    /// Returns `None` if the argument should be omitted entirely.
+    fn resolve(&self, values: &TemplateValues) -> Result<Option<String>> {
+        if let [Segment::Placeholder(placeholder)] = self.0.as_slice() {
+            return Ok(values.get(*placeholder));
+        }
+        self.0
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => Ok(literal.clone()),
+                Segment::Placeholder(placeholder) => values
+                    .get(*placeholder)
+                    .ok_or_else(|| eyre!("no value for '{{{}}}'", placeholder.name())),
+            })
+            .collect::<Result<String>>()
+            .map(Some)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+
+    Placeholder(Placeholder),
+}
+
+impl Segment {
+    fn is_placeholder(&self) -> bool {
+        matches!(self, Segment::Placeholder(_))
+    }
+}
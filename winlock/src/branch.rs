@@ -0,0 +1,188 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Branch names for agent sessions.
+//!
+//! Names are validated as-is by [`BranchName`]'s `FromStr` implementation;
+//! [`sanitize`] converts free-form text, such as an issue title, into a valid name,
+//! and [`from_prompt`] derives one from what an agent is asked to do.
+
+use std::{fmt, str::FromStr};
+
+use color_eyre::eyre::{bail, Error, Result};
+use serde::{Deserialize, Serialize};
+
+/// The name of a branch created for an agent session.
+///
+/// Validated against the same rules `git check-ref-format --branch` applies,
+/// so that a name accepted here never fails later when the branch is created
+/// inside a freshly copied workspace.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct BranchName(String);
+
+impl BranchName {
+    /// View the branch name as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for BranchName {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            bail!("branch name must not be empty");
+        }
+        if s == "@" {
+            bail!("branch name must not be '@'");
+        }
+        if s.starts_with('-') {
+            bail!("branch name must not start with '-': {s}");
+        }
+        if s.starts_with('/') || s.ends_with('/') || s.contains("//") {
+            bail!("branch name must not have empty path components: {s}");
+        }
+        if s.ends_with('.') || s.ends_with(".lock") {
+            bail!("branch name must not end with '.' or '.lock': {s}");
+        }
+        if s.contains("..") || s.contains("@{") {
+            bail!("branch name must not contain '..' or '@{{': {s}");
+        }
+        if s.split('/').any(|component| component.starts_with('.')) {
+            bail!("branch name components must not start with '.': {s}");
+        }
+        let mut forbidden = s.chars().filter(|&c| is_forbidden(c)).collect::<Vec<_>>();
+        if !forbidden.is_empty() {
+            forbidden.sort_unstable();
+            forbidden.dedup();
+            let forbidden = forbidden
+                .iter()
+                .map(|c| format!("{c:?}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            bail!(
+                "branch name '{}' contains forbidden characters: {forbidden}",
+                s.escape_debug()
+            );
+        }
+        Ok(Self(String::from(s)))
+    }
+}
+
+impl TryFrom<String> for BranchName {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<BranchName> for String {
+    fn from(value: BranchName) -> Self {
+        value.0
+    }
+}
+
+impl fmt::Display for BranchName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for BranchName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Convert free-form text into a valid branch name.
+///
+/// Text is lowercased and each run of characters other than letters, digits,
+/// `_` and `.` becomes a single `-`; `/` is kept to separate path components.
+/// For example, `Fix: crash on load` becomes `fix-crash-on-load`.
+/// Fails if nothing usable remains.
+pub fn sanitize(text: &str) -> Result<BranchName> {
+    let name = text
+        .to_lowercase()
+        .split('/')
+        .map(sanitize_component)
+        .filter(|component| !component.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+    if name.is_empty() {
+        bail!(
+            "branch name '{}' has no characters usable in a branch name",
+            text.escape_debug()
+        );
+    }
+    name.parse()
+}
+
+/// The length beyond which branch names derived from prompts stop adding words.
+const MAX_PROMPT_BRANCH_LEN: usize = 40;
+
+/// Words left out of branch names derived from prompts, since they make names
+/// longer without telling branches apart.
+const FILLER_WORDS: &[&str] = &[
+    "a", "an", "and", "for", "in", "of", "on", "please", "the", "to", "with",
+];
+
+/// Derive a branch name from a prompt: the words of its first line, without
+/// filler words, up to a limit so that the name still reads naturally.
+/// For example, `Add retry logic to the HTTP client` becomes `add-retry-logic-http-client`.
+/// Fails if nothing usable remains.
+pub fn from_prompt(prompt: &str) -> Result<BranchName> {
+    let line = prompt
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or_default()
+        // Slashes would nest the name, which reads as a category rather than a task.
+        .replace('/', " ");
+    let mut name = String::new();
+    let words = sanitize_component(&line.to_lowercase());
+    for word in words.split('-') {
+        if FILLER_WORDS.contains(&word) && !name.is_empty() {
+            continue;
+        }
+        if !name.is_empty() && name.len() + word.len() >= MAX_PROMPT_BRANCH_LEN {
+            break;
+        }
+        if !name.is_empty() {
+            name.push('-');
+        }
+        name.push_str(word);
+    }
+    // Leaving words out can end the name on one that may only appear within names,
+    // such as `cargo.lock`.
+    let name = sanitize_component(&name);
+    if name.is_empty() {
+        bail!("the prompt has no words usable in a branch name");
+    }
+    name.parse()
+}
+
+fn sanitize_component(component: &str) -> String {
+    let mut component = component
+        .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+        .map(|word| {
+            // Dots may only separate text, never lead, trail, or repeat.
+            word.split('.')
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join(".")
+        })
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    while let Some(stripped) = component.strip_suffix(".lock") {
+        component = String::from(stripped);
+    }
+    component
+}
+
+fn is_forbidden(c: char) -> bool {
+    c.is_ascii_control() || matches!(c, ' ' | '~' | '^' | ':' | '?' | '*' | '[' | '\\')
+}
@@ -0,0 +1,1059 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! User configuration.
+//!
+//! Configuration is read from `config.toml` in the [config directory](config_dir).
+//! A missing file is equivalent to an empty one; everything has a default.
+//! Projects can also check in settings for themselves, which take the same form
+//! as `[projects."..."]` below, in [`PROJECT_CONFIG`] at their root. Since whoever
+//! can commit to the project writes those, they can only add to the files withheld
+//! from workspaces, only [harmless git settings](CHECKED_IN_GIT_SETTINGS) are applied,
+//! their copy engine and where caches come from are ignored, and their setup and
+//! verification commands only run once the user [trusts them](Config::trust_project).
+//!
+//! ```toml
+//! # The profile used when none is specified.
+//! profile = "claude"
+//!
+//! # Commit changes the backend leaves uncommitted when it exits.
+//! auto_commit = true
+//!
+//! # Run backends under a pseudo-terminal; enabled by default.
+//! pty = true
+//!
+//! # Record a transcript of every backend run; enabled by default, requires `pty`.
+//! transcripts = true
+//!
+//! # A shell command run in the workspace after the backend exits;
+//! # whether it passed is shown by `anna session list`.
+//! verify = "cargo test"
+//!
+//! # Check that nothing in the project changed while an agent ran, as with `--paranoid`.
+//! paranoid = false
+//!
+//! # Ask before copying more than this into a new workspace; `0` never asks.
+//! # Sizes take units such as `500 MB` or `2 GiB`.
+//! confirm_copy_size = "2 GiB"
+//!
+//! # Remove sessions once `anna session merge` merged them, as with `--and-remove`.
+//! remove_merged = false
+//!
+//! # Make each new git workspace's branch track the branch of the same name on this
+//! # remote, as `anna session set-upstream` does, so that plain `git push` works there.
+//! upstream = "origin"
+//!
+//! # The terminal multiplexer `--mux` runs backends in: tmux, zellij, or screen.
+//! multiplexer = "tmux"
+//!
+//! # The editor `anna session code` opens workspaces in; defaults to $VISUAL,
+//! # or else VS Code or Zed, whichever is installed.
+//! editor = "code --new-window"
+//!
+//! [profiles.claude]
+//! command = "claude --model {model} --session-id {resume_token} {prompt}"
+//! # Used instead of `command` once the backend has run in the session.
+//! resume = "claude --model {model} --resume {resume_token} {prompt}"
+//! # The model used unless `--model` selects another.
+//! model = "sonnet"
+//! # Hosts the backend needs to reach when its network access is restricted.
+//! hosts = ["api.anthropic.com"]
+//! # Arguments added for new conversations and for continued ones respectively.
+//! run_args_created = ["--append-system-prompt", "Work carefully."]
+//! run_args_resumed = ["--continue"]
+//! # Asks the backend to name the branch when `anna agent` is given only a prompt.
+//! # Without it, the name is derived from the prompt's words.
+//! name_branch = "claude --model haiku --print"
+//! # Reviews a session's changes for `anna session review`, printing the review.
+//! review = "claude --print"
+//! # Commands like these two get their request as `{prompt}` or `{prompt_file}`
+//! # if they reference either, and on standard input otherwise.
+//! # Overrides the global notification settings for this profile.
+//! notify = { desktop = false }
+//!
+//! # Environment variables set for the backend. Secrets are referenced rather than
+//! # written here, and read each time the backend runs: from 1Password with `op://`,
+//! # from pass with `pass://`, or from anna's own environment with `${VARIABLE}`.
+//! [profiles.claude.env]
+//! ANTHROPIC_API_KEY = "op://Work/Anthropic/credential"
+//! GITHUB_TOKEN = "pass://github/agent-token"
+//! HTTP_PROXY = "http://${PROXY_HOST}:3128"
+//!
+//! # Restricting backends' network access, on Linux.
+//! [network]
+//! # Restrict it for every run, as with `--no-network`.
+//! isolate = false
+//! # Hosts every backend may reach, in addition to its profile's `hosts`.
+//! allow = ["*.crates.io", "static.rust-lang.org"]
+//!
+//! # Notifications that agents finished, for unattended runs.
+//! [notify]
+//! # Show a desktop notification, on Linux and macOS.
+//! desktop = true
+//! # Post a summary to a Slack incoming webhook.
+//! slack = "https://hooks.slack.com/services/..."
+//! # Post the session, exit status, diff stat, and duration as JSON.
+//! webhook = "https://example.com/anna"
+//!
+//! # Git settings written to each new workspace's repository, such as
+//! # who agent commits are attributed to; project settings take precedence.
+//! [git]
+//! "user.name" = "Anna"
+//! "user.email" = "anna@example.com"
+//! "commit.gpgsign" = "false"
+//!
+//! # Files withheld from workspaces because they may hold secrets.
+//! [secrets]
+//! # Whether to withhold files matching the built-in patterns; enabled by default.
+//! builtin = true
+//! # More patterns, in gitignore syntax.
+//! exclude = ["*.tfvars"]
+//!
+//! # The kind of forge at hosts whose names don't give it away.
+//! [forges]
+//! "git.example.com" = "gitlab"
+//!
+//! # Settings for a specific project, keyed by its path.
+//! [projects."/home/me/src/project"]
+//! vcs = "hg"
+//! verify = "make check"
+//! # Shell commands run in each new workspace, such as to fetch dependencies.
+//! setup = ["make deps"]
+//! # How the project is copied into workspaces; `anna bench copy` picks the fastest.
+//! copy_engine = "reflink"
+//!
+//! [projects."/home/me/src/project".git]
+//! "core.hooksPath" = ".githooks"
+//!
+//! # Build caches seeded into each new workspace, so agents don't rebuild everything.
+//! [projects."/home/me/src/project".caches]
+//! dirs = ["target", "web/node_modules"]
+//! # Where they're taken from, if not the project itself.
+//! template = "/home/me/cache/project"
+//! # Link them rather than copying them, sharing them between workspaces.
+//! link = false
+//!
+//! # Copy Git LFS pointers rather than the files they point to, as with `--lfs-pointers`,
+//! # sharing the project's LFS storage; pull files later with `git lfs pull --include`.
+//! [projects."/home/me/src/project".lfs]
+//! pointers = true
+//! # Files pulled into each new workspace right away.
+//! pull = ["assets/ui/**"]
+//!
+//! # Extends (or, with `builtin`, overrides) the global setting.
+//! [projects."/home/me/src/project".secrets]
+//! exclude = ["!fixtures/test.pem"]
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    backend::{ArgsTemplate, CommandTemplate},
+    forge::ForgeKind,
+    multiplexer::MultiplexerKind,
+    network::NetworkPolicy,
+    notify::NotifyConfig,
+    prompt::detect_language,
+    toolchain::Toolchain,
+    usage::UsageSource,
+    vcs::{lfs::Lfs, VcsKind},
+    workspace::{ByteSize, Caches, CopyEngineKind, SECRET_PATTERNS},
+    CreateOptions, LinkedProject,
+};
+
+mod check;
+mod edit;
+
+pub use check::{check, ConfigFile, Problem, Severity};
+pub use edit::{get_value, parse_value, set_value, unset_value};
+
+/// The name of the profile that is always available, even if not configured.
+pub const DEFAULT_PROFILE: &str = "claude";
+
+/// The file in which a project checks in its own settings, at the project's root.
+pub const PROJECT_CONFIG: &str = ".anna.toml";
+
+/// The git settings a project's checked-in [`PROJECT_CONFIG`] can write to its
+/// workspaces' repositories. Others, such as `core.hooksPath` or `core.sshCommand`,
+/// can run commands, and cloning a repository mustn't be enough to run its code;
+/// the user's own settings for the project can set anything.
+pub const CHECKED_IN_GIT_SETTINGS: &[&str] = &[
+    "user.name",
+    "user.email",
+    "commit.gpgSign",
+    "core.autocrlf",
+    "core.eol",
+    "core.safecrlf",
+    "diff.algorithm",
+    "merge.conflictStyle",
+];
+
+/// The environment variable which overrides the [config directory](config_dir).
+pub const CONFIG_DIR_VARIABLE: &str = "ANNA_CONFIG_DIR";
+
+/// The environment variable which overrides the [state directory](state_dir).
+pub const STATE_DIR_VARIABLE: &str = "ANNA_STATE_DIR";
+
+/// The directory in which anna stores its configuration, and by default its state.
+///
+/// This is `$ANNA_CONFIG_DIR` if set, otherwise `anna` in the platform's configuration
+/// directory: `$XDG_CONFIG_HOME` (by default `~/.config`) on Linux,
+/// `~/Library/Application Support` on macOS, and `%APPDATA%` on Windows.
+/// Without a home directory to find that in, as in some containers and CI jobs,
+/// it's [a directory of the user's own](private_temp_dir) in the temporary directory,
+/// which may not outlast the machine.
+pub fn config_dir() -> Result<PathBuf> {
+    if let Some(dir) = env::var_os(CONFIG_DIR_VARIABLE).filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    match dirs::config_dir() {
+        Some(config) => Ok(migrate_legacy_dir(config.join("anna"))),
+        None => private_temp_dir(),
+    }
+}
+
+/// `anna-<uid>` in the temporary directory, created so that only the user can use it.
+///
+/// Anyone can create files in the temporary directory, so one that's already there
+/// could have been made by someone else to read or plant configuration and sessions;
+/// it's only used if it's a directory the user owns that no one else can use.
+#[cfg(unix)]
+fn private_temp_dir() -> Result<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    // SAFETY: getuid can't fail.
+    let uid = unsafe { libc::getuid() };
+    let dir = env::temp_dir().join(format!("anna-{uid}"));
+    match fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Err(err) if err.kind() != ErrorKind::AlreadyExists => {
+            return Err(err).context(format!("create {}", dir.display()))
+        }
+        _ => {}
+    }
+    let metadata = fs::symlink_metadata(&dir)
+        .with_context(|| format!("read metadata of {}", dir.display()))?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        bail!(
+            "there's no home directory, and {} isn't a directory only you can use; \
+             set {CONFIG_DIR_VARIABLE} and {STATE_DIR_VARIABLE} to one that is",
+            dir.display()
+        );
+    }
+    Ok(dir)
+}
+
+/// `anna` in the temporary directory, which on Windows is the user's own.
+#[cfg(not(unix))]
+fn private_temp_dir() -> Result<PathBuf> {
+    Ok(env::temp_dir().join("anna"))
+}
+
+/// The directory in which anna stores its state, such as the
+/// [session store](crate::session::Sessions).
+///
+/// This is `$ANNA_STATE_DIR` if set, otherwise the [config directory](config_dir).
+pub fn state_dir() -> Result<PathBuf> {
+    match env::var_os(STATE_DIR_VARIABLE).filter(|dir| !dir.is_empty()) {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => config_dir(),
+    }
+}
+
+/// The file configuration is read from: `config.toml` in the [config directory](config_dir).
+pub fn config_file() -> Result<PathBuf> {
+    Ok(config_dir()?.join("config.toml"))
+}
+
+/// Set `key` to `value` in the settings for `project` in the configuration file at `path`,
+/// creating the file if needed. The rest of the file, comments included, is left as it was.
+pub fn set_project_value(
+    path: &Path,
+    project: &Path,
+    key: &str,
+    value: impl Into<toml_edit::Value>,
+) -> Result<()> {
+    let name = project.to_string_lossy();
+    edit::set(
+        path,
+        ConfigFile::User,
+        &["projects", &name, key],
+        value.into(),
+    )
+}
+
+/// Earlier versions kept everything in `~/.annawinlock`; move it to `dir`
+/// the first time it's needed, so that upgrading doesn't lose any sessions.
+///
+/// If it can't be moved, for example because `dir` is on another file system,
+/// the legacy directory keeps being used rather than starting over empty.
+fn migrate_legacy_dir(dir: PathBuf) -> PathBuf {
+    let Some(legacy) = dirs::home_dir().map(|home| home.join(".annawinlock")) else {
+        return dir;
+    };
+    if dir.exists() || !legacy.is_dir() {
+        return dir;
+    }
+    let moved = dir
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::rename(&legacy, &dir));
+    // Another process may have migrated it concurrently.
+    if moved.is_ok() || dir.exists() {
+        dir
+    } else {
+        legacy
+    }
+}
+
+/// User configuration.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The profile used when none is specified.
+    pub profile: String,
+
+    /// Commit any changes the backend leaves uncommitted when it exits.
+    pub auto_commit: bool,
+
+    /// Run backends under a pseudo-terminal.
+    pub pty: bool,
+
+    /// Record a transcript of every backend run; requires [`pty`](Self::pty).
+    pub transcripts: bool,
+
+    /// A shell command run in the workspace after the backend exits.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify: Option<String>,
+
+    /// Check that nothing in the project changed while an agent worked on a copy of it,
+    /// by recording the state of everything in it before and comparing it after.
+    pub paranoid: bool,
+
+    /// Remove sessions once `anna session merge` merged their branches back into the project.
+    pub remove_merged: bool,
+
+    /// The remote whose branch of the same name each new git workspace's branch tracks,
+    /// in projects that have the remote.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<String>,
+
+    /// Ask before copying more than this into a new workspace; zero never asks.
+    pub confirm_copy_size: ByteSize,
+
+    /// The terminal multiplexer backends run in when asked to run in one.
+    pub multiplexer: MultiplexerKind,
+
+    /// The command, with arguments, that opens a directory in the user's editor
+    /// when the directory is added to it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub editor: Option<String>,
+
+    /// Files withheld from workspaces because they may hold secrets.
+    pub secrets: SecretsConfig,
+
+    /// Restrictions on backends' network access.
+    pub network: NetworkConfig,
+
+    /// Where to notify that agents finished.
+    pub notify: NotifyConfig,
+
+    /// Git settings written to the repository of each new workspace, by key.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub git: BTreeMap<String, String>,
+
+    /// Named backend profiles.
+    pub profiles: BTreeMap<String, Profile>,
+
+    /// The kind of forge at each host, for hosts [`ForgeKind::detect`] doesn't recognize.
+    pub forges: BTreeMap<String, ForgeKind>,
+
+    /// Settings for specific projects, keyed by project path.
+    pub projects: BTreeMap<PathBuf, ProjectConfig>,
+}
+
+impl Config {
+    /// Load the configuration from `config.toml` in the [config directory](config_dir).
+    pub fn load() -> Result<Self> {
+        Self::load_from(&config_file()?)
+    }
+
+    /// Load the configuration from the provided path.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let config = match fs::read_to_string(path) {
+            Ok(content) => toml::from_str::<Self>(&content)
+                .with_context(|| format!("parse config: {}", path.display()))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => Self::default(),
+            Err(err) => return Err(err).context(format!("read config: {}", path.display())),
+        };
+        Ok(config.with_builtin_profiles())
+    }
+
+    /// Look up a profile by name, or the default profile if no name is provided.
+    pub fn profile(&self, name: Option<&str>) -> Result<&Profile> {
+        let name = name.unwrap_or(&self.profile);
+        self.profiles.get(name).ok_or_else(|| {
+            let known = self.profiles.keys().cloned().collect::<Vec<_>>().join(", ");
+            eyre!("unknown profile '{name}'; configured profiles: {known}")
+        })
+    }
+
+    /// The settings for the project at `path`.
+    pub fn project(&self, path: &Path) -> ProjectConfig {
+        self.projects.get(path).cloned().unwrap_or_default()
+    }
+
+    /// Merge the settings the project at `path` checked in to its [`PROJECT_CONFIG`], if any,
+    /// into its settings here. The user's own settings for the project take precedence.
+    ///
+    /// A checked-in file comes with the project, from whoever can commit to it, so
+    /// it can't weaken which files are [withheld](SecretsConfig) from workspaces, and the
+    /// commands it runs are left out, and returned, until the user
+    /// [trusts](Self::trust_project) them.
+    pub fn load_project(&mut self, path: &Path) -> Result<Option<CheckedInCommands>> {
+        let file = path.join(PROJECT_CONFIG);
+        let checked_in = match fs::read_to_string(&file) {
+            Ok(content) => toml::from_str::<ProjectConfig>(&content)
+                .with_context(|| format!("parse project config: {}", file.display()))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(err).context(format!("read project config: {}", file.display()))
+            }
+        };
+        let user = self.project(path);
+        // Only those the user's own settings don't override would run.
+        let commands = CheckedInCommands {
+            file,
+            setup: match user.setup.is_empty() {
+                true => checked_in.setup,
+                false => Vec::new(),
+            },
+            verify: checked_in.verify.filter(|_| user.verify.is_none()),
+        };
+        let trusted =
+            commands.is_empty() || user.trusted.as_deref() == Some(commands.fingerprint().as_str());
+        let merged = ProjectConfig {
+            vcs: user.vcs.or(checked_in.vcs),
+            verify: match trusted {
+                true => user.verify.or(commands.verify.clone()),
+                false => user.verify,
+            },
+            setup: match trusted {
+                true if user.setup.is_empty() => commands.setup.clone(),
+                _ => user.setup,
+            },
+            trusted: user.trusted,
+            toolchains: match user.toolchains.is_empty() {
+                true => checked_in.toolchains,
+                false => user.toolchains,
+            },
+            // Some engines share files with the project, which is the user's call.
+            copy_engine: user.copy_engine,
+            secrets: SecretsConfig {
+                builtin: user.secrets.builtin,
+                exclude: checked_in
+                    .secrets
+                    .exclude
+                    .into_iter()
+                    .filter(|pattern| !pattern.starts_with('!'))
+                    .chain(user.secrets.exclude)
+                    .collect(),
+            },
+            git: checked_in
+                .git
+                .into_iter()
+                .filter(|(key, _)| is_checked_in_git_setting(key))
+                .chain(user.git)
+                .collect(),
+            caches: CachesConfig {
+                dirs: match user.caches.dirs.is_empty() {
+                    true => checked_in.caches.dirs,
+                    false => user.caches.dirs,
+                },
+                // Where caches come from, and whether workspaces share them,
+                // reach outside the project, so they're the user's call.
+                template: user.caches.template,
+                link: user.caches.link,
+            },
+            lfs: LfsConfig {
+                pointers: user.lfs.pointers.or(checked_in.lfs.pointers),
+                pull: match user.lfs.pull.is_empty() {
+                    true => checked_in.lfs.pull,
+                    false => user.lfs.pull,
+                },
+            },
+        };
+        self.projects.insert(path.to_path_buf(), merged);
+        Ok((!trusted).then_some(commands))
+    }
+
+    /// Trust the commands of the project at `path` that [`load_project`](Self::load_project)
+    /// left out, applying them here and recording that they're trusted in the
+    /// configuration file at `config_file`, until they change.
+    pub fn trust_project(
+        &mut self,
+        config_file: &Path,
+        path: &Path,
+        commands: &CheckedInCommands,
+    ) -> Result<()> {
+        let fingerprint = commands.fingerprint();
+        set_project_value(config_file, path, "trusted", fingerprint.as_str())?;
+        let project = self.projects.entry(path.to_path_buf()).or_default();
+        if project.setup.is_empty() {
+            project.setup = commands.setup.clone();
+        }
+        project.verify = project.verify.take().or_else(|| commands.verify.clone());
+        project.trusted = Some(fingerprint);
+        Ok(())
+    }
+
+    /// The patterns for files withheld from workspaces of the project at `path`,
+    /// combining the built-in, global, and project settings in that order
+    /// so that later patterns can re-include files with `!`.
+    pub fn secret_patterns(&self, path: &Path) -> Vec<String> {
+        let project = self.project(path).secrets;
+        let builtin = project.builtin.or(self.secrets.builtin).unwrap_or(true);
+        let builtin = SECRET_PATTERNS
+            .iter()
+            .copied()
+            .map(String::from)
+            .filter(|_| builtin);
+        builtin
+            .chain(self.secrets.exclude.iter().cloned())
+            .chain(project.exclude)
+            .collect()
+    }
+
+    /// The git settings for new workspaces of the project at `path`,
+    /// with the project's overriding the global ones.
+    pub fn git_config(&self, path: &Path) -> BTreeMap<String, String> {
+        self.git
+            .clone()
+            .into_iter()
+            .chain(self.project(path).git)
+            .collect()
+    }
+
+    /// The cache directories seeded into new workspaces of the project at `path`, if any.
+    pub fn caches(&self, path: &Path) -> Option<Caches> {
+        let caches = self.project(path).caches;
+        if caches.dirs.is_empty() {
+            return None;
+        }
+        Some(Caches {
+            template: caches
+                .template
+                .map_or_else(|| path.to_path_buf(), |template| path.join(template)),
+            dirs: caches.dirs,
+            link: caches.link.unwrap_or(false),
+            exclude: self.secret_patterns(path),
+        })
+    }
+
+    /// The options new sessions of the project at `path` are created with by default,
+    /// as configured; the settings it [checked in](Self::load_project) are only
+    /// included once they're loaded.
+    pub fn create_options(&self, path: &Path) -> CreateOptions {
+        let vcs = self
+            .project(path)
+            .vcs
+            .unwrap_or_else(|| VcsKind::detect(path));
+        self.create_options_with_vcs(path, vcs)
+    }
+
+    /// Like [`create_options`](Self::create_options), for sessions using `vcs`
+    /// whatever the project is configured to use or detected to be under.
+    pub fn create_options_with_vcs(&self, path: &Path, vcs: VcsKind) -> CreateOptions {
+        let project = self.project(path);
+        CreateOptions {
+            vcs,
+            exclude: self.secret_patterns(path),
+            copy_engine: self.copy_engine(path),
+            // Settings for git are meaningless elsewhere, so they're only applied where they can be.
+            git_config: match vcs {
+                VcsKind::Git => self.git_config(path),
+                _ => BTreeMap::new(),
+            },
+            // Projects without the remote have nothing to track.
+            upstream: self
+                .upstream
+                .clone()
+                .filter(|remote| vcs == VcsKind::Git && vcs.vcs().remote_url(path, remote).is_ok()),
+            caches: self.caches(path),
+            setup: project.setup,
+            toolchains: project.toolchains,
+            copy_limit: (self.confirm_copy_size.0 > 0).then_some(self.confirm_copy_size.0),
+            lfs: self.lfs(path),
+            ..CreateOptions::default()
+        }
+    }
+
+    /// How the project at `path` is copied into the workspaces linked to new sessions
+    /// of other projects, as configured for it; like [`create_options`](Self::create_options),
+    /// the settings it checked in are only included once they're loaded.
+    pub fn linked_project(&self, path: &Path) -> LinkedProject {
+        let vcs = self
+            .project(path)
+            .vcs
+            .unwrap_or_else(|| VcsKind::detect(path));
+        LinkedProject {
+            path: path.to_path_buf(),
+            vcs,
+            exclude: self.secret_patterns(path),
+            copy_engine: self.copy_engine(path),
+            git_config: match vcs {
+                VcsKind::Git => self.git_config(path),
+                _ => BTreeMap::new(),
+            },
+        }
+    }
+
+    /// How LFS files are brought into new workspaces of the project at `path`,
+    /// if they're not copied.
+    pub fn lfs(&self, path: &Path) -> Option<Lfs> {
+        let lfs = self.project(path).lfs;
+        lfs.pointers
+            .unwrap_or(false)
+            .then_some(Lfs { pull: lfs.pull })
+    }
+
+    /// The hosts a backend run with `profile` may reach when its network access is restricted.
+    pub fn network_policy(&self, profile: &Profile) -> NetworkPolicy {
+        NetworkPolicy {
+            allow: profile
+                .hosts
+                .iter()
+                .chain(&self.network.allow)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Where to notify that a run of a backend with `profile` finished:
+    /// the profile's settings, falling back to the global ones.
+    pub fn notify(&self, profile: &Profile) -> NotifyConfig {
+        match &profile.notify {
+            Some(notify) => notify.clone().or(&self.notify),
+            None => self.notify.clone(),
+        }
+    }
+
+    /// How the project at `path` is copied into its workspaces.
+    pub fn copy_engine(&self, path: &Path) -> CopyEngineKind {
+        self.project(path).copy_engine.unwrap_or_default()
+    }
+
+    /// The verification command for the project at `path`, if any.
+    pub fn verify(&self, path: &Path) -> Option<String> {
+        self.project(path).verify.or_else(|| self.verify.clone())
+    }
+
+    /// The kind of forge at `host`: as configured, or else detected from its name.
+    pub fn forge(&self, host: &str) -> Option<ForgeKind> {
+        self.forges
+            .get(host)
+            .copied()
+            .or_else(|| ForgeKind::detect(host))
+    }
+
+    /// Configuring some profiles shouldn't make the built-in profile disappear.
+    fn with_builtin_profiles(mut self) -> Self {
+        self.profiles
+            .entry(String::from(DEFAULT_PROFILE))
+            .or_insert_with(Profile::claude);
+        self
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            profile: String::from(DEFAULT_PROFILE),
+            auto_commit: false,
+            pty: true,
+            transcripts: true,
+            verify: None,
+            paranoid: false,
+            remove_merged: false,
+            upstream: None,
+            confirm_copy_size: ByteSize(2 * 1024 * 1024 * 1024),
+            multiplexer: MultiplexerKind::default(),
+            editor: None,
+            secrets: SecretsConfig::default(),
+            network: NetworkConfig::default(),
+            notify: NotifyConfig::default(),
+            git: BTreeMap::new(),
+            profiles: BTreeMap::new(),
+            forges: BTreeMap::new(),
+            projects: BTreeMap::new(),
+        }
+        .with_builtin_profiles()
+    }
+}
+
+/// Commands a project's checked-in [`PROJECT_CONFIG`] would have anna run, which
+/// [`Config::load_project`] leaves out until the user [trusts](Config::trust_project) them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CheckedInCommands {
+    /// The file they're checked in to.
+    #[serde(skip)]
+    pub file: PathBuf,
+
+    /// The [setup commands](ProjectConfig::setup).
+    pub setup: Vec<String>,
+
+    /// The [verification command](ProjectConfig::verify).
+    pub verify: Option<String>,
+}
+
+impl CheckedInCommands {
+    /// Identifies exactly these commands, so that trusting them doesn't extend
+    /// to whatever they're changed to later; recorded as [`ProjectConfig::trusted`].
+    pub fn fingerprint(&self) -> String {
+        let commands = serde_json::to_string(self).expect("commands always serialize");
+        Sha256::digest(commands.as_bytes())
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    /// Whether there are no commands.
+    pub fn is_empty(&self) -> bool {
+        self.setup.is_empty() && self.verify.is_none()
+    }
+}
+
+/// Whether a project's checked-in [`PROJECT_CONFIG`] can set the git setting `key`:
+/// whether it's one of [`CHECKED_IN_GIT_SETTINGS`], whose sections and names,
+/// like git's, are case-insensitive.
+pub fn is_checked_in_git_setting(key: &str) -> bool {
+    CHECKED_IN_GIT_SETTINGS
+        .iter()
+        .any(|setting| setting.eq_ignore_ascii_case(key))
+}
+
+/// A commented [`PROJECT_CONFIG`] for the project at `path`,
+/// with defaults suggested by the project's language where it can be detected.
+pub fn project_template(path: &Path) -> String {
+    let language = detect_language(path);
+    let defaults = LanguageDefaults::for_language(language, path);
+
+    let mut template = String::from(
+        "# Settings for anna agents working on this project.\n\
+         # Settings for the project in your own config.toml take precedence.\n",
+    );
+    if let Some(language) = language {
+        template.push_str(&format!("# Defaults suggested for a {language} project.\n"));
+    }
+
+    template.push_str(
+        "\n# Shell commands run in each new workspace once it's created,\n\
+         # such as to fetch dependencies so the agent can start working offline.\n",
+    );
+    template.push_str(&match defaults.setup.is_empty() {
+        true => toml_line("setup", &["make deps"], true),
+        false => toml_line("setup", &defaults.setup, false),
+    });
+
+    template.push_str(
+        "\n# A shell command run in the workspace after the backend exits;\n\
+         # whether it passed is shown by `anna session list`.\n",
+    );
+    let verify = defaults.verify.unwrap_or("make test");
+    template.push_str(&toml_line("verify", &verify, true));
+
+    template.push_str(
+        "\n# Files withheld from workspaces, in gitignore syntax, in addition to\n\
+         # files git ignores and ones that commonly hold secrets.\n\
+         [secrets]\n",
+    );
+    template.push_str(&match defaults.exclude.is_empty() {
+        true => toml_line("exclude", &["*.log"], true),
+        false => toml_line("exclude", &defaults.exclude, false),
+    });
+    template
+}
+
+/// A `key = value` line, commented out if `commented`.
+fn toml_line(key: &str, value: &impl Serialize, commented: bool) -> String {
+    let value = toml::Value::try_from(value).expect("template values must serialize");
+    let prefix = if commented { "# " } else { "" };
+    format!("{prefix}{key} = {value}\n")
+}
+
+/// Project settings that suit most projects in a language.
+#[derive(Debug, Default)]
+struct LanguageDefaults {
+    setup: Vec<&'static str>,
+
+    verify: Option<&'static str>,
+
+    exclude: Vec<&'static str>,
+}
+
+impl LanguageDefaults {
+    fn for_language(language: Option<&str>, path: &Path) -> Self {
+        let (setup, verify, exclude): (&[&str], _, &[&str]) = match language {
+            Some("Rust") => (&["cargo fetch"], "cargo test", &[]),
+            Some("Go") => (&["go mod download"], "go test ./...", &[]),
+            Some("TypeScript" | "JavaScript") => {
+                // Installing with the wrong package manager would ignore the lockfile.
+                let install = if path.join("pnpm-lock.yaml").exists() {
+                    "pnpm install"
+                } else if path.join("yarn.lock").exists() {
+                    "yarn install"
+                } else {
+                    "npm install"
+                };
+                (&[install][..], "npm test", &["node_modules/"][..])
+            }
+            Some("Python") => (&[], "pytest", &[".venv/", "__pycache__/"]),
+            Some("Ruby") => (&["bundle install"], "bundle exec rake", &["vendor/bundle/"]),
+            Some("Java") if path.join("pom.xml").exists() => (&[], "mvn test", &[]),
+            Some("Java") => (&[], "gradle test", &[".gradle/"]),
+            Some("Elixir") => (&["mix deps.get"], "mix test", &["_build/", "deps/"]),
+            Some("PHP") => (&["composer install"], "composer test", &["vendor/"]),
+            _ => return Self::default(),
+        };
+        Self {
+            setup: setup.to_vec(),
+            verify: Some(verify),
+            exclude: exclude.to_vec(),
+        }
+    }
+}
+
+/// A backend profile: how to invoke a particular backend.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// The command used to run the backend.
+    pub command: CommandTemplate,
+
+    /// The command used instead of [`command`](Self::command) once the backend
+    /// has run in the session, to continue its previous conversation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resume: Option<CommandTemplate>,
+
+    /// The model substituted for `{model}` in the command,
+    /// unless another is selected for the run or was for the session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
+    /// The flag which selects the backend's model, such as `--model`. When a model
+    /// is selected and the command doesn't reference `{model}`, the flag and model
+    /// are passed after the program, so that without a model the flag is left out too.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_flag: Option<String>,
+
+    /// Hosts the backend needs to reach, such as its API, when its network access is restricted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+
+    /// Where the backend records the tokens it uses, so they can be tracked per session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<UsageSource>,
+
+    /// Arguments added after the program when the backend starts a new conversation,
+    /// such as in a session that was just created, like the flag that passes the prompt.
+    #[serde(default, skip_serializing_if = "ArgsTemplate::is_empty")]
+    pub run_args_created: ArgsTemplate,
+
+    /// Arguments added after the program when the backend continues the session's
+    /// conversation, such as `--continue`.
+    #[serde(default, skip_serializing_if = "ArgsTemplate::is_empty")]
+    pub run_args_resumed: ArgsTemplate,
+
+    /// How to install the backend, suggested when it can't be run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub install: Option<String>,
+
+    /// Where to notify that runs of the backend finished, instead of the global setting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify: Option<NotifyConfig>,
+
+    /// The command that asks the backend to name the branch for a prompt,
+    /// printing the name; see [`suggest_branch`](crate::backend::suggest_branch).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_branch: Option<CommandTemplate>,
+
+    /// The command that asks the backend to review a session's changes,
+    /// printing the review; see [`review`](crate::review).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub review: Option<CommandTemplate>,
+
+    /// Environment variables set for the backend, whose values may reference secrets
+    /// kept by credential helpers; they're [resolved](crate::credentials) each time
+    /// the backend runs, so that the secrets are never written down.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl Profile {
+    /// A profile which runs `command`, with nothing else configured.
+    pub fn with_command(command: CommandTemplate) -> Self {
+        Self {
+            command,
+            resume: None,
+            model: None,
+            model_flag: None,
+            hosts: Vec::new(),
+            usage: None,
+            run_args_created: ArgsTemplate::default(),
+            run_args_resumed: ArgsTemplate::default(),
+            install: None,
+            notify: None,
+            name_branch: None,
+            review: None,
+            env: BTreeMap::new(),
+        }
+    }
+
+    fn claude() -> Self {
+        Self {
+            command: "claude --session-id {resume_token} {prompt}"
+                .parse()
+                .expect("built-in command template must be valid"),
+            resume: Some(
+                "claude --resume {resume_token} {prompt}"
+                    .parse()
+                    .expect("built-in command template must be valid"),
+            ),
+            model: None,
+            model_flag: Some(String::from("--model")),
+            hosts: vec![String::from("api.anthropic.com")],
+            usage: Some(UsageSource::Claude),
+            run_args_created: ArgsTemplate::default(),
+            run_args_resumed: ArgsTemplate::default(),
+            install: Some(String::from("npm install -g @anthropic-ai/claude-code")),
+            notify: None,
+            // Naming a branch costs a request, so it's only done when configured.
+            name_branch: None,
+            review: Some(
+                "claude --print"
+                    .parse()
+                    .expect("built-in command template must be valid"),
+            ),
+            env: BTreeMap::new(),
+        }
+    }
+}
+
+/// Settings for a specific project.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectConfig {
+    /// The version control system used by the project;
+    /// detected from the project directory if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vcs: Option<VcsKind>,
+
+    /// The command run in the project's workspaces after the backend exits,
+    /// instead of the global setting.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify: Option<String>,
+
+    /// Shell commands run in each new workspace of the project once it's created,
+    /// such as `cargo fetch`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub setup: Vec<String>,
+
+    /// The [fingerprint](CheckedInCommands::fingerprint) of the commands the project's
+    /// checked-in [`PROJECT_CONFIG`] runs, once the user trusted them;
+    /// only read from the user's own settings.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trusted: Option<String>,
+
+    /// [Toolchains](Toolchain) whose state, such as build output or installed packages,
+    /// is kept in each workspace of the project rather than shared between them.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub toolchains: Vec<Toolchain>,
+
+    /// How the project is copied into its workspaces.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub copy_engine: Option<CopyEngineKind>,
+
+    /// Files withheld from the project's workspaces, in addition to the global setting.
+    pub secrets: SecretsConfig,
+
+    /// Git settings written to the repository of each new workspace of the project,
+    /// taking precedence over the global ones.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub git: BTreeMap<String, String>,
+
+    /// Cache directories seeded into each new workspace of the project.
+    pub caches: CachesConfig,
+
+    /// How Git LFS files are brought into the project's workspaces.
+    pub lfs: LfsConfig,
+}
+
+/// How Git LFS files are brought into new workspaces.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LfsConfig {
+    /// Copy the pointers LFS committed rather than the files they point to;
+    /// disabled unless enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pointers: Option<bool>,
+
+    /// Patterns, as for `git lfs pull --include`, of files pulled into each new
+    /// workspace when only pointers are copied.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pull: Vec<String>,
+}
+
+/// Cache directories, such as build output, seeded into new workspaces from a template.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CachesConfig {
+    /// The directories, relative to the project, such as `target` or `node_modules`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dirs: Vec<PathBuf>,
+
+    /// The directory the caches are taken from, relative to the project
+    /// unless absolute; defaults to the project itself.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<PathBuf>,
+
+    /// Link the caches into workspaces rather than copying them; disabled unless enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<bool>,
+}
+
+/// Which files are withheld from workspaces because they may hold secrets.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecretsConfig {
+    /// Whether to withhold files matching [`SECRET_PATTERNS`];
+    /// enabled unless disabled here or, for projects, globally.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub builtin: Option<bool>,
+
+    /// Additional patterns in gitignore syntax;
+    /// a pattern starting with `!` re-includes files matched by earlier ones.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+/// Restrictions on backends' network access.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// Restrict network access for every run, rather than only with `--no-network`.
+    pub isolate: bool,
+
+    /// Hosts every backend may reach, in addition to its profile's [`hosts`](Profile::hosts),
+    /// either exactly or, as `*.example.com`, any subdomain.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+}
@@ -0,0 +1,301 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Checking configuration files for every problem at once, with their positions.
+//!
+//! Loading stops at the first problem, which makes fixing a file by trial and error
+//! tedious. Here each setting is deserialized on its own instead, so that one bad
+//! setting doesn't hide the next; the spans the parser records for each of them
+//! give positions in the original file.
+
+use std::{fmt, ops::Range, path::Path};
+
+use serde::de::DeserializeOwned;
+use toml::{
+    de::{DeString, DeTable, DeValue, Deserializer, ValueDeserializer},
+    Spanned,
+};
+
+use super::{is_checked_in_git_setting, Config, Profile, ProjectConfig, DEFAULT_PROFILE};
+
+/// Settings that are still accepted but no longer have the effect they used to,
+/// by dotted path, with what to do instead. Settings of projects are matched as
+/// `projects.*.<key>` in either file.
+const DEPRECATED: &[(&str, &str)] = &[];
+
+/// Which kind of configuration file is being checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFile {
+    /// The user's `config.toml`.
+    User,
+
+    /// A project's checked-in [`PROJECT_CONFIG`](super::PROJECT_CONFIG).
+    Project,
+}
+
+/// How bad a [`Problem`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The file loads, but a setting probably doesn't do what was meant.
+    Warning,
+
+    /// The file doesn't load.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found in a configuration file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// The line it's on, from 1.
+    pub line: usize,
+
+    /// The column it starts at in characters, from 1.
+    pub column: usize,
+
+    /// How bad it is.
+    pub severity: Severity,
+
+    /// What's wrong.
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            line,
+            column,
+            severity,
+            message,
+        } = self;
+        write!(f, "{line}:{column}: {severity}: {message}")
+    }
+}
+
+/// Check the content of a configuration file, returning every problem found
+/// in the order they appear.
+pub fn check(content: &str, file: ConfigFile) -> Vec<Problem> {
+    let (document, errors) = DeTable::parse_recoverable(content);
+    let mut checker = Checker {
+        content,
+        problems: Vec::new(),
+    };
+    for err in errors {
+        checker.report(Severity::Error, err.span(), err.message());
+    }
+
+    let span = document.span();
+    let document = document.into_inner();
+    match file {
+        ConfigFile::User => checker.user(&document, span),
+        ConfigFile::Project => {
+            checker.project(&document, span, "projects.*.");
+            checker.checked_in(&document);
+        }
+    }
+
+    // Values the parser recovered from syntax errors usually fail to deserialize too;
+    // the syntax errors, found first, are the ones worth reporting.
+    let mut problems = checker.problems;
+    problems.sort_by_key(|problem| (problem.line, problem.column));
+    problems.dedup_by_key(|problem| (problem.line, problem.column));
+    problems
+}
+
+struct Checker<'a> {
+    content: &'a str,
+    problems: Vec<Problem>,
+}
+
+impl Checker<'_> {
+    fn user(&mut self, document: &DeTable<'_>, span: Range<usize>) {
+        for (key, value) in document {
+            self.deprecated("", key);
+            match (key.get_ref().as_ref(), value.get_ref()) {
+                ("projects", DeValue::Table(projects)) => {
+                    for (path, settings) in projects {
+                        // Projects are looked up by their absolute path, so no other ever matches.
+                        if !Path::new(path.get_ref().as_ref()).is_absolute() {
+                            self.report(
+                                Severity::Warning,
+                                Some(path.span()),
+                                "project paths must be absolute; these settings never apply",
+                            );
+                        }
+                        match settings.get_ref() {
+                            DeValue::Table(table) => {
+                                self.project(table, settings.span(), "projects.*.")
+                            }
+                            _ => self.value::<ProjectConfig>(settings),
+                        }
+                    }
+                }
+                ("profiles", DeValue::Table(profiles)) => {
+                    for profile in profiles.values() {
+                        self.value::<Profile>(profile);
+                    }
+                }
+                _ => self.setting::<Config>(key, value, span.clone()),
+            }
+        }
+
+        // Only reported when it's configured here: otherwise nothing runs with it.
+        if let Some((key, value)) = document.get_key_value("profile") {
+            let known = document
+                .get("profiles")
+                .and_then(|profiles| match profiles.get_ref() {
+                    DeValue::Table(profiles) => Some(profiles),
+                    _ => None,
+                });
+            if let DeValue::String(name) = value.get_ref() {
+                let configured = known.is_some_and(|profiles| profiles.contains_key(name.as_ref()));
+                if name != DEFAULT_PROFILE && !configured {
+                    self.report(
+                        Severity::Error,
+                        Some(key.span().start..value.span().end),
+                        &format!("unknown profile '{name}'"),
+                    );
+                }
+            }
+        }
+
+        let pty = document.get("pty").map(Spanned::get_ref);
+        let transcripts = document.get("transcripts").map(Spanned::get_ref);
+        if let (Some(DeValue::Boolean(false)), Some(DeValue::Boolean(true))) = (pty, transcripts) {
+            let (key, _) = document
+                .get_key_value("transcripts")
+                .expect("transcripts was just found");
+            self.report(
+                Severity::Warning,
+                Some(key.span()),
+                "transcripts are only recorded with `pty` enabled",
+            );
+        }
+    }
+
+    fn project(&mut self, table: &DeTable<'_>, span: Range<usize>, prefix: &str) {
+        for (key, value) in table {
+            self.deprecated(prefix, key);
+            self.setting::<ProjectConfig>(key, value, span.clone());
+        }
+    }
+
+    /// Warn about settings a checked-in project file has that aren't applied from it.
+    fn checked_in(&mut self, document: &DeTable<'_>) {
+        if let Some((key, _)) = document.get_key_value("copy_engine") {
+            self.report(
+                Severity::Warning,
+                Some(key.span()),
+                "`copy_engine` is only applied from your own config.toml",
+            );
+        }
+        if let Some(DeValue::Table(secrets)) = document.get("secrets").map(Spanned::get_ref) {
+            if let Some((key, _)) = secrets.get_key_value("builtin") {
+                self.report(
+                    Severity::Warning,
+                    Some(key.span()),
+                    "`secrets.builtin` is only applied from your own config.toml",
+                );
+            }
+            if let Some(DeValue::Array(exclude)) = secrets.get("exclude").map(Spanned::get_ref) {
+                for pattern in exclude {
+                    if let DeValue::String(text) = pattern.get_ref() {
+                        if text.starts_with('!') {
+                            self.report(
+                                Severity::Warning,
+                                Some(pattern.span()),
+                                "patterns re-including files are only applied \
+                                 from your own config.toml",
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(DeValue::Table(caches)) = document.get("caches").map(Spanned::get_ref) {
+            for name in ["template", "link"] {
+                if let Some((key, _)) = caches.get_key_value(name) {
+                    self.report(
+                        Severity::Warning,
+                        Some(key.span()),
+                        &format!("`caches.{name}` is only applied from your own config.toml"),
+                    );
+                }
+            }
+        }
+        if let Some(DeValue::Table(git)) = document.get("git").map(Spanned::get_ref) {
+            for key in git.keys() {
+                if !is_checked_in_git_setting(key.get_ref()) {
+                    self.report(
+                        Severity::Warning,
+                        Some(key.span()),
+                        &format!(
+                            "git setting `{}` is only applied from your own config.toml",
+                            key.get_ref()
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Deserialize a single setting of `T` on its own, so that the others don't
+    /// get in the way of its problems or it in the way of theirs.
+    fn setting<T: DeserializeOwned>(
+        &mut self,
+        key: &Spanned<DeString<'_>>,
+        value: &Spanned<DeValue<'_>>,
+        span: Range<usize>,
+    ) {
+        let mut table = DeTable::new();
+        table.insert(key.clone(), value.clone());
+        if let Err(err) = T::deserialize(Deserializer::from(Spanned::new(span, table))) {
+            let span = err.span().unwrap_or_else(|| key.span());
+            self.report(Severity::Error, Some(span), err.message());
+        }
+    }
+
+    fn value<T: DeserializeOwned>(&mut self, value: &Spanned<DeValue<'_>>) {
+        if let Err(err) = T::deserialize(ValueDeserializer::from(value.clone())) {
+            let span = err.span().unwrap_or_else(|| value.span());
+            self.report(Severity::Error, Some(span), err.message());
+        }
+    }
+
+    fn deprecated(&mut self, prefix: &str, key: &Spanned<DeString<'_>>) {
+        let path = format!("{prefix}{}", key.get_ref());
+        if let Some((_, instead)) = DEPRECATED
+            .iter()
+            .find(|(deprecated, _)| *deprecated == path)
+        {
+            self.report(
+                Severity::Warning,
+                Some(key.span()),
+                &format!("`{path}` is deprecated; {instead}"),
+            );
+        }
+    }
+
+    fn report(&mut self, severity: Severity, span: Option<Range<usize>>, message: &str) {
+        let offset = span.map_or(0, |span| span.start).min(self.content.len());
+        let before = &self.content[..offset];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        let column = before[line_start..].chars().count() + 1;
+        self.problems.push(Problem {
+            line,
+            column,
+            severity,
+            message: String::from(message.trim()),
+        });
+    }
+}
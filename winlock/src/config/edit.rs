@@ -0,0 +1,170 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Reading and changing single settings in configuration files.
+//!
+//! Settings are named by TOML dotted keys, such as `network.isolate` or
+//! `projects."/home/me/src/project".verify`. Files are edited in place, so their
+//! comments and layout are kept, and never written if they'd no longer load.
+
+use std::{fs, io::ErrorKind, path::Path};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use toml_edit::{DocumentMut, Item, Key, Table, TableLike};
+
+use super::{Config, ConfigFile, ProjectConfig};
+
+/// The value of the setting `key` in the configuration file at `path`,
+/// including its default if the file doesn't set it, or `None` if it has none.
+pub fn get_value(path: &Path, file: ConfigFile, key: &str) -> Result<Option<toml::Value>> {
+    let keys = parse_key(key)?;
+    let mut value = match file {
+        ConfigFile::User => toml::Value::try_from(Config::load_from(path)?),
+        ConfigFile::Project => {
+            let settings = match read(path)? {
+                Some(content) => toml::from_str::<ProjectConfig>(&content)
+                    .with_context(|| format!("parse project config: {}", path.display()))?,
+                None => ProjectConfig::default(),
+            };
+            toml::Value::try_from(settings)
+        }
+    }
+    .context("serialize config")?;
+    for key in &keys {
+        match value {
+            toml::Value::Table(mut table) => match table.remove(key) {
+                Some(next) => value = next,
+                None => return Ok(None),
+            },
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some(value))
+}
+
+/// Set `key` to `value` in the configuration file at `path`,
+/// creating the file and any tables the key is in as needed.
+pub fn set_value(
+    path: &Path,
+    file: ConfigFile,
+    key: &str,
+    value: impl Into<toml_edit::Value>,
+) -> Result<()> {
+    let keys = parse_key(key)?;
+    let keys = keys.iter().map(String::as_str).collect::<Vec<_>>();
+    set(path, file, &keys, value.into())
+}
+
+/// Remove `key` from the configuration file at `path`, so that it takes its default,
+/// along with any tables left empty. Returns whether the file set it.
+pub fn unset_value(path: &Path, file: ConfigFile, key: &str) -> Result<bool> {
+    let keys = parse_key(key)?;
+    let Some(content) = read(path)? else {
+        return Ok(false);
+    };
+    let mut document = parse(path, &content)?;
+    if !remove(document.as_table_mut(), &keys) {
+        return Ok(false);
+    }
+    write(path, file, &document).with_context(|| format!("unset '{key}'"))?;
+    Ok(true)
+}
+
+/// Interpret a value given on the command line: as TOML if it is a valid TOML value,
+/// such as `true`, `3`, or `["a", "b"]`, and otherwise as a string, so that strings
+/// rarely need quoting.
+pub fn parse_value(value: &str) -> toml_edit::Value {
+    value
+        .parse::<toml_edit::Value>()
+        .unwrap_or_else(|_| value.into())
+}
+
+/// Set the setting at the path of `keys`, as [`set_value`] does.
+pub(super) fn set(
+    path: &Path,
+    file: ConfigFile,
+    keys: &[&str],
+    value: toml_edit::Value,
+) -> Result<()> {
+    let Some((last, tables)) = keys.split_last() else {
+        bail!("no setting to set");
+    };
+    let content = read(path)?.unwrap_or_default();
+    let mut document = parse(path, &content)?;
+    let mut table = document.as_table_mut() as &mut dyn TableLike;
+    for (depth, key) in tables.iter().enumerate() {
+        table = table
+            .entry(key)
+            .or_insert_with(|| {
+                // Otherwise an empty header is written for each table above the setting's.
+                let mut table = Table::new();
+                table.set_implicit(true);
+                Item::Table(table)
+            })
+            .as_table_like_mut()
+            .ok_or_else(|| {
+                let name = display_key(&keys[..=depth]);
+                eyre!("'{name}' is not a table in config: {}", path.display())
+            })?;
+    }
+    table.insert(last, toml_edit::value(value));
+    write(path, file, &document).with_context(|| format!("set '{}'", display_key(keys)))
+}
+
+fn remove(table: &mut dyn TableLike, keys: &[String]) -> bool {
+    match keys {
+        [] => false,
+        [key] => table.remove(key).is_some(),
+        [key, rest @ ..] => {
+            let Some(child) = table.get_mut(key).and_then(Item::as_table_like_mut) else {
+                return false;
+            };
+            let removed = remove(child, rest);
+            if removed && child.is_empty() {
+                table.remove(key);
+            }
+            removed
+        }
+    }
+}
+
+fn parse_key(key: &str) -> Result<Vec<String>> {
+    let keys = Key::parse(key).map_err(|err| eyre!("invalid key '{key}': {err}"))?;
+    Ok(keys.iter().map(|key| String::from(key.get())).collect())
+}
+
+fn display_key(keys: &[&str]) -> String {
+    keys.iter()
+        .map(|key| Key::new(*key).display_repr().into_owned())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn read(path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).context(format!("read config: {}", path.display())),
+    }
+}
+
+fn parse(path: &Path, content: &str) -> Result<DocumentMut> {
+    content
+        .parse::<DocumentMut>()
+        .with_context(|| format!("parse config: {}", path.display()))
+}
+
+/// Write the document to `path`, unless the change would leave it unloadable.
+fn write(path: &Path, file: ConfigFile, document: &DocumentMut) -> Result<()> {
+    let content = document.to_string();
+    match file {
+        ConfigFile::User => toml::from_str::<Config>(&content).map(drop),
+        ConfigFile::Project => toml::from_str::<ProjectConfig>(&content).map(drop),
+    }
+    .with_context(|| format!("check config: {}", path.display()))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("create config dir: {}", dir.display()))?;
+    }
+    fs::write(path, content).with_context(|| format!("write config: {}", path.display()))
+}
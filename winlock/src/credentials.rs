@@ -0,0 +1,112 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Secrets, such as API keys, that a profile's [environment](crate::config::Profile::env)
+//! references rather than holds, resolved each time a backend runs.
+//!
+//! Only the references are configured, and the secrets they resolve to are handed
+//! straight to the backend's process, so they're never written to disk by anna:
+//! not in the configuration, the session record, or its history. A value is either:
+//!
+//! - `op://vault/item/field`, a [1Password secret reference], read with `op read`.
+//! - `pass://path/to/entry`, the first line of a [pass] entry, read with `pass show`.
+//! - Anything else, in which `${VARIABLE}` is replaced by the variable
+//!   from anna's own environment, such as `Bearer ${GITHUB_TOKEN}`.
+//!
+//! Backends run in [multiplexers](crate::multiplexer) are given their environment in a
+//! file only the user can read, which is deleted as soon as it's read. Those run in
+//! [dev containers](crate::devcontainer) can only be given it on the command line that
+//! starts them there, where others on this machine could see it, so references
+//! aren't resolved for them; they fail to run instead. Backends run on
+//! [remote machines](crate::remote) are sent theirs the same way as to multiplexers,
+//! over SSH.
+//!
+//! [1Password secret reference]: https://developer.1password.com/docs/cli/secret-references/
+//! [pass]: https://www.passwordstore.org/
+
+use std::{collections::BTreeMap, env, ffi::OsString, process::Command};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+
+/// The scheme of 1Password secret references.
+const ONE_PASSWORD: &str = "op://";
+
+/// The scheme of references to pass entries.
+const PASS: &str = "pass://";
+
+/// Whether `value`, as configured for an environment variable,
+/// refers to a secret kept by a password manager.
+pub fn is_reference(value: &str) -> bool {
+    value.starts_with(ONE_PASSWORD) || value.starts_with(PASS)
+}
+
+/// Resolve `value`, as configured for an environment variable.
+pub fn resolve(value: &str) -> Result<String> {
+    if value.starts_with(ONE_PASSWORD) {
+        return helper(
+            Command::new("op").args(["read", "--no-newline", value]),
+            value,
+        );
+    }
+    if let Some(entry) = value.strip_prefix(PASS) {
+        let output = helper(Command::new("pass").args(["show", entry]), value)?;
+        // Entries hold the password on the first line and anything else after.
+        return Ok(String::from(output.lines().next().unwrap_or_default()));
+    }
+    expand(value)
+}
+
+/// Resolve each value of `env`, a profile's environment.
+pub fn resolve_env(env: &BTreeMap<String, String>) -> Result<Vec<(&str, OsString)>> {
+    env.iter()
+        .map(|(name, value)| {
+            let value = resolve(value).with_context(|| format!("resolve ${name}"))?;
+            Ok((name.as_str(), OsString::from(value)))
+        })
+        .collect()
+}
+
+/// A shell script exporting `env`, for a shell to source, so that it needn't be
+/// given on a command line, where anyone on the machine could see it.
+pub(crate) fn shell_script(env: &[(&str, OsString)]) -> String {
+    env.iter()
+        .map(|(variable, value)| {
+            let value = shell_words::quote(&value.to_string_lossy()).into_owned();
+            format!("export {variable}={value}\n")
+        })
+        .collect()
+}
+
+/// Run the credential helper `command`, returning what it prints for `reference`.
+fn helper(command: &mut Command, reference: &str) -> Result<String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .with_context(|| format!("run {program} to read {reference}"))?;
+    if !output.status.success() {
+        bail!(
+            "{program} failed to read {reference}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8(output.stdout).map_err(|_| eyre!("{reference} isn't valid UTF-8"))
+}
+
+/// Replace each `${VARIABLE}` in `template` with the variable from anna's environment.
+fn expand(template: &str) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start..].find('}') else {
+            bail!("unclosed ${{ in {template}");
+        };
+        let name = &rest[start + 2..start + len];
+        let value = env::var(name).with_context(|| format!("read ${name}"))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&value);
+        rest = &rest[start + len + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
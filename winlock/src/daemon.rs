@@ -0,0 +1,501 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A long-running process that keeps the session store in memory and supervises
+//! agents started through it, answering requests over a local socket.
+//!
+//! Requests and responses are [JSON-RPC 2.0](https://www.jsonrpc.org/specification)
+//! objects, one per line, over a Unix socket in the state directory; the
+//! [`Client`] speaks it for the CLI, and editors and other frontends can too.
+//! The methods are:
+//!
+//! - `ping`: the daemon's process ID, as `{"pid": 1234}`.
+//! - `sessions.list`: the sessions, as the store records them, optionally only those
+//!   of `{"project": "/src/project"}`. Served from memory, so it's fast,
+//!   though it may lag changes to the store by up to a second.
+//! - `sessions.running`: the sessions with a backend running, each as
+//!   `{"session": ..., "backend": ...}`.
+//! - `agents.start`: run `{"dir": "/src/project", "args": ["agent", ...]}` detached,
+//!   as `anna` would run them from `dir`, returning the [`SupervisedAgent`]. With
+//!   `"env": {"NAME": "value", ...}`, the agent gets exactly that environment, as the
+//!   [`Client`] sends its own; otherwise it inherits the daemon's, which may be stale.
+//! - `agents.list`: the agents started through the daemon, running or not.
+//! - `shutdown`: stop the daemon, leaving the agents it started running.
+//!
+//! Only the user running the daemon can connect: the socket is only theirs to open,
+//! and connections from processes of other users are hung up on regardless.
+//!
+//! The daemon only reads the session store, never taking its lock, so it never holds
+//! up anything changing it. Only Unix-like systems are supported, for now.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, Permissions},
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    os::{
+        fd::AsRawFd,
+        unix::{
+            fs::PermissionsExt,
+            net::{UnixListener, UnixStream},
+            process::CommandExt,
+        },
+    },
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use jiff::Timestamp;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+pub use crate::jsonrpc::{Request, Response, RpcError};
+use crate::{
+    config::state_dir,
+    jsonrpc::{parse_params, METHOD_NOT_FOUND, PARSE_ERROR},
+    session::{RunningBackend, Session, Sessions},
+};
+
+/// How often the daemon rereads the session store.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The socket the daemon listens on: `daemon.sock` in the
+/// [state directory](crate::config::state_dir).
+pub fn socket_path() -> Result<PathBuf> {
+    Ok(state_dir()?.join("daemon.sock"))
+}
+
+/// An agent started through the daemon.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SupervisedAgent {
+    /// The agent's number, unique while the daemon runs.
+    pub id: u64,
+
+    /// The directory the agent was started from.
+    pub dir: PathBuf,
+
+    /// The arguments it was started with, not including the program.
+    pub args: Vec<String>,
+
+    /// The agent's process ID.
+    pub pid: u32,
+
+    /// When the agent started.
+    pub started_at: Timestamp,
+
+    /// The file its output goes to.
+    pub log: PathBuf,
+
+    /// When the agent exited, if it has.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<Timestamp>,
+
+    /// The agent's exit code, if it exited rather than being killed by a signal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<i32>,
+}
+
+/// The daemon, which [serves](Self::serve) requests until asked to shut down.
+#[derive(Debug, Clone)]
+pub struct Daemon {
+    state: Arc<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    store: Sessions,
+
+    program: PathBuf,
+
+    logs: PathBuf,
+
+    sessions: RwLock<Vec<Session>>,
+
+    agents: Mutex<Vec<SupervisedAgent>>,
+
+    stopping: AtomicBool,
+}
+
+impl Daemon {
+    /// A daemon serving the session store in `dir` [read-only](Sessions::open_read_only_in),
+    /// which starts agents by running `program`, keeping their logs in `dir`.
+    pub fn new(dir: &Path, program: &Path) -> Result<Self> {
+        let logs = dir.join("daemon").join("logs");
+        fs::create_dir_all(&logs)
+            .with_context(|| format!("create daemon log directory: {}", logs.display()))?;
+        let store = Sessions::open_read_only_in(dir);
+        let sessions = store.iter()?.skip_unreadable().collect();
+        Ok(Self {
+            state: Arc::new(State {
+                store,
+                program: program.to_path_buf(),
+                logs,
+                sessions: RwLock::new(sessions),
+                agents: Mutex::new(Vec::new()),
+                stopping: AtomicBool::new(false),
+            }),
+        })
+    }
+
+    /// Listen on the socket at `path`, answering requests until one asks the daemon
+    /// to shut down. Fails if another daemon is listening there already.
+    pub fn serve(&self, path: &Path) -> Result<()> {
+        if UnixStream::connect(path).is_ok() {
+            bail!("a daemon is already listening on {}", path.display());
+        }
+        // Left behind by a daemon that didn't shut down cleanly.
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                return Err(err).context(format!("remove stale socket: {}", path.display()))
+            }
+            _ => {}
+        }
+        let listener =
+            UnixListener::bind(path).with_context(|| format!("listen on {}", path.display()))?;
+        // Until this, others may connect, but the peer check below turns them away.
+        fs::set_permissions(path, Permissions::from_mode(0o600))
+            .with_context(|| format!("restrict access to {}", path.display()))?;
+        // SAFETY: getuid can't fail.
+        let uid = unsafe { libc::getuid() };
+
+        let state = Arc::clone(&self.state);
+        thread::spawn(move || state.refresh_periodically());
+        for stream in listener.incoming() {
+            if self.state.stopping.load(Ordering::SeqCst) {
+                break;
+            }
+            let Ok(stream) = stream else {
+                continue;
+            };
+            // Anyone connecting can run agents as the user, so only the user may.
+            if peer_uid(&stream).ok() != Some(uid) {
+                continue;
+            }
+            let (daemon, path) = (self.clone(), path.to_path_buf());
+            // Detached, so that idle clients can't hold up shutting down.
+            thread::spawn(move || daemon.converse(stream, &path));
+        }
+        // Best effort: a stale socket is cleaned up by the next daemon anyway.
+        let _ = fs::remove_file(path);
+        Ok(())
+    }
+
+    /// Answer the requests on `stream`, accepted on the socket at `path`,
+    /// until the client hangs up.
+    fn converse(&self, stream: UnixStream, path: &Path) {
+        let Ok(mut writer) = stream.try_clone() else {
+            return;
+        };
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => self.handle(request),
+                Err(err) => Some(Response::new(
+                    Value::Null,
+                    Err(RpcError::new(PARSE_ERROR, err.to_string())),
+                )),
+            };
+            let Some(response) = response else {
+                continue;
+            };
+            let content = serde_json::to_string(&response).expect("responses must serialize");
+            if writeln!(writer, "{content}").is_err() {
+                return;
+            }
+            if self.state.stopping.load(Ordering::SeqCst) {
+                // Wakes the listener so that it notices.
+                let _ = UnixStream::connect(path);
+                return;
+            }
+        }
+    }
+
+    /// Answer `request`, or `None` if it's a notification.
+    pub fn handle(&self, request: Request) -> Option<Response> {
+        let result = self.call(&request.method, request.params);
+        Some(Response::new(request.id?, result))
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "ping" => Ok(json!({ "pid": std::process::id() })),
+            "sessions.list" => {
+                #[derive(Deserialize)]
+                struct Params {
+                    project: Option<PathBuf>,
+                }
+                let params: Params = parse_params(params)?;
+                let sessions = self.state.sessions.read().expect("no reader panics");
+                let sessions = sessions
+                    .iter()
+                    .filter(|session| {
+                        params
+                            .project
+                            .as_ref()
+                            .is_none_or(|project| session.project == *project)
+                    })
+                    .collect::<Vec<_>>();
+                Ok(serde_json::to_value(sessions).expect("sessions must serialize"))
+            }
+            "sessions.running" => {
+                let sessions = self
+                    .state
+                    .sessions
+                    .read()
+                    .expect("no reader panics")
+                    .clone();
+                let mut running = Vec::new();
+                for session in sessions {
+                    let backend = self
+                        .state
+                        .store
+                        .running_backend(&session.project, &session.branch)
+                        .map_err(RpcError::server)?;
+                    if let Some(backend) = backend {
+                        running.push(RunningSession { session, backend });
+                    }
+                }
+                Ok(serde_json::to_value(running).expect("running sessions must serialize"))
+            }
+            "agents.start" => {
+                #[derive(Deserialize)]
+                struct Params {
+                    dir: PathBuf,
+                    args: Vec<String>,
+                    env: Option<BTreeMap<String, String>>,
+                }
+                let params: Params = parse_params(params)?;
+                let agent = self
+                    .start(params.dir, params.args, params.env)
+                    .map_err(RpcError::server)?;
+                Ok(serde_json::to_value(agent).expect("agents must serialize"))
+            }
+            "agents.list" => {
+                let agents = self.state.agents.lock().expect("no supervisor panics");
+                Ok(serde_json::to_value(&*agents).expect("agents must serialize"))
+            }
+            "shutdown" => {
+                self.state.stopping.store(true, Ordering::SeqCst);
+                Ok(Value::Null)
+            }
+            method => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("no such method: {method}"),
+            )),
+        }
+    }
+
+    /// Run `args` detached from `dir`, with exactly the environment `env` if it's given,
+    /// recording how it went once it exits.
+    fn start(
+        &self,
+        dir: PathBuf,
+        args: Vec<String>,
+        env: Option<BTreeMap<String, String>>,
+    ) -> Result<SupervisedAgent> {
+        let mut agents = self.state.agents.lock().expect("no supervisor panics");
+        let id = agents.len() as u64 + 1;
+        let log = self.state.logs.join(format!("{id}.log"));
+        let file = File::create(&log).with_context(|| format!("create log: {}", log.display()))?;
+        let mut command = Command::new(&self.state.program);
+        command
+            .args(&args)
+            .current_dir(&dir)
+            .stdin(Stdio::null())
+            .stdout(file.try_clone().context("open log for stderr")?)
+            .stderr(file);
+        if let Some(env) = env {
+            command.env_clear().envs(env);
+        }
+        // In a process group of its own, so that interrupting the daemon leaves it running.
+        command.process_group(0);
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("run {}", self.state.program.display()))?;
+
+        let agent = SupervisedAgent {
+            id,
+            dir,
+            args,
+            pid: child.id(),
+            started_at: Timestamp::now(),
+            log,
+            finished_at: None,
+            code: None,
+        };
+        agents.push(agent.clone());
+
+        let state = Arc::clone(&self.state);
+        thread::spawn(move || {
+            let code = child.wait().ok().and_then(|status| status.code());
+            let mut agents = state.agents.lock().expect("no supervisor panics");
+            if let Some(agent) = agents.iter_mut().find(|agent| agent.id == id) {
+                agent.finished_at = Some(Timestamp::now());
+                agent.code = code;
+            }
+        });
+        Ok(agent)
+    }
+}
+
+impl State {
+    /// Reread the session store every so often, until the daemon stops.
+    fn refresh_periodically(&self) {
+        while !self.stopping.load(Ordering::SeqCst) {
+            thread::sleep(REFRESH_INTERVAL);
+            // Keeps what it had if the store can't be read, such as while it's moved.
+            if let Ok(sessions) = self.store.iter() {
+                *self.sessions.write().expect("no reader panics") =
+                    sessions.skip_unreadable().collect();
+            }
+        }
+    }
+}
+
+/// The user ID of the process at the other end of `stream`.
+fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        // SAFETY: ucred is plain integers, for which zero is valid.
+        let mut credentials = unsafe { std::mem::zeroed::<libc::ucred>() };
+        let mut len = size_of::<libc::ucred>() as libc::socklen_t;
+        // SAFETY: the buffer and its length match what SO_PEERCRED writes.
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                (&raw mut credentials).cast(),
+                &mut len,
+            )
+        };
+        match result {
+            0 => Ok(credentials.uid),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let (mut uid, mut gid) = (0, 0);
+        // SAFETY: getpeereid only writes the IDs.
+        match unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } {
+            0 => Ok(uid),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+/// A session with a backend running, from `sessions.running`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunningSession {
+    /// The session.
+    pub session: Session,
+
+    /// How its backend was run.
+    pub backend: RunningBackend,
+}
+
+/// A connection to the daemon.
+#[derive(Debug)]
+pub struct Client {
+    reader: BufReader<UnixStream>,
+
+    writer: UnixStream,
+
+    next_id: u64,
+}
+
+impl Client {
+    /// Connect to the daemon listening on the [usual socket](socket_path).
+    pub fn connect() -> Result<Self> {
+        Self::connect_to(&socket_path()?)
+    }
+
+    /// Connect to the daemon listening on the socket at `path`.
+    pub fn connect_to(path: &Path) -> Result<Self> {
+        let writer = UnixStream::connect(path)
+            .with_context(|| format!("connect to the daemon: {}", path.display()))?;
+        let reader = BufReader::new(writer.try_clone().context("clone daemon connection")?);
+        Ok(Self {
+            reader,
+            writer,
+            next_id: 1,
+        })
+    }
+
+    /// Call `method` with `params`, returning what it returned.
+    pub fn call<T: DeserializeOwned>(&mut self, method: &str, params: Value) -> Result<T> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = Request {
+            jsonrpc: String::from("2.0"),
+            id: Some(Value::from(id)),
+            method: String::from(method),
+            params,
+        };
+        let content = serde_json::to_string(&request).expect("requests must serialize");
+        writeln!(self.writer, "{content}").context("send request to the daemon")?;
+
+        let mut line = String::new();
+        self.reader
+            .read_line(&mut line)
+            .context("read response from the daemon")?;
+        if line.is_empty() {
+            bail!("the daemon hung up");
+        }
+        let response =
+            serde_json::from_str::<Response>(&line).context("parse response from the daemon")?;
+        match (response.result, response.error) {
+            (_, Some(error)) => Err(eyre!("the daemon failed to {method}: {}", error.message)),
+            // A null result reads as none.
+            (result, None) => serde_json::from_value(result.unwrap_or_default())
+                .with_context(|| format!("parse the daemon's result for {method}")),
+        }
+    }
+
+    /// The sessions, or only those of `project`.
+    pub fn sessions(&mut self, project: Option<&Path>) -> Result<Vec<Session>> {
+        self.call("sessions.list", json!({ "project": project }))
+    }
+
+    /// The sessions with a backend running.
+    pub fn running(&mut self) -> Result<Vec<RunningSession>> {
+        self.call("sessions.running", Value::Null)
+    }
+
+    /// Run `args` detached from `dir` with the environment `env`,
+    /// as `anna` would run them there.
+    pub fn start_agent(
+        &mut self,
+        dir: &Path,
+        args: &[String],
+        env: &BTreeMap<String, String>,
+    ) -> Result<SupervisedAgent> {
+        self.call(
+            "agents.start",
+            json!({ "dir": dir, "args": args, "env": env }),
+        )
+    }
+
+    /// The agents started through the daemon, running or not.
+    pub fn agents(&mut self) -> Result<Vec<SupervisedAgent>> {
+        self.call("agents.list", Value::Null)
+    }
+
+    /// Stop the daemon, leaving the agents it started running.
+    pub fn shutdown(&mut self) -> Result<()> {
+        self.call::<Value>("shutdown", Value::Null).map(drop)
+    }
+}
@@ -0,0 +1,293 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A read-only view of the sessions over HTTP, so that a team dashboard
+//! or a browser tab can keep an eye on every agent.
+//!
+//! The [`Dashboard`] answers `GET` requests for:
+//!
+//! - `/`: a page listing the sessions, which reloads itself every few seconds.
+//! - `/api/sessions`: every session, each as a [`SessionStatus`].
+//! - `/api/sessions/{id}`: the session with that [short ID](Sessions::short_ids),
+//!   as a [`SessionReport`], which includes its diff stats.
+//! - `/api/sessions/{id}/history`: the session's [history](crate::history), its log
+//!   of what happened to it.
+//!
+//! Nothing can be changed through it, and it only reads the store
+//! [without locking](Sessions::open_read_only), so it holds up nothing.
+//! Nor does it serve what backends were told or how to continue their
+//! conversations, and it only answers requests addressed to it by the address
+//! it listens on or as `localhost`, so that web pages can't read it by pointing
+//! a name of their own at it.
+
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt::{self, Write},
+    io::{self, Cursor},
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use serde::Serialize;
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Response, Server};
+use uuid::Uuid;
+
+use crate::{
+    session::{Divergence, RunningBackend, Session, Sessions, WorkspaceState},
+    usage,
+};
+
+/// How often the page reloads itself, in seconds.
+const RELOAD_INTERVAL: u32 = 5;
+
+/// A session as `/api/sessions` lists it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SessionStatus {
+    /// The session's record, without its prompts or its backends'
+    /// [resume tokens](Session::resume_tokens).
+    #[serde(flatten)]
+    pub session: Session,
+
+    /// The session's [short ID](Sessions::short_ids), which its URL ends with.
+    pub short_id: String,
+
+    /// The state of the session's workspace.
+    pub state: WorkspaceState,
+
+    /// The backend running in the session, if one is.
+    pub running: Option<RunningBackend>,
+}
+
+/// A session as `/api/sessions/{id}` reports it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SessionReport {
+    /// What `/api/sessions` lists for the session.
+    #[serde(flatten)]
+    pub status: SessionStatus,
+
+    /// A summary of the changes committed to the branch, as from [`Session::diff_stat`].
+    pub diff_stat: Option<String>,
+
+    /// How the branch and the project's default branch have diverged.
+    pub divergence: Option<Divergence>,
+}
+
+/// The dashboard, listening but yet to [serve](Self::serve).
+pub struct Dashboard {
+    server: Server,
+
+    sessions: Sessions,
+}
+
+impl Dashboard {
+    /// Listen on `addr`, such as `127.0.0.1:7777`, to serve `sessions`.
+    pub fn bind(sessions: Sessions, addr: impl ToSocketAddrs) -> Result<Self> {
+        let addr = addr
+            .to_socket_addrs()
+            .context("resolve address to listen on")?
+            .collect::<Vec<_>>();
+        let server = Server::http(addr.as_slice())
+            .map_err(|err| eyre!(err))
+            .with_context(|| format!("listen on {addr:?}"))?;
+        Ok(Self { server, sessions })
+    }
+
+    /// The address the dashboard listens on, which tells the port
+    /// when it was bound to port 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.server.server_addr().to_ip()
+    }
+
+    /// Answer requests one at a time until [stopped](Self::stop).
+    pub fn serve(&self) {
+        for request in self.server.incoming_requests() {
+            // Best effort: a client that went away doesn't need its answer.
+            let _ = self.answer(request);
+        }
+    }
+
+    /// Stop serving, once the request being answered is.
+    pub fn stop(&self) {
+        self.server.unblock();
+    }
+
+    fn answer(&self, request: Request) -> io::Result<()> {
+        if !self.addressed_to_us(&request) {
+            return request.respond(json_error(403, "unexpected Host header"));
+        }
+        if !matches!(request.method(), Method::Get | Method::Head) {
+            return request.respond(json_error(405, "the dashboard is read-only"));
+        }
+        let path = request.url().split('?').next().unwrap_or_default();
+        let segments = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>();
+        let response = match segments.as_slice() {
+            [] => self
+                .page()
+                .map(|page| with_type(page, "text/html; charset=utf-8")),
+            ["api", "sessions"] => self
+                .statuses()
+                .and_then(|statuses| json_response(&statuses)),
+            ["api", "sessions", id] => match self.sessions.find_by_id(id) {
+                Ok(Some(session)) => self
+                    .report(session)
+                    .and_then(|report| json_response(&report)),
+                Ok(None) => Ok(json_error(404, &format!("no session with ID '{id}'"))),
+                Err(err) => Err(err),
+            },
+            ["api", "sessions", id, "history"] => match self.sessions.find_by_id(id) {
+                Ok(Some(session)) => self
+                    .sessions
+                    .history(&session.project, &session.branch)
+                    .and_then(|history| json_response(&history)),
+                Ok(None) => Ok(json_error(404, &format!("no session with ID '{id}'"))),
+                Err(err) => Err(err),
+            },
+            _ => Ok(json_error(404, &format!("nothing at {path}"))),
+        };
+        request.respond(response.unwrap_or_else(|err| json_error(500, &format!("{err:#}"))))
+    }
+
+    /// Whether `request` names the dashboard by the address it listens on, or as
+    /// `localhost`, rather than by a name that may have been pointed at it to let
+    /// a web page read it as one of its own.
+    fn addressed_to_us(&self, request: &Request) -> bool {
+        let Some(host) = request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv("Host"))
+            .map(|header| header.value.as_str())
+        else {
+            return false;
+        };
+        let name = match host.strip_prefix('[') {
+            Some(rest) => rest.split(']').next().unwrap_or_default(),
+            None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+        };
+        if name.eq_ignore_ascii_case("localhost") {
+            return true;
+        }
+        // Listening on every address, any of the machine's may be used.
+        let listening = self.local_addr().map(|addr| addr.ip());
+        name.parse::<IpAddr>().is_ok_and(|ip| {
+            listening.is_some_and(|listening| listening.is_unspecified() || listening == ip)
+        })
+    }
+
+    /// Every session, most recently active first.
+    fn statuses(&self) -> Result<Vec<SessionStatus>> {
+        let mut sessions = self.sessions.iter()?.skip_unreadable().collect::<Vec<_>>();
+        sessions.sort_by_key(|session| Reverse(session.last_active()));
+        let short_ids = self.sessions.short_ids()?;
+        sessions
+            .into_iter()
+            .map(|session| self.status(session, &short_ids))
+            .collect()
+    }
+
+    fn status(
+        &self,
+        mut session: Session,
+        short_ids: &BTreeMap<Uuid, String>,
+    ) -> Result<SessionStatus> {
+        // Prompts may hold anything the user had to tell the backend, and resume tokens
+        // let whoever has them continue its conversation.
+        session.prompt = None;
+        session.prompts.clear();
+        session.resume_tokens.clear();
+        let running = self
+            .sessions
+            .running_backend(&session.project, &session.branch)?;
+        Ok(SessionStatus {
+            short_id: short_ids
+                .get(&session.id)
+                .cloned()
+                .unwrap_or_else(|| session.short_id()),
+            state: session.state(),
+            running,
+            session,
+        })
+    }
+
+    fn report(&self, session: Session) -> Result<SessionReport> {
+        let diff_stat = session.diff_stat().ok();
+        let divergence = session.divergence().ok().flatten();
+        Ok(SessionReport {
+            status: self.status(session, &self.sessions.short_ids()?)?,
+            diff_stat,
+            divergence,
+        })
+    }
+
+    /// The page listing the sessions.
+    fn page(&self) -> Result<String> {
+        let mut page = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <meta http-equiv=\"refresh\" content=\"{RELOAD_INTERVAL}\">\n\
+             <title>anna</title>\n\
+             <style>body {{ font-family: sans-serif; }} td, th {{ padding: 0.2em 0.8em; \
+             text-align: left; }}</style>\n</head>\n<body>\n<h1>Sessions</h1>\n\
+             <table>\n<tr><th>ID</th><th>Project</th><th>Branch</th><th>State</th>\
+             <th>Backend</th><th>Tokens</th><th>Last active</th></tr>\n"
+        );
+        for status in self.statuses()? {
+            let session = &status.session;
+            let (tokens, _) = usage::total(session.usage.values());
+            let backend = match &status.running {
+                Some(_) => "running",
+                None => "idle",
+            };
+            writeln!(
+                page,
+                "<tr><td><a href=\"/api/sessions/{id}\">{id}</a></td><td>{project}</td>\
+                 <td>{branch}</td><td>{state}</td><td>{backend}</td><td>{tokens}</td>\
+                 <td>{active}</td></tr>",
+                id = status.short_id,
+                project = escape(&session.project.display().to_string()),
+                branch = escape(session.branch.as_str()),
+                state = status.state,
+                tokens = tokens.total(),
+                active = session.last_active(),
+            )
+            .expect("writing to a string can't fail");
+        }
+        page.push_str("</table>\n</body>\n</html>\n");
+        Ok(page)
+    }
+}
+
+impl fmt::Debug for Dashboard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dashboard")
+            .field("addr", &self.local_addr())
+            .field("sessions", &self.sessions)
+            .finish()
+    }
+}
+
+fn with_type(body: String, content_type: &str) -> Response<Cursor<Vec<u8>>> {
+    let header = Header::from_bytes("Content-Type", content_type).expect("valid header");
+    Response::from_string(body).with_header(header)
+}
+
+fn json_response(value: &impl Serialize) -> Result<Response<Cursor<Vec<u8>>>> {
+    let body = serde_json::to_string_pretty(value).context("serialize response")?;
+    Ok(with_type(body, "application/json"))
+}
+
+fn json_error(status: u16, message: &str) -> Response<Cursor<Vec<u8>>> {
+    with_type(json!({ "error": message }).to_string(), "application/json").with_status_code(status)
+}
+
+/// Escape `text` for HTML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
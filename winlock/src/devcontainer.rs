@@ -0,0 +1,140 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Running backends inside a project's development container, so that they use
+//! the toolchain the project is actually built with rather than whatever this
+//! machine has installed.
+//!
+//! Containers are managed with the [Dev Container CLI](https://github.com/devcontainers/cli):
+//! `devcontainer up` starts one for the workspace, with the workspace mounted,
+//! and `devcontainer exec` runs the backend in it. Since the workspace is mounted
+//! rather than copied, what the backend changes is in the workspace as it changes it.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use color_eyre::eyre::{bail, Context, Result};
+use serde::Deserialize;
+
+/// Where a project's development container can be configured, relative to the
+/// project, in the order they're looked for.
+pub const CONFIG_PATHS: [&str; 2] = [".devcontainer/devcontainer.json", ".devcontainer.json"];
+
+/// The development container configuration of the project or workspace in `dir`, if it has one.
+pub fn config(dir: &Path) -> Option<PathBuf> {
+    CONFIG_PATHS
+        .iter()
+        .map(|path| dir.join(path))
+        .find(|path| path.is_file())
+}
+
+/// A running development container with a workspace mounted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    /// The ID of the container.
+    pub id: String,
+
+    /// The workspace on this machine.
+    pub workspace: PathBuf,
+
+    /// Where the workspace is mounted in the container.
+    pub workspace_in_container: PathBuf,
+}
+
+/// What `devcontainer up` reports on its last line of output.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpOutcome {
+    outcome: String,
+
+    container_id: Option<String>,
+
+    remote_workspace_folder: Option<PathBuf>,
+
+    message: Option<String>,
+}
+
+/// Start the development container of `workspace`, or reuse it if it's already running.
+pub fn up(workspace: &Path) -> Result<Container> {
+    if config(workspace).is_none() {
+        bail!(
+            "workspace has no development container configuration: {}",
+            workspace.display()
+        );
+    }
+    let output = Command::new("devcontainer")
+        .arg("up")
+        .arg("--workspace-folder")
+        .arg(workspace)
+        .stdin(Stdio::null())
+        // Building an image can take a while, so its progress is shown.
+        .stderr(Stdio::inherit())
+        .output()
+        .context("run devcontainer; is the Dev Container CLI installed?")?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let outcome = stdout
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .and_then(|line| serde_json::from_str::<UpOutcome>(line).ok());
+    let (id, workspace_in_container) = match outcome {
+        Some(UpOutcome {
+            outcome,
+            container_id: Some(id),
+            remote_workspace_folder: Some(folder),
+            ..
+        }) if outcome == "success" => (id, folder),
+        Some(UpOutcome {
+            message: Some(message),
+            ..
+        }) => bail!("start development container: {message}"),
+        _ => bail!(
+            "start development container: devcontainer exited with {}",
+            output.status
+        ),
+    };
+    Ok(Container {
+        id,
+        workspace: workspace.to_path_buf(),
+        workspace_in_container,
+    })
+}
+
+impl Container {
+    /// The command line running `command_line`, a program followed by its arguments,
+    /// in the container's workspace with `env` set.
+    pub fn command_line(&self, command_line: &[String], env: &[(&str, OsString)]) -> Vec<String> {
+        let mut exec = vec![
+            String::from("devcontainer"),
+            String::from("exec"),
+            String::from("--workspace-folder"),
+            self.workspace.to_string_lossy().into_owned(),
+        ];
+        for (variable, value) in env {
+            exec.push(String::from("--remote-env"));
+            exec.push(format!("{variable}={}", value.to_string_lossy()));
+        }
+        exec.push(String::from("--"));
+        exec.extend(command_line.iter().cloned());
+        exec
+    }
+
+    /// A command running the shell command `command` in the container's workspace.
+    pub fn shell(&self, command: &str) -> Command {
+        let command_line = self.command_line(
+            &[
+                String::from("sh"),
+                String::from("-c"),
+                String::from(command),
+            ],
+            &[],
+        );
+        let mut exec = Command::new(&command_line[0]);
+        exec.args(&command_line[1..]);
+        exec
+    }
+}
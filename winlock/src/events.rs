@@ -0,0 +1,227 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Machine-readable progress events, for integrations such as editor plugins.
+//!
+//! Events are written as newline-delimited JSON, one object per line, tagged
+//! with its kind in the `event` field, or handed to a [function](Events::from_fn),
+//! such as one telling the user what's happening:
+//!
+//! ```json
+//! {"event":"session_created","project":"/src/project","branch":"feature","workspace":"/tmp/project-feature-a1b2c3"}
+//! {"event":"backend_exited","code":0,"success":true}
+//! ```
+
+use std::{
+    fmt,
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::multiplexer::MultiplexerKind;
+
+/// Something that happened while creating or running an agent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// Part of the project has been copied into a new workspace.
+    CopyProgress {
+        /// The number of files and symlinks copied so far.
+        files: u64,
+
+        /// The size of the files copied so far, in bytes.
+        bytes: u64,
+    },
+
+    /// Part of a large file has been copied into a new workspace.
+    FileCopyProgress {
+        /// The file, relative to the project.
+        path: PathBuf,
+
+        /// How much of it has been copied so far, in bytes.
+        copied: u64,
+
+        /// Its size, in bytes.
+        size: u64,
+    },
+
+    /// The project has been copied into a new workspace.
+    CopyFinished {
+        /// The number of files and symlinks copied.
+        files: u64,
+
+        /// The size of the files copied, in bytes.
+        bytes: u64,
+
+        /// The number of entries withheld because they may hold secrets.
+        withheld: usize,
+
+        /// The number of files skipped for being larger than the maximum.
+        oversized: usize,
+
+        /// The number of entries that failed to copy.
+        failed: usize,
+
+        /// How long creating the workspace took, in milliseconds.
+        duration_ms: u64,
+    },
+
+    /// A new session was created.
+    SessionCreated {
+        /// The project the session belongs to.
+        project: PathBuf,
+
+        /// The branch the session works on.
+        branch: String,
+
+        /// The session's workspace.
+        workspace: PathBuf,
+    },
+
+    /// An existing session was resumed.
+    SessionResumed {
+        /// The project the session belongs to.
+        project: PathBuf,
+
+        /// The branch the session works on.
+        branch: String,
+
+        /// The session's workspace.
+        workspace: PathBuf,
+    },
+
+    /// The backend is starting.
+    BackendStarted {
+        /// The backend's program.
+        program: String,
+
+        /// Whether the backend continues its previous conversation in the session.
+        resumed: bool,
+    },
+
+    /// The backend exited.
+    BackendExited {
+        /// The backend's exit code, if it exited normally rather than by a signal.
+        code: Option<i32>,
+
+        /// Whether the backend exited successfully.
+        success: bool,
+    },
+
+    /// The backend failed and is about to be relaunched.
+    BackendRestarting {
+        /// Which relaunch this is, counting from 1.
+        attempt: u32,
+
+        /// How many relaunches there may be, if limited.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_attempts: Option<u32>,
+
+        /// How long until the backend is relaunched, in milliseconds.
+        delay_ms: u64,
+    },
+
+    /// The user detached from the multiplexer window the backend runs in,
+    /// which keeps running there.
+    BackendDetached {
+        /// The multiplexer the backend runs in.
+        multiplexer: MultiplexerKind,
+
+        /// The window it runs in, in the multiplexer's [session](crate::multiplexer::SESSION).
+        window: String,
+    },
+
+    /// Changes the backend left uncommitted were committed.
+    AutoCommitted {
+        /// The commit holding the changes.
+        commit: String,
+    },
+
+    /// The verification command finished.
+    Verified {
+        /// The command that was run.
+        command: String,
+
+        /// Whether it passed.
+        passed: bool,
+    },
+
+    /// Waiting for another process to finish with something, such as a lock.
+    Waiting {
+        /// What's waited for, such as "the sessions lock held by ...".
+        on: String,
+    },
+
+    /// Something went wrong without stopping what was being done.
+    Warning {
+        /// What went wrong.
+        message: String,
+    },
+}
+
+/// Where events are written; events are discarded if nowhere.
+///
+/// Cheap to clone: clones write to the same place.
+#[derive(Clone, Default)]
+pub struct Events(Option<Arc<Handler>>);
+
+/// What's done with each event.
+type Handler = dyn Fn(&Event) + Send + Sync;
+
+impl Events {
+    /// Write events to `writer`.
+    ///
+    /// Failing to write isn't an error: a consumer going away
+    /// shouldn't interrupt the agent it was watching.
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        let writer = Mutex::new(writer);
+        Self::from_fn(move |event| {
+            let mut line = serde_json::to_vec(event).expect("events must serialize");
+            line.push(b'\n');
+            let mut writer = writer
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let _ = writer.write_all(&line).and_then(|()| writer.flush());
+        })
+    }
+
+    /// Call `handle` with each event, such as to tell the user about it.
+    pub fn from_fn(handle: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(handle)))
+    }
+
+    /// Whether events are written anywhere.
+    pub fn enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Write `event`, if events are written anywhere.
+    pub fn emit(&self, event: &Event) {
+        if let Some(handle) = &self.0 {
+            handle(event);
+        }
+    }
+}
+
+impl fmt::Debug for Events {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Events").field(&self.enabled()).finish()
+    }
+}
+
+/// Events are equal if they write to the same place.
+impl PartialEq for Events {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Events {}
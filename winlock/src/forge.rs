@@ -0,0 +1,414 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Code hosting services ("forges"), where agent branches are proposed for review.
+//!
+//! Each forge is driven through its HTTP API. What GitHub and Gitea call a
+//! pull request, GitLab calls a merge request; anna calls both pull requests.
+
+#[cfg(feature = "forge")]
+use std::{env, process::Command};
+use std::{fmt, str::FromStr};
+
+#[cfg(feature = "forge")]
+use color_eyre::eyre::bail;
+use color_eyre::eyre::{eyre, Context, Report, Result};
+#[cfg(feature = "forge")]
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    branch::{self, BranchName},
+    session::Session,
+};
+
+#[cfg(feature = "forge")]
+mod gitea;
+#[cfg(feature = "forge")]
+mod github;
+#[cfg(feature = "forge")]
+mod gitlab;
+
+#[cfg(feature = "forge")]
+pub use gitea::Gitea;
+#[cfg(feature = "forge")]
+pub use github::GitHub;
+#[cfg(feature = "forge")]
+pub use gitlab::GitLab;
+
+/// Operations anna needs from a forge.
+pub trait Forge: fmt::Debug + Send + Sync {
+    /// The kind of forge this is.
+    fn kind(&self) -> ForgeKind;
+
+    /// Open a pull request in `repository`.
+    fn create_pull_request(
+        &self,
+        repository: &Repository,
+        draft: &PullRequestDraft,
+    ) -> Result<PullRequest>;
+
+    /// Fetch issue `number` of `repository`.
+    fn issue(&self, repository: &Repository, number: u64) -> Result<Issue>;
+}
+
+/// The kinds of forge anna supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForgeKind {
+    /// GitHub or GitHub Enterprise Server.
+    GitHub,
+
+    /// GitLab, hosted or self-managed.
+    GitLab,
+
+    /// Gitea, or its fork Forgejo.
+    Gitea,
+}
+
+impl ForgeKind {
+    /// Detect the kind of forge at `host` from its name, which works for
+    /// the public instances and for self-hosted ones named after their software.
+    pub fn detect(host: &str) -> Option<Self> {
+        let host = host.to_lowercase();
+        let named =
+            |name: &str| host == format!("{name}.com") || host.starts_with(&format!("{name}."));
+        if named("github") {
+            Some(ForgeKind::GitHub)
+        } else if named("gitlab") {
+            Some(ForgeKind::GitLab)
+        } else if named("gitea") || named("forgejo") || host == "codeberg.org" {
+            Some(ForgeKind::Gitea)
+        } else {
+            None
+        }
+    }
+
+    /// Connect to the forge of this kind at `host`, using credentials from the environment.
+    #[cfg(feature = "forge")]
+    pub fn connect(self, host: &str) -> Result<Box<dyn Forge>> {
+        Ok(match self {
+            ForgeKind::GitHub => Box::new(GitHub::connect(host)?),
+            ForgeKind::GitLab => Box::new(GitLab::connect(host)?),
+            ForgeKind::Gitea => Box::new(Gitea::connect(host)?),
+        })
+    }
+}
+
+impl fmt::Display for ForgeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ForgeKind::GitHub => "github",
+            ForgeKind::GitLab => "gitlab",
+            ForgeKind::Gitea => "gitea",
+        })
+    }
+}
+
+/// The longest title derived from a prompt; longer first lines are cut short.
+const MAX_TITLE_LEN: usize = 72;
+
+/// The length beyond which branch names derived from issues stop adding words.
+const MAX_BRANCH_LEN: usize = 48;
+
+/// A repository on a forge, as identified by a remote URL.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Repository {
+    /// The host serving the repository, such as `github.com`.
+    pub host: String,
+
+    /// The path of the repository on the host, such as `jssblck/anna`.
+    pub path: String,
+}
+
+impl Repository {
+    /// The owner of the repository: everything in the path before its name.
+    pub fn owner(&self) -> &str {
+        self.path.rsplit_once('/').map_or("", |(owner, _)| owner)
+    }
+
+    /// The name of the repository.
+    pub fn name(&self) -> &str {
+        self.path
+            .rsplit_once('/')
+            .map_or(self.path.as_str(), |(_, name)| name)
+    }
+}
+
+impl FromStr for Repository {
+    type Err = Report;
+
+    /// Parse a remote URL, in any of the forms git accepts for network remotes:
+    /// `https://host/owner/repo.git`, `ssh://git@host/owner/repo.git`, or `git@host:owner/repo.git`.
+    fn from_str(url: &str) -> Result<Self> {
+        let (host, path) = match url.split_once("://") {
+            Some((_, rest)) => rest
+                .split_once('/')
+                .ok_or_else(|| eyre!("remote URL has no repository path: {url}"))?,
+            None => url
+                .split_once(':')
+                .ok_or_else(|| eyre!("unrecognized remote URL: {url}"))?,
+        };
+
+        // Credentials and ports identify how to connect, not which repository it is.
+        let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+        let host = host.split_once(':').map_or(host, |(host, _)| host);
+        let path = path.trim_matches('/');
+        let path = path.strip_suffix(".git").unwrap_or(path);
+        if host.is_empty() || !path.contains('/') {
+            return Err(eyre!("unrecognized remote URL: {url}"));
+        }
+
+        Ok(Self {
+            host: host.to_lowercase(),
+            path: String::from(path),
+        })
+    }
+}
+
+impl fmt::Display for Repository {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.host, self.path)
+    }
+}
+
+/// The content of a pull request about to be opened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PullRequestDraft {
+    /// The title of the pull request.
+    pub title: String,
+
+    /// The description of the pull request, in Markdown.
+    pub body: String,
+
+    /// The branch with the changes.
+    pub head: String,
+
+    /// The branch the changes are proposed for.
+    pub base: String,
+}
+
+impl PullRequestDraft {
+    /// Draft a pull request of the session's branch into `base`,
+    /// describing it with the session's prompt and a summary of its changes.
+    pub fn new(session: &Session, base: &str) -> Result<Self> {
+        let prompt = session.prompt.as_deref().map(str::trim);
+        let title = prompt
+            .and_then(|prompt| prompt.lines().next())
+            .map(title)
+            .filter(|title| !title.is_empty())
+            .unwrap_or_else(|| session.branch.to_string());
+
+        let mut body = prompt.map(String::from).unwrap_or_default();
+        if let Some(revision) = &session.base {
+            let stat = session
+                .vcs
+                .vcs()
+                .diff_stat(&session.workspace, revision, &session.branch)
+                .context("summarize changes")?;
+            if !stat.is_empty() {
+                if !body.is_empty() {
+                    body.push_str("\n\n");
+                }
+                body.push_str(&format!("```\n{stat}\n```"));
+            }
+        }
+
+        if let Some(issue) = &session.issue {
+            if !body.is_empty() {
+                body.push_str("\n\n");
+            }
+            body.push_str(&format!("Closes {issue}"));
+        }
+
+        Ok(Self {
+            title,
+            body,
+            head: session.branch.to_string(),
+            base: String::from(base),
+        })
+    }
+}
+
+/// An item in a forge's issue tracker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    /// The number of the issue in its repository.
+    pub number: u64,
+
+    /// The title of the issue.
+    pub title: String,
+
+    /// The description of the issue, in Markdown.
+    pub body: String,
+
+    /// The web page of the issue.
+    pub url: String,
+}
+
+impl Issue {
+    /// A branch name for working on the issue, such as `issue-12-fix-crash-on-load`.
+    pub fn branch(&self) -> Result<BranchName> {
+        // Long titles make unwieldy branch names; whole words are kept
+        // up to a limit so that the name still reads naturally.
+        let mut name = format!("issue-{}", self.number);
+        for word in self.title.split_whitespace() {
+            if name.len() + word.len() >= MAX_BRANCH_LEN {
+                break;
+            }
+            name.push('-');
+            name.push_str(word);
+        }
+        branch::sanitize(&name)
+    }
+
+    /// A prompt asking the backend to resolve the issue.
+    pub fn prompt(&self) -> String {
+        let mut prompt = format!("{}\n\n", self.title);
+        if !self.body.trim().is_empty() {
+            prompt.push_str(self.body.trim());
+            prompt.push_str("\n\n");
+        }
+        prompt.push_str(&format!("Resolve issue #{}: {}", self.number, self.url));
+        prompt
+    }
+}
+
+/// A reference to an issue: either its web page,
+/// or its number in the project's own repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssueReference {
+    /// The repository the issue belongs to, if the reference names one.
+    pub repository: Option<Repository>,
+
+    /// The number of the issue.
+    pub number: u64,
+}
+
+impl FromStr for IssueReference {
+    type Err = Report;
+
+    /// Parse `12`, `#12`, or the URL of an issue page such as
+    /// `https://github.com/owner/repo/issues/12` or `https://gitlab.com/group/project/-/issues/12`.
+    fn from_str(reference: &str) -> Result<Self> {
+        if let Ok(number) = reference.trim_start_matches('#').parse() {
+            return Ok(Self {
+                repository: None,
+                number,
+            });
+        }
+
+        let (repository, number) = reference
+            .trim_end_matches('/')
+            .rsplit_once("/issues/")
+            .ok_or_else(|| eyre!("expected an issue number or URL, got '{reference}'"))?;
+        let number = number
+            .parse()
+            .with_context(|| format!("parse issue number in '{reference}'"))?;
+        let repository = repository.strip_suffix("/-").unwrap_or(repository);
+        Ok(Self {
+            repository: Some(repository.parse()?),
+            number,
+        })
+    }
+}
+
+/// An opened pull request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PullRequest {
+    /// The number of the pull request in its repository.
+    pub number: u64,
+
+    /// The web page of the pull request.
+    pub url: String,
+}
+
+/// GET `url`, parsing the JSON response.
+#[cfg(feature = "forge")]
+fn get_json<T: DeserializeOwned>(url: &str, headers: &[(&str, impl AsRef<str>)]) -> Result<T> {
+    let mut request = agent().get(url).header("User-Agent", "anna");
+    for (name, value) in headers {
+        request = request.header(*name, value.as_ref());
+    }
+    let response = request
+        .call()
+        .with_context(|| format!("send request: {url}"))?;
+    read_json(url, response)
+}
+
+/// POST `body` as JSON to `url`, parsing the JSON response.
+#[cfg(feature = "forge")]
+fn post_json<T: DeserializeOwned>(
+    url: &str,
+    headers: &[(&str, impl AsRef<str>)],
+    body: &serde_json::Value,
+) -> Result<T> {
+    let mut request = agent().post(url).header("User-Agent", "anna");
+    for (name, value) in headers {
+        request = request.header(*name, value.as_ref());
+    }
+    let response = request
+        .send_json(body)
+        .with_context(|| format!("send request: {url}"))?;
+    read_json(url, response)
+}
+
+#[cfg(feature = "forge")]
+fn agent() -> ureq::Agent {
+    // Error responses carry an explanation worth showing,
+    // which ureq would otherwise discard.
+    ureq::Agent::config_builder()
+        .http_status_as_error(false)
+        .build()
+        .new_agent()
+}
+
+#[cfg(feature = "forge")]
+fn read_json<T: DeserializeOwned>(
+    url: &str,
+    mut response: ureq::http::Response<ureq::Body>,
+) -> Result<T> {
+    let status = response.status();
+    if !status.is_success() {
+        let message = response.body_mut().read_to_string().unwrap_or_default();
+        bail!("{status}: {message}");
+    }
+    response
+        .body_mut()
+        .read_json::<T>()
+        .with_context(|| format!("parse response: {url}"))
+}
+
+/// The first token found in the named environment variables, or else printed by `fallback`,
+/// a command of the forge's CLI.
+#[cfg(feature = "forge")]
+fn token(forge: ForgeKind, host: &str, variables: &[&str], fallback: &[&str]) -> Result<String> {
+    if let Some(token) = variables
+        .iter()
+        .find_map(|name| env::var(name).ok().filter(|token| !token.is_empty()))
+    {
+        return Ok(token);
+    }
+
+    let output = fallback
+        .split_first()
+        .and_then(|(program, args)| Command::new(program).args(args).output().ok());
+    match output {
+        Some(output) if output.status.success() && !output.stdout.is_empty() => {
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        }
+        _ => Err(eyre!(
+            "no {forge} token for {host}; set {}",
+            variables.join(" or ")
+        )),
+    }
+}
+
+fn title(line: &str) -> String {
+    let line = line.trim();
+    match line.char_indices().nth(MAX_TITLE_LEN) {
+        Some((end, _)) => format!("{}…", line[..end].trim_end()),
+        None => String::from(line),
+    }
+}
@@ -0,0 +1,106 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use color_eyre::eyre::{Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{Forge, ForgeKind, Issue, PullRequest, PullRequestDraft, Repository};
+
+/// Gitea, or its fork Forgejo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gitea {
+    api: String,
+
+    token: String,
+}
+
+impl Gitea {
+    /// Connect to the Gitea instance at `host`,
+    /// authenticating with the token in `GITEA_TOKEN` or `FORGEJO_TOKEN`.
+    pub fn connect(host: &str) -> Result<Self> {
+        // The tea CLI has no way to print its token, so there is no fallback.
+        let token = super::token(
+            ForgeKind::Gitea,
+            host,
+            &["GITEA_TOKEN", "FORGEJO_TOKEN"],
+            &[],
+        )?;
+        Ok(Self::new(format!("https://{host}/api/v1"), token))
+    }
+
+    /// Use the API at the provided base URL, authenticating with `token`.
+    pub fn new(api: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            api: api.into(),
+            token: token.into(),
+        }
+    }
+}
+
+impl Forge for Gitea {
+    fn kind(&self) -> ForgeKind {
+        ForgeKind::Gitea
+    }
+
+    fn create_pull_request(
+        &self,
+        repository: &Repository,
+        draft: &PullRequestDraft,
+    ) -> Result<PullRequest> {
+        #[derive(Deserialize)]
+        struct Created {
+            number: u64,
+            html_url: String,
+        }
+
+        let url = format!(
+            "{}/repos/{}/{}/pulls",
+            self.api,
+            repository.owner(),
+            repository.name()
+        );
+        let body = json!({
+            "title": draft.title,
+            "body": draft.body,
+            "head": draft.head,
+            "base": draft.base,
+        });
+        let authorization = format!("token {}", self.token);
+        let headers = [("Authorization", authorization.as_str())];
+
+        let created = super::post_json::<Created>(&url, &headers, &body)
+            .with_context(|| format!("create pull request in {repository}"))?;
+        Ok(PullRequest {
+            number: created.number,
+            url: created.html_url,
+        })
+    }
+
+    fn issue(&self, repository: &Repository, number: u64) -> Result<Issue> {
+        #[derive(Deserialize)]
+        struct Fetched {
+            title: String,
+            body: Option<String>,
+            html_url: String,
+        }
+
+        let url = format!(
+            "{}/repos/{}/{}/issues/{number}",
+            self.api,
+            repository.owner(),
+            repository.name()
+        );
+        let authorization = format!("token {}", self.token);
+        let headers = [("Authorization", authorization.as_str())];
+        let fetched = super::get_json::<Fetched>(&url, &headers)
+            .with_context(|| format!("fetch issue #{number} of {repository}"))?;
+        Ok(Issue {
+            number,
+            title: fetched.title,
+            body: fetched.body.unwrap_or_default(),
+            url: fetched.html_url,
+        })
+    }
+}
@@ -0,0 +1,112 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use color_eyre::eyre::{Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{Forge, ForgeKind, Issue, PullRequest, PullRequestDraft, Repository};
+
+/// GitHub, or a GitHub Enterprise Server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitHub {
+    api: String,
+
+    token: String,
+}
+
+impl GitHub {
+    /// Connect to the GitHub instance at `host`, authenticating with the token in
+    /// `GITHUB_TOKEN` or `GH_TOKEN`, or else the one the GitHub CLI is logged in with.
+    pub fn connect(host: &str) -> Result<Self> {
+        let api = match host {
+            "github.com" => String::from("https://api.github.com"),
+            host => format!("https://{host}/api/v3"),
+        };
+        let token = super::token(
+            ForgeKind::GitHub,
+            host,
+            &["GITHUB_TOKEN", "GH_TOKEN"],
+            &["gh", "auth", "token", "--hostname", host],
+        )?;
+        Ok(Self::new(api, token))
+    }
+
+    /// Use the API at the provided base URL, authenticating with `token`.
+    pub fn new(api: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            api: api.into(),
+            token: token.into(),
+        }
+    }
+
+    fn headers(&self) -> Vec<(&str, String)> {
+        vec![
+            ("Accept", String::from("application/vnd.github+json")),
+            ("Authorization", format!("Bearer {}", self.token)),
+            ("X-GitHub-Api-Version", String::from("2022-11-28")),
+        ]
+    }
+}
+
+impl Forge for GitHub {
+    fn kind(&self) -> ForgeKind {
+        ForgeKind::GitHub
+    }
+
+    fn create_pull_request(
+        &self,
+        repository: &Repository,
+        draft: &PullRequestDraft,
+    ) -> Result<PullRequest> {
+        #[derive(Deserialize)]
+        struct Created {
+            number: u64,
+            html_url: String,
+        }
+
+        let url = format!(
+            "{}/repos/{}/{}/pulls",
+            self.api,
+            repository.owner(),
+            repository.name()
+        );
+        let body = json!({
+            "title": draft.title,
+            "body": draft.body,
+            "head": draft.head,
+            "base": draft.base,
+        });
+        let created = super::post_json::<Created>(&url, &self.headers(), &body)
+            .with_context(|| format!("create pull request in {repository}"))?;
+        Ok(PullRequest {
+            number: created.number,
+            url: created.html_url,
+        })
+    }
+
+    fn issue(&self, repository: &Repository, number: u64) -> Result<Issue> {
+        #[derive(Deserialize)]
+        struct Fetched {
+            title: String,
+            body: Option<String>,
+            html_url: String,
+        }
+
+        let url = format!(
+            "{}/repos/{}/{}/issues/{number}",
+            self.api,
+            repository.owner(),
+            repository.name()
+        );
+        let fetched = super::get_json::<Fetched>(&url, &self.headers())
+            .with_context(|| format!("fetch issue #{number} of {repository}"))?;
+        Ok(Issue {
+            number,
+            title: fetched.title,
+            body: fetched.body.unwrap_or_default(),
+            url: fetched.html_url,
+        })
+    }
+}
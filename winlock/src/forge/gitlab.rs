@@ -0,0 +1,115 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use color_eyre::eyre::{Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{Forge, ForgeKind, Issue, PullRequest, PullRequestDraft, Repository};
+
+/// GitLab, hosted or self-managed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitLab {
+    api: String,
+
+    token: String,
+}
+
+impl GitLab {
+    /// Connect to the GitLab instance at `host`, authenticating with the token in
+    /// `GITLAB_TOKEN`, or else the one the GitLab CLI is configured with.
+    pub fn connect(host: &str) -> Result<Self> {
+        let token = super::token(
+            ForgeKind::GitLab,
+            host,
+            &["GITLAB_TOKEN"],
+            &["glab", "config", "get", "token", "--host", host],
+        )?;
+        Ok(Self::new(format!("https://{host}/api/v4"), token))
+    }
+
+    /// Use the API at the provided base URL, authenticating with `token`.
+    pub fn new(api: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            api: api.into(),
+            token: token.into(),
+        }
+    }
+}
+
+impl Forge for GitLab {
+    fn kind(&self) -> ForgeKind {
+        ForgeKind::GitLab
+    }
+
+    fn create_pull_request(
+        &self,
+        repository: &Repository,
+        draft: &PullRequestDraft,
+    ) -> Result<PullRequest> {
+        #[derive(Deserialize)]
+        struct Created {
+            iid: u64,
+            web_url: String,
+        }
+
+        // GitLab accepts the URL-encoded path in place of the numeric project ID,
+        // which saves looking the ID up.
+        let url = format!(
+            "{}/projects/{}/merge_requests",
+            self.api,
+            encode(&repository.path)
+        );
+        let body = json!({
+            "title": draft.title,
+            "description": draft.body,
+            "source_branch": draft.head,
+            "target_branch": draft.base,
+        });
+        let headers = [("PRIVATE-TOKEN", self.token.as_str())];
+
+        let created = super::post_json::<Created>(&url, &headers, &body)
+            .with_context(|| format!("create merge request in {repository}"))?;
+        Ok(PullRequest {
+            number: created.iid,
+            url: created.web_url,
+        })
+    }
+
+    fn issue(&self, repository: &Repository, number: u64) -> Result<Issue> {
+        #[derive(Deserialize)]
+        struct Fetched {
+            title: String,
+            description: Option<String>,
+            web_url: String,
+        }
+
+        let url = format!(
+            "{}/projects/{}/issues/{number}",
+            self.api,
+            encode(&repository.path)
+        );
+        let headers = [("PRIVATE-TOKEN", self.token.as_str())];
+        let fetched = super::get_json::<Fetched>(&url, &headers)
+            .with_context(|| format!("fetch issue #{number} of {repository}"))?;
+        Ok(Issue {
+            number,
+            title: fetched.title,
+            body: fetched.description.unwrap_or_default(),
+            url: fetched.web_url,
+        })
+    }
+}
+
+/// Percent-encode everything but unreserved URL characters.
+fn encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
@@ -0,0 +1,198 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! What happened to each session over its life, for auditing what agents did.
+//!
+//! Each branch of a project has a history in the store, kept after its sessions are
+//! removed, of newline-delimited JSON [entries](HistoryEntry) such as:
+//!
+//! ```json
+//! {"at":"2026-10-16T09:30:00Z","session":"3f2a…","event":"created","workspace":"/tmp/project-feature-a1b2c3"}
+//! {"at":"2026-10-16T09:41:12Z","session":"3f2a…","event":"backend_exited","backend":"claude","code":0,"success":true,"duration_ms":672000}
+//! ```
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::eyre::{Context, Result};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// An event in a session's history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// When it happened.
+    pub at: Timestamp,
+
+    /// The [session](crate::session::Session::id) it happened to, which tells apart
+    /// the sessions of a branch created and removed over time.
+    pub session: Uuid,
+
+    /// What happened.
+    #[serde(flatten)]
+    pub event: HistoryEvent,
+}
+
+/// Something that happened to a session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum HistoryEvent {
+    /// The session was created, with a new workspace.
+    Created {
+        /// The session's workspace.
+        workspace: PathBuf,
+    },
+
+    /// An existing checkout was adopted as the session's workspace.
+    Adopted {
+        /// The session's workspace.
+        workspace: PathBuf,
+    },
+
+    /// The session was resumed.
+    Resumed,
+
+    /// A backend run in the session exited.
+    BackendExited {
+        /// The backend's program.
+        backend: String,
+
+        /// Its exit code, or `None` if it was killed by a signal.
+        code: Option<i32>,
+
+        /// Whether it exited successfully.
+        success: bool,
+
+        /// How long it ran, restarts included, in milliseconds.
+        duration_ms: u64,
+    },
+
+    /// The session's workspace was verified.
+    Verified {
+        /// The verification command.
+        command: String,
+
+        /// Whether it passed.
+        passed: bool,
+    },
+
+    /// The session's branch was rebased onto what's checked out in the project.
+    Rebased {
+        /// The revision it was rebased onto.
+        base: String,
+    },
+
+    /// The session's branch was merged back into the project.
+    Merged,
+
+    /// The session's workspace was archived.
+    Archived,
+
+    /// The session's workspace was restored from its archive.
+    Unarchived,
+
+    /// The session's records were moved here from where the project used to be.
+    Relinked {
+        /// The project's previous directory.
+        from: PathBuf,
+    },
+
+    /// The session was removed.
+    Removed,
+}
+
+/// A description of the event for people, without the [name](HistoryEvent::name) of its kind.
+impl fmt::Display for HistoryEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryEvent::Created { workspace } | HistoryEvent::Adopted { workspace } => {
+                write!(f, "{}", workspace.display())
+            }
+            HistoryEvent::BackendExited {
+                backend,
+                code,
+                success: _,
+                duration_ms,
+            } => {
+                let seconds = Duration::from_millis(*duration_ms).as_secs_f64();
+                match code {
+                    Some(code) => write!(f, "{backend} exited with {code} after {seconds:.1}s"),
+                    None => write!(f, "{backend} was killed after {seconds:.1}s"),
+                }
+            }
+            HistoryEvent::Verified { command, passed } => {
+                let result = if *passed { "passed" } else { "failed" };
+                write!(f, "{result}: {command}")
+            }
+            HistoryEvent::Rebased { base } => write!(f, "onto {base}"),
+            HistoryEvent::Relinked { from } => write!(f, "from {}", from.display()),
+            HistoryEvent::Resumed
+            | HistoryEvent::Merged
+            | HistoryEvent::Archived
+            | HistoryEvent::Unarchived
+            | HistoryEvent::Removed => Ok(()),
+        }
+    }
+}
+
+impl HistoryEvent {
+    /// The name of the event's kind, as in its `event` field.
+    pub fn name(&self) -> &'static str {
+        match self {
+            HistoryEvent::Created { .. } => "created",
+            HistoryEvent::Adopted { .. } => "adopted",
+            HistoryEvent::Resumed => "resumed",
+            HistoryEvent::BackendExited { .. } => "backend_exited",
+            HistoryEvent::Verified { .. } => "verified",
+            HistoryEvent::Rebased { .. } => "rebased",
+            HistoryEvent::Merged => "merged",
+            HistoryEvent::Archived => "archived",
+            HistoryEvent::Unarchived => "unarchived",
+            HistoryEvent::Relinked { .. } => "relinked",
+            HistoryEvent::Removed => "removed",
+        }
+    }
+}
+
+/// Append `entry` to the history at `path`.
+///
+/// Each entry is written with a single append, which the platforms anna supports keep
+/// whole when several processes append at once, so this needs no lock. That matters
+/// since entries are appended while the sessions lock is held, and it doesn't nest.
+pub(crate) fn append(path: &Path, entry: &HistoryEntry) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("create history directory: {}", dir.display()))?;
+    }
+    let mut line = serde_json::to_string(entry).context("serialize history entry")?;
+    line.push('\n');
+    let mut file = File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("open history: {}", path.display()))?;
+    file.write_all(line.as_bytes())
+        .with_context(|| format!("append to history: {}", path.display()))
+}
+
+/// Read the history at `path`, oldest first; empty if there is none.
+pub(crate) fn read(path: &Path) -> Result<Vec<HistoryEntry>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).context(format!("read history: {}", path.display())),
+    };
+    // A line cut short by a crash mid-append, and whatever was appended to it after,
+    // is skipped rather than hiding the rest of the history.
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
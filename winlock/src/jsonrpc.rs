@@ -0,0 +1,109 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The [JSON-RPC 2.0](https://www.jsonrpc.org/specification) messages spoken by
+//! the [daemon](crate::daemon) and the [MCP server](crate::mcp), one per line.
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// The error code for requests that aren't valid JSON.
+pub const PARSE_ERROR: i64 = -32700;
+
+/// The error code for methods the server doesn't have.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+
+/// The error code for parameters a method can't take.
+pub const INVALID_PARAMS: i64 = -32602;
+
+/// The error code for methods that failed.
+pub const SERVER_ERROR: i64 = -32000;
+
+/// A JSON-RPC request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    /// Always `2.0`.
+    pub jsonrpc: String,
+
+    /// Echoed in the response; requests without one are notifications,
+    /// which get no response.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+
+    /// The method to call.
+    pub method: String,
+
+    /// The method's parameters.
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+}
+
+/// A JSON-RPC response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    /// Always `2.0`.
+    pub jsonrpc: String,
+
+    /// The ID of the request this responds to; null if it couldn't be read.
+    pub id: Value,
+
+    /// What the method returned, if it succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+
+    /// Why the method failed, if it did.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    /// A response to request `id` with what the method returned or why it failed.
+    pub fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: String::from("2.0"),
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+/// Why a JSON-RPC request failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcError {
+    /// The JSON-RPC error code.
+    pub code: i64,
+
+    /// A description of the error.
+    pub message: String,
+}
+
+impl RpcError {
+    /// An error with `code` and `message`.
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// The method failed with `err`.
+    pub fn server(err: color_eyre::Report) -> Self {
+        Self::new(SERVER_ERROR, format!("{err:#}"))
+    }
+}
+
+/// Read a method's parameters, failing with [`INVALID_PARAMS`].
+pub fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // Methods whose parameters are all optional may be called without any.
+    let params = match params {
+        Value::Null => json!({}),
+        params => params,
+    };
+    serde_json::from_value(params).map_err(|err| RpcError::new(INVALID_PARAMS, err.to_string()))
+}
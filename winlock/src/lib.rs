@@ -7,18 +7,1721 @@
 //! Anna Winlock was a pioneering woman in computing who worked at Harvard College
 //! Observatory in the late 1800s. She was known for her mathematical and
 //! computational work in astronomy.
+//!
+//! # Features
+//!
+//! Functionality with heavy dependencies can be left out by embedders
+//! who don't need it; all of it is enabled by default.
+//!
+//! - `archive`: [archiving](session::Sessions::archive) idle sessions' workspaces.
+//! - `cli-backends`: [running](Agent::run) backends.
+//! - `copy`: copying projects into workspaces, which [creating](Agent::new) sessions requires,
+//!   and [refreshing](workspace::refresh_workspace) them.
+//! - `dashboard`: serving a read-only view of the sessions over HTTP, the [`dashboard`].
+//! - `forge`: clients for forges' APIs, such as [`forge::GitHub`].
+//! - `notify`: posting [notifications](notify) to webhooks.
+//! - `pty`: running backends under a pseudo-terminal, which [transcripts](transcript) require.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    time::{Duration, Instant},
+};
+#[cfg(feature = "cli-backends")]
+use std::{io::IsTerminal, thread};
+
+use color_eyre::eyre::{bail, Context, Result};
+use jiff::Timestamp;
+use uuid::Uuid;
+
+pub mod backend;
+pub mod branch;
+pub mod config;
+pub mod credentials;
+#[cfg(unix)]
+pub mod daemon;
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
+pub mod devcontainer;
+pub mod environment;
+pub mod events;
+pub mod forge;
+pub mod history;
+pub mod jsonrpc;
+pub mod mcp;
+pub mod multiplexer;
+pub mod network;
+pub mod notify;
+pub mod prompt;
+#[cfg(feature = "pty")]
+mod pty;
+pub mod queue;
+pub mod remote;
+pub mod resources;
+pub mod review;
+pub mod session;
+pub mod snapshot;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod toolchain;
+pub mod transcript;
+pub mod usage;
+pub mod vcs;
+pub mod workflow;
+pub mod workspace;
+
+#[cfg(feature = "cli-backends")]
+use backend::{CommandTemplate, Invocation, Placeholder, TemplateValues};
+use backend::{Launcher, Restart};
+use branch::BranchName;
+use config::Profile;
+use devcontainer::Container;
+use environment::Environment;
+use events::{Event, Events};
+use history::HistoryEvent;
+use multiplexer::MultiplexerKind;
+use network::NetworkPolicy;
+use remote::Remote;
+use resources::ResourceUsage;
+#[cfg(feature = "cli-backends")]
+use session::{AlreadyRunning, PromptRecord, RunRecord, RunningBackend};
+use session::{LinkedWorkspace, Session, Sessions, Verification, WorkspaceMarker};
+use toolchain::Toolchain;
+use vcs::VcsKind;
+use workspace::{CacheReport, CopyEngineKind, CopyOptions, CopyProgress, CopyReport, CopyTooLarge};
+
+/// The environment variable through which the backend finds the session's
+/// [linked workspaces](session::LinkedWorkspace), separated as in `PATH`.
+pub const LINKED_WORKSPACES_VARIABLE: &str = "ANNA_LINKED_WORKSPACES";
+
+/// How often [`Event::CopyProgress`] is emitted while creating a workspace.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
 
 /// The main agent type that coordinates interactions with the LLM.
 #[derive(Debug)]
 pub struct Agent {
-    // Fields will be added as we implement functionality
+    session: Session,
+
+    status: AgentSessionStatus,
+
+    data_dir: PathBuf,
+
+    sessions: Sessions,
+
+    creation: Option<CreationReport>,
+
+    events: Events,
 }
 
 impl Agent {
-    /// Creates a new agent instance.
-    pub fn new() -> Self {
-        Self {}
+    /// Creates an agent working on `branch` of `project`.
+    ///
+    /// If a session for the branch already exists it is resumed;
+    /// otherwise a new session is created with a fresh copy of the project.
+    pub fn new(
+        sessions: &Sessions,
+        project: impl AsRef<Path>,
+        branch: BranchName,
+        options: &CreateOptions,
+    ) -> Result<Self> {
+        let project = project.as_ref();
+        let project = fs::canonicalize(project)
+            .with_context(|| format!("canonicalize project: {}", project.display()))?;
+
+        Self::check_project(sessions, &project)?;
+        let mut options = options.clone();
+        for other in &mut options.also {
+            let path = &mut other.path;
+            *path = fs::canonicalize(&*path)
+                .with_context(|| format!("canonicalize linked project: {}", path.display()))?;
+            if *path == project {
+                bail!("the project can't be linked to itself: {}", path.display());
+            }
+            Self::check_project(sessions, path)?;
+        }
+        let options = &options;
+
+        let data_dir = sessions.data_dir(&project, &branch);
+        let mut creation = None;
+        let (mut session, status) = sessions.get_or_create(&project, &branch, || {
+            let started = Instant::now();
+            let (session, copy, caches, linked) =
+                Self::create_session(project.clone(), branch.clone(), options)?;
+            creation = Some(CreationReport {
+                copy,
+                caches,
+                linked,
+                duration: started.elapsed(),
+            });
+            Ok(session)
+        })?;
+        if status == AgentSessionStatus::Resumed {
+            // Lost a race with another process creating the same session.
+            creation = None;
+        }
+        if let Some(CreationReport { copy, duration, .. }) = &creation {
+            options.events.emit(&Event::CopyFinished {
+                files: copy.files,
+                bytes: copy.bytes,
+                withheld: copy.withheld.len(),
+                oversized: copy.oversized.len(),
+                failed: copy.warnings.len(),
+                duration_ms: u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+            });
+        }
+        if status == AgentSessionStatus::Resumed && session.archived {
+            session = Self::unarchive(sessions, &project, &branch)?;
+        }
+        if status == AgentSessionStatus::Resumed && !session.workspace.is_dir() {
+            bail!(
+                "workspace for '{branch}' no longer exists: {}",
+                session.workspace.display()
+            );
+        }
+
+        if status == AgentSessionStatus::Resumed {
+            sessions.record(&session, HistoryEvent::Resumed);
+        }
+
+        let (project, branch, workspace) = (
+            session.project.clone(),
+            session.branch.to_string(),
+            session.workspace.clone(),
+        );
+        options.events.emit(&match status {
+            AgentSessionStatus::Created => Event::SessionCreated {
+                project,
+                branch,
+                workspace,
+            },
+            AgentSessionStatus::Resumed => Event::SessionResumed {
+                project,
+                branch,
+                workspace,
+            },
+        });
+
+        Ok(Self {
+            session,
+            status,
+            data_dir,
+            sessions: sessions.clone(),
+            creation,
+            events: options.events.clone(),
+        })
+    }
+
+    /// Opens the existing session for `branch` of `project` as it is, without resuming it:
+    /// nothing is recorded in its history, and an archived session stays archived.
+    /// `None` if there's no such session.
+    pub fn open(
+        sessions: &Sessions,
+        project: impl AsRef<Path>,
+        branch: &BranchName,
+    ) -> Result<Option<Self>> {
+        let project = project.as_ref();
+        // Canonical, as when the session was created, so that relative and symlinked
+        // paths find it; a project that's gone, such as one that was moved,
+        // can only be named as it was recorded.
+        let project = fs::canonicalize(project).unwrap_or_else(|_| project.to_path_buf());
+        let Some(session) = sessions.get(&project, branch)? else {
+            return Ok(None);
+        };
+        if !session.workspace.is_dir() {
+            bail!(
+                "workspace for '{branch}' no longer exists: {}",
+                session.workspace.display()
+            );
+        }
+        Ok(Some(Self {
+            data_dir: sessions.data_dir(&project, branch),
+            session,
+            status: AgentSessionStatus::Resumed,
+            sessions: sessions.clone(),
+            creation: None,
+            events: Events::default(),
+        }))
+    }
+
+    /// Refuse to treat anna's own state as a project: copying a workspace
+    /// or the session store would copy earlier workspaces into the new one.
+    fn check_project(sessions: &Sessions, project: &Path) -> Result<()> {
+        if let Some(session) = sessions.containing(project)? {
+            return Err(UnsuitableProject::Workspace {
+                dir: project.to_path_buf(),
+                project: session.project,
+                branch: session.branch,
+            }
+            .into());
+        }
+        // The store may not exist yet when it's somewhere other than where sessions live.
+        let store =
+            fs::canonicalize(sessions.dir()).unwrap_or_else(|_| sessions.dir().to_path_buf());
+        if project.starts_with(&store) {
+            return Err(UnsuitableProject::Store {
+                dir: project.to_path_buf(),
+                store,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "archive")]
+    fn unarchive(sessions: &Sessions, project: &Path, branch: &BranchName) -> Result<Session> {
+        let Some(session) = sessions.unarchive(project, branch)? else {
+            bail!("session for '{branch}' was removed while resuming it");
+        };
+        Ok(session)
+    }
+
+    #[cfg(not(feature = "archive"))]
+    fn unarchive(_sessions: &Sessions, _project: &Path, branch: &BranchName) -> Result<Session> {
+        bail!("session '{branch}' is archived, and unarchiving it requires winlock's `archive` feature")
+    }
+
+    fn create_session(
+        project: PathBuf,
+        branch: BranchName,
+        options: &CreateOptions,
+    ) -> Result<(Session, CopyReport, CacheReport, Vec<CopyReport>)> {
+        // Checked before copying, since the copy is by far the most expensive step.
+        let detected = VcsKind::detect(&project);
+        if options.vcs != VcsKind::None && options.vcs != detected {
+            bail!(
+                "project is not a {} repository: {}",
+                options.vcs,
+                project.display()
+            );
+        }
+        if options.lfs.is_some() && options.vcs != VcsKind::Git {
+            bail!(
+                "LFS pointers can only be used in git workspaces, not {}",
+                options.vcs
+            );
+        }
+        if options.existing && options.base.is_some() {
+            bail!("an existing branch can't be checked out from a base revision");
+        }
+        if options.existing && options.vcs == VcsKind::None {
+            bail!("checking out an existing branch requires version control");
+        }
+        if !options.git_config.is_empty() && options.vcs != VcsKind::Git {
+            bail!(
+                "git settings can only be applied to git workspaces, not {}",
+                options.vcs
+            );
+        }
+        if options.upstream.is_some() && options.vcs != VcsKind::Git {
+            bail!(
+                "tracking a remote branch is only supported in git workspaces, not {}",
+                options.vcs
+            );
+        }
+
+        if let Some(group) = &options.group {
+            session::check_group_name(group)?;
+        }
+        // Linked workspaces are found through paths on this machine.
+        if options.remote.is_some() && !options.also.is_empty() {
+            bail!("sessions spanning several projects can't run on a remote machine");
+        }
+
+        if let Some(program) = &options.backend {
+            backend::probe(program, &project)?;
+        }
+
+        let pointers = match &options.lfs {
+            Some(_) => Some(vcs::lfs::pointers(&project).context("find LFS files")?),
+            None => None,
+        };
+        let mut exclude = match &pointers {
+            Some(pointers) => [options.exclude.clone(), pointers.exclude()].concat(),
+            None => options.exclude.clone(),
+        };
+        exclude.extend(
+            options
+                .toolchains
+                .iter()
+                .flat_map(|toolchain| toolchain.exclude())
+                .map(|&pattern| String::from(pattern)),
+        );
+
+        if options.check_space || options.copy_limit.is_some() {
+            let estimate = workspace::estimate_copy(&project, &exclude, options.max_file_size)?;
+            if let Some(limit) = options.copy_limit.filter(|&limit| estimate.bytes > limit) {
+                return Err(CopyTooLarge { estimate, limit }.into());
+            }
+            if options.check_space {
+                workspace::ensure_space(&workspace::root(), estimate.bytes)?;
+            }
+        }
+
+        let workspace = workspace::create_dir(&workspace::root(), &project, branch.as_str())?;
+        // Canonical so that it can be compared with the current directory,
+        // even where the temporary directory is behind a symlink.
+        let workspace = fs::canonicalize(&workspace)
+            .with_context(|| format!("canonicalize workspace: {}", workspace.display()))?;
+
+        // Progress is reported at most every so often, since entries are copied
+        // far faster than anything watching can usefully keep up with.
+        let mut reported = Instant::now();
+        let mut progress = |progress: CopyProgress<'_>| {
+            if options.events.enabled() && reported.elapsed() >= PROGRESS_INTERVAL {
+                reported = Instant::now();
+                options.events.emit(&match progress {
+                    CopyProgress::Entry(report) => Event::CopyProgress {
+                        files: report.files,
+                        bytes: report.bytes,
+                    },
+                    CopyProgress::File { path, copied, size } => Event::FileCopyProgress {
+                        path: path.to_path_buf(),
+                        copied,
+                        size,
+                    },
+                });
+            }
+        };
+        options
+            .copy_engine
+            .engine()
+            .copy(
+                &project,
+                &workspace,
+                &exclude,
+                &options.copy_options(),
+                &mut progress,
+            )
+            .with_context(|| format!("copy project to workspace: {}", workspace.display()))
+            .and_then(|copy| {
+                let vcs = options.vcs.vcs();
+                vcs.own_repository(&project, &workspace)?;
+                Ok(copy)
+            })
+            .and_then(|mut copy| {
+                let (session, caches, linked) = Self::prepare_workspace(
+                    project,
+                    branch,
+                    workspace.clone(),
+                    options,
+                    pointers,
+                    &mut copy,
+                )?;
+                Ok((session, copy, caches, linked))
+            })
+            .inspect_err(|_| {
+                // A partial workspace is of no use, and nothing records it to clean it up
+                // until the session is stored. Best effort: the error is what's worth reporting.
+                let _ = workspace::delete_dir(&workspace);
+            })
+    }
+
+    /// Make `workspace`, just copied from `project`, ready for a session on `branch`:
+    /// check out the branch, and run everything that's configured to set it up.
+    /// Tracked files left out of the `copy` that still read as deleted are added to it,
+    /// as are submodules that failed to check out, as warnings.
+    fn prepare_workspace(
+        project: PathBuf,
+        branch: BranchName,
+        workspace: PathBuf,
+        options: &CreateOptions,
+        pointers: Option<vcs::lfs::Pointers>,
+        copy: &mut CopyReport,
+    ) -> Result<(Session, CacheReport, Vec<CopyReport>)> {
+        let vcs = options.vcs.vcs();
+        // Applied first so that the settings, such as hooks, cover everything done here.
+        for (key, value) in &options.git_config {
+            vcs.set_config(&workspace, key, value)
+                .with_context(|| format!("set git config '{key}' in workspace"))?;
+        }
+        if let Some(pointers) = &pointers {
+            pointers.write(&workspace)?;
+        }
+        // After the pointers, which were left out only to be written in their place.
+        copy.deleted = vcs.hide_left_out(&project, &workspace)?;
+        let base = match (options.vcs, &options.base) {
+            _ if options.existing => Some(
+                vcs.check_out_existing(&workspace, &branch)
+                    .with_context(|| format!("check out branch '{branch}' in workspace"))?,
+            ),
+            (VcsKind::None, None) => None,
+            (VcsKind::None, Some(_)) => bail!("a base revision requires version control"),
+            (_, None) => Some(vcs.head(&workspace).context("get base revision")?),
+            (_, Some(base)) => Some(
+                vcs.resolve(&workspace, base)
+                    .with_context(|| format!("resolve base revision '{base}'"))?,
+            ),
+        };
+        if let Some(base) = base.as_ref().filter(|_| !options.existing) {
+            vcs.create_branch(&workspace, &branch, base)
+                .with_context(|| format!("create branch '{branch}' in workspace"))?;
+        }
+        if let Some(remote) = &options.upstream {
+            vcs.set_upstream(&workspace, remote, &branch)
+                .with_context(|| format!("make '{branch}' track {remote}/{branch}"))?;
+        }
+        // Only a warning, since a submodule that can't be fetched, such as a private one,
+        // is usually one the agent can do without.
+        if options.submodules {
+            if let Err(err) = vcs.init_submodules(&workspace) {
+                copy.warnings
+                    .push(format!("check out submodules in workspace: {err:#}"));
+            }
+        }
+        if let Some(lfs) = options.lfs.as_ref().filter(|lfs| !lfs.pull.is_empty()) {
+            vcs::lfs::pull(&workspace, &lfs.pull)?;
+        }
+        let marker = WorkspaceMarker {
+            project: project.clone(),
+            branch: branch.clone(),
+            id: Uuid::new_v4(),
+        };
+        marker.write(&workspace, options.vcs)?;
+
+        let caches = match &options.caches {
+            Some(caches) => workspace::seed_caches(&workspace, caches)?,
+            None => CacheReport::default(),
+        };
+
+        // Toolchains on remote machines are theirs to set up.
+        let toolchains = match &options.remote {
+            Some(_) => Vec::new(),
+            None => options.toolchains.clone(),
+        };
+        for toolchain in &toolchains {
+            toolchain
+                .prepare(&workspace)
+                .with_context(|| format!("prepare {toolchain} toolchain in workspace"))?;
+        }
+        let toolchain_env = toolchain::env(&toolchains, &workspace)?;
+
+        // Setup commands run where the backend does, since what they make,
+        // such as fetched dependencies, is usually ignored by git and so not mirrored.
+        if let Some(remote) = &options.remote {
+            remote.push(&workspace)?;
+        }
+        for command in &options.setup {
+            let mut setup = match &options.remote {
+                Some(remote) => remote.shell(&workspace, command)?,
+                None => {
+                    let mut setup = shell(command);
+                    setup
+                        .envs(toolchain_env.iter().cloned())
+                        .current_dir(&workspace);
+                    setup
+                }
+            };
+            // Their output is progress, like narration, so it goes where narration does,
+            // leaving standard output to data, such as what an MCP server answers;
+            // nor do they read standard input, which may be the MCP client's requests.
+            let status = setup
+                .stdin(Stdio::null())
+                .stdout(io::stderr())
+                .status()
+                .with_context(|| format!("run setup command: {command}"))?;
+            if !status.success() {
+                bail!("setup command failed with {status}: {command}");
+            }
+        }
+
+        let project_id = session::project_id(&project, options.vcs)?;
+        // Last, so that nothing can fail once they're made and leave them behind.
+        let mut linked = Vec::new();
+        let mut linked_copies = Vec::new();
+        for other in &options.also {
+            match Self::create_linked(other, &marker, options) {
+                Ok((workspace, copy)) => {
+                    linked.push(workspace);
+                    linked_copies.push(copy);
+                }
+                Err(err) => {
+                    // Best effort, as for the session's own workspace.
+                    for linked in &linked {
+                        let _ = workspace::delete_dir(&linked.workspace);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        let session = Session {
+            id: marker.id,
+            project,
+            branch,
+            workspace,
+            vcs: options.vcs,
+            base,
+            created_at: Timestamp::now(),
+            refreshed_at: None,
+            adopted: false,
+            archived: false,
+            resume_tokens: BTreeMap::new(),
+            prompt: None,
+            model: None,
+            issue: options.issue.clone(),
+            verification: None,
+            environment: Some(Environment::capture()),
+            usage: BTreeMap::new(),
+            linked,
+            prompts: Vec::new(),
+            resources: Vec::new(),
+            remote: options.remote.clone(),
+            group: options.group.clone(),
+            project_id,
+            runs: Vec::new(),
+            toolchains,
+        };
+        Ok((session, caches, linked_copies))
+    }
+
+    /// Copy `project` into a workspace linked to the session `marker` identifies,
+    /// creating the session's branch there from what's checked out in the project.
+    /// If that fails, the linked workspace is deleted.
+    fn create_linked(
+        project: &LinkedProject,
+        marker: &WorkspaceMarker,
+        options: &CreateOptions,
+    ) -> Result<(LinkedWorkspace, CopyReport)> {
+        let workspace =
+            workspace::create_dir(&workspace::root(), &project.path, marker.branch.as_str())?;
+        let workspace = fs::canonicalize(&workspace)
+            .with_context(|| format!("canonicalize workspace: {}", workspace.display()))?;
+        Self::prepare_linked(project, &workspace, marker, options).inspect_err(|_| {
+            // Best effort: the error is what's worth reporting.
+            let _ = workspace::delete_dir(&workspace);
+        })
+    }
+
+    /// Copy `project` into `workspace`, and make it ready, for [`create_linked`](Self::create_linked).
+    fn prepare_linked(
+        project: &LinkedProject,
+        workspace: &Path,
+        marker: &WorkspaceMarker,
+        options: &CreateOptions,
+    ) -> Result<(LinkedWorkspace, CopyReport)> {
+        let (branch, kind) = (&marker.branch, project.vcs);
+        let mut copy = project
+            .copy_engine
+            .engine()
+            .copy(
+                &project.path,
+                workspace,
+                &project.exclude,
+                &options.copy_options(),
+                &mut |_| {},
+            )
+            .with_context(|| {
+                format!(
+                    "copy linked project {} to workspace: {}",
+                    project.path.display(),
+                    workspace.display()
+                )
+            })?;
+
+        let vcs = kind.vcs();
+        vcs.own_repository(&project.path, workspace)?;
+        copy.deleted = vcs.hide_left_out(&project.path, workspace)?;
+        for (key, value) in &project.git_config {
+            vcs.set_config(workspace, key, value)
+                .with_context(|| format!("set git config '{key}' in linked workspace"))?;
+        }
+        let base = match kind {
+            VcsKind::None => None,
+            _ => {
+                let base = vcs.head(workspace).context("get linked base revision")?;
+                vcs.create_branch(workspace, branch, &base)
+                    .with_context(|| format!("create branch '{branch}' in linked workspace"))?;
+                Some(base)
+            }
+        };
+        if options.submodules {
+            if let Err(err) = vcs.init_submodules(workspace) {
+                copy.warnings
+                    .push(format!("check out submodules in linked workspace: {err:#}"));
+            }
+        }
+        marker.write(workspace, kind)?;
+
+        let linked = LinkedWorkspace {
+            project: project.path.clone(),
+            workspace: workspace.to_path_buf(),
+            vcs: kind,
+            base,
+        };
+        Ok((linked, copy))
+    }
+
+    /// The session the agent works in.
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Whether the session was created or resumed.
+    pub fn status(&self) -> AgentSessionStatus {
+        self.status
+    }
+
+    /// How the workspace was created, if it was created rather than resumed.
+    pub fn creation(&self) -> Option<&CreationReport> {
+        self.creation.as_ref()
+    }
+
+    /// The directory holding data recorded for the session, such as transcripts.
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    /// The directory holding the session's transcripts.
+    pub fn transcript_dir(&self) -> PathBuf {
+        transcript::dir(&self.data_dir)
+    }
+
+    /// The original project directory.
+    pub fn project(&self) -> &Path {
+        &self.session.project
+    }
+
+    /// The directory in which the agent works.
+    pub fn workspace(&self) -> &Path {
+        &self.session.workspace
+    }
+
+    /// The branch on which the agent works.
+    pub fn branch(&self) -> &BranchName {
+        &self.session.branch
+    }
+
+    /// The version control system used in the workspace.
+    pub fn vcs(&self) -> VcsKind {
+        self.session.vcs
+    }
+
+    /// The revision the branch was created from,
+    /// or `None` if the workspace isn't under version control.
+    pub fn base(&self) -> Option<&str> {
+        self.session.base.as_deref()
+    }
+
+    /// Render the changes made in the workspace since the branch was created.
+    pub fn diff(&self) -> Result<String> {
+        self.session.diff()
+    }
+
+    /// Bring the branch back into the project, without changing what is checked out there.
+    pub fn merge_back(&self) -> Result<()> {
+        self.session.merge_back()?;
+        self.sessions.record(&self.session, HistoryEvent::Merged);
+        Ok(())
+    }
+
+    /// How many commits the revision checked out in the project is ahead of the
+    /// revision the branch was created from, or `None` if the workspace isn't
+    /// under version control.
+    ///
+    /// Agents resumed long after their branch was created are working
+    /// against code that may have changed a lot since.
+    pub fn commits_behind(&self) -> Result<Option<u64>> {
+        let Some(base) = &self.session.base else {
+            return Ok(None);
+        };
+        let vcs = self.session.vcs.vcs();
+        let head = vcs.head(&self.session.project)?;
+        vcs.count_commits(&self.session.project, base, &head)
+            .map(Some)
+    }
+
+    /// Rebase the branch onto the revision checked out in the project,
+    /// which becomes the session's base, and likewise in each linked workspace
+    /// onto what's checked out in its project; returns the session's new base.
+    pub fn rebase(&mut self) -> Result<String> {
+        let branch = self.session.branch.clone();
+        let base = self.session.vcs.vcs().rebase_onto_project(
+            &self.session.workspace,
+            &self.session.project,
+            &branch,
+        )?;
+        // Recorded as each is rebased, so that a conflict in a linked workspace
+        // leaves the bases of those already rebased right.
+        self.record_base(|session| session.base = Some(base.clone()))?;
+        let event = HistoryEvent::Rebased { base: base.clone() };
+        self.sessions.record(&self.session, event);
+
+        for index in 0..self.session.linked.len() {
+            let linked = &self.session.linked[index];
+            if linked.base.is_none() {
+                continue;
+            }
+            let linked_base = linked
+                .vcs
+                .vcs()
+                .rebase_onto_project(&linked.workspace, &linked.project, &branch)
+                .with_context(|| {
+                    format!("rebase linked workspace: {}", linked.workspace.display())
+                })?;
+            let workspace = linked.workspace.clone();
+            self.record_base(|session| {
+                let linked = session
+                    .linked
+                    .iter_mut()
+                    .find(|linked| linked.workspace == workspace);
+                if let Some(linked) = linked {
+                    linked.base = Some(linked_base);
+                }
+            })?;
+        }
+        Ok(base)
+    }
+
+    /// Record the session's new base, or a linked workspace's, as `update` sets it.
+    fn record_base(&mut self, update: impl FnOnce(&mut Session)) -> Result<()> {
+        let updated = self
+            .sessions
+            .update(&self.session.project, &self.session.branch, update)
+            .context("record new base")?;
+        if let Some(updated) = updated {
+            self.session = updated;
+        }
+        Ok(())
+    }
+
+    /// Run the backend described by `profile` in the workspace, waiting for it to exit.
+    ///
+    /// If the backend has run in this session before, its previous conversation
+    /// is continued using the profile's [`resume`](Profile::resume) command.
+    ///
+    /// Fails with [`AlreadyRunning`](session::AlreadyRunning) if a backend is running
+    /// in the session already, here or in another process.
+    #[cfg(feature = "cli-backends")]
+    pub fn run(&mut self, profile: &Profile, options: &RunOptions) -> Result<RunOutcome> {
+        let prompt = options.prompt.as_deref();
+
+        // Held until the run is done with the workspace, so that others can tell it
+        // isn't free, and taken before anything else so that nothing is prepared
+        // for a backend that can't run.
+        let Some(running) = self
+            .sessions
+            .mark_running(&self.session.project, &self.session.branch)?
+        else {
+            let backend = self
+                .sessions
+                .running_backend(&self.session.project, &self.session.branch)?
+                .unwrap_or_default();
+            return Err(AlreadyRunning {
+                branch: self.session.branch.clone(),
+                backend,
+            }
+            .into());
+        };
+        let mut running_backend = RunningBackend::current(options.multiplexer);
+        self.sessions.record_running(
+            &self.session.project,
+            &self.session.branch,
+            &running_backend,
+        )?;
+
+        let backend = profile.command.program();
+        let previous = match options.new_conversation {
+            true => None,
+            false => self.session.resume_tokens.get(backend).cloned(),
+        };
+        let resumed = previous.is_some();
+        let model = options
+            .model
+            .clone()
+            .or_else(|| self.session.model.clone())
+            .or_else(|| profile.model.clone());
+        let (command, resume_token) = match (&profile.resume, previous) {
+            (Some(resume), Some(token)) => (resume, token),
+            // Without a resume command the token is still reused, for backends
+            // whose single command both starts and continues conversations.
+            (_, token) => (
+                &profile.command,
+                token.unwrap_or_else(|| Uuid::new_v4().to_string()),
+            ),
+        };
+
+        // The backend runs under the multiplexer rather than anna, out of reach of
+        // the namespace isolation is applied with.
+        if let (Some(multiplexer), Some(_)) = (options.multiplexer, &options.network) {
+            bail!("network isolation isn't supported for backends run in {multiplexer}");
+        }
+        // Backends on other machines or in containers can't see this machine's files,
+        // nor be reached by the network isolation applied to processes here.
+        let elsewhere = match (&self.session.remote, options.devcontainer) {
+            (Some(remote), true) => {
+                bail!("backends run on {remote} can't also run in a development container")
+            }
+            (Some(remote), false) => Some(format!("on {remote}")),
+            (None, true) => Some(String::from("in a development container")),
+            (None, false) => None,
+        };
+        if let Some(elsewhere) = &elsewhere {
+            if options.network.is_some() {
+                bail!("network isolation isn't supported for backends run {elsewhere}");
+            }
+            if !self.session.linked.is_empty() {
+                bail!("linked workspaces can't be reached by backends run {elsewhere}");
+            }
+            let uses_prompt_file = |command: &CommandTemplate| {
+                command
+                    .placeholders()
+                    .any(|placeholder| placeholder == Placeholder::PromptFile)
+            };
+            if prompt.is_some()
+                && (uses_prompt_file(&profile.command)
+                    || profile.resume.as_ref().is_some_and(uses_prompt_file))
+            {
+                bail!("the prompt file can't be passed to backends run {elsewhere}; reference {{prompt}} instead");
+            }
+        }
+        // Containers are only given their environment on the command line
+        // that starts the backend, where anyone on this machine could see it.
+        if options.devcontainer {
+            let secret = profile
+                .env
+                .iter()
+                .find(|(_, value)| credentials::is_reference(value));
+            if let Some((name, _)) = secret {
+                bail!("${name} is a secret, which can't be passed to backends run in a development container");
+            }
+        }
+        let container = match options.devcontainer {
+            true => Some(devcontainer::up(&self.session.workspace)?),
+            false => None,
+        };
+
+        let references_model = command
+            .placeholders()
+            .any(|placeholder| placeholder == Placeholder::Model);
+        if options.model.is_some() && !references_model && profile.model_flag.is_none() {
+            bail!("the profile can't select a model; reference {{model}} in its command or set model_flag");
+        }
+
+        // Backends generally read the prompt file at startup, but we can't know when,
+        // so the file is kept alive until the backend exits.
+        let prompt_file = prompt
+            .map(|prompt| {
+                let file = tempfile::NamedTempFile::new().context("create prompt file")?;
+                fs::write(file.path(), prompt).context("write prompt file")?;
+                Ok::<_, color_eyre::Report>(file)
+            })
+            .transpose()?;
+
+        let values = TemplateValues {
+            branch: Some(self.session.branch.to_string()),
+            // Remote backends run in the mirror, whose path is only known relative
+            // to the remote user's home directory.
+            workspace: Some(match (&self.session.remote, &container) {
+                (Some(_), _) => PathBuf::from("."),
+                (None, Some(container)) => container.workspace_in_container.clone(),
+                (None, None) => self.session.workspace.clone(),
+            }),
+            project: Some(self.session.project.clone()),
+            prompt: prompt.map(String::from),
+            prompt_file: prompt_file.as_ref().map(|file| file.path().to_path_buf()),
+            model: model.clone(),
+            resume_token: Some(resume_token.clone()),
+        };
+        let resolve = |command: &CommandTemplate, resumed: bool| {
+            let mut args = command.resolve(&values)?;
+            if let (Some(model), Some(flag), false) =
+                (&model, &profile.model_flag, references_model)
+            {
+                args.splice(1..1, [flag.clone(), model.clone()]);
+            }
+            let status_args = match resumed {
+                false => &profile.run_args_created,
+                true => &profile.run_args_resumed,
+            };
+            args.splice(1..1, status_args.resolve(&values)?);
+            Ok::<_, color_eyre::Report>(args)
+        };
+        let mut command_line = resolve(command, resumed)?;
+
+        // Started before the backend and stopped after it exits, with the backend
+        // only able to reach the network through it.
+        let proxy = options
+            .network
+            .clone()
+            .map(network::Proxy::start)
+            .transpose()
+            .context("start network proxy")?;
+
+        // Asked before the backend runs, so that it describes the version which did.
+        // Backends started by a custom launcher may not be programs at all.
+        let version = self
+            .session
+            .environment
+            .as_ref()
+            .filter(|_| options.launcher.custom().is_none())
+            .filter(|environment| !environment.backends.contains_key(backend))
+            .and_then(|_| environment::backend_version(backend, &self.session.workspace));
+
+        if let Some(remote) = &self.session.remote {
+            remote.push(&self.session.workspace)?;
+        }
+        // Remote backends need a terminal there whenever they have one here.
+        let terminal = options.multiplexer.is_some() || options.pty || io::stdin().is_terminal();
+
+        let started_at = Timestamp::now();
+        let started = Instant::now();
+        // What the environment refers to is only found at these paths on this machine.
+        let mut env = match &elsewhere {
+            Some(_) => Vec::new(),
+            None => self.workspace_env()?,
+        };
+        // Resolved for every run, and only ever held in memory, so that secrets
+        // are never written down and rotated ones are picked up.
+        env.extend(
+            credentials::resolve_env(&profile.env).context("resolve the profile's environment")?,
+        );
+        // Backends in multiplexers, on remote machines, or in containers
+        // aren't descendants of anna, so there's nothing to sample.
+        let sampler = match (options.multiplexer, &elsewhere) {
+            (None, None) => resources::Sampler::start(),
+            _ => None,
+        };
+        let mut command = command;
+        let mut resumed = resumed;
+        let mut restarts = 0;
+        let status = loop {
+            self.events.emit(&Event::BackendStarted {
+                program: command_line
+                    .first()
+                    .cloned()
+                    .expect("resolved commands are never empty"),
+                resumed,
+            });
+            let wrapped_command_line = match (&self.session.remote, &container) {
+                (Some(remote), _) => {
+                    // Sent for each run, since each deletes it once it has read it.
+                    remote.send_env(&self.session.workspace, &env)?;
+                    remote.command_line(&self.session.workspace, &command_line, &env, terminal)?
+                }
+                (None, Some(container)) => container.command_line(&command_line, &env),
+                (None, None) => command_line.clone(),
+            };
+            let (program, args) = wrapped_command_line
+                .split_first()
+                .expect("resolved commands are never empty");
+            let status = if let Some(launcher) = options.launcher.custom() {
+                launcher.launch(&Invocation {
+                    program,
+                    args,
+                    env: &env,
+                    workspace: &self.session.workspace,
+                    prompt,
+                })
+            } else if let Some(multiplexer) = options.multiplexer {
+                multiplexer::run(
+                    multiplexer.multiplexer(),
+                    self.session.branch.as_str(),
+                    &wrapped_command_line,
+                    &env,
+                    &self.session.workspace,
+                    &self.events,
+                    |window| {
+                        // Recorded so that `anna session attach` can find it.
+                        running_backend.window = Some(String::from(window));
+                        self.sessions.record_running(
+                            &self.session.project,
+                            &self.session.branch,
+                            &running_backend,
+                        )
+                    },
+                )
+            } else if options.pty {
+                self.run_under_pty(program, args, &env, options.transcript, proxy.as_ref())
+            } else {
+                let mut backend = Command::new(program);
+                backend
+                    .args(args)
+                    .envs(env.iter().cloned())
+                    .current_dir(&self.session.workspace);
+                if let Some(proxy) = &proxy {
+                    network::isolate(&mut backend, proxy)?;
+                }
+                backend.status().map_err(Into::into)
+            }
+            .with_context(|| format!("run backend: {command}"))?;
+
+            let Some(delay) = options.restart.delay(restarts, &status) else {
+                break status;
+            };
+            restarts += 1;
+            self.events.emit(&Event::BackendExited {
+                code: status.code(),
+                success: status.success(),
+            });
+            self.events.emit(&Event::BackendRestarting {
+                attempt: restarts,
+                max_attempts: match options.restart {
+                    Restart::OnFailure { max, .. } => max,
+                    _ => None,
+                },
+                delay_ms: u64::try_from(delay.as_millis()).unwrap_or(u64::MAX),
+            });
+            thread::sleep(delay);
+            // Continues the conversation the failed backend had, if the profile can,
+            // rather than starting over and losing its progress.
+            if let Some(resume) = &profile.resume {
+                command = resume;
+                resumed = true;
+                command_line = resolve(command, resumed)?;
+            }
+        };
+        let duration = started.elapsed();
+        let resources = sampler.map(|sampler| {
+            let (cpu, peak_rss) = sampler.finish();
+            ResourceUsage {
+                started_at,
+                duration_ms: u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+                cpu_ms: u64::try_from(cpu.as_millis()).unwrap_or(u64::MAX),
+                peak_rss,
+            }
+        });
+        self.events.emit(&Event::BackendExited {
+            code: status.code(),
+            success: status.success(),
+        });
+        let event = HistoryEvent::BackendExited {
+            backend: String::from(backend),
+            code: status.code(),
+            success: status.success(),
+            duration_ms: u64::try_from(duration.as_millis()).unwrap_or(u64::MAX),
+        };
+        self.sessions.record(&self.session, event);
+
+        // Tracking usage is best effort: a backend that didn't log what it used,
+        // or logged it somewhere unreadable, shouldn't fail the run.
+        let usage = profile
+            .usage
+            .and_then(|source| source.read(&resume_token).ok().flatten())
+            .filter(|usage| self.session.usage.get(&resume_token) != Some(usage));
+
+        // Recorded once the backend has actually run, since until then
+        // there is no conversation to resume, nor an exit status.
+        let updated = self
+            .sessions
+            .update(&self.session.project, &self.session.branch, |session| {
+                session.runs.push(RunRecord {
+                    backend: String::from(backend),
+                    started_at,
+                    code: status.code(),
+                    success: status.success(),
+                });
+                if let Some(usage) = usage {
+                    session.usage.insert(resume_token.clone(), usage);
+                }
+                session.resources.extend(resources);
+                session
+                    .resume_tokens
+                    .insert(String::from(backend), resume_token);
+                if let Some(prompt) = prompt {
+                    // Carries over the first prompt of sessions from before
+                    // every prompt was recorded.
+                    session.prompts = session.prompt_history();
+                    session.prompts.push(PromptRecord {
+                        prompt: String::from(prompt),
+                        given_at: started_at,
+                    });
+                }
+                if session.prompt.is_none() {
+                    session.prompt = prompt.map(String::from);
+                }
+                if options.model.is_some() {
+                    session.model = options.model.clone();
+                }
+                if let (Some(environment), Some(version)) = (&mut session.environment, version) {
+                    environment.backends.insert(String::from(backend), version);
+                }
+            })
+            .context("record backend run")?;
+        if let Some(updated) = updated {
+            self.session = updated;
+        }
+
+        if let Some(remote) = &self.session.remote {
+            remote.pull(&self.session.workspace)?;
+        }
+
+        // The backend may have crashed or been interrupted;
+        // either way its work is committed so that it isn't lost in the workspace.
+        let auto_commit = if options.auto_commit {
+            let message = auto_commit_message(prompt, Timestamp::now());
+            self.session
+                .vcs
+                .vcs()
+                .commit_all(&self.session.workspace, &self.session.branch, &message)
+                .context("commit agent changes")?
+        } else {
+            None
+        };
+        if let Some(commit) = &auto_commit {
+            self.events.emit(&Event::AutoCommitted {
+                commit: commit.clone(),
+            });
+        }
+        let mut linked_auto_commits = BTreeMap::new();
+        if options.auto_commit {
+            let message = auto_commit_message(prompt, Timestamp::now());
+            for linked in &self.session.linked {
+                let commit = linked
+                    .vcs
+                    .vcs()
+                    .commit_all(&linked.workspace, &self.session.branch, &message)
+                    .with_context(|| {
+                        format!(
+                            "commit agent changes in linked workspace: {}",
+                            linked.workspace.display()
+                        )
+                    })?;
+                if let Some(commit) = commit {
+                    linked_auto_commits.insert(linked.project.clone(), commit);
+                }
+            }
+        }
+
+        let verification = match &options.verify {
+            Some(command) => Some(self.verify_in(command, container.as_ref(), None)?),
+            None => None,
+        };
+        // Not before now: pulling, committing and verifying all act on the workspace.
+        drop(running);
+
+        Ok(RunOutcome {
+            status,
+            auto_commit,
+            linked_auto_commits,
+            restarts,
+            duration,
+            resources,
+            verification,
+            denied_hosts: proxy.map(|proxy| proxy.denied()).unwrap_or_default(),
+        })
+    }
+
+    #[cfg(not(feature = "cli-backends"))]
+    pub fn run(&mut self, _profile: &Profile, _options: &RunOptions) -> Result<RunOutcome> {
+        bail!("running backends requires winlock's `cli-backends` feature")
+    }
+
+    /// Run `program` in the workspace under a pseudo-terminal,
+    /// recording a transcript if requested.
+    #[cfg(feature = "pty")]
+    fn run_under_pty(
+        &self,
+        program: &str,
+        args: &[String],
+        env: &[(&str, OsString)],
+        transcript: bool,
+        proxy: Option<&network::Proxy>,
+    ) -> Result<ExitStatus> {
+        let transcript = transcript.then(|| {
+            let name = Timestamp::now().strftime("%Y%m%dT%H%M%S%.3fZ");
+            self.transcript_dir()
+                .join(format!("{name}.{}", transcript::EXTENSION))
+        });
+        pty::run(
+            program,
+            args,
+            env,
+            &self.session.workspace,
+            transcript.as_deref(),
+            proxy,
+        )
+    }
+
+    #[cfg(all(feature = "cli-backends", not(feature = "pty")))]
+    fn run_under_pty(
+        &self,
+        _program: &str,
+        _args: &[String],
+        _env: &[(&str, OsString)],
+        _transcript: bool,
+        _proxy: Option<&network::Proxy>,
+    ) -> Result<ExitStatus> {
+        bail!("running backends under a pseudo-terminal requires winlock's `pty` feature")
+    }
+
+    /// The environment of commands run in the workspace: through it they find the
+    /// session's linked workspaces, if it has any, and its isolated toolchains.
+    fn workspace_env(&self) -> Result<Vec<(&'static str, OsString)>> {
+        let mut env = toolchain::env(&self.session.toolchains, &self.session.workspace)?;
+        if !self.session.linked.is_empty() {
+            let workspaces = self.session.linked.iter().map(|linked| &linked.workspace);
+            let joined = env::join_paths(workspaces).context("join linked workspace paths")?;
+            env.push((LINKED_WORKSPACES_VARIABLE, joined));
+        }
+        Ok(env)
+    }
+
+    /// Run `command` with the platform's shell in the workspace, or in its mirror
+    /// for remote sessions, recording whether it succeeded on the session.
+    pub fn verify(&mut self, command: &str) -> Result<Verification> {
+        self.verify_in(command, None, None)
+    }
+
+    /// Run `command` as [`verify`](Self::verify) does, writing what it prints to the
+    /// file `log` rather than to anna's own output, so that several can run at once.
+    pub fn verify_logged(&mut self, command: &str, log: &Path) -> Result<Verification> {
+        self.verify_in(command, None, Some(log))
+    }
+
+    /// Run the verification command `command` as [`verify`](Self::verify) does,
+    /// or in `container` if given, as it is after a backend ran there,
+    /// writing its output to `log` if given.
+    fn verify_in(
+        &mut self,
+        command: &str,
+        container: Option<&Container>,
+        log: Option<&Path>,
+    ) -> Result<Verification> {
+        let mut verify = match (&self.session.remote, container) {
+            // Sent again, since the workspace may have changed here since the backend ran.
+            (Some(remote), _) => {
+                remote.push(&self.session.workspace)?;
+                remote.shell(&self.session.workspace, command)?
+            }
+            (None, Some(container)) => container.shell(command),
+            (None, None) => {
+                let mut verify = shell(command);
+                verify
+                    .envs(self.workspace_env()?)
+                    .current_dir(&self.session.workspace);
+                verify
+            }
+        };
+        if let Some(log) = log {
+            let file = fs::File::create(log)
+                .with_context(|| format!("create verification log: {}", log.display()))?;
+            let stderr = file.try_clone().context("share verification log")?;
+            verify.stdin(Stdio::null()).stdout(file).stderr(stderr);
+        }
+        let status = verify
+            .status()
+            .with_context(|| format!("run verification: {command}"))?;
+
+        let verification = Verification {
+            command: String::from(command),
+            passed: status.success(),
+            finished_at: Timestamp::now(),
+        };
+        let updated = self
+            .sessions
+            .update(&self.session.project, &self.session.branch, |session| {
+                session.verification = Some(verification.clone());
+            })
+            .context("record verification")?;
+        if let Some(updated) = updated {
+            self.session = updated;
+        }
+        self.events.emit(&Event::Verified {
+            command: verification.command.clone(),
+            passed: verification.passed,
+        });
+        let event = HistoryEvent::Verified {
+            command: verification.command.clone(),
+            passed: verification.passed,
+        };
+        self.sessions.record(&self.session, event);
+        Ok(verification)
+    }
+}
+
+/// Verification and setup commands are written by the user, who expects shell syntax
+/// such as `&&` and pipes to work.
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    }
+}
+
+/// Whether [`Agent::new`] or [`Sessions::get_or_create`] created a new session
+/// or resumed an existing one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AgentSessionStatus {
+    /// A new session was created.
+    Created,
+
+    /// An existing session was resumed.
+    Resumed,
+}
+
+/// Options for [`Agent::new`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateOptions {
+    /// The version control system used in the workspace.
+    /// Ignored when resuming a session.
+    pub vcs: VcsKind,
+
+    /// The revision the branch is created from, in any form the VCS understands;
+    /// defaults to the revision checked out in the project.
+    /// Ignored when resuming a session.
+    pub base: Option<String>,
+
+    /// Check out the branch where it already exists, as a local branch or a
+    /// remote-tracking one, rather than creating it, so that work started elsewhere
+    /// can be continued; its tip becomes the base. Can't be combined with [`base`](Self::base).
+    /// Applies to the main workspace only, and is ignored when resuming a session.
+    pub existing: bool,
+
+    /// Patterns, in gitignore syntax, for files withheld from the workspace;
+    /// defaults to [`SECRET_PATTERNS`](workspace::SECRET_PATTERNS).
+    /// Ignored when resuming a session.
+    pub exclude: Vec<String>,
+
+    /// How the project is copied into the workspace.
+    /// Ignored when resuming a session.
+    pub copy_engine: CopyEngineKind,
+
+    /// The web page of the issue the session is started from.
+    /// Ignored when resuming a session.
+    pub issue: Option<String>,
+
+    /// Git settings written to the workspace's repository once it's copied,
+    /// before the branch is created; only supported with git.
+    /// Ignored when resuming a session.
+    pub git_config: BTreeMap<String, String>,
+
+    /// Make the branch track the branch of the same name on this remote, such as
+    /// `origin`, so that plain `git push` and `git pull` work in the workspace;
+    /// only supported with git.
+    /// Applies to the main workspace only, and is ignored when resuming a session.
+    pub upstream: Option<String>,
+
+    /// Cache directories seeded into the workspace once it's copied,
+    /// before the setup commands run.
+    /// Ignored when resuming a session.
+    pub caches: Option<workspace::Caches>,
+
+    /// Other projects the session spans, each copied into a linked workspace
+    /// with the branch created from what's checked out there.
+    /// Ignored when resuming a session.
+    pub also: Vec<LinkedProject>,
+
+    /// Shell commands run in the workspace once it's created, such as to fetch dependencies.
+    /// Ignored when resuming a session.
+    pub setup: Vec<String>,
+
+    /// Toolchains whose state is kept in the workspace rather than shared, from the
+    /// setup commands on; ignored for remote sessions, and when resuming a session.
+    pub toolchains: Vec<Toolchain>,
+
+    /// The program the backend runs, which is [probed](backend::probe) before the
+    /// workspace is created so that a missing backend is found before the copy.
+    /// Ignored when resuming a session.
+    pub backend: Option<String>,
+
+    /// Run the backend on this machine over SSH rather than locally, with the workspace
+    /// [mirrored](remote) there; setup commands and verification run there too.
+    /// Can't be combined with [`also`](Self::also).
+    /// Ignored when resuming a session.
+    pub remote: Option<Remote>,
+
+    /// The [group](Session::group) the session is in.
+    /// Ignored when resuming a session.
+    pub group: Option<String>,
+
+    /// Refuse to create the workspace if there doesn't seem to be enough
+    /// free disk space to copy the project; enabled by default.
+    pub check_space: bool,
+
+    /// Refuse, with [`CopyTooLarge`], to create the workspace if the copy would be
+    /// larger than this many bytes, so that a huge project isn't copied by accident.
+    /// Ignored when resuming a session.
+    pub copy_limit: Option<u64>,
+
+    /// Skip files larger than this many bytes when copying the project, reporting
+    /// them as [oversized](CopyReport::oversized) rather than copying them.
+    /// Ignored when resuming a session.
+    pub max_file_size: Option<u64>,
+
+    /// Cancels copying the project, which fails creating the session with
+    /// [`CopyCancelled`](workspace::CopyCancelled) and deletes the partial workspace.
+    pub cancel: workspace::Cancel,
+
+    /// Check out submodules that aren't checked out in the project, so that the
+    /// workspace builds; enabled by default. Submodules that are checked out are
+    /// copied as they are.
+    /// Ignored when resuming a session.
+    pub submodules: bool,
+
+    /// Copy [LFS pointers](vcs::lfs) rather than the files LFS checked out in the project,
+    /// pulling only those it selects; requires git.
+    /// Applies to the main workspace only, and is ignored when resuming a session.
+    pub lfs: Option<vcs::lfs::Lfs>,
+
+    /// Where the agent reports its progress, both while it is created
+    /// and while it [runs](Agent::run).
+    pub events: Events,
+}
+
+impl CreateOptions {
+    /// How the project is copied into the workspace.
+    fn copy_options(&self) -> CopyOptions {
+        CopyOptions {
+            max_file_size: self.max_file_size,
+            cancel: self.cancel.clone(),
+        }
+    }
+}
+
+impl Default for CreateOptions {
+    fn default() -> Self {
+        Self {
+            vcs: VcsKind::default(),
+            base: None,
+            existing: false,
+            exclude: workspace::SECRET_PATTERNS
+                .iter()
+                .copied()
+                .map(String::from)
+                .collect(),
+            copy_engine: CopyEngineKind::default(),
+            issue: None,
+            git_config: BTreeMap::new(),
+            upstream: None,
+            caches: None,
+            also: Vec::new(),
+            setup: Vec::new(),
+            toolchains: Vec::new(),
+            backend: None,
+            remote: None,
+            group: None,
+            check_space: true,
+            copy_limit: None,
+            max_file_size: None,
+            cancel: workspace::Cancel::default(),
+            submodules: true,
+            lfs: None,
+            events: Events::default(),
+        }
+    }
+}
+
+/// Another project a new session spans, and how it's copied into its linked workspace;
+/// see [`CreateOptions::also`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkedProject {
+    /// The project's directory.
+    pub path: PathBuf,
+
+    /// The version control system used in the linked workspace.
+    pub vcs: VcsKind,
+
+    /// Patterns, in gitignore syntax, for files withheld from the linked workspace.
+    pub exclude: Vec<String>,
+
+    /// How the project is copied into the linked workspace.
+    pub copy_engine: CopyEngineKind,
+
+    /// Git settings written to the linked workspace's repository once it's copied;
+    /// only supported with git.
+    pub git_config: BTreeMap<String, String>,
+}
+
+impl LinkedProject {
+    /// Link the project at `path` with the defaults [`CreateOptions`] has:
+    /// its version control detected, and [`SECRET_PATTERNS`](workspace::SECRET_PATTERNS)
+    /// withheld.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let defaults = CreateOptions::default();
+        Self {
+            vcs: VcsKind::detect(&path),
+            path,
+            exclude: defaults.exclude,
+            copy_engine: defaults.copy_engine,
+            git_config: defaults.git_config,
+        }
+    }
+}
+
+/// Options for [`Agent::run`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunOptions {
+    /// The prompt given to the backend.
+    pub prompt: Option<String>,
+
+    /// Commit any changes left uncommitted when the backend exits,
+    /// in the workspace and in linked ones.
+    /// Has no effect in workspaces without version control.
+    pub auto_commit: bool,
+
+    /// Run the backend under a pseudo-terminal rather than handing it the user's terminal,
+    /// forwarding resizes and signals to it.
+    pub pty: bool,
+
+    /// Start a new backend conversation rather than continuing the session's previous one.
+    pub new_conversation: bool,
+
+    /// Run the backend in a window of this multiplexer named after the session,
+    /// which can be detached from and reattached to, instead of in the user's terminal.
+    /// Takes precedence over [`pty`](Self::pty); neither transcripts nor resource usage
+    /// are recorded, and it can't be combined with [`network`](Self::network).
+    pub multiplexer: Option<MultiplexerKind>,
+
+    /// Run the backend, and the verification command, in the workspace's
+    /// [development container](devcontainer), started if it isn't running.
+    /// Neither resource usage nor network isolation apply there, and the prompt
+    /// can't be passed as a file.
+    pub devcontainer: bool,
+
+    /// Record a transcript in the session's [transcript directory](Agent::transcript_dir).
+    /// Has no effect unless the backend runs under a pseudo-terminal.
+    pub transcript: bool,
+
+    /// A shell command run in the workspace after the backend exits,
+    /// such as a test suite; whether it passed is recorded on the session.
+    pub verify: Option<String>,
+
+    /// The model the backend uses, instead of the one previously selected for the session
+    /// or the profile's; recorded so that later runs in the session keep using it.
+    pub model: Option<String>,
+
+    /// Run the backend without network access, except to the hosts the policy allows.
+    /// Only supported on Linux.
+    pub network: Option<NetworkPolicy>,
+
+    /// Whether to relaunch the backend if it fails, such as on a crash or a rate limit.
+    /// Relaunches continue the backend's conversation where the profile can
+    /// [resume](Profile::resume) one.
+    pub restart: Restart,
+
+    /// How the backend is started: by spawning its process, by default, or through
+    /// a custom launcher, such as a mock in tests, which takes precedence over
+    /// [`multiplexer`](Self::multiplexer) and [`pty`](Self::pty).
+    pub launcher: Launcher,
+}
+
+/// The directory [`Agent::new`] was asked to work on is part of anna's own state
+/// rather than a project.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnsuitableProject {
+    /// The directory is inside the workspace of an existing session.
+    Workspace {
+        /// The directory.
+        dir: PathBuf,
+
+        /// The project the workspace belongs to.
+        project: PathBuf,
+
+        /// The branch of the workspace's session.
+        branch: BranchName,
+    },
+
+    /// The directory is inside the session store.
+    Store {
+        /// The directory.
+        dir: PathBuf,
+
+        /// The directory of the session store.
+        store: PathBuf,
+    },
+}
+
+impl fmt::Display for UnsuitableProject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Workspace {
+                dir,
+                project,
+                branch,
+            } => write!(
+                f,
+                "{} is in the workspace of session '{branch}' of {}, not a project",
+                dir.display(),
+                project.display()
+            ),
+            Self::Store { dir, store } => write!(
+                f,
+                "{} is in anna's session store at {}, not a project",
+                dir.display(),
+                store.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UnsuitableProject {}
+
+/// How [`Agent::new`] created a session's workspace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreationReport {
+    /// What was copied from the project, including entries withheld
+    /// by [`CreateOptions::exclude`] and ones that failed to copy.
+    pub copy: CopyReport,
+
+    /// The cache directories seeded by [`CreateOptions::caches`].
+    pub caches: CacheReport,
+
+    /// What was copied into each [linked workspace](Session::linked), in order.
+    pub linked: Vec<CopyReport>,
+
+    /// How long creating the workspace took.
+    pub duration: Duration,
+}
+
+/// The result of [`Agent::run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunOutcome {
+    /// The exit status of the backend.
+    pub status: ExitStatus,
+
+    /// The commit containing changes the backend left uncommitted, if one was made.
+    pub auto_commit: Option<String>,
+
+    /// The commits made likewise in [linked workspaces](Session::linked), by project.
+    pub linked_auto_commits: BTreeMap<PathBuf, String>,
+
+    /// How many times the backend was relaunched after failing, per [`RunOptions::restart`];
+    /// [`status`](Self::status) is from its last run.
+    pub restarts: u32,
+
+    /// How long the backend ran for, restarts included.
+    pub duration: Duration,
+
+    /// The CPU time and memory the backend used, where they can be measured;
+    /// also recorded on the session.
+    pub resources: Option<ResourceUsage>,
+
+    /// The result of the verification command, if one was run.
+    pub verification: Option<Verification>,
+
+    /// The hosts the backend was refused access to by [`RunOptions::network`].
+    pub denied_hosts: Vec<String>,
+}
+
+#[cfg(feature = "cli-backends")]
+fn auto_commit_message(prompt: Option<&str>, timestamp: Timestamp) -> String {
+    let subject = format!("anna: auto-commit agent changes at {timestamp}");
+    match prompt {
+        Some(prompt) => format!("{subject}\n\nPrompt:\n{prompt}"),
+        None => subject,
+    }
+}
+
+/// Replace the file at `path` with `content` through a temporary file beside it,
+/// so that a crash mid-write can never leave a truncated file behind,
+/// and readers never see one.
+pub(crate) fn replace_file(path: &Path, content: &str) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{name}.{}.tmp", Uuid::new_v4().simple()));
+    let result = fs::write(&temp, content).and_then(|()| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
     }
+    result
 }
 
 pub fn add(left: u64, right: u64) -> u64 {
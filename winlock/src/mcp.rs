@@ -0,0 +1,362 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A [Model Context Protocol](https://modelcontextprotocol.io) server, so that
+//! orchestrating agents can drive sessions of their own: start one per branch
+//! of a task, see what each changed, and merge the ones that worked.
+//!
+//! The server speaks [JSON-RPC](crate::jsonrpc), one message per line, over
+//! whatever it's [given](Server::serve), which for `anna mcp serve` is standard input
+//! and output, as MCP's stdio transport has it. Its tools are:
+//!
+//! - `list_sessions`: the sessions, optionally only those of a project.
+//! - `create_session`: create a session for a branch of a project, or resume
+//!   the one there is, without running a backend in it.
+//! - `diff_session`: the changes made in a session's workspace.
+//! - `merge_session`: merge a session's branch back into its project,
+//!   optionally removing the session once it's merged.
+//!
+//! Projects are given as absolute paths.
+
+use std::{
+    fmt, fs,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    branch::BranchName,
+    history::HistoryEvent,
+    jsonrpc::{
+        parse_params, Request, Response, RpcError, INVALID_PARAMS, METHOD_NOT_FOUND, PARSE_ERROR,
+    },
+    session::{Session, Sessions},
+    Agent, CreateOptions,
+};
+
+/// The protocol versions the server speaks, oldest first.
+const PROTOCOL_VERSIONS: [&str; 3] = ["2024-11-05", "2025-03-26", "2025-06-18"];
+
+type MakeOptions = Box<dyn Fn(&Path) -> Result<CreateOptions>>;
+
+/// An MCP server acting on a session store.
+pub struct Server {
+    sessions: Sessions,
+
+    create_options: MakeOptions,
+}
+
+impl Server {
+    /// A server acting on `sessions`, creating sessions with
+    /// [default options](CreateOptions::default).
+    pub fn new(sessions: Sessions) -> Self {
+        Self {
+            sessions,
+            create_options: Box::new(|_| Ok(CreateOptions::default())),
+        }
+    }
+
+    /// Create sessions with the options `make` returns for their project,
+    /// such as those its configuration calls for. The base a caller asks for
+    /// takes precedence over the options' own.
+    pub fn create_options(self, make: impl Fn(&Path) -> Result<CreateOptions> + 'static) -> Self {
+        Self {
+            create_options: Box::new(make),
+            ..self
+        }
+    }
+
+    /// Answer the requests read from `input` on `output` until `input` ends.
+    pub fn serve(&self, input: impl BufRead, mut output: impl Write) -> Result<()> {
+        for line in input.lines() {
+            let line = line.context("read request")?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => self.handle(request),
+                Err(err) => Some(Response::new(
+                    Value::Null,
+                    Err(RpcError::new(PARSE_ERROR, err.to_string())),
+                )),
+            };
+            let Some(response) = response else {
+                continue;
+            };
+            let content = serde_json::to_string(&response).expect("responses must serialize");
+            writeln!(output, "{content}").context("write response")?;
+            output.flush().context("write response")?;
+        }
+        Ok(())
+    }
+
+    /// Answer `request`, or `None` if it's a notification.
+    pub fn handle(&self, request: Request) -> Option<Response> {
+        let result = self.call(&request.method, request.params);
+        Some(Response::new(request.id?, result))
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "initialize" => {
+                #[derive(Deserialize)]
+                #[serde(rename_all = "camelCase")]
+                struct Params {
+                    protocol_version: Option<String>,
+                }
+                let params: Params = parse_params(params)?;
+                // Clients newer than the server are answered with the newest it speaks,
+                // which they may decline.
+                let version = params
+                    .protocol_version
+                    .filter(|version| PROTOCOL_VERSIONS.contains(&version.as_str()))
+                    .unwrap_or_else(|| {
+                        String::from(PROTOCOL_VERSIONS[PROTOCOL_VERSIONS.len() - 1])
+                    });
+                Ok(json!({
+                    "protocolVersion": version,
+                    "capabilities": { "tools": {} },
+                    "serverInfo": {
+                        "name": "anna",
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                }))
+            }
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": tools() })),
+            "tools/call" => {
+                #[derive(Deserialize)]
+                struct Params {
+                    name: String,
+                    #[serde(default)]
+                    arguments: Value,
+                }
+                let params: Params = parse_params(params)?;
+                // Failing tools are reported in the result, for the model to see;
+                // errors are for calls that couldn't be made.
+                let (text, failed) = match self.call_tool(&params.name, params.arguments)? {
+                    Ok(text) => (text, false),
+                    Err(err) => (format!("{err:#}"), true),
+                };
+                Ok(json!({
+                    "content": [{ "type": "text", "text": text }],
+                    "isError": failed,
+                }))
+            }
+            method if method.starts_with("notifications/") => Ok(Value::Null),
+            method => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("no such method: {method}"),
+            )),
+        }
+    }
+
+    /// Call the tool `name` with `arguments`, returning what it has to say.
+    fn call_tool(&self, name: &str, arguments: Value) -> Result<Result<String>, RpcError> {
+        Ok(match name {
+            "list_sessions" => {
+                #[derive(Deserialize)]
+                struct Arguments {
+                    project: Option<PathBuf>,
+                }
+                let arguments: Arguments = parse_params(arguments)?;
+                self.list_sessions(arguments.project.as_deref())
+            }
+            "create_session" => {
+                #[derive(Deserialize)]
+                struct Arguments {
+                    project: PathBuf,
+                    branch: BranchName,
+                    base: Option<String>,
+                }
+                let arguments: Arguments = parse_params(arguments)?;
+                self.create_session(&arguments.project, arguments.branch, arguments.base)
+            }
+            "diff_session" => {
+                #[derive(Deserialize)]
+                struct Arguments {
+                    project: PathBuf,
+                    branch: BranchName,
+                }
+                let arguments: Arguments = parse_params(arguments)?;
+                self.get(&arguments.project, &arguments.branch)
+                    .and_then(|session| session.diff())
+            }
+            "merge_session" => {
+                #[derive(Deserialize)]
+                struct Arguments {
+                    project: PathBuf,
+                    branch: BranchName,
+                    #[serde(default)]
+                    remove: bool,
+                }
+                let arguments: Arguments = parse_params(arguments)?;
+                self.merge_session(&arguments.project, &arguments.branch, arguments.remove)
+            }
+            name => {
+                return Err(RpcError::new(
+                    INVALID_PARAMS,
+                    format!("no such tool: {name}"),
+                ))
+            }
+        })
+    }
+
+    fn list_sessions(&self, project: Option<&Path>) -> Result<String> {
+        let sessions = match project {
+            Some(project) => self.sessions.list(&canonicalize(project)?)?,
+            None => self.sessions.iter()?.skip_unreadable().collect(),
+        };
+        serde_json::to_string_pretty(&sessions).context("serialize sessions")
+    }
+
+    fn create_session(
+        &self,
+        project: &Path,
+        branch: BranchName,
+        base: Option<String>,
+    ) -> Result<String> {
+        let project = canonicalize(project)?;
+        let mut options = (self.create_options)(&project)?;
+        if base.is_some() {
+            options.base = base;
+        }
+        let agent = Agent::new(&self.sessions, &project, branch, &options)?;
+        serde_json::to_string_pretty(agent.session()).context("serialize session")
+    }
+
+    fn merge_session(&self, project: &Path, branch: &BranchName, remove: bool) -> Result<String> {
+        let session = self.get(project, branch)?;
+        let project = &session.project;
+        if !remove {
+            session.merge_back()?;
+            self.sessions.record(&session, HistoryEvent::Merged);
+            return Ok(format!("Merged '{branch}' into {}", project.display()));
+        }
+        // Commits are merged, so only uncommitted changes would be lost.
+        let check = |session: &Session| {
+            if session.adopted {
+                return Ok(());
+            }
+            for (workspace, work) in session.unsaved_work()? {
+                if !work.changed.is_empty() {
+                    bail!(
+                        "removing '{branch}' would lose {} uncommitted changes in {}; \
+                         commit them, or merge without removing the session",
+                        work.changed.len(),
+                        workspace.display()
+                    );
+                }
+            }
+            Ok(())
+        };
+        let Some((session, removal)) = self.sessions.merge_and_remove(project, branch, check)?
+        else {
+            bail!("no session for '{branch}' in {}", project.display());
+        };
+        removal.finish()?;
+        Ok(format!(
+            "Merged '{branch}' into {} and removed the session",
+            session.project.display()
+        ))
+    }
+
+    fn get(&self, project: &Path, branch: &BranchName) -> Result<Session> {
+        let project = canonicalize(project)?;
+        self.sessions
+            .get(&project, branch)?
+            .ok_or_else(|| eyre!("no session for '{branch}' in {}", project.display()))
+    }
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server")
+            .field("sessions", &self.sessions)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Sessions are recorded under their project's canonical path.
+fn canonicalize(project: &Path) -> Result<PathBuf> {
+    fs::canonicalize(project).with_context(|| format!("find project: {}", project.display()))
+}
+
+/// The tools the server has, with the JSON Schema of their arguments.
+fn tools() -> Value {
+    let project = json!({
+        "type": "string",
+        "description": "The absolute path of the project.",
+    });
+    let branch = json!({
+        "type": "string",
+        "description": "The branch the session works on.",
+    });
+    json!([
+        {
+            "name": "list_sessions",
+            "description": "List anna's sessions, each with its project, branch, and workspace.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "project": {
+                        "type": "string",
+                        "description": "Only list the sessions of the project at this absolute path.",
+                    },
+                },
+            },
+        },
+        {
+            "name": "create_session",
+            "description": "Create a session working on a new branch in a copy of a project, \
+                            or resume the session there is for the branch, returning it. \
+                            Work happens in the session's workspace.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "project": project,
+                    "branch": branch,
+                    "base": {
+                        "type": "string",
+                        "description": "The revision to create the branch from; \
+                                        defaults to what's checked out in the project.",
+                    },
+                },
+                "required": ["project", "branch"],
+            },
+        },
+        {
+            "name": "diff_session",
+            "description": "Show the changes made in a session's workspace since its branch \
+                            was created, committed or not, as a unified diff.",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "project": project, "branch": branch },
+                "required": ["project", "branch"],
+            },
+        },
+        {
+            "name": "merge_session",
+            "description": "Merge the commits on a session's branch back into its project, \
+                            without changing what's checked out there.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "project": project,
+                    "branch": branch,
+                    "remove": {
+                        "type": "boolean",
+                        "description": "Remove the session and its workspace once merged; \
+                                        refused if that would lose uncommitted changes.",
+                    },
+                },
+                "required": ["project", "branch"],
+            },
+        },
+    ])
+}
@@ -0,0 +1,266 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Running backends in terminal multiplexers: tmux, Zellij, or GNU screen.
+//!
+//! Each backend gets a window of its own, named after its session, so that several
+//! agents can be supervised from one terminal and left running while detached.
+//! Inside a multiplexer the window opens in the current session of it; otherwise
+//! it opens in a multiplexer session named [`SESSION`], created if needed,
+//! which is attached to when anna has a terminal.
+
+// Backends are only run in multiplexers with the `cli-backends` feature,
+// without which only what tracking sessions needs of them is used.
+#![cfg_attr(not(feature = "cli-backends"), allow(dead_code, unused_imports))]
+
+use std::{
+    ffi::OsString,
+    fmt, fs,
+    io::{self, ErrorKind, IsTerminal},
+    path::Path,
+    process::{Command, ExitStatus, Stdio},
+    str::FromStr,
+    thread,
+    time::Duration,
+};
+
+use color_eyre::eyre::{bail, eyre, Context, Error, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    credentials,
+    events::{Event, Events},
+};
+
+mod screen;
+mod tmux;
+mod zellij;
+
+pub use screen::Screen;
+pub use tmux::Tmux;
+pub use zellij::Zellij;
+
+/// The multiplexer session windows are opened in when anna isn't run inside one.
+pub const SESSION: &str = "anna";
+
+/// How often to check whether a backend has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A terminal multiplexer backends can run in.
+pub trait Multiplexer: fmt::Debug + Send + Sync {
+    /// The kind of multiplexer this is.
+    fn kind(&self) -> MultiplexerKind;
+
+    /// Whether anna is itself running inside this multiplexer, in which case windows
+    /// are opened in its current session and there's nothing to attach to.
+    fn inside(&self) -> bool;
+
+    /// Open a window called `name` running `command`, a program followed by its
+    /// arguments, returning something identifying the window to [`is_open`](Self::is_open)
+    /// and [`attach`](Self::attach).
+    fn open(&self, name: &str, command: &[OsString]) -> Result<String>;
+
+    /// Whether the window is still open.
+    fn is_open(&self, window: &str) -> Result<bool>;
+
+    /// Attach the user's terminal to the window, returning once they detach,
+    /// or switch to it if already [inside](Self::inside) the multiplexer.
+    fn attach(&self, window: &str) -> Result<()>;
+}
+
+/// The kinds of multiplexer anna supports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MultiplexerKind {
+    /// tmux.
+    #[default]
+    Tmux,
+
+    /// Zellij.
+    Zellij,
+
+    /// GNU screen.
+    Screen,
+}
+
+impl MultiplexerKind {
+    /// All supported multiplexers.
+    pub const ALL: [MultiplexerKind; 3] = [
+        MultiplexerKind::Tmux,
+        MultiplexerKind::Zellij,
+        MultiplexerKind::Screen,
+    ];
+
+    /// The implementation of this multiplexer.
+    pub fn multiplexer(self) -> &'static dyn Multiplexer {
+        match self {
+            MultiplexerKind::Tmux => &Tmux,
+            MultiplexerKind::Zellij => &Zellij,
+            MultiplexerKind::Screen => &Screen,
+        }
+    }
+}
+
+impl fmt::Display for MultiplexerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MultiplexerKind::Tmux => "tmux",
+            MultiplexerKind::Zellij => "zellij",
+            MultiplexerKind::Screen => "screen",
+        })
+    }
+}
+
+impl FromStr for MultiplexerKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == s)
+            .ok_or_else(|| {
+                let known = Self::ALL.map(|kind| kind.to_string()).join(", ");
+                eyre!("unknown multiplexer '{s}'; expected one of: {known}")
+            })
+    }
+}
+
+/// Run `command_line`, a program followed by its arguments, in `dir` in a new window
+/// of `multiplexer` called `name`, calling `opened` with the window once it's open,
+/// and returning the program's exit status once it exits. The user detaching from
+/// the window is reported to `events`.
+#[cfg(feature = "cli-backends")]
+pub(crate) fn run(
+    multiplexer: &dyn Multiplexer,
+    name: &str,
+    command_line: &[String],
+    env: &[(&str, OsString)],
+    dir: &Path,
+    events: &Events,
+    opened: impl FnOnce(&str) -> Result<()>,
+) -> Result<ExitStatus> {
+    let kind = multiplexer.kind();
+    // The backend's exit status goes with its window, so a wrapper keeps it here.
+    // Changing directory and setting the environment are left to the wrapper too,
+    // since not every multiplexer can do them for a window.
+    let state = tempfile::tempdir().with_context(|| format!("create {kind} state dir"))?;
+    let status_file = state.path().join("status");
+    // The environment can hold secrets, so rather than on the command line, where anyone
+    // on the machine could see them, it's passed in a file in the state dir, which only
+    // the user can read, and which the wrapper deletes once it has read it.
+    let env_file = state.path().join("env");
+    fs::write(&env_file, credentials::shell_script(env))
+        .with_context(|| format!("write backend environment: {}", env_file.display()))?;
+    let mut command = [
+        "sh",
+        "-c",
+        r#"status=$1; cd "$2" || exit; . "$3"; rm -f "$3"; shift 3; "$@"; echo $? > "$status""#,
+        "sh",
+    ]
+    .map(OsString::from)
+    .to_vec();
+    command.extend([
+        status_file.clone().into_os_string(),
+        dir.as_os_str().to_owned(),
+        env_file.into_os_string(),
+    ]);
+    command.extend(command_line.iter().map(OsString::from));
+    let window = multiplexer.open(name, &command)?;
+    opened(&window)?;
+
+    let inside = multiplexer.inside();
+    if !inside && io::stdin().is_terminal() {
+        // Returns when the user detaches, whether or not the backend has exited.
+        if let Err(err) = multiplexer.attach(&window) {
+            events.emit(&Event::Warning {
+                message: format!("couldn't attach to {kind} window '{name}': {err:#}"),
+            });
+        }
+    }
+    if !inside && !status_file.exists() {
+        events.emit(&Event::BackendDetached {
+            multiplexer: kind,
+            window: String::from(name),
+        });
+    }
+
+    loop {
+        match fs::read_to_string(&status_file) {
+            Ok(status) if status.ends_with('\n') => {
+                let code = status
+                    .trim()
+                    .parse::<i32>()
+                    .with_context(|| format!("parse backend exit status: {status:?}"))?;
+                return Ok(exit_status(code));
+            }
+            // Still being written.
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                // Checked again since the backend may have exited in between.
+                if !multiplexer.is_open(&window)? && !status_file.exists() {
+                    bail!("{kind} window '{name}' was closed before the backend exited");
+                }
+            }
+            Err(err) => {
+                return Err(err).context(format!(
+                    "read backend exit status: {}",
+                    status_file.display()
+                ))
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// The command that reattaches to [`SESSION`] with the multiplexer.
+pub fn attach_hint(kind: MultiplexerKind) -> String {
+    match kind {
+        MultiplexerKind::Tmux => format!("`tmux attach -t {SESSION}`"),
+        MultiplexerKind::Zellij => format!("`zellij attach {SESSION}`"),
+        MultiplexerKind::Screen => format!("`screen -r {SESSION}`"),
+    }
+}
+
+/// Run `command`, returning its output, or failing with its error output if it fails.
+fn output(command: &mut Command) -> Result<String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("run {program}; is it installed?"))?;
+    if !output.status.success() {
+        bail!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8(output.stdout).with_context(|| format!("read {program} output"))
+}
+
+/// Run `command` quietly, returning whether it succeeded.
+fn succeeds(command: &mut Command) -> Result<bool> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let status = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .with_context(|| format!("run {program}; is it installed?"))?;
+    Ok(status.success())
+}
+
+/// The exit status of a process that exited with `code`.
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+
+    ExitStatus::from_raw(code as u32)
+}
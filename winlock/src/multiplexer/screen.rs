@@ -0,0 +1,70 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{env, ffi::OsString, process::Command};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+
+use super::{output, succeeds, Multiplexer, MultiplexerKind, SESSION};
+
+/// GNU screen, whose windows are identified as `session:title`,
+/// since screen doesn't report the numbers of windows it opens.
+#[derive(Debug, Clone, Copy)]
+pub struct Screen;
+
+impl Screen {
+    fn split(window: &str) -> Result<(&str, &str)> {
+        // Session names never contain colons, unlike the titles, which are branch names.
+        window
+            .split_once(':')
+            .ok_or_else(|| eyre!("not a screen window: {window}"))
+    }
+}
+
+impl Multiplexer for Screen {
+    fn kind(&self) -> MultiplexerKind {
+        MultiplexerKind::Screen
+    }
+
+    fn inside(&self) -> bool {
+        env::var_os("STY").is_some_and(|sty| !sty.is_empty())
+    }
+
+    fn open(&self, name: &str, command: &[OsString]) -> Result<String> {
+        let session = match env::var("STY") {
+            Ok(sty) if self.inside() => sty,
+            _ => String::from(SESSION),
+        };
+        let exists = succeeds(Command::new("screen").args(["-S", &session, "-Q", "select", "."]))?;
+        let mut screen = Command::new("screen");
+        match exists {
+            true => screen.args(["-S", &session, "-X", "screen", "-t", name]),
+            false => screen.args(["-dmS", &session, "-t", name]),
+        };
+        screen.args(command);
+        output(&mut screen).context("open screen window")?;
+        Ok(format!("{session}:{name}"))
+    }
+
+    fn is_open(&self, window: &str) -> Result<bool> {
+        let (session, title) = Self::split(window)?;
+        // Lists windows as `0$ title  1*$ other`; close enough for telling whether one is gone.
+        let windows = output(Command::new("screen").args(["-S", session, "-Q", "windows"]))
+            .unwrap_or_default();
+        Ok(windows.contains(title))
+    }
+
+    fn attach(&self, window: &str) -> Result<()> {
+        let (session, title) = Self::split(window)?;
+        // `-x` rather than `-r`, so that attaching works even if another terminal is.
+        let status = Command::new("screen")
+            .args(["-x", session, "-p", title])
+            .status()
+            .context("run screen")?;
+        if !status.success() {
+            bail!("screen failed to attach: {status}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{env, ffi::OsString, process::Command};
+
+use color_eyre::eyre::{bail, Context, Result};
+
+use super::{output, succeeds, Multiplexer, MultiplexerKind, SESSION};
+
+/// tmux, whose windows are identified by their window IDs.
+#[derive(Debug, Clone, Copy)]
+pub struct Tmux;
+
+impl Multiplexer for Tmux {
+    fn kind(&self) -> MultiplexerKind {
+        MultiplexerKind::Tmux
+    }
+
+    fn inside(&self) -> bool {
+        env::var_os("TMUX").is_some_and(|tmux| !tmux.is_empty())
+    }
+
+    fn open(&self, name: &str, command: &[OsString]) -> Result<String> {
+        // Matched exactly, rather than as a prefix of another session's name.
+        let session = format!("={SESSION}");
+        let mut tmux = Command::new("tmux");
+        if self.inside() {
+            tmux.arg("new-window");
+        } else if succeeds(Command::new("tmux").args(["has-session", "-t", &session]))? {
+            tmux.args(["new-window", "-d", "-t", &format!("{session}:")]);
+        } else {
+            tmux.args(["new-session", "-d", "-s", SESSION]);
+        }
+        tmux.args(["-n", name, "-P", "-F", "#{window_id}", "--"])
+            .args(command);
+        let window = output(&mut tmux).context("open tmux window")?;
+        Ok(String::from(window.trim()))
+    }
+
+    fn is_open(&self, window: &str) -> Result<bool> {
+        // The server exits with its last window, after which listing fails.
+        let windows =
+            output(Command::new("tmux").args(["list-windows", "-a", "-F", "#{window_id}"]))
+                .unwrap_or_default();
+        Ok(windows.lines().any(|line| line == window))
+    }
+
+    fn attach(&self, window: &str) -> Result<()> {
+        // Attaching from inside tmux would nest it; switching to the window is what's meant.
+        let command = match self.inside() {
+            true => "switch-client",
+            false => "attach-session",
+        };
+        let status = Command::new("tmux")
+            .args([command, "-t", window])
+            .status()
+            .context("run tmux")?;
+        if !status.success() {
+            bail!("tmux {command} failed: {status}");
+        }
+        Ok(())
+    }
+}
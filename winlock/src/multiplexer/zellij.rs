@@ -0,0 +1,78 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{env, ffi::OsString, process::Command};
+
+use color_eyre::eyre::{bail, Context, Result};
+
+use super::{output, Multiplexer, MultiplexerKind, SESSION};
+
+/// Zellij, which runs each backend in a pane of its own.
+///
+/// Zellij doesn't report on individual panes, so windows are identified by
+/// the Zellij session they're in, and a pane closed before its backend exited
+/// is only noticed once that session ends.
+#[derive(Debug, Clone, Copy)]
+pub struct Zellij;
+
+impl Zellij {
+    fn session(&self) -> String {
+        env::var("ZELLIJ_SESSION_NAME")
+            .ok()
+            .filter(|session| self.inside() && !session.is_empty())
+            .unwrap_or_else(|| String::from(SESSION))
+    }
+}
+
+impl Multiplexer for Zellij {
+    fn kind(&self) -> MultiplexerKind {
+        MultiplexerKind::Zellij
+    }
+
+    fn inside(&self) -> bool {
+        env::var_os("ZELLIJ").is_some()
+    }
+
+    fn open(&self, name: &str, command: &[OsString]) -> Result<String> {
+        let session = self.session();
+        if !self.inside() {
+            // Does nothing if the session already exists.
+            output(Command::new("zellij").args(["attach", "--create-background", &session]))
+                .context("start zellij session")?;
+        }
+        output(
+            Command::new("zellij")
+                .args([
+                    "--session",
+                    &session,
+                    "run",
+                    "--close-on-exit",
+                    "--name",
+                    name,
+                    "--",
+                ])
+                .args(command),
+        )
+        .context("open zellij pane")?;
+        Ok(session)
+    }
+
+    fn is_open(&self, window: &str) -> Result<bool> {
+        let sessions =
+            output(Command::new("zellij").args(["list-sessions", "--short", "--no-formatting"]))
+                .unwrap_or_default();
+        Ok(sessions.lines().any(|line| line.trim() == window))
+    }
+
+    fn attach(&self, window: &str) -> Result<()> {
+        let status = Command::new("zellij")
+            .args(["attach", window])
+            .status()
+            .context("run zellij")?;
+        if !status.success() {
+            bail!("zellij attach failed: {status}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Restricting which hosts a backend can reach.
+//!
+//! An isolated backend runs in its own network namespace, whose only network
+//! is a loopback interface, so it can't connect anywhere directly. Inside the
+//! namespace a relay listens on [`PROXY_PORT`] and forwards each connection
+//! over a Unix socket, which is reachable from any network namespace since it
+//! lives in the filesystem, to a [`Proxy`] running in anna. The proxy only
+//! connects to hosts the [`NetworkPolicy`] allows. Backends find the relay
+//! through the standard proxy environment variables.
+//!
+//! Namespaces are a Linux feature; isolation fails on other platforms.
+
+use std::process::Command;
+
+use color_eyre::eyre::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+#[cfg(all(target_os = "linux", feature = "cli-backends"))]
+mod namespace;
+#[cfg(all(unix, feature = "cli-backends"))]
+mod proxy;
+
+#[cfg(all(unix, feature = "cli-backends"))]
+pub use proxy::Proxy;
+
+/// The port the relay listens on inside the backend's network namespace.
+/// The namespace is private to the backend, so nothing else can be using it.
+pub const PROXY_PORT: u16 = 3128;
+
+/// The environment variables through which programs discover a proxy;
+/// both spellings are in common use.
+#[cfg(all(target_os = "linux", feature = "cli-backends"))]
+const PROXY_VARIABLES: [&str; 6] = [
+    "HTTPS_PROXY",
+    "https_proxy",
+    "HTTP_PROXY",
+    "http_proxy",
+    "ALL_PROXY",
+    "all_proxy",
+];
+
+/// Which hosts a backend may connect to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkPolicy {
+    /// Allowed hosts, either exactly (`api.anthropic.com`)
+    /// or as any subdomain of a domain (`*.anthropic.com`).
+    pub allow: Vec<String>,
+}
+
+impl NetworkPolicy {
+    /// Whether the policy allows connecting to `host`.
+    pub fn allows(&self, host: &str) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let host = host.trim_end_matches('.').to_lowercase();
+        self.allow.iter().any(|pattern| {
+            let pattern = pattern.to_lowercase();
+            match pattern.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|subdomain| subdomain.ends_with('.')),
+                None => host == pattern,
+            }
+        })
+    }
+}
+
+/// Arrange for `command` to run in its own network namespace,
+/// with access to the network only through `proxy`.
+#[cfg(all(target_os = "linux", feature = "cli-backends"))]
+pub fn isolate(command: &mut Command, proxy: &Proxy) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    let url = format!("http://127.0.0.1:{PROXY_PORT}");
+    for variable in PROXY_VARIABLES {
+        command.env(variable, &url);
+    }
+    // Anything exempted from the proxy would be unreachable anyway.
+    command.env_remove("NO_PROXY").env_remove("no_proxy");
+
+    let namespace = namespace::Namespace::new(&proxy.socket())?;
+    // SAFETY: `Namespace::enter` only makes async-signal-safe system calls
+    // and doesn't allocate, as required between fork and exec.
+    unsafe {
+        command.pre_exec(move || namespace.enter());
+    }
+    Ok(())
+}
+
+/// Arrange for `command` to run in its own network namespace,
+/// with access to the network only through `proxy`.
+#[cfg(not(all(target_os = "linux", feature = "cli-backends")))]
+pub fn isolate(_command: &mut Command, _proxy: &Proxy) -> Result<()> {
+    bail!("network isolation is only supported on Linux, with winlock's `cli-backends` feature")
+}
+
+/// Check that network isolation works here, by running a trivial isolated command.
+///
+/// Some systems disable the unprivileged user namespaces isolation relies on.
+pub fn check() -> Result<()> {
+    let proxy = Proxy::start(NetworkPolicy::default())?;
+    let mut command = Command::new("true");
+    isolate(&mut command, &proxy)?;
+    let status = command.status().context("run isolated command")?;
+    if !status.success() {
+        bail!("isolated command failed: {status}");
+    }
+    Ok(())
+}
+
+/// Stands in for the proxy where there are no Unix sockets, or without the `cli-backends`
+/// feature, and so no isolation either.
+#[cfg(not(all(unix, feature = "cli-backends")))]
+#[derive(Debug)]
+pub struct Proxy;
+
+#[cfg(not(all(unix, feature = "cli-backends")))]
+impl Proxy {
+    /// Fails: network isolation is only supported on Linux, with the `cli-backends` feature.
+    pub fn start(_policy: NetworkPolicy) -> Result<Self> {
+        bail!("network isolation is only supported on Linux, with winlock's `cli-backends` feature")
+    }
+
+    /// The hosts connections were refused to so far; always empty.
+    pub fn denied(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
@@ -0,0 +1,259 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    ffi::{c_int, c_uint, CStr},
+    io, mem,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+    ptr,
+};
+
+use color_eyre::eyre::{bail, Result};
+
+use super::PROXY_PORT;
+
+/// The most connections the relay handles at once; backends make a handful.
+const MAX_CONNECTIONS: usize = 64;
+
+/// Everything needed to set up the namespace, prepared in advance
+/// because nothing may be allocated in the child process.
+pub(super) struct Namespace {
+    uid_map: Vec<u8>,
+
+    gid_map: Vec<u8>,
+
+    proxy: libc::sockaddr_un,
+}
+
+impl Namespace {
+    pub(super) fn new(proxy: &Path) -> Result<Self> {
+        // SAFETY: `sockaddr_un` is plain data, for which all zeroes is valid.
+        let mut address = unsafe { mem::zeroed::<libc::sockaddr_un>() };
+        address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        let path = proxy.as_os_str().as_bytes();
+        // One byte is left over for the terminating nul.
+        if path.len() >= address.sun_path.len() {
+            bail!("proxy socket path is too long: {}", proxy.display());
+        }
+        for (to, from) in address.sun_path.iter_mut().zip(path) {
+            *to = *from as libc::c_char;
+        }
+
+        // SAFETY: these calls have no preconditions and can't fail.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Ok(Self {
+            // The user keeps their own identity inside the namespace,
+            // so files the backend creates are owned by them as usual.
+            uid_map: format!("{uid} {uid} 1").into_bytes(),
+            gid_map: format!("{gid} {gid} 1").into_bytes(),
+            proxy: address,
+        })
+    }
+
+    /// Move the calling process into new user and network namespaces
+    /// and start the relay to the proxy in a child process.
+    pub(super) fn enter(&self) -> io::Result<()> {
+        // SAFETY: these are system calls on memory owned by this function
+        // or `self`, which outlive the calls.
+        unsafe {
+            // A user namespace is what allows creating a network namespace without privileges.
+            check(libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET))?;
+            write_file(c"/proc/self/setgroups", b"deny")?;
+            write_file(c"/proc/self/uid_map", &self.uid_map)?;
+            write_file(c"/proc/self/gid_map", &self.gid_map)?;
+            loopback_up()?;
+            let listener = listen()?;
+
+            let backend = libc::getpid();
+            match check(libc::fork())? {
+                0 => relay(backend, listener, &self.proxy),
+                _ => {
+                    libc::close(listener);
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+fn check(result: c_int) -> io::Result<c_int> {
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        result => Ok(result),
+    }
+}
+
+unsafe fn write_file(path: &CStr, content: &[u8]) -> io::Result<()> {
+    let fd = check(libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC))?;
+    let written = libc::write(fd, content.as_ptr().cast(), content.len());
+    libc::close(fd);
+    match written {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// New network namespaces start with their loopback interface down.
+unsafe fn loopback_up() -> io::Result<()> {
+    let fd = check(libc::socket(
+        libc::AF_INET,
+        libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+        0,
+    ))?;
+    let mut request = mem::zeroed::<libc::ifreq>();
+    for (to, from) in request.ifr_name.iter_mut().zip(b"lo") {
+        *to = *from as libc::c_char;
+    }
+    request.ifr_ifru.ifru_flags = (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+    let result = libc::ioctl(fd, libc::SIOCSIFFLAGS as _, &request);
+    libc::close(fd);
+    check(result).map(drop)
+}
+
+unsafe fn listen() -> io::Result<c_int> {
+    let fd = check(libc::socket(
+        libc::AF_INET,
+        libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+        0,
+    ))?;
+    let mut address = mem::zeroed::<libc::sockaddr_in>();
+    address.sin_family = libc::AF_INET as libc::sa_family_t;
+    address.sin_port = PROXY_PORT.to_be();
+    address.sin_addr.s_addr = u32::from(std::net::Ipv4Addr::LOCALHOST).to_be();
+    let bound = libc::bind(
+        fd,
+        ptr::from_ref(&address).cast(),
+        mem::size_of_val(&address) as libc::socklen_t,
+    );
+    if bound == -1 || libc::listen(fd, MAX_CONNECTIONS as c_int) == -1 {
+        let err = io::Error::last_os_error();
+        libc::close(fd);
+        return Err(err);
+    }
+    Ok(fd)
+}
+
+/// Relay connections accepted on `listener` to the proxy, until the backend exits.
+///
+/// This runs in a child forked from a process which may have had other threads,
+/// so like the rest of this module it must not allocate or take locks.
+unsafe fn relay(backend: libc::pid_t, listener: c_int, proxy: &libc::sockaddr_un) -> ! {
+    // The parent becomes the backend, so this exits along with it;
+    // unless it already has, in which case the signal will never come.
+    libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+    if libc::getppid() != backend {
+        libc::_exit(0);
+    }
+    // Signals meant for the backend mustn't cut it off from the network.
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT] {
+        libc::signal(signal, libc::SIG_IGN);
+    }
+    // Inherited descriptors are closed: the terminal belongs to the backend,
+    // and holding others open, such as the pipe through which the spawning
+    // process learns that exec succeeded, would keep their readers waiting.
+    let listener = match listener {
+        3 => listener,
+        _ => libc::dup2(listener, 3),
+    };
+    for fd in 0..3 {
+        libc::close(fd);
+    }
+    libc::syscall(libc::SYS_close_range, 4, c_uint::MAX, 0);
+
+    let mut pairs = [[-1 as c_int; 2]; MAX_CONNECTIONS];
+    let mut fds = [libc::pollfd {
+        fd: -1,
+        events: 0,
+        revents: 0,
+    }; 1 + 2 * MAX_CONNECTIONS];
+    let mut buf = [0u8; 16 * 1024];
+    loop {
+        fds[0] = libc::pollfd {
+            fd: listener,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        for (pair, slots) in pairs.iter().zip(fds[1..].chunks_exact_mut(2)) {
+            for (fd, slot) in pair.iter().zip(slots) {
+                // Negative descriptors are ignored by poll.
+                *slot = libc::pollfd {
+                    fd: *fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+            }
+        }
+        if libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) == -1 {
+            continue;
+        }
+
+        if fds[0].revents & libc::POLLIN != 0 {
+            accept(listener, proxy, &mut pairs);
+        }
+        for (pair, slots) in pairs.iter_mut().zip(fds[1..].chunks_exact(2)) {
+            for (side, slot) in slots.iter().enumerate() {
+                if slot.fd < 0 || slot.fd != pair[side] || slot.revents == 0 {
+                    continue;
+                }
+                let n = libc::read(slot.fd, buf.as_mut_ptr().cast(), buf.len());
+                let forwarded = match usize::try_from(n) {
+                    Ok(n @ 1..) => write_all(pair[1 - side], &buf[..n.min(buf.len())]),
+                    _ => false,
+                };
+                if !forwarded {
+                    libc::close(pair[0]);
+                    libc::close(pair[1]);
+                    *pair = [-1, -1];
+                    break;
+                }
+            }
+        }
+    }
+}
+
+unsafe fn accept(
+    listener: c_int,
+    proxy: &libc::sockaddr_un,
+    pairs: &mut [[c_int; 2]; MAX_CONNECTIONS],
+) {
+    let client = libc::accept4(
+        listener,
+        ptr::null_mut(),
+        ptr::null_mut(),
+        libc::SOCK_CLOEXEC,
+    );
+    if client == -1 {
+        return;
+    }
+    let Some(pair) = pairs.iter_mut().find(|pair| pair[0] < 0) else {
+        libc::close(client);
+        return;
+    };
+    let upstream = libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0);
+    let connected = upstream != -1
+        && libc::connect(
+            upstream,
+            ptr::from_ref(proxy).cast(),
+            mem::size_of_val(proxy) as libc::socklen_t,
+        ) != -1;
+    if !connected {
+        libc::close(client);
+        libc::close(upstream);
+        return;
+    }
+    *pair = [client, upstream];
+}
+
+unsafe fn write_all(fd: c_int, mut data: &[u8]) -> bool {
+    while !data.is_empty() {
+        let n = libc::write(fd, data.as_ptr().cast(), data.len());
+        match usize::try_from(n) {
+            Ok(n) => data = &data[n.min(data.len())..],
+            Err(_) if *libc::__errno_location() == libc::EINTR => continue,
+            Err(_) => return false,
+        }
+    }
+    true
+}
@@ -0,0 +1,186 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, BufReader, Write},
+    net::{Shutdown, TcpStream},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use color_eyre::eyre::{Context, Result};
+use tempfile::TempDir;
+
+use super::NetworkPolicy;
+
+/// The longest request head the proxy accepts; real ones are far shorter.
+const MAX_HEAD_LEN: usize = 16 * 1024;
+
+/// An HTTP proxy which only connects to hosts allowed by a [`NetworkPolicy`],
+/// listening on a Unix socket. It stops when dropped.
+///
+/// Both `CONNECT` tunnels, used for HTTPS, and plain HTTP requests are supported.
+#[derive(Debug)]
+pub struct Proxy {
+    dir: TempDir,
+
+    denied: Arc<Mutex<BTreeSet<String>>>,
+
+    stopped: Arc<AtomicBool>,
+}
+
+impl Proxy {
+    /// Start the proxy on a new socket.
+    pub fn start(policy: NetworkPolicy) -> Result<Self> {
+        let dir = tempfile::tempdir().context("create proxy directory")?;
+        let path = dir.path().join("proxy.sock");
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("listen on proxy socket: {}", path.display()))?;
+
+        let policy = Arc::new(policy);
+        let denied = Arc::new(Mutex::new(BTreeSet::new()));
+        let stopped = Arc::new(AtomicBool::new(false));
+        thread::spawn({
+            let denied = Arc::clone(&denied);
+            let stopped = Arc::clone(&stopped);
+            move || {
+                for stream in listener.incoming() {
+                    if stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    let policy = Arc::clone(&policy);
+                    let denied = Arc::clone(&denied);
+                    thread::spawn(move || {
+                        // Failures only affect the one connection,
+                        // which the backend sees as a network error.
+                        let _ = serve(stream, &policy, &denied);
+                    });
+                }
+            }
+        });
+
+        Ok(Self {
+            dir,
+            denied,
+            stopped,
+        })
+    }
+
+    /// The path of the socket the proxy listens on.
+    pub fn socket(&self) -> PathBuf {
+        self.dir.path().join("proxy.sock")
+    }
+
+    /// The hosts connections were refused to so far, in order.
+    pub fn denied(&self) -> Vec<String> {
+        let denied = self
+            .denied
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        denied.iter().cloned().collect()
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Wakes the listener so that it notices it has been stopped.
+        let _ = UnixStream::connect(self.socket());
+    }
+}
+
+fn serve(
+    client: UnixStream,
+    policy: &NetworkPolicy,
+    denied: &Mutex<BTreeSet<String>>,
+) -> Result<()> {
+    let mut reader = BufReader::new(client.try_clone().context("clone client stream")?);
+    let mut head = String::new();
+    loop {
+        let n = reader.read_line(&mut head).context("read request")?;
+        if n == 0 || head.len() > MAX_HEAD_LEN {
+            return Ok(());
+        }
+        if head.ends_with("\r\n\r\n") || head.ends_with("\n\n") {
+            break;
+        }
+    }
+
+    let mut request = head.split_whitespace();
+    let (method, target) = match (request.next(), request.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return respond(&client, "400 Bad Request"),
+    };
+    let tunnel = method.eq_ignore_ascii_case("CONNECT");
+    let authority = match tunnel {
+        true => Some(target),
+        false => target
+            .strip_prefix("http://")
+            .map(|rest| rest.split('/').next().unwrap_or(rest)),
+    };
+    let Some((host, port)) = authority.and_then(|authority| split_authority(authority, tunnel))
+    else {
+        return respond(&client, "400 Bad Request");
+    };
+
+    if !policy.allows(host) {
+        denied
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(String::from(host));
+        return respond(&client, "403 Forbidden");
+    }
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let Ok(mut upstream) = TcpStream::connect((host, port)) else {
+        return respond(&client, "502 Bad Gateway");
+    };
+
+    if tunnel {
+        respond(&client, "200 Connection Established")?;
+    } else {
+        // Servers must accept requests in absolute form, so the head is passed on as is.
+        upstream
+            .write_all(head.as_bytes())
+            .context("forward request")?;
+    }
+    // Anything the client sent after the head was read into the buffer along with it.
+    upstream
+        .write_all(reader.buffer())
+        .context("forward request")?;
+
+    let mut client_write = client.try_clone().context("clone client stream")?;
+    let mut upstream_read = upstream.try_clone().context("clone upstream stream")?;
+    let download = thread::spawn(move || {
+        let _ = io::copy(&mut upstream_read, &mut client_write);
+        let _ = client_write.shutdown(Shutdown::Write);
+    });
+    let mut client_read = reader.into_inner();
+    let _ = io::copy(&mut client_read, &mut upstream);
+    let _ = upstream.shutdown(Shutdown::Write);
+    let _ = download.join();
+    Ok(())
+}
+
+/// Split `host:port`; the port is required for tunnels, and otherwise defaults to HTTP's.
+fn split_authority(authority: &str, tunnel: bool) -> Option<(&str, u16)> {
+    // IPv6 addresses are bracketed, since they contain colons themselves.
+    match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => Some((host, port.parse().ok()?)),
+        _ if tunnel => None,
+        _ => Some((authority, 80)),
+    }
+}
+
+fn respond(mut client: &UnixStream, status: &str) -> Result<()> {
+    write!(client, "HTTP/1.1 {status}\r\n\r\n").context("write response")
+}
@@ -0,0 +1,204 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Notifications that an agent has finished, for runs left unattended.
+//!
+//! Notifications can be shown on the desktop, posted to a Slack incoming webhook,
+//! or posted as a JSON [`Notification`] to any other webhook.
+
+use std::{path::PathBuf, process::Command};
+
+use color_eyre::eyre::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{branch::BranchName, session::Session, RunOutcome};
+
+/// Where to send notifications; see [`Config::notify`](method@crate::config::Config::notify).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifyConfig {
+    /// Show a desktop notification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desktop: Option<bool>,
+
+    /// A URL to which the [`Notification`] is posted as JSON.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
+
+    /// The URL of a Slack incoming webhook, to which a summary is posted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slack: Option<String>,
+}
+
+impl NotifyConfig {
+    /// These settings, falling back to `other` for any that are unset.
+    pub fn or(self, other: &Self) -> Self {
+        Self {
+            desktop: self.desktop.or(other.desktop),
+            webhook: self.webhook.or_else(|| other.webhook.clone()),
+            slack: self.slack.or_else(|| other.slack.clone()),
+        }
+    }
+
+    /// Whether any notifications are sent.
+    pub fn enabled(&self) -> bool {
+        self.desktop == Some(true) || self.webhook.is_some() || self.slack.is_some()
+    }
+}
+
+/// What a finished agent run is reported as.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+    /// The project the session belongs to.
+    pub project: PathBuf,
+
+    /// The session's branch.
+    pub branch: BranchName,
+
+    /// The session's workspace.
+    pub workspace: PathBuf,
+
+    /// Whether the backend exited successfully.
+    pub success: bool,
+
+    /// The backend's exit code, if it exited normally rather than by a signal.
+    pub code: Option<i32>,
+
+    /// Whether the workspace passed verification, if it was verified.
+    pub verified: Option<bool>,
+
+    /// A summary of the changes committed to the branch, as from [`Session::diff_stat`].
+    pub diff_stat: Option<String>,
+
+    /// How long the run took, in milliseconds.
+    pub duration_ms: u64,
+}
+
+impl Notification {
+    /// Describe the run of `session` which ended with `outcome`.
+    pub fn new(session: &Session, outcome: &RunOutcome) -> Self {
+        Self {
+            project: session.project.clone(),
+            branch: session.branch.clone(),
+            workspace: session.workspace.clone(),
+            success: outcome.status.success(),
+            code: outcome.status.code(),
+            verified: outcome
+                .verification
+                .as_ref()
+                .map(|verification| verification.passed),
+            // Best effort: a notification without the changes is better than none.
+            diff_stat: session.diff_stat().ok(),
+            duration_ms: u64::try_from(outcome.duration.as_millis()).unwrap_or(u64::MAX),
+        }
+    }
+
+    /// A one-line summary, such as "'feature' finished successfully in 12m 5s".
+    pub fn summary(&self) -> String {
+        let result = match (self.success, self.verified) {
+            (true, Some(false)) => "finished, but failed verification",
+            (true, _) => "finished successfully",
+            (false, _) => "failed",
+        };
+        let secs = self.duration_ms / 1000;
+        let duration = match secs {
+            0..60 => format!("{secs}s"),
+            60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+            _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        };
+        format!("'{}' {result} in {duration}", self.branch)
+    }
+
+    /// Send the notification everywhere `config` asks for, returning what failed
+    /// rather than stopping there, since the others may still get through.
+    pub fn send(&self, config: &NotifyConfig) -> Vec<color_eyre::Report> {
+        let mut errors = Vec::new();
+        if config.desktop == Some(true) {
+            errors.extend(self.show().err());
+        }
+        if let Some(url) = &config.webhook {
+            let body = serde_json::to_value(self).expect("notifications must serialize");
+            errors.extend(post(url, &body).err());
+        }
+        if let Some(url) = &config.slack {
+            let mut text = format!("{} ({})", self.summary(), self.project.display());
+            if let Some(diff_stat) = self.diff_stat.as_deref().filter(|s| !s.is_empty()) {
+                text.push_str(&format!("\n```\n{}\n```", diff_stat.trim_end()));
+            }
+            errors.extend(post(url, &serde_json::json!({ "text": text })).err());
+        }
+        errors
+    }
+
+    /// Show the notification on the desktop, using the platform's own tools
+    /// so that no notification daemon bindings are needed.
+    fn show(&self) -> Result<()> {
+        let title = "anna";
+        let body = format!("{} ({})", self.summary(), self.project.display());
+        let mut command = if cfg!(target_os = "macos") {
+            let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+            let script = format!(
+                "display notification {} with title {}",
+                quote(&body),
+                quote(title)
+            );
+            let mut command = Command::new("osascript");
+            command.args(["-e", &script]);
+            command
+        } else if cfg!(unix) {
+            let mut command = Command::new("notify-send");
+            command.args([title, &body]);
+            command
+        } else {
+            bail!("desktop notifications are only supported on Linux and macOS");
+        };
+        let program = command.get_program().to_string_lossy().into_owned();
+        let output = command
+            .output()
+            .with_context(|| format!("run {program} to show a desktop notification"))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!(
+                "{program} failed to show a desktop notification ({}): {}",
+                output.status,
+                stderr.trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "notify")]
+fn post(url: &str, body: &serde_json::Value) -> Result<()> {
+    let host = host(url);
+    let response = ureq::post(url)
+        .config()
+        .http_status_as_error(false)
+        .build()
+        .send_json(body)
+        .with_context(|| format!("send notification to {host}"))?;
+    let status = response.status();
+    if !status.is_success() {
+        bail!("send notification to {host}: {status}");
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "notify"))]
+fn post(url: &str, _body: &serde_json::Value) -> Result<()> {
+    bail!(
+        "sending notifications to {} requires winlock's `notify` feature",
+        host(url)
+    )
+}
+
+/// The host `url` points to, which is all of it that's shown in errors:
+/// webhooks' URLs hold the secret that lets anyone post to them.
+fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host)
+}
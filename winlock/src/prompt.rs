@@ -0,0 +1,268 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Prompt templates.
+//!
+//! Templates live in `prompts/` in the [config directory](crate::config::config_dir),
+//! or in [`PROJECT_PROMPTS`] in a project for templates specific to it, and are
+//! referenced by file stem, so `prompts/bugfix.md` is the `bugfix` template.
+//! They interpolate `{{name}}` variables supplied by the user, alongside
+//! variables describing the project:
+//!
+//! - `{{branch}}`: the branch the agent works on.
+//! - `{{project.name}}`: the name of the project directory.
+//! - `{{project.language}}`: the project's primary language, if it can be determined.
+//! - `{{project.tree}}`: a summary of the project's file tree.
+
+#[cfg(feature = "copy")]
+use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::ErrorKind,
+    path::Path,
+};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+#[cfg(feature = "copy")]
+use ignore::WalkBuilder;
+
+use crate::{branch::BranchName, config::config_dir};
+
+/// The directory, relative to a project's root, holding the project's own templates.
+pub const PROJECT_PROMPTS: &str = ".anna/prompts";
+
+/// The maximum number of entries included in `{{project.tree}}`.
+#[cfg(feature = "copy")]
+const TREE_LIMIT: usize = 200;
+
+/// The maximum depth of entries included in `{{project.tree}}`.
+#[cfg(feature = "copy")]
+const TREE_DEPTH: usize = 3;
+
+/// A prompt template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptTemplate {
+    source: String,
+}
+
+impl PromptTemplate {
+    /// Load the named template from `prompts/` in the [config directory](crate::config::config_dir).
+    pub fn load(name: &str) -> Result<Self> {
+        Self::load_from(&config_dir()?.join("prompts"), name)
+    }
+
+    /// Load the named template for `project`: from its [`PROJECT_PROMPTS`] if it's there,
+    /// otherwise from `prompts/` in the [config directory](crate::config::config_dir).
+    pub fn load_for_project(project: &Path, name: &str) -> Result<Self> {
+        let local = project.join(PROJECT_PROMPTS);
+        if let Some(template) = Self::find(&local, name)? {
+            return Ok(template);
+        }
+        let global = config_dir()?.join("prompts");
+        match Self::find(&global, name)? {
+            Some(template) => Ok(template),
+            None => bail!(
+                "no prompt template named '{name}' in {} or {}",
+                local.display(),
+                global.display()
+            ),
+        }
+    }
+
+    /// Load the named template from the provided directory.
+    pub fn load_from(dir: &Path, name: &str) -> Result<Self> {
+        match Self::find(dir, name)? {
+            Some(template) => Ok(template),
+            None => bail!("no prompt template named '{name}' in {}", dir.display()),
+        }
+    }
+
+    fn find(dir: &Path, name: &str) -> Result<Option<Self>> {
+        let candidates = [
+            format!("{name}.md"),
+            format!("{name}.txt"),
+            String::from(name),
+        ];
+        for candidate in candidates {
+            let path = dir.join(candidate);
+            match fs::read_to_string(&path) {
+                Ok(source) => return Ok(Some(Self { source })),
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Err(err).context(format!("read prompt template: {}", path.display()))
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// The names of the variables referenced by the template.
+    pub fn variables(&self) -> Result<BTreeSet<String>> {
+        tokens(&self.source)
+            .filter_map(|token| match token {
+                Ok(Token::Variable(name)) => Some(Ok(String::from(name))),
+                Ok(Token::Literal(_)) => None,
+                Err(err) => Some(Err(err)),
+            })
+            .collect()
+    }
+
+    /// Render the template for an agent working on `branch` of `project`.
+    ///
+    /// Variables in `vars` take precedence over the built-in project variables.
+    pub fn render(
+        &self,
+        project: &Path,
+        branch: &BranchName,
+        vars: &BTreeMap<String, String>,
+    ) -> Result<String> {
+        let referenced = self.variables()?;
+        let builtin = referenced
+            .iter()
+            .filter(|name| !vars.contains_key(*name))
+            .filter_map(|name| builtin(project, branch, name).transpose())
+            .collect::<Result<BTreeMap<_, _>>>()?;
+
+        let missing = referenced
+            .iter()
+            .filter(|name| !vars.contains_key(*name) && !builtin.contains_key(*name))
+            .map(|name| format!("{{{{{name}}}}}"))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            bail!(
+                "no value for {}; provide it with --var name=value",
+                missing.join(", ")
+            );
+        }
+
+        tokens(&self.source)
+            .map(|token| match token? {
+                Token::Literal(literal) => Ok(literal),
+                Token::Variable(name) => vars
+                    .get(name)
+                    .or_else(|| builtin.get(name))
+                    .map(String::as_str)
+                    .ok_or_else(|| eyre!("no value for '{{{{{name}}}}}'")),
+            })
+            .collect()
+    }
+}
+
+impl From<String> for PromptTemplate {
+    fn from(source: String) -> Self {
+        Self { source }
+    }
+}
+
+fn builtin(project: &Path, branch: &BranchName, name: &str) -> Result<Option<(String, String)>> {
+    let value = match name {
+        "branch" => Some(branch.to_string()),
+        "project.name" => project
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string()),
+        "project.language" => detect_language(project).map(String::from),
+        "project.tree" => Some(summarize_tree(project)?),
+        _ => None,
+    };
+    Ok(value.map(|value| (String::from(name), value)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    Literal(&'a str),
+
+    Variable(&'a str),
+}
+
+fn tokens(source: &str) -> impl Iterator<Item = Result<Token<'_>>> {
+    let mut rest = source;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let Some(start) = rest.find("{{") else {
+            let literal = rest;
+            rest = "";
+            return Some(Ok(Token::Literal(literal)));
+        };
+        if start > 0 {
+            let (literal, remainder) = rest.split_at(start);
+            rest = remainder;
+            return Some(Ok(Token::Literal(literal)));
+        }
+        let Some(end) = rest.find("}}") else {
+            rest = "";
+            return Some(Err(eyre!("unclosed '{{{{' in prompt template")));
+        };
+        let name = rest[2..end].trim();
+        rest = &rest[end + 2..];
+        if name.is_empty() {
+            return Some(Err(eyre!("empty variable name in prompt template")));
+        }
+        Some(Ok(Token::Variable(name)))
+    })
+}
+
+/// Guess the project's primary language from well-known manifest files.
+pub fn detect_language(project: &Path) -> Option<&'static str> {
+    const MARKERS: [(&str, &str); 12] = [
+        ("Cargo.toml", "Rust"),
+        ("go.mod", "Go"),
+        ("tsconfig.json", "TypeScript"),
+        ("package.json", "JavaScript"),
+        ("pyproject.toml", "Python"),
+        ("requirements.txt", "Python"),
+        ("setup.py", "Python"),
+        ("Gemfile", "Ruby"),
+        ("pom.xml", "Java"),
+        ("build.gradle", "Java"),
+        ("mix.exs", "Elixir"),
+        ("composer.json", "PHP"),
+    ];
+    MARKERS
+        .into_iter()
+        .find(|(marker, _)| project.join(marker).exists())
+        .map(|(_, language)| language)
+}
+
+/// Summarize the project's file tree, respecting ignore files.
+#[cfg(feature = "copy")]
+pub fn summarize_tree(project: &Path) -> Result<String> {
+    let walker = WalkBuilder::new(project)
+        .max_depth(Some(TREE_DEPTH))
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    let mut lines = Vec::new();
+    for entry in walker {
+        let entry = entry.context("walk project")?;
+        if entry.depth() == 0 {
+            continue;
+        }
+        if lines.len() == TREE_LIMIT {
+            lines.push(String::from("..."));
+            break;
+        }
+
+        let relative = entry
+            .path()
+            .strip_prefix(project)
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| entry.path().to_path_buf());
+        let suffix = if entry.file_type().is_some_and(|t| t.is_dir()) {
+            "/"
+        } else {
+            ""
+        };
+        lines.push(format!("{}{suffix}", relative.display()));
+    }
+
+    Ok(lines.join("\n"))
+}
+
+#[cfg(not(feature = "copy"))]
+pub fn summarize_tree(_project: &Path) -> Result<String> {
+    bail!("summarizing project trees requires winlock's `copy` feature")
+}
@@ -0,0 +1,457 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Running backends under a pseudo-terminal.
+//!
+//! Backends are interactive TUIs which expect a terminal, so to observe their
+//! output we run them under a PTY and relay between it and the user's terminal.
+//! Since the backend no longer shares the user's terminal, changes to its size
+//! and signals sent to anna are forwarded to the backend explicitly.
+
+use std::{
+    ffi::OsString,
+    io::{self, IsTerminal, Read, Write},
+    path::Path,
+    process::ExitStatus,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use crossterm::terminal;
+use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
+
+use crate::{network::Proxy, transcript::Recorder};
+
+/// The terminal size used when the user isn't attached to a terminal.
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+/// How often to check whether the backend has exited or the terminal was resized.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Signals forwarded to the backend rather than handled by anna.
+#[cfg(unix)]
+const FORWARDED_SIGNALS: [i32; 4] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT];
+
+/// Run `program` with `args` in `dir` under a PTY, recording output to `transcript`,
+/// and [isolated](crate::network::isolate) behind `proxy` if provided.
+pub(crate) fn run(
+    program: &str,
+    args: &[String],
+    env: &[(&str, OsString)],
+    dir: &Path,
+    transcript: Option<&Path>,
+    proxy: Option<&Proxy>,
+) -> Result<ExitStatus> {
+    let (mut cols, mut rows) = terminal_size();
+    let pair = native_pty_system()
+        .openpty(PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })
+        .map_err(|err| eyre!("open pty: {err:#}"))?;
+
+    let mut child = match proxy {
+        Some(proxy) => spawn_isolated(&*pair.master, program, args, env, dir, proxy)?,
+        None => {
+            let mut command = CommandBuilder::new(program);
+            command.args(args);
+            for (name, value) in env {
+                command.env(name, value);
+            }
+            command.cwd(dir);
+            pair.slave
+                .spawn_command(command)
+                .map_err(|err| eyre!("spawn {program}: {err:#}"))?
+        }
+    };
+
+    // The slave must be closed on our side, otherwise reading the master
+    // never reaches end of file after the backend exits.
+    drop(pair.slave);
+
+    // Registered before anything else can fail, so that a signal arriving
+    // while the relays start up still reaches the backend instead of killing
+    // anna and orphaning it.
+    #[cfg(unix)]
+    let signals = SignalForwarding::install()?;
+
+    let recorder = transcript
+        .map(|path| {
+            let command =
+                shell_words::join(std::iter::once(program).chain(args.iter().map(String::as_str)));
+            Recorder::create(path, cols, rows, &command)
+        })
+        .transpose()?;
+    // Shared so that resizes are recorded in order with the output they affect.
+    let recorder = Arc::new(Mutex::new(recorder));
+
+    let mut reader = pair
+        .master
+        .try_clone_reader()
+        .map_err(|err| eyre!("read pty: {err:#}"))?;
+    let writer = pair
+        .master
+        .take_writer()
+        .map_err(|err| eyre!("write pty: {err:#}"))?;
+
+    let _raw = RawMode::enable()?;
+    let input = InputRelay::start(writer);
+
+    let output = thread::spawn({
+        let recorder = Arc::clone(&recorder);
+        move || -> Result<()> {
+            let mut stdout = io::stdout();
+            let mut buf = [0u8; 8192];
+            loop {
+                // Linux reports EIO rather than end of file once the slave closes.
+                let n = match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                stdout.write_all(&buf[..n]).context("write output")?;
+                stdout.flush().context("flush output")?;
+                if let Some(recorder) = lock(&recorder).as_mut() {
+                    recorder.output(&buf[..n])?;
+                }
+            }
+            Ok(())
+        }
+    });
+
+    let status = loop {
+        if let Some(status) = child.try_wait().context("wait for backend")? {
+            break status;
+        }
+
+        #[cfg(unix)]
+        for signal in signals.take() {
+            if let Some(pid) = child.process_id() {
+                forward_signal(pid, signal);
+            }
+        }
+
+        // Polled rather than driven by SIGWINCH, which doesn't exist on Windows.
+        let size = terminal_size();
+        if size != (cols, rows) {
+            (cols, rows) = size;
+            pair.master
+                .resize(PtySize {
+                    rows,
+                    cols,
+                    pixel_width: 0,
+                    pixel_height: 0,
+                })
+                .map_err(|err| eyre!("resize pty: {err:#}"))?;
+            if let Some(recorder) = lock(&recorder).as_mut() {
+                recorder.resize(cols, rows)?;
+            }
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    // Anna's own handling of signals, and of input, is back once the backend is gone.
+    #[cfg(unix)]
+    drop(signals);
+    drop(input);
+
+    output
+        .join()
+        .map_err(|_| eyre!("output relay panicked"))??;
+    if let Some(recorder) = lock(&recorder).take() {
+        recorder.finish()?;
+    }
+
+    Ok(exit_status(status.exit_code()))
+}
+
+/// Spawn the backend on the PTY ourselves, since the PTY library
+/// has no way to run the setup isolation needs in the child.
+#[cfg(target_os = "linux")]
+fn spawn_isolated(
+    master: &dyn MasterPty,
+    program: &str,
+    args: &[String],
+    env: &[(&str, OsString)],
+    dir: &Path,
+    proxy: &Proxy,
+) -> Result<Box<dyn Child + Send + Sync>> {
+    use std::{fs::File, os::unix::process::CommandExt, process::Command};
+
+    let tty = master
+        .tty_name()
+        .ok_or_else(|| eyre!("pty has no device"))?;
+    let open = || {
+        File::options()
+            .read(true)
+            .write(true)
+            .open(&tty)
+            .with_context(|| format!("open pty: {}", tty.display()))
+    };
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .envs(env.iter().cloned())
+        .current_dir(dir)
+        .stdin(open()?)
+        .stdout(open()?)
+        .stderr(open()?);
+    crate::network::isolate(&mut command, proxy)?;
+    // SAFETY: only async-signal-safe system calls are made between fork and exec.
+    // As the PTY library does, the backend becomes a session leader with the PTY
+    // as its controlling terminal, so that it receives job control signals.
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = command
+        .spawn()
+        .with_context(|| format!("spawn {program}"))?;
+    Ok(Box::new(child))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn spawn_isolated(
+    _master: &dyn MasterPty,
+    _program: &str,
+    _args: &[String],
+    _env: &[(&str, OsString)],
+    _dir: &Path,
+    _proxy: &Proxy,
+) -> Result<Box<dyn Child + Send + Sync>> {
+    color_eyre::eyre::bail!("network isolation is only supported on Linux")
+}
+
+fn lock(recorder: &Mutex<Option<Recorder>>) -> std::sync::MutexGuard<'_, Option<Recorder>> {
+    // A panic while recording leaves nothing inconsistent worth refusing to touch.
+    recorder
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Signals sent to anna, recorded by [`record_signal`] until [taken](SignalForwarding::take),
+/// as a set of bits indexed by signal number.
+#[cfg(unix)]
+static PENDING_SIGNALS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+#[cfg(unix)]
+extern "C" fn record_signal(signal: libc::c_int) {
+    // Nothing but an atomic operation, which is safe to do in a signal handler.
+    PENDING_SIGNALS.fetch_or(1 << signal, Ordering::SeqCst);
+}
+
+/// Records the [forwarded signals](FORWARDED_SIGNALS) sent to anna for as long as it is
+/// alive, rather than letting them stop anna, and then puts back how they were handled.
+///
+/// What's recorded is shared, since only one backend at a time runs under a PTY:
+/// each takes over the user's terminal.
+#[cfg(unix)]
+struct SignalForwarding {
+    previous: Vec<(libc::c_int, libc::sigaction)>,
+}
+
+#[cfg(unix)]
+impl SignalForwarding {
+    fn install() -> Result<Self> {
+        PENDING_SIGNALS.store(0, Ordering::SeqCst);
+        let mut forwarding = Self {
+            previous: Vec::new(),
+        };
+        for signal in FORWARDED_SIGNALS {
+            // SAFETY: the handler is async-signal-safe, and both actions are valid for
+            // the call. Those installed so far are put back if one fails, on drop.
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = record_signal as *const () as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                let mut previous: libc::sigaction = std::mem::zeroed();
+                if libc::sigaction(signal, &action, &mut previous) != 0 {
+                    return Err(io::Error::last_os_error()).context("register signal handlers");
+                }
+                forwarding.previous.push((signal, previous));
+            }
+        }
+        Ok(forwarding)
+    }
+
+    /// The signals recorded since they were last taken.
+    fn take(&self) -> impl Iterator<Item = libc::c_int> {
+        let pending = PENDING_SIGNALS.swap(0, Ordering::SeqCst);
+        FORWARDED_SIGNALS
+            .into_iter()
+            .filter(move |signal| pending & (1 << signal) != 0)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SignalForwarding {
+    fn drop(&mut self) {
+        for (signal, previous) in &self.previous {
+            // SAFETY: `previous` is what `sigaction` reported for the signal.
+            unsafe {
+                libc::sigaction(*signal, previous, std::ptr::null_mut());
+            }
+        }
+    }
+}
+
+/// Relays the user's input to the backend on a thread of its own, since reading it blocks,
+/// until it's dropped, which stops the thread and waits for it.
+struct InputRelay {
+    stop: Arc<AtomicBool>,
+
+    thread: Option<JoinHandle<()>>,
+}
+
+impl InputRelay {
+    fn start(mut writer: Box<dyn Write + Send>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let stop = Arc::clone(&stop);
+            move || {
+                let mut buf = [0u8; 1024];
+                while let Some(n) = read_input(&mut buf, &stop) {
+                    if writer.write_all(&buf[..n]).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Self {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for InputRelay {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        let Some(thread) = self.thread.take() else {
+            return;
+        };
+        // Reads of the console can't be polled, so the one the thread is blocked in
+        // is cancelled, until the thread notices it's stopped.
+        #[cfg(windows)]
+        while !thread.is_finished() {
+            use std::os::windows::io::AsRawHandle;
+            // SAFETY: the handle is the thread's, which stays valid until it's joined.
+            unsafe {
+                windows_sys::Win32::System::IO::CancelSynchronousIo(thread.as_raw_handle());
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = thread.join();
+    }
+}
+
+/// Read the user's input into `buf`, returning how much was read, or `None`
+/// once there's no more or the relay is stopped.
+#[cfg(unix)]
+fn read_input(buf: &mut [u8], stop: &AtomicBool) -> Option<usize> {
+    // Polled, so that a stopped relay doesn't wait for input that may never come.
+    while !stop.load(Ordering::SeqCst) {
+        let mut stdin = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = libc::c_int::try_from(POLL_INTERVAL.as_millis()).unwrap_or(libc::c_int::MAX);
+        // SAFETY: `stdin` is valid for the call.
+        let ready = unsafe { libc::poll(&mut stdin, 1, timeout) };
+        if ready == 0 {
+            continue;
+        }
+        // Read directly, since what `io::stdin()` buffers wouldn't wake `poll`.
+        // SAFETY: `buf` is valid for writes of its length.
+        let read = match ready {
+            ..0 => -1,
+            _ => unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) },
+        };
+        match usize::try_from(read) {
+            Ok(0) => return None,
+            Ok(n) => return Some(n),
+            Err(_) if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return None,
+        }
+    }
+    None
+}
+
+#[cfg(windows)]
+fn read_input(buf: &mut [u8], stop: &AtomicBool) -> Option<usize> {
+    if stop.load(Ordering::SeqCst) {
+        return None;
+    }
+    io::stdin().read(buf).ok().filter(|&n| n > 0)
+}
+
+/// Send `signal` to the backend's process group, as the terminal driver would
+/// if the backend were attached to the user's terminal directly.
+#[cfg(unix)]
+fn forward_signal(pid: u32, signal: i32) {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return;
+    };
+    // SAFETY: `killpg` has no memory safety requirements; at worst the
+    // process group has already exited and the call fails with ESRCH.
+    unsafe {
+        libc::killpg(pid, signal);
+    }
+}
+
+fn terminal_size() -> (u16, u16) {
+    if io::stdout().is_terminal() {
+        terminal::size().unwrap_or(DEFAULT_SIZE)
+    } else {
+        DEFAULT_SIZE
+    }
+}
+
+/// Puts the user's terminal in raw mode for as long as it is alive,
+/// so keystrokes reach the backend unprocessed.
+struct RawMode(bool);
+
+impl RawMode {
+    fn enable() -> Result<Self> {
+        if !io::stdin().is_terminal() {
+            return Ok(Self(false));
+        }
+        terminal::enable_raw_mode().context("enable raw mode")?;
+        Ok(Self(true))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if self.0 {
+            let _ = terminal::disable_raw_mode();
+        }
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(code: u32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw((code as i32 & 0xff) << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: u32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code)
+}
@@ -0,0 +1,318 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Agent runs queued to run later, a few at a time, such as a day's worth
+//! of tasks queued in the morning.
+//!
+//! The queue records what to run and how each run went; how a run is carried out
+//! is up to whoever [runs the queue](Queue::run), which `anna` does by running
+//! the `anna agent` command line that was queued.
+
+use std::{
+    fmt,
+    fs::{self, File},
+    io::ErrorKind,
+    num::NonZero,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::Mutex,
+    thread,
+};
+
+use color_eyre::eyre::{bail, Context, Result};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::state_dir,
+    session::{hostname, process_exists},
+};
+
+/// A run waiting in, or taken from, the [`Queue`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedRun {
+    /// The run's number, unique within the queue.
+    pub id: u64,
+
+    /// The project the run works on.
+    pub project: PathBuf,
+
+    /// The directory the run was queued from, which it runs in.
+    pub dir: PathBuf,
+
+    /// The arguments to run, not including the program.
+    pub args: Vec<String>,
+
+    /// When the run was queued.
+    pub queued_at: Timestamp,
+
+    /// How far the run got.
+    pub state: QueuedRunState,
+}
+
+impl QueuedRun {
+    /// Whether the run is waiting to be taken, including one taken by a process
+    /// on this host that has since died, which left it unfinished.
+    fn is_waiting(&self) -> bool {
+        match &self.state {
+            QueuedRunState::Pending => true,
+            QueuedRunState::Running { pid, hostname, .. } => {
+                *hostname == self::hostname() && !process_exists(*pid)
+            }
+            QueuedRunState::Finished { .. } => false,
+        }
+    }
+}
+
+/// How far a [`QueuedRun`] got.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum QueuedRunState {
+    /// The run is waiting to be taken.
+    Pending,
+
+    /// The run was taken by the process `pid` on `hostname`, which is running it.
+    Running {
+        /// When the run started.
+        started_at: Timestamp,
+
+        /// The process running the queue.
+        pid: u32,
+
+        /// The host that process runs on, since the queue may be on a shared filesystem.
+        hostname: String,
+    },
+
+    /// The run finished.
+    Finished {
+        /// When the run started.
+        started_at: Timestamp,
+
+        /// When the run finished.
+        finished_at: Timestamp,
+
+        /// The run's exit code, or `None` if it was killed by a signal or couldn't start.
+        code: Option<i32>,
+
+        /// Why the run couldn't start, if it couldn't.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+impl QueuedRunState {
+    /// Whether the run finished and exited successfully.
+    pub fn succeeded(&self) -> bool {
+        matches!(self, Self::Finished { code: Some(0), .. })
+    }
+}
+
+impl fmt::Display for QueuedRunState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pending => write!(f, "pending"),
+            Self::Running { .. } => write!(f, "running"),
+            Self::Finished { code: Some(0), .. } => write!(f, "succeeded"),
+            Self::Finished {
+                code: Some(code), ..
+            } => write!(f, "failed ({code})"),
+            Self::Finished { error: Some(_), .. } => write!(f, "failed to start"),
+            Self::Finished { .. } => write!(f, "killed"),
+        }
+    }
+}
+
+/// The queue of agent runs, kept in the state directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Queue {
+    dir: PathBuf,
+}
+
+impl Queue {
+    /// Open the queue in the [state directory](crate::config::state_dir).
+    pub fn open() -> Result<Self> {
+        Self::open_in(&state_dir()?)
+    }
+
+    /// Open the queue in the provided directory.
+    pub fn open_in(dir: &Path) -> Result<Self> {
+        let dir = dir.join("queue");
+        let logs = dir.join("logs");
+        fs::create_dir_all(&logs)
+            .with_context(|| format!("create queue directory: {}", logs.display()))?;
+        Ok(Self { dir })
+    }
+
+    /// Queue a run of `args`, from `dir`, working on `project`.
+    pub fn push(&self, project: &Path, dir: &Path, args: Vec<String>) -> Result<QueuedRun> {
+        let _lock = self.lock()?;
+        let mut runs = self.read()?;
+        let run = QueuedRun {
+            id: runs.iter().map(|run| run.id).max().unwrap_or(0) + 1,
+            project: project.to_path_buf(),
+            dir: dir.to_path_buf(),
+            args,
+            queued_at: Timestamp::now(),
+            state: QueuedRunState::Pending,
+        };
+        runs.push(run.clone());
+        self.write(&runs)?;
+        Ok(run)
+    }
+
+    /// List the runs in the queue, in the order they were queued.
+    pub fn list(&self) -> Result<Vec<QueuedRun>> {
+        self.read()
+    }
+
+    /// Take run `id` out of the queue, returning it, or `None` if there is no such run.
+    /// Runs being run can't be taken out.
+    pub fn remove(&self, id: u64) -> Result<Option<QueuedRun>> {
+        let _lock = self.lock()?;
+        let mut runs = self.read()?;
+        let Some(index) = runs.iter().position(|run| run.id == id) else {
+            return Ok(None);
+        };
+        let running = matches!(runs[index].state, QueuedRunState::Running { .. });
+        if running && !runs[index].is_waiting() {
+            bail!("queued run {id} is running");
+        }
+        let run = runs.remove(index);
+        self.write(&runs)?;
+        // Best effort: the log is of no use once the run is gone.
+        let _ = fs::remove_file(self.log_path(id));
+        Ok(Some(run))
+    }
+
+    /// The file a run's output is written to.
+    pub fn log_path(&self, id: u64) -> PathBuf {
+        self.dir.join("logs").join(format!("{id}.log"))
+    }
+
+    /// Run the waiting runs in the order they were queued, at most `max_parallel`
+    /// at once, until none are left, returning those run as they finished.
+    ///
+    /// Runs queued meanwhile are run too. Other processes may run the queue at the same
+    /// time, each taking different runs, so the limit applies to each separately.
+    /// An error from `run` is recorded as the run failing to start; only an error
+    /// reading or writing the queue stops it, once the runs already started finish.
+    pub fn run(
+        &self,
+        max_parallel: NonZero<usize>,
+        run: impl Fn(&QueuedRun) -> Result<ExitStatus> + Sync,
+    ) -> Result<Vec<QueuedRun>> {
+        let finished = Mutex::new(Vec::new());
+        let failed = Mutex::new(None);
+        thread::scope(|scope| {
+            for _ in 0..max_parallel.get() {
+                scope.spawn(|| loop {
+                    if failed.lock().expect("no worker panics").is_some() {
+                        return;
+                    }
+                    let result = self.take().and_then(|taken| {
+                        let Some(taken) = taken else {
+                            return Ok(None);
+                        };
+                        let status = run(&taken);
+                        self.finish(taken, status).map(Some)
+                    });
+                    match result {
+                        Ok(Some(done)) => finished.lock().expect("no worker panics").push(done),
+                        Ok(None) => return,
+                        Err(err) => {
+                            failed.lock().expect("no worker panics").get_or_insert(err);
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        match failed.into_inner().expect("no worker panics") {
+            Some(err) => Err(err),
+            None => Ok(finished.into_inner().expect("no worker panics")),
+        }
+    }
+
+    /// Take the first waiting run, marking it as run by this process.
+    fn take(&self) -> Result<Option<QueuedRun>> {
+        let _lock = self.lock()?;
+        let mut runs = self.read()?;
+        let Some(run) = runs.iter_mut().find(|run| run.is_waiting()) else {
+            return Ok(None);
+        };
+        run.state = QueuedRunState::Running {
+            started_at: Timestamp::now(),
+            pid: std::process::id(),
+            hostname: hostname(),
+        };
+        let run = run.clone();
+        self.write(&runs)?;
+        Ok(Some(run))
+    }
+
+    /// Record how the run went, returning it as recorded.
+    fn finish(&self, mut run: QueuedRun, status: Result<ExitStatus>) -> Result<QueuedRun> {
+        let started_at = match run.state {
+            QueuedRunState::Running { started_at, .. } => started_at,
+            _ => Timestamp::now(),
+        };
+        let (code, error) = match status {
+            Ok(status) => (status.code(), None),
+            Err(err) => (None, Some(format!("{err:#}"))),
+        };
+        run.state = QueuedRunState::Finished {
+            started_at,
+            finished_at: Timestamp::now(),
+            code,
+            error,
+        };
+
+        let _lock = self.lock()?;
+        let mut runs = self.read()?;
+        // Gone if it was taken out of the queue meanwhile, in which case it stays out.
+        if let Some(recorded) = runs.iter_mut().find(|recorded| recorded.id == run.id) {
+            *recorded = run.clone();
+            self.write(&runs)?;
+        }
+        Ok(run)
+    }
+
+    /// Take the lock on the queue, held until the returned file is dropped.
+    fn lock(&self) -> Result<File> {
+        let path = self.dir.join("queue.lock");
+        let file = File::options()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("open queue lock: {}", path.display()))?;
+        file.lock()
+            .with_context(|| format!("lock queue: {}", path.display()))?;
+        Ok(file)
+    }
+
+    fn index(&self) -> PathBuf {
+        self.dir.join("queue.json")
+    }
+
+    fn read(&self) -> Result<Vec<QueuedRun>> {
+        let path = self.index();
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("parse queue: {}", path.display())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err).context(format!("read queue: {}", path.display())),
+        }
+    }
+
+    /// Writes go through a temporary file, as the session store's do,
+    /// so that readers, which don't take the lock, never see a partial queue.
+    fn write(&self, runs: &[QueuedRun]) -> Result<()> {
+        let path = self.index();
+        let content = serde_json::to_string_pretty(runs).context("serialize queue")?;
+        crate::replace_file(&path, &content)
+            .with_context(|| format!("replace queue: {}", path.display()))?;
+        Ok(())
+    }
+}
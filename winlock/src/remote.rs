@@ -0,0 +1,267 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Running backends on another machine over SSH, such as a build server
+//! with more cores than the machine anna runs on.
+//!
+//! A remote session keeps its workspace locally as usual, and mirrors it to the
+//! remote machine with rsync: before each backend run, the workspace is sent there,
+//! the backend and verification run there over SSH, and afterwards what they changed
+//! is brought back. Everything else anna does with the workspace, such as diffing,
+//! committing, and merging, keeps working on the local copy. Files git ignores,
+//! such as build output, stay on whichever side made them.
+
+use std::{
+    ffi::OsString,
+    fmt,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    str::FromStr,
+};
+
+use color_eyre::eyre::{bail, eyre, Context, Error, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::credentials;
+
+/// Where remote workspaces are kept, relative to the remote user's home directory.
+pub const REMOTE_ROOT: &str = ".anna/workspaces";
+
+/// A machine reachable over SSH, as `user@host`, `host`, or an alias from `~/.ssh/config`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Remote(String);
+
+impl Remote {
+    /// The SSH destination.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The directory mirroring `workspace` on the remote machine.
+    pub fn dir(&self, workspace: &Path) -> Result<PathBuf> {
+        let name = workspace
+            .file_name()
+            .ok_or_else(|| eyre!("workspace has no name: {}", workspace.display()))?;
+        Ok(Path::new(REMOTE_ROOT).join(name))
+    }
+
+    /// Send `workspace` to its mirror, creating it if needed and deleting files
+    /// that were deleted locally.
+    pub fn push(&self, workspace: &Path) -> Result<()> {
+        let dir = self.dir(workspace)?;
+        let mkdir = shell_words::join(["mkdir", "-p", &dir.to_string_lossy()]);
+        run(self.ssh(false).arg(mkdir)).context("create remote workspace")?;
+        let mut source = workspace.as_os_str().to_owned();
+        source.push("/");
+        run(rsync().arg(source).arg(self.path(&dir)))
+            .with_context(|| format!("send workspace to {self}"))
+    }
+
+    /// Bring changes made in the mirror of `workspace` back into it.
+    pub fn pull(&self, workspace: &Path) -> Result<()> {
+        let dir = self.dir(workspace)?;
+        let mut destination = workspace.as_os_str().to_owned();
+        destination.push("/");
+        run(rsync().arg(self.path(&dir)).arg(destination))
+            .with_context(|| format!("bring back workspace from {self}"))
+    }
+
+    /// The file [`send_env`](Self::send_env) writes the environment for a backend
+    /// run in the mirror of `workspace` to, next to the mirror.
+    pub fn env_file(&self, workspace: &Path) -> Result<PathBuf> {
+        let mut file = self.dir(workspace)?.into_os_string();
+        file.push(".env");
+        Ok(PathBuf::from(file))
+    }
+
+    /// Write `env` to the [environment file](Self::env_file) for `workspace`,
+    /// which only the remote user can read, for the [command line](Self::command_line)
+    /// to read and delete. It's sent over SSH's standard input, since it can hold
+    /// secrets, and command lines can be seen by anyone on either machine.
+    pub fn send_env(&self, workspace: &Path, env: &[(&str, OsString)]) -> Result<()> {
+        if env.is_empty() {
+            return Ok(());
+        }
+        let file = self.env_file(workspace)?;
+        let parent = file.parent().unwrap_or(Path::new("."));
+        let script = format!(
+            "umask 077 && mkdir -p {} && cat > {}",
+            shell_words::quote(&parent.to_string_lossy()),
+            shell_words::quote(&file.to_string_lossy())
+        );
+        let mut ssh = self.ssh(false);
+        ssh.arg(script);
+        run_with_input(&mut ssh, credentials::shell_script(env).as_bytes())
+            .with_context(|| format!("send the backend's environment to {self}"))
+    }
+
+    /// The command line running `command_line`, a program followed by its arguments,
+    /// in the mirror of `workspace`, allocating a terminal there if `terminal` is set.
+    /// If there's an `env`, it's read from what [`send_env`](Self::send_env) sent.
+    pub fn command_line(
+        &self,
+        workspace: &Path,
+        command_line: &[String],
+        env: &[(&str, OsString)],
+        terminal: bool,
+    ) -> Result<Vec<String>> {
+        let dir = self.dir(workspace)?;
+        let mut script = String::new();
+        if !env.is_empty() {
+            let file = self.env_file(workspace)?;
+            let file = shell_words::quote(&file.to_string_lossy()).into_owned();
+            script.push_str(&format!(". {file} && rm -f {file} && "));
+        }
+        script.push_str(&format!(
+            "cd {} && exec {}",
+            shell_words::quote(&dir.to_string_lossy()),
+            shell_words::join(command_line)
+        ));
+        Ok(vec![
+            String::from("ssh"),
+            String::from(if terminal { "-t" } else { "-T" }),
+            String::from("--"),
+            self.0.clone(),
+            script,
+        ])
+    }
+
+    /// A command running the shell command `command` in the mirror of `workspace`.
+    pub fn shell(&self, workspace: &Path, command: &str) -> Result<Command> {
+        let dir = self.dir(workspace)?;
+        let mut ssh = self.ssh(false);
+        ssh.arg(format!(
+            "cd {} && {command}",
+            shell_words::quote(&dir.to_string_lossy())
+        ));
+        Ok(ssh)
+    }
+
+    /// A command deleting the mirror of `workspace`.
+    pub fn remove_command(&self, workspace: &Path) -> Result<Command> {
+        let dir = self.dir(workspace)?;
+        let mut ssh = self.ssh(false);
+        ssh.arg(shell_words::join([
+            "rm",
+            "-rf",
+            "--",
+            &dir.to_string_lossy(),
+        ]));
+        Ok(ssh)
+    }
+
+    /// Delete the mirror of `workspace`.
+    pub fn remove(&self, workspace: &Path) -> Result<()> {
+        run(&mut self.remove_command(workspace)?)
+            .with_context(|| format!("delete remote workspace on {self}"))
+    }
+
+    fn ssh(&self, terminal: bool) -> Command {
+        let mut ssh = Command::new("ssh");
+        ssh.arg(if terminal { "-t" } else { "-T" })
+            .arg("--")
+            .arg(&self.0);
+        ssh
+    }
+
+    fn path(&self, dir: &Path) -> OsString {
+        let mut path = OsString::from(format!("{}:", self.0));
+        path.push(dir);
+        path.push("/");
+        path
+    }
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for Remote {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Anything else would be taken for an option, or split into several arguments.
+        if s.is_empty() || s.starts_with('-') || s.contains(|c: char| c.is_whitespace() || c == ':')
+        {
+            bail!("invalid SSH destination '{s}'; expected user@host or host");
+        }
+        Ok(Self(String::from(s)))
+    }
+}
+
+impl TryFrom<String> for Remote {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Remote> for String {
+    fn from(remote: Remote) -> Self {
+        remote.0
+    }
+}
+
+/// Files git ignores are left out both ways: they're usually build output,
+/// which is large and specific to the machine that built it. Excluded files
+/// are also kept from deletion, so neither side's build output is lost.
+fn rsync() -> Command {
+    let mut rsync = Command::new("rsync");
+    rsync.args([
+        "--archive",
+        "--delete",
+        "--compress",
+        "--filter=:- .gitignore",
+    ]);
+    rsync
+}
+
+/// Run `command`, writing `input` to its standard input.
+fn run_with_input(command: &mut Command, input: &[u8]) -> Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("run {program}; is it installed?"))?;
+    // Dropped once written, so that the command sees the end of its input.
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    stdin
+        .write_all(input)
+        .with_context(|| format!("write to {program}"))?;
+    drop(stdin);
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("wait for {program}"))?;
+    if !output.status.success() {
+        bail!(
+            "{program} failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("run {program}; is it installed?"))?;
+    if !output.status.success() {
+        bail!(
+            "{program} failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
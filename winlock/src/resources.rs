@@ -0,0 +1,76 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The CPU time and memory backends use while they run, for judging how many
+//! agents a machine can run at once.
+//!
+//! Backends are sampled as a tree of processes, since the work is often done by
+//! the tools they start rather than by the backend itself. Sampling is only
+//! supported on Linux, where it reads `/proc`; elsewhere nothing is recorded.
+
+use std::time::Duration;
+
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+/// How often a running backend's processes are sampled.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The resources a backend run used, recorded on the session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// When the backend started.
+    pub started_at: Timestamp,
+
+    /// How long the backend ran, restarts included, in milliseconds.
+    pub duration_ms: u64,
+
+    /// The CPU time, user and system, used by the backend and everything it ran,
+    /// in milliseconds.
+    pub cpu_ms: u64,
+
+    /// The most memory the backend and everything it ran had resident at once,
+    /// in bytes, as of the samples taken every [`SAMPLE_INTERVAL`].
+    pub peak_rss: u64,
+}
+
+impl ResourceUsage {
+    /// The average number of CPUs the backend kept busy while it ran.
+    pub fn cpus(&self) -> f64 {
+        match self.duration_ms {
+            0 => 0.0,
+            duration => self.cpu_ms as f64 / duration as f64,
+        }
+    }
+}
+
+/// Samples the processes this process started, on a background thread,
+/// until [finished](Self::finish).
+#[derive(Debug)]
+pub struct Sampler {
+    #[cfg(target_os = "linux")]
+    inner: linux::Sampler,
+}
+
+impl Sampler {
+    /// Start sampling, or `None` where that isn't supported.
+    pub fn start() -> Option<Self> {
+        #[cfg(target_os = "linux")]
+        return linux::Sampler::start().map(|inner| Self { inner });
+        #[cfg(not(target_os = "linux"))]
+        None
+    }
+
+    /// Stop sampling, returning the CPU time used and the peak resident memory
+    /// in bytes since sampling started.
+    pub fn finish(self) -> (Duration, u64) {
+        #[cfg(target_os = "linux")]
+        return self.inner.finish();
+        #[cfg(not(target_os = "linux"))]
+        unreachable!("samplers are only started on Linux")
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux;
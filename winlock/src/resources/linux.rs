@@ -0,0 +1,129 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::HashMap,
+    fs, process,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::SAMPLE_INTERVAL;
+
+#[derive(Debug)]
+pub struct Sampler {
+    stop: mpsc::Sender<()>,
+
+    thread: JoinHandle<Totals>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Totals {
+    cpu_ticks: u64,
+
+    peak_rss_pages: u64,
+}
+
+impl Sampler {
+    pub fn start() -> Option<Self> {
+        let baseline = stat(process::id())?.children_ticks;
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let mut totals = Totals::default();
+            loop {
+                let current = sample(baseline);
+                totals.cpu_ticks = totals.cpu_ticks.max(current.cpu_ticks);
+                totals.peak_rss_pages = totals.peak_rss_pages.max(current.peak_rss_pages);
+                match stopped.recv_timeout(SAMPLE_INTERVAL) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    // One last sample, now that the backend has been waited for
+                    // and its CPU time counts towards this process's children.
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => {
+                        let current = sample(baseline);
+                        totals.cpu_ticks = totals.cpu_ticks.max(current.cpu_ticks);
+                        return totals;
+                    }
+                }
+            }
+        });
+        Some(Self { stop, thread })
+    }
+
+    pub fn finish(self) -> (Duration, u64) {
+        let _ = self.stop.send(());
+        let totals = self.thread.join().unwrap_or_default();
+        // SAFETY: `sysconf` only reads configuration.
+        let (ticks_per_sec, page_size) = unsafe {
+            (
+                libc::sysconf(libc::_SC_CLK_TCK),
+                libc::sysconf(libc::_SC_PAGESIZE),
+            )
+        };
+        let ticks_per_sec = u64::try_from(ticks_per_sec).unwrap_or(100).max(1);
+        let page_size = u64::try_from(page_size).unwrap_or(4096);
+        let cpu = Duration::from_millis(totals.cpu_ticks * 1000 / ticks_per_sec);
+        (cpu, totals.peak_rss_pages * page_size)
+    }
+}
+
+/// The CPU time used so far by this process's descendants, and the memory
+/// they have resident now.
+///
+/// Descendants that exited count through their parents' `cutime`, which covers
+/// waited-for children; this process's own is counted from `baseline`,
+/// so that children it waited for before sampling started are left out.
+fn sample(baseline: u64) -> Totals {
+    let stats = fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter_map(|pid| Some((pid, stat(pid)?)))
+        .collect::<HashMap<_, _>>();
+
+    let own = process::id();
+    let mut totals = Totals {
+        cpu_ticks: stats
+            .get(&own)
+            .map_or(0, |stat| stat.children_ticks.saturating_sub(baseline)),
+        peak_rss_pages: 0,
+    };
+    let mut parents = vec![own];
+    while let Some(parent) = parents.pop() {
+        for (pid, stat) in &stats {
+            if stat.ppid == parent {
+                totals.cpu_ticks += stat.own_ticks + stat.children_ticks;
+                totals.peak_rss_pages += stat.rss_pages;
+                parents.push(*pid);
+            }
+        }
+    }
+    totals
+}
+
+struct Stat {
+    ppid: u32,
+
+    own_ticks: u64,
+
+    children_ticks: u64,
+
+    rss_pages: u64,
+}
+
+fn stat(pid: u32) -> Option<Stat> {
+    let content = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The command name is in parentheses and may itself contain spaces
+    // or parentheses, so fields are counted from the last closing one.
+    let (_, fields) = content.rsplit_once(')')?;
+    let fields = fields.split_whitespace().collect::<Vec<_>>();
+    let field = |index: usize| fields.get(index)?.parse::<u64>().ok();
+    Some(Stat {
+        ppid: u32::try_from(field(1)?).ok()?,
+        own_ticks: field(11)? + field(12)?,
+        children_ticks: field(13)? + field(14)?,
+        rss_pages: field(21)?,
+    })
+}
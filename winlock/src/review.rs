@@ -0,0 +1,62 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Reviews of sessions' changes by a backend, for a second opinion before merging them.
+//!
+//! The backend is run [in batch mode](crate::backend::run_batch) with the session's
+//! diff and the task it was given, and what it prints is written to [`REVIEW_FILE`]
+//! in the workspace, where the agent can be asked to address it.
+
+use std::{fs, path::PathBuf};
+
+use color_eyre::eyre::{bail, Context, Result};
+
+use crate::{
+    backend::{self, CommandTemplate, TemplateValues},
+    session::Session,
+};
+
+/// The file, in the workspace, that reviews are written to.
+/// It's kept out of version control, so that reviews aren't committed with the work.
+pub const REVIEW_FILE: &str = "REVIEW.md";
+
+/// What the backend is asked, followed by the task and the diff.
+const REVIEW_REQUEST: &str = "Review the following changes, made by a coding agent, \
+    before they're merged. Point out bugs, missing tests, and anything else worth fixing, \
+    most important first, citing the files and lines concerned. Reply in Markdown.";
+
+/// Review `session`'s changes by running `template` with a request to do so,
+/// writing the review to [`REVIEW_FILE`] in the workspace and returning its path.
+pub fn review(session: &Session, template: &CommandTemplate) -> Result<PathBuf> {
+    let diff = session.diff().context("render the session's changes")?;
+    if diff.trim().is_empty() {
+        bail!("session '{}' has no changes to review", session.branch);
+    }
+    let request = match &session.prompt {
+        Some(prompt) => {
+            format!("{REVIEW_REQUEST}\n\nThe agent's task:\n\n{prompt}\n\nThe changes:\n\n{diff}")
+        }
+        None => format!("{REVIEW_REQUEST}\n\nThe changes:\n\n{diff}"),
+    };
+    let values = TemplateValues {
+        branch: Some(session.branch.to_string()),
+        workspace: Some(session.workspace.clone()),
+        project: Some(session.project.clone()),
+        ..TemplateValues::default()
+    };
+    let review = backend::run_batch(template, values, &request, Some(&session.workspace))
+        .context("run reviewer")?;
+    if review.trim().is_empty() {
+        bail!("the reviewer printed nothing");
+    }
+
+    session
+        .vcs
+        .vcs()
+        .ignore(&session.workspace, REVIEW_FILE.as_ref())
+        .context("keep review out of version control")?;
+    let path = session.workspace.join(REVIEW_FILE);
+    fs::write(&path, review).with_context(|| format!("write review: {}", path.display()))?;
+    Ok(path)
+}
@@ -0,0 +1,2532 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Persistent records of agent sessions.
+//!
+//! Sessions are stored under `sessions/` in the [state directory](crate::config::state_dir),
+//! sharded by project: each project has a directory holding one directory per session,
+//! containing the session's record and any data recorded for it, and a lock file.
+//! Sharding by project means commands for one project never contend with
+//! commands for another, and a corrupted file only affects a single session.
+//!
+//! Whoever holds a lock records a [`LockOwner`] next to it, so that a process
+//! waiting for the lock can say what it's waiting for.
+//!
+//! While a backend runs in a session, a file in the session's data directory is
+//! locked too, so that only one backend runs in a workspace at a time. The lock is
+//! released when the process exits, even if it crashes, so it tells whether a backend
+//! is running better than any record could. On filesystems without locks, whether
+//! the process recorded as running the backend is alive tells instead.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    env, fmt,
+    fs::{self, File, TryLockError},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use color_eyre::eyre::{bail, Context, Result};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    branch::BranchName,
+    config::state_dir,
+    environment::Environment,
+    events::{Event, Events},
+    history::{self, HistoryEntry, HistoryEvent},
+    multiplexer::MultiplexerKind,
+    remote::Remote,
+    resources::ResourceUsage,
+    toolchain::Toolchain,
+    usage::ConversationUsage,
+    vcs::{UnsavedWork, VcsKind},
+    workspace::{self, RefreshReport},
+    AgentSessionStatus,
+};
+
+/// The name of an archived workspace, in its session's data directory.
+#[cfg(feature = "archive")]
+const ARCHIVE: &str = "workspace.tar.zst";
+
+/// The name of the file locked while a backend runs in a session,
+/// in its session's data directory.
+const RUNNING: &str = "running.lock";
+
+/// How many times taking [`RUNNING`] is tried again before a backend is taken to be
+/// running, since [checking](Sessions::is_running) whether one is takes it for a moment.
+const RUNNING_RETRIES: u32 = 10;
+
+/// How long to wait before trying to take [`RUNNING`] again.
+const RUNNING_RETRY_DELAY: Duration = Duration::from_millis(5);
+
+/// The name of the file describing the backend running in a session, next to [`RUNNING`].
+/// Kept apart from it since on Windows the lock is mandatory, and only meaningful
+/// while the lock is held, since it isn't cleared on release.
+const RUNNING_BACKEND: &str = "running.json";
+
+/// The name of the lock file in a session's data directory held while the session
+/// is created, so that only one process copies a workspace for its branch.
+const CREATING: &str = "creating.lock";
+
+/// How the name of a removed session's data directory starts once it's moved aside
+/// in its shard, where it stays until it's deleted.
+const REMOVED_PREFIX: &str = ".removed-";
+
+/// How many hex digits of a session's ID make up its [short ID](Session::short_id).
+pub const SHORT_ID_LEN: usize = 6;
+
+/// The record of an agent session: a branch of a project being worked on in a workspace.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Session {
+    /// Uniquely identifies the session, even among sessions of the same branch
+    /// created and removed over time.
+    ///
+    /// Sessions recorded before sessions had IDs are given one derived from
+    /// what identified them before, so that it's the same each time they're read.
+    #[serde(default, skip_serializing_if = "Uuid::is_nil")]
+    pub id: Uuid,
+
+    /// The original project directory.
+    pub project: PathBuf,
+
+    /// The branch the agent works on.
+    pub branch: BranchName,
+
+    /// The directory in which the agent works.
+    pub workspace: PathBuf,
+
+    /// The version control system used in the workspace.
+    pub vcs: VcsKind,
+
+    /// The revision the branch was created from,
+    /// or `None` if the workspace isn't under version control.
+    pub base: Option<String>,
+
+    /// When the session was created.
+    pub created_at: Timestamp,
+
+    /// When the workspace was last [refreshed](Sessions::refresh) from the project, if ever.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refreshed_at: Option<Timestamp>,
+
+    /// Whether the workspace was created outside of anna and adopted.
+    /// Adopted workspaces belong to the user, so they are never deleted.
+    #[serde(default)]
+    pub adopted: bool,
+
+    /// Whether the workspace has been [archived](Sessions::archive): compressed into
+    /// the session's data directory and deleted, until the session is resumed.
+    #[serde(default)]
+    pub archived: bool,
+
+    /// The conversation each backend program had in this session,
+    /// for the `{resume_token}` placeholder.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub resume_tokens: BTreeMap<String, String>,
+
+    /// The prompt the backend was first given in this session, describing its task.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+
+    /// The model last selected for the session's backend runs, which later runs keep using.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
+    /// The web page of the issue the session was started from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issue: Option<String>,
+
+    /// The result of the most recent verification of the workspace, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verification: Option<Verification>,
+
+    /// The environment the session was created in;
+    /// `None` for sessions created before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<Environment>,
+
+    /// The tokens used by each of the session's backend conversations, by resume token,
+    /// for backends whose profile says where to [read](crate::config::Profile::usage) them.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub usage: BTreeMap<String, ConversationUsage>,
+
+    /// Workspaces of other projects the session spans, each with the branch too.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub linked: Vec<LinkedWorkspace>,
+
+    /// Every prompt given to the session's backends, oldest first;
+    /// see [`prompt_history`](Self::prompt_history).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prompts: Vec<PromptRecord>,
+
+    /// The CPU time and memory used by each run of a backend in the session, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub resources: Vec<ResourceUsage>,
+
+    /// The machine the backend runs on, if not this one; the workspace is
+    /// [mirrored](crate::remote) there.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<Remote>,
+
+    /// The group of related sessions of the project this one belongs to, if any,
+    /// such as the sessions of agents working on parts of one larger change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+
+    /// The [stable ID](project_id) of the project when the session was created,
+    /// by which it can be matched to the project after the project is moved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_id: Option<String>,
+
+    /// Every run of a backend in the session, oldest first, with how it exited;
+    /// each stage of a pipeline of backends is a run of its own.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub runs: Vec<RunRecord>,
+
+    /// The toolchains whose state is kept in the workspace, through the
+    /// environment of what runs there.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub toolchains: Vec<Toolchain>,
+}
+
+/// A run of a backend in a session.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// The backend's program.
+    pub backend: String,
+
+    /// When the backend started.
+    pub started_at: Timestamp,
+
+    /// The backend's exit code, or `None` if it was killed by a signal.
+    pub code: Option<i32>,
+
+    /// Whether the backend succeeded; after restarts, whether its last run did.
+    pub success: bool,
+}
+
+/// A prompt given to a session's backend.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PromptRecord {
+    /// The prompt itself.
+    pub prompt: String,
+
+    /// When the backend given the prompt started.
+    pub given_at: Timestamp,
+}
+
+/// The workspace of another project that a session spans, for changes across repositories.
+///
+/// The backend runs in the session's own workspace, and finds linked ones
+/// in [`LINKED_WORKSPACES_VARIABLE`](crate::LINKED_WORKSPACES_VARIABLE).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LinkedWorkspace {
+    /// The original project directory.
+    pub project: PathBuf,
+
+    /// The directory in which the agent works on the project.
+    pub workspace: PathBuf,
+
+    /// The version control system used in the workspace.
+    pub vcs: VcsKind,
+
+    /// The revision the branch was created from,
+    /// or `None` if the workspace isn't under version control.
+    pub base: Option<String>,
+}
+
+/// The result of running a verification command, such as a test suite, in a workspace.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Verification {
+    /// The command that was run.
+    pub command: String,
+
+    /// Whether the command succeeded.
+    pub passed: bool,
+
+    /// When the command finished.
+    pub finished_at: Timestamp,
+}
+
+impl Session {
+    /// Render the changes made in the workspace since the branch was created,
+    /// followed by those in each linked workspace under a line naming its project.
+    pub fn diff(&self) -> Result<String> {
+        let Some(base) = &self.base else {
+            bail!("workspace is not under version control");
+        };
+        let diff = self.vcs.vcs().diff(&self.workspace, base)?;
+        self.with_linked(diff, |linked, base| {
+            linked.vcs.vcs().diff(&linked.workspace, base)
+        })
+    }
+
+    /// Summarize the changes committed to the branch since it was created,
+    /// with a line for each changed file, followed by the same for each linked
+    /// workspace under a line naming its project.
+    pub fn diff_stat(&self) -> Result<String> {
+        let Some(base) = &self.base else {
+            bail!("workspace is not under version control");
+        };
+        let stat = self
+            .vcs
+            .vcs()
+            .diff_stat(&self.workspace, base, &self.branch)?;
+        self.with_linked(stat, |linked, base| {
+            linked
+                .vcs
+                .vcs()
+                .diff_stat(&linked.workspace, base, &self.branch)
+        })
+    }
+
+    /// Append what `render` gives for each linked workspace under version control
+    /// to `own`, under a line naming its project, skipping those with nothing to show.
+    fn with_linked(
+        &self,
+        mut own: String,
+        render: impl Fn(&LinkedWorkspace, &str) -> Result<String>,
+    ) -> Result<String> {
+        for linked in &self.linked {
+            let Some(base) = &linked.base else {
+                continue;
+            };
+            let rendered = render(linked, base)
+                .with_context(|| format!("linked workspace: {}", linked.workspace.display()))?;
+            if rendered.is_empty() {
+                continue;
+            }
+            if !own.is_empty() {
+                own.push_str("\n\n");
+            }
+            own.push_str(&format!(
+                "# Linked project {}\n{rendered}",
+                linked.project.display()
+            ));
+        }
+        Ok(own)
+    }
+
+    /// How the branch and the project's default branch have diverged: the commits on
+    /// the branch the default branch doesn't have, and those it gained since the branch
+    /// was created. `None` if the workspace isn't under version control or is gone.
+    ///
+    /// Commits the project has under any reference, such as once merged back or
+    /// fetched, are compared exactly; otherwise every commit since the base is unlanded.
+    pub fn divergence(&self) -> Result<Option<Divergence>> {
+        let Some(base) = &self.base else {
+            return Ok(None);
+        };
+        if !self.workspace.is_dir() {
+            return Ok(None);
+        }
+        let vcs = self.vcs.vcs();
+        let target = vcs.default_branch(&self.project)?;
+        let target = vcs.resolve(&self.project, &target)?;
+        let head = vcs.resolve(&self.workspace, self.branch.as_str())?;
+        let ahead = match vcs.count_commits(&self.project, &target, &head) {
+            Ok(ahead) => ahead,
+            // The project doesn't have the branch's commits.
+            Err(_) => vcs.count_commits(&self.workspace, base, &head)?,
+        };
+        let behind = vcs.count_commits(&self.project, base, &target)?;
+        Ok(Some(Divergence { ahead, behind }))
+    }
+
+    /// When anything last happened in the session that anna recorded: it was created,
+    /// refreshed, or verified, or a backend started in it.
+    pub fn last_active(&self) -> Timestamp {
+        let runs = self.runs.iter().map(|run| run.started_at);
+        let prompts = self.prompts.iter().map(|prompt| prompt.given_at);
+        let verified = self
+            .verification
+            .iter()
+            .map(|verification| verification.finished_at);
+        [self.created_at]
+            .into_iter()
+            .chain(self.refreshed_at)
+            .chain(runs)
+            .chain(prompts)
+            .chain(verified)
+            .max()
+            .expect("there's always the creation time")
+    }
+
+    /// The first few hex digits of the session's ID, such as `a1b2c3`: usually enough
+    /// to tell it apart from other sessions, of any project, with the same branch name.
+    /// Where it isn't, [`Sessions::short_ids`] has as many more as that takes.
+    pub fn short_id(&self) -> String {
+        let mut id = self.id.simple().to_string();
+        id.truncate(SHORT_ID_LEN);
+        id
+    }
+
+    /// The state of the workspace, as in [`SessionDetails::state`] without gathering the rest.
+    pub fn state(&self) -> WorkspaceState {
+        WorkspaceState::of(self, self.workspace.is_dir())
+    }
+
+    /// Gather everything known about the session, for display.
+    ///
+    /// Details which can't be determined, such as the changes in a workspace
+    /// that no longer exists, are left out rather than failing.
+    pub fn details(&self) -> SessionDetails {
+        let workspace_exists = self.workspace.is_dir();
+        SessionDetails {
+            session: self.clone(),
+            workspace_exists,
+            diff_stat: workspace_exists.then(|| self.diff_stat().ok()).flatten(),
+            disk_usage: workspace_exists
+                .then(|| workspace::disk_usage(&self.workspace).ok())
+                .flatten(),
+            divergence: self.divergence().ok().flatten(),
+        }
+    }
+
+    /// Make the branch track the branch of the same name on the workspace's remote
+    /// named `remote`, such as `origin`, so that plain `git push` and `git pull` in the
+    /// workspace go there.
+    pub fn set_upstream(&self, remote: &str) -> Result<()> {
+        self.vcs
+            .vcs()
+            .set_upstream(&self.workspace, remote, &self.branch)
+            .with_context(|| format!("make '{}' track {remote}/{}", self.branch, self.branch))
+    }
+
+    /// Bring the branch back into the project, and into each linked project,
+    /// without changing what is checked out there.
+    pub fn merge_back(&self) -> Result<()> {
+        self.vcs
+            .vcs()
+            .merge_back(&self.project, &self.workspace, &self.branch)
+            .with_context(|| format!("merge '{}' back into project", self.branch))?;
+        for linked in &self.linked {
+            linked
+                .vcs
+                .vcs()
+                .merge_back(&linked.project, &linked.workspace, &self.branch)
+                .with_context(|| {
+                    format!(
+                        "merge '{}' back into linked project: {}",
+                        self.branch,
+                        linked.project.display()
+                    )
+                })?;
+        }
+        Ok(())
+    }
+
+    /// The reference the branch is [fetched](Self::fetch_into_project) into in the project.
+    pub fn fetched_ref(&self) -> String {
+        format!("refs/anna/{}", self.branch)
+    }
+
+    /// Fetch the branch into the project, and into each linked project, as
+    /// [`fetched_ref`](Self::fetched_ref), so that its commits can be inspected there
+    /// without merging them or changing what is checked out.
+    pub fn fetch_into_project(&self) -> Result<()> {
+        let reference = self.fetched_ref();
+        self.vcs
+            .vcs()
+            .fetch_into(&self.project, &self.workspace, &self.branch, &reference)
+            .with_context(|| format!("fetch '{}' into project", self.branch))?;
+        for linked in &self.linked {
+            linked
+                .vcs
+                .vcs()
+                .fetch_into(&linked.project, &linked.workspace, &self.branch, &reference)
+                .with_context(|| {
+                    format!(
+                        "fetch '{}' into linked project: {}",
+                        self.branch,
+                        linked.project.display()
+                    )
+                })?;
+        }
+        Ok(())
+    }
+
+    /// What deleting the session's workspaces would lose, for each workspace
+    /// with anything to lose: uncommitted changes, and commits the project lacks.
+    ///
+    /// Workspaces that aren't under version control, or no longer exist, are skipped,
+    /// since there's nothing to compare them with.
+    pub fn unsaved_work(&self) -> Result<Vec<(PathBuf, UnsavedWork)>> {
+        let own = (&self.project, &self.workspace, self.vcs, &self.base);
+        let linked = self
+            .linked
+            .iter()
+            .map(|linked| (&linked.project, &linked.workspace, linked.vcs, &linked.base));
+        let mut unsaved = Vec::new();
+        for (project, workspace, vcs, base) in [own].into_iter().chain(linked) {
+            let Some(base) = base else {
+                continue;
+            };
+            if vcs == VcsKind::None || !workspace.is_dir() {
+                continue;
+            }
+            let work = vcs
+                .vcs()
+                .unsaved_work(workspace, project, &self.branch, base)
+                .with_context(|| format!("check for unsaved work in {}", workspace.display()))?;
+            if !work.is_empty() {
+                unsaved.push((workspace.clone(), work));
+            }
+        }
+        Ok(unsaved)
+    }
+
+    /// Every prompt given to the session, oldest first.
+    ///
+    /// Sessions from before every prompt was recorded only have their first;
+    /// it's dated to when the session was created.
+    pub fn prompt_history(&self) -> Vec<PromptRecord> {
+        match (&self.prompt, self.prompts.is_empty()) {
+            (Some(prompt), true) => vec![PromptRecord {
+                prompt: prompt.clone(),
+                given_at: self.created_at,
+            }],
+            _ => self.prompts.clone(),
+        }
+    }
+
+    /// Every workspace of the session: its own, then the linked ones.
+    pub fn workspaces(&self) -> impl Iterator<Item = &Path> {
+        [self.workspace.as_path()]
+            .into_iter()
+            .chain(self.linked.iter().map(|linked| linked.workspace.as_path()))
+    }
+
+    /// The marker identifying the session's workspaces as belonging to it.
+    pub fn marker(&self) -> WorkspaceMarker {
+        WorkspaceMarker {
+            project: self.project.clone(),
+            branch: self.branch.clone(),
+            id: self.id,
+        }
+    }
+
+    /// Give a session recorded before sessions had IDs the one it's known by.
+    fn with_id(mut self) -> Self {
+        if self.id.is_nil() {
+            let digest = Sha256::new()
+                .chain_update(self.project.to_string_lossy().as_bytes())
+                .chain_update([0])
+                .chain_update(self.branch.as_str())
+                .chain_update([0])
+                .chain_update(self.created_at.to_string())
+                .finalize();
+            let bytes = digest[..16]
+                .try_into()
+                .expect("SHA-256 digests are 32 bytes");
+            self.id = uuid::Builder::from_random_bytes(bytes).into_uuid();
+        }
+        self
+    }
+}
+
+/// The name of the file at the root of each workspace anna creates, recording the
+/// session it belongs to as a [`WorkspaceMarker`].
+///
+/// It identifies the session to tools and to people looking around the temporary
+/// directory, and lets `anna session clean` find workspaces no session tracks.
+/// It's kept out of version control in the workspace.
+pub const MARKER: &str = ".anna-session";
+
+/// What a workspace's [`MARKER`] records about the session it belongs to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorkspaceMarker {
+    /// The session's project.
+    pub project: PathBuf,
+
+    /// The session's branch.
+    pub branch: BranchName,
+
+    /// The session's [ID](Session::id).
+    pub id: Uuid,
+}
+
+impl WorkspaceMarker {
+    /// Read the marker in `workspace`, if it has one.
+    pub fn read(workspace: &Path) -> Result<Option<Self>> {
+        let path = workspace.join(MARKER);
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map(Some)
+                .with_context(|| format!("parse workspace marker: {}", path.display())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).context(format!("read workspace marker: {}", path.display())),
+        }
+    }
+
+    /// Write the marker into `workspace`, keeping it out of version control there.
+    pub fn write(&self, workspace: &Path, vcs: VcsKind) -> Result<()> {
+        vcs.vcs()
+            .ignore(workspace, Path::new(MARKER))
+            .context("keep workspace marker out of version control")?;
+        let path = workspace.join(MARKER);
+        let content = serde_json::to_string_pretty(self).context("serialize workspace marker")?;
+        fs::write(&path, content)
+            .with_context(|| format!("write workspace marker: {}", path.display()))
+    }
+}
+
+/// The state of a session's workspace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkspaceState {
+    /// The workspace passed its most recent verification.
+    Verified,
+
+    /// The workspace failed its most recent verification.
+    Failing,
+
+    /// The workspace hasn't been verified.
+    Unverified,
+
+    /// The workspace has been [archived](Sessions::archive).
+    Archived,
+
+    /// The workspace no longer exists, though it hasn't been archived.
+    Missing,
+}
+
+impl WorkspaceState {
+    /// Every state, in order.
+    pub const ALL: [Self; 5] = [
+        Self::Verified,
+        Self::Failing,
+        Self::Unverified,
+        Self::Archived,
+        Self::Missing,
+    ];
+
+    fn of(session: &Session, workspace_exists: bool) -> Self {
+        if session.archived {
+            return Self::Archived;
+        }
+        match (workspace_exists, &session.verification) {
+            (false, _) => Self::Missing,
+            (true, Some(verification)) if verification.passed => Self::Verified,
+            (true, Some(_)) => Self::Failing,
+            (true, None) => Self::Unverified,
+        }
+    }
+}
+
+impl fmt::Display for WorkspaceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Verified => "verified",
+            Self::Failing => "failing",
+            Self::Unverified => "unverified",
+            Self::Archived => "archived",
+            Self::Missing => "missing",
+        })
+    }
+}
+
+/// Everything known about a session, as gathered by [`Session::details`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SessionDetails {
+    /// The session's record.
+    #[serde(flatten)]
+    pub session: Session,
+
+    /// Whether the workspace still exists.
+    pub workspace_exists: bool,
+
+    /// A summary of the changes committed to the branch, as from [`Session::diff_stat`].
+    pub diff_stat: Option<String>,
+
+    /// The space the workspace takes up on disk, in bytes,
+    /// including files the copy left out such as build artifacts.
+    pub disk_usage: Option<u64>,
+
+    /// How the branch and the project's default branch have diverged, as from
+    /// [`Session::divergence`].
+    pub divergence: Option<Divergence>,
+}
+
+/// How a session's branch and the project's default branch have diverged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Divergence {
+    /// Commits on the branch that the default branch doesn't have: its unlanded work.
+    pub ahead: u64,
+
+    /// Commits on the default branch since the branch was created.
+    pub behind: u64,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "+{}/-{}", self.ahead, self.behind)
+    }
+}
+
+impl SessionDetails {
+    /// The state of the session's workspace.
+    pub fn state(&self) -> WorkspaceState {
+        WorkspaceState::of(&self.session, self.workspace_exists)
+    }
+}
+
+/// The files of a session removed by [`Sessions::remove_deferred`], yet to be deleted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[must_use = "a removed session's files are only deleted by finishing or spawning its removal"]
+pub struct Removal {
+    dirs: Vec<PathBuf>,
+
+    remote: Option<(Remote, PathBuf)>,
+}
+
+impl Removal {
+    /// The directories to be deleted.
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    /// Delete the files, waiting until they're gone.
+    pub fn finish(self) -> Result<()> {
+        for dir in &self.dirs {
+            workspace::delete_dir(dir)?;
+        }
+        if let Some((remote, workspace)) = &self.remote {
+            remote.remove(workspace)?;
+        }
+        Ok(())
+    }
+
+    /// Delete the files in a background process, which carries on after this one exits.
+    pub fn spawn(self) -> Result<()> {
+        let local = (!self.dirs.is_empty()).then(|| delete_command(&self.dirs));
+        let remote = self
+            .remote
+            .map(|(remote, workspace)| remote.remove_command(&workspace))
+            .transpose()?;
+        for mut command in local.into_iter().chain(remote) {
+            command
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null());
+            // In its own process group, so that interrupting this process doesn't stop it.
+            #[cfg(unix)]
+            std::os::unix::process::CommandExt::process_group(&mut command, 0);
+            command
+                .spawn()
+                .context("start deleting in the background")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn delete_command(dirs: &[PathBuf]) -> Command {
+    let mut command = Command::new("rm");
+    command.arg("-rf").arg("--").args(dirs);
+    command
+}
+
+#[cfg(windows)]
+fn delete_command(dirs: &[PathBuf]) -> Command {
+    use std::os::windows::process::CommandExt;
+
+    // cmd doesn't parse its arguments the way other programs do, so the command
+    // line is passed as is; paths can't contain quotes on Windows.
+    let script = dirs
+        .iter()
+        .map(|dir| format!("rmdir /S /Q \"{}\"", dir.display()))
+        .collect::<Vec<_>>()
+        .join(" & ");
+    let mut command = Command::new("cmd");
+    command.raw_arg(format!("/C {script}"));
+    command
+}
+
+/// What's recorded about a group of sessions, from [`Sessions::groups`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupInfo {
+    /// What the group's sessions are working towards.
+    pub description: Option<String>,
+
+    /// When the group was first recorded.
+    pub created_at: Timestamp,
+}
+
+/// An overview of every session in the store, as gathered by [`Sessions::stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct StoreStats {
+    /// The overview of each project's sessions.
+    pub projects: BTreeMap<PathBuf, ProjectStats>,
+
+    /// The number of sessions.
+    pub sessions: usize,
+
+    /// The number of sessions whose backend is running.
+    pub running: usize,
+
+    /// The space the sessions' workspaces and data take up on disk, in bytes.
+    pub disk_usage: u64,
+
+    /// When the oldest session was created.
+    pub oldest: Option<Timestamp>,
+}
+
+/// An overview of one project's sessions.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ProjectStats {
+    /// The number of sessions whose workspace is in each state;
+    /// states no session is in are left out.
+    pub states: BTreeMap<WorkspaceState, usize>,
+
+    /// The branches of sessions whose backend is running.
+    pub running: Vec<BranchName>,
+
+    /// The space the sessions' workspaces and data take up on disk, in bytes.
+    pub disk_usage: u64,
+
+    /// When the oldest session was created.
+    pub oldest: Option<Timestamp>,
+}
+
+impl ProjectStats {
+    /// The number of sessions.
+    pub fn sessions(&self) -> usize {
+        self.states.values().sum()
+    }
+}
+
+/// The store of agent sessions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sessions {
+    root: PathBuf,
+
+    read_only: bool,
+
+    events: Events,
+}
+
+impl Sessions {
+    /// Open the session store in the [state directory](crate::config::state_dir).
+    pub fn open() -> Result<Self> {
+        Self::open_in(&state_dir()?)
+    }
+
+    /// Open the session store in the provided directory.
+    pub fn open_in(dir: &Path) -> Result<Self> {
+        let root = dir.join("sessions");
+        fs::create_dir_all(&root)
+            .with_context(|| format!("create sessions directory: {}", root.display()))?;
+        let sessions = Self {
+            root,
+            read_only: false,
+            events: Events::default(),
+        };
+        sessions.sweep_removed();
+        Ok(sessions)
+    }
+
+    /// Delete what removals left behind, such as when they were interrupted.
+    /// Best effort: anything left is tried again the next time the store is opened.
+    fn sweep_removed(&self) {
+        for shard in self.shards().unwrap_or_default() {
+            let Ok(entries) = fs::read_dir(&shard.dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if is_removed(&entry.path()) {
+                    let _ = workspace::delete_dir(&entry.path());
+                }
+            }
+        }
+    }
+
+    /// Open the session store in the [state directory](crate::config::state_dir)
+    /// [read-only](Self::open_read_only_in).
+    pub fn open_read_only() -> Result<Self> {
+        Ok(Self::open_read_only_in(&state_dir()?))
+    }
+
+    /// Open the session store in the provided directory for reading only,
+    /// such as for tools that poll it to show what sessions are up to.
+    ///
+    /// Reading never waits on, or holds up, anything changing the store,
+    /// since no lock is taken; sessions stored by earlier versions are read as
+    /// they are rather than converted. Changing the store fails.
+    pub fn open_read_only_in(dir: &Path) -> Self {
+        Self {
+            root: dir.join("sessions"),
+            read_only: true,
+            events: Events::default(),
+        }
+    }
+
+    /// Report to `events` what the store waits for, such as another process
+    /// holding a lock, and sessions it skips because they can't be read.
+    pub fn with_events(mut self, events: Events) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Whether the store was opened [read-only](Self::open_read_only_in).
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Fail if the store was opened read-only, before anything is changed.
+    fn ensure_writable(&self) -> Result<()> {
+        if self.read_only {
+            bail!(
+                "the session store was opened read-only: {}",
+                self.dir().display()
+            );
+        }
+        Ok(())
+    }
+
+    /// The directory the store was opened in.
+    pub fn dir(&self) -> &Path {
+        self.root
+            .parent()
+            .expect("the sessions directory is inside the store directory")
+    }
+
+    /// Get the session for `branch` in `project`, if one exists.
+    pub fn get(&self, project: &Path, branch: &BranchName) -> Result<Option<Session>> {
+        let shard = self.shard(project);
+        shard.migrate()?;
+        shard.read(branch)
+    }
+
+    /// Get the session for `branch` in `project`, if one exists, [held
+    /// idle](Self::hold_idle) until the returned file is dropped.
+    pub(crate) fn get_idle(
+        &self,
+        project: &Path,
+        branch: &BranchName,
+    ) -> Result<Option<(Session, File)>> {
+        let shard = self.shard(project);
+        shard.migrate()?;
+        self.read_idle(&shard, project, branch)
+    }
+
+    /// Get the session for `branch` in `project`, or store the one returned by `create`.
+    ///
+    /// Creating a session is slow, so the sessions lock isn't held while `create` runs.
+    /// Instead the branch is claimed first: another process creating a session for
+    /// it waits for this one, then resumes its session rather than creating one too.
+    /// If a session for the branch is stored some other way in the meantime, such as
+    /// by [adopting](Self::adopt) a workspace, that session wins, and the workspace
+    /// of the one just created is deleted unless it was adopted.
+    pub fn get_or_create(
+        &self,
+        project: &Path,
+        branch: &BranchName,
+        create: impl FnOnce() -> Result<Session>,
+    ) -> Result<(Session, AgentSessionStatus)> {
+        if let Some(existing) = self.get(project, branch)? {
+            return Ok((existing, AgentSessionStatus::Resumed));
+        }
+
+        self.ensure_writable()?;
+        let _claim = self.claim(project, branch)?;
+        if let Some(existing) = self.get(project, branch)? {
+            return Ok((existing, AgentSessionStatus::Resumed));
+        }
+        let created = create()?;
+        if created.project != project || &created.branch != branch {
+            bail!(
+                "created session for '{}' in {} doesn't match '{branch}' in {}",
+                created.branch,
+                created.project.display(),
+                project.display()
+            );
+        }
+
+        let shard = self.shard(project);
+        let _lock = shard.lock()?;
+        if let Some(existing) = shard.read(branch)? {
+            if !created.adopted {
+                remove_dir(&created.workspace).context("remove redundant workspace")?;
+            }
+            return Ok((existing, AgentSessionStatus::Resumed));
+        }
+        shard.write(&created)?;
+        let workspace = created.workspace.clone();
+        self.record(&created, HistoryEvent::Created { workspace });
+        Ok((created, AgentSessionStatus::Created))
+    }
+
+    /// Claim creating the session for `branch` in `project`, waiting for whoever has.
+    /// The claim lasts until the returned file is closed, or its process exits.
+    ///
+    /// Where files can't be locked, nothing is claimed.
+    fn claim(&self, project: &Path, branch: &BranchName) -> Result<Option<File>> {
+        let dir = self.data_dir(project, branch);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("create session directory: {}", dir.display()))?;
+        let path = dir.join(CREATING);
+        let file = File::options()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("open {}", path.display()))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                self.events.emit(&Event::Waiting {
+                    on: format!("another process to create the session for '{branch}'"),
+                });
+                file.lock()
+                    .with_context(|| format!("lock {}", path.display()))?;
+            }
+            Err(TryLockError::Error(err)) if err.kind() == ErrorKind::Unsupported => {
+                return Ok(None)
+            }
+            Err(TryLockError::Error(err)) => {
+                return Err(err).context(format!("lock {}", path.display()))
+            }
+        }
+        Ok(Some(file))
+    }
+
+    /// Store the session, replacing any existing session for the same project and branch.
+    pub fn store(&self, session: &Session) -> Result<()> {
+        let shard = self.shard(&session.project);
+        let _lock = shard.lock()?;
+        shard.write(session)
+    }
+
+    /// Register an existing checkout of `branch` in `workspace` as a session of `project`.
+    pub fn adopt(&self, project: &Path, branch: &BranchName, workspace: &Path) -> Result<Session> {
+        let workspace = fs::canonicalize(workspace)
+            .with_context(|| format!("canonicalize workspace: {}", workspace.display()))?;
+        if !workspace.is_dir() {
+            bail!("workspace is not a directory: {}", workspace.display());
+        }
+
+        let kind = VcsKind::detect(&workspace);
+        let vcs = kind.vcs();
+        if !vcs.branch_exists(&workspace, branch)? {
+            bail!(
+                "branch '{branch}' does not exist in {}",
+                workspace.display()
+            );
+        }
+
+        // The project's current revision is the best available guess for where
+        // the work started, since the branch was created outside of anna.
+        let base = match kind {
+            VcsKind::None => None,
+            _ => vcs.head(project).ok(),
+        };
+
+        let session = Session {
+            id: Uuid::new_v4(),
+            project: project.to_path_buf(),
+            branch: branch.clone(),
+            workspace,
+            vcs: kind,
+            base,
+            created_at: Timestamp::now(),
+            refreshed_at: None,
+            adopted: true,
+            archived: false,
+            resume_tokens: BTreeMap::new(),
+            prompt: None,
+            model: None,
+            issue: None,
+            verification: None,
+            environment: Some(Environment::capture()),
+            usage: BTreeMap::new(),
+            linked: Vec::new(),
+            prompts: Vec::new(),
+            resources: Vec::new(),
+            remote: None,
+            group: None,
+            project_id: project_id(project, VcsKind::detect(project))?,
+            runs: Vec::new(),
+            toolchains: Vec::new(),
+        };
+
+        let shard = self.shard(project);
+        let _lock = shard.lock()?;
+        if let Some(existing) = shard.read(branch)? {
+            bail!(
+                "a session for '{branch}' already exists: {}",
+                existing.workspace.display()
+            );
+        }
+        shard.write(&session)?;
+        let workspace = session.workspace.clone();
+        self.record(&session, HistoryEvent::Adopted { workspace });
+        Ok(session)
+    }
+
+    /// Modify the stored session for `branch` in `project`, returning the updated session.
+    /// Returns `None` without calling `update` if there is no such session.
+    pub fn update(
+        &self,
+        project: &Path,
+        branch: &BranchName,
+        update: impl FnOnce(&mut Session),
+    ) -> Result<Option<Session>> {
+        let shard = self.shard(project);
+        let _lock = shard.lock()?;
+        let Some(mut session) = shard.read(branch)? else {
+            return Ok(None);
+        };
+        update(&mut session);
+        shard.write(&session)?;
+        Ok(Some(session))
+    }
+
+    /// Append `event` to the [history](crate::history) of `session`'s branch.
+    ///
+    /// Best effort: the history describes what happened, so failing to write
+    /// it must not undo, or stop, what it describes.
+    pub fn record(&self, session: &Session, event: HistoryEvent) {
+        let entry = HistoryEntry {
+            at: Timestamp::now(),
+            session: session.id,
+            event,
+        };
+        if self.read_only {
+            return;
+        }
+        let path = self.shard(&session.project).history_path(&session.branch);
+        let _ = history::append(&path, &entry);
+    }
+
+    /// The [history](crate::history) of `branch` in `project`, oldest first, covering
+    /// every session of the branch, including removed ones; empty if there is none.
+    pub fn history(&self, project: &Path, branch: &BranchName) -> Result<Vec<HistoryEntry>> {
+        history::read(&self.shard(project).history_path(branch))
+    }
+
+    /// Bring files that are new or changed in `project` into the workspace of its session
+    /// for `branch`, and likewise for each linked project and its linked workspace,
+    /// keeping the workspaces' own changes; see [`workspace::refresh_workspace`].
+    /// `exclude` gives the patterns withheld from each project's workspace.
+    ///
+    /// Returns what was refreshed in each workspace, the session's own first,
+    /// or `None` if there is no such session.
+    pub fn refresh(
+        &self,
+        project: &Path,
+        branch: &BranchName,
+        exclude: impl Fn(&Path) -> Vec<String>,
+    ) -> Result<Option<Vec<(PathBuf, RefreshReport)>>> {
+        self.ensure_writable()?;
+        let Some(session) = self.get(project, branch)? else {
+            return Ok(None);
+        };
+        if session.archived {
+            bail!("session '{branch}' is archived; resume it first");
+        }
+        // Taken before copying, so that anything the agent changes meanwhile
+        // still counts as its own change next time.
+        let now = Timestamp::now();
+        let since = SystemTime::from(session.refreshed_at.unwrap_or(session.created_at));
+        let own = (&session.project, &session.workspace);
+        let linked = session
+            .linked
+            .iter()
+            .map(|linked| (&linked.project, &linked.workspace));
+        let reports = [own]
+            .into_iter()
+            .chain(linked)
+            .map(|(project, workspace)| {
+                let report =
+                    workspace::refresh_workspace(project, workspace, &exclude(project), since)?;
+                Ok((workspace.clone(), report))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.update(project, branch, |session| session.refreshed_at = Some(now))?;
+        Ok(Some(reports))
+    }
+
+    /// Compress the workspace of the session for `branch` in `project` into the session's
+    /// data directory, then delete it, returning the archive's path. Returns `None` if
+    /// there is no such session.
+    ///
+    /// Everything in the workspace is kept, build artifacts included, so that
+    /// [unarchiving](Self::unarchive) it gives the agent back exactly what it left.
+    /// The session is [held idle](Self::hold_idle) throughout, so nothing can resume it
+    /// in between.
+    ///
+    /// Fails, keeping the workspace, if a backend is running in it.
+    #[cfg(feature = "archive")]
+    pub fn archive(&self, project: &Path, branch: &BranchName) -> Result<Option<PathBuf>> {
+        self.ensure_writable()?;
+        let shard = self.shard(project);
+        let Some((session, _idle)) = self.read_idle(&shard, project, branch)? else {
+            return Ok(None);
+        };
+        if session.adopted {
+            bail!("adopted workspaces belong to you, so they can't be archived");
+        }
+        if session.archived {
+            bail!("session '{branch}' is already archived");
+        }
+        if !session.linked.is_empty() {
+            bail!("sessions with linked workspaces can't be archived");
+        }
+
+        let path = self.archive_path(project, branch);
+        let dir = path.parent().expect("archives are in the data directory");
+        fs::create_dir_all(dir)
+            .with_context(|| format!("create session data directory: {}", dir.display()))?;
+        // Written under a temporary name, so that a failure never leaves
+        // what looks like a complete archive.
+        let file = tempfile::NamedTempFile::new_in(dir).context("create archive")?;
+        let encoder = zstd::Encoder::new(file, 0).context("start compressing workspace")?;
+        let mut builder = tar::Builder::new(encoder);
+        builder.follow_symlinks(false);
+        builder
+            .append_dir_all(".", &session.workspace)
+            .with_context(|| format!("archive workspace: {}", session.workspace.display()))?;
+        let file = builder
+            .into_inner()
+            .and_then(zstd::Encoder::finish)
+            .context("finish archive")?;
+        file.persist(&path)
+            .with_context(|| format!("save archive: {}", path.display()))?;
+
+        // Recorded before the workspace is deleted: if that fails partway,
+        // the archive is the complete copy.
+        let session = {
+            let _lock = shard.lock()?;
+            let mut session = reread(&shard, branch)?;
+            session.archived = true;
+            shard.write(&session)?;
+            session
+        };
+        self.record(&session, HistoryEvent::Archived);
+        remove_dir(&session.workspace).context("remove archived workspace")?;
+        Ok(Some(path))
+    }
+
+    /// Restore the [archived](Self::archive) workspace of the session for `branch`
+    /// in `project` to where it was, returning the updated session. Returns `None`
+    /// if there is no such session, and does nothing if it isn't archived.
+    ///
+    /// Fails if a backend is running in it, which can only have started
+    /// on what's left of the workspace.
+    #[cfg(feature = "archive")]
+    pub fn unarchive(&self, project: &Path, branch: &BranchName) -> Result<Option<Session>> {
+        self.ensure_writable()?;
+        let shard = self.shard(project);
+        let lock = shard.lock()?;
+        let Some(session) = shard.read(branch)? else {
+            return Ok(None);
+        };
+        if !session.archived {
+            return Ok(Some(session));
+        }
+        let _idle = self.hold_idle(project, branch)?;
+        // Decompressing can take minutes, which the project's other sessions
+        // shouldn't wait for; being held idle keeps this one from changing meanwhile.
+        drop(lock);
+
+        let path = self.archive_path(project, branch);
+        let file =
+            File::open(&path).with_context(|| format!("open archive: {}", path.display()))?;
+        let decoder = zstd::Decoder::new(file).context("start decompressing workspace")?;
+        // Anything left at the workspace's path is from an archive interrupted
+        // before it finished deleting the workspace; the archive supersedes it.
+        remove_dir(&session.workspace).context("remove partial workspace")?;
+        fs::create_dir_all(&session.workspace).with_context(|| {
+            format!(
+                "create workspace directory: {}",
+                session.workspace.display()
+            )
+        })?;
+        let mut archive = tar::Archive::new(decoder);
+        archive.set_preserve_permissions(true);
+        archive.set_preserve_mtime(true);
+        archive
+            .unpack(&session.workspace)
+            .with_context(|| format!("unarchive workspace: {}", session.workspace.display()))?;
+
+        let session = {
+            let _lock = shard.lock()?;
+            let mut session = reread(&shard, branch)?;
+            session.archived = false;
+            shard.write(&session)?;
+            session
+        };
+        self.record(&session, HistoryEvent::Unarchived);
+        fs::remove_file(&path).with_context(|| format!("remove archive: {}", path.display()))?;
+        Ok(Some(session))
+    }
+
+    /// Where the workspace of the session for `branch` in `project` is kept while archived.
+    #[cfg(feature = "archive")]
+    pub fn archive_path(&self, project: &Path, branch: &BranchName) -> PathBuf {
+        self.data_dir(project, branch).join(ARCHIVE)
+    }
+
+    /// Remove the session for `branch` in `project` along with its workspaces,
+    /// returning the removed session. Adopted workspaces are left in place.
+    ///
+    /// Fails, keeping the session, if a backend is running in it.
+    pub fn remove(&self, project: &Path, branch: &BranchName) -> Result<Option<Session>> {
+        let Some((session, removal)) = self.remove_deferred(project, branch, |_| Ok(()))? else {
+            return Ok(None);
+        };
+        removal.finish()?;
+        Ok(Some(session))
+    }
+
+    /// Like [`remove`](Self::remove), but leaves deleting the session's workspace
+    /// and data to the returned [`Removal`], which can take minutes for a large
+    /// workspace. Until then, the session is gone but its files aren't.
+    ///
+    /// `check` is called with the session before it's removed, such as to refuse
+    /// to lose its [unsaved work](Session::unsaved_work); the session is locked
+    /// from then on, so nothing can resume it in between. Its error keeps the session.
+    pub fn remove_deferred(
+        &self,
+        project: &Path,
+        branch: &BranchName,
+        check: impl FnOnce(&Session) -> Result<()>,
+    ) -> Result<Option<(Session, Removal)>> {
+        let shard = self.shard(project);
+        let _lock = shard.lock()?;
+        let Some(session) = shard.read(branch)? else {
+            return Ok(None);
+        };
+        let _idle = self.hold_idle(project, branch)?;
+        check(&session)?;
+        let removal = self.remove_locked(&shard, &session)?;
+        Ok(Some((session, removal)))
+    }
+
+    /// [Merge](Session::merge_back) the session for `branch` back into the project at
+    /// `project`, then remove it as [`remove_deferred`](Self::remove_deferred) does.
+    /// The session is [held idle](Self::hold_idle) throughout, so nothing can resume it
+    /// in between, and `check` is called with it before the merge.
+    ///
+    /// Fails, keeping the session, if a backend is running in it, `check` fails,
+    /// or the merge fails.
+    pub fn merge_and_remove(
+        &self,
+        project: &Path,
+        branch: &BranchName,
+        check: impl FnOnce(&Session) -> Result<()>,
+    ) -> Result<Option<(Session, Removal)>> {
+        let shard = self.shard(project);
+        // Whatever the backend commits after the merge would be lost with the workspace.
+        let Some((session, _idle)) = self.read_idle(&shard, project, branch)? else {
+            return Ok(None);
+        };
+        check(&session)?;
+        session.merge_back()?;
+        self.record(&session, HistoryEvent::Merged);
+        let _lock = shard.lock()?;
+        let session = reread(&shard, branch)?;
+        let removal = self.remove_locked(&shard, &session)?;
+        Ok(Some((session, removal)))
+    }
+
+    /// Read the session for `branch` in `project` from its `shard` and [hold it
+    /// idle](Self::hold_idle), locking the shard only meanwhile. Returns `None` if
+    /// there is no such session.
+    ///
+    /// For operations too slow to hold up every other session of the project,
+    /// which lock the shard again to record what they did.
+    fn read_idle(
+        &self,
+        shard: &Shard,
+        project: &Path,
+        branch: &BranchName,
+    ) -> Result<Option<(Session, File)>> {
+        let _lock = shard.lock()?;
+        let Some(session) = shard.read(branch)? else {
+            return Ok(None);
+        };
+        let idle = self.hold_idle(project, branch)?;
+        Ok(Some((session, idle)))
+    }
+
+    /// Remove `session`, recorded in `shard`, which the caller has locked.
+    fn remove_locked(&self, shard: &Shard, session: &Session) -> Result<Removal> {
+        let (project, branch) = (&session.project, &session.branch);
+        // The record is in the session's data directory, so moving that aside removes
+        // the session at once, and frees its path for a new session of the branch
+        // while the data is deleted.
+        let data_dir = self.data_dir(project, branch);
+        let removed = shard
+            .dir
+            .join(format!("{REMOVED_PREFIX}{}", Uuid::new_v4()));
+        fs::rename(&data_dir, &removed)
+            .with_context(|| format!("move session data aside: {}", data_dir.display()))?;
+        self.record(session, HistoryEvent::Removed);
+
+        let workspace = (!session.adopted).then(|| session.workspace.clone());
+        let linked = session.linked.iter().map(|linked| linked.workspace.clone());
+        let dirs = workspace
+            .into_iter()
+            .chain(linked)
+            .chain([removed])
+            .collect();
+        let remote = session
+            .remote
+            .clone()
+            .map(|remote| (remote, session.workspace.clone()));
+        Ok(Removal { dirs, remote })
+    }
+
+    /// Move the sessions recorded for the project at `old` to the project at `new`,
+    /// such as after the project's directory was moved, returning them.
+    ///
+    /// Fails without moving any if `new` already has a session for one of
+    /// their branches, or a backend is running in one.
+    pub fn relink(&self, old: &Path, new: &Path) -> Result<Vec<Session>> {
+        if old == new {
+            bail!("sessions are already recorded for {}", new.display());
+        }
+        let from = self.shard(old);
+        let to = self.shard(new);
+        // Taken in a consistent order so that concurrent relinks can't deadlock,
+        // and only once when the hashes collide, since the locks don't nest.
+        let mut shards = vec![&from, &to];
+        shards.sort_by(|a, b| a.dir.cmp(&b.dir));
+        shards.dedup_by(|a, b| a.dir == b.dir);
+        let _locks = shards
+            .iter()
+            .map(|shard| shard.lock())
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut moving = from.read_all()?;
+        // Different projects' sessions may share a shard if their hashes collide.
+        moving.retain(|session| session.project == old);
+        let mut idle = Vec::with_capacity(moving.len());
+        for session in &moving {
+            if from.dir != to.dir && to.read(&session.branch)?.is_some() {
+                bail!(
+                    "{} already has a session for '{}'",
+                    new.display(),
+                    session.branch
+                );
+            }
+            idle.push(self.hold_idle(old, &session.branch)?);
+        }
+
+        if from.dir != to.dir && !moving.is_empty() {
+            fs::create_dir_all(&to.dir)
+                .with_context(|| format!("create sessions directory: {}", to.dir.display()))?;
+            let mut groups = to.read_groups()?;
+            for (name, info) in from.read_groups()? {
+                groups.entry(name).or_insert(info);
+            }
+            to.write_groups(&groups)?;
+        }
+        for session in &mut moving {
+            if from.dir != to.dir {
+                let dir = from.session_dir(&session.branch);
+                fs::rename(&dir, to.session_dir(&session.branch))
+                    .with_context(|| format!("move session data: {}", dir.display()))?;
+                move_history(&from, &to, &session.branch)?;
+            }
+            session.project = new.to_path_buf();
+            to.write(session)?;
+            // Only workspaces anna created have markers; adopted ones are left alone.
+            // Linked workspaces' markers name the session too, so they're rewritten with it.
+            let workspaces = std::iter::once((&session.workspace, session.vcs)).chain(
+                session
+                    .linked
+                    .iter()
+                    .map(|linked| (&linked.workspace, linked.vcs)),
+            );
+            for (workspace, vcs) in workspaces {
+                if WorkspaceMarker::read(workspace)?.is_some() {
+                    session.marker().write(workspace, vcs)?;
+                }
+            }
+            let from = old.to_path_buf();
+            self.record(session, HistoryEvent::Relinked { from });
+        }
+        Ok(moving)
+    }
+
+    /// The project directories which no longer exist but have sessions recorded
+    /// for the project with the [stable ID](project_id) `project_id`,
+    /// such as where the project was before it was moved.
+    pub fn moved(&self, project_id: &str) -> Result<BTreeSet<PathBuf>> {
+        Ok(self
+            .iter()?
+            .filter_map(Result::ok)
+            .filter(|session| session.project_id.as_deref() == Some(project_id))
+            .map(|session| session.project)
+            .filter(|project| !project.exists())
+            .collect())
+    }
+
+    /// What's recorded about each group of `project`'s sessions, by name.
+    /// Groups are recorded when first [described](Self::describe_group),
+    /// so sessions may be in groups this doesn't have.
+    pub fn groups(&self, project: &Path) -> Result<BTreeMap<String, GroupInfo>> {
+        self.shard(project).read_groups()
+    }
+
+    /// Set the description of the group of `project`'s sessions called `group`,
+    /// recording the group if it isn't yet.
+    pub fn describe_group(&self, project: &Path, group: &str, description: &str) -> Result<()> {
+        check_group_name(group)?;
+        let shard = self.shard(project);
+        let _lock = shard.lock()?;
+        let mut groups = shard.read_groups()?;
+        groups
+            .entry(String::from(group))
+            .or_insert_with(|| GroupInfo {
+                description: None,
+                created_at: Timestamp::now(),
+            })
+            .description = Some(String::from(description));
+        shard.write_groups(&groups)
+    }
+
+    /// Forget what's recorded about the group of `project`'s sessions called `group`,
+    /// leaving its sessions alone. Returns whether anything was recorded.
+    pub fn forget_group(&self, project: &Path, group: &str) -> Result<bool> {
+        let shard = self.shard(project);
+        let _lock = shard.lock()?;
+        let mut groups = shard.read_groups()?;
+        if groups.remove(group).is_none() {
+            return Ok(false);
+        }
+        shard.write_groups(&groups)?;
+        Ok(true)
+    }
+
+    /// Put the session for `branch` in `project` in `group`, or in no group if `None`,
+    /// returning the updated session, or `None` if there is no such session.
+    pub fn set_group(
+        &self,
+        project: &Path,
+        branch: &BranchName,
+        group: Option<&str>,
+    ) -> Result<Option<Session>> {
+        if let Some(group) = group {
+            check_group_name(group)?;
+        }
+        self.update(project, branch, |session| {
+            session.group = group.map(String::from);
+        })
+    }
+
+    /// List the sessions for `project`.
+    pub fn list(&self, project: &Path) -> Result<Vec<Session>> {
+        let shard = self.shard(project);
+        shard.migrate()?;
+        shard.read_all()
+    }
+
+    /// List the sessions for all projects.
+    ///
+    /// Sessions that can't be read are skipped with a warning,
+    /// so that one damaged file doesn't hide every other session.
+    pub fn list_all(&self) -> Result<HashSet<Session>> {
+        Ok(self.iter()?.skip_unreadable().collect())
+    }
+
+    /// Iterate over the sessions for all projects, in no particular order.
+    ///
+    /// Sessions are read from the store one at a time as the iterator advances,
+    /// so stopping early never reads the rest; narrow it down with the filters
+    /// on [`SessionIter`]. A session that can't be read is yielded as an error,
+    /// and iteration carries on with the next.
+    pub fn iter(&self) -> Result<SessionIter> {
+        Ok(SessionIter {
+            shards: self.shards()?.into_iter(),
+            entries: None,
+            unmigrated: Vec::new().into_iter(),
+            project: None,
+            archived: None,
+            created_since: None,
+            events: self.events.clone(),
+        })
+    }
+
+    /// The workspaces in `root` whose [markers](WorkspaceMarker) name sessions this store
+    /// doesn't track, such as ones left behind by a removal that failed partway,
+    /// or by a store that was deleted, along with their markers.
+    pub fn untracked_workspaces(&self, root: &Path) -> Result<Vec<(PathBuf, WorkspaceMarker)>> {
+        // Workspaces are recorded by their canonical paths.
+        let root = fs::canonicalize(root)
+            .with_context(|| format!("canonicalize workspace root: {}", root.display()))?;
+        let entries = fs::read_dir(&root)
+            .with_context(|| format!("read workspace root: {}", root.display()))?;
+
+        let mut untracked = Vec::new();
+        for entry in entries {
+            let dir = entry
+                .with_context(|| format!("read workspace root: {}", root.display()))?
+                .path();
+            if !dir.is_dir() {
+                continue;
+            }
+            // Anyone can write to the temporary directory, so a marker that
+            // doesn't parse is someone else's business rather than an error.
+            let Ok(Some(marker)) = WorkspaceMarker::read(&dir) else {
+                continue;
+            };
+            let tracked = self
+                .get(&marker.project, &marker.branch)?
+                .is_some_and(|session| {
+                    session.id == marker.id && session.workspaces().any(|ws| ws == dir)
+                });
+            if !tracked {
+                untracked.push((dir, marker));
+            }
+        }
+        untracked.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(untracked)
+    }
+
+    /// Gather an overview of every session in the store.
+    ///
+    /// This measures every workspace, so it takes a while for large ones.
+    pub fn stats(&self) -> Result<StoreStats> {
+        let mut stats = StoreStats::default();
+        for session in self.iter()?.skip_unreadable() {
+            let data_dir = self.data_dir(&session.project, &session.branch);
+            let workspace_exists = session.workspace.is_dir();
+            // Measured best effort, since files may vanish while they're walked.
+            let disk_usage = session
+                .workspaces()
+                .chain([data_dir.as_path()])
+                .filter(|dir| dir.is_dir())
+                .map(|dir| workspace::disk_usage(dir).unwrap_or_default())
+                .sum::<u64>();
+            let running = self.is_running(&session.project, &session.branch)?;
+
+            let project = stats.projects.entry(session.project.clone()).or_default();
+            *project
+                .states
+                .entry(WorkspaceState::of(&session, workspace_exists))
+                .or_default() += 1;
+            if running {
+                project.running.push(session.branch.clone());
+            }
+            project.disk_usage += disk_usage;
+            project.oldest = Some(oldest(project.oldest, session.created_at));
+
+            stats.sessions += 1;
+            stats.running += usize::from(running);
+            stats.disk_usage += disk_usage;
+            stats.oldest = Some(oldest(stats.oldest, session.created_at));
+        }
+        for project in stats.projects.values_mut() {
+            project.running.sort();
+        }
+        Ok(stats)
+    }
+
+    /// Whether a backend is running in the session for `branch` in `project`.
+    pub fn is_running(&self, project: &Path, branch: &BranchName) -> Result<bool> {
+        let path = self.data_dir(project, branch).join(RUNNING);
+        let file = match File::options().write(true).open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err).context(format!("open {}", path.display())),
+        };
+        match file.try_lock() {
+            Ok(()) => Ok(false),
+            Err(TryLockError::WouldBlock) => Ok(true),
+            Err(TryLockError::Error(err)) if err.kind() == ErrorKind::Unsupported => Ok(self
+                .recorded_backend(project, branch)
+                .is_some_and(|backend| backend.is_alive())),
+            Err(TryLockError::Error(err)) => {
+                Err(err).context(format!("check run lock: {}", path.display()))
+            }
+        }
+    }
+
+    /// How the backend running in the session for `branch` in `project` was run,
+    /// or `None` if no backend is running.
+    pub fn running_backend(
+        &self,
+        project: &Path,
+        branch: &BranchName,
+    ) -> Result<Option<RunningBackend>> {
+        if !self.is_running(project, branch)? {
+            return Ok(None);
+        }
+        // Missing for backends started by earlier versions, and briefly for new ones.
+        let backend = self.recorded_backend(project, branch).unwrap_or_default();
+        Ok(Some(backend))
+    }
+
+    /// How the backend last running in the session for `branch` in `project` was run,
+    /// whether or not it's still running.
+    fn recorded_backend(&self, project: &Path, branch: &BranchName) -> Option<RunningBackend> {
+        let path = self.data_dir(project, branch).join(RUNNING_BACKEND);
+        let content = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    /// Record how the backend running in the session for `branch` in `project` was run,
+    /// while it's [marked as running](Self::mark_running).
+    #[cfg(feature = "cli-backends")]
+    pub(crate) fn record_running(
+        &self,
+        project: &Path,
+        branch: &BranchName,
+        backend: &RunningBackend,
+    ) -> Result<()> {
+        let path = self.data_dir(project, branch).join(RUNNING_BACKEND);
+        let content = serde_json::to_string(backend).expect("running backends must serialize");
+        fs::write(&path, content).with_context(|| format!("write {}", path.display()))
+    }
+
+    /// Mark a backend as running in the session for `branch` in `project`
+    /// until the returned file is dropped, which happens even if the process dies.
+    ///
+    /// Returns `None` if the session is already marked as running. Where the filesystem
+    /// doesn't support locks, it's marked by [recording](Self::record_running) the backend
+    /// alone, and is taken to be running for as long as the recorded process is alive.
+    pub(crate) fn mark_running(&self, project: &Path, branch: &BranchName) -> Result<Option<File>> {
+        self.ensure_writable()?;
+        let dir = self.data_dir(project, branch);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("create session directory: {}", dir.display()))?;
+        let path = dir.join(RUNNING);
+        let file = File::options()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("open run lock: {}", path.display()))?;
+        let mut retries = 0;
+        let locked = loop {
+            match file.try_lock() {
+                Err(TryLockError::WouldBlock) if retries < RUNNING_RETRIES => {
+                    retries += 1;
+                    thread::sleep(RUNNING_RETRY_DELAY);
+                }
+                locked => break locked,
+            }
+        };
+        match locked {
+            Ok(()) => Ok(Some(file)),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(err)) if err.kind() == ErrorKind::Unsupported => {
+                match self.recorded_backend(project, branch) {
+                    Some(backend) if backend.is_alive() && !backend.is_current() => Ok(None),
+                    _ => Ok(Some(file)),
+                }
+            }
+            Err(TryLockError::Error(err)) => {
+                Err(err).context(format!("take run lock: {}", path.display()))
+            }
+        }
+    }
+
+    /// Keep backends from starting in the session for `branch` in `project` until the
+    /// returned file is dropped, failing if one is running in it already.
+    ///
+    /// The lock is taken rather than checked, so that no backend can start
+    /// between the check and whatever relies on there being none.
+    fn hold_idle(&self, project: &Path, branch: &BranchName) -> Result<File> {
+        match self.mark_running(project, branch)? {
+            Some(file) => Ok(file),
+            None => bail!("a backend is running in the session for '{branch}'"),
+        }
+    }
+
+    /// Find the session whose workspace, or one of whose linked workspaces,
+    /// contains `dir`, if any.
+    ///
+    /// This lets commands run from inside a workspace act on its session
+    /// as though they were run from the original project.
+    pub fn containing(&self, dir: &Path) -> Result<Option<Session>> {
+        Ok(self
+            .iter()?
+            .skip_unreadable()
+            .filter_map(|session| {
+                let depth = session
+                    .workspaces()
+                    .filter(|workspace| dir.starts_with(workspace))
+                    .map(|workspace| workspace.components().count())
+                    .max()?;
+                Some((depth, session))
+            })
+            // Workspaces may be nested inside other workspaces; the innermost wins.
+            .max_by_key(|(depth, _)| *depth)
+            .map(|(_, session)| session))
+    }
+
+    /// Find the session, of any project, whose ID starts with `prefix`,
+    /// such as its [short ID](Session::short_id); `None` if there isn't one, or if
+    /// `prefix` is shorter than a short ID, so that branch names which happen to be
+    /// hex digits, like `cafe`, aren't readily taken for IDs, or has anything
+    /// but hex digits and dashes.
+    ///
+    /// Fails if more than one session's ID starts with `prefix`.
+    pub fn find_by_id(&self, prefix: &str) -> Result<Option<Session>> {
+        let digits = prefix.replace('-', "").to_ascii_lowercase();
+        if digits.len() < SHORT_ID_LEN || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(None);
+        }
+        let mut matches = self
+            .iter()?
+            .skip_unreadable()
+            .filter(|session| session.id.simple().to_string().starts_with(&digits));
+        let Some(found) = matches.next() else {
+            return Ok(None);
+        };
+        if let Some(other) = matches.next() {
+            bail!(
+                "session ID '{prefix}' is ambiguous: it could be '{}' in {} or '{}' in {}",
+                found.branch,
+                found.project.display(),
+                other.branch,
+                other.project.display()
+            );
+        }
+        Ok(Some(found))
+    }
+
+    /// The [short ID](Session::short_id) of every session, of any project, widened
+    /// where it's shared with another until it isn't, so that it always
+    /// [finds](Self::find_by_id) just that session.
+    pub fn short_ids(&self) -> Result<BTreeMap<Uuid, String>> {
+        let ids = self.iter()?.skip_unreadable().map(|session| session.id);
+        Ok(unique_prefixes(ids))
+    }
+
+    /// The directory holding data recorded for the session, such as transcripts.
+    pub fn data_dir(&self, project: &Path, branch: &BranchName) -> PathBuf {
+        self.shard(project).session_dir(branch)
+    }
+
+    /// Whether another process holds the lock on `project`'s sessions.
+    pub fn is_locked(&self, project: &Path) -> Result<bool> {
+        self.shard(project).is_locked()
+    }
+
+    /// Who holds the lock on `project`'s sessions;
+    /// `None` if it isn't held or the owner didn't record itself.
+    pub fn lock_owner(&self, project: &Path) -> Result<Option<LockOwner>> {
+        self.shard(project).owner()
+    }
+
+    /// Every held lock in the store, as the path of the lock file and its owner.
+    pub fn lock_owners(&self) -> Result<Vec<(PathBuf, Option<LockOwner>)>> {
+        let mut owners = Vec::new();
+        for shard in self.shards()? {
+            if shard.is_locked()? {
+                owners.push((shard.lock_path(), shard.owner()?));
+            }
+        }
+        owners.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(owners)
+    }
+
+    fn shard(&self, project: &Path) -> Shard {
+        Shard {
+            dir: self.root.join(project_hash(project)),
+            read_only: self.read_only,
+            events: self.events.clone(),
+        }
+    }
+
+    /// Every project shard in the store, including ones in the legacy format.
+    fn shards(&self) -> Result<Vec<Shard>> {
+        let entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            // Only stores opened read-only aren't created when opened.
+            Err(err) if self.read_only && err.kind() == ErrorKind::NotFound => {
+                return Ok(Vec::new())
+            }
+            Err(err) => {
+                return Err(err)
+                    .context(format!("read sessions directory: {}", self.root.display()))
+            }
+        };
+
+        let mut dirs = BTreeSet::new();
+        for entry in entries {
+            let path = entry.context("read sessions directory entry")?.path();
+            if path.is_dir() {
+                dirs.insert(path);
+            } else if path.extension().is_some_and(|ext| ext == "json") {
+                dirs.insert(path.with_extension(""));
+            }
+        }
+        Ok(dirs
+            .into_iter()
+            .map(|dir| Shard {
+                dir,
+                read_only: self.read_only,
+                events: self.events.clone(),
+            })
+            .collect())
+    }
+}
+
+/// The process holding a lock on a project's sessions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockOwner {
+    /// The process ID of the owner.
+    pub pid: u32,
+
+    /// The host the owner runs on, since the store may be on a shared filesystem.
+    pub hostname: String,
+
+    /// When the lock was taken.
+    pub since: Timestamp,
+
+    /// The owner's command line.
+    pub command: String,
+}
+
+impl LockOwner {
+    fn current() -> Self {
+        Self {
+            pid: std::process::id(),
+            hostname: hostname(),
+            since: Timestamp::now(),
+            command: shell_words::join(env::args()),
+        }
+    }
+
+    /// Whether the owner is this process.
+    fn is_current(&self) -> bool {
+        self.pid == std::process::id() && self.hostname == hostname()
+    }
+}
+
+impl fmt::Display for LockOwner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "PID {} on {} since {} ({})",
+            self.pid, self.hostname, self.since, self.command
+        )
+    }
+}
+
+/// How a backend running in a session was run, from [`Sessions::running_backend`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunningBackend {
+    /// The process ID of the anna process running it;
+    /// `None` if it was started by a version of anna that didn't record it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+
+    /// The host that process runs on, since the store may be on a shared filesystem;
+    /// `None` if it was started by a version of anna that didn't record it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+
+    /// The multiplexer it runs in, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multiplexer: Option<MultiplexerKind>,
+
+    /// The [multiplexer's window](crate::multiplexer::Multiplexer::open) it runs in,
+    /// once that's open.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window: Option<String>,
+}
+
+impl RunningBackend {
+    /// Describe the backend as run by this process.
+    #[cfg(feature = "cli-backends")]
+    pub(crate) fn current(multiplexer: Option<MultiplexerKind>) -> Self {
+        Self {
+            pid: Some(std::process::id()),
+            hostname: Some(hostname()),
+            multiplexer,
+            window: None,
+        }
+    }
+
+    /// Whether the process running the backend is alive, as far as can be told:
+    /// processes on other hosts are assumed to be, and without a recorded process
+    /// there's nothing to tell by.
+    pub fn is_alive(&self) -> bool {
+        match (self.pid, &self.hostname) {
+            (Some(pid), Some(host)) if *host == hostname() => process_exists(pid),
+            (Some(_), _) => true,
+            (None, _) => false,
+        }
+    }
+
+    fn is_current(&self) -> bool {
+        self.pid == Some(std::process::id()) && self.hostname.as_deref() == Some(&*hostname())
+    }
+}
+
+impl fmt::Display for RunningBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.pid, &self.hostname) {
+            (Some(pid), Some(host)) => write!(f, "PID {pid} on {host}")?,
+            (Some(pid), None) => write!(f, "PID {pid}")?,
+            (None, _) => write!(f, "an unknown process")?,
+        }
+        match (self.multiplexer, &self.window) {
+            (Some(kind), Some(window)) => write!(f, ", in {kind} window '{window}'"),
+            (Some(kind), None) => write!(f, ", in {kind}"),
+            (None, _) => Ok(()),
+        }
+    }
+}
+
+/// The error running a backend in a session whose backend is already running,
+/// so that two backends don't work on the same workspace at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlreadyRunning {
+    /// The session's branch.
+    pub branch: BranchName,
+
+    /// How the running backend was run.
+    pub backend: RunningBackend,
+}
+
+impl fmt::Display for AlreadyRunning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "a backend is already running in session '{}' ({})",
+            self.branch, self.backend
+        )
+    }
+}
+
+impl std::error::Error for AlreadyRunning {}
+
+/// The session for `branch` in `shard`, which the caller has locked, read again after
+/// it was [held idle](Sessions::hold_idle) without the lock, in case its record changed.
+///
+/// Since it's held idle, it can't have been removed in the meantime.
+fn reread(shard: &Shard, branch: &BranchName) -> Result<Session> {
+    match shard.read(branch)? {
+        Some(session) => Ok(session),
+        None => bail!("session '{branch}' disappeared while it was held idle"),
+    }
+}
+
+/// Each of `ids` as its shortest prefix, of at least [`SHORT_ID_LEN`] hex digits,
+/// that none of the others start with.
+fn unique_prefixes(ids: impl Iterator<Item = Uuid>) -> BTreeMap<Uuid, String> {
+    let mut ids = ids
+        .map(|id| (id.simple().to_string(), id))
+        .collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+    let common = |a: &str, b: &str| a.bytes().zip(b.bytes()).take_while(|(a, b)| a == b).count();
+    // Sorted, the ID sharing the longest prefix with each is a neighbor of it.
+    (0..ids.len())
+        .map(|i| {
+            let (hex, id) = &ids[i];
+            let before = i
+                .checked_sub(1)
+                .map_or(0, |before| common(hex, &ids[before].0));
+            let after = ids.get(i + 1).map_or(0, |(after, _)| common(hex, after));
+            let len = (before.max(after) + 1).clamp(SHORT_ID_LEN, hex.len());
+            (*id, String::from(&hex[..len]))
+        })
+        .collect()
+}
+
+/// The name of this host, as recorded for lock owners and running backends.
+pub(crate) fn hostname() -> String {
+    gethostname::gethostname().to_string_lossy().into_owned()
+}
+
+/// Whether a process with ID `pid` exists on this host.
+#[cfg(unix)]
+pub(crate) fn process_exists(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 is never delivered; `kill` only checks whether
+    // the process exists and could be signalled.
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    // The process exists, but belongs to someone else.
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Whether a process with ID `pid` exists on this host; assumed to,
+/// since it can't be checked here.
+#[cfg(not(unix))]
+pub(crate) fn process_exists(_pid: u32) -> bool {
+    true
+}
+
+/// An iterator over the sessions in the store, from [`Sessions::iter`].
+///
+/// Filters apply to the sessions not yet yielded, and [`project`](Self::project)
+/// also skips reading other projects' sessions altogether.
+#[derive(Debug)]
+pub struct SessionIter {
+    shards: std::vec::IntoIter<Shard>,
+
+    entries: Option<fs::ReadDir>,
+
+    unmigrated: std::vec::IntoIter<Session>,
+
+    project: Option<PathBuf>,
+
+    archived: Option<bool>,
+
+    created_since: Option<Timestamp>,
+
+    events: Events,
+}
+
+impl SessionIter {
+    /// Only the sessions for `project`.
+    pub fn project(mut self, project: &Path) -> Self {
+        let hash = project_hash(project);
+        self.shards = self
+            .shards
+            .filter(|shard| shard.dir.file_name().is_some_and(|name| *name == *hash))
+            .collect::<Vec<_>>()
+            .into_iter();
+        self.project = Some(project.to_path_buf());
+        self
+    }
+
+    /// Only the sessions that are archived, or only those that aren't.
+    pub fn archived(mut self, archived: bool) -> Self {
+        self.archived = Some(archived);
+        self
+    }
+
+    /// Only the sessions created at or after `since`.
+    pub fn created_since(mut self, since: Timestamp) -> Self {
+        self.created_since = Some(since);
+        self
+    }
+
+    /// Skip sessions that can't be read, with a [warning](Event::Warning) for each,
+    /// so that one damaged file doesn't hide every other session.
+    pub fn skip_unreadable(self) -> impl Iterator<Item = Session> {
+        let events = self.events.clone();
+        self.filter_map(move |session| {
+            session
+                .inspect_err(|err| {
+                    events.emit(&Event::Warning {
+                        message: format!("{err:#}"),
+                    })
+                })
+                .ok()
+        })
+    }
+
+    fn matches(&self, session: &Session) -> bool {
+        // Different projects' sessions may share a shard if their hashes collide.
+        self.project
+            .as_ref()
+            .is_none_or(|project| session.project == *project)
+            && self
+                .archived
+                .is_none_or(|archived| session.archived == archived)
+            && self
+                .created_since
+                .is_none_or(|since| session.created_at >= since)
+    }
+}
+
+impl Iterator for SessionIter {
+    type Item = Result<Session>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(session) = self.unmigrated.next() {
+                if self.matches(&session) {
+                    return Some(Ok(session));
+                }
+                continue;
+            }
+            let Some(entries) = &mut self.entries else {
+                let shard = self.shards.next()?;
+                if let Err(err) = shard.migrate() {
+                    return Some(Err(err));
+                }
+                match shard.read_unmigrated() {
+                    Ok(sessions) => self.unmigrated = sessions.into_iter(),
+                    Err(err) => return Some(Err(err)),
+                }
+                match fs::read_dir(&shard.dir) {
+                    Ok(entries) => self.entries = Some(entries),
+                    Err(err) if err.kind() == ErrorKind::NotFound => {}
+                    Err(err) => {
+                        return Some(
+                            Err(err).context(format!("read sessions: {}", shard.dir.display())),
+                        )
+                    }
+                }
+                continue;
+            };
+            let Some(entry) = entries.next() else {
+                self.entries = None;
+                continue;
+            };
+            let path = match entry {
+                Ok(entry) if is_removed(&entry.path()) => continue,
+                Ok(entry) => entry.path().join("session.json"),
+                Err(err) => return Some(Err(err).context("read sessions directory entry")),
+            };
+            match read_session(&path) {
+                Ok(Some(session)) if self.matches(&session) => return Some(Ok(session)),
+                Ok(_) => {}
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+fn project_hash(project: &Path) -> String {
+    Sha256::digest(project.to_string_lossy().as_bytes())
+        .iter()
+        .take(8)
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// A name for files of `branch`'s in a shard. Branch names may contain slashes,
+/// which would otherwise nest directories and let one branch's data shadow another's.
+fn file_name(branch: &BranchName) -> String {
+    branch.as_str().replace('%', "%25").replace('/', "%2F")
+}
+
+fn oldest(oldest: Option<Timestamp>, created_at: Timestamp) -> Timestamp {
+    oldest.map_or(created_at, |oldest| oldest.min(created_at))
+}
+
+/// Move the history of `branch` from the shard `from` to `to`, after any that `to`
+/// already has, such as of the branch's sessions removed before the project moved.
+fn move_history(from: &Shard, to: &Shard, branch: &BranchName) -> Result<()> {
+    let (old, new) = (from.history_path(branch), to.history_path(branch));
+    for entry in history::read(&old)? {
+        history::append(&new, &entry)?;
+    }
+    match fs::remove_file(&old) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("remove moved history: {}", old.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn remove_dir(dir: &Path) -> Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("remove {}", dir.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The sessions for a single project.
+///
+/// Each session is stored in its own file inside its data directory,
+/// so single-session operations don't need to read every session of the project.
+/// Files are replaced atomically, so reading doesn't take the lock;
+/// only changes do, so that concurrent changes can't be lost.
+#[derive(Debug)]
+struct Shard {
+    dir: PathBuf,
+
+    /// Whether the shard is only read, in which case it's never locked,
+    /// and sessions in the legacy format are read without being converted.
+    read_only: bool,
+
+    /// Where waiting for the lock is reported.
+    events: Events,
+}
+
+impl Shard {
+    /// Take the lock for this shard, held until the returned file is dropped.
+    ///
+    /// If another process holds the lock, reports who before waiting for it.
+    fn lock(&self) -> Result<File> {
+        let path = self.lock_path();
+        if self.read_only {
+            bail!(
+                "the sessions lock can't be taken read-only: {}",
+                path.display()
+            );
+        }
+        let file = self.open_lock()?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                // Another of this process's threads only holds it for a moment.
+                let on = match self.read_owner() {
+                    Some(owner) if owner.is_current() => None,
+                    Some(owner) => Some(format!("the sessions lock held by {owner}")),
+                    None => Some(format!("the sessions lock: {}", path.display())),
+                };
+                if let Some(on) = on {
+                    self.events.emit(&Event::Waiting { on });
+                }
+                file.lock()
+                    .with_context(|| format!("lock sessions: {}", path.display()))?;
+            }
+            Err(TryLockError::Error(err)) => {
+                return Err(err).context(format!("lock sessions: {}", path.display()))
+            }
+        }
+
+        // Best effort: it's purely informational, and failing to write it
+        // must not stop the operation the lock was taken for.
+        if let Ok(owner) = serde_json::to_string(&LockOwner::current()) {
+            let _ = fs::write(self.owner_path(), owner);
+        }
+
+        self.migrate_locked()?;
+        Ok(file)
+    }
+
+    fn is_locked(&self) -> Result<bool> {
+        let path = self.lock_path();
+        match self.open_lock()?.try_lock() {
+            Ok(()) => Ok(false),
+            Err(TryLockError::WouldBlock) => Ok(true),
+            Err(TryLockError::Error(err)) => {
+                Err(err).context(format!("check sessions lock: {}", path.display()))
+            }
+        }
+    }
+
+    /// Who holds the lock, or `None` if it isn't held.
+    fn owner(&self) -> Result<Option<LockOwner>> {
+        if !self.is_locked()? {
+            return Ok(None);
+        }
+        Ok(self.read_owner())
+    }
+
+    /// The recorded owner of the lock; only meaningful while the lock is held,
+    /// since it isn't cleared on release.
+    fn read_owner(&self) -> Option<LockOwner> {
+        let content = fs::read_to_string(self.owner_path()).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn open_lock(&self) -> Result<File> {
+        let path = self.lock_path();
+        // Not truncated, since the file may be locked by someone else;
+        // on Windows the lock is mandatory, which is why the owner
+        // is recorded in a separate file rather than in the lock file.
+        File::options()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("open sessions lock: {}", path.display()))
+    }
+
+    fn lock_path(&self) -> PathBuf {
+        self.dir.with_extension("lock")
+    }
+
+    fn owner_path(&self) -> PathBuf {
+        self.dir.with_extension("owner")
+    }
+
+    /// Earlier versions stored all of a project's sessions in this one file.
+    fn legacy_path(&self) -> PathBuf {
+        self.dir.with_extension("json")
+    }
+
+    /// The directory holding a session's record and data.
+    fn session_dir(&self, branch: &BranchName) -> PathBuf {
+        self.dir.join(file_name(branch))
+    }
+
+    /// Kept apart from the session's data directory, which goes when the session does,
+    /// in a directory readers of sessions skip, as with [`groups_path`](Self::groups_path).
+    fn history_path(&self, branch: &BranchName) -> PathBuf {
+        self.dir
+            .join(".history")
+            .join(format!("{}.jsonl", file_name(branch)))
+    }
+
+    fn session_path(&self, branch: &BranchName) -> PathBuf {
+        self.session_dir(branch).join("session.json")
+    }
+
+    fn read(&self, branch: &BranchName) -> Result<Option<Session>> {
+        if let Some(session) = self
+            .read_unmigrated()?
+            .into_iter()
+            .find(|session| &session.branch == branch)
+        {
+            return Ok(Some(session));
+        }
+        read_session(&self.session_path(branch))
+    }
+
+    fn read_all(&self) -> Result<Vec<Session>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err).context(format!("read sessions: {}", self.dir.display())),
+        };
+
+        let mut sessions = Vec::new();
+        for entry in entries {
+            let dir = entry
+                .with_context(|| format!("read sessions: {}", self.dir.display()))?
+                .path();
+            if !is_removed(&dir) {
+                sessions.extend(read_session(&dir.join("session.json"))?);
+            }
+        }
+        sessions.extend(self.read_unmigrated()?);
+        Ok(sessions)
+    }
+
+    /// Writes go through a temporary file so that a crash mid-write
+    /// can never leave a truncated file behind, and readers never see one.
+    fn write(&self, session: &Session) -> Result<()> {
+        let dir = self.session_dir(&session.branch);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("create session directory: {}", dir.display()))?;
+        let path = self.session_path(&session.branch);
+        let content = serde_json::to_string_pretty(session).context("serialize session")?;
+        crate::replace_file(&path, &content)
+            .with_context(|| format!("replace session: {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Kept in a directory, which can't be mistaken for a session's directory since
+    /// branch names can't start with a dot, and which readers of sessions skip
+    /// since it has no `session.json`.
+    fn groups_path(&self) -> PathBuf {
+        self.dir.join(".groups").join("groups.json")
+    }
+
+    fn read_groups(&self) -> Result<BTreeMap<String, GroupInfo>> {
+        let path = self.groups_path();
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("parse groups: {}", path.display())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(err) => Err(err).context(format!("read groups: {}", path.display())),
+        }
+    }
+
+    /// Written as [`write`](Self::write) writes sessions; the lock must be held.
+    fn write_groups(&self, groups: &BTreeMap<String, GroupInfo>) -> Result<()> {
+        let path = self.groups_path();
+        let dir = path.parent().expect("the groups file is in a directory");
+        fs::create_dir_all(dir)
+            .with_context(|| format!("create groups directory: {}", dir.display()))?;
+        let content = serde_json::to_string_pretty(groups).context("serialize groups")?;
+        crate::replace_file(&path, &content)
+            .with_context(|| format!("replace groups: {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Convert the legacy format, if present, taking the lock to do so;
+    /// a read-only shard is left as it is.
+    fn migrate(&self) -> Result<()> {
+        if !self.read_only && self.legacy_path().exists() {
+            // Taking the lock migrates.
+            self.lock()?;
+        }
+        Ok(())
+    }
+
+    /// Convert the legacy format, if present; the lock must be held.
+    fn migrate_locked(&self) -> Result<()> {
+        let path = self.legacy_path();
+        let Some(sessions) = self.read_legacy()? else {
+            return Ok(());
+        };
+        for session in sessions {
+            self.write(&session)?;
+        }
+        fs::remove_file(&path).with_context(|| format!("remove {}", path.display()))
+    }
+
+    /// The sessions in the legacy format, if present.
+    fn read_legacy(&self) -> Result<Option<Vec<Session>>> {
+        let path = self.legacy_path();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).context(format!("read sessions: {}", path.display())),
+        };
+        let sessions = serde_json::from_str::<Vec<Session>>(&content)
+            .with_context(|| format!("parse sessions: {}", path.display()))?;
+        Ok(Some(sessions.into_iter().map(Session::with_id).collect()))
+    }
+
+    /// The sessions in the legacy format that a read-only shard reads
+    /// in place of converting them; always empty otherwise.
+    fn read_unmigrated(&self) -> Result<Vec<Session>> {
+        match self.read_only {
+            true => Ok(self.read_legacy()?.unwrap_or_default()),
+            false => Ok(Vec::new()),
+        }
+    }
+}
+
+/// The file, relative to a project's root, which may record the project's
+/// [stable ID](project_id), for projects without history or whose history is rewritten.
+pub const PROJECT_ID: &str = ".anna/project-id";
+
+/// The stable ID of the project at `project`, which identifies it wherever it's moved:
+/// the contents of its [`PROJECT_ID`] file if it has one, else its
+/// [first commit](crate::vcs::Vcs::root_commit). `None` if it has neither.
+///
+/// Clones of a repository share their first commit, so it tells that two directories
+/// hold the same project, but not that one was moved to the other.
+pub fn project_id(project: &Path, vcs: VcsKind) -> Result<Option<String>> {
+    let path = project.join(PROJECT_ID);
+    match fs::read_to_string(&path) {
+        Ok(content) if !content.trim().is_empty() => return Ok(Some(String::from(content.trim()))),
+        Ok(_) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err).context(format!("read project ID: {}", path.display())),
+    }
+    vcs.vcs()
+        .root_commit(project)
+        .context("determine project ID")
+}
+
+/// Group names are shown in tab-separated listings, so they can't contain tabs
+/// or anything else that would break those up.
+pub fn check_group_name(group: &str) -> Result<()> {
+    if group.trim().is_empty() || group.contains(char::is_control) {
+        bail!("invalid group name '{group}'; it must be non-empty and on one line");
+    }
+    Ok(())
+}
+
+/// Whether `dir`, in a shard, is the data directory of a removed session, moved aside.
+fn is_removed(dir: &Path) -> bool {
+    dir.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with(REMOVED_PREFIX))
+}
+
+fn read_session(path: &Path) -> Result<Option<Session>> {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map(|session: Session| Some(session.with_id()))
+            .with_context(|| format!("parse session: {}", path.display())),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).context(format!("read session: {}", path.display())),
+    }
+}
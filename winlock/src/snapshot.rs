@@ -0,0 +1,191 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Snapshots of agent workspaces, so that risky changes can be rolled back.
+//!
+//! Where the version control system supports it, a snapshot is stored in the
+//! workspace's own repository (for git, a commit that isn't on any branch),
+//! which makes it nearly free. Otherwise the workspace is copied into the
+//! session's data directory.
+
+use std::{
+    fs::{self, File},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    branch::BranchName,
+    session::{Session, Sessions},
+    workspace::{clear_workspace, copy_workspace, CopyReport},
+};
+
+/// A recorded state of a workspace.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The snapshot's number, unique within the session.
+    pub id: u32,
+
+    /// A description of the snapshot.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+
+    /// When the snapshot was taken.
+    pub created_at: Timestamp,
+
+    /// The revision holding the snapshot, if the version control system stored it;
+    /// otherwise the snapshot is a copy of the workspace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+}
+
+/// The snapshots of a session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshots {
+    dir: PathBuf,
+}
+
+impl Snapshots {
+    /// The snapshots recorded in a session's data directory.
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            dir: data_dir.join("snapshots"),
+        }
+    }
+
+    /// List the snapshots, oldest first.
+    pub fn list(&self) -> Result<Vec<Snapshot>> {
+        let path = self.index();
+        match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("parse snapshots: {}", path.display())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err).context(format!("read snapshots: {}", path.display())),
+        }
+    }
+
+    /// Snapshot the workspace of the session for `branch` in `project`,
+    /// including uncommitted changes.
+    ///
+    /// Fails if there is no such session, if it's archived, or if a backend is running in it.
+    pub fn create(
+        &self,
+        sessions: &Sessions,
+        project: &Path,
+        branch: &BranchName,
+        message: Option<&str>,
+    ) -> Result<Snapshot> {
+        // Held until the snapshot is recorded, which also keeps another from taking its ID.
+        let (session, _idle) = hold_idle(sessions, project, branch)?;
+        let mut snapshots = self.list()?;
+        let id = snapshots
+            .iter()
+            .map(|snapshot| snapshot.id)
+            .max()
+            .unwrap_or(0)
+            + 1;
+
+        let description = message.map_or_else(|| format!("anna snapshot {id}"), String::from);
+        let revision = session
+            .vcs
+            .vcs()
+            .snapshot(&session.workspace, &description)
+            .context("snapshot workspace")?;
+        if revision.is_none() {
+            let copy = self.copy(id);
+            fs::create_dir_all(&copy)
+                .with_context(|| format!("create snapshot directory: {}", copy.display()))?;
+            copy_workspace(&session.workspace, &copy, &[] as &[&str])
+                .and_then(complete)
+                .context("copy workspace to snapshot")?;
+        }
+
+        let snapshot = Snapshot {
+            id,
+            message: message.map(String::from),
+            created_at: Timestamp::now(),
+            revision,
+        };
+        snapshots.push(snapshot.clone());
+        self.write(&snapshots)?;
+        Ok(snapshot)
+    }
+
+    /// Return the workspace of the session for `branch` in `project` to the state recorded
+    /// in snapshot `id`, discarding everything that changed since. Ignored files are left
+    /// alone.
+    ///
+    /// Fails if there is no such session, if it's archived, or if a backend is running in it.
+    pub fn rollback(
+        &self,
+        sessions: &Sessions,
+        project: &Path,
+        branch: &BranchName,
+        id: u32,
+    ) -> Result<Snapshot> {
+        let (session, _idle) = hold_idle(sessions, project, branch)?;
+        let snapshot = self
+            .list()?
+            .into_iter()
+            .find(|snapshot| snapshot.id == id)
+            .ok_or_else(|| eyre!("no snapshot {id} for '{branch}'"))?;
+
+        match &snapshot.revision {
+            Some(revision) => session
+                .vcs
+                .vcs()
+                .restore(&session.workspace, revision)
+                .context("restore snapshot")?,
+            None => {
+                clear_workspace(&session.workspace).context("clear workspace")?;
+                copy_workspace(&self.copy(id), &session.workspace, &[] as &[&str])
+                    .and_then(complete)
+                    .context("copy snapshot to workspace")?;
+            }
+        }
+
+        Ok(snapshot)
+    }
+
+    fn index(&self) -> PathBuf {
+        self.dir.join("snapshots.json")
+    }
+
+    fn copy(&self, id: u32) -> PathBuf {
+        self.dir.join(id.to_string())
+    }
+
+    fn write(&self, snapshots: &[Snapshot]) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("create snapshots directory: {}", self.dir.display()))?;
+        let content = serde_json::to_string_pretty(snapshots).context("serialize snapshots")?;
+        crate::replace_file(&self.index(), &content)
+            .with_context(|| format!("replace snapshots: {}", self.index().display()))?;
+        Ok(())
+    }
+}
+
+/// Get the session for `branch` in `project`, [held idle](Sessions::get_idle) so that
+/// nothing changes its workspace meanwhile.
+fn hold_idle(sessions: &Sessions, project: &Path, branch: &BranchName) -> Result<(Session, File)> {
+    let Some((session, idle)) = sessions.get_idle(project, branch)? else {
+        bail!("no session for '{branch}' in {}", project.display());
+    };
+    if session.archived {
+        bail!("session '{branch}' is archived; resume it first");
+    }
+    Ok((session, idle))
+}
+
+/// Unlike a new workspace, a snapshot missing some files can't be trusted to roll back to.
+fn complete(report: CopyReport) -> Result<()> {
+    match report.warnings.first() {
+        Some(warning) => Err(eyre!("{warning}")),
+        None => Ok(()),
+    }
+}
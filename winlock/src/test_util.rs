@@ -0,0 +1,255 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Stand-ins for exercising [`Agent`](crate::Agent) and [`Sessions`] in tests,
+//! available with the `test-util` feature.
+//!
+//! A [`FakeStore`] keeps sessions in a temporary directory rather than the user's
+//! state directory, and a [`MockBackend`] stands in for the backend: runs are
+//! recorded and can change the workspace as an agent would, without spawning
+//! a process. Together they cover creating, running, and removing sessions:
+//!
+//! ```
+//! # use std::fs;
+//! # use winlock::{test_util::{FakeStore, MockBackend}, vcs::VcsKind, Agent, CreateOptions};
+//! # fn main() -> color_eyre::Result<()> {
+//! # let project = tempfile::TempDir::new()?;
+//! let store = FakeStore::new()?;
+//! let backend = MockBackend::new().on_run(|run| {
+//!     fs::write(run.workspace.join("done.txt"), "done")?;
+//!     Ok(())
+//! });
+//! let options = CreateOptions {
+//!     vcs: VcsKind::None,
+//!     ..CreateOptions::default()
+//! };
+//! let mut agent = Agent::new(&store, project.path(), "feature".parse()?, &options)?;
+//! agent.run(&backend.profile(), &backend.run_options())?;
+//! assert_eq!(backend.runs().len(), 1);
+//! assert!(agent.workspace().join("done.txt").exists());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::VecDeque,
+    ffi::OsString,
+    fmt,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::{Arc, Mutex},
+};
+
+use color_eyre::eyre::Result;
+use tempfile::TempDir;
+
+use crate::{
+    backend::{CommandTemplate, Invocation, Launch, Launcher},
+    config::Profile,
+    session::Sessions,
+    workspace, RunOptions,
+};
+
+/// A session store in a temporary directory, which dereferences to its [`Sessions`].
+///
+/// Dropping it deletes the store along with the workspaces of its sessions,
+/// which are otherwise left in the [workspace root](workspace::root).
+pub struct FakeStore {
+    sessions: Sessions,
+
+    dir: TempDir,
+}
+
+impl FakeStore {
+    /// Open an empty store.
+    pub fn new() -> Result<Self> {
+        let dir = TempDir::new()?;
+        let sessions = Sessions::open_in(dir.path())?;
+        Ok(Self { sessions, dir })
+    }
+
+    /// The directory the store is in.
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+impl Deref for FakeStore {
+    type Target = Sessions;
+
+    fn deref(&self) -> &Sessions {
+        &self.sessions
+    }
+}
+
+impl fmt::Debug for FakeStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FakeStore").field(&self.dir.path()).finish()
+    }
+}
+
+impl Drop for FakeStore {
+    fn drop(&mut self) {
+        // Best effort: failing to clean up shouldn't fail, or abort, the test.
+        let Ok(sessions) = self.sessions.list_all() else {
+            return;
+        };
+        for session in sessions {
+            let linked = session.linked.iter().map(|linked| &linked.workspace);
+            for workspace in [&session.workspace].into_iter().chain(linked) {
+                let _ = workspace::delete_dir(workspace);
+            }
+        }
+    }
+}
+
+/// A run of a [`MockBackend`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockRun {
+    /// The program the backend's command line runs, followed by its arguments.
+    pub command_line: Vec<String>,
+
+    /// Environment variables set for it, on top of the ones anna inherited.
+    pub env: Vec<(String, OsString)>,
+
+    /// The directory it ran in.
+    pub workspace: PathBuf,
+
+    /// The prompt it was given, if any.
+    pub prompt: Option<String>,
+}
+
+type OnRun = Box<dyn FnMut(&MockRun) -> Result<()> + Send>;
+
+#[derive(Default)]
+struct MockState {
+    runs: Vec<MockRun>,
+
+    exit_codes: VecDeque<i32>,
+
+    on_run: Option<OnRun>,
+}
+
+/// A backend that records its runs rather than spawning a process,
+/// started by running an agent with its [profile](Self::profile) and
+/// [options](Self::run_options).
+///
+/// Cheap to clone: clones record the same runs.
+#[derive(Clone, Default)]
+pub struct MockBackend(Arc<Mutex<MockState>>);
+
+impl MockBackend {
+    /// The program of the backend's [profile](Self::profile), which is recorded
+    /// on sessions as the backend that ran.
+    pub const PROGRAM: &'static str = "mock-backend";
+
+    /// A backend whose runs all succeed without doing anything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Exit with each of `codes` in turn, one per run, then successfully.
+    pub fn exiting_with(self, codes: impl IntoIterator<Item = i32>) -> Self {
+        self.state().exit_codes.extend(codes);
+        self
+    }
+
+    /// Call `on_run` in each run, before exiting, such as to change the workspace
+    /// as an agent would; an error fails running the agent.
+    pub fn on_run(self, on_run: impl FnMut(&MockRun) -> Result<()> + Send + 'static) -> Self {
+        self.state().on_run = Some(Box::new(on_run));
+        self
+    }
+
+    /// A profile running the backend, passing it the prompt,
+    /// and resuming its conversations with `--resume`.
+    pub fn profile(&self) -> Profile {
+        let template = |template: String| {
+            template
+                .parse::<CommandTemplate>()
+                .expect("the mock backend's templates are valid")
+        };
+        let mut profile = Profile::with_command(template(format!("{} {{prompt}}", Self::PROGRAM)));
+        profile.resume = Some(template(format!(
+            "{} --resume {{resume_token}} {{prompt}}",
+            Self::PROGRAM
+        )));
+        profile
+    }
+
+    /// Options for running an agent that start this backend.
+    pub fn run_options(&self) -> RunOptions {
+        RunOptions {
+            launcher: self.launcher(),
+            ..RunOptions::default()
+        }
+    }
+
+    /// A launcher starting this backend, for [`RunOptions::launcher`].
+    pub fn launcher(&self) -> Launcher {
+        Launcher::new(self.clone())
+    }
+
+    /// The backend's runs so far, oldest first.
+    pub fn runs(&self) -> Vec<MockRun> {
+        self.state().runs.clone()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Launch for MockBackend {
+    fn launch(&self, invocation: &Invocation<'_>) -> Result<ExitStatus> {
+        let run = MockRun {
+            command_line: [invocation.program]
+                .into_iter()
+                .chain(invocation.args.iter().map(String::as_str))
+                .map(String::from)
+                .collect(),
+            env: invocation
+                .env
+                .iter()
+                .map(|(name, value)| (String::from(*name), value.clone()))
+                .collect(),
+            workspace: invocation.workspace.to_path_buf(),
+            prompt: invocation.prompt.map(String::from),
+        };
+        let mut state = self.state();
+        state.runs.push(run.clone());
+        if let Some(on_run) = &mut state.on_run {
+            on_run(&run)?;
+        }
+        let code = state.exit_codes.pop_front().unwrap_or(0);
+        Ok(exit_status(code))
+    }
+}
+
+impl fmt::Debug for MockBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockBackend")
+            .field("runs", &self.state().runs)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The status of a process that exited with `code`.
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+
+    ExitStatus::from_raw((code & 0xff) << 8)
+}
+
+/// The status of a process that exited with `code`.
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+
+    ExitStatus::from_raw(code as u32)
+}
@@ -0,0 +1,223 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Toolchain state kept in each workspace rather than shared between them.
+//!
+//! Language toolchains keep state outside the project that concurrent agents would
+//! otherwise trample: a `CARGO_TARGET_DIR` set for the user sends every workspace's
+//! builds to one directory, where they wait on each other's locks and overwrite
+//! each other's artifacts, and a virtualenv copied from the project still installs
+//! into the project's. [`Toolchain`] presets point that state into the workspace,
+//! through the environment commands run in the workspace get from [`env`].
+
+use std::{
+    env,
+    ffi::OsString,
+    fmt, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+use color_eyre::eyre::{bail, eyre, Context, Error, Result};
+use serde::{Deserialize, Serialize};
+
+/// A preset isolating a toolchain's state in each workspace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Toolchain {
+    /// Cargo builds into the workspace's `target` directory, whatever
+    /// `CARGO_TARGET_DIR` is set to.
+    Cargo,
+
+    /// Python uses a virtualenv of its own at the workspace's `.venv`, created
+    /// when the workspace is, since one copied from the project would refer to it.
+    Python,
+
+    /// Node is the version the workspace's `.nvmrc` names, installed with nvm,
+    /// as after `nvm use`.
+    Node,
+}
+
+impl Toolchain {
+    /// All supported presets.
+    pub const ALL: [Toolchain; 3] = [Toolchain::Cargo, Toolchain::Python, Toolchain::Node];
+
+    /// Patterns, in gitignore syntax, for the toolchain's state in the project,
+    /// which is left out of workspaces rather than shared with the project.
+    pub fn exclude(self) -> &'static [&'static str] {
+        match self {
+            Toolchain::Python => &["/.venv/"],
+            Toolchain::Cargo | Toolchain::Node => &[],
+        }
+    }
+
+    /// Set up the toolchain's state in the new `workspace`,
+    /// before setup commands run there.
+    pub fn prepare(self, workspace: &Path) -> Result<()> {
+        match self {
+            Toolchain::Python => {
+                let python = if cfg!(windows) { "python" } else { "python3" };
+                let status = Command::new(python)
+                    .args(["-m", "venv", VENV])
+                    .current_dir(workspace)
+                    .status()
+                    .with_context(|| format!("run {python}"))?;
+                if !status.success() {
+                    bail!("creating a virtualenv failed with {status}");
+                }
+                Ok(())
+            }
+            Toolchain::Cargo | Toolchain::Node => Ok(()),
+        }
+    }
+
+    fn apply(self, workspace: &Path, vars: &mut Vars) -> Result<()> {
+        match self {
+            Toolchain::Cargo => {
+                vars.set("CARGO_TARGET_DIR", workspace.join("target"));
+            }
+            Toolchain::Python => {
+                let venv = workspace.join(VENV);
+                vars.path
+                    .push(venv.join(if cfg!(windows) { "Scripts" } else { "bin" }));
+                // uv otherwise uses whichever environment it's pointed at.
+                vars.set("UV_PROJECT_ENVIRONMENT", venv.clone());
+                vars.set("VIRTUAL_ENV", venv);
+            }
+            Toolchain::Node => {
+                if let Some(bin) = nvm_bin(workspace)? {
+                    vars.path.push(bin);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Toolchain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Toolchain::Cargo => "cargo",
+            Toolchain::Python => "python",
+            Toolchain::Node => "node",
+        })
+    }
+}
+
+impl FromStr for Toolchain {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|toolchain| toolchain.to_string() == s)
+            .ok_or_else(|| {
+                let known = Self::ALL.map(|toolchain| toolchain.to_string()).join(", ");
+                eyre!("unknown toolchain '{s}'; expected one of: {known}")
+            })
+    }
+}
+
+/// The virtualenv of the [Python](Toolchain::Python) preset, relative to the workspace.
+const VENV: &str = ".venv";
+
+/// The environment variables isolating `toolchains` in `workspace`,
+/// with `PATH` leading to their programs ahead of the ones it already leads to.
+pub fn env(toolchains: &[Toolchain], workspace: &Path) -> Result<Vec<(&'static str, OsString)>> {
+    let mut vars = Vars::default();
+    for toolchain in toolchains {
+        toolchain
+            .apply(workspace, &mut vars)
+            .with_context(|| format!("isolate {toolchain} toolchain"))?;
+    }
+    if !vars.path.is_empty() {
+        let inherited = env::var_os("PATH").unwrap_or_default();
+        let path = env::join_paths(
+            vars.path
+                .iter()
+                .cloned()
+                .chain(env::split_paths(&inherited)),
+        )
+        .context("join PATH")?;
+        vars.vars.push(("PATH", path));
+    }
+    Ok(vars.vars)
+}
+
+#[derive(Default)]
+struct Vars {
+    vars: Vec<(&'static str, OsString)>,
+
+    path: Vec<PathBuf>,
+}
+
+impl Vars {
+    fn set(&mut self, name: &'static str, value: impl Into<OsString>) {
+        self.vars.push((name, value.into()));
+    }
+}
+
+/// The directory of the programs of the Node version `workspace`'s `.nvmrc` names,
+/// if it has one, resolving nvm's aliases such as `lts/*` as `nvm use` does.
+fn nvm_bin(workspace: &Path) -> Result<Option<PathBuf>> {
+    let nvmrc = match fs::read_to_string(workspace.join(".nvmrc")) {
+        Ok(nvmrc) => nvmrc,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).context("read .nvmrc"),
+    };
+    let requested = nvmrc.trim();
+    let nvm = env::var_os("NVM_DIR")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".nvm")))
+        .ok_or_else(|| eyre!("nvm's directory is unknown; set NVM_DIR"))?;
+
+    // Aliases are files naming a version or another alias.
+    let mut version = String::from(requested);
+    for _ in 0..8 {
+        match fs::read_to_string(nvm.join("alias").join(&version)) {
+            Ok(target) => version = String::from(target.trim()),
+            Err(_) => break,
+        }
+    }
+    let version = match version.as_str() {
+        "node" | "stable" => "",
+        version => version.trim_start_matches('v'),
+    };
+
+    let versions = nvm.join("versions/node");
+    let installed = fs::read_dir(&versions)
+        .with_context(|| {
+            format!(
+                "list Node versions installed with nvm: {}",
+                versions.display()
+            )
+        })?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter_map(|name| {
+            let number = parse_version(name.strip_prefix('v')?)?;
+            Some((number, name))
+        });
+    // As with nvm, a partial version such as `20` means the newest release it covers.
+    let wanted = version
+        .split('.')
+        .filter(|part| !part.is_empty())
+        .map(str::parse)
+        .collect::<Result<Vec<u64>, _>>()
+        .map_err(|_| eyre!("unsupported Node version '{requested}' in .nvmrc"))?;
+    let newest = installed
+        .filter(|(number, _)| number.starts_with(&wanted))
+        .max()
+        .map(|(_, name)| name);
+    match newest {
+        Some(name) => Ok(Some(versions.join(name).join("bin"))),
+        None => bail!("Node {requested}, named in .nvmrc, isn't installed; run `nvm install`"),
+    }
+}
+
+fn parse_version(version: &str) -> Option<Vec<u64>> {
+    version.split('.').map(|part| part.parse().ok()).collect()
+}
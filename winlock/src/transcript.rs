@@ -0,0 +1,203 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Recordings of backend sessions.
+//!
+//! Transcripts use the [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/)
+//! format, so they can also be played back with `asciinema play`.
+
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+
+/// The file extension used for transcripts.
+pub const EXTENSION: &str = "cast";
+
+/// The header line of an asciicast v2 file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+    /// The format version; always 2.
+    pub version: u8,
+
+    /// The terminal width in columns.
+    pub width: u16,
+
+    /// The terminal height in rows.
+    pub height: u16,
+
+    /// When the recording started, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub timestamp: Option<i64>,
+
+    /// The command that was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+}
+
+/// Records terminal output to an asciicast file.
+#[derive(Debug)]
+pub struct Recorder {
+    file: BufWriter<File>,
+
+    started: Instant,
+
+    /// Bytes of a UTF-8 sequence split across reads, held until it is complete,
+    /// since asciicast events must be valid strings.
+    pending: Vec<u8>,
+}
+
+impl Recorder {
+    /// Start recording a terminal of the given size to `path`.
+    pub fn create(path: &Path, width: u16, height: u16, command: &str) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("create transcript directory: {}", parent.display()))?;
+        }
+        let file =
+            File::create(path).with_context(|| format!("create transcript: {}", path.display()))?;
+        let mut file = BufWriter::new(file);
+
+        let header = Header {
+            version: 2,
+            width,
+            height,
+            timestamp: Some(Timestamp::now().as_second()),
+            command: Some(String::from(command)),
+        };
+        serde_json::to_writer(&mut file, &header).context("write transcript header")?;
+        writeln!(file).context("write transcript header")?;
+
+        Ok(Self {
+            file,
+            started: Instant::now(),
+            pending: Vec::new(),
+        })
+    }
+
+    /// Record output written to the terminal.
+    pub fn output(&mut self, data: &[u8]) -> Result<()> {
+        self.pending.extend_from_slice(data);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        let chunk = self.pending.drain(..valid).collect::<Vec<_>>();
+        if chunk.is_empty() {
+            return Ok(());
+        }
+
+        self.event("o", &String::from_utf8_lossy(&chunk))
+    }
+
+    /// Record that the terminal was resized.
+    pub fn resize(&mut self, width: u16, height: u16) -> Result<()> {
+        self.event("r", &format!("{width}x{height}"))
+    }
+
+    /// Flush the recording to disk.
+    pub fn finish(mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            self.event("o", &String::from_utf8_lossy(&pending))?;
+        }
+        self.file.flush().context("flush transcript")
+    }
+
+    fn event(&mut self, kind: &str, data: &str) -> Result<()> {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        serde_json::to_writer(&mut self.file, &(elapsed, kind, data))
+            .context("write transcript event")?;
+        writeln!(self.file).context("write transcript event")
+    }
+}
+
+/// A recorded transcript.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transcript {
+    /// The recording's header.
+    pub header: Header,
+
+    /// Output events: the time since the recording started, and the output.
+    pub events: Vec<(Duration, String)>,
+}
+
+impl Transcript {
+    /// Read a transcript from disk.
+    pub fn read(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("open transcript: {}", path.display()))?;
+        let mut lines = BufReader::new(file).lines();
+
+        let header = lines
+            .next()
+            .ok_or_else(|| eyre!("transcript is empty: {}", path.display()))?
+            .context("read transcript header")?;
+        let header = serde_json::from_str::<Header>(&header).context("parse transcript header")?;
+        if header.version != 2 {
+            bail!("unsupported transcript version: {}", header.version);
+        }
+
+        let mut events = Vec::new();
+        for line in lines {
+            let line = line.context("read transcript event")?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (time, kind, data) = serde_json::from_str::<(f64, String, String)>(&line)
+                .context("parse transcript event")?;
+            if kind == "o" {
+                events.push((Duration::from_secs_f64(time.max(0.0)), data));
+            }
+        }
+
+        Ok(Self { header, events })
+    }
+
+    /// Play the transcript back to `out` in real time, adjusted by `speed`.
+    ///
+    /// Pauses longer than `max_idle` are shortened to it,
+    /// since watching an agent wait on the network isn't informative.
+    pub fn replay(&self, out: &mut impl Write, speed: f64, max_idle: Duration) -> Result<()> {
+        let mut previous = Duration::ZERO;
+        for (time, data) in &self.events {
+            let idle = time.saturating_sub(previous).min(max_idle);
+            previous = *time;
+            if speed > 0.0 {
+                thread::sleep(idle.div_f64(speed));
+            }
+            out.write_all(data.as_bytes()).context("write output")?;
+            out.flush().context("flush output")?;
+        }
+        Ok(())
+    }
+}
+
+/// The directory holding transcripts within a session's data directory.
+pub fn dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("transcripts")
+}
+
+/// List the transcripts in `dir`, oldest first.
+pub fn list(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut transcripts = fs::read_dir(dir)
+        .with_context(|| format!("read transcripts: {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("read transcripts: {}", dir.display()))?;
+    transcripts.retain(|path| path.extension().is_some_and(|ext| ext == EXTENSION));
+    transcripts.sort();
+    Ok(transcripts)
+}
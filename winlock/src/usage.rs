@@ -0,0 +1,205 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Token usage and estimated costs of backend conversations.
+//!
+//! Backends don't report usage in any common format, so it's read from
+//! wherever each kind of backend records it, as named by the profile's
+//! [`usage`](crate::config::Profile::usage). Claude Code, for example, logs
+//! every message of a conversation, with the tokens it used, to a JSON lines file
+//! named after the conversation's ID, which is the session's resume token.
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::ErrorKind,
+    ops::AddAssign,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{eyre, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Tokens used by a backend, with one model.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TokenUsage {
+    /// Tokens sent to the model, other than from the cache.
+    #[serde(default)]
+    pub input_tokens: u64,
+
+    /// Tokens the model generated.
+    #[serde(default)]
+    pub output_tokens: u64,
+
+    /// Tokens written to the prompt cache.
+    #[serde(default)]
+    pub cache_creation_input_tokens: u64,
+
+    /// Tokens read from the prompt cache.
+    #[serde(default)]
+    pub cache_read_input_tokens: u64,
+}
+
+impl TokenUsage {
+    /// All the tokens used, however they were billed.
+    pub fn total(&self) -> u64 {
+        self.input_tokens
+            + self.output_tokens
+            + self.cache_creation_input_tokens
+            + self.cache_read_input_tokens
+    }
+
+    /// The estimated cost in US dollars of using these tokens with `model`,
+    /// if the model's prices are known.
+    ///
+    /// This is only an estimate: it uses list prices, which change over time.
+    pub fn estimate_cost(&self, model: &str) -> Option<f64> {
+        let (input, output) = PRICES
+            .iter()
+            .find(|(fragment, _, _)| model.contains(fragment))
+            .map(|(_, input, output)| (input, output))?;
+        // Cache writes cost a quarter more than input, and reads a tenth as much.
+        let cost = self.input_tokens as f64 * input
+            + self.cache_creation_input_tokens as f64 * input * 1.25
+            + self.cache_read_input_tokens as f64 * input * 0.1
+            + self.output_tokens as f64 * output;
+        Some(cost / 1_000_000.0)
+    }
+}
+
+impl AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_creation_input_tokens += other.cache_creation_input_tokens;
+        self.cache_read_input_tokens += other.cache_read_input_tokens;
+    }
+}
+
+/// List prices in US dollars per million input and output tokens, by a fragment
+/// of the model name. The first matching fragment applies, so the most specific
+/// come first.
+const PRICES: &[(&str, f64, f64)] = &[
+    ("opus-4-1", 15.0, 75.0),
+    ("opus-4-0", 15.0, 75.0),
+    ("opus-4-2025", 15.0, 75.0),
+    ("3-opus", 15.0, 75.0),
+    ("opus", 5.0, 25.0),
+    ("sonnet", 3.0, 15.0),
+    ("3-5-haiku", 0.8, 4.0),
+    ("3-haiku", 0.25, 1.25),
+    ("haiku", 1.0, 5.0),
+];
+
+/// The usage of one conversation, by model.
+pub type ConversationUsage = BTreeMap<String, TokenUsage>;
+
+/// The total tokens used by many conversations, and their estimated total cost.
+///
+/// The cost is `None` if the price of any model used is unknown,
+/// since leaving it out would understate the total.
+pub fn total<'a>(
+    conversations: impl IntoIterator<Item = &'a ConversationUsage>,
+) -> (TokenUsage, Option<f64>) {
+    let mut tokens = TokenUsage::default();
+    let mut cost = Some(0.0);
+    for (model, usage) in conversations.into_iter().flatten() {
+        tokens += *usage;
+        cost = cost.zip(usage.estimate_cost(model)).map(|(a, b)| a + b);
+    }
+    (tokens, cost)
+}
+
+/// Where a kind of backend records its usage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageSource {
+    /// Claude Code's conversation logs, in `projects/` in its config directory.
+    Claude,
+}
+
+impl UsageSource {
+    /// Read the usage of `conversation` so far, or `None` if there's no record of it.
+    pub fn read(self, conversation: &str) -> Result<Option<ConversationUsage>> {
+        match self {
+            Self::Claude => read_claude(&claude_dir()?, conversation),
+        }
+    }
+
+    /// Like [`read`](Self::read), with the backend's records in `dir`.
+    pub fn read_in(self, dir: &Path, conversation: &str) -> Result<Option<ConversationUsage>> {
+        match self {
+            Self::Claude => read_claude(dir, conversation),
+        }
+    }
+}
+
+fn claude_dir() -> Result<PathBuf> {
+    match env::var_os("CLAUDE_CONFIG_DIR") {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => dirs::home_dir()
+            .map(|home| home.join(".claude"))
+            .ok_or_else(|| eyre!("unable to determine home directory")),
+    }
+}
+
+/// An entry in a Claude Code conversation log; only messages from the model have usage.
+#[derive(Debug, Deserialize)]
+struct ClaudeEntry {
+    message: Option<ClaudeMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClaudeMessage {
+    id: Option<String>,
+
+    model: Option<String>,
+
+    usage: Option<TokenUsage>,
+}
+
+/// Logs are kept in a directory per project, named after its path, but how the
+/// path is encoded is an implementation detail; the conversation ID is unique anyway.
+fn read_claude(dir: &Path, conversation: &str) -> Result<Option<ConversationUsage>> {
+    let projects = dir.join("projects");
+    let entries = match fs::read_dir(&projects) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).context(format!("read {}", projects.display())),
+    };
+    let name = format!("{conversation}.jsonl");
+    let Some(log) = entries
+        .flatten()
+        .map(|entry| entry.path().join(&name))
+        .find(|path| path.is_file())
+    else {
+        return Ok(None);
+    };
+    let content = fs::read_to_string(&log).with_context(|| format!("read {}", log.display()))?;
+
+    // A message with several parts is logged once per part, each with the
+    // message's full usage, so usage is counted once per message.
+    let mut messages = BTreeMap::new();
+    for line in content.lines() {
+        // Entries this doesn't understand, even malformed ones, aren't usage.
+        let Ok(entry) = serde_json::from_str::<ClaudeEntry>(line) else {
+            continue;
+        };
+        let Some(ClaudeMessage {
+            id: Some(id),
+            model: Some(model),
+            usage: Some(usage),
+        }) = entry.message
+        else {
+            continue;
+        };
+        messages.insert(id, (model, usage));
+    }
+
+    let mut usage = ConversationUsage::new();
+    for (model, tokens) in messages.into_values() {
+        *usage.entry(model).or_default() += tokens;
+    }
+    Ok(Some(usage))
+}
@@ -0,0 +1,477 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Version control systems used to track agent work.
+//!
+//! Each supported system is driven through its CLI rather than a library
+//! so that behavior (hooks, config, credential helpers) matches what the user
+//! gets at their terminal.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use color_eyre::eyre::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::branch::BranchName;
+
+mod git;
+mod hg;
+mod jj;
+pub mod lfs;
+mod none;
+
+pub use git::Git;
+pub use hg::Mercurial;
+pub use jj::Jujutsu;
+pub use none::NoVcs;
+
+/// Operations anna needs from a version control system.
+///
+/// "Branch" is used generically: systems without git-style branches
+/// implement it with their closest equivalent, such as bookmarks.
+pub trait Vcs: fmt::Debug + Send + Sync {
+    /// The kind of version control system this is.
+    fn kind(&self) -> VcsKind;
+
+    /// The identifier of the revision currently checked out in `dir`.
+    fn head(&self, dir: &Path) -> Result<String>;
+
+    /// Resolve a revision expression (such as a branch name) to a revision identifier.
+    fn resolve(&self, dir: &Path, revision: &str) -> Result<String>;
+
+    /// The name of the repository's default branch, suitable for [`Vcs::resolve`].
+    fn default_branch(&self, dir: &Path) -> Result<String>;
+
+    /// Whether `branch` exists in `dir`.
+    fn branch_exists(&self, dir: &Path, branch: &BranchName) -> Result<bool>;
+
+    /// Create `branch` at the `start` revision in `workspace` and switch to it.
+    fn create_branch(&self, workspace: &Path, branch: &BranchName, start: &str) -> Result<()>;
+
+    /// Switch `workspace` to the existing `branch`.
+    fn switch(&self, workspace: &Path, branch: &BranchName) -> Result<()>;
+
+    /// Commit every change in `workspace` to `branch`, returning the new revision.
+    ///
+    /// Returns `None` if there was nothing to commit.
+    fn commit_all(
+        &self,
+        workspace: &Path,
+        branch: &BranchName,
+        message: &str,
+    ) -> Result<Option<String>>;
+
+    /// Render the changes in `workspace` since the `base` revision,
+    /// including uncommitted changes.
+    fn diff(&self, workspace: &Path, base: &str) -> Result<String>;
+
+    /// Bring `branch` from `workspace` back into `project` without
+    /// changing what the user has checked out there. Fails with a [`ConflictReport`]
+    /// if the project's branch has changes that conflict with it.
+    fn merge_back(&self, project: &Path, workspace: &Path, branch: &BranchName) -> Result<()>;
+
+    /// Fetch `branch` from `workspace` into `project` as `reference`, such as
+    /// `refs/anna/feature`, without touching any branch or what's checked out.
+    /// The reference is moved to wherever the branch is, even backwards.
+    fn fetch_into(
+        &self,
+        _project: &Path,
+        _workspace: &Path,
+        _branch: &BranchName,
+        _reference: &str,
+    ) -> Result<()> {
+        bail!("{} does not support fetching into references", self.kind())
+    }
+
+    /// Record the state of `workspace`, including uncommitted changes,
+    /// without changing it; the returned revision can be passed to [`Vcs::restore`].
+    ///
+    /// Returns `None` if the system has no cheap way to do this,
+    /// in which case callers fall back to copying the workspace.
+    fn snapshot(&self, _workspace: &Path, _message: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Return `workspace` to the state recorded by [`Vcs::snapshot`],
+    /// discarding everything that changed since.
+    fn restore(&self, _workspace: &Path, _snapshot: &str) -> Result<()> {
+        bail!("{} does not support snapshots", self.kind())
+    }
+
+    /// The URL of the remote named `remote` in `dir`.
+    fn remote_url(&self, _dir: &Path, _remote: &str) -> Result<String> {
+        bail!("{} does not support remotes", self.kind())
+    }
+
+    /// Push `branch` from `workspace` to the remote named `remote`.
+    fn push(&self, _workspace: &Path, _remote: &str, _branch: &BranchName) -> Result<()> {
+        bail!("{} does not support pushing", self.kind())
+    }
+
+    /// Make `branch` in `workspace` track the branch of the same name on the remote
+    /// named `remote`, so that plain pushes and pulls there go to it. The remote's
+    /// branch need not exist yet; the first push creates it.
+    fn set_upstream(&self, _workspace: &Path, _remote: &str, _branch: &BranchName) -> Result<()> {
+        bail!("{} does not support tracking branches", self.kind())
+    }
+
+    /// Summarize the changes committed to `branch` since the `base` revision,
+    /// with a line for each changed file.
+    fn diff_stat(&self, _workspace: &Path, _base: &str, _branch: &BranchName) -> Result<String> {
+        bail!("{} does not support diff summaries", self.kind())
+    }
+
+    /// The identifier of the first commit in `dir`'s history, which stays the same
+    /// wherever the repository is moved or cloned to.
+    ///
+    /// Returns `None` if there are no commits yet, or the system can't tell
+    /// which commit came first.
+    fn root_commit(&self, _dir: &Path) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// The number of commits in `dir` reachable from the `to` revision but not from `from`.
+    fn count_commits(&self, _dir: &Path, _from: &str, _to: &str) -> Result<u64> {
+        bail!("{} does not support counting commits", self.kind())
+    }
+
+    /// Rebase `branch` in `workspace` onto the revision checked out in `project`,
+    /// returning that revision. Uncommitted changes are kept, and if the rebase
+    /// conflicts the workspace is left as it was and a [`ConflictReport`] returned.
+    fn rebase_onto_project(
+        &self,
+        _workspace: &Path,
+        _project: &Path,
+        _branch: &BranchName,
+    ) -> Result<String> {
+        bail!("{} does not support rebasing", self.kind())
+    }
+
+    /// Set `key` to `value` in the repository's own configuration in `workspace`,
+    /// leaving the user's global configuration alone.
+    fn set_config(&self, _workspace: &Path, _key: &str, _value: &str) -> Result<()> {
+        bail!(
+            "{} does not support setting repository configuration",
+            self.kind()
+        )
+    }
+
+    /// What deleting `workspace` would lose: changes not committed there, and commits
+    /// on `branch` since `base` that `project` doesn't have under any reference.
+    fn unsaved_work(
+        &self,
+        _workspace: &Path,
+        _project: &Path,
+        _branch: &BranchName,
+        _base: &str,
+    ) -> Result<UnsavedWork> {
+        bail!("{} does not support checking for unsaved work", self.kind())
+    }
+
+    /// Switch `workspace` to `branch` where it already exists: locally, or otherwise
+    /// as a remote-tracking branch, which a local branch is created to track.
+    /// Returns the revision checked out.
+    fn check_out_existing(&self, _workspace: &Path, branch: &BranchName) -> Result<String> {
+        bail!(
+            "{} does not support checking out existing branches such as '{branch}'",
+            self.kind()
+        )
+    }
+
+    /// Check out the submodules in `workspace` that aren't checked out yet, at the
+    /// commits recorded for them, returning their paths.
+    /// Submodules that are already checked out are left as they are, since a
+    /// different commit there may be work in progress copied from the project.
+    fn init_submodules(&self, _workspace: &Path) -> Result<Vec<PathBuf>> {
+        Ok(Vec::new())
+    }
+
+    /// Give `workspace`, a copy of `project`, a repository of its own where what was
+    /// copied only points at the project's, as a linked git worktree's `.git` file does,
+    /// so that nothing done in the workspace changes the user's repository.
+    fn own_repository(&self, _project: &Path, _workspace: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    /// Keep files tracked in `workspace` that were left out of copying it from `project`,
+    /// such as annaignored ones or withheld secrets, from reading as deleted, so that they
+    /// aren't committed as such. Changes to them, should they be written again, are then
+    /// ignored too. Returns those it couldn't hide, which the VCS still reads as deleted.
+    fn hide_left_out(&self, _project: &Path, _workspace: &Path) -> Result<Vec<PathBuf>> {
+        Ok(Vec::new())
+    }
+
+    /// Keep `path`, relative to the root of `workspace`, out of version control there
+    /// without changing anything that's checked in, such as `.gitignore`.
+    /// Ignoring a path again adds nothing, so it can be done every time it's written.
+    fn ignore(&self, _workspace: &Path, _path: &Path) -> Result<()> {
+        bail!("{} does not support ignoring files", self.kind())
+    }
+}
+
+/// Work in a workspace that deleting it would lose, from [`Vcs::unsaved_work`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnsavedWork {
+    /// Files with changes that aren't committed, untracked ones included,
+    /// relative to the workspace.
+    pub changed: Vec<PathBuf>,
+
+    /// The number of commits on the branch that the project doesn't have.
+    pub commits: u64,
+}
+
+impl UnsavedWork {
+    /// Whether there's nothing to lose.
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.commits == 0
+    }
+}
+
+impl fmt::Display for UnsavedWork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |n: usize, what: &str| match n {
+            1 => format!("1 {what}"),
+            n => format!("{n} {what}s"),
+        };
+        let parts = [
+            (!self.changed.is_empty()).then(|| plural(self.changed.len(), "uncommitted change")),
+            (self.commits > 0).then(|| {
+                let commits = usize::try_from(self.commits).unwrap_or(usize::MAX);
+                format!("{} not in the project", plural(commits, "commit"))
+            }),
+        ];
+        match parts {
+            [None, None] => f.write_str("nothing unsaved"),
+            [Some(one), None] | [None, Some(one)] => f.write_str(&one),
+            [Some(changed), Some(commits)] => write!(f, "{changed} and {commits}"),
+        }
+    }
+}
+
+/// A merge or rebase that was given up on because changes on both sides conflict,
+/// returned as the error of [`Vcs::merge_back`] and [`Vcs::rebase_onto_project`].
+/// Whatever was being changed is left as it was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictReport {
+    /// What was being done.
+    pub operation: ConflictOperation,
+
+    /// The branch being merged or rebased.
+    pub branch: BranchName,
+
+    /// The revision the branch was being merged into or rebased onto.
+    pub onto: String,
+
+    /// The commit of the branch that conflicted, where there's a single one,
+    /// as when a rebase stops partway through.
+    pub commit: Option<ConflictingCommit>,
+
+    /// The files that conflict, in order.
+    pub files: Vec<ConflictedFile>,
+}
+
+impl fmt::Display for ConflictReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            operation,
+            branch,
+            onto,
+            ..
+        } = self;
+        let onto = onto.get(..12).unwrap_or(onto);
+        let files = match self.files.len() {
+            1 => String::from("1 file"),
+            n => format!("{n} files"),
+        };
+        write!(f, "{operation} '{branch}' onto {onto} conflicts in {files}")
+    }
+}
+
+impl std::error::Error for ConflictReport {}
+
+/// What a [`ConflictReport`] was made for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConflictOperation {
+    /// Bringing the branch back into the project.
+    Merge,
+
+    /// Moving the branch onto the revision checked out in the project.
+    Rebase,
+}
+
+impl fmt::Display for ConflictOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConflictOperation::Merge => "merging",
+            ConflictOperation::Rebase => "rebasing",
+        })
+    }
+}
+
+/// The commit a [`ConflictReport`] is about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictingCommit {
+    /// The commit's ID.
+    pub id: String,
+
+    /// The first line of the commit's message.
+    pub subject: String,
+}
+
+/// A file in a [`ConflictReport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictedFile {
+    /// The file, relative to the workspace.
+    pub path: PathBuf,
+
+    /// The number of places in the file where both sides changed it differently,
+    /// or 0 where the conflict is over the whole file, such as one side deleting it.
+    pub hunks: usize,
+}
+
+/// The number of conflicts marked in `content`, as left by a merge in a conflicted file.
+fn count_conflict_markers(content: &str) -> usize {
+    content
+        .lines()
+        .filter(|line| line.starts_with("<<<<<<< ") || *line == "<<<<<<<")
+        .count()
+}
+
+/// The kinds of version control system anna supports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VcsKind {
+    /// Git; the agent works on a new branch.
+    #[default]
+    Git,
+
+    /// Mercurial; the agent works on a new bookmark.
+    #[serde(rename = "hg")]
+    Mercurial,
+
+    /// Jujutsu; the agent works on a new bookmark.
+    #[serde(rename = "jj")]
+    Jujutsu,
+
+    /// No version control: the workspace is a plain directory
+    /// and the branch name only identifies the session.
+    None,
+}
+
+impl VcsKind {
+    /// Detect the version control system used by the project.
+    ///
+    /// Only the project root is considered: a plain directory nested inside
+    /// a repository isn't a repository once it is copied into a workspace.
+    pub fn detect(project: &Path) -> Self {
+        // Jujutsu repositories are often colocated with git,
+        // in which case jj is the tool actually in use.
+        if project.join(".jj").is_dir() {
+            VcsKind::Jujutsu
+        } else if project.join(".hg").is_dir() {
+            VcsKind::Mercurial
+        } else if project.join(".git").exists() {
+            VcsKind::Git
+        } else {
+            VcsKind::None
+        }
+    }
+
+    /// The implementation of this version control system.
+    pub fn vcs(self) -> &'static dyn Vcs {
+        match self {
+            VcsKind::Git => &Git,
+            VcsKind::Mercurial => &Mercurial,
+            VcsKind::Jujutsu => &Jujutsu,
+            VcsKind::None => &NoVcs,
+        }
+    }
+}
+
+impl fmt::Display for VcsKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            VcsKind::Git => "git",
+            VcsKind::Mercurial => "hg",
+            VcsKind::Jujutsu => "jj",
+            VcsKind::None => "none",
+        })
+    }
+}
+
+/// Add `line` to the end of the file at `path` unless it's already there,
+/// creating the file and its directory if needed.
+fn append_line(path: &Path, line: &str) -> Result<()> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).context(format!("read {}", path.display())),
+    };
+    if content.lines().any(|existing| existing == line) {
+        return Ok(());
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+    }
+    let separator = if content.is_empty() || content.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    fs::write(path, format!("{content}{separator}{line}\n"))
+        .with_context(|| format!("write {}", path.display()))
+}
+
+/// Whether `a` and `b` are both files with the same content.
+fn same_file_content(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a_meta), Ok(b_meta)) if a_meta.is_file() && a_meta.len() == b_meta.len() => {
+            matches!((fs::read(a), fs::read(b)), (Ok(a), Ok(b)) if a == b)
+        }
+        _ => false,
+    }
+}
+
+/// Run `program` with the provided arguments in `dir` as a yes/no question:
+/// exit code 0 means yes, 1 means no, and anything else is an error.
+fn probe(program: &str, dir: &Path, args: &[&str]) -> Result<bool> {
+    let output = Command::new(program)
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .with_context(|| format!("run {program} {}", args.join(" ")))?;
+
+    match output.status.code() {
+        Some(0) => Ok(true),
+        Some(1) => Ok(false),
+        _ => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!("{program} {} failed: {}", args.join(" "), stderr.trim());
+        }
+    }
+}
+
+/// Run `program` with the provided arguments in `dir`, returning trimmed stdout.
+fn run(program: &str, dir: &Path, args: &[&str]) -> Result<String> {
+    run_command(Command::new(program).current_dir(dir), program, args)
+}
+
+/// Run a prepared command with the provided arguments, returning trimmed stdout.
+fn run_command(command: &mut Command, program: &str, args: &[&str]) -> Result<String> {
+    let output = command
+        .args(args)
+        .output()
+        .with_context(|| format!("run {program} {}", args.join(" ")))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{program} {} failed: {}", args.join(" "), stderr.trim());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,606 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::BTreeSet,
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use color_eyre::eyre::{bail, Context, Result};
+use uuid::Uuid;
+
+use super::{
+    ConflictOperation, ConflictReport, ConflictedFile, ConflictingCommit, UnsavedWork, Vcs, VcsKind,
+};
+use crate::branch::BranchName;
+
+/// Git.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Git;
+
+impl Vcs for Git {
+    fn kind(&self) -> VcsKind {
+        VcsKind::Git
+    }
+
+    fn head(&self, dir: &Path) -> Result<String> {
+        git(dir, &["rev-parse", "HEAD"])
+    }
+
+    fn branch_exists(&self, dir: &Path, branch: &BranchName) -> Result<bool> {
+        let reference = format!("refs/heads/{branch}");
+        super::probe("git", dir, &["show-ref", "--verify", "--quiet", &reference])
+    }
+
+    fn resolve(&self, dir: &Path, revision: &str) -> Result<String> {
+        let revision = format!("{revision}^{{commit}}");
+        git(dir, &["rev-parse", "--verify", "--quiet", &revision])
+    }
+
+    fn default_branch(&self, dir: &Path) -> Result<String> {
+        // The remote's HEAD is authoritative when it's known; it's only set for clones
+        // (or after `git remote set-head`), so fall back to the configured default for
+        // new repositories and finally to the conventional names.
+        let remote = git(
+            dir,
+            &[
+                "symbolic-ref",
+                "--quiet",
+                "--short",
+                "refs/remotes/origin/HEAD",
+            ],
+        )
+        .ok()
+        .and_then(|head| head.strip_prefix("origin/").map(String::from));
+        let configured = git(dir, &["config", "--get", "init.defaultBranch"]).ok();
+        let candidates = remote
+            .into_iter()
+            .chain(configured)
+            .chain([String::from("main"), String::from("master")]);
+
+        for candidate in candidates {
+            let local = format!("refs/heads/{candidate}");
+            if super::probe("git", dir, &["show-ref", "--verify", "--quiet", &local])? {
+                return Ok(candidate);
+            }
+            let tracking = format!("refs/remotes/origin/{candidate}");
+            if super::probe("git", dir, &["show-ref", "--verify", "--quiet", &tracking])? {
+                return Ok(format!("origin/{candidate}"));
+            }
+        }
+
+        bail!(
+            "unable to determine the default branch in {}",
+            dir.display()
+        )
+    }
+
+    fn create_branch(&self, workspace: &Path, branch: &BranchName, start: &str) -> Result<()> {
+        git(workspace, &["switch", "--create", branch.as_str(), start]).map(drop)
+    }
+
+    fn switch(&self, workspace: &Path, branch: &BranchName) -> Result<()> {
+        git(workspace, &["switch", branch.as_str()]).map(drop)
+    }
+
+    fn commit_all(
+        &self,
+        workspace: &Path,
+        _branch: &BranchName,
+        message: &str,
+    ) -> Result<Option<String>> {
+        git(workspace, &["add", "--all"])?;
+        if git(workspace, &["status", "--porcelain"])?.is_empty() {
+            return Ok(None);
+        }
+
+        // Hooks are skipped because the point of committing here is to make sure
+        // the work is never lost; a failing lint hook must not prevent that.
+        git(workspace, &["commit", "--no-verify", "--message", message])?;
+        self.head(workspace).map(Some)
+    }
+
+    fn diff(&self, workspace: &Path, base: &str) -> Result<String> {
+        // Untracked files are otherwise invisible to `git diff`; intent-to-add makes
+        // them show up without staging their content, in a throwaway index so that
+        // what the agent has staged, and left unstaged, is left as it is.
+        with_temporary_index(workspace, |with_index| {
+            with_index(&["add", "--all", "--intent-to-add"])?;
+            with_index(&["diff", base])
+        })
+    }
+
+    fn merge_back(&self, project: &Path, workspace: &Path, branch: &BranchName) -> Result<()> {
+        let workspace = workspace.to_string_lossy();
+        let refspec = format!("{branch}:{branch}");
+        let Err(err) = git(project, &["fetch", &workspace, &refspec]) else {
+            return Ok(());
+        };
+        // Most likely the project's branch moved on, so the fetch couldn't fast-forward it;
+        // whether the two would conflict is worth knowing before merging them by hand.
+        match merge_conflict(project, &workspace, branch) {
+            Some(report) => Err(report.into()),
+            None => Err(err),
+        }
+    }
+
+    fn fetch_into(
+        &self,
+        project: &Path,
+        workspace: &Path,
+        branch: &BranchName,
+        reference: &str,
+    ) -> Result<()> {
+        let workspace = workspace.to_string_lossy();
+        // Forced, since the agent may have rewritten its branch since the last fetch.
+        let refspec = format!("+refs/heads/{branch}:{reference}");
+        git(project, &["fetch", "--no-tags", &workspace, &refspec]).map(drop)
+    }
+
+    fn snapshot(&self, workspace: &Path, message: &str) -> Result<Option<String>> {
+        // Staging into a throwaway index captures untracked files too,
+        // without disturbing whatever the agent has staged itself.
+        let tree = with_temporary_index(workspace, |with_index| {
+            with_index(&["add", "--all"])?;
+            with_index(&["write-tree"])
+        })?;
+
+        let commit = git(
+            workspace,
+            &["commit-tree", &tree, "-p", "HEAD", "-m", message],
+        )?;
+        // Referenced so that garbage collection doesn't prune it.
+        let reference = format!("refs/anna/snapshots/{commit}");
+        git(workspace, &["update-ref", &reference, &commit])?;
+        Ok(Some(commit))
+    }
+
+    fn restore(&self, workspace: &Path, snapshot: &str) -> Result<()> {
+        // The branch goes back to where it was when the snapshot was taken,
+        // then the snapshot's files are checked out on top and unstaged,
+        // leaving uncommitted changes as uncommitted as they were.
+        let hidden = hidden_paths(workspace)?;
+        let parent = format!("{snapshot}^");
+        git(workspace, &["reset", "--quiet", "--hard", &parent])?;
+        git(workspace, &["clean", "--quiet", "--force", "-d"])?;
+        git(workspace, &["read-tree", "-u", "--reset", snapshot])?;
+        git(workspace, &["reset", "--quiet"])?;
+        // Older snapshots left hidden files out, which dropped them from the index,
+        // and with them the bits keeping git from taking them to be deleted.
+        let tracked = split_paths(&git(workspace, &["ls-files", "-z"])?).collect::<BTreeSet<_>>();
+        let hidden = hidden
+            .into_iter()
+            .filter(|path| tracked.contains(path))
+            .collect::<Vec<_>>();
+        hide(workspace, &hidden)
+    }
+
+    fn remote_url(&self, dir: &Path, remote: &str) -> Result<String> {
+        git(dir, &["remote", "get-url", remote])
+    }
+
+    fn push(&self, workspace: &Path, remote: &str, branch: &BranchName) -> Result<()> {
+        let refspec = format!("refs/heads/{branch}:refs/heads/{branch}");
+        git(workspace, &["push", "--quiet", remote, &refspec]).map(drop)
+    }
+
+    fn set_upstream(&self, workspace: &Path, remote: &str, branch: &BranchName) -> Result<()> {
+        self.remote_url(workspace, remote)
+            .with_context(|| format!("find remote '{remote}'"))?;
+        // Set directly rather than with `git branch --set-upstream-to`, which refuses
+        // until the remote has the branch, and it usually won't until the first push.
+        let merge = format!("refs/heads/{branch}");
+        self.set_config(workspace, &format!("branch.{branch}.remote"), remote)?;
+        self.set_config(workspace, &format!("branch.{branch}.merge"), &merge)
+    }
+
+    fn diff_stat(&self, workspace: &Path, base: &str, branch: &BranchName) -> Result<String> {
+        git(workspace, &["diff", "--stat", base, branch.as_str()])
+    }
+
+    fn root_commit(&self, dir: &Path) -> Result<Option<String>> {
+        if !super::probe("git", dir, &["rev-parse", "--verify", "--quiet", "HEAD"])? {
+            return Ok(None);
+        }
+        // Merged histories have several roots; the oldest is listed last.
+        let roots = git(dir, &["rev-list", "--max-parents=0", "HEAD"])?;
+        Ok(roots.lines().last().map(String::from))
+    }
+
+    fn count_commits(&self, dir: &Path, from: &str, to: &str) -> Result<u64> {
+        let range = format!("{from}..{to}");
+        let count = git(dir, &["rev-list", "--count", &range])?;
+        count
+            .parse()
+            .with_context(|| format!("parse commit count: {count}"))
+    }
+
+    fn rebase_onto_project(
+        &self,
+        workspace: &Path,
+        project: &Path,
+        branch: &BranchName,
+    ) -> Result<String> {
+        let project = project.to_string_lossy();
+        git(workspace, &["fetch", "--quiet", &project, "HEAD"])?;
+        let onto = self.resolve(workspace, "FETCH_HEAD")?;
+        let rebased = git(
+            workspace,
+            &["rebase", "--quiet", "--autostash", &onto, branch.as_str()],
+        );
+        if let Err(err) = rebased {
+            let conflict = rebase_conflict(workspace, branch, &onto);
+            // Best effort: if there's no rebase in progress there's nothing to undo.
+            let _ = git(workspace, &["rebase", "--abort"]);
+            return match conflict {
+                Some(report) => Err(report.into()),
+                None => Err(err).context(format!("rebase '{branch}' onto {onto}")),
+            };
+        }
+        Ok(onto)
+    }
+
+    fn set_config(&self, workspace: &Path, key: &str, value: &str) -> Result<()> {
+        git(workspace, &["config", "--local", key, value]).map(drop)
+    }
+
+    fn unsaved_work(
+        &self,
+        workspace: &Path,
+        project: &Path,
+        branch: &BranchName,
+        base: &str,
+    ) -> Result<UnsavedWork> {
+        let modified = git(workspace, &["diff", "--name-only", "-z", "HEAD"])?;
+        let untracked = git(
+            workspace,
+            &["ls-files", "--others", "--exclude-standard", "-z"],
+        )?;
+        let changed = modified
+            .split('\0')
+            .chain(untracked.split('\0'))
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            // Uncommitted files copied from the project, and left as they were,
+            // are still in the project.
+            .filter(|path| !super::same_file_content(&workspace.join(path), &project.join(path)))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        let reference = format!("refs/heads/{branch}");
+        let head = git(workspace, &["rev-parse", "--verify", "--quiet", &reference]);
+        let commits = match head {
+            // Nothing is lost if the project has the branch's commits under any
+            // reference; that includes a missing object, which is an error here.
+            Ok(head) => {
+                let kept = git(
+                    project,
+                    &[
+                        "for-each-ref",
+                        "--count=1",
+                        "--contains",
+                        &head,
+                        "--format=%(refname)",
+                    ],
+                )
+                .is_ok_and(|refs| !refs.is_empty());
+                match kept {
+                    true => 0,
+                    false => self.count_commits(workspace, base, &head)?,
+                }
+            }
+            Err(_) => 0,
+        };
+        Ok(UnsavedWork { changed, commits })
+    }
+
+    fn check_out_existing(&self, workspace: &Path, branch: &BranchName) -> Result<String> {
+        if !self.branch_exists(workspace, branch)? {
+            // Matched per remote, since the remote's name and the branch's may both
+            // contain slashes.
+            let mut remotes = Vec::new();
+            for remote in git(workspace, &["remote"])?.lines() {
+                let upstream = format!("{remote}/{branch}");
+                let reference = format!("refs/remotes/{upstream}");
+                if super::probe(
+                    "git",
+                    workspace,
+                    &["show-ref", "--verify", "--quiet", &reference],
+                )? {
+                    remotes.push(upstream);
+                }
+            }
+            // The same branch on several remotes is ambiguous, unless one is origin,
+            // which is where branches are conventionally shared.
+            let origin = format!("origin/{branch}");
+            let upstream = match remotes.as_slice() {
+                [] => bail!("branch '{branch}' doesn't exist locally or on any remote"),
+                [upstream] => upstream,
+                _ if remotes.contains(&origin) => &origin,
+                _ => bail!(
+                    "branch '{branch}' exists on several remotes: {}",
+                    remotes.join(", ")
+                ),
+            };
+            git(workspace, &["branch", "--track", branch.as_str(), upstream])?;
+        }
+        self.switch(workspace, branch)?;
+        self.head(workspace)
+    }
+
+    fn init_submodules(&self, workspace: &Path) -> Result<Vec<PathBuf>> {
+        if !workspace.join(".gitmodules").is_file() {
+            return Ok(Vec::new());
+        }
+        // Uninitialized submodules are listed with a leading '-', then their recorded
+        // commit and path; other lines end with a description of what's checked out.
+        let status = git(workspace, &["submodule", "status"])?;
+        let missing = status
+            .lines()
+            .filter_map(|line| line.strip_prefix('-'))
+            .filter_map(|line| line.split_once(' '))
+            .map(|(_, path)| String::from(path))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut args = vec!["submodule", "update", "--init", "--recursive", "--"];
+        args.extend(missing.iter().map(String::as_str));
+        git(workspace, &args)?;
+        Ok(missing.into_iter().map(PathBuf::from).collect())
+    }
+
+    fn own_repository(&self, project: &Path, workspace: &Path) -> Result<()> {
+        // Linked worktrees and submodules have a `.git` file naming the repository
+        // they're checked out from, rather than a repository of their own.
+        let git_dir = workspace.join(".git");
+        if !git_dir.is_file() {
+            return Ok(());
+        }
+        let git_path = |args: &[&str]| {
+            let mut args = args.to_vec();
+            args.splice(0..0, ["rev-parse", "--path-format=absolute"]);
+            git(project, &args).map(PathBuf::from)
+        };
+        let common = git_path(&["--git-common-dir"])?;
+        let index = git_path(&["--git-path", "index"])?;
+        let head = git(project, &["symbolic-ref", "--quiet", "HEAD"])
+            .or_else(|_| git(project, &["rev-parse", "HEAD"]));
+        fs::remove_file(&git_dir).with_context(|| format!("remove {}", git_dir.display()))?;
+
+        // Mirrored, so that it has every reference the project's repository has,
+        // as a copy of a repository would; local clones hard link objects where
+        // they can, and those are never modified.
+        let destination = git_dir.to_string_lossy();
+        git(
+            workspace,
+            &[
+                "clone",
+                "--quiet",
+                "--mirror",
+                "--",
+                &common.to_string_lossy(),
+                &destination,
+            ],
+        )
+        .context("clone the project's repository into the workspace")?;
+        // The repository's own configuration, with its remotes, replaces the one
+        // mirroring it; submodules' point their repository at the project.
+        fs::copy(common.join("config"), git_dir.join("config"))
+            .context("copy the project's repository configuration")?;
+        self.set_config(workspace, "core.bare", "false")?;
+        let _ = git(
+            workspace,
+            &["config", "--local", "--unset-all", "core.worktree"],
+        );
+        match head {
+            Ok(head) if head.starts_with("refs/") => {
+                git(workspace, &["symbolic-ref", "HEAD", &head])?;
+            }
+            Ok(head) => {
+                git(workspace, &["update-ref", "--no-deref", "HEAD", &head])?;
+            }
+            // Nothing is committed yet, so there's nothing to check out.
+            Err(_) => {}
+        }
+        // Carries over what's staged, leaving uncommitted changes as they were.
+        if index.is_file() {
+            fs::copy(&index, git_dir.join("index")).context("copy the project's index")?;
+        }
+        Ok(())
+    }
+
+    fn hide_left_out(&self, project: &Path, workspace: &Path) -> Result<Vec<PathBuf>> {
+        // Only those the project has: files deleted there are deleted in the copy too.
+        let deleted = git(workspace, &["ls-files", "-z", "--deleted"])?;
+        let left_out = split_paths(&deleted)
+            .filter(|path| fs::symlink_metadata(project.join(path)).is_ok())
+            .collect::<Vec<_>>();
+        hide(workspace, &left_out)?;
+        Ok(Vec::new())
+    }
+
+    fn ignore(&self, workspace: &Path, path: &Path) -> Result<()> {
+        let exclude = git(workspace, &["rev-parse", "--git-path", "info/exclude"])?;
+        super::append_line(
+            &workspace.join(exclude),
+            &format!("/{}", path.to_string_lossy()),
+        )
+    }
+}
+
+/// What conflicts in the rebase of `branch` stopped in `workspace`, if it stopped on conflicts.
+fn rebase_conflict(workspace: &Path, branch: &BranchName, onto: &str) -> Option<ConflictReport> {
+    let unmerged = git(workspace, &["diff", "--name-only", "--diff-filter=U", "-z"]).ok()?;
+    let files = split_paths(&unmerged)
+        .map(|path| ConflictedFile {
+            hunks: fs::read_to_string(workspace.join(&path))
+                .map(|content| super::count_conflict_markers(&content))
+                .unwrap_or_default(),
+            path,
+        })
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        return None;
+    }
+    let commit = git(
+        workspace,
+        &["log", "-1", "--format=%H%x00%s", "REBASE_HEAD"],
+    )
+    .ok()
+    .and_then(|commit| {
+        let (id, subject) = commit.split_once('\0')?;
+        Some(ConflictingCommit {
+            id: String::from(id),
+            subject: String::from(subject),
+        })
+    });
+    Some(ConflictReport {
+        operation: ConflictOperation::Rebase,
+        branch: branch.clone(),
+        onto: String::from(onto),
+        commit,
+        files,
+    })
+}
+
+/// What would conflict in merging `branch` from `workspace` into the project's `branch`,
+/// if anything would, found without touching either.
+fn merge_conflict(project: &Path, workspace: &str, branch: &BranchName) -> Option<ConflictReport> {
+    let reference = format!("refs/heads/{branch}");
+    let onto = git(project, &["rev-parse", "--verify", "--quiet", &reference]).ok()?;
+    // Fetched into a reference of its own rather than FETCH_HEAD, which is the user's,
+    // and which another fetch could replace before it's read.
+    let fetched = format!("refs/anna/merge-checks/{}", Uuid::new_v4().simple());
+    let refspec = format!("+refs/heads/{branch}:{fetched}");
+    git(
+        project,
+        &["fetch", "--quiet", "--no-tags", workspace, &refspec],
+    )
+    .ok()?;
+    // Needs git 2.38 or later; with older ones there's just no report.
+    let output = Command::new("git")
+        .args([
+            "merge-tree",
+            "--write-tree",
+            "--name-only",
+            "--no-messages",
+            "-z",
+            &onto,
+            &fetched,
+        ])
+        .current_dir(project)
+        .output();
+    let _ = git(project, &["update-ref", "-d", &fetched]);
+    let output = output.ok()?;
+    // Exits with 1 if the merge conflicts, and anything else if it couldn't be tried.
+    if output.status.code() != Some(1) {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (tree, conflicted) = stdout.split_once('\0')?;
+    let files = split_paths(conflicted)
+        .map(|path| {
+            // The merged tree has the conflicted files with conflict markers in.
+            let object = format!("{tree}:{}", path.display());
+            let hunks = git(project, &["cat-file", "blob", &object])
+                .map(|content| super::count_conflict_markers(&content))
+                .unwrap_or_default();
+            ConflictedFile { path, hunks }
+        })
+        .collect::<Vec<_>>();
+    Some(ConflictReport {
+        operation: ConflictOperation::Merge,
+        branch: branch.clone(),
+        onto,
+        commit: None,
+        files,
+    })
+}
+
+/// The paths in NUL-separated output, such as from `git diff -z`, deduplicated and in order.
+fn split_paths(output: &str) -> impl Iterator<Item = PathBuf> {
+    output
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+}
+
+/// Keep git from taking the tracked `paths` missing from `workspace` to be deleted.
+fn hide(workspace: &Path, paths: &[PathBuf]) -> Result<()> {
+    // In batches, so that the command line stays within the system's limits.
+    for batch in paths.chunks(1000) {
+        let paths = batch
+            .iter()
+            .map(|path| path.to_string_lossy())
+            .collect::<Vec<_>>();
+        let mut args = vec!["update-index", "--skip-worktree", "--"];
+        args.extend(paths.iter().map(AsRef::as_ref));
+        git(workspace, &args).context("hide files left out of the workspace from git")?;
+    }
+    Ok(())
+}
+
+/// The paths in `workspace` [hidden](hide) from git.
+fn hidden_paths(workspace: &Path) -> Result<Vec<PathBuf>> {
+    // Skip-worktree entries are tagged `S`, or `s` when also assumed unchanged.
+    let listed = git(workspace, &["ls-files", "-z", "-v"])?;
+    let hidden = listed
+        .split('\0')
+        .filter_map(|entry| {
+            entry
+                .strip_prefix("S ")
+                .or_else(|| entry.strip_prefix("s "))
+        })
+        .map(PathBuf::from)
+        .collect();
+    Ok(hidden)
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    super::run("git", dir, args)
+}
+
+/// Call `f` with a way to run git in `dir` against a throwaway index,
+/// which is deleted afterwards.
+///
+/// It starts out as a copy of `dir`'s own index, or of `HEAD` if there's none, so that
+/// files hidden from git with `--skip-worktree` stay as they are rather than deleted.
+fn with_temporary_index<T>(
+    dir: &Path,
+    f: impl FnOnce(&dyn Fn(&[&str]) -> Result<String>) -> Result<T>,
+) -> Result<T> {
+    let index = env::temp_dir().join(format!("anna-index-{}", Uuid::new_v4().simple()));
+    let with_index = |args: &[&str]| {
+        super::run_command(
+            Command::new("git")
+                .current_dir(dir)
+                .env("GIT_INDEX_FILE", &index),
+            "git",
+            args,
+        )
+    };
+    let result = seed_index(dir, &index, &with_index).and_then(|()| f(&with_index));
+    let _ = fs::remove_file(&index);
+    result
+}
+
+/// Fill the throwaway `index` that `with_index` runs git against from `dir`'s own.
+fn seed_index(
+    dir: &Path,
+    index: &Path,
+    with_index: &dyn Fn(&[&str]) -> Result<String>,
+) -> Result<()> {
+    let own = dir.join(git(dir, &["rev-parse", "--git-path", "index"])?);
+    if own.is_file() {
+        fs::copy(&own, index).context("copy the index")?;
+        return Ok(());
+    }
+    with_index(&["read-tree", "HEAD"]).map(drop)
+}
@@ -0,0 +1,201 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{bail, Result};
+
+use super::{UnsavedWork, Vcs, VcsKind};
+use crate::branch::BranchName;
+
+/// Mercurial, using bookmarks as branches.
+///
+/// Named branches in Mercurial are permanent and recorded in every commit,
+/// which is far heavier than what an agent session needs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Mercurial;
+
+impl Vcs for Mercurial {
+    fn kind(&self) -> VcsKind {
+        VcsKind::Mercurial
+    }
+
+    fn head(&self, dir: &Path) -> Result<String> {
+        hg(dir, &["log", "--rev", ".", "--template", "{node}"])
+    }
+
+    fn branch_exists(&self, dir: &Path, branch: &BranchName) -> Result<bool> {
+        let bookmarks = hg(dir, &["bookmarks", "--template", "{bookmark}\n"])?;
+        Ok(bookmarks
+            .lines()
+            .any(|bookmark| bookmark == branch.as_str()))
+    }
+
+    fn resolve(&self, dir: &Path, revision: &str) -> Result<String> {
+        hg(
+            dir,
+            &[
+                "log",
+                "--rev",
+                revision,
+                "--limit",
+                "1",
+                "--template",
+                "{node}",
+            ],
+        )
+    }
+
+    fn default_branch(&self, _dir: &Path) -> Result<String> {
+        // Every Mercurial repository has a branch named "default";
+        // as a revision it refers to that branch's tip.
+        Ok(String::from("default"))
+    }
+
+    fn create_branch(&self, workspace: &Path, branch: &BranchName, start: &str) -> Result<()> {
+        hg(workspace, &["update", "--rev", start])?;
+        hg(workspace, &["bookmark", branch.as_str()]).map(drop)
+    }
+
+    fn switch(&self, workspace: &Path, branch: &BranchName) -> Result<()> {
+        hg(workspace, &["update", branch.as_str()]).map(drop)
+    }
+
+    fn commit_all(
+        &self,
+        workspace: &Path,
+        _branch: &BranchName,
+        message: &str,
+    ) -> Result<Option<String>> {
+        hg(workspace, &["addremove"])?;
+        if hg(workspace, &["status"])?.is_empty() {
+            return Ok(None);
+        }
+
+        // The active bookmark advances with the commit.
+        hg(workspace, &["commit", "--message", message])?;
+        self.head(workspace).map(Some)
+    }
+
+    fn diff(&self, workspace: &Path, base: &str) -> Result<String> {
+        hg(workspace, &["addremove"])?;
+        hg(workspace, &["diff", "--rev", base])
+    }
+
+    fn merge_back(&self, project: &Path, workspace: &Path, branch: &BranchName) -> Result<()> {
+        let workspace = workspace.to_string_lossy();
+        hg(
+            project,
+            &["pull", "--bookmark", branch.as_str(), &workspace],
+        )
+        .map(drop)
+    }
+
+    fn root_commit(&self, dir: &Path) -> Result<Option<String>> {
+        // Revision 0 is the first commit, but naming it fails in empty repositories.
+        let node = hg(
+            dir,
+            &["log", "--rev", "first(all())", "--template", "{node}"],
+        )?;
+        Ok(Some(node).filter(|node| !node.is_empty()))
+    }
+
+    fn count_commits(&self, dir: &Path, from: &str, to: &str) -> Result<u64> {
+        let range = format!("only({to}, {from})");
+        let nodes = hg(dir, &["log", "--rev", &range, "--template", "{node}\n"])?;
+        Ok(nodes.lines().count() as u64)
+    }
+
+    fn unsaved_work(
+        &self,
+        workspace: &Path,
+        project: &Path,
+        branch: &BranchName,
+        base: &str,
+    ) -> Result<UnsavedWork> {
+        // Missing files aren't listed: they include those left out of the workspace,
+        // which the project still has.
+        let status = hg(
+            workspace,
+            &[
+                "status",
+                "--modified",
+                "--added",
+                "--removed",
+                "--unknown",
+                "--no-status",
+                "--print0",
+            ],
+        )?;
+        let changed = status
+            .split('\0')
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .filter(|path| !super::same_file_content(&workspace.join(path), &project.join(path)))
+            .collect();
+
+        let commits = match self.branch_exists(workspace, branch)? {
+            // A changeset the project has comes with all its ancestors,
+            // and looking up one it doesn't have fails.
+            true => {
+                let head = self.resolve(workspace, branch.as_str())?;
+                let kept = hg(project, &["log", "--rev", &head, "--template", "{node}"]).is_ok();
+                match kept {
+                    true => 0,
+                    false => self.count_commits(workspace, base, &head)?,
+                }
+            }
+            false => 0,
+        };
+        Ok(UnsavedWork { changed, commits })
+    }
+
+    fn own_repository(&self, project: &Path, workspace: &Path) -> Result<()> {
+        // Made with `hg share`, whose store is the shared repository's.
+        if workspace.join(".hg/sharedpath").is_file() {
+            bail!(
+                "{} shares another Mercurial repository's store, which the workspace would \
+                 share too; start the session from that repository",
+                project.display()
+            );
+        }
+        Ok(())
+    }
+
+    fn hide_left_out(&self, project: &Path, workspace: &Path) -> Result<Vec<PathBuf>> {
+        // Mercurial has nothing like git's skip-worktree, so they can only be reported.
+        // Only those the project has: files deleted there are deleted in the copy too.
+        let deleted = hg(
+            workspace,
+            &["status", "--deleted", "--no-status", "--print0"],
+        )?;
+        Ok(deleted
+            .split('\0')
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .filter(|path| fs::symlink_metadata(project.join(path)).is_ok())
+            .collect())
+    }
+
+    fn ignore(&self, workspace: &Path, path: &Path) -> Result<()> {
+        // Mercurial has no untracked exclude file, but the repository's own
+        // configuration can name extra ignore files.
+        let ignore = workspace.join(".hg/anna-ignore");
+        if !ignore.exists() {
+            super::append_line(
+                &workspace.join(".hg/hgrc"),
+                &format!("\n[ui]\nignore.anna = {}", ignore.display()),
+            )?;
+        }
+        super::append_line(&ignore, "syntax: rootglob")?;
+        super::append_line(&ignore, &path.to_string_lossy())
+    }
+}
+
+fn hg(dir: &Path, args: &[&str]) -> Result<String> {
+    super::run("hg", dir, args)
+}
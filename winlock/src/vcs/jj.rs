@@ -0,0 +1,265 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{bail, Context, Result};
+
+use super::{UnsavedWork, Vcs, VcsKind};
+use crate::branch::BranchName;
+
+/// Jujutsu, using bookmarks as branches.
+///
+/// Jujutsu snapshots the working copy automatically, so "committing"
+/// means finalizing the working-copy change and pointing the bookmark at it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Jujutsu;
+
+impl Vcs for Jujutsu {
+    fn kind(&self) -> VcsKind {
+        VcsKind::Jujutsu
+    }
+
+    fn head(&self, dir: &Path) -> Result<String> {
+        jj(
+            dir,
+            &[
+                "log",
+                "--no-graph",
+                "--revisions",
+                "@",
+                "--template",
+                "commit_id",
+            ],
+        )
+    }
+
+    fn branch_exists(&self, dir: &Path, branch: &BranchName) -> Result<bool> {
+        let bookmarks = jj(
+            dir,
+            &[
+                "bookmark",
+                "list",
+                "--template",
+                "name ++ \"\\n\"",
+                branch.as_str(),
+            ],
+        )?;
+        Ok(bookmarks
+            .lines()
+            .any(|bookmark| bookmark == branch.as_str()))
+    }
+
+    fn resolve(&self, dir: &Path, revision: &str) -> Result<String> {
+        jj(
+            dir,
+            &[
+                "log",
+                "--no-graph",
+                "--revisions",
+                revision,
+                "--template",
+                "commit_id",
+            ],
+        )
+    }
+
+    fn default_branch(&self, _dir: &Path) -> Result<String> {
+        // `trunk()` is jj's built-in revset alias for the default branch of the
+        // default remote, falling back to the root commit.
+        Ok(String::from("trunk()"))
+    }
+
+    fn create_branch(&self, workspace: &Path, branch: &BranchName, start: &str) -> Result<()> {
+        jj(workspace, &["new", start])?;
+        jj(
+            workspace,
+            &["bookmark", "create", branch.as_str(), "--revision", "@"],
+        )
+        .map(drop)
+    }
+
+    fn switch(&self, workspace: &Path, branch: &BranchName) -> Result<()> {
+        jj(workspace, &["new", branch.as_str()]).map(drop)
+    }
+
+    fn commit_all(
+        &self,
+        workspace: &Path,
+        branch: &BranchName,
+        message: &str,
+    ) -> Result<Option<String>> {
+        let empty = jj(
+            workspace,
+            &[
+                "log",
+                "--no-graph",
+                "--revisions",
+                "@",
+                "--template",
+                "empty",
+            ],
+        )?;
+        if empty == "true" {
+            return Ok(None);
+        }
+
+        jj(workspace, &["commit", "--message", message])?;
+        jj(
+            workspace,
+            &["bookmark", "set", branch.as_str(), "--revision", "@-"],
+        )?;
+        jj(
+            workspace,
+            &[
+                "log",
+                "--no-graph",
+                "--revisions",
+                "@-",
+                "--template",
+                "commit_id",
+            ],
+        )
+        .map(Some)
+    }
+
+    fn diff(&self, workspace: &Path, base: &str) -> Result<String> {
+        jj(workspace, &["diff", "--git", "--from", base, "--to", "@"])
+    }
+
+    fn merge_back(&self, project: &Path, workspace: &Path, branch: &BranchName) -> Result<()> {
+        // Jujutsu can only fetch from git remotes, so the workspace's backing
+        // git store is registered as a temporary remote for the duration of the fetch.
+        let remote = format!("anna-{}", branch.as_str().replace('/', "-"));
+        let store = workspace.join(".jj").join("repo").join("store").join("git");
+        let store = store.to_string_lossy();
+
+        jj(project, &["git", "remote", "add", &remote, &store])?;
+        let fetched = jj(
+            project,
+            &[
+                "git",
+                "fetch",
+                "--remote",
+                &remote,
+                "--branch",
+                branch.as_str(),
+            ],
+        );
+        let removed = jj(project, &["git", "remote", "remove", &remote]);
+        fetched.context("fetch from workspace")?;
+        removed.context("remove temporary remote").map(drop)
+    }
+
+    fn count_commits(&self, dir: &Path, from: &str, to: &str) -> Result<u64> {
+        let range = format!("({from})..({to})");
+        let commits = jj(
+            dir,
+            &[
+                "log",
+                "--no-graph",
+                "--revisions",
+                &range,
+                "--template",
+                "commit_id ++ \"\\n\"",
+            ],
+        )?;
+        Ok(commits.lines().count() as u64)
+    }
+
+    fn unsaved_work(
+        &self,
+        workspace: &Path,
+        project: &Path,
+        branch: &BranchName,
+        base: &str,
+    ) -> Result<UnsavedWork> {
+        // The working-copy change holds what isn't committed yet. jj can't tell files
+        // deleted from it apart from those left out of the workspace, but either way
+        // the project still has the ones it has.
+        let summary = jj(workspace, &["diff", "--name-only"])?;
+        let changed = summary
+            .lines()
+            .map(PathBuf::from)
+            .filter(|path| {
+                let (copy, original) = (workspace.join(path), project.join(path));
+                let deleted = fs::symlink_metadata(&copy).is_err();
+                !(super::same_file_content(&copy, &original)
+                    || deleted && fs::symlink_metadata(&original).is_ok())
+            })
+            .collect();
+
+        let commits = match self.branch_exists(workspace, branch)? {
+            // A commit the project has comes with all its ancestors, and looking up
+            // one it doesn't have fails. The project's working copy is left alone.
+            true => {
+                let head = self.resolve(workspace, branch.as_str())?;
+                let kept = jj(
+                    project,
+                    &[
+                        "log",
+                        "--ignore-working-copy",
+                        "--no-graph",
+                        "--revisions",
+                        &head,
+                        "--template",
+                        "commit_id",
+                    ],
+                )
+                .is_ok();
+                match kept {
+                    true => 0,
+                    false => self.count_commits(workspace, base, &head)?,
+                }
+            }
+            false => 0,
+        };
+        Ok(UnsavedWork { changed, commits })
+    }
+
+    fn own_repository(&self, project: &Path, workspace: &Path) -> Result<()> {
+        // Workspaces other than a repository's first have a file naming its
+        // store rather than a store of their own.
+        if workspace.join(".jj/repo").is_file() {
+            bail!(
+                "{} is a secondary jj workspace, whose repository the workspace would share; \
+                 start the session from the repository's main workspace",
+                project.display()
+            );
+        }
+        Ok(())
+    }
+
+    fn hide_left_out(&self, project: &Path, workspace: &Path) -> Result<Vec<PathBuf>> {
+        // jj snapshots whatever is missing as deleted, and has no way to skip files
+        // it tracks, so they can only be reported.
+        let summary = jj(workspace, &["diff", "--summary"])?;
+        Ok(summary
+            .lines()
+            .filter_map(|line| line.strip_prefix("D "))
+            .map(PathBuf::from)
+            .filter(|path| fs::symlink_metadata(project.join(path)).is_ok())
+            .collect())
+    }
+
+    fn ignore(&self, workspace: &Path, path: &Path) -> Result<()> {
+        // jj reads the exclude file of its backing git repository, which is
+        // `.git` when colocated and inside its own store otherwise.
+        let git_dir = match workspace.join(".git") {
+            colocated if colocated.is_dir() => colocated,
+            _ => workspace.join(".jj/repo/store/git"),
+        };
+        super::append_line(
+            &git_dir.join("info/exclude"),
+            &format!("/{}", path.to_string_lossy()),
+        )
+    }
+}
+
+fn jj(dir: &Path, args: &[&str]) -> Result<String> {
+    super::run("jj", dir, args)
+}
@@ -0,0 +1,140 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Git LFS files in workspaces.
+//!
+//! Copying the multi-gigabyte files LFS checks out wastes time and disk when agents
+//! don't need them, so workspaces can instead get the LFS [`Pointers`] committed for
+//! them, sharing the project's LFS storage so that files [pulled](pull) later,
+//! with `git lfs pull --include`, are only downloaded if the project doesn't have them.
+//!
+//! Finding and writing pointers doesn't need `git lfs`; pulling does.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{Context, Result};
+
+/// How LFS files are brought into a new workspace, when they're not copied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lfs {
+    /// Patterns, as for `git lfs pull --include`, of files pulled right away;
+    /// the rest are left as pointers until pulled.
+    pub pull: Vec<String>,
+}
+
+/// Pathspec matching the files LFS manages.
+const LFS_FILES: &str = ":(attr:filter=lfs)";
+
+/// The LFS files in a project that can be replaced by their pointers in a workspace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pointers {
+    files: Vec<PathBuf>,
+    storage: PathBuf,
+}
+
+/// Find the LFS files in `project` that can be replaced by their pointers:
+/// those checked in and unchanged, since changes would be lost.
+pub fn pointers(project: &Path) -> Result<Pointers> {
+    let tracked = super::run("git", project, &["ls-files", "-z", "--", LFS_FILES])?;
+    let changed = super::run(
+        "git",
+        project,
+        &["diff", "--name-only", "-z", "--", LFS_FILES],
+    )?;
+    let changed = changed.split('\0').collect::<BTreeSet<_>>();
+    let files = tracked
+        .split('\0')
+        .filter(|path| !path.is_empty() && !changed.contains(path))
+        .map(PathBuf::from)
+        .collect();
+
+    let common = super::run(
+        "git",
+        project,
+        &["rev-parse", "--path-format=absolute", "--git-common-dir"],
+    )?;
+    let configured = super::run(
+        "git",
+        project,
+        &["config", "--default", "", "--get", "lfs.storage"],
+    )?;
+    // Relative storage is relative to the repository's own directory, which the
+    // workspace's is a copy of, so it's made absolute to share it.
+    let storage = match configured.is_empty() {
+        true => Path::new(&common).join("lfs"),
+        false => Path::new(&common).join(configured),
+    };
+    Ok(Pointers { files, storage })
+}
+
+impl Pointers {
+    /// Patterns, in gitignore syntax, excluding from the copy the files replaced by
+    /// their pointers and the LFS storage shared with the project.
+    pub fn exclude(&self) -> Vec<String> {
+        self.files
+            .iter()
+            .map(|path| literal_pattern(path))
+            .chain([String::from("/.git/lfs/")])
+            .collect()
+    }
+
+    /// Write the pointers into `workspace`, a copy of the project that [excluded](Self::exclude)
+    /// their files, and have it share the project's LFS storage.
+    pub fn write(&self, workspace: &Path) -> Result<()> {
+        let storage = self.storage.to_string_lossy();
+        super::run(
+            "git",
+            workspace,
+            &["config", "--local", "lfs.storage", &storage],
+        )
+        .context("share LFS storage with the project")?;
+        // Without a filter, what's checked out is what's committed: the pointers.
+        let checkout = [
+            "--literal-pathspecs",
+            "-c",
+            "filter.lfs.process=",
+            "-c",
+            "filter.lfs.smudge=",
+            "-c",
+            "filter.lfs.required=false",
+            "checkout",
+            "--",
+        ];
+        // In batches, to stay well within limits on the length of command lines.
+        for batch in self.files.chunks(256) {
+            let paths = batch
+                .iter()
+                .map(|path| path.to_string_lossy())
+                .collect::<Vec<_>>();
+            let mut args = checkout.to_vec();
+            args.extend(paths.iter().map(AsRef::as_ref));
+            super::run("git", workspace, &args).context("check out LFS pointers")?;
+        }
+        Ok(())
+    }
+}
+
+/// Replace the pointers in `workspace` matching `include`, patterns as for
+/// `git lfs pull --include`, with the files they point to.
+pub fn pull(workspace: &Path, include: &[String]) -> Result<()> {
+    let include = format!("--include={}", include.join(","));
+    super::run("git", workspace, &["lfs", "pull", &include])
+        .context("pull LFS files; is git-lfs installed?")
+        .map(drop)
+}
+
+/// A gitignore pattern matching exactly `path`, relative to the root.
+fn literal_pattern(path: &Path) -> String {
+    let mut pattern = String::from("/");
+    for c in path.to_string_lossy().chars() {
+        if matches!(c, '\\' | '*' | '?' | '[' | ' ') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
@@ -0,0 +1,68 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::path::Path;
+
+use color_eyre::eyre::{bail, Result};
+
+use super::{Vcs, VcsKind};
+use crate::branch::BranchName;
+
+/// No version control.
+///
+/// Branches are purely names, so creating and switching them does nothing;
+/// operations that need history are errors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct NoVcs;
+
+impl Vcs for NoVcs {
+    fn kind(&self) -> VcsKind {
+        VcsKind::None
+    }
+
+    fn head(&self, dir: &Path) -> Result<String> {
+        bail!("not under version control: {}", dir.display())
+    }
+
+    fn branch_exists(&self, _dir: &Path, _branch: &BranchName) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn resolve(&self, dir: &Path, _revision: &str) -> Result<String> {
+        bail!("not under version control: {}", dir.display())
+    }
+
+    fn default_branch(&self, dir: &Path) -> Result<String> {
+        bail!("not under version control: {}", dir.display())
+    }
+
+    fn create_branch(&self, _workspace: &Path, _branch: &BranchName, _start: &str) -> Result<()> {
+        Ok(())
+    }
+
+    fn switch(&self, _workspace: &Path, _branch: &BranchName) -> Result<()> {
+        Ok(())
+    }
+
+    fn commit_all(
+        &self,
+        _workspace: &Path,
+        _branch: &BranchName,
+        _message: &str,
+    ) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn diff(&self, workspace: &Path, _base: &str) -> Result<String> {
+        bail!("not under version control: {}", workspace.display())
+    }
+
+    fn merge_back(&self, _project: &Path, workspace: &Path, _branch: &BranchName) -> Result<()> {
+        bail!("not under version control: {}", workspace.display())
+    }
+
+    fn ignore(&self, _workspace: &Path, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+}
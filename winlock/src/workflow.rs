@@ -0,0 +1,359 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The whole life of a session in one call: create or resume it, run a backend in it,
+//! merge its branch back into the project, and remove it.
+//!
+//! This is what `anna agent` followed by `anna session merge --and-remove` does, for
+//! embedders who'd rather not put the steps together themselves:
+//!
+//! ```no_run
+//! # fn main() -> color_eyre::Result<()> {
+//! use winlock::workflow::Workflow;
+//!
+//! let outcome = Workflow::builder()
+//!     .project("/src/project")
+//!     .prompt("Fix the flaky test in tests/it/session.rs")
+//!     .verify("cargo test")
+//!     .auto_merge(true)
+//!     .remove_after_merge(true)
+//!     .run()?;
+//! println!("merged: {}", outcome.merged);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Callbacks let embedders follow along, such as to show the workspace once it
+//! exists or to report how the backend did; an error returned from one stops the
+//! workflow, leaving the session as it is.
+
+use std::{fmt, path::PathBuf};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+
+use crate::{
+    branch::{self, BranchName},
+    config::{Config, Profile},
+    session::{Session, Sessions},
+    Agent, AgentSessionStatus, CreateOptions, RunOptions, RunOutcome,
+};
+
+type OnSession = Box<dyn FnMut(&Agent) -> Result<()>>;
+
+type OnOutcome = Box<dyn FnMut(&Agent, &RunOutcome) -> Result<()>>;
+
+type OnMerged = Box<dyn FnMut(&Session) -> Result<()>>;
+
+/// A session's life from creation to removal, built with [`Workflow::builder`].
+pub struct Workflow {
+    sessions: Sessions,
+
+    project: PathBuf,
+
+    branch: BranchName,
+
+    profile: Profile,
+
+    create: CreateOptions,
+
+    run: RunOptions,
+
+    auto_merge: bool,
+
+    remove_after_merge: bool,
+
+    on_session: Option<OnSession>,
+
+    on_outcome: Option<OnOutcome>,
+
+    on_merged: Option<OnMerged>,
+}
+
+impl Workflow {
+    /// Start describing a workflow; only the [project](WorkflowBuilder::project) is required.
+    pub fn builder() -> WorkflowBuilder {
+        WorkflowBuilder::default()
+    }
+
+    /// Run the workflow: create or resume the session, run the backend in it, and,
+    /// if asked to, merge the branch back and remove the session.
+    ///
+    /// The branch is only merged if the backend exited successfully and the
+    /// verification command, if any, passed; otherwise the session is kept for
+    /// someone to look at, and the outcome says it wasn't merged.
+    pub fn run(mut self) -> Result<WorkflowOutcome> {
+        let mut agent = Agent::new(&self.sessions, &self.project, self.branch, &self.create)?;
+        if let Some(on_session) = &mut self.on_session {
+            on_session(&agent)?;
+        }
+
+        let outcome = agent.run(&self.profile, &self.run)?;
+        if let Some(on_outcome) = &mut self.on_outcome {
+            on_outcome(&agent, &outcome)?;
+        }
+
+        let status = agent.status();
+        let passed = outcome.status.success()
+            && outcome
+                .verification
+                .as_ref()
+                .is_none_or(|verification| verification.passed);
+        if !self.auto_merge || !passed {
+            return Ok(WorkflowOutcome {
+                session: agent.session().clone(),
+                status,
+                run: outcome,
+                merged: false,
+                removed: false,
+            });
+        }
+
+        // Commits are merged, so only uncommitted changes would be lost with the workspace;
+        // a session with any is kept, as `anna session merge --and-remove` would refuse to.
+        // Checked again under the session's lock, in case it was resumed in between.
+        let remove = self.remove_after_merge && !has_uncommitted(agent.session())?;
+        let session = match remove {
+            true => {
+                let (session, removal) = self
+                    .sessions
+                    .merge_and_remove(agent.project(), agent.branch(), |session| {
+                        match has_uncommitted(session)? {
+                            true => bail!("session '{}' has uncommitted changes", session.branch),
+                            false => Ok(()),
+                        }
+                    })?
+                    .ok_or_else(|| eyre!("session '{}' was removed", agent.branch()))?;
+                removal.finish().context("delete the merged session")?;
+                session
+            }
+            false => {
+                agent.merge_back()?;
+                agent.session().clone()
+            }
+        };
+        if let Some(on_merged) = &mut self.on_merged {
+            on_merged(&session)?;
+        }
+        Ok(WorkflowOutcome {
+            session,
+            status,
+            run: outcome,
+            merged: true,
+            removed: remove,
+        })
+    }
+}
+
+impl fmt::Debug for Workflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Workflow")
+            .field("project", &self.project)
+            .field("branch", &self.branch)
+            .field("auto_merge", &self.auto_merge)
+            .field("remove_after_merge", &self.remove_after_merge)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Describes a [`Workflow`], from [`Workflow::builder`].
+#[derive(Default)]
+pub struct WorkflowBuilder {
+    sessions: Option<Sessions>,
+
+    project: Option<PathBuf>,
+
+    branch: Option<BranchName>,
+
+    profile: Option<Profile>,
+
+    create: Option<CreateOptions>,
+
+    run: RunOptions,
+
+    auto_merge: bool,
+
+    remove_after_merge: bool,
+
+    on_session: Option<OnSession>,
+
+    on_outcome: Option<OnOutcome>,
+
+    on_merged: Option<OnMerged>,
+}
+
+impl WorkflowBuilder {
+    /// The session store to use; defaults to the one in the
+    /// [state directory](crate::config::state_dir).
+    pub fn sessions(mut self, sessions: &Sessions) -> Self {
+        self.sessions = Some(sessions.clone());
+        self
+    }
+
+    /// The project the agent works on.
+    pub fn project(mut self, project: impl Into<PathBuf>) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    /// The branch the agent works on, whose session is resumed if it exists;
+    /// defaults to one [named after the prompt](branch::from_prompt).
+    pub fn branch(mut self, branch: BranchName) -> Self {
+        self.branch = Some(branch);
+        self
+    }
+
+    /// The prompt given to the backend.
+    pub fn prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.run.prompt = Some(prompt.into());
+        self
+    }
+
+    /// The backend to run; defaults to the default profile in the user's configuration.
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// How the session is created, if it doesn't exist yet; defaults to
+    /// [as configured](Config::create_options) for the project, including the
+    /// settings it checked in that don't run commands the user hasn't trusted.
+    pub fn create_options(mut self, options: CreateOptions) -> Self {
+        self.create = Some(options);
+        self
+    }
+
+    /// How the backend is run; replaces the [prompt](Self::prompt) and
+    /// [verification command](Self::verify) set so far.
+    pub fn run_options(mut self, options: RunOptions) -> Self {
+        self.run = options;
+        self
+    }
+
+    /// A shell command run in the workspace after the backend exits,
+    /// which must pass for the branch to be merged.
+    pub fn verify(mut self, command: impl Into<String>) -> Self {
+        self.run.verify = Some(command.into());
+        self
+    }
+
+    /// Merge the branch back into the project once the backend has succeeded.
+    pub fn auto_merge(mut self, auto_merge: bool) -> Self {
+        self.auto_merge = auto_merge;
+        self
+    }
+
+    /// Remove the session once its branch has been merged, unless it has changes
+    /// that aren't committed; has no effect without [`auto_merge`](Self::auto_merge).
+    pub fn remove_after_merge(mut self, remove: bool) -> Self {
+        self.remove_after_merge = remove;
+        self
+    }
+
+    /// Call `on_session` once the session has been created or resumed,
+    /// before the backend runs.
+    pub fn on_session(mut self, on_session: impl FnMut(&Agent) -> Result<()> + 'static) -> Self {
+        self.on_session = Some(Box::new(on_session));
+        self
+    }
+
+    /// Call `on_outcome` once the backend has exited, and been verified if asked to.
+    pub fn on_outcome(
+        mut self,
+        on_outcome: impl FnMut(&Agent, &RunOutcome) -> Result<()> + 'static,
+    ) -> Self {
+        self.on_outcome = Some(Box::new(on_outcome));
+        self
+    }
+
+    /// Call `on_merged` with the session once its branch has been merged,
+    /// and it's been removed if asked to.
+    pub fn on_merged(mut self, on_merged: impl FnMut(&Session) -> Result<()> + 'static) -> Self {
+        self.on_merged = Some(Box::new(on_merged));
+        self
+    }
+
+    /// Finish describing the workflow, filling in defaults.
+    pub fn build(self) -> Result<Workflow> {
+        let Some(project) = self.project else {
+            bail!("a workflow needs a project");
+        };
+        let branch = match (self.branch, &self.run.prompt) {
+            (Some(branch), _) => branch,
+            (None, Some(prompt)) => branch::from_prompt(prompt)?,
+            (None, None) => bail!("a workflow needs a branch or a prompt to name one after"),
+        };
+        let profile = match self.profile {
+            Some(profile) => profile,
+            None => Config::load()?.profile(None)?.clone(),
+        };
+        let create = match self.create {
+            Some(create) => create,
+            None => {
+                let mut config = Config::load()?;
+                config.load_project(&project)?;
+                config.create_options(&project)
+            }
+        };
+        let sessions = match self.sessions {
+            Some(sessions) => sessions,
+            None => Sessions::open()?,
+        };
+        Ok(Workflow {
+            sessions,
+            project,
+            branch,
+            profile,
+            create,
+            run: self.run,
+            auto_merge: self.auto_merge,
+            remove_after_merge: self.remove_after_merge,
+            on_session: self.on_session,
+            on_outcome: self.on_outcome,
+            on_merged: self.on_merged,
+        })
+    }
+
+    /// [Build](Self::build) the workflow and [run](Workflow::run) it.
+    pub fn run(self) -> Result<WorkflowOutcome> {
+        self.build()?.run()
+    }
+}
+
+impl fmt::Debug for WorkflowBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WorkflowBuilder")
+            .field("project", &self.project)
+            .field("branch", &self.branch)
+            .field("auto_merge", &self.auto_merge)
+            .field("remove_after_merge", &self.remove_after_merge)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The result of [`Workflow::run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkflowOutcome {
+    /// The session, as it was when the workflow finished with it.
+    pub session: Session,
+
+    /// Whether the session was created or resumed.
+    pub status: AgentSessionStatus,
+
+    /// How the backend's run went.
+    pub run: RunOutcome,
+
+    /// Whether the branch was merged back into the project.
+    pub merged: bool,
+
+    /// Whether the session was removed after being merged.
+    pub removed: bool,
+}
+
+/// Whether any of `session`'s workspaces has uncommitted changes the project lacks.
+fn has_uncommitted(session: &Session) -> Result<bool> {
+    Ok(session
+        .unsaved_work()?
+        .iter()
+        .any(|(_, work)| !work.changed.is_empty()))
+}
@@ -0,0 +1,1813 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Creation of agent workspaces.
+//!
+//! A workspace is a full copy of the project, including its VCS metadata,
+//! so that the agent can freely build, test, and commit without disturbing
+//! the user's checkout.
+
+// Without the `copy` feature, only what tracking sessions needs is used.
+#![cfg_attr(not(feature = "copy"), allow(dead_code, unused_imports))]
+
+use std::{
+    collections::BTreeMap,
+    env, fmt,
+    fs::{self, File},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicPtr, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime},
+};
+
+#[cfg(feature = "copy")]
+use std::path::Component;
+
+use color_eyre::eyre::{bail, Context, Report, Result};
+#[cfg(feature = "copy")]
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    DirEntry, WalkBuilder, WalkState,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+mod engine;
+
+pub use engine::{CopyEngine, CopyEngineKind, GitArchive, Hardlink, Naive, Parallel, Reflink};
+
+/// The file at the project's root listing, in gitignore syntax, what's left out of
+/// workspaces besides what's gitignored, such as build output that's committed.
+///
+/// Unlike [excluded](SECRET_PATTERNS) files, what it matches isn't reported as
+/// withheld, since it's left out by choice rather than to keep it from the agent.
+pub const ANNAIGNORE: &str = ".annaignore";
+
+/// Patterns, in gitignore syntax, for files that commonly hold secrets.
+///
+/// An agent has no need for production credentials, and anything in its
+/// workspace may end up in a prompt sent to the LLM provider.
+pub const SECRET_PATTERNS: &[&str] = &[
+    ".env",
+    ".env.*",
+    "!.env.example",
+    "!.env.sample",
+    "!.env.template",
+    "*.pem",
+    "*.key",
+    "*.p12",
+    "*.pfx",
+    "*.jks",
+    "*.keystore",
+    "id_rsa",
+    "id_dsa",
+    "id_ecdsa",
+    "id_ed25519",
+    ".netrc",
+    ".git-credentials",
+    ".pypirc",
+    "**/.aws/credentials",
+    "**/.docker/config.json",
+    "**/.kube/config",
+    "**/.config/gcloud/",
+    "credentials.json",
+    "service-account*.json",
+];
+
+/// The environment variable which overrides the [workspace root](root).
+pub const ROOT_VARIABLE: &str = "ANNA_WORKSPACE_DIR";
+
+/// The directory new workspaces are created in: `$ANNA_WORKSPACE_DIR` if set,
+/// otherwise the temporary directory.
+pub fn root() -> PathBuf {
+    env::var_os(ROOT_VARIABLE)
+        .filter(|dir| !dir.is_empty())
+        .map_or_else(env::temp_dir, PathBuf::from)
+}
+
+/// Create an empty directory in `root` for a workspace of `branch` in `project`.
+///
+/// The directory is named `<project-name>-<branch>-<short-id>` so that process
+/// listings, editor title bars, and shell prompts show which session it's for;
+/// the random suffix keeps sessions with similar names apart.
+///
+/// On macOS, workspaces are kept out of Spotlight's index and Time Machine's
+/// backups, since they're short-lived copies of a project that's already indexed
+/// and backed up, and dozens of them would otherwise burn CPU and disk.
+pub fn create_dir(root: &Path, project: &Path, branch: &str) -> Result<PathBuf> {
+    let project = project
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let prefix = format!("{}-{}-", dir_name_part(&project), dir_name_part(branch));
+    fs::create_dir_all(root)
+        .with_context(|| format!("create workspace root: {}", root.display()))?;
+    let dir = loop {
+        let suffix = Uuid::new_v4().simple().to_string();
+        let dir = root.join(format!("{prefix}{}", &suffix[..6]));
+        match fs::create_dir(&dir) {
+            Ok(()) => break dir,
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("create workspace directory in {}", root.display()))
+            }
+        }
+    };
+    #[cfg(target_os = "macos")]
+    exclude_from_indexing(root, &dir);
+    Ok(dir)
+}
+
+/// The file that tells Spotlight not to index the directory it's in.
+#[cfg(target_os = "macos")]
+const NEVER_INDEX: &str = ".metadata_never_index";
+
+/// Keep the workspace `dir` in `root` out of Spotlight and Time Machine.
+///
+/// Best effort: a workspace that gets indexed or backed up still works,
+/// so failing here isn't worth failing its creation over.
+/// The Spotlight marker goes in `root` rather than the workspace,
+/// where it would show up as an untracked file.
+#[cfg(target_os = "macos")]
+fn exclude_from_indexing(root: &Path, dir: &Path) {
+    use std::process::{Command, Stdio};
+
+    let marker = root.join(NEVER_INDEX);
+    if !marker.exists() {
+        let _ = File::create(&marker);
+    }
+    // Without `-p`, the exclusion is a sticky attribute on the directory itself,
+    // which needs no special privileges and goes away when the workspace is deleted.
+    let _ = Command::new("tmutil")
+        .arg("addexclusion")
+        .arg(dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+/// `text` with anything that's awkward in a file name, such as the slashes
+/// in `feature/foo`, replaced with `-`.
+fn dir_name_part(text: &str) -> String {
+    let part = text
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+    // A leading dot would hide the workspace from directory listings.
+    let part = part.trim_start_matches('.');
+    if part.is_empty() {
+        String::from("workspace")
+    } else {
+        String::from(part)
+    }
+}
+
+/// What [`copy_workspace`] copied, and what it didn't.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CopyReport {
+    /// The number of files and symlinks copied.
+    pub files: u64,
+
+    /// The total size of the files copied, in bytes.
+    pub bytes: u64,
+
+    /// Paths, relative to the project, withheld because they matched an exclude pattern.
+    pub withheld: Vec<PathBuf>,
+
+    /// Paths, relative to the project, of files skipped for being larger
+    /// than the [maximum](CopyOptions::max_file_size).
+    pub oversized: Vec<PathBuf>,
+
+    /// Paths, relative to the project, of tracked files that were left out and that the
+    /// workspace's version control reads as deleted, since it can't be told not to.
+    pub deleted: Vec<PathBuf>,
+
+    /// Errors for entries that failed to copy.
+    pub warnings: Vec<String>,
+
+    /// Errors for entries that kept failing with errors that are usually transient,
+    /// even after being [retried](COPY_ATTEMPTS). Copying fails with [`CopyFailed`]
+    /// if there are any.
+    pub failures: Vec<String>,
+}
+
+impl CopyReport {
+    /// This report, or [`CopyFailed`] with it if any entry [kept failing](Self::failures).
+    fn finish(self) -> Result<Self> {
+        if self.failures.is_empty() {
+            Ok(self)
+        } else {
+            Err(CopyFailed { report: self }.into())
+        }
+    }
+}
+
+/// How a project is copied into a workspace, besides what's excluded.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CopyOptions {
+    /// Skip files larger than this many bytes, reporting them as
+    /// [oversized](CopyReport::oversized) rather than copying them.
+    pub max_file_size: Option<u64>,
+
+    /// Stops the copy, failing it with [`CopyCancelled`], once cancelled.
+    pub cancel: Cancel,
+}
+
+/// A handle for cancelling a copy from elsewhere, such as another thread;
+/// clones cancel the same copy.
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    /// Stop the copy as soon as possible.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the copy has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fail with [`CopyCancelled`] if the copy has been cancelled.
+    fn check(&self) -> Result<()> {
+        match self.is_cancelled() {
+            true => Err(CopyCancelled.into()),
+            false => Ok(()),
+        }
+    }
+
+    /// Cancel the copy when the user interrupts the process, such as with Ctrl-C, rather
+    /// than letting the interrupt stop it, for as long as the returned guard is alive.
+    /// Dropping the guard puts back how interrupts were handled before.
+    ///
+    /// Only one copy at a time can be cancelled this way.
+    pub fn on_interrupt(&self) -> Result<InterruptGuard> {
+        // Shared with the handler, which can't be given anything but a static. Never
+        // freed, since a handler that's running as the guard is dropped may still use it.
+        let flag = Arc::into_raw(Arc::clone(&self.0)).cast_mut();
+        if INTERRUPT_FLAG
+            .compare_exchange(
+                std::ptr::null_mut(),
+                flag,
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .is_err()
+        {
+            // SAFETY: the pointer came from `Arc::into_raw` just above, and was never shared.
+            drop(unsafe { Arc::from_raw(flag) });
+            bail!("another copy is already cancelled by interrupts");
+        }
+        InterruptGuard::install().inspect_err(|_| {
+            INTERRUPT_FLAG.store(std::ptr::null_mut(), Ordering::SeqCst);
+        })
+    }
+}
+
+/// The flag of the [`Cancel`] that interrupts [cancel](Cancel::on_interrupt), if any.
+static INTERRUPT_FLAG: AtomicPtr<AtomicBool> = AtomicPtr::new(std::ptr::null_mut());
+
+/// Cancel the copy whose flag is [`INTERRUPT_FLAG`], if there is one.
+fn cancel_interrupted() {
+    let flag = INTERRUPT_FLAG.load(Ordering::SeqCst);
+    // SAFETY: a flag that was set is never freed. Storing to an atomic is all that's
+    // done, which is safe in a signal handler.
+    if let Some(flag) = unsafe { flag.as_ref() } {
+        flag.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Keeps interrupts [cancelling a copy](Cancel::on_interrupt) until it's dropped.
+#[derive(Debug)]
+pub struct InterruptGuard {
+    #[cfg(unix)]
+    previous: libc::sigaction,
+}
+
+impl InterruptGuard {
+    #[cfg(unix)]
+    fn install() -> Result<Self> {
+        extern "C" fn handle(_signal: libc::c_int) {
+            cancel_interrupted();
+        }
+
+        // SAFETY: the handler is async-signal-safe, and both actions are valid for the call.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(libc::SIGINT, &action, &mut previous) != 0 {
+                return Err(io::Error::last_os_error()).context("handle interrupts");
+            }
+            Ok(Self { previous })
+        }
+    }
+
+    #[cfg(windows)]
+    fn install() -> Result<Self> {
+        // SAFETY: the handler is valid for as long as it's registered, which is forever.
+        if unsafe { windows_sys::Win32::System::Console::SetConsoleCtrlHandler(Some(handle), 1) }
+            == 0
+        {
+            return Err(io::Error::last_os_error()).context("handle interrupts");
+        }
+        Ok(Self {})
+    }
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn handle(event: u32) -> windows_sys::core::BOOL {
+    use windows_sys::Win32::System::Console::{CTRL_BREAK_EVENT, CTRL_C_EVENT};
+
+    match event {
+        CTRL_C_EVENT | CTRL_BREAK_EVENT => {
+            cancel_interrupted();
+            1
+        }
+        _ => 0,
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        // SAFETY: `previous` is what `sigaction` reported for SIGINT, and on Windows
+        // the handler being removed is the one registered.
+        #[cfg(unix)]
+        unsafe {
+            libc::sigaction(libc::SIGINT, &self.previous, std::ptr::null_mut());
+        }
+        #[cfg(windows)]
+        unsafe {
+            windows_sys::Win32::System::Console::SetConsoleCtrlHandler(Some(handle), 0);
+        }
+        INTERRUPT_FLAG.store(std::ptr::null_mut(), Ordering::SeqCst);
+    }
+}
+
+/// Handles are equal if they cancel the same copy.
+impl PartialEq for Cancel {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Cancel {}
+
+/// Copying a project was [cancelled](Cancel) before it finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CopyCancelled;
+
+impl fmt::Display for CopyCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("copying was cancelled")
+    }
+}
+
+impl std::error::Error for CopyCancelled {}
+
+/// What a copy has done so far, as it goes.
+#[derive(Debug, Clone, Copy)]
+pub enum CopyProgress<'a> {
+    /// An entry was copied, or failed to, with what has been copied so far.
+    Entry(&'a CopyReport),
+
+    /// Part of a file of at least [`CHUNKED_COPY_SIZE`] was copied.
+    File {
+        /// The file, relative to the project.
+        path: &'a Path,
+
+        /// How much of it has been copied so far, in bytes.
+        copied: u64,
+
+        /// Its size, in bytes.
+        size: u64,
+    },
+}
+
+/// Files at least this large are copied a [chunk](COPY_CHUNK_SIZE) at a time,
+/// reporting [progress](CopyProgress::File) and checking for cancellation between
+/// chunks, rather than in one opaque call that can take minutes.
+pub const CHUNKED_COPY_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How much of a large file is copied between progress reports;
+/// on Windows, the system picks its own chunk size.
+pub const COPY_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// How many times copying an entry is attempted when it fails with an error
+/// that's usually transient, such as a file being locked by the sync client of
+/// a Dropbox or OneDrive folder, or a network filesystem timing out.
+/// Attempts are spaced by [`COPY_RETRY_BACKOFF`], doubling each time.
+pub const COPY_ATTEMPTS: u32 = 4;
+
+/// How long to wait before retrying a failed copy for the first time.
+pub const COPY_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Copying a project into a workspace failed, since some entries kept failing with
+/// errors that are usually transient even after being retried: the workspace would
+/// be missing files that the project has, which is worse than having no workspace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyFailed {
+    /// What was copied, including the [failures](CopyReport::failures).
+    pub report: CopyReport,
+}
+
+impl fmt::Display for CopyFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failures = &self.report.failures;
+        let first = failures.first().map(String::as_str).unwrap_or_default();
+        match failures.len() {
+            1 => write!(
+                f,
+                "1 entry could not be copied, even after retrying: {first}"
+            ),
+            n => write!(
+                f,
+                "{n} entries could not be copied, even after retrying; the first: {first}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CopyFailed {}
+
+/// Run `attempt`, retrying it with exponential backoff while it fails
+/// with [transient errors](is_transient), up to [`COPY_ATTEMPTS`] times.
+fn retrying<T>(mut attempt: impl FnMut() -> Result<T>) -> Result<T> {
+    let mut backoff = COPY_RETRY_BACKOFF;
+    for _ in 1..COPY_ATTEMPTS {
+        match attempt() {
+            Err(err) if is_transient(&err) => {
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            result => return result,
+        }
+    }
+    attempt()
+}
+
+/// Whether `err` is usually transient: caused by a file being locked or busy,
+/// or by a filesystem that didn't respond in time.
+fn is_transient(err: &Report) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|err| {
+            matches!(
+                err.kind(),
+                ErrorKind::Interrupted
+                    | ErrorKind::WouldBlock
+                    | ErrorKind::TimedOut
+                    | ErrorKind::ResourceBusy
+                    | ErrorKind::StaleNetworkFileHandle
+            )
+            // Windows' sharing and lock violations, for files another process has open.
+            || (cfg!(windows) && matches!(err.raw_os_error(), Some(32 | 33)))
+        })
+}
+
+/// Record the error for an entry that failed to copy in `report`.
+fn record_failure(report: &mut CopyReport, err: &Report) {
+    if is_transient(err) {
+        report.failures.push(format!("{err:#}"));
+    } else {
+        report.warnings.push(format!("{err:#}"));
+    }
+}
+
+/// Copy the project into the workspace.
+///
+/// Files ignored by git are skipped: they're typically build artifacts
+/// which are large, cheap to regenerate, and often tied to absolute paths.
+/// Patterns in `exclude` use gitignore syntax, including `!` to re-include.
+/// Files keep their permissions and modification times, and files hard linked
+/// to each other in the project are hard linked to each other in the workspace.
+/// Entries that fail to copy are reported as warnings rather than aborting,
+/// since a mostly-complete workspace is usually still useful, unless they keep
+/// failing with transient errors, which fails with a [`CopyFailed`] once everything
+/// else is copied; those are [retried](COPY_ATTEMPTS) first.
+/// The project is only ever opened for reading.
+pub fn copy_workspace(
+    project: &Path,
+    workspace: &Path,
+    exclude: &[impl AsRef<str>],
+) -> Result<CopyReport> {
+    copy_workspace_with_progress(project, workspace, exclude, &CopyOptions::default(), |_| {})
+}
+
+/// Like [`copy_workspace`], with `options`, calling `progress` with what has been
+/// copied so far after each entry and between the chunks of large files.
+#[cfg(feature = "copy")]
+pub fn copy_workspace_with_progress(
+    project: &Path,
+    workspace: &Path,
+    exclude: &[impl AsRef<str>],
+    options: &CopyOptions,
+    mut progress: impl FnMut(CopyProgress<'_>),
+) -> Result<CopyReport> {
+    ensure_outside(project, workspace)?;
+    let mut report = CopyReport::default();
+    let mut links = HardLinks::default();
+    for_each_included(project, exclude, options, &mut report, |entry, report| {
+        let path = relative(project, entry.path())?;
+        let linked = links
+            .earlier(entry, path)
+            .map(|linked| fs::hard_link(workspace.join(linked), workspace.join(path)));
+        let result = match linked {
+            Some(Ok(())) => {
+                report.files += 1;
+                Ok(())
+            }
+            // Copied after all when it can't be linked, such as when the file
+            // it's linked to failed to copy.
+            None | Some(Err(_)) => copy_workspace_entry(
+                project,
+                workspace,
+                entry,
+                report,
+                &options.cancel,
+                &mut |copied, size| progress(CopyProgress::File { path, copied, size }),
+            ),
+        };
+        progress(CopyProgress::Entry(report));
+        result
+    })?;
+    report.finish()
+}
+
+#[cfg(not(feature = "copy"))]
+pub fn copy_workspace_with_progress(
+    _project: &Path,
+    _workspace: &Path,
+    _exclude: &[impl AsRef<str>],
+    _options: &CopyOptions,
+    _progress: impl FnMut(CopyProgress<'_>),
+) -> Result<CopyReport> {
+    bail!("copying projects into workspaces requires winlock's `copy` feature")
+}
+
+/// Refuse to copy into a workspace inside the project, which would write into the
+/// project, and would copy the copy: the project is only ever read from.
+fn ensure_outside(project: &Path, workspace: &Path) -> Result<()> {
+    if workspace.starts_with(project) {
+        bail!(
+            "workspace {} is inside the project {}, which anna never writes to",
+            workspace.display(),
+            project.display()
+        );
+    }
+    Ok(())
+}
+
+/// The state of every entry in a directory, so that [changes](Self::changes)
+/// to it can be detected: everything, including ignored files and VCS metadata.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fingerprint(BTreeMap<PathBuf, EntryState>);
+
+/// What [`Fingerprint`] records of an entry: enough to tell if it was written,
+/// without reading it, and without being fooled by it merely being read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EntryState {
+    is_dir: bool,
+
+    len: u64,
+
+    modified: Option<SystemTime>,
+
+    readonly: bool,
+}
+
+impl Fingerprint {
+    /// Record the state of everything in `dir`.
+    pub fn take(dir: &Path) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+        walk_all(dir, &mut |path, metadata| {
+            // A directory's size and modification time only change with its entries,
+            // which are compared themselves.
+            let is_dir = metadata.is_dir();
+            entries.insert(
+                relative(dir, path)?.to_path_buf(),
+                EntryState {
+                    is_dir,
+                    len: if is_dir { 0 } else { metadata.len() },
+                    modified: metadata.modified().ok().filter(|_| !is_dir),
+                    readonly: metadata.permissions().readonly(),
+                },
+            );
+            Ok(())
+        })?;
+        Ok(Self(entries))
+    }
+
+    /// The paths, relative to the directory, which were added, removed, or changed
+    /// between this fingerprint and `later`.
+    pub fn changes(&self, later: &Self) -> Vec<PathBuf> {
+        let mut changes = self
+            .0
+            .iter()
+            .filter(|(path, state)| later.0.get(*path) != Some(state))
+            .map(|(path, _)| path.clone())
+            .chain(
+                later
+                    .0
+                    .keys()
+                    .filter(|path| !self.0.contains_key(*path))
+                    .cloned(),
+            )
+            .collect::<Vec<_>>();
+        changes.sort();
+        changes
+    }
+}
+
+/// The project was written to while anna worked on a copy of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProjectModified {
+    /// The project.
+    pub project: PathBuf,
+
+    /// The paths, relative to the project, which were added, removed, or changed.
+    pub changes: Vec<PathBuf>,
+}
+
+impl fmt::Display for ProjectModified {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        /// Enough to see what happened, without burying the message.
+        const SHOWN: usize = 5;
+        let shown = self
+            .changes
+            .iter()
+            .take(SHOWN)
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let more = match self.changes.len().saturating_sub(SHOWN) {
+            0 => String::new(),
+            more => format!(", and {more} more"),
+        };
+        write!(
+            f,
+            "{} paths changed in the project {} while anna worked on it: {shown}{more}",
+            self.changes.len(),
+            self.project.display()
+        )
+    }
+}
+
+impl std::error::Error for ProjectModified {}
+
+/// What [`refresh_workspace`] brought over from the project, and what it left alone.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefreshReport {
+    /// The files and symlinks copied because they were new or changed in the project,
+    /// along with what was withheld and what failed to copy.
+    pub copy: CopyReport,
+
+    /// The number of files and symlinks already up to date.
+    pub unchanged: u64,
+
+    /// Paths, relative to the project, that differ from the project but were changed
+    /// in the workspace too, so were left as they are in the workspace.
+    pub conflicts: Vec<PathBuf>,
+}
+
+/// Bring files that are new or changed in the project into an existing workspace,
+/// without touching anything else.
+///
+/// As when [copying](copy_workspace), ignored files are skipped and `exclude`
+/// patterns withheld. A file counts as changed when its size or modification time
+/// differ from the workspace's copy. The workspace's own changes are never overwritten:
+/// files modified in the workspace after `since`, typically when the workspace was
+/// last synced with the project, are reported as conflicts instead. Nothing is deleted,
+/// nor brought back: what the project had by `since` but the workspace doesn't was
+/// deleted there, and stays deleted, reported as a conflict if the project changed it
+/// since. The VCS metadata is left alone, since it holds the agent's branch.
+#[cfg(feature = "copy")]
+pub fn refresh_workspace(
+    project: &Path,
+    workspace: &Path,
+    exclude: &[impl AsRef<str>],
+    since: SystemTime,
+) -> Result<RefreshReport> {
+    ensure_outside(project, workspace)?;
+    let mut report = RefreshReport::default();
+    let mut copy = CopyReport::default();
+    for_each_included(
+        project,
+        exclude,
+        &CopyOptions::default(),
+        &mut copy,
+        |entry, copy| refresh_workspace_entry(project, workspace, entry, since, copy, &mut report),
+    )?;
+    report.copy = copy.finish()?;
+    Ok(report)
+}
+
+#[cfg(not(feature = "copy"))]
+pub fn refresh_workspace(
+    _project: &Path,
+    _workspace: &Path,
+    _exclude: &[impl AsRef<str>],
+    _since: SystemTime,
+) -> Result<RefreshReport> {
+    bail!("refreshing workspaces requires winlock's `copy` feature")
+}
+
+/// Call `visit` with each entry of the project that's copied into workspaces,
+/// recording excluded and oversized entries and errors in `report`,
+/// until the copy is cancelled.
+#[cfg(feature = "copy")]
+fn for_each_included(
+    project: &Path,
+    exclude: &[impl AsRef<str>],
+    options: &CopyOptions,
+    report: &mut CopyReport,
+    mut visit: impl FnMut(&DirEntry, &mut CopyReport) -> Result<()>,
+) -> Result<()> {
+    let exclude = matcher(project, exclude)?;
+    for entry in walk(project)? {
+        options.cancel.check()?;
+        let result = entry.context("walk project").and_then(|entry| {
+            let is_dir = entry.file_type().is_some_and(|ty| ty.is_dir());
+            if entry.depth() > 0 {
+                // Children of withheld directories are still walked, so
+                // they're checked against their parents to skip them too.
+                if exclude.matched(entry.path(), is_dir).is_ignore() {
+                    let path = relative(project, entry.path())?;
+                    report.withheld.push(path.to_path_buf());
+                    return Ok(());
+                }
+                if exclude
+                    .matched_path_or_any_parents(entry.path(), is_dir)
+                    .is_ignore()
+                {
+                    return Ok(());
+                }
+            }
+            let path = relative(project, entry.path())?;
+            if is_oversized(path, &entry, options.max_file_size)? {
+                report.oversized.push(path.to_path_buf());
+                return Ok(());
+            }
+            retrying(|| visit(&entry, report))
+        });
+        match result {
+            Err(err) if err.downcast_ref::<CopyCancelled>().is_some() => return Err(err),
+            Err(err) => record_failure(report, &err),
+            Ok(()) => {}
+        }
+    }
+    Ok(())
+}
+
+/// The files walked so far with other hard links to them, each by its inode, with
+/// the path it was walked at. Package managers such as pnpm and build tools such as
+/// Bazel hard link the same file into many places, which copying would multiply.
+#[derive(Debug, Default)]
+#[cfg(feature = "copy")]
+struct HardLinks(BTreeMap<(u64, u64), PathBuf>);
+
+#[cfg(feature = "copy")]
+impl HardLinks {
+    /// The path of the file walked earlier that `entry`, at `path`, is a hard link to,
+    /// if there is one; otherwise `entry` is the one later links to it are linked to.
+    #[cfg(unix)]
+    fn earlier(&mut self, entry: &DirEntry, path: &Path) -> Option<PathBuf> {
+        use std::{collections::btree_map::Entry, os::unix::fs::MetadataExt};
+
+        if !entry.file_type().is_some_and(|ty| ty.is_file()) {
+            return None;
+        }
+        let metadata = entry
+            .metadata()
+            .ok()
+            .filter(|metadata| metadata.nlink() > 1)?;
+        match self.0.entry((metadata.dev(), metadata.ino())) {
+            // Entries are visited again when retried.
+            Entry::Occupied(first) if first.get() != path => Some(first.get().clone()),
+            Entry::Occupied(_) => None,
+            Entry::Vacant(first) => {
+                first.insert(path.to_path_buf());
+                None
+            }
+        }
+    }
+
+    /// The path of the file walked earlier that `entry`, at `path`, is a hard link to;
+    /// hard links aren't detected on this platform, so there never is one.
+    #[cfg(not(unix))]
+    fn earlier(&mut self, _entry: &DirEntry, _path: &Path) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Whether `entry`, at `path` relative to the project, is a file larger than
+/// `max_file_size`. VCS metadata never is, since the repository would be
+/// broken without any of it, however large.
+#[cfg(feature = "copy")]
+fn is_oversized(path: &Path, entry: &DirEntry, max_file_size: Option<u64>) -> Result<bool> {
+    let Some(max_file_size) = max_file_size else {
+        return Ok(false);
+    };
+    if !entry.file_type().is_some_and(|ty| ty.is_file()) || in_vcs_dir(path) {
+        return Ok(false);
+    }
+    let metadata = entry
+        .metadata()
+        .with_context(|| format!("read metadata of {}", entry.path().display()))?;
+    Ok(metadata.len() > max_file_size)
+}
+
+/// There isn't enough free disk space to copy a project into a new workspace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsufficientSpace {
+    /// The directory the workspace would be created in.
+    pub dir: PathBuf,
+
+    /// The estimated size of the copy, in bytes.
+    pub needed: u64,
+
+    /// The free space available in `dir`, in bytes.
+    pub available: u64,
+}
+
+impl fmt::Display for InsufficientSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "not enough disk space for the workspace: the project needs about {} but only {} is free in {}",
+            format_size(self.needed),
+            format_size(self.available),
+            self.dir.display()
+        )
+    }
+}
+
+impl std::error::Error for InsufficientSpace {}
+
+/// Copying a project into a new workspace would copy more than was allowed without
+/// asking first, which usually means a huge project, or one with large files that
+/// should be excluded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyTooLarge {
+    /// What would be copied.
+    pub estimate: CopyEstimate,
+
+    /// The most that may be copied without asking, in bytes.
+    pub limit: u64,
+}
+
+impl fmt::Display for CopyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the workspace would copy {} files ({}), more than the {} allowed without confirmation",
+            self.estimate.files,
+            format_size(self.estimate.bytes),
+            format_size(self.limit)
+        )
+    }
+}
+
+impl std::error::Error for CopyTooLarge {}
+
+/// Check that `dir` has room for a copy of the project made by [`copy_workspace`],
+/// failing with [`InsufficientSpace`] otherwise.
+///
+/// Running out of space partway through would leave a broken workspace,
+/// and copying a large project only to find that out wastes a lot of time.
+pub fn check_space(project: &Path, dir: &Path, exclude: &[impl AsRef<str>]) -> Result<()> {
+    ensure_space(dir, estimate_copy(project, exclude, None)?.bytes)
+}
+
+/// Like [`check_space`], for a copy already [estimated](estimate_copy) to need `needed` bytes.
+pub fn ensure_space(dir: &Path, needed: u64) -> Result<()> {
+    let Some(available) = available_space(dir)? else {
+        return Ok(());
+    };
+    if needed > available {
+        return Err(InsufficientSpace {
+            dir: dir.to_path_buf(),
+            needed,
+            available,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// What [`copy_workspace`] would copy, as counted by [`estimate_copy`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CopyEstimate {
+    /// The number of files.
+    pub files: u64,
+
+    /// The total size of the files, in bytes.
+    pub bytes: u64,
+}
+
+/// Count the files [`copy_workspace`] would copy, and their total size,
+/// leaving out files larger than `max_file_size`.
+#[cfg(feature = "copy")]
+pub fn estimate_copy(
+    project: &Path,
+    exclude: &[impl AsRef<str>],
+    max_file_size: Option<u64>,
+) -> Result<CopyEstimate> {
+    let exclude = matcher(project, exclude)?;
+    let mut estimate = CopyEstimate::default();
+    let mut links = HardLinks::default();
+    // Entries that can't be read won't be copied either, so they're not counted.
+    for entry in walk(project)?.flatten() {
+        let is_file = entry.file_type().is_some_and(|ty| ty.is_file());
+        if !is_file
+            || exclude
+                .matched_path_or_any_parents(entry.path(), false)
+                .is_ignore()
+        {
+            continue;
+        }
+        let bytes = entry.metadata().map_or(0, |metadata| metadata.len());
+        if max_file_size.is_some_and(|max| bytes > max)
+            && !relative(project, entry.path()).is_ok_and(in_vcs_dir)
+        {
+            continue;
+        }
+        estimate.files += 1;
+        // Hard links to a file already counted are linked rather than copied.
+        if links.earlier(&entry, entry.path()).is_none() {
+            estimate.bytes += bytes;
+        }
+    }
+    Ok(estimate)
+}
+
+#[cfg(not(feature = "copy"))]
+pub fn estimate_copy(
+    _project: &Path,
+    _exclude: &[impl AsRef<str>],
+    _max_file_size: Option<u64>,
+) -> Result<CopyEstimate> {
+    bail!("estimating copies of projects requires winlock's `copy` feature")
+}
+
+/// The free space available to unprivileged users in the file system holding `dir`,
+/// or that would hold it if it doesn't exist yet, if it can be determined on this platform.
+#[cfg(unix)]
+pub fn available_space(dir: &Path) -> Result<Option<u64>> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    // Such as a workspace root that's created along with the first workspace.
+    let dir = dir.ancestors().find(|dir| dir.exists()).unwrap_or(dir);
+    let path = CString::new(dir.as_os_str().as_bytes())
+        .with_context(|| format!("invalid path: {}", dir.display()))?;
+    // SAFETY: `statvfs` is plain data, for which all zeroes is valid,
+    // and both pointers are valid for the duration of the call.
+    let stat = unsafe {
+        let mut stat = std::mem::zeroed::<libc::statvfs>();
+        if libc::statvfs(path.as_ptr(), &mut stat) == -1 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("get free space: {}", dir.display()));
+        }
+        stat
+    };
+    #[allow(
+        clippy::unnecessary_cast,
+        reason = "the field types vary between platforms"
+    )]
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+/// The free space available to unprivileged users in the file system holding `dir`,
+/// if it can be determined on this platform.
+#[cfg(not(unix))]
+pub fn available_space(_dir: &Path) -> Result<Option<u64>> {
+    Ok(None)
+}
+
+/// The total size, in bytes, of everything in `dir`, ignored files included.
+pub fn disk_usage(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    walk_all(dir, &mut |_, metadata| {
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+        Ok(())
+    })?;
+    Ok(size)
+}
+
+/// Call `visit` with everything in `dir`, ignored files and VCS metadata included,
+/// and its metadata; symlinks aren't followed.
+fn walk_all(dir: &Path, visit: &mut dyn FnMut(&Path, &fs::Metadata) -> Result<()>) -> Result<()> {
+    let entries = fs::read_dir(dir).with_context(|| format!("walk {}", dir.display()))?;
+    for entry in entries {
+        let path = entry.context("walk directory")?.path();
+        let metadata = fs::symlink_metadata(&path)
+            .with_context(|| format!("read metadata: {}", path.display()))?;
+        visit(&path, &metadata)?;
+        if metadata.is_dir() {
+            walk_all(&path, visit)?;
+        }
+    }
+    Ok(())
+}
+
+/// Format a size in bytes for people to read, such as `1.5 GiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next in &UNITS[1..] {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{size:.1} {unit}")
+}
+
+/// A size in bytes, as configured: a number of bytes, or a number with a unit such as
+/// `2 GiB` or `500MB`. Binary units (`KiB`, `MiB`, ...) are powers of 1024, and
+/// decimal ones (`KB`, `MB`, ...) powers of 1000.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(try_from = "ByteSizeSetting", into = "String")]
+pub struct ByteSize(pub u64);
+
+/// The units [`ByteSize`] understands, largest first so that sizes are shown in the largest
+/// that divides them evenly.
+const BYTE_UNITS: [(&str, u64); 9] = [
+    ("TiB", 1 << 40),
+    ("TB", 1_000_000_000_000),
+    ("GiB", 1 << 30),
+    ("GB", 1_000_000_000),
+    ("MiB", 1 << 20),
+    ("MB", 1_000_000),
+    ("KiB", 1 << 10),
+    ("KB", 1_000),
+    ("B", 1),
+];
+
+impl FromStr for ByteSize {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number = number
+            .parse::<f64>()
+            .with_context(|| format!("size '{s}' must start with a number"))?;
+        let multiplier = match unit.trim() {
+            "" => 1,
+            unit => match BYTE_UNITS
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(unit))
+            {
+                Some((_, multiplier)) => *multiplier,
+                None => bail!(
+                    "unknown unit in size '{s}'; use B, KB, KiB, MB, MiB, GB, GiB, TB, or TiB"
+                ),
+            },
+        };
+        Ok(Self((number * multiplier as f64) as u64))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, multiplier) = BYTE_UNITS
+            .iter()
+            .find(|(_, multiplier)| self.0 >= *multiplier && self.0.is_multiple_of(*multiplier))
+            .unwrap_or(&("B", 1));
+        write!(f, "{} {name}", self.0 / multiplier)
+    }
+}
+
+impl From<ByteSize> for String {
+    fn from(size: ByteSize) -> Self {
+        size.to_string()
+    }
+}
+
+/// How a [`ByteSize`] may be written in the configuration.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ByteSizeSetting {
+    Bytes(u64),
+    Text(String),
+}
+
+impl TryFrom<ByteSizeSetting> for ByteSize {
+    type Error = Report;
+
+    fn try_from(setting: ByteSizeSetting) -> Result<Self> {
+        match setting {
+            ByteSizeSetting::Bytes(bytes) => Ok(Self(bytes)),
+            ByteSizeSetting::Text(text) => text.parse(),
+        }
+    }
+}
+
+#[cfg(feature = "copy")]
+fn matcher(root: &Path, patterns: &[impl AsRef<str>]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(root);
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        builder
+            .add_line(None, pattern)
+            .with_context(|| format!("parse exclude pattern '{pattern}'"))?;
+    }
+    builder.build().context("build exclude patterns")
+}
+
+fn relative<'a>(root: &Path, path: &'a Path) -> Result<&'a Path> {
+    path.strip_prefix(root)
+        .with_context(|| format!("relativize {}", path.display()))
+}
+
+/// Delete everything in the workspace that [`copy_workspace`] would copy,
+/// leaving ignored files such as build artifacts in place.
+#[cfg(feature = "copy")]
+pub fn clear_workspace(workspace: &Path) -> Result<()> {
+    let entries = walk(workspace)?
+        .collect::<Result<Vec<_>, _>>()
+        .context("walk workspace")?;
+
+    // Directories are removed after their contents, and only if that left them
+    // empty: ones holding ignored files are kept along with those files.
+    let (dirs, files) = entries
+        .into_iter()
+        .filter(|entry| entry.depth() > 0)
+        .partition::<Vec<_>, _>(|entry| entry.file_type().is_some_and(|ty| ty.is_dir()));
+    for entry in files {
+        fs::remove_file(entry.path())
+            .with_context(|| format!("remove {}", entry.path().display()))?;
+    }
+    for entry in dirs.iter().rev() {
+        let _ = fs::remove_dir(entry.path());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "copy"))]
+pub fn clear_workspace(_workspace: &Path) -> Result<()> {
+    bail!("clearing workspaces requires winlock's `copy` feature")
+}
+
+/// Delete `dir` and everything in it, like [`fs::remove_dir_all`] but faster
+/// for large directories. A directory that doesn't exist is already deleted.
+pub fn delete_dir(dir: &Path) -> Result<()> {
+    // Deleting is mostly waiting on the filesystem, so files are deleted on many
+    // threads first. That's best effort: whatever it leaves, including every
+    // directory, is deleted afterwards, which reports any errors properly.
+    #[cfg(feature = "copy")]
+    WalkBuilder::new(dir)
+        .standard_filters(false)
+        .build_parallel()
+        .run(|| {
+            Box::new(|entry| {
+                if let Ok(entry) = entry {
+                    if !entry.file_type().is_some_and(|ty| ty.is_dir()) {
+                        let _ = fs::remove_file(entry.path());
+                    }
+                }
+                WalkState::Continue
+            })
+        });
+    match fs::remove_dir_all(dir) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            Err(err).with_context(|| format!("remove {}", dir.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Walk what's in `root` that isn't ignored, by its `.gitignore` files or its
+/// [`.annaignore`](ANNAIGNORE).
+#[cfg(feature = "copy")]
+fn walk(root: &Path) -> Result<ignore::Walk> {
+    // Entries are left out here rather than walked and skipped, so the walk
+    // doesn't descend into the large directories this is usually for.
+    let annaignore = annaignore(root)?;
+    Ok(WalkBuilder::new(root)
+        .hidden(false)
+        .ignore(false)
+        .parents(false)
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|ty| ty.is_dir());
+            !annaignore.matched(entry.path(), is_dir).is_ignore()
+        })
+        .build())
+}
+
+/// The patterns of the [`.annaignore`](ANNAIGNORE) at `root`, if it has one.
+#[cfg(feature = "copy")]
+fn annaignore(root: &Path) -> Result<Gitignore> {
+    let path = root.join(ANNAIGNORE);
+    if !path.is_file() {
+        return Ok(Gitignore::empty());
+    }
+    let mut builder = GitignoreBuilder::new(root);
+    if let Some(err) = builder.add(&path) {
+        return Err(err).with_context(|| format!("parse {}", path.display()));
+    }
+    builder
+        .build()
+        .with_context(|| format!("build patterns of {}", path.display()))
+}
+
+/// Copy `entry` of the project into the workspace, passing `progress` how much
+/// of a large file has been copied so far, and its size.
+#[cfg(feature = "copy")]
+fn copy_workspace_entry(
+    project: &Path,
+    workspace: &Path,
+    entry: &DirEntry,
+    report: &mut CopyReport,
+    cancel: &Cancel,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<()> {
+    let source = entry.path();
+    let destination = workspace.join(relative(project, source)?);
+
+    let Some(file_type) = entry.file_type() else {
+        return Ok(());
+    };
+
+    if file_type.is_dir() {
+        fs::create_dir_all(&destination)
+            .with_context(|| format!("create directory {}", destination.display()))?;
+        copy_directory_permissions(source, &destination)
+    } else if file_type.is_symlink() {
+        copy_symlink(source, &destination)?;
+        report.files += 1;
+        Ok(())
+    } else {
+        report.bytes += copy_file_with_progress(entry, &destination, cancel, progress)?;
+        report.files += 1;
+        Ok(())
+    }
+}
+
+/// Cache directories, such as build output, seeded into new workspaces from a template
+/// so that agents don't start by rebuilding everything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caches {
+    /// The directory the caches are taken from, such as the project itself.
+    pub template: PathBuf,
+
+    /// The cache directories, relative to both the template and the workspace.
+    pub dirs: Vec<PathBuf>,
+
+    /// Link the caches into workspaces rather than copying them. That's much faster,
+    /// but every workspace then shares, and builds into, the template's caches.
+    pub link: bool,
+
+    /// Patterns for files withheld from workspaces, as for [`copy_workspace`],
+    /// which are left out of copied caches; caches holding any aren't linked.
+    pub exclude: Vec<String>,
+}
+
+/// What [`seed_caches`] brought into a workspace.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheReport {
+    /// The cache directories seeded, relative to the workspace.
+    pub seeded: Vec<PathBuf>,
+
+    /// The total size of the files copied, in bytes; nothing is copied when linking.
+    pub bytes: u64,
+}
+
+/// Bring `caches` from their template into `workspace`.
+///
+/// Everything in a cache is copied, ignored files included, since that's what
+/// caches are made of, except files that are withheld. Caches the template doesn't
+/// have are skipped, as are ones the workspace already has, which were copied from
+/// the project with the rest of it.
+#[cfg(feature = "copy")]
+pub fn seed_caches(workspace: &Path, caches: &Caches) -> Result<CacheReport> {
+    let exclude = matcher(&caches.template, &caches.exclude)?;
+    let mut report = CacheReport::default();
+    for dir in &caches.dirs {
+        // Caches are only ever seeded inside the workspace.
+        if !dir
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!(
+                "cache directory must be a relative path within the project: {}",
+                dir.display()
+            );
+        }
+        // Nor through symlinks, such as one the project checked in, out of either.
+        if through_symlink(&caches.template, dir) || through_symlink(workspace, dir) {
+            bail!(
+                "cache directory must not be behind a symlink: {}",
+                dir.display()
+            );
+        }
+        let source = caches.template.join(dir);
+        let destination = workspace.join(dir);
+        if !source.is_dir() || fs::symlink_metadata(&destination).is_ok() {
+            continue;
+        }
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("create directory {}", parent.display()))?;
+        }
+
+        if caches.link {
+            if let Some(secret) = first_excluded(&source, &exclude)? {
+                bail!(
+                    "cache {} holds files withheld from workspaces, such as {}, \
+                     so it can't be linked",
+                    dir.display(),
+                    secret.display()
+                );
+            }
+            symlink(&source, &destination)
+                .with_context(|| format!("link cache {}", destination.display()))?;
+        } else {
+            report.bytes += copy_tree(&source, &destination, &exclude)
+                .with_context(|| format!("copy cache {}", source.display()))?;
+        }
+        report.seeded.push(dir.clone());
+    }
+    Ok(report)
+}
+
+#[cfg(not(feature = "copy"))]
+pub fn seed_caches(_workspace: &Path, _caches: &Caches) -> Result<CacheReport> {
+    bail!("seeding caches requires winlock's `copy` feature")
+}
+
+/// Whether any of the directories leading to `dir` within `root`, or `dir` itself,
+/// is a symlink.
+fn through_symlink(root: &Path, dir: &Path) -> bool {
+    dir.ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .any(|ancestor| {
+            fs::symlink_metadata(root.join(ancestor)).is_ok_and(|metadata| metadata.is_symlink())
+        })
+}
+
+/// The first entry in `dir` matching `exclude`, if any.
+#[cfg(feature = "copy")]
+fn first_excluded(dir: &Path, exclude: &Gitignore) -> Result<Option<PathBuf>> {
+    for entry in WalkBuilder::new(dir).standard_filters(false).build() {
+        let entry = entry.context("walk directory")?;
+        let is_dir = entry.file_type().is_some_and(|ty| ty.is_dir());
+        if exclude
+            .matched_path_or_any_parents(entry.path(), is_dir)
+            .is_ignore()
+        {
+            return Ok(Some(entry.into_path()));
+        }
+    }
+    Ok(None)
+}
+
+/// Copy everything in `source` to `destination`, except what matches `exclude`,
+/// returning the total size of the files.
+#[cfg(feature = "copy")]
+fn copy_tree(source: &Path, destination: &Path, exclude: &Gitignore) -> Result<u64> {
+    let mut bytes = 0;
+    let exclude = exclude.clone();
+    let walk = WalkBuilder::new(source)
+        .standard_filters(false)
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|ty| ty.is_dir());
+            !exclude.matched(entry.path(), is_dir).is_ignore()
+        })
+        .build();
+    for entry in walk {
+        let entry = entry.context("walk directory")?;
+        let target = destination.join(relative(source, entry.path())?);
+        let Some(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            fs::create_dir_all(&target)
+                .with_context(|| format!("create directory {}", target.display()))?;
+            copy_directory_permissions(entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            copy_symlink(entry.path(), &target)?;
+        } else {
+            bytes += copy_file(&entry, &target)?;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Directories holding VCS metadata, which refreshing a workspace leaves alone.
+const VCS_DIRS: &[&str] = &[".git", ".hg", ".jj"];
+
+/// Whether `path`, relative to the project, is in a directory holding VCS metadata.
+fn in_vcs_dir(path: &Path) -> bool {
+    path.components()
+        .next()
+        .is_some_and(|first| VCS_DIRS.iter().any(|dir| first.as_os_str() == *dir))
+}
+
+#[cfg(feature = "copy")]
+fn refresh_workspace_entry(
+    project: &Path,
+    workspace: &Path,
+    entry: &DirEntry,
+    since: SystemTime,
+    copy: &mut CopyReport,
+    report: &mut RefreshReport,
+) -> Result<()> {
+    let source = entry.path();
+    let path = relative(project, source)?;
+    let Some(file_type) = entry.file_type().filter(|_| !in_vcs_dir(path)) else {
+        return Ok(());
+    };
+    let destination = workspace.join(path);
+    let metadata = entry
+        .metadata()
+        .with_context(|| format!("read metadata of {}", source.display()))?;
+    // Where the file system doesn't record when files were created, which some
+    // report as the epoch, those changed since count as new.
+    let existed = metadata
+        .created()
+        .ok()
+        .filter(|&created| created > SystemTime::UNIX_EPOCH)
+        .or_else(|| metadata.modified().ok())
+        .is_some_and(|created| created <= since);
+
+    if file_type.is_dir() {
+        if destination.is_dir() || existed {
+            return Ok(());
+        }
+        fs::create_dir_all(&destination)
+            .with_context(|| format!("create directory {}", destination.display()))?;
+        return copy_directory_permissions(source, &destination);
+    }
+
+    let existing = match fs::symlink_metadata(&destination) {
+        Ok(metadata) => Some(metadata),
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => {
+            return Err(err).with_context(|| format!("read metadata of {}", destination.display()))
+        }
+    };
+    if existing.is_none() && existed {
+        if metadata.modified().is_ok_and(|modified| modified > since) {
+            report.conflicts.push(path.to_path_buf());
+        }
+        return Ok(());
+    }
+    if let Some(existing) = existing {
+        let unchanged = if file_type.is_symlink() {
+            fs::read_link(source).ok() == fs::read_link(&destination).ok()
+        } else {
+            existing.len() == metadata.len() && existing.modified().ok() == metadata.modified().ok()
+        };
+        if unchanged {
+            report.unchanged += 1;
+            return Ok(());
+        }
+        if existing.modified().is_ok_and(|modified| modified > since) {
+            report.conflicts.push(path.to_path_buf());
+            return Ok(());
+        }
+        // Removed rather than overwritten, since the copy may be read-only.
+        fs::remove_file(&destination)
+            .with_context(|| format!("remove {}", destination.display()))?;
+    } else if let Some(parent) = destination.parent().filter(|parent| !parent.is_dir()) {
+        // New in a directory the workspace deleted.
+        fs::create_dir_all(parent)
+            .with_context(|| format!("create directory {}", parent.display()))?;
+    }
+
+    if file_type.is_symlink() {
+        copy_symlink(source, &destination)?;
+    } else {
+        copy.bytes += copy_file(entry, &destination)?;
+    }
+    copy.files += 1;
+    Ok(())
+}
+
+fn copy_symlink(source: &Path, destination: &Path) -> Result<()> {
+    let target =
+        fs::read_link(source).with_context(|| format!("read symlink {}", source.display()))?;
+    symlink(&target, destination)
+        .with_context(|| format!("create symlink {}", destination.display()))
+}
+
+/// Copy the file at `entry` to `destination`, returning its size.
+#[cfg(feature = "copy")]
+fn copy_file(entry: &DirEntry, destination: &Path) -> Result<u64> {
+    copy_file_with_progress(entry, destination, &Cancel::default(), &mut |_, _| {})
+}
+
+/// Like [`copy_file`], copying [large files](CHUNKED_COPY_SIZE) a chunk at a time
+/// until `cancel` is cancelled, passing `progress` how much has been copied so far
+/// and the file's size after each chunk.
+#[cfg(feature = "copy")]
+fn copy_file_with_progress(
+    entry: &DirEntry,
+    destination: &Path,
+    cancel: &Cancel,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<u64> {
+    let source = entry.path();
+    let size = entry
+        .metadata()
+        .with_context(|| format!("read metadata of {}", source.display()))?
+        .len();
+    let bytes = if size < CHUNKED_COPY_SIZE {
+        fs::copy(source, destination).with_context(|| format!("copy {}", source.display()))?
+    } else {
+        copy_in_chunks(source, destination, size, cancel, progress)?
+    };
+    copy_file_metadata(entry, destination)?;
+    Ok(bytes)
+}
+
+/// Copy a [large file](CHUNKED_COPY_SIZE) a [chunk](COPY_CHUNK_SIZE) at a time.
+#[cfg(not(windows))]
+fn copy_in_chunks(
+    source: &Path,
+    destination: &Path,
+    size: u64,
+    cancel: &Cancel,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<u64> {
+    use std::io::Read;
+
+    let mut reader = File::open(source).with_context(|| format!("open {}", source.display()))?;
+    let mut writer =
+        File::create(destination).with_context(|| format!("create {}", destination.display()))?;
+    // Each chunk is written before the next is read, so a slow destination holds
+    // the copy back rather than letting what's been read pile up in memory.
+    let mut copied = 0;
+    loop {
+        cancel.check()?;
+        let chunk = io::copy(&mut (&mut reader).take(COPY_CHUNK_SIZE), &mut writer)
+            .with_context(|| format!("copy {}", source.display()))?;
+        if chunk == 0 {
+            return Ok(copied);
+        }
+        copied += chunk;
+        progress(copied, size);
+    }
+}
+
+/// Copy a [large file](CHUNKED_COPY_SIZE) with `CopyFile2`, which reports each chunk
+/// it copies and can be cancelled between them. The file isn't buffered: it's read
+/// once, so caching it would only evict what the rest of the copy, and the agent's
+/// builds, will want; and Windows copies large files several times faster that way.
+#[cfg(windows)]
+fn copy_in_chunks(
+    source: &Path,
+    destination: &Path,
+    size: u64,
+    cancel: &Cancel,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<u64> {
+    use std::{ffi::c_void, os::windows::ffi::OsStrExt};
+
+    use windows_sys::Win32::Storage::FileSystem::{
+        CopyFile2, COPYFILE2_CALLBACK_CHUNK_FINISHED, COPYFILE2_EXTENDED_PARAMETERS,
+        COPYFILE2_MESSAGE, COPYFILE2_MESSAGE_ACTION, COPYFILE2_PROGRESS_CANCEL,
+        COPYFILE2_PROGRESS_CONTINUE, COPY_FILE_NO_BUFFERING,
+    };
+
+    struct Callback<'a> {
+        cancel: &'a Cancel,
+
+        progress: &'a mut dyn FnMut(u64, u64),
+    }
+
+    unsafe extern "system" fn on_progress(
+        message: *const COPYFILE2_MESSAGE,
+        callback: *const c_void,
+    ) -> COPYFILE2_MESSAGE_ACTION {
+        // SAFETY: `CopyFile2` passes a valid message, and the context it was given,
+        // which is only used on this thread and outlives the call.
+        let (message, callback) =
+            unsafe { (&*message, &mut *callback.cast_mut().cast::<Callback<'_>>()) };
+        if callback.cancel.is_cancelled() {
+            return COPYFILE2_PROGRESS_CANCEL;
+        }
+        if message.Type == COPYFILE2_CALLBACK_CHUNK_FINISHED {
+            // SAFETY: messages of this type hold this variant.
+            let chunk = unsafe { message.Info.ChunkFinished };
+            (callback.progress)(chunk.uliTotalBytesTransferred, chunk.uliTotalFileSize);
+        }
+        COPYFILE2_PROGRESS_CONTINUE
+    }
+
+    let wide = |path: &Path| {
+        path.as_os_str()
+            .encode_wide()
+            .chain([0])
+            .collect::<Vec<_>>()
+    };
+    let (from, to) = (wide(source), wide(destination));
+    let mut callback = Callback { cancel, progress };
+    let parameters = COPYFILE2_EXTENDED_PARAMETERS {
+        dwSize: u32::try_from(size_of::<COPYFILE2_EXTENDED_PARAMETERS>())
+            .expect("the parameters are small"),
+        dwCopyFlags: COPY_FILE_NO_BUFFERING,
+        pfCancel: std::ptr::null_mut(),
+        pProgressRoutine: Some(on_progress),
+        pvCallbackContext: (&raw mut callback).cast(),
+    };
+    // SAFETY: both paths are NUL-terminated, and the parameters, along with
+    // the callback they point to, outlive the call.
+    let result = unsafe { CopyFile2(from.as_ptr(), to.as_ptr(), &parameters) };
+    // A cancelled copy fails, and deletes what it copied.
+    cancel.check()?;
+    if result < 0 {
+        // Windows errors come wrapped in an HRESULT of the Win32 facility.
+        let err = match (result as u32) >> 16 {
+            0x8007 => io::Error::from_raw_os_error(result & 0xffff),
+            _ => io::Error::other(format!("CopyFile2 failed with HRESULT {result:#010x}")),
+        };
+        return Err(err).with_context(|| format!("copy {}", source.display()));
+    }
+    Ok(size)
+}
+
+/// Give the copy of the file at `entry` at `destination` the same metadata.
+#[cfg(feature = "copy")]
+fn copy_file_metadata(entry: &DirEntry, destination: &Path) -> Result<()> {
+    let source = entry.path();
+    // Not every platform's copy carries the mode over, and scripts
+    // that lose their executable bit fail in confusing ways.
+    let metadata = entry
+        .metadata()
+        .with_context(|| format!("read metadata of {}", source.display()))?;
+    let modified = metadata
+        .modified()
+        .with_context(|| format!("read modification time of {}", source.display()))?;
+    // The modification time is set first, since the permissions may make the copy read-only.
+    // Build tools compare it to decide what to rebuild, so it's kept as well.
+    set_modified(destination, modified)
+        .with_context(|| format!("set modification time of {}", destination.display()))?;
+    fs::set_permissions(destination, metadata.permissions())
+        .with_context(|| format!("set permissions of {}", destination.display()))
+}
+
+/// Directories keep their permissions, except that the owner can always
+/// write to them: the copy has to be filled in, and later removed.
+#[cfg(unix)]
+fn copy_directory_permissions(source: &Path, destination: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(source)
+        .with_context(|| format!("read metadata of {}", source.display()))?
+        .permissions()
+        .mode();
+    fs::set_permissions(destination, fs::Permissions::from_mode(mode | 0o700))
+        .with_context(|| format!("set permissions of {}", destination.display()))
+}
+
+/// Windows ignores the read-only attribute on directories, so there's nothing to copy.
+#[cfg(windows)]
+fn copy_directory_permissions(_source: &Path, _destination: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Set the modification time of the file at `path`, even if it's read-only.
+fn set_modified(path: &Path, modified: SystemTime) -> std::io::Result<()> {
+    let mut options = File::options();
+    #[cfg(unix)]
+    options.read(true);
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+
+        // FILE_WRITE_ATTRIBUTES: enough to set times, without needing write access.
+        options.access_mode(0x100);
+    }
+    options.open(path)?.set_modified(modified)
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Links to directories fall back to junctions where symlinks can't be created,
+/// since creating them needs Developer Mode or an administrator, and junctions don't.
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    use windows_sys::Win32::Foundation::ERROR_PRIVILEGE_NOT_HELD;
+
+    let resolved = link.parent().map(|parent| parent.join(target));
+    let Some(resolved) = resolved.filter(|resolved| resolved.is_dir()) else {
+        return std::os::windows::fs::symlink_file(target, link);
+    };
+    match std::os::windows::fs::symlink_dir(target, link) {
+        Err(err) if err.raw_os_error() == i32::try_from(ERROR_PRIVILEGE_NOT_HELD).ok() => {
+            junction(&std::path::absolute(resolved)?, link)
+        }
+        linked => linked,
+    }
+}
+
+/// Create a junction at `link` to the directory at the absolute path `target`,
+/// by making an empty directory there into a mount point reparse point.
+#[cfg(windows)]
+fn junction(target: &Path, link: &Path) -> std::io::Result<()> {
+    use std::{
+        fs::OpenOptions,
+        os::windows::{ffi::OsStrExt, fs::OpenOptionsExt, io::AsRawHandle},
+        ptr,
+    };
+
+    use windows_sys::Win32::{
+        Storage::FileSystem::{FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OPEN_REPARSE_POINT},
+        System::{
+            Ioctl::FSCTL_SET_REPARSE_POINT, SystemServices::IO_REPARSE_TAG_MOUNT_POINT,
+            IO::DeviceIoControl,
+        },
+    };
+
+    // Junctions record their target twice: as the NT path the system follows,
+    // and as the path shown to people, both without the verbatim prefix.
+    let target = target
+        .to_str()
+        .and_then(|target| target.strip_prefix(r"\\?\"))
+        .map_or(target, Path::new);
+    let wide = |path: &std::ffi::OsStr| path.encode_wide().collect::<Vec<u16>>();
+    let print = wide(target.as_os_str());
+    let substitute = [wide(r"\??\".as_ref()), print.clone()].concat();
+    let bytes = |name: &[u16]| u16::try_from(name.len() * 2).map_err(io::Error::other);
+    let (substitute_len, print_len) = (bytes(&substitute)?, bytes(&print)?);
+    // The names' offsets and lengths, then both names, each terminated by a NUL.
+    let data_len = u16::try_from(8 + substitute.len() * 2 + 2 + print.len() * 2 + 2)
+        .map_err(io::Error::other)?;
+    let mut buffer = Vec::with_capacity(8 + usize::from(data_len));
+    buffer.extend_from_slice(&IO_REPARSE_TAG_MOUNT_POINT.to_le_bytes());
+    buffer.extend_from_slice(&data_len.to_le_bytes());
+    buffer.extend_from_slice(&0u16.to_le_bytes());
+    for field in [0, substitute_len, substitute_len + 2, print_len] {
+        buffer.extend_from_slice(&field.to_le_bytes());
+    }
+    for unit in substitute.iter().chain(&[0]).chain(&print).chain(&[0]) {
+        buffer.extend_from_slice(&unit.to_le_bytes());
+    }
+    let buffer_len = u32::try_from(buffer.len()).map_err(io::Error::other)?;
+
+    fs::create_dir(link)?;
+    let made = OpenOptions::new()
+        .write(true)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS | FILE_FLAG_OPEN_REPARSE_POINT)
+        .open(link)
+        .and_then(|dir| {
+            let mut returned = 0;
+            // SAFETY: the handle is open for the duration of the call, and the input
+            // buffer, of the given length, holds a complete reparse data buffer.
+            let result = unsafe {
+                DeviceIoControl(
+                    dir.as_raw_handle(),
+                    FSCTL_SET_REPARSE_POINT,
+                    buffer.as_ptr().cast(),
+                    buffer_len,
+                    ptr::null_mut(),
+                    0,
+                    &mut returned,
+                    ptr::null_mut(),
+                )
+            };
+            match result {
+                0 => Err(io::Error::last_os_error()),
+                _ => Ok(()),
+            }
+        });
+    if made.is_err() {
+        let _ = fs::remove_dir(link);
+    }
+    made
+}
@@ -0,0 +1,606 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Strategies for copying a project into a workspace.
+//!
+//! Which is fastest depends on the project and, above all, the filesystem:
+//! reflinks are nearly free on Btrfs, XFS, and APFS but unavailable elsewhere,
+//! and parallelism helps far more on SSDs than on spinning disks.
+//! `anna bench copy` measures them on a particular project.
+
+use std::{fmt, path::Path, str::FromStr};
+#[cfg(feature = "copy")]
+use std::{
+    io::Write,
+    num::NonZero,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+#[cfg(feature = "copy")]
+use color_eyre::eyre::{bail, Context};
+use color_eyre::eyre::{eyre, Error, Result};
+#[cfg(feature = "copy")]
+use ignore::{gitignore::Gitignore, DirEntry};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "copy")]
+use super::{
+    annaignore, copy_file_metadata, copy_file_with_progress, copy_tree, copy_workspace_entry,
+    ensure_outside, for_each_included, matcher, record_failure, relative, retrying, HardLinks,
+};
+use super::{copy_workspace_with_progress, CopyOptions, CopyProgress, CopyReport};
+
+/// A way of copying a project into a workspace.
+pub trait CopyEngine: fmt::Debug + Send + Sync {
+    /// The kind of engine this is.
+    fn kind(&self) -> CopyEngineKind;
+
+    /// Copy `project` into `workspace` with `options`, withholding files matching
+    /// `exclude` as [`copy_workspace`](super::copy_workspace) does, and calling
+    /// `progress` with what has been copied so far as it goes.
+    fn copy(
+        &self,
+        project: &Path,
+        workspace: &Path,
+        exclude: &[String],
+        options: &CopyOptions,
+        progress: &mut dyn FnMut(CopyProgress<'_>),
+    ) -> Result<CopyReport>;
+
+    /// How the workspace this engine makes differs from a plain copy, if it does;
+    /// such engines are only used when chosen explicitly.
+    fn caveat(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+/// The kinds of copy engine anna supports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CopyEngineKind {
+    /// Copy files one at a time.
+    #[default]
+    Naive,
+
+    /// Copy files on as many threads as there are CPUs, or more on Windows.
+    Parallel,
+
+    /// Clone files where the filesystem supports it, sharing their contents
+    /// until either copy changes, and copy them where it doesn't.
+    Reflink,
+
+    /// Hard link read-only files into the workspace, and copy the rest.
+    Hardlink,
+
+    /// Check out what's committed at `HEAD` from a copy of the repository.
+    GitArchive,
+}
+
+impl CopyEngineKind {
+    /// All supported engines.
+    pub const ALL: [CopyEngineKind; 5] = [
+        CopyEngineKind::Naive,
+        CopyEngineKind::Parallel,
+        CopyEngineKind::Reflink,
+        CopyEngineKind::Hardlink,
+        CopyEngineKind::GitArchive,
+    ];
+
+    /// The implementation of this engine.
+    pub fn engine(self) -> &'static dyn CopyEngine {
+        match self {
+            CopyEngineKind::Naive => &Naive,
+            #[cfg(feature = "copy")]
+            CopyEngineKind::Parallel => &Parallel,
+            #[cfg(feature = "copy")]
+            CopyEngineKind::Reflink => &Reflink,
+            #[cfg(feature = "copy")]
+            CopyEngineKind::Hardlink => &Hardlink,
+            #[cfg(feature = "copy")]
+            CopyEngineKind::GitArchive => &GitArchive,
+            // Which copies nothing without the feature, and says so.
+            #[cfg(not(feature = "copy"))]
+            _ => &Naive,
+        }
+    }
+}
+
+impl fmt::Display for CopyEngineKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CopyEngineKind::Naive => "naive",
+            CopyEngineKind::Parallel => "parallel",
+            CopyEngineKind::Reflink => "reflink",
+            CopyEngineKind::Hardlink => "hardlink",
+            CopyEngineKind::GitArchive => "git-archive",
+        })
+    }
+}
+
+impl FromStr for CopyEngineKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.to_string() == s)
+            .ok_or_else(|| {
+                let known = Self::ALL.map(|kind| kind.to_string()).join(", ");
+                eyre!("unknown copy engine '{s}'; expected one of: {known}")
+            })
+    }
+}
+
+/// Copies files one at a time.
+#[derive(Debug, Clone, Copy)]
+pub struct Naive;
+
+impl CopyEngine for Naive {
+    fn kind(&self) -> CopyEngineKind {
+        CopyEngineKind::Naive
+    }
+
+    fn copy(
+        &self,
+        project: &Path,
+        workspace: &Path,
+        exclude: &[String],
+        options: &CopyOptions,
+        progress: &mut dyn FnMut(CopyProgress<'_>),
+    ) -> Result<CopyReport> {
+        copy_workspace_with_progress(project, workspace, exclude, options, progress)
+    }
+}
+
+/// Copies files on as many threads as there are CPUs, or more on Windows.
+#[derive(Debug, Clone, Copy)]
+pub struct Parallel;
+
+#[cfg(feature = "copy")]
+impl CopyEngine for Parallel {
+    fn kind(&self) -> CopyEngineKind {
+        CopyEngineKind::Parallel
+    }
+
+    fn copy(
+        &self,
+        project: &Path,
+        workspace: &Path,
+        exclude: &[String],
+        options: &CopyOptions,
+        progress: &mut dyn FnMut(CopyProgress<'_>),
+    ) -> Result<CopyReport> {
+        copy_files_in_parallel(
+            project,
+            workspace,
+            exclude,
+            options,
+            progress,
+            |entry, destination, file_progress| {
+                copy_file_with_progress(entry, destination, &options.cancel, file_progress)
+            },
+        )
+    }
+}
+
+/// Clones files where the filesystem supports it, and copies them where it doesn't,
+/// as `cp --reflink=auto` does.
+#[derive(Debug, Clone, Copy)]
+pub struct Reflink;
+
+#[cfg(feature = "copy")]
+impl CopyEngine for Reflink {
+    fn kind(&self) -> CopyEngineKind {
+        CopyEngineKind::Reflink
+    }
+
+    fn copy(
+        &self,
+        project: &Path,
+        workspace: &Path,
+        exclude: &[String],
+        options: &CopyOptions,
+        progress: &mut dyn FnMut(CopyProgress<'_>),
+    ) -> Result<CopyReport> {
+        copy_files_in_parallel(
+            project,
+            workspace,
+            exclude,
+            options,
+            progress,
+            |entry, destination, file_progress| match clone_file(entry.path(), destination) {
+                Ok(()) => {
+                    copy_file_metadata(entry, destination)?;
+                    let metadata = entry
+                        .metadata()
+                        .with_context(|| format!("read metadata of {}", entry.path().display()))?;
+                    Ok(metadata.len())
+                }
+                Err(_) => {
+                    copy_file_with_progress(entry, destination, &options.cancel, file_progress)
+                }
+            },
+        )
+    }
+}
+
+/// Hard links read-only files into the workspace, falling back to copying them
+/// where the workspace is on another filesystem. Files that can be written are
+/// copied, since writing to a link in place would change the project's file too.
+#[derive(Debug, Clone, Copy)]
+pub struct Hardlink;
+
+#[cfg(feature = "copy")]
+impl CopyEngine for Hardlink {
+    fn kind(&self) -> CopyEngineKind {
+        CopyEngineKind::Hardlink
+    }
+
+    fn copy(
+        &self,
+        project: &Path,
+        workspace: &Path,
+        exclude: &[String],
+        options: &CopyOptions,
+        progress: &mut dyn FnMut(CopyProgress<'_>),
+    ) -> Result<CopyReport> {
+        copy_files_in_parallel(
+            project,
+            workspace,
+            exclude,
+            options,
+            progress,
+            |entry, destination, file_progress| {
+                let metadata = entry
+                    .metadata()
+                    .with_context(|| format!("read metadata of {}", entry.path().display()))?;
+                if !metadata.permissions().readonly() {
+                    return copy_file_with_progress(
+                        entry,
+                        destination,
+                        &options.cancel,
+                        file_progress,
+                    );
+                }
+                // The link shares the project's file, metadata included,
+                // so unlike a copy there's nothing more to carry over.
+                match std::fs::hard_link(entry.path(), destination) {
+                    Ok(()) => Ok(metadata.len()),
+                    Err(_) => {
+                        copy_file_with_progress(entry, destination, &options.cancel, file_progress)
+                    }
+                }
+            },
+        )
+    }
+
+    fn caveat(&self) -> Option<&'static str> {
+        Some("read-only files are shared with the project, so making one writable and writing to it changes the project")
+    }
+}
+
+/// Checks out what's committed at `HEAD` from a copy of the repository.
+///
+/// Unlike `git archive`, which it was once built on, this checks out everything
+/// committed, such as files marked `export-ignore` and the contents of LFS files,
+/// so that the workspace only differs from `HEAD` where files are left out.
+/// Those are never written to the workspace in the first place.
+#[derive(Debug, Clone, Copy)]
+pub struct GitArchive;
+
+#[cfg(feature = "copy")]
+impl CopyEngine for GitArchive {
+    fn kind(&self) -> CopyEngineKind {
+        CopyEngineKind::GitArchive
+    }
+
+    fn copy(
+        &self,
+        project: &Path,
+        workspace: &Path,
+        exclude: &[String],
+        options: &CopyOptions,
+        progress: &mut dyn FnMut(CopyProgress<'_>),
+    ) -> Result<CopyReport> {
+        ensure_outside(project, workspace)?;
+        let git_dir = project.join(".git");
+        if !git_dir.is_dir() {
+            bail!(
+                "the git-archive copy engine requires a git repository: {}",
+                project.display()
+            );
+        }
+        copy_tree(&git_dir, &workspace.join(".git"), &Gitignore::empty())
+            .context("copy git repository")?;
+        // Whatever the project has staged is left out, like what isn't committed at all.
+        git(workspace, &["read-tree", "HEAD"], None)?;
+
+        // What's committed can include files that must be withheld or are annaignored,
+        // so they're left out of the checkout; the patterns are the project's,
+        // since nothing has been checked out yet.
+        let exclude = matcher(project, exclude)?;
+        let annaignore = annaignore(project)?;
+        let listing = git(workspace, &["ls-tree", "-r", "-z", "--long", "HEAD"], None)?;
+        let mut report = CopyReport::default();
+        let mut checkout = Vec::new();
+        for record in listing.split(|&byte| byte == 0).filter(|r| !r.is_empty()) {
+            options.cancel.check()?;
+            let record = String::from_utf8_lossy(record);
+            let Some((info, path)) = record.split_once('\t') else {
+                bail!("unexpected git ls-tree output: {record}");
+            };
+            // Submodules are checked out by the repositories they're in.
+            let mut info = info.split_whitespace();
+            if info.nth(1) != Some("blob") {
+                continue;
+            }
+            let bytes = info.nth(1).and_then(|size| size.parse().ok()).unwrap_or(0);
+            let absolute = project.join(path);
+            if annaignore
+                .matched_path_or_any_parents(&absolute, false)
+                .is_ignore()
+            {
+                continue;
+            }
+            if exclude
+                .matched_path_or_any_parents(&absolute, false)
+                .is_ignore()
+            {
+                report.withheld.push(PathBuf::from(path));
+                continue;
+            }
+            if options.max_file_size.is_some_and(|max| bytes > max) {
+                report.oversized.push(PathBuf::from(path));
+                continue;
+            }
+            report.files += 1;
+            report.bytes += bytes;
+            checkout.extend_from_slice(path.as_bytes());
+            checkout.push(0);
+        }
+        git(
+            workspace,
+            &["checkout-index", "-z", "--stdin"],
+            Some(&checkout),
+        )?;
+        progress(CopyProgress::Entry(&report));
+        Ok(report)
+    }
+
+    fn caveat(&self) -> Option<&'static str> {
+        Some("only what's committed is copied, without uncommitted or untracked files")
+    }
+}
+
+/// Run git with `args` in `dir`, writing `input` to its standard input,
+/// and return its output.
+#[cfg(feature = "copy")]
+fn git(dir: &Path, args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>> {
+    let describe = || format!("run git {}", args.join(" "));
+    let mut child = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .stdin(match input {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(describe)?;
+    if let Some(input) = input {
+        // Dropped once written, so that git sees the end of its input.
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        stdin.write_all(input).with_context(describe)?;
+    }
+    let output = child.wait_with_output().with_context(describe)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "{} failed ({}): {}",
+            describe(),
+            output.status,
+            stderr.trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// What a thread copying files in parallel has done.
+#[cfg(feature = "copy")]
+enum Copied {
+    /// Part of a large file.
+    Chunk {
+        path: PathBuf,
+
+        copied: u64,
+
+        size: u64,
+    },
+
+    /// A whole file, or an error copying it.
+    File(Result<u64>),
+}
+
+/// Copy the project's directories and symlinks in order, then its files
+/// on [several threads](copy_threads) using `copy`, which passes the
+/// progress it makes on large files to the function it's given.
+#[cfg(feature = "copy")]
+fn copy_files_in_parallel(
+    project: &Path,
+    workspace: &Path,
+    exclude: &[String],
+    options: &CopyOptions,
+    progress: &mut dyn FnMut(CopyProgress<'_>),
+    copy: impl Fn(&DirEntry, &Path, &mut dyn FnMut(u64, u64)) -> Result<u64> + Sync,
+) -> Result<CopyReport> {
+    ensure_outside(project, workspace)?;
+    let mut report = CopyReport::default();
+    // Directories are created as they're walked, before their files
+    // are copied, and only files are worth spreading across threads.
+    let mut files = Vec::new();
+    // Hard links to files walked earlier are linked once those are copied.
+    let (mut links, mut linked) = (HardLinks::default(), Vec::new());
+    for_each_included(
+        project,
+        exclude,
+        options,
+        &mut report,
+        |entry, report| match entry.file_type() {
+            Some(ty) if ty.is_file() => {
+                let path = relative(project, entry.path())?;
+                match links.earlier(entry, path) {
+                    Some(earlier) => linked.push((entry.clone(), earlier)),
+                    None => files.push(entry.clone()),
+                }
+                Ok(())
+            }
+            _ => copy_workspace_entry(
+                project,
+                workspace,
+                entry,
+                report,
+                &options.cancel,
+                &mut |_, _| {},
+            ),
+        },
+    )?;
+
+    let threads = copy_threads();
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let (files, next, copy) = (&files, &next, &copy);
+            scope.spawn(move || {
+                while let Some(entry) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    if options.cancel.is_cancelled() {
+                        break;
+                    }
+                    let result = relative(project, entry.path()).and_then(|path| {
+                        let destination = workspace.join(path);
+                        retrying(|| {
+                            copy(entry, &destination, &mut |copied, size| {
+                                let path = path.to_path_buf();
+                                let _ = sender.send(Copied::Chunk { path, copied, size });
+                            })
+                        })
+                    });
+                    if sender.send(Copied::File(result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+        // Tallied here rather than on the copying threads, so that `progress`
+        // is only ever called from this one.
+        for copied in receiver {
+            match copied {
+                Copied::Chunk { path, copied, size } => {
+                    progress(CopyProgress::File {
+                        path: &path,
+                        copied,
+                        size,
+                    });
+                    continue;
+                }
+                Copied::File(Ok(bytes)) => {
+                    report.files += 1;
+                    report.bytes += bytes;
+                }
+                Copied::File(Err(err)) => record_failure(&mut report, &err),
+            }
+            progress(CopyProgress::Entry(&report));
+        }
+    });
+    for (entry, earlier) in &linked {
+        if options.cancel.is_cancelled() {
+            break;
+        }
+        let result = relative(project, entry.path()).and_then(|path| {
+            let destination = workspace.join(path);
+            match std::fs::hard_link(workspace.join(earlier), &destination) {
+                Ok(()) => Ok(0),
+                // Copied after all when it can't be linked, such as when
+                // the file it's linked to failed to copy.
+                Err(_) => retrying(|| copy(entry, &destination, &mut |_, _| {})),
+            }
+        });
+        match result {
+            Ok(bytes) => {
+                report.files += 1;
+                report.bytes += bytes;
+            }
+            Err(err) => record_failure(&mut report, &err),
+        }
+        progress(CopyProgress::Entry(&report));
+    }
+    // Files cut short by the cancellation were recorded as failures,
+    // so it's reported instead.
+    options.cancel.check()?;
+    report.finish()
+}
+
+/// How many threads copy files at once: one per CPU, except on Windows, where
+/// creating and closing each file waits on filter drivers such as antivirus
+/// scanners far more than on the CPU, and NTFS keeps up with more at once.
+#[cfg(feature = "copy")]
+fn copy_threads() -> usize {
+    let cpus = thread::available_parallelism().map_or(4, NonZero::get);
+    match cfg!(windows) {
+        true => (cpus * 4).min(64),
+        false => cpus,
+    }
+}
+
+/// Clone the file at `source` to `destination`, sharing its contents.
+#[cfg(target_os = "linux")]
+#[cfg(feature = "copy")]
+fn clone_file(source: &Path, destination: &Path) -> std::io::Result<()> {
+    use std::{fs::File, os::fd::AsRawFd};
+
+    let source = File::open(source)?;
+    let cloned = File::create(destination)?;
+    // SAFETY: both descriptors are open for the duration of the call.
+    let result = unsafe { libc::ioctl(cloned.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) };
+    if result == -1 {
+        let err = std::io::Error::last_os_error();
+        drop(cloned);
+        let _ = std::fs::remove_file(destination);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Clone the file at `source` to `destination`, sharing its contents.
+#[cfg(target_os = "macos")]
+#[cfg(feature = "copy")]
+fn clone_file(source: &Path, destination: &Path) -> std::io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))
+    };
+    let (source, destination) = (path(source)?, path(destination)?);
+    // SAFETY: both paths are valid NUL-terminated strings.
+    match unsafe { libc::clonefile(source.as_ptr(), destination.as_ptr(), 0) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+#[cfg(feature = "copy")]
+fn clone_file(_source: &Path, _destination: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
@@ -0,0 +1,1855 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use tempfile::TempDir;
+use winlock::{
+    backend::{ArgsTemplate, BackendUnavailable, Restart},
+    branch::BranchName,
+    config::Profile,
+    history::HistoryEvent,
+    multiplexer::MultiplexerKind,
+    session::{Divergence, Sessions, WorkspaceMarker},
+    test_util::FakeStore,
+    toolchain::Toolchain,
+    vcs::{lfs::Lfs, ConflictOperation, ConflictReport, ConflictedFile, VcsKind},
+    workspace::{self, CopyTooLarge, Fingerprint, ANNAIGNORE},
+    Agent, AgentSessionStatus, CreateOptions, LinkedProject, RunOptions, UnsuitableProject,
+};
+
+use crate::{git, git_project, with_env};
+
+/// Create an agent for the `feature` branch, recorded in a throwaway session store
+/// which lives, along with the agent's workspace, as long as the returned store.
+pub fn create_agent(project: &Path, vcs: VcsKind) -> (FakeStore, Agent) {
+    let store = FakeStore::new().expect("open store");
+    let options = CreateOptions {
+        vcs,
+        ..CreateOptions::default()
+    };
+    let agent = Agent::new(
+        &store,
+        project,
+        "feature".parse().expect("branch"),
+        &options,
+    )
+    .expect("create agent");
+    (store, agent)
+}
+
+#[test]
+fn creates_workspace_on_branch() {
+    let project = git_project();
+    let (_store, agent) = create_agent(project.path(), VcsKind::Git);
+
+    let workspace = agent.workspace();
+    assert_ne!(workspace, project.path());
+    assert_eq!(git(workspace, &["branch", "--show-current"]), "feature");
+    assert!(workspace.join("README.md").exists());
+    assert!(
+        !workspace.join("target").exists(),
+        "ignored files are not copied"
+    );
+    let marker = WorkspaceMarker::read(workspace)
+        .expect("read marker")
+        .expect("workspace is marked");
+    assert_eq!(marker, agent.session().marker());
+    assert_eq!(
+        git(workspace, &["status", "--porcelain"]),
+        "",
+        "the marker is ignored"
+    );
+
+    let report = agent.creation().expect("created");
+    assert!(report.copy.files > 0);
+    assert!(report.copy.warnings.is_empty());
+
+    fs::remove_dir_all(workspace).expect("remove workspace");
+}
+
+#[test]
+fn runs_templated_backend() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let profile = toml::from_str::<Profile>(
+        r#"command = "sh -c 'printf %s:%s {branch} \"$(cat {prompt_file})\" > out.txt'""#,
+    )
+    .expect("parse profile");
+
+    let options = RunOptions {
+        prompt: Some(String::from("do the thing")),
+        ..RunOptions::default()
+    };
+    let outcome = agent.run(&profile, &options).expect("run backend");
+    assert!(outcome.status.success());
+    assert_eq!(outcome.auto_commit, None);
+
+    let out = fs::read_to_string(agent.workspace().join("out.txt")).expect("read output");
+    assert_eq!(out, "feature:do the thing");
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn records_every_prompt() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let profile = toml::from_str::<Profile>(r#"command = "true""#).expect("parse profile");
+    for prompt in [Some("start"), None, Some("keep going")] {
+        let options = RunOptions {
+            prompt: prompt.map(String::from),
+            ..RunOptions::default()
+        };
+        agent.run(&profile, &options).expect("run backend");
+    }
+
+    let session = agent.session();
+    let prompts = session.prompt_history();
+    let prompts = prompts
+        .iter()
+        .map(|record| record.prompt.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(prompts, ["start", "keep going"]);
+    assert_eq!(session.prompt.as_deref(), Some("start"));
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn leaves_project_untouched() {
+    let project = git_project();
+    let before = Fingerprint::take(project.path()).expect("fingerprint");
+
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let profile = toml::from_str::<Profile>(r#"command = "sh -c 'echo change >> README.md'""#)
+        .expect("parse profile");
+    let options = RunOptions {
+        prompt: Some(String::from("change the readme")),
+        auto_commit: true,
+        verify: Some(String::from("git status")),
+        ..RunOptions::default()
+    };
+    agent.run(&profile, &options).expect("run backend");
+    agent.commits_behind().expect("count commits behind");
+
+    let after = Fingerprint::take(project.path()).expect("fingerprint");
+    assert_eq!(before.changes(&after), Vec::<PathBuf>::new());
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn restarts_failed_backend() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    // Fails the first two times it runs.
+    let profile = toml::from_str::<Profile>(
+        r#"command = "sh -c 'echo run >> runs.txt; test $(wc -l < runs.txt) -gt 2'""#,
+    )
+    .expect("parse profile");
+
+    for (max, restarts, success) in [(Some(1), 1, false), (None, 2, true)] {
+        fs::remove_file(agent.workspace().join("runs.txt")).ok();
+        let options = RunOptions {
+            restart: Restart::OnFailure {
+                max,
+                backoff: Duration::from_millis(1),
+            },
+            ..RunOptions::default()
+        };
+        let outcome = agent.run(&profile, &options).expect("run backend");
+        assert_eq!(outcome.restarts, restarts);
+        assert_eq!(outcome.status.success(), success);
+    }
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn auto_commits_leftover_changes() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let profile = toml::from_str::<Profile>(r#"command = "sh -c 'echo change > new.txt'""#)
+        .expect("parse profile");
+    let options = RunOptions {
+        prompt: Some(String::from("add a file")),
+        auto_commit: true,
+        ..RunOptions::default()
+    };
+
+    let outcome = agent.run(&profile, &options).expect("run backend");
+    let commit = outcome.auto_commit.expect("changes are committed");
+
+    let workspace = agent.workspace();
+    assert_eq!(git(workspace, &["rev-parse", "HEAD"]), commit);
+    assert_eq!(git(workspace, &["status", "--porcelain"]), "");
+    assert!(git(workspace, &["log", "-1", "--format=%B"]).contains("add a file"));
+
+    fs::remove_dir_all(workspace).expect("remove workspace");
+}
+
+#[test]
+fn auto_commit_skips_clean_workspace() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let profile = toml::from_str::<Profile>(r#"command = "true""#).expect("parse profile");
+    let options = RunOptions {
+        auto_commit: true,
+        ..RunOptions::default()
+    };
+
+    let outcome = agent.run(&profile, &options).expect("run backend");
+    assert_eq!(outcome.auto_commit, None);
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn refuses_plain_directory_as_git() {
+    let project = TempDir::new().expect("create project dir");
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let options = CreateOptions::default();
+    let err = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &options,
+    )
+    .expect_err("plain directories are not git repositories");
+    assert!(format!("{err:#}").contains("not a git repository"));
+}
+
+#[test]
+fn probes_backend_before_copying() {
+    let project = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let options = CreateOptions {
+        backend: Some(String::from("anna-test-missing-backend")),
+        ..CreateOptions::default()
+    };
+    let err = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &options,
+    )
+    .expect_err("missing backend");
+    let unavailable = err
+        .downcast_ref::<BackendUnavailable>()
+        .expect("backend is unavailable");
+    assert_eq!(unavailable.program, "anna-test-missing-backend");
+    assert!(sessions.list(project.path()).expect("list").is_empty());
+}
+
+#[test]
+fn applies_git_config_to_workspace() {
+    let project = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let options = CreateOptions {
+        git_config: [
+            ("user.name", "Anna Winlock"),
+            ("user.email", "anna@example.com"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (String::from(key), String::from(value)))
+        .collect(),
+        ..CreateOptions::default()
+    };
+    let mut agent = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &options,
+    )
+    .expect("create agent");
+
+    let profile = toml::from_str::<Profile>(r#"command = "sh -c 'echo change > new.txt'""#)
+        .expect("parse profile");
+    let options = RunOptions {
+        auto_commit: true,
+        ..RunOptions::default()
+    };
+    agent.run(&profile, &options).expect("run backend");
+    let author = git(agent.workspace(), &["log", "-1", "--format=%an <%ae>"]);
+    assert_eq!(author, "Anna Winlock <anna@example.com>");
+    // The project's own configuration is left alone.
+    assert_eq!(git(project.path(), &["config", "user.name"]), "Test");
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn deletes_linked_workspaces_when_linking_fails() {
+    let project = git_project();
+    let linked = git_project();
+    // Not a repository, so creating the branch in it fails.
+    let broken = TempDir::new().expect("create project dir");
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let options = CreateOptions {
+        also: vec![
+            LinkedProject::new(linked.path()),
+            LinkedProject {
+                vcs: VcsKind::Git,
+                ..LinkedProject::new(broken.path())
+            },
+        ],
+        ..CreateOptions::default()
+    };
+    let created = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &options,
+    );
+    assert!(created.is_err());
+
+    let workspaces = fs::read_dir(workspace::root())
+        .expect("list workspaces")
+        .map(|entry| entry.expect("read entry").file_name())
+        .collect::<Vec<_>>();
+    for dir in [&project, &linked, &broken] {
+        let name = dir.path().file_name().expect("name").to_string_lossy();
+        let name = name.trim_start_matches('.');
+        assert!(
+            !workspaces
+                .iter()
+                .any(|workspace| workspace.to_string_lossy().starts_with(name)),
+            "a workspace of {} is left",
+            dir.path().display()
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn links_other_projects() {
+    let project = git_project();
+    let other = git_project();
+    // Withheld by the linked project's own settings, which the session's don't share.
+    fs::write(other.path().join("notes.txt"), "private").expect("write notes");
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let options = CreateOptions {
+        also: vec![LinkedProject {
+            exclude: vec![String::from("notes.txt")],
+            ..LinkedProject::new(other.path())
+        }],
+        ..CreateOptions::default()
+    };
+    let mut agent = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &options,
+    )
+    .expect("create agent");
+    let linked = agent.session().linked[0].clone();
+    assert_eq!(
+        linked.project,
+        fs::canonicalize(other.path()).expect("canonicalize")
+    );
+    assert_eq!(
+        git(&linked.workspace, &["branch", "--show-current"]),
+        "feature"
+    );
+    assert_eq!(agent.creation().expect("created").linked.len(), 1);
+    assert!(!linked.workspace.join("notes.txt").exists());
+    assert_eq!(
+        WorkspaceMarker::read(&linked.workspace).expect("read marker"),
+        Some(agent.session().marker())
+    );
+
+    let profile = toml::from_str::<Profile>(
+        r#"command = "sh -c 'echo change > \"$ANNA_LINKED_WORKSPACES/linked.txt\"'""#,
+    )
+    .expect("parse profile");
+    let options = RunOptions {
+        auto_commit: true,
+        ..RunOptions::default()
+    };
+    let outcome = agent.run(&profile, &options).expect("run backend");
+    assert_eq!(outcome.auto_commit, None);
+    let commit = &outcome.linked_auto_commits[&linked.project];
+    assert_eq!(git(&linked.workspace, &["rev-parse", "HEAD"]), *commit);
+    let diff = agent.diff().expect("diff");
+    let heading = format!("# Linked project {}", linked.project.display());
+    assert!(diff.contains(&heading), "{diff}");
+    assert!(diff.contains("linked.txt"), "{diff}");
+
+    let containing = sessions
+        .containing(&linked.workspace)
+        .expect("find session")
+        .expect("linked workspace belongs to the session");
+    assert_eq!(containing.branch, *agent.branch());
+
+    sessions
+        .remove(agent.project(), agent.branch())
+        .expect("remove session");
+    assert!(!linked.workspace.exists());
+    assert!(!agent.workspace().exists());
+}
+
+#[test]
+fn supports_plain_directories() {
+    let project = TempDir::new().expect("create project dir");
+    fs::write(project.path().join("notes.txt"), "hello").expect("write file");
+    assert_eq!(VcsKind::detect(project.path()), VcsKind::None);
+
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::None);
+    let workspace = agent.workspace().to_path_buf();
+    assert_eq!(agent.vcs(), VcsKind::None);
+    assert!(workspace.join("notes.txt").exists());
+    assert!(!workspace.join(".git").exists());
+
+    let profile = toml::from_str::<Profile>(r#"command = "sh -c 'echo change > new.txt'""#)
+        .expect("parse profile");
+    let options = RunOptions {
+        auto_commit: true,
+        ..RunOptions::default()
+    };
+    let outcome = agent.run(&profile, &options).expect("run backend");
+    assert_eq!(outcome.auto_commit, None);
+
+    fs::remove_dir_all(&workspace).expect("remove workspace");
+}
+
+#[test]
+fn diffs_against_base() {
+    let project = git_project();
+    let (_store, agent) = create_agent(project.path(), VcsKind::Git);
+    let workspace = agent.workspace();
+    assert_eq!(
+        agent.base(),
+        Some(git(project.path(), &["rev-parse", "HEAD"]).as_str())
+    );
+
+    fs::write(workspace.join("README.md"), "goodbye\n").expect("modify readme");
+    fs::write(workspace.join("new.txt"), "new\n").expect("write new file");
+
+    let diff = agent.diff().expect("diff workspace");
+    assert!(diff.contains("+goodbye"), "{diff}");
+    assert!(diff.contains("+++ b/new.txt"), "{diff}");
+    assert_eq!(
+        git(workspace, &["status", "--porcelain"]),
+        "M README.md\n?? new.txt",
+        "diffing leaves the index alone"
+    );
+
+    fs::remove_dir_all(workspace).expect("remove workspace");
+}
+
+#[test]
+fn merges_back_into_project() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let profile = toml::from_str::<Profile>(r#"command = "sh -c 'echo change > new.txt'""#)
+        .expect("parse profile");
+    let options = RunOptions {
+        auto_commit: true,
+        ..RunOptions::default()
+    };
+    let commit = agent
+        .run(&profile, &options)
+        .expect("run backend")
+        .auto_commit
+        .expect("changes are committed");
+
+    agent.merge_back().expect("merge back");
+    assert_eq!(git(project.path(), &["rev-parse", "feature"]), commit);
+    assert_eq!(git(project.path(), &["branch", "--show-current"]), "main");
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn merges_and_removes_sessions() {
+    let project = git_project();
+    let (store, agent) = create_agent(project.path(), VcsKind::Git);
+    let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+    let branch = agent.branch().clone();
+    fs::write(agent.workspace().join("README.md"), "agent\n").expect("write agent work");
+    git(agent.workspace(), &["commit", "-am", "agent work"]);
+    let commit = git(agent.workspace(), &["rev-parse", "HEAD"]);
+
+    let (session, removal) = sessions
+        .merge_and_remove(project.path(), &branch, |_| Ok(()))
+        .expect("merge and remove")
+        .expect("session exists");
+    removal.finish().expect("delete workspace");
+    assert_eq!(git(project.path(), &["rev-parse", "feature"]), commit);
+    assert!(!session.workspace.exists());
+    assert_eq!(
+        sessions.get(project.path(), &branch).expect("get session"),
+        None
+    );
+}
+
+#[test]
+fn records_session_history() {
+    let project = git_project();
+    let (store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+    let branch = agent.branch().clone();
+    let first = agent.session().id;
+    let profile =
+        toml::from_str::<Profile>(r#"command = "sh -c 'exit 3'""#).expect("parse profile");
+    agent
+        .run(&profile, &RunOptions::default())
+        .expect("run backend");
+    agent.verify("true").expect("verify");
+    let options = CreateOptions::default();
+    Agent::new(&sessions, project.path(), branch.clone(), &options).expect("resume agent");
+    let (_, removal) = sessions
+        .merge_and_remove(project.path(), &branch, |_| Ok(()))
+        .expect("merge and remove")
+        .expect("session exists");
+    removal.finish().expect("delete workspace");
+    // Otherwise the merged branch would be copied into the next session's workspace.
+    git(project.path(), &["branch", "--delete", "feature"]);
+    let options = CreateOptions {
+        vcs: VcsKind::Git,
+        ..options
+    };
+    let agent = Agent::new(&sessions, project.path(), branch.clone(), &options)
+        .expect("create agent again");
+    let second = agent.session().id;
+
+    let history = sessions.history(project.path(), &branch).expect("history");
+    let events = history
+        .iter()
+        .map(|entry| (entry.session, entry.event.name()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        [
+            (first, "created"),
+            (first, "backend_exited"),
+            (first, "verified"),
+            (first, "resumed"),
+            (first, "merged"),
+            (first, "removed"),
+            (second, "created"),
+        ]
+    );
+    assert!(matches!(
+        history[1].event,
+        HistoryEvent::BackendExited {
+            code: Some(3),
+            success: false,
+            ..
+        }
+    ));
+    assert!(history.windows(2).all(|pair| pair[0].at <= pair[1].at));
+}
+
+#[test]
+fn counts_commits_ahead_and_behind() {
+    let project = git_project();
+    let (_store, agent) = create_agent(project.path(), VcsKind::Git);
+    for content in ["one\n", "two\n"] {
+        fs::write(agent.workspace().join("README.md"), content).expect("write agent work");
+        git(agent.workspace(), &["commit", "-am", content.trim()]);
+    }
+    fs::write(project.path().join("other.txt"), "project\n").expect("write project work");
+    git(project.path(), &["add", "other.txt"]);
+    git(project.path(), &["commit", "-m", "project work"]);
+
+    let divergence = |agent: &Agent| {
+        agent
+            .session()
+            .divergence()
+            .expect("count commits")
+            .expect("under version control")
+    };
+    assert_eq!(
+        divergence(&agent),
+        Divergence {
+            ahead: 2,
+            behind: 1
+        }
+    );
+    assert_eq!(divergence(&agent).to_string(), "+2/-1");
+
+    // Once the project has the commits, they're compared there.
+    agent.merge_back().expect("merge back");
+    assert_eq!(divergence(&agent).ahead, 2);
+    git(
+        project.path(),
+        &["merge", "--quiet", "--no-edit", "feature"],
+    );
+    assert_eq!(divergence(&agent).ahead, 0, "landed work isn't counted");
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn refuses_copies_over_the_limit() {
+    let project = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let options = CreateOptions {
+        vcs: VcsKind::Git,
+        copy_limit: Some(1),
+        ..CreateOptions::default()
+    };
+    let branch = "feature".parse::<BranchName>().expect("branch");
+
+    let err = Agent::new(&sessions, project.path(), branch.clone(), &options)
+        .expect_err("copy is too large");
+    let too_large = err
+        .downcast_ref::<CopyTooLarge>()
+        .expect("refused for its size");
+    assert!(too_large.estimate.files > 0);
+    assert_eq!(too_large.limit, 1);
+    assert_eq!(
+        sessions.get(project.path(), &branch).expect("get session"),
+        None
+    );
+}
+
+#[test]
+fn keeps_sessions_that_fail_to_merge() {
+    let project = git_project();
+    let (store, agent) = create_agent(project.path(), VcsKind::Git);
+    let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+    let branch = agent.branch().clone();
+    fs::write(agent.workspace().join("README.md"), "agent\n").expect("write agent work");
+    git(agent.workspace(), &["commit", "-am", "agent work"]);
+    git(project.path(), &["checkout", "--quiet", "-b", "feature"]);
+    fs::write(project.path().join("README.md"), "project\n").expect("write project work");
+    git(project.path(), &["commit", "-am", "project work"]);
+    git(project.path(), &["checkout", "--quiet", "main"]);
+
+    let err = sessions
+        .merge_and_remove(project.path(), &branch, |_| Ok(()))
+        .expect_err("merge conflicts");
+    assert!(err.downcast_ref::<ConflictReport>().is_some());
+    assert!(agent.workspace().exists());
+    assert!(sessions
+        .get(project.path(), &branch)
+        .expect("get session")
+        .is_some());
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn fetches_into_project_reference() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let profile = toml::from_str::<Profile>(r#"command = "sh -c 'echo change >> new.txt'""#)
+        .expect("parse profile");
+    let options = RunOptions {
+        auto_commit: true,
+        ..RunOptions::default()
+    };
+    let session = agent.session().clone();
+    assert_eq!(session.fetched_ref(), "refs/anna/feature");
+
+    // Fetched twice, the second time after the agent amends its commit,
+    // since the reference follows the branch even when it's rewritten.
+    for amend in [false, true] {
+        agent.run(&profile, &options).expect("run backend");
+        if amend {
+            git(agent.workspace(), &["commit", "--amend", "-m", "amended"]);
+        }
+        session.fetch_into_project().expect("fetch");
+        assert_eq!(
+            git(project.path(), &["rev-parse", "refs/anna/feature"]),
+            git(agent.workspace(), &["rev-parse", "HEAD"])
+        );
+    }
+    assert_eq!(git(project.path(), &["branch", "--list", "feature"]), "");
+    assert_eq!(git(project.path(), &["status", "--porcelain"]), "");
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn resumes_existing_session() {
+    let project = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let branch = "feature".parse().expect("branch");
+    let options = CreateOptions::default();
+
+    let created = Agent::new(&sessions, project.path(), branch, &options).expect("create agent");
+    assert_eq!(created.status(), AgentSessionStatus::Created);
+    fs::write(created.workspace().join("progress.txt"), "wip").expect("write progress");
+
+    let branch = "feature".parse().expect("branch");
+    let resumed = Agent::new(&sessions, project.path(), branch, &options).expect("resume agent");
+    assert_eq!(resumed.status(), AgentSessionStatus::Resumed);
+    assert_eq!(resumed.creation(), None);
+    assert_eq!(resumed.session(), created.session());
+    assert!(resumed.workspace().join("progress.txt").exists());
+
+    sessions
+        .remove(resumed.project(), resumed.branch())
+        .expect("remove session");
+    assert!(!resumed.workspace().exists());
+}
+
+#[test]
+fn opens_existing_sessions_without_resuming_them() {
+    let project = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let branch = "feature".parse::<BranchName>().expect("branch");
+    assert!(Agent::open(&sessions, project.path(), &branch)
+        .expect("open missing session")
+        .is_none());
+    assert!(sessions
+        .get(project.path(), &branch)
+        .expect("get")
+        .is_none());
+
+    let created = Agent::new(
+        &sessions,
+        project.path(),
+        branch.clone(),
+        &CreateOptions::default(),
+    )
+    .expect("create agent");
+    let opened = Agent::open(&sessions, project.path(), &branch)
+        .expect("open session")
+        .expect("session exists");
+    assert_eq!(opened.session(), created.session());
+    let history = sessions.history(project.path(), &branch).expect("history");
+    assert!(
+        history.iter().all(|entry| entry.event.name() != "resumed"),
+        "{history:?}"
+    );
+
+    sessions
+        .remove(opened.project(), opened.branch())
+        .expect("remove session");
+}
+
+// Relative paths to temporary directories go through the root, which is only `/` here.
+#[cfg(unix)]
+#[test]
+fn opens_sessions_by_relative_paths() {
+    let project = git_project();
+    let (store, created) = create_agent(project.path(), VcsKind::Git);
+    let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+    let cwd = std::env::current_dir().expect("current dir");
+    let relative = cwd
+        .components()
+        .skip(1)
+        .map(|_| Path::new(".."))
+        .collect::<PathBuf>()
+        .join(project.path().strip_prefix("/").expect("absolute project"));
+
+    let opened = Agent::open(&sessions, &relative, created.branch())
+        .expect("open session")
+        .expect("session exists");
+    assert_eq!(opened.session(), created.session());
+
+    fs::remove_dir_all(created.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn gives_linked_worktrees_their_own_repository() {
+    let project = git_project();
+    let worktrees = TempDir::new().expect("create worktree dir");
+    let worktree = worktrees.path().join("wip");
+    git(
+        project.path(),
+        &["worktree", "add", "-b", "wip", &worktree.to_string_lossy()],
+    );
+    fs::write(worktree.join("README.md"), "changed\n").expect("change readme");
+    git(&worktree, &["add", "README.md"]);
+    let (_store, mut agent) = create_agent(&worktree, VcsKind::Git);
+    let workspace = agent.workspace().to_path_buf();
+
+    assert!(workspace.join(".git").is_dir());
+    assert_eq!(
+        agent.base(),
+        Some(git(&worktree, &["rev-parse", "HEAD"]).as_str())
+    );
+    assert_eq!(
+        git(&workspace, &["diff", "--cached", "--name-only"]),
+        "README.md"
+    );
+    fs::write(workspace.join("new.txt"), "new").expect("write file");
+    let profile = toml::from_str::<Profile>(r#"command = "true""#).expect("parse profile");
+    let options = RunOptions {
+        auto_commit: true,
+        ..RunOptions::default()
+    };
+    agent.run(&profile, &options).expect("run backend");
+
+    assert_eq!(git(project.path(), &["branch", "--list", "feature"]), "");
+    assert_eq!(git(&worktree, &["status", "--porcelain"]), "M  README.md");
+    agent.merge_back().expect("merge back");
+    assert_eq!(
+        git(project.path(), &["show", "feature:new.txt"]),
+        "new",
+        "merged into the worktree's repository"
+    );
+
+    fs::remove_dir_all(workspace).expect("remove workspace");
+}
+
+#[test]
+fn hides_tracked_files_left_out_of_workspaces() {
+    let project = git_project();
+    fs::write(project.path().join(ANNAIGNORE), "/fixtures/\n").expect("write .annaignore");
+    fs::create_dir(project.path().join("fixtures")).expect("create fixtures");
+    fs::write(project.path().join("fixtures/large.json"), "{}").expect("write fixture");
+    fs::write(project.path().join("gone.txt"), "gone").expect("write file");
+    git(project.path(), &["add", "."]);
+    git(project.path(), &["commit", "-m", "fixtures"]);
+    // Deleted in the project, so that's a change the workspace should show too.
+    fs::remove_file(project.path().join("gone.txt")).expect("delete file");
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let workspace = agent.workspace().to_path_buf();
+
+    assert!(!workspace.join("fixtures").exists());
+    assert_eq!(git(&workspace, &["status", "--porcelain"]), "D gone.txt");
+    fs::write(workspace.join("new.txt"), "new").expect("write file");
+    let profile = toml::from_str::<Profile>(r#"command = "true""#).expect("parse profile");
+    let options = RunOptions {
+        auto_commit: true,
+        ..RunOptions::default()
+    };
+    agent.run(&profile, &options).expect("run backend");
+    assert_eq!(
+        git(&workspace, &["show", "--name-status", "--format=", "HEAD"]),
+        "D\tgone.txt\nA\tnew.txt",
+        "the left out fixtures aren't committed as deleted"
+    );
+
+    fs::remove_dir_all(workspace).expect("remove workspace");
+}
+
+#[test]
+fn hides_tracked_files_too_large_to_copy() {
+    let project = git_project();
+    fs::write(project.path().join("large.bin"), [0; 2048]).expect("write large file");
+    git(project.path(), &["add", "large.bin"]);
+    git(project.path(), &["commit", "-m", "add large file"]);
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let options = CreateOptions {
+        vcs: VcsKind::Git,
+        max_file_size: Some(1024),
+        ..CreateOptions::default()
+    };
+    let agent = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &options,
+    )
+    .expect("create agent");
+
+    assert!(!agent.workspace().join("large.bin").exists());
+    assert_eq!(git(agent.workspace(), &["status", "--porcelain"]), "");
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn hides_withheld_tracked_secrets() {
+    let project = git_project();
+    fs::write(project.path().join(".env"), "TOKEN=secret\n").expect("write .env");
+    git(project.path(), &["add", "--force", ".env"]);
+    git(project.path(), &["commit", "-m", "commit a secret"]);
+    let (_store, agent) = create_agent(project.path(), VcsKind::Git);
+    let workspace = agent.workspace().to_path_buf();
+
+    assert!(!workspace.join(".env").exists());
+    assert_eq!(git(&workspace, &["status", "--porcelain"]), "");
+    let creation = agent.creation().expect("created");
+    assert_eq!(creation.copy.withheld, [PathBuf::from(".env")]);
+    assert!(creation.copy.deleted.is_empty(), "git hides them");
+
+    fs::remove_dir_all(workspace).expect("remove workspace");
+}
+
+#[test]
+fn diffs_leave_out_withheld_tracked_secrets() {
+    let project = git_project();
+    fs::write(project.path().join(".env"), "TOKEN=secret\n").expect("write .env");
+    git(project.path(), &["add", "--force", ".env"]);
+    git(project.path(), &["commit", "-m", "commit a secret"]);
+    let (_store, agent) = create_agent(project.path(), VcsKind::Git);
+    let workspace = agent.workspace().to_path_buf();
+    fs::write(workspace.join("new.txt"), "new\n").expect("write file");
+
+    let diff = agent.diff().expect("diff workspace");
+    assert!(diff.contains("new.txt"), "{diff}");
+    assert!(!diff.contains(".env"), "{diff}");
+    assert!(!diff.contains("secret"), "{diff}");
+
+    fs::remove_dir_all(workspace).expect("remove workspace");
+}
+
+#[test]
+fn refuses_workspaces_and_store_as_projects() {
+    let project = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let options = CreateOptions::default();
+    let agent = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &options,
+    )
+    .expect("create agent");
+
+    let nested = agent.workspace().join("nested");
+    fs::create_dir(&nested).expect("create nested dir");
+    for dir in [agent.workspace(), nested.as_path()] {
+        let err = Agent::new(&sessions, dir, "inner".parse().expect("branch"), &options)
+            .expect_err("workspace is not a project");
+        let err = err
+            .downcast_ref::<UnsuitableProject>()
+            .expect("unsuitable project");
+        assert!(
+            matches!(err, UnsuitableProject::Workspace { branch, .. } if branch.as_str() == "feature"),
+            "{err:?}"
+        );
+    }
+
+    let err = Agent::new(
+        &sessions,
+        store.path(),
+        "inner".parse().expect("branch"),
+        &CreateOptions {
+            vcs: VcsKind::None,
+            ..CreateOptions::default()
+        },
+    )
+    .expect_err("store is not a project");
+    assert!(matches!(
+        err.downcast_ref::<UnsuitableProject>(),
+        Some(UnsuitableProject::Store { .. })
+    ));
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn rebases_onto_project() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    fs::write(agent.workspace().join("agent.txt"), "work").expect("write agent work");
+    git(agent.workspace(), &["add", "."]);
+    git(agent.workspace(), &["commit", "-m", "agent work"]);
+    fs::write(agent.workspace().join("wip.txt"), "uncommitted").expect("write wip");
+    assert_eq!(agent.commits_behind().expect("count commits"), Some(0));
+
+    fs::write(project.path().join("upstream.txt"), "new").expect("write upstream");
+    git(project.path(), &["add", "."]);
+    git(project.path(), &["commit", "-m", "upstream"]);
+    assert_eq!(agent.commits_behind().expect("count commits"), Some(1));
+
+    let base = agent.rebase().expect("rebase");
+    assert_eq!(base, git(project.path(), &["rev-parse", "HEAD"]));
+    assert_eq!(agent.base(), Some(base.as_str()));
+    assert_eq!(agent.commits_behind().expect("count commits"), Some(0));
+    assert_eq!(git(agent.workspace(), &["rev-parse", "HEAD^"]), base);
+    assert!(agent.workspace().join("upstream.txt").exists());
+    assert!(agent.workspace().join("wip.txt").exists());
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn reports_rebase_conflicts() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    fs::write(agent.workspace().join("README.md"), "agent\n").expect("write agent work");
+    git(agent.workspace(), &["commit", "-am", "agent work"]);
+    let head = git(agent.workspace(), &["rev-parse", "HEAD"]);
+
+    fs::write(project.path().join("README.md"), "upstream\n").expect("write upstream");
+    git(project.path(), &["commit", "-am", "upstream"]);
+
+    let err = agent.rebase().expect_err("rebase conflicts");
+    let report = err
+        .downcast_ref::<ConflictReport>()
+        .expect("conflicts are reported");
+    assert_eq!(report.operation, ConflictOperation::Rebase);
+    assert_eq!(report.onto, git(project.path(), &["rev-parse", "HEAD"]));
+    assert_eq!(
+        report.commit.as_ref().map(|commit| commit.subject.as_str()),
+        Some("agent work")
+    );
+    assert_eq!(
+        report.files,
+        [ConflictedFile {
+            path: PathBuf::from("README.md"),
+            hunks: 1,
+        }]
+    );
+    assert_eq!(git(agent.workspace(), &["rev-parse", "HEAD"]), head);
+    assert_eq!(git(agent.workspace(), &["status", "--porcelain"]), "");
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn reports_merge_conflicts() {
+    let project = git_project();
+    let (_store, agent) = create_agent(project.path(), VcsKind::Git);
+    fs::write(agent.workspace().join("README.md"), "agent\n").expect("write agent work");
+    git(agent.workspace(), &["commit", "-am", "agent work"]);
+
+    git(project.path(), &["checkout", "--quiet", "-b", "feature"]);
+    fs::write(project.path().join("README.md"), "project\n").expect("write project work");
+    git(project.path(), &["commit", "-am", "project work"]);
+    git(project.path(), &["checkout", "--quiet", "main"]);
+    let project_branch = git(project.path(), &["rev-parse", "feature"]);
+
+    let err = agent.merge_back().expect_err("merge conflicts");
+    let report = err
+        .downcast_ref::<ConflictReport>()
+        .expect("conflicts are reported");
+    assert_eq!(report.operation, ConflictOperation::Merge);
+    assert_eq!(report.onto, project_branch);
+    assert_eq!(report.commit, None);
+    assert_eq!(
+        report.files,
+        [ConflictedFile {
+            path: PathBuf::from("README.md"),
+            hunks: 1,
+        }]
+    );
+    assert_eq!(
+        git(project.path(), &["rev-parse", "feature"]),
+        project_branch
+    );
+    assert_eq!(
+        git(project.path(), &["for-each-ref", "refs/anna"]),
+        "",
+        "what was fetched to check for conflicts isn't kept"
+    );
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[cfg(all(unix, feature = "archive"))]
+#[test]
+fn unarchives_on_resume() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let project = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let branch = "feature".parse::<BranchName>().expect("branch");
+    let options = CreateOptions::default();
+
+    let created =
+        Agent::new(&sessions, project.path(), branch.clone(), &options).expect("create agent");
+    let workspace = created.workspace().to_path_buf();
+    let script = workspace.join("run.sh");
+    fs::write(&script, "#!/bin/sh\n").expect("write script");
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).expect("set permissions");
+    fs::create_dir(workspace.join("target")).expect("create target");
+    fs::write(workspace.join("target/artifact"), "built").expect("write artifact");
+
+    let archive = sessions
+        .archive(project.path(), &branch)
+        .expect("archive")
+        .expect("session exists");
+    assert!(archive.is_file());
+    assert!(!workspace.exists());
+    let archived = sessions
+        .get(created.project(), &branch)
+        .expect("get session")
+        .expect("session exists");
+    assert!(archived.archived);
+    assert!(sessions.archive(project.path(), &branch).is_err());
+
+    let resumed = Agent::new(&sessions, project.path(), branch, &options).expect("resume agent");
+    assert_eq!(resumed.status(), AgentSessionStatus::Resumed);
+    assert!(!resumed.session().archived);
+    assert!(!archive.exists());
+    assert_eq!(git(&workspace, &["branch", "--show-current"]), "feature");
+    let mode = fs::metadata(&script).expect("stat").permissions().mode();
+    assert_eq!(mode & 0o777, 0o755);
+    assert!(workspace.join("target/artifact").exists());
+
+    fs::remove_dir_all(&workspace).expect("remove workspace");
+}
+
+#[test]
+fn branches_from_requested_base() {
+    let project = git_project();
+    let initial = git(project.path(), &["rev-parse", "HEAD"]);
+    git(project.path(), &["switch", "--create", "other"]);
+    fs::write(project.path().join("other.txt"), "other").expect("write file");
+    git(project.path(), &["add", "."]);
+    git(project.path(), &["commit", "-m", "other"]);
+
+    let default = VcsKind::Git
+        .vcs()
+        .default_branch(project.path())
+        .expect("default branch");
+    assert_eq!(default, "main");
+
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let options = CreateOptions {
+        base: Some(default),
+        ..CreateOptions::default()
+    };
+    let agent = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &options,
+    )
+    .expect("create agent");
+
+    assert_eq!(agent.base(), Some(initial.as_str()));
+    assert_eq!(git(agent.workspace(), &["rev-parse", "HEAD"]), initial);
+    assert!(!agent.workspace().join("other.txt").exists());
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn resumes_backend_conversation() {
+    let project = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let branch = "feature".parse::<BranchName>().expect("branch");
+    let options = CreateOptions::default();
+    let profile = toml::from_str::<Profile>(
+        r#"
+        command = "sh -c 'echo start {resume_token} >> conversation.log' -- ignored"
+        resume = "sh -c 'echo resume {resume_token} >> conversation.log' -- ignored"
+        "#,
+    )
+    .expect("parse profile");
+    let log = |agent: &Agent| {
+        let log = agent.workspace().join("conversation.log");
+        let log = fs::read_to_string(log).expect("read log");
+        log.lines().map(String::from).collect::<Vec<_>>()
+    };
+
+    let mut agent =
+        Agent::new(&sessions, project.path(), branch.clone(), &options).expect("create agent");
+    agent
+        .run(&profile, &RunOptions::default())
+        .expect("run backend");
+    let token = agent.session().resume_tokens["sh"].clone();
+    assert_eq!(log(&agent), [format!("start {token}")]);
+
+    // A later invocation resumes the session from the store.
+    let mut agent =
+        Agent::new(&sessions, project.path(), branch.clone(), &options).expect("resume agent");
+    agent
+        .run(&profile, &RunOptions::default())
+        .expect("run backend");
+    assert_eq!(log(&agent)[1], format!("resume {token}"));
+
+    let fresh = RunOptions {
+        new_conversation: true,
+        ..RunOptions::default()
+    };
+    agent.run(&profile, &fresh).expect("run backend");
+    let new_token = agent.session().resume_tokens["sh"].clone();
+    assert_ne!(new_token, token);
+    assert_eq!(log(&agent)[2], format!("start {new_token}"));
+    let stored = sessions
+        .get(agent.project(), &branch)
+        .expect("get session")
+        .expect("session is stored");
+    assert_eq!(stored.resume_tokens["sh"], new_token);
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn records_verification() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let profile =
+        toml::from_str::<Profile>(r#"command = "sh -c 'touch built'""#).expect("parse profile");
+
+    // The verification sees what the backend left in the workspace.
+    let options = RunOptions {
+        verify: Some(String::from("test -f built && test -f README.md")),
+        ..RunOptions::default()
+    };
+    let outcome = agent.run(&profile, &options).expect("run backend");
+    let verification = outcome.verification.expect("verification ran");
+    assert!(verification.passed);
+    assert_eq!(agent.session().verification, Some(verification));
+
+    let failed = agent.verify("test -f missing").expect("verify");
+    assert!(!failed.passed);
+    assert_eq!(failed.command, "test -f missing");
+    assert_eq!(agent.session().verification, Some(failed));
+
+    let log = agent.data_dir().join("verify.log");
+    fs::create_dir_all(agent.data_dir()).expect("create data dir");
+    let logged = agent
+        .verify_logged("echo checked; echo complaint >&2", &log)
+        .expect("verify");
+    assert!(logged.passed);
+    assert_eq!(agent.session().verification, Some(logged));
+    let output = fs::read_to_string(&log).expect("read log");
+    assert!(output.contains("checked") && output.contains("complaint"));
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn runs_setup_commands() {
+    let project = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let options = CreateOptions {
+        vcs: VcsKind::Git,
+        setup: vec![String::from("git branch --show-current > setup.txt")],
+        ..CreateOptions::default()
+    };
+
+    let agent = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &options,
+    )
+    .expect("create agent");
+    let setup = fs::read_to_string(agent.workspace().join("setup.txt")).expect("read output");
+    assert_eq!(setup.trim(), "feature", "setup runs on the new branch");
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+
+    let failed = store.path().join("failed.txt");
+    let options = CreateOptions {
+        setup: vec![format!("pwd > '{}'; exit 1", failed.display())],
+        ..options
+    };
+    let branch = "failing".parse().expect("branch");
+    assert!(Agent::new(&sessions, project.path(), branch, &options).is_err());
+    assert_eq!(sessions.list(project.path()).expect("list").len(), 1);
+    let workspace = fs::read_to_string(&failed).expect("read output");
+    assert!(
+        !Path::new(workspace.trim()).exists(),
+        "the failed workspace is deleted"
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn runs_setup_commands_without_standard_input() {
+    let project = git_project();
+    let options = CreateOptions {
+        vcs: VcsKind::Git,
+        setup: vec![String::from("readlink /proc/self/fd/0 > stdin.txt")],
+        ..CreateOptions::default()
+    };
+    let store = FakeStore::new().expect("open store");
+    let branch = "feature".parse().expect("branch");
+    let agent = Agent::new(&store, project.path(), branch, &options).expect("create agent");
+
+    let stdin = fs::read_to_string(agent.workspace().join("stdin.txt")).expect("read output");
+    assert_eq!(stdin.trim(), "/dev/null");
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+/// Write an executable shell script, for backends which need to be a program of their own.
+#[cfg(unix)]
+pub fn script(dir: &Path, name: &str, content: &str) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join(name);
+    fs::write(&path, format!("#!/bin/sh\n{content}\n")).expect("write script");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).expect("make executable");
+    path
+}
+
+#[cfg(unix)]
+#[test]
+fn records_backend_version() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let bin = TempDir::new().expect("create bin dir");
+    let backend = script(
+        bin.path(),
+        "backend",
+        "if [ \"$1\" = --version ]; then echo 'backend 1.2.3'; fi",
+    );
+    let profile = Profile {
+        command: backend
+            .display()
+            .to_string()
+            .parse()
+            .expect("parse command"),
+        resume: None,
+        model: None,
+        model_flag: None,
+        hosts: Vec::new(),
+        usage: None,
+        run_args_created: ArgsTemplate::default(),
+        run_args_resumed: ArgsTemplate::default(),
+        install: None,
+        notify: None,
+        name_branch: None,
+        review: None,
+        env: BTreeMap::new(),
+    };
+
+    assert!(agent.session().environment.is_some());
+    agent
+        .run(&profile, &RunOptions::default())
+        .expect("run backend");
+    let environment = agent.session().environment.as_ref().expect("environment");
+    assert_eq!(
+        environment.backends[&backend.display().to_string()],
+        "backend 1.2.3"
+    );
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn records_each_run() {
+    let project = git_project();
+    let (store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    // A pipeline: one backend writes, the next reviews and fails.
+    for command in [
+        "sh -c 'echo draft > notes.txt'",
+        "sh -c 'grep -q final notes.txt'",
+    ] {
+        let profile = Profile::with_command(command.parse().expect("parse command"));
+        agent
+            .run(&profile, &RunOptions::default())
+            .expect("run backend");
+    }
+
+    let runs = &agent.session().runs;
+    assert_eq!(runs.len(), 2);
+    assert!(runs.iter().all(|run| run.backend == "sh"));
+    assert_eq!((runs[0].code, runs[0].success), (Some(0), true));
+    assert_eq!((runs[1].code, runs[1].success), (Some(1), false));
+    assert!(runs[0].started_at <= runs[1].started_at);
+
+    let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+    let stored = sessions
+        .get(agent.project(), agent.branch())
+        .expect("get session")
+        .expect("session exists");
+    assert_eq!(stored.runs, *runs);
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[cfg(unix)]
+#[test]
+fn remembers_selected_model() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let bin = TempDir::new().expect("create bin dir");
+    let backend = script(bin.path(), "backend", r#"echo "$@" > args.txt"#);
+    let profile = Profile {
+        command: format!("{} {{prompt}}", backend.display())
+            .parse()
+            .expect("parse command"),
+        resume: None,
+        model: Some(String::from("sonnet")),
+        model_flag: Some(String::from("--model")),
+        hosts: Vec::new(),
+        usage: None,
+        run_args_created: ArgsTemplate::default(),
+        run_args_resumed: ArgsTemplate::default(),
+        install: None,
+        notify: None,
+        name_branch: None,
+        review: None,
+        env: BTreeMap::new(),
+    };
+    let args =
+        |agent: &Agent| fs::read_to_string(agent.workspace().join("args.txt")).expect("read args");
+
+    let options = RunOptions {
+        prompt: Some(String::from("go")),
+        ..RunOptions::default()
+    };
+    agent.run(&profile, &options).expect("run backend");
+    assert_eq!(args(&agent), "--model sonnet go\n", "the profile's default");
+
+    let options = RunOptions {
+        model: Some(String::from("opus")),
+        ..RunOptions::default()
+    };
+    agent.run(&profile, &options).expect("run backend");
+    assert_eq!(args(&agent), "--model opus\n");
+    assert_eq!(agent.session().model.as_deref(), Some("opus"));
+
+    agent
+        .run(&profile, &RunOptions::default())
+        .expect("run backend");
+    assert_eq!(args(&agent), "--model opus\n", "the session's model");
+
+    let profile = Profile {
+        model_flag: None,
+        ..profile
+    };
+    assert!(
+        agent.run(&profile, &options).is_err(),
+        "can't select a model"
+    );
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[cfg(unix)]
+#[test]
+fn adds_arguments_for_created_or_resumed_sessions() {
+    let project = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let bin = TempDir::new().expect("create bin dir");
+    let backend = script(bin.path(), "backend", r#"echo "$@" > args.txt"#);
+    let profile = toml::from_str::<Profile>(&format!(
+        r#"
+        command = "{}"
+        run_args_created = ["--prompt", "{{prompt}}"]
+        run_args_resumed = ["--continue", "{{prompt}}"]
+        "#,
+        backend.display()
+    ))
+    .expect("parse profile");
+
+    // What counts is whether the conversation continues, not whether the session did.
+    for (prompt, new_conversation, expected) in [
+        ("start", false, "--prompt start"),
+        ("more", false, "--continue more"),
+        ("again", true, "--prompt again"),
+    ] {
+        let mut agent = Agent::new(
+            &sessions,
+            project.path(),
+            "feature".parse().expect("branch"),
+            &CreateOptions::default(),
+        )
+        .expect("create agent");
+        let options = RunOptions {
+            prompt: Some(String::from(prompt)),
+            new_conversation,
+            ..RunOptions::default()
+        };
+        agent.run(&profile, &options).expect("run backend");
+        let args = fs::read_to_string(agent.workspace().join("args.txt")).expect("read args");
+        assert_eq!(args.trim(), expected, "{:?}", agent.status());
+    }
+
+    sessions
+        .remove(project.path(), &"feature".parse().expect("branch"))
+        .expect("remove session");
+}
+
+#[test]
+fn finds_work_removal_would_lose() {
+    let project = git_project();
+    let (_store, agent) = create_agent(project.path(), VcsKind::Git);
+    let session = agent.session().clone();
+    let workspace = agent.workspace();
+    assert_eq!(session.unsaved_work().expect("check"), []);
+
+    // Copied from the project as they were, so they're still there.
+    fs::write(project.path().join("untracked.txt"), "new\n").expect("add file");
+    fs::write(workspace.join("untracked.txt"), "new\n").expect("add file");
+    fs::write(workspace.join("README.md"), "changed\n").expect("change file");
+    fs::create_dir(workspace.join("new")).expect("create dir");
+    fs::write(workspace.join("new/file.txt"), "new\n").expect("add file");
+    let unsaved = session.unsaved_work().expect("check");
+    let [(dir, work)] = unsaved.as_slice() else {
+        panic!("expected unsaved work in one workspace: {unsaved:?}");
+    };
+    assert_eq!(dir, workspace);
+    assert_eq!(
+        work.changed,
+        ["README.md", "new/file.txt"].map(PathBuf::from)
+    );
+    assert_eq!(work.commits, 0);
+    assert_eq!(work.to_string(), "2 uncommitted changes");
+
+    git(workspace, &["add", "--all"]);
+    git(workspace, &["commit", "-m", "work"]);
+    let unsaved = session.unsaved_work().expect("check");
+    assert_eq!(unsaved.len(), 1);
+    assert_eq!(unsaved[0].1.to_string(), "1 commit not in the project");
+
+    session.fetch_into_project().expect("fetch");
+    assert_eq!(session.unsaved_work().expect("check"), []);
+
+    fs::remove_dir_all(workspace).expect("remove workspace");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn records_resource_usage() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    // Busy for a while, then idle long enough to be sampled at least once.
+    let profile = toml::from_str::<Profile>(
+        r#"command = "sh -c 'i=0; while [ $i -lt 300000 ]; do i=$((i+1)); done; sleep 1.5'""#,
+    )
+    .expect("parse profile");
+
+    let outcome = agent
+        .run(&profile, &RunOptions::default())
+        .expect("run backend");
+    let resources = outcome.resources.expect("resources are measured on Linux");
+    assert!(resources.cpu_ms > 0, "{resources:?}");
+    assert!(resources.peak_rss > 0, "{resources:?}");
+    assert!(resources.duration_ms >= 1500, "{resources:?}");
+    assert_eq!(agent.session().resources, [resources]);
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_backend_in_tmux() {
+    // A server of its own, so that the test neither needs nor disturbs the user's.
+    let sockets = TempDir::new().expect("create tmux socket dir");
+    let vars = [
+        ("TMUX_TMPDIR", Some(sockets.path().as_os_str())),
+        ("TMUX", None),
+    ];
+    if !with_env("agent::runs_backend_in_tmux", &vars) {
+        return;
+    }
+
+    let project = git_project();
+    let (store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    // Runs until told to stop, so that it can be found while it does.
+    let profile = toml::from_str::<Profile>(
+        r#"command = "sh -c 'printf %s {branch} > out.txt; while [ ! -f stop ]; do sleep 0.1; done; exit 3'""#,
+    )
+    .expect("parse profile");
+    let options = RunOptions {
+        multiplexer: Some(MultiplexerKind::Tmux),
+        ..RunOptions::default()
+    };
+    let workspace = agent.workspace().to_path_buf();
+    let project_dir = project.path().to_path_buf();
+    let watcher = thread::spawn(move || {
+        let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+        let branch = "feature".parse().expect("branch");
+        let backend = loop {
+            match sessions
+                .running_backend(&project_dir, &branch)
+                .expect("check running backend")
+            {
+                Some(backend) if backend.window.is_some() => break backend,
+                _ => thread::sleep(Duration::from_millis(50)),
+            }
+        };
+        fs::write(workspace.join("stop"), "").expect("stop backend");
+        (store, backend)
+    });
+    let outcome = agent.run(&profile, &options).expect("run backend");
+    let (store, backend) = watcher.join().expect("watch backend");
+    assert_eq!(outcome.status.code(), Some(3));
+    assert_eq!(outcome.resources, None);
+    assert_eq!(backend.multiplexer, Some(MultiplexerKind::Tmux));
+    assert_eq!(backend.pid, Some(std::process::id()));
+    let out = fs::read_to_string(agent.workspace().join("out.txt")).expect("read output");
+    assert_eq!(out, "feature");
+    let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+    assert_eq!(
+        sessions
+            .running_backend(project.path(), agent.branch())
+            .expect("check running backend"),
+        None
+    );
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn checks_out_submodules_missing_from_the_project() {
+    let library = git_project();
+    let project = git_project();
+    let url = library.path().to_string_lossy();
+    git(
+        project.path(),
+        &[
+            "-c",
+            "protocol.file.allow=always",
+            "submodule",
+            "add",
+            &url,
+            "lib",
+        ],
+    );
+    git(project.path(), &["commit", "-m", "add lib"]);
+    // As after cloning without `--recursive`, the submodule isn't checked out,
+    // though here its repository is kept so that checking it out needs no clone.
+    git(project.path(), &["submodule", "deinit", "--force", "lib"]);
+    assert!(!project.path().join("lib/README.md").exists());
+
+    let (_store, agent) = create_agent(project.path(), VcsKind::Git);
+    assert_eq!(
+        fs::read_to_string(agent.workspace().join("lib/README.md")).expect("read submodule"),
+        "hello\n"
+    );
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let options = CreateOptions {
+        vcs: VcsKind::Git,
+        submodules: false,
+        ..CreateOptions::default()
+    };
+    let agent = Agent::new(
+        &sessions,
+        project.path(),
+        "plain".parse().expect("branch"),
+        &options,
+    )
+    .expect("create agent");
+    assert!(!agent.workspace().join("lib/README.md").exists());
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+
+    // Without its repository, the submodule can't be checked out, which only warns.
+    fs::remove_dir_all(project.path().join(".git/modules/lib")).expect("remove submodule");
+    drop(library);
+    let (_store, agent) = create_agent(project.path(), VcsKind::Git);
+    assert!(!agent.workspace().join("lib/README.md").exists());
+    let warnings = &agent.creation().expect("created").copy.warnings;
+    assert!(
+        warnings
+            .iter()
+            .any(|warning| warning.contains("submodules")),
+        "{warnings:?}"
+    );
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn copies_lfs_pointers_instead_of_files() {
+    let project = git_project();
+    // Stands in for git-lfs, which needn't be installed: what's committed is a pointer
+    // identifying the content, and pointers themselves are committed as they are.
+    let clean = r#"sh -c 'content=$(cat); case "$content" in pointer*) echo "$content" ;; *) echo pointer; echo "$content" | cksum ;; esac'"#;
+    git(project.path(), &["config", "filter.lfs.clean", clean]);
+    git(
+        project.path(),
+        &[
+            "config",
+            "filter.lfs.smudge",
+            "sh -c 'cat >/dev/null; echo contents'",
+        ],
+    );
+    git(project.path(), &["config", "filter.lfs.required", "true"]);
+    fs::write(project.path().join(".gitattributes"), "*.bin filter=lfs\n")
+        .expect("write attributes");
+    fs::write(project.path().join("big.bin"), "contents\n").expect("write big file");
+    fs::write(project.path().join("changed.bin"), "contents\n").expect("write changed file");
+    git(project.path(), &["add", "."]);
+    git(project.path(), &["commit", "-m", "add big files"]);
+    fs::write(project.path().join("changed.bin"), "changed\n").expect("change file");
+    fs::create_dir_all(project.path().join(".git/lfs/objects")).expect("create lfs storage");
+
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let options = CreateOptions {
+        vcs: VcsKind::Git,
+        lfs: Some(Lfs::default()),
+        ..CreateOptions::default()
+    };
+    let agent = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &options,
+    )
+    .expect("create agent");
+
+    let workspace = agent.workspace();
+    let read = |path| fs::read_to_string(workspace.join(path)).expect("read file");
+    assert!(read("big.bin").starts_with("pointer\n"));
+    assert_eq!(read("changed.bin"), "changed\n", "changes are copied");
+    assert!(!workspace.join(".git/lfs").exists(), "storage is shared");
+    let storage = git(workspace, &["config", "lfs.storage"]);
+    assert_eq!(
+        fs::canonicalize(storage).expect("canonicalize storage"),
+        fs::canonicalize(project.path().join(".git/lfs")).expect("canonicalize project storage")
+    );
+    assert_eq!(
+        git(workspace, &["status", "--porcelain"]),
+        git(project.path(), &["status", "--porcelain"]),
+        "pointers don't show as changes"
+    );
+}
+
+#[test]
+fn checks_out_existing_branches() {
+    let upstream = git_project();
+    git(upstream.path(), &["switch", "--create", "fix/issue-42"]);
+    fs::write(upstream.path().join("fix.txt"), "started\n").expect("write fix");
+    git(upstream.path(), &["add", "fix.txt"]);
+    git(upstream.path(), &["commit", "-m", "start fix"]);
+    let started = git(upstream.path(), &["rev-parse", "HEAD"]);
+
+    let project = git_project();
+    let url = upstream.path().to_string_lossy();
+    git(project.path(), &["remote", "add", "origin", &url]);
+    git(project.path(), &["fetch", "--quiet", "origin"]);
+    git(project.path(), &["switch", "--create", "local"]);
+    fs::write(project.path().join("local.txt"), "local\n").expect("write local");
+    git(project.path(), &["add", "local.txt"]);
+    git(project.path(), &["commit", "-m", "local work"]);
+    let local = git(project.path(), &["rev-parse", "HEAD"]);
+    git(project.path(), &["switch", "main"]);
+
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let options = CreateOptions {
+        vcs: VcsKind::Git,
+        existing: true,
+        ..CreateOptions::default()
+    };
+    let create = |branch: &str| {
+        Agent::new(
+            &sessions,
+            project.path(),
+            branch.parse().expect("branch"),
+            &options,
+        )
+    };
+
+    let agent = create("fix/issue-42").expect("check out remote branch");
+    let workspace = agent.workspace();
+    assert_eq!(agent.base(), Some(started.as_str()));
+    assert_eq!(
+        git(workspace, &["branch", "--show-current"]),
+        "fix/issue-42"
+    );
+    assert_eq!(
+        git(workspace, &["rev-parse", "--abbrev-ref", "@{upstream}"]),
+        "origin/fix/issue-42"
+    );
+    assert!(workspace.join("fix.txt").exists());
+
+    let agent = create("local").expect("check out local branch");
+    assert_eq!(agent.base(), Some(local.as_str()));
+    assert_eq!(
+        git(agent.workspace(), &["branch", "--show-current"]),
+        "local"
+    );
+
+    let err = create("missing").expect_err("branch doesn't exist");
+    assert!(format!("{err:#}").contains("doesn't exist locally or on any remote"));
+    let options = CreateOptions {
+        base: Some(String::from("main")),
+        ..options.clone()
+    };
+    let err = Agent::new(
+        &sessions,
+        project.path(),
+        "other".parse().expect("branch"),
+        &options,
+    )
+    .expect_err("existing branches have no base");
+    assert!(err.to_string().contains("base revision"));
+}
+
+#[cfg(unix)]
+#[test]
+fn isolates_toolchains_in_workspaces() {
+    let project = git_project();
+    fs::create_dir(project.path().join(".venv")).expect("create venv");
+    fs::write(project.path().join(".venv/pyvenv.cfg"), "home = /usr/bin\n").expect("write venv");
+
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let options = CreateOptions {
+        vcs: VcsKind::Git,
+        toolchains: vec![Toolchain::Cargo],
+        setup: vec![String::from("echo \"$CARGO_TARGET_DIR\" > target-dir.txt")],
+        ..CreateOptions::default()
+    };
+    let mut agent = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &options,
+    )
+    .expect("create agent");
+    let workspace = agent.workspace().to_path_buf();
+    let target = workspace.join("target");
+    assert_eq!(
+        fs::read_to_string(workspace.join("target-dir.txt")).expect("read setup output"),
+        format!("{}\n", target.display()),
+        "setup commands are isolated"
+    );
+    assert_eq!(agent.session().toolchains, [Toolchain::Cargo]);
+    assert!(
+        workspace.join(".venv").exists(),
+        "without the python preset, the virtualenv is copied"
+    );
+
+    let check = format!("test \"$CARGO_TARGET_DIR\" = '{}'", target.display());
+    assert!(agent.verify(&check).expect("verify").passed);
+}
+
+#[test]
+fn tracks_remote_branches_in_new_workspaces() {
+    let project = git_project();
+    let remote = TempDir::new().expect("create remote dir");
+    git(remote.path(), &["init", "--bare", "--initial-branch=main"]);
+    let url = remote.path().to_string_lossy();
+    git(project.path(), &["remote", "add", "origin", &url]);
+
+    let store = FakeStore::new().expect("open store");
+    let options = CreateOptions {
+        vcs: VcsKind::Git,
+        upstream: Some(String::from("origin")),
+        ..CreateOptions::default()
+    };
+    let agent = Agent::new(
+        &store,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &options,
+    )
+    .expect("create agent");
+    git(agent.workspace(), &["push", "--quiet"]);
+    assert_eq!(
+        git(
+            agent.workspace(),
+            &["rev-parse", "--abbrev-ref", "@{upstream}"]
+        ),
+        "origin/feature"
+    );
+
+    let options = CreateOptions {
+        vcs: VcsKind::None,
+        ..options
+    };
+    let err = Agent::new(
+        &store,
+        project.path(),
+        "plain".parse().expect("branch"),
+        &options,
+    )
+    .expect_err("only git tracks remote branches");
+    assert!(err.to_string().contains("only supported in git"), "{err}");
+}
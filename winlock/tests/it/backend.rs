@@ -0,0 +1,258 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use winlock::backend::{
+    self, ArgsTemplate, CommandTemplate, Placeholder, Restart, TemplateValues, RESTART_BACKOFF,
+};
+
+fn values() -> TemplateValues {
+    TemplateValues {
+        branch: Some(String::from("feature")),
+        workspace: Some(PathBuf::from("/tmp/workspace")),
+        project: Some(PathBuf::from("/src/project")),
+        prompt: Some(String::from("fix the bug, please")),
+        prompt_file: Some(PathBuf::from("/tmp/prompt")),
+        model: Some(String::from("opus")),
+        resume_token: None,
+    }
+}
+
+#[test]
+fn resolves_placeholders() {
+    let template = "claude --model {model} --append-system-prompt {prompt_file} --cwd={workspace}"
+        .parse::<CommandTemplate>()
+        .expect("parse template");
+    let args = template.resolve(&values()).expect("resolve template");
+    assert_eq!(
+        args,
+        [
+            "claude",
+            "--model",
+            "opus",
+            "--append-system-prompt",
+            "/tmp/prompt",
+            "--cwd=/tmp/workspace"
+        ]
+    );
+}
+
+#[test]
+fn substituted_values_are_not_split() {
+    let template = "claude {prompt}"
+        .parse::<CommandTemplate>()
+        .expect("parse template");
+    let args = template.resolve(&values()).expect("resolve template");
+    assert_eq!(args, ["claude", "fix the bug, please"]);
+}
+
+#[test]
+fn omits_standalone_placeholders_without_values() {
+    let template = "claude {prompt}"
+        .parse::<CommandTemplate>()
+        .expect("parse template");
+    let values = TemplateValues {
+        prompt: None,
+        ..values()
+    };
+    let args = template.resolve(&values).expect("resolve template");
+    assert_eq!(args, ["claude"]);
+}
+
+#[test]
+fn errors_on_embedded_placeholders_without_values() {
+    let template = "claude --model={model}"
+        .parse::<CommandTemplate>()
+        .expect("parse template");
+    let values = TemplateValues {
+        model: None,
+        ..values()
+    };
+    assert!(template.resolve(&values).is_err());
+}
+
+#[test]
+fn supports_escaped_braces() {
+    let template = "jq {{.{branch}}}"
+        .parse::<CommandTemplate>()
+        .expect("parse template");
+    let args = template.resolve(&values()).expect("resolve template");
+    assert_eq!(args, ["jq", "{.feature}"]);
+}
+
+#[test]
+fn rejects_invalid_templates() {
+    for template in [
+        "",
+        "claude {nope}",
+        "claude {branch",
+        "claude }",
+        "{model} --flag",
+    ] {
+        assert!(
+            template.parse::<CommandTemplate>().is_err(),
+            "{template:?} should be rejected"
+        );
+    }
+}
+
+#[test]
+fn reports_placeholders() {
+    let template = "claude {branch} --x={project}/{branch}"
+        .parse::<CommandTemplate>()
+        .expect("parse template");
+    let placeholders = template.placeholders().collect::<Vec<_>>();
+    assert_eq!(
+        placeholders,
+        [
+            Placeholder::Branch,
+            Placeholder::Project,
+            Placeholder::Branch
+        ]
+    );
+}
+
+#[test]
+fn exposes_program() {
+    let template = "claude --resume {resume_token}"
+        .parse::<CommandTemplate>()
+        .expect("parse template");
+    assert_eq!(template.program(), "claude");
+    assert!(template
+        .placeholders()
+        .any(|p| p == Placeholder::ResumeToken));
+}
+
+#[test]
+fn probes_backends() {
+    let version = backend::probe("git", Path::new(".")).expect("probe git");
+    assert!(version.starts_with("git version"), "{version}");
+
+    let err =
+        backend::probe("anna-test-missing-backend", Path::new(".")).expect_err("missing backend");
+    assert_eq!(err.problem, "is not installed");
+
+    let err = backend::probe("false", Path::new(".")).expect_err("failing backend");
+    assert!(
+        err.problem.starts_with("failed to report its version"),
+        "{err}"
+    );
+}
+
+#[test]
+fn parses_restart_policies() {
+    assert_eq!("no".parse::<Restart>().expect("parse"), Restart::No);
+    for (policy, max) in [("on-failure", None), ("on-failure:3", Some(3))] {
+        let restart = policy.parse::<Restart>().expect("parse");
+        assert_eq!(
+            restart,
+            Restart::OnFailure {
+                max,
+                backoff: RESTART_BACKOFF
+            }
+        );
+        assert_eq!(restart.to_string(), policy);
+    }
+    for policy in ["always", "on-failure:", "on-failure:-1"] {
+        assert!(policy.parse::<Restart>().is_err(), "{policy}");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn backs_off_restarts() {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+
+    let failed = ExitStatus::from_raw(1 << 8);
+    let restart = Restart::OnFailure {
+        max: Some(3),
+        backoff: Duration::from_secs(1),
+    };
+    let delays = (0..4)
+        .map(|restarts| restart.delay(restarts, &failed))
+        .collect::<Vec<_>>();
+    let secs = |secs| Some(Duration::from_secs(secs));
+    assert_eq!(delays, [secs(1), secs(2), secs(4), None]);
+
+    assert_eq!(restart.delay(0, &ExitStatus::from_raw(0)), None);
+    assert_eq!(restart.delay(0, &ExitStatus::from_raw(2)), None);
+    assert_eq!(restart.delay(0, &ExitStatus::from_raw(130 << 8)), None);
+    assert_eq!(Restart::No.delay(0, &failed), None);
+
+    let unlimited = Restart::OnFailure {
+        max: None,
+        backoff: Duration::from_secs(1),
+    };
+    assert_eq!(unlimited.delay(100, &failed), secs(300));
+}
+
+#[test]
+fn resolves_argument_templates() {
+    let args = ArgsTemplate::try_from(vec![
+        String::from("--append-system-prompt"),
+        String::from("{prompt}"),
+        String::from("{resume_token}"),
+        String::from("--branch={branch}"),
+    ])
+    .expect("parse arguments");
+    assert_eq!(
+        args.resolve(&values()).expect("resolve"),
+        [
+            "--append-system-prompt",
+            "fix the bug, please",
+            "--branch=feature"
+        ]
+    );
+    assert!(ArgsTemplate::try_from(vec![String::from("{unknown}")]).is_err());
+    assert!(ArgsTemplate::default().is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn suggests_branch_names() {
+    // Backends often format the name, despite being asked for only the name.
+    let template = "sh -c \"echo; echo '`Retry HTTP requests`'\" sh {prompt}"
+        .parse::<CommandTemplate>()
+        .expect("parse template");
+    let branch =
+        backend::suggest_branch(&template, "retry failed requests").expect("suggest branch");
+    assert_eq!(branch.as_str(), "retry-http-requests");
+
+    let failing = "false {prompt}"
+        .parse::<CommandTemplate>()
+        .expect("parse template");
+    assert!(backend::suggest_branch(&failing, "retry failed requests").is_err());
+
+    // Nothing of the project, which is where anna runs, is there for it to read.
+    let looking = r#"sh -c 'test -z "$(ls -A)" && echo empty-dir'"#
+        .parse::<CommandTemplate>()
+        .expect("parse template");
+    let branch = backend::suggest_branch(&looking, "anything").expect("suggest branch");
+    assert_eq!(branch.as_str(), "empty-dir");
+}
+
+#[cfg(unix)]
+#[test]
+fn gives_batch_backends_their_prompt() {
+    for template in [
+        "sh -c 'printf %s \"$1\"' sh {prompt}",
+        "sh -c 'cat \"$1\"' sh {prompt_file}",
+        "cat",
+    ] {
+        let template = template.parse::<CommandTemplate>().expect("parse template");
+        let output = backend::run_batch(
+            &template,
+            TemplateValues::default(),
+            "a 'long' prompt",
+            None,
+        )
+        .expect("run backend");
+        assert_eq!(output, "a 'long' prompt", "{template}");
+    }
+}
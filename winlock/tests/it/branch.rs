@@ -0,0 +1,105 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use winlock::branch::{from_prompt, sanitize, BranchName};
+
+#[test]
+fn accepts_valid_names() {
+    for name in ["main", "feature/retry-logic", "fix_42", "jessica/wip.2"] {
+        let branch = name.parse::<BranchName>().expect("valid branch name");
+        assert_eq!(branch.as_str(), name);
+    }
+}
+
+#[test]
+fn rejects_invalid_names() {
+    for name in [
+        "",
+        "@",
+        "-flag",
+        "/leading",
+        "trailing/",
+        "a//b",
+        "a..b",
+        "a.lock",
+        "a.",
+        ".hidden",
+        "a/.b",
+        "has space",
+        "a~b",
+        "a^b",
+        "a:b",
+        "a?b",
+        "a*b",
+        "a[b",
+        "a\\b",
+        "a@{b",
+    ] {
+        assert!(
+            name.parse::<BranchName>().is_err(),
+            "{name:?} should be rejected"
+        );
+    }
+}
+
+#[test]
+fn lists_forbidden_characters() {
+    let err = "Fix: crash on load?"
+        .parse::<BranchName>()
+        .expect_err("forbidden characters");
+    assert_eq!(
+        err.to_string(),
+        "branch name 'Fix: crash on load?' contains forbidden characters: ' ', ':', '?'"
+    );
+}
+
+#[test]
+fn sanitizes_free_form_text() {
+    for (text, expected) in [
+        ("Fix: crash on load", "fix-crash-on-load"),
+        ("feature/Retry Logic!", "feature/retry-logic"),
+        ("  --leading and trailing--  ", "leading-and-trailing"),
+        ("v1..2 release.", "v1.2-release"),
+        ("/a//b/", "a/b"),
+        ("deps.lock", "deps"),
+        ("@{upstream}", "upstream"),
+        ("Añadir soporte", "añadir-soporte"),
+    ] {
+        let branch = sanitize(text).expect("sanitizable text");
+        assert_eq!(branch.as_str(), expected, "sanitizing {text:?}");
+    }
+}
+
+#[test]
+fn sanitize_rejects_unusable_text() {
+    for text in ["", "!!!", "/ / ..", "@"] {
+        assert!(sanitize(text).is_err(), "{text:?} should be rejected");
+    }
+}
+
+#[test]
+fn derives_branch_from_prompt() {
+    for (prompt, expected) in [
+        (
+            "add retry logic to the HTTP client",
+            "add-retry-logic-http-client",
+        ),
+        (
+            "\n  Fix the crash on load.\n\nIt happens when...",
+            "fix-crash-load",
+        ),
+        ("The parser/lexer rewrite", "the-parser-lexer-rewrite"),
+        (
+            "Refactor the configuration loading so that profiles can inherit settings",
+            "refactor-configuration-loading-so-that",
+        ),
+        ("Update Cargo.lock please", "update-cargo"),
+        ("Update Cargo.lock for serde", "update-cargo.lock-serde"),
+    ] {
+        let branch = from_prompt(prompt).expect("usable prompt");
+        assert_eq!(branch.as_str(), expected, "deriving from {prompt:?}");
+    }
+    assert!(from_prompt("").is_err());
+    assert!(from_prompt("???\nmore").is_err());
+}
@@ -0,0 +1,569 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{env, fs, path::Path};
+
+use tempfile::TempDir;
+use winlock::{
+    config::{
+        self, Config, ConfigFile, ProjectConfig, Severity, CONFIG_DIR_VARIABLE, DEFAULT_PROFILE,
+        PROJECT_CONFIG, STATE_DIR_VARIABLE,
+    },
+    forge::ForgeKind,
+    vcs::VcsKind,
+    workspace::{CopyEngineKind, SECRET_PATTERNS},
+};
+
+use crate::with_env;
+
+fn load(content: &str) -> Config {
+    let dir = TempDir::new().expect("create config dir");
+    let path = dir.path().join("config.toml");
+    fs::write(&path, content).expect("write config");
+    Config::load_from(&path).expect("load config")
+}
+
+#[test]
+fn missing_config_is_default() {
+    let dir = TempDir::new().expect("create config dir");
+    let config = Config::load_from(&dir.path().join("config.toml")).expect("load config");
+    assert_eq!(config, Config::default());
+    assert!(config.profile(None).is_ok());
+}
+
+#[test]
+fn keeps_builtin_profile() {
+    let config = load(
+        r#"
+        profile = "aider"
+
+        [profiles.aider]
+        command = "aider --message {prompt}"
+        "#,
+    );
+    let aider = config.profile(None).expect("default profile");
+    assert_eq!(aider.command.to_string(), "aider --message {prompt}");
+    assert!(config.profile(Some(DEFAULT_PROFILE)).is_ok());
+    assert!(config.profile(Some("missing")).is_err());
+}
+
+#[test]
+fn reads_project_settings() {
+    let config = load(
+        r#"
+        [projects."/src/project"]
+        vcs = "hg"
+        "#,
+    );
+    assert_eq!(
+        config.project(Path::new("/src/project")).vcs,
+        Some(VcsKind::Mercurial)
+    );
+    assert_eq!(config.project(Path::new("/src/other")).vcs, None);
+}
+
+#[test]
+fn combines_secret_patterns() {
+    let config = load(
+        r#"
+        [secrets]
+        exclude = ["*.tfvars"]
+
+        [projects."/src/a".secrets]
+        exclude = ["!fixtures/test.pem"]
+
+        [projects."/src/b".secrets]
+        builtin = false
+        "#,
+    );
+
+    let a = config.secret_patterns(Path::new("/src/a"));
+    assert_eq!(a.len(), SECRET_PATTERNS.len() + 2);
+    assert_eq!(a[..SECRET_PATTERNS.len()], *SECRET_PATTERNS);
+    assert_eq!(
+        a[SECRET_PATTERNS.len()..],
+        ["*.tfvars", "!fixtures/test.pem"]
+    );
+
+    let b = config.secret_patterns(Path::new("/src/b"));
+    assert_eq!(b, ["*.tfvars"]);
+}
+
+#[test]
+fn project_git_settings_override_global() {
+    let config = load(
+        r#"
+        [git]
+        "user.name" = "Anna"
+        "commit.gpgsign" = "false"
+
+        [projects."/src/project".git]
+        "user.name" = "Project Anna"
+        "core.hooksPath" = ".githooks"
+        "#,
+    );
+
+    let git = config.git_config(Path::new("/src/project"));
+    let git = git
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        git,
+        [
+            ("commit.gpgsign", "false"),
+            ("core.hooksPath", ".githooks"),
+            ("user.name", "Project Anna"),
+        ]
+    );
+    assert_eq!(config.git_config(Path::new("/src/other")), config.git);
+}
+
+#[test]
+fn resolves_caches() {
+    let config = load(
+        r#"
+        [projects."/src/a".caches]
+        dirs = ["target"]
+
+        [projects."/src/b".caches]
+        dirs = ["node_modules"]
+        template = "/cache/b"
+        link = true
+        "#,
+    );
+
+    let a = config.caches(Path::new("/src/a")).expect("caches for a");
+    assert_eq!(a.template, Path::new("/src/a"));
+    assert_eq!(a.dirs, [Path::new("target")]);
+    assert!(!a.link);
+
+    let b = config.caches(Path::new("/src/b")).expect("caches for b");
+    assert_eq!(b.template, Path::new("/cache/b"));
+    assert!(b.link);
+
+    assert_eq!(config.caches(Path::new("/src/other")), None);
+}
+
+#[test]
+fn resolves_lfs_settings() {
+    let config = load(
+        r#"
+        [projects."/src/a".lfs]
+        pointers = true
+        pull = ["assets/ui/**"]
+
+        [projects."/src/b".lfs]
+        pull = ["assets/ui/**"]
+        "#,
+    );
+
+    let a = config.lfs(Path::new("/src/a")).expect("lfs for a");
+    assert_eq!(a.pull, ["assets/ui/**"]);
+    assert_eq!(
+        config.lfs(Path::new("/src/b")),
+        None,
+        "pointers must be enabled"
+    );
+    assert_eq!(config.lfs(Path::new("/src/other")), None);
+}
+
+#[test]
+fn configured_forges_override_detection() {
+    let config = load(
+        r#"
+        [forges]
+        "git.example.com" = "gitlab"
+        "github.example.com" = "gitea"
+        "#,
+    );
+
+    assert_eq!(config.forge("git.example.com"), Some(ForgeKind::GitLab));
+    assert_eq!(config.forge("github.example.com"), Some(ForgeKind::Gitea));
+    assert_eq!(config.forge("github.com"), Some(ForgeKind::GitHub));
+    assert_eq!(config.forge("git.unknown.org"), None);
+}
+
+#[test]
+fn merges_checked_in_project_config() {
+    let project = TempDir::new().expect("create project dir");
+    fs::write(
+        project.path().join(PROJECT_CONFIG),
+        r#"
+        verify = "cargo test"
+        setup = ["cargo fetch"]
+        copy_engine = "hardlink"
+
+        [secrets]
+        exclude = ["fixtures/"]
+
+        [caches]
+        dirs = ["target"]
+        template = "/home"
+        link = true
+        "#,
+    )
+    .expect("write project config");
+    let dir = TempDir::new().expect("create config dir");
+    let config_file = dir.path().join("config.toml");
+    fs::write(
+        &config_file,
+        format!(
+            r#"
+            [projects."{}"]
+            verify = "cargo nextest run"
+
+            [projects."{}".secrets]
+            exclude = ["!fixtures/small/"]
+            "#,
+            project.path().display(),
+            project.path().display()
+        ),
+    )
+    .expect("write config");
+    let mut config = Config::load_from(&config_file).expect("load config");
+
+    let untrusted = config
+        .load_project(project.path())
+        .expect("load project config")
+        .expect("setup isn't trusted");
+    assert_eq!(untrusted.setup, ["cargo fetch"]);
+    assert_eq!(untrusted.verify, None, "the user's verify overrides it");
+    let merged = config.project(project.path());
+    assert_eq!(merged.verify.as_deref(), Some("cargo nextest run"));
+    assert!(merged.setup.is_empty(), "{merged:?}");
+    assert_eq!(merged.secrets.exclude, ["fixtures/", "!fixtures/small/"]);
+    assert_eq!(config.copy_engine(project.path()), CopyEngineKind::Naive);
+    let caches = config.caches(project.path()).expect("caches");
+    assert_eq!(caches.template, project.path());
+    assert!(!caches.link);
+
+    config
+        .trust_project(&config_file, project.path(), &untrusted)
+        .expect("trust project commands");
+    assert_eq!(config.project(project.path()).setup, ["cargo fetch"]);
+    let mut reloaded = Config::load_from(&config_file).expect("load config");
+    let untrusted = reloaded
+        .load_project(project.path())
+        .expect("load project config");
+    assert_eq!(untrusted, None);
+    assert_eq!(reloaded.project(project.path()).setup, ["cargo fetch"]);
+
+    fs::write(
+        project.path().join(PROJECT_CONFIG),
+        r#"setup = ["curl https://example.com/install.sh | sh"]"#,
+    )
+    .expect("write project config");
+    let mut reloaded = Config::load_from(&config_file).expect("load config");
+    let untrusted = reloaded
+        .load_project(project.path())
+        .expect("load project config");
+    assert!(
+        untrusted.is_some(),
+        "changed commands must be trusted again"
+    );
+    assert!(reloaded.project(project.path()).setup.is_empty());
+
+    let other = TempDir::new().expect("create project dir");
+    config
+        .load_project(other.path())
+        .expect("load missing project config");
+    assert_eq!(config.project(other.path()), ProjectConfig::default());
+}
+
+#[test]
+fn checked_in_config_cannot_weaken_secrets() {
+    let project = TempDir::new().expect("create project dir");
+    let checked_in = r#"
+[secrets]
+builtin = false
+exclude = ["fixtures/", "!.env.local"]
+"#;
+    fs::write(project.path().join(PROJECT_CONFIG), checked_in).expect("write project config");
+    let mut config = Config::default();
+
+    config
+        .load_project(project.path())
+        .expect("load project config");
+    let patterns = config.secret_patterns(project.path());
+    assert!(
+        SECRET_PATTERNS
+            .iter()
+            .all(|pattern| patterns.iter().any(|p| p == pattern)),
+        "{patterns:?}"
+    );
+    assert!(patterns.iter().any(|pattern| pattern == "fixtures/"));
+    assert!(!patterns.iter().any(|pattern| pattern == "!.env.local"));
+
+    let problems = config::check(checked_in, ConfigFile::Project);
+    let found = problems
+        .iter()
+        .map(|problem| (problem.line, problem.severity))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [(3, Severity::Warning), (4, Severity::Warning)],
+        "{problems:#?}"
+    );
+}
+
+#[test]
+fn applies_only_harmless_checked_in_git_settings() {
+    let project = TempDir::new().expect("create project dir");
+    let checked_in = r#"
+[git]
+"user.name" = "Anna"
+"core.hooksPath" = ".githooks"
+"CORE.FSMONITOR" = "./watch"
+"#;
+    fs::write(project.path().join(PROJECT_CONFIG), checked_in).expect("write project config");
+    let mut config = load(&format!(
+        r#"
+        [projects."{}".git]
+        "core.sshCommand" = "ssh -i ~/.ssh/work"
+        "#,
+        project.path().display()
+    ));
+
+    config
+        .load_project(project.path())
+        .expect("load project config");
+    let git = config.git_config(project.path());
+    let keys = git.keys().map(String::as_str).collect::<Vec<_>>();
+    assert_eq!(keys, ["core.sshCommand", "user.name"]);
+
+    let problems = config::check(checked_in, ConfigFile::Project);
+    let found = problems
+        .iter()
+        .map(|problem| (problem.line, problem.severity))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [(4, Severity::Warning), (5, Severity::Warning)],
+        "{problems:#?}"
+    );
+}
+
+#[test]
+fn project_templates_parse() {
+    let rust = TempDir::new().expect("create project dir");
+    fs::write(rust.path().join("Cargo.toml"), "").expect("write manifest");
+    let unknown = TempDir::new().expect("create project dir");
+
+    for project in [&rust, &unknown] {
+        let template = config::project_template(project.path());
+        fs::write(project.path().join(PROJECT_CONFIG), template).expect("write project config");
+        let mut config = Config::default();
+        config
+            .load_project(project.path())
+            .expect("load project config");
+    }
+
+    let mut config = Config::default();
+    let untrusted = config
+        .load_project(rust.path())
+        .expect("load project config")
+        .expect("setup isn't trusted");
+    assert_eq!(untrusted.setup, ["cargo fetch"]);
+    config
+        .load_project(unknown.path())
+        .expect("load project config");
+    assert_eq!(config.project(unknown.path()), ProjectConfig::default());
+}
+
+#[test]
+fn config_dir_can_be_overridden() {
+    let config = TempDir::new().expect("create config dir");
+    let vars = [
+        (CONFIG_DIR_VARIABLE, Some(config.path().as_os_str())),
+        (STATE_DIR_VARIABLE, None),
+    ];
+    if !with_env("config::config_dir_can_be_overridden", &vars) {
+        return;
+    }
+    let config = env::var_os(CONFIG_DIR_VARIABLE).expect("config dir variable");
+    assert_eq!(config::config_dir().expect("config dir"), config);
+    assert_eq!(
+        config::state_dir().expect("state dir"),
+        config,
+        "state is kept with the configuration by default"
+    );
+}
+
+#[test]
+fn state_dir_can_be_overridden() {
+    let state = TempDir::new().expect("create state dir");
+    let vars = [(STATE_DIR_VARIABLE, Some(state.path().as_os_str()))];
+    if !with_env("config::state_dir_can_be_overridden", &vars) {
+        return;
+    }
+    let state = env::var_os(STATE_DIR_VARIABLE).expect("state dir variable");
+    assert_eq!(config::state_dir().expect("state dir"), state);
+}
+
+#[test]
+fn sets_project_values_keeping_the_rest() {
+    let dir = TempDir::new().expect("create config dir");
+    let path = dir.path().join("config.toml");
+    let project = Path::new("/src/project");
+    fs::write(&path, "# Keep this.\nauto_commit = true\n").expect("write config");
+
+    config::set_project_value(&path, project, "copy_engine", "parallel").expect("set value");
+    config::set_project_value(&path, project, "copy_engine", "reflink").expect("set value");
+    let content = fs::read_to_string(&path).expect("read config");
+    assert!(
+        content.starts_with("# Keep this.\nauto_commit = true\n"),
+        "{content}"
+    );
+    assert!(!content.contains("[projects]"), "{content}");
+
+    let config = Config::load_from(&path).expect("load config");
+    assert!(config.auto_commit);
+    assert_eq!(config.copy_engine(project), CopyEngineKind::Reflink);
+    assert_eq!(
+        config.copy_engine(Path::new("/src/other")),
+        CopyEngineKind::Naive
+    );
+
+    // Values that wouldn't load are refused rather than written.
+    assert!(config::set_project_value(&path, project, "copy_engine", "rsync").is_err());
+    assert_eq!(
+        Config::load_from(&path)
+            .expect("load config")
+            .copy_engine(project),
+        CopyEngineKind::Reflink
+    );
+}
+
+#[test]
+fn checks_report_every_problem_where_it_is() {
+    let content = r#"profile = "aider"
+auto_commit = "yes"
+colour = true
+pty = false
+transcripts = true
+
+[profiles.aider]
+comand = "aider"
+
+[projects."src/project"]
+vcs = "svn"
+setup = ["make deps"]
+"#;
+    let problems = config::check(content, ConfigFile::User);
+    let found = problems
+        .iter()
+        .map(|problem| (problem.line, problem.column, problem.severity))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        found,
+        [
+            (2, 15, Severity::Error),
+            (3, 1, Severity::Error),
+            (5, 1, Severity::Warning),
+            (8, 1, Severity::Error),
+            (10, 11, Severity::Warning),
+            (11, 7, Severity::Error),
+        ],
+        "{problems:#?}"
+    );
+    assert!(problems[1].message.contains("colour"), "{}", problems[1]);
+    assert!(problems[2].to_string().starts_with("5:1: warning: "));
+
+    // Without a user config, the same settings of projects are checked.
+    let problems = config::check("vcs = \"svn\"\nsetup = 1\n", ConfigFile::Project);
+    let lines = problems
+        .iter()
+        .map(|problem| problem.line)
+        .collect::<Vec<_>>();
+    assert_eq!(lines, [1, 2], "{problems:#?}");
+}
+
+#[test]
+fn checks_pass_valid_config() {
+    let content = r#"
+        profile = "aider"
+
+        [profiles.aider]
+        command = "aider --message {prompt}"
+
+        [projects."/src/project"]
+        vcs = "hg"
+        caches = { dirs = ["target"] }
+        "#;
+    assert_eq!(config::check(content, ConfigFile::User), []);
+
+    // Syntax errors don't stop the rest of the file being checked.
+    let problems = config::check("pty = \nauto_commit = 1\n", ConfigFile::User);
+    let lines = problems
+        .iter()
+        .map(|problem| problem.line)
+        .collect::<Vec<_>>();
+    assert_eq!(lines, [1, 2], "{problems:#?}");
+}
+
+#[test]
+fn gets_sets_and_unsets_values() {
+    let dir = TempDir::new().expect("create config dir");
+    let path = dir.path().join("config.toml");
+    fs::write(&path, "# Keep this.\nauto_commit = true\n").expect("write config");
+
+    let get = |key| config::get_value(&path, ConfigFile::User, key).expect("get value");
+    assert_eq!(get("pty"), Some(toml::Value::Boolean(true)));
+    assert_eq!(get("network.allow"), None);
+
+    config::set_value(
+        &path,
+        ConfigFile::User,
+        "network.isolate",
+        config::parse_value("true"),
+    )
+    .expect("set value");
+    config::set_value(
+        &path,
+        ConfigFile::User,
+        r#"git."user.name""#,
+        config::parse_value("Anna Agent"),
+    )
+    .expect("set value");
+    assert_eq!(get("network.isolate"), Some(toml::Value::Boolean(true)));
+    assert_eq!(
+        get(r#"git."user.name""#),
+        Some(toml::Value::String(String::from("Anna Agent")))
+    );
+    assert!(config::set_value(&path, ConfigFile::User, "auto_commit", "maybe").is_err());
+    assert!(config::set_value(&path, ConfigFile::User, "auto_commit.x", true).is_err());
+
+    assert!(config::unset_value(&path, ConfigFile::User, r#"git."user.name""#).expect("unset"));
+    assert!(!config::unset_value(&path, ConfigFile::User, r#"git."user.name""#).expect("unset"));
+    let content = fs::read_to_string(&path).expect("read config");
+    assert!(
+        content.starts_with("# Keep this.\nauto_commit = true\n"),
+        "{content}"
+    );
+    assert!(!content.contains("[git]"), "{content}");
+
+    // Project files take the settings of a single project.
+    let path = dir.path().join(PROJECT_CONFIG);
+    config::set_value(&path, ConfigFile::Project, "caches.link", true).expect("set value");
+    assert!(config::set_value(&path, ConfigFile::Project, "auto_commit", true).is_err());
+    let settings = toml::from_str::<ProjectConfig>(&fs::read_to_string(&path).expect("read"))
+        .expect("parse project config");
+    assert_eq!(settings.caches.link, Some(true));
+}
+
+#[test]
+fn parses_values_as_toml_or_strings() {
+    assert_eq!(config::parse_value("3").as_integer(), Some(3));
+    assert_eq!(config::parse_value("false").as_bool(), Some(false));
+    assert_eq!(
+        config::parse_value(r#"["a"]"#).as_array().map(|a| a.len()),
+        Some(1)
+    );
+    assert_eq!(
+        config::parse_value("cargo test").as_str(),
+        Some("cargo test")
+    );
+    assert_eq!(config::parse_value(r#""3""#).as_str(), Some("3"));
+}
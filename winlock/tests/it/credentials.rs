@@ -0,0 +1,87 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{ffi::OsString, fs};
+
+use winlock::{credentials, test_util::MockBackend, vcs::VcsKind, RunOptions};
+
+use crate::{agent::create_agent, git_project};
+
+#[test]
+fn expands_environment_templates() {
+    // Cargo sets it for the tests it runs.
+    assert_eq!(
+        credentials::resolve("key-${CARGO_PKG_NAME}").expect("resolve"),
+        "key-winlock"
+    );
+    assert_eq!(
+        credentials::resolve("plain value").expect("resolve"),
+        "plain value"
+    );
+    assert!(credentials::resolve("${ANNA_TEST_UNSET_VARIABLE}").is_err());
+    assert!(credentials::resolve("${CARGO_PKG_NAME").is_err());
+}
+
+#[test]
+fn resolves_profile_environment_without_recording_it() {
+    let project = git_project();
+    let (store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let backend = MockBackend::new();
+    let mut profile = backend.profile();
+    profile.env.insert(
+        String::from("ANTHROPIC_API_KEY"),
+        String::from("sk-${CARGO_PKG_NAME}-secret"),
+    );
+
+    agent
+        .run(&profile, &backend.run_options())
+        .expect("run backend");
+    let runs = backend.runs();
+    assert!(runs[0].env.contains(&(
+        String::from("ANTHROPIC_API_KEY"),
+        OsString::from("sk-winlock-secret")
+    )));
+
+    let mut dirs = vec![store.dir().to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).expect("read store") {
+            let path = entry.expect("read store entry").path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if let Ok(content) = fs::read_to_string(&path) {
+                assert!(!content.contains("sk-winlock-secret"), "{}", path.display());
+            }
+        }
+    }
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn refuses_secrets_for_backends_in_containers() {
+    assert!(credentials::is_reference("op://vault/item/field"));
+    assert!(credentials::is_reference("pass://work/api"));
+    assert!(!credentials::is_reference("Bearer ${GITHUB_TOKEN}"));
+
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let backend = MockBackend::new();
+    let mut profile = backend.profile();
+    profile.env.insert(
+        String::from("ANTHROPIC_API_KEY"),
+        String::from("op://vault/anthropic/key"),
+    );
+    let options = RunOptions {
+        devcontainer: true,
+        ..backend.run_options()
+    };
+
+    let err = agent
+        .run(&profile, &options)
+        .expect_err("secrets are refused");
+    assert!(err.to_string().contains("ANTHROPIC_API_KEY"), "{err}");
+    assert!(backend.runs().is_empty());
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
@@ -0,0 +1,107 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::BTreeMap, env, fs, os::unix::fs::PermissionsExt, path::Path, thread,
+    time::Duration,
+};
+
+use serde_json::Value;
+use tempfile::TempDir;
+use winlock::{
+    daemon::{Client, Daemon},
+    session::Sessions,
+};
+
+use crate::session::session;
+
+/// Connect to the daemon listening on `socket`, once it is.
+fn connect(socket: &Path) -> Client {
+    for _ in 0..100 {
+        if let Ok(client) = Client::connect_to(socket) {
+            return client;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    panic!("the daemon never listened on {}", socket.display());
+}
+
+#[test]
+fn answers_requests_until_shut_down() {
+    let state = TempDir::new().expect("create state dir");
+    let sessions = Sessions::open_in(state.path()).expect("open sessions");
+    let one = session("/src/a", "one");
+    let two = session("/src/b", "two");
+    sessions.store(&one).expect("store one");
+    sessions.store(&two).expect("store two");
+
+    let socket = state.path().join("daemon.sock");
+    let daemon = Daemon::new(state.path(), Path::new("git")).expect("create daemon");
+    let server = thread::spawn({
+        let socket = socket.clone();
+        move || daemon.serve(&socket)
+    });
+    let mut client = connect(&socket);
+    let mode = fs::metadata(&socket)
+        .expect("socket metadata")
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600, "only the user can connect");
+
+    let ping = client.call::<Value>("ping", Value::Null).expect("ping");
+    assert_eq!(ping["pid"], std::process::id());
+    assert_eq!(
+        client.sessions(Some(&one.project)).expect("list sessions"),
+        [one]
+    );
+    assert_eq!(client.sessions(None).expect("list sessions").len(), 2);
+    assert!(client.running().expect("list running").is_empty());
+
+    let env = [
+        (String::from("GIT_AUTHOR_NAME"), String::from("Anna")),
+        (
+            String::from("GIT_AUTHOR_EMAIL"),
+            String::from("anna@example.com"),
+        ),
+        (String::from("GIT_AUTHOR_DATE"), String::from("@0 +0000")),
+        (String::from("PATH"), env::var("PATH").unwrap_or_default()),
+    ];
+    let agent = client
+        .start_agent(
+            state.path(),
+            &[String::from("var"), String::from("GIT_AUTHOR_IDENT")],
+            &BTreeMap::from(env),
+        )
+        .expect("start agent");
+    assert_eq!(agent.id, 1);
+    let finished = (0..100)
+        .find_map(|_| {
+            let agents = client.agents().expect("list agents");
+            let finished = agents.into_iter().find(|agent| agent.finished_at.is_some());
+            if finished.is_none() {
+                thread::sleep(Duration::from_millis(20));
+            }
+            finished
+        })
+        .expect("the agent finishes");
+    assert_eq!(finished.code, Some(0));
+    let log = fs::read_to_string(&finished.log).expect("read log");
+    assert_eq!(
+        log.trim(),
+        "Anna <anna@example.com> 0 +0000",
+        "the agent gets the env"
+    );
+
+    let err = client
+        .call::<Value>("nonsense", Value::Null)
+        .expect_err("no such method");
+    assert!(err.to_string().contains("no such method"), "{err}");
+
+    client.shutdown().expect("shut down");
+    server
+        .join()
+        .expect("the daemon doesn't panic")
+        .expect("serve");
+    assert!(!socket.exists());
+}
@@ -0,0 +1,110 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::Arc,
+    thread,
+};
+
+use serde_json::Value;
+use tempfile::TempDir;
+use winlock::{dashboard::Dashboard, history::HistoryEvent, session::Sessions};
+
+use crate::session::session;
+
+/// Make a `method` request for `path` to the dashboard at `addr`,
+/// returning the status code and body.
+fn request(addr: SocketAddr, method: &str, path: &str) -> (u16, String) {
+    request_to(addr, "localhost", method, path)
+}
+
+/// Make a request as [`request`] does, addressed to `host`.
+fn request_to(addr: SocketAddr, host: &str, method: &str, path: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).expect("connect to dashboard");
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: {host}\r\nConnection: close\r\n\r\n"
+    )
+    .expect("send request");
+    let mut response = String::new();
+    stream.read_to_string(&mut response).expect("read response");
+    let (head, body) = response.split_once("\r\n\r\n").expect("headers end");
+    let status = head
+        .split(' ')
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .expect("status code");
+    (status, String::from(body))
+}
+
+#[test]
+fn serves_sessions_read_only() {
+    let state = TempDir::new().expect("create state dir");
+    let sessions = Sessions::open_in(state.path()).expect("open sessions");
+    let mut one = session("/src/a", "one");
+    one.prompt = Some(String::from("use the key sk-secret"));
+    one.resume_tokens
+        .insert(String::from("claude"), String::from("conversation"));
+    sessions.store(&one).expect("store session");
+    sessions.record(&one, HistoryEvent::Resumed);
+
+    let dashboard = Dashboard::bind(Sessions::open_read_only_in(state.path()), "127.0.0.1:0")
+        .map(Arc::new)
+        .expect("bind dashboard");
+    let addr = dashboard.local_addr().expect("listening on an IP address");
+    let server = thread::spawn({
+        let dashboard = Arc::clone(&dashboard);
+        move || dashboard.serve()
+    });
+
+    let (status, body) = request(addr, "GET", "/api/sessions");
+    assert_eq!(status, 200);
+    let listed = serde_json::from_str::<Value>(&body).expect("parse sessions");
+    assert_eq!(listed[0]["branch"], "one");
+    assert_eq!(listed[0]["short_id"], one.short_id());
+    assert_eq!(listed[0]["state"], "missing");
+    assert_eq!(listed[0]["running"], Value::Null);
+    assert!(!body.contains("sk-secret"), "{body}");
+    assert!(!body.contains("conversation"), "{body}");
+
+    let (status, body) = request(addr, "GET", &format!("/api/sessions/{}", one.short_id()));
+    assert_eq!(status, 200);
+    let report = serde_json::from_str::<Value>(&body).expect("parse session");
+    assert_eq!(report["project"], "/src/a");
+    assert!(report.get("diff_stat").is_some(), "{body}");
+
+    let (status, body) = request(
+        addr,
+        "GET",
+        &format!("/api/sessions/{}/history", one.short_id()),
+    );
+    assert_eq!(status, 200);
+    let history = serde_json::from_str::<Value>(&body).expect("parse history");
+    assert_eq!(history[0]["event"], "resumed");
+
+    let (status, body) = request(addr, "GET", "/");
+    assert_eq!(status, 200);
+    assert!(body.contains("<td>one</td>"), "{body}");
+
+    assert_eq!(request(addr, "GET", "/api/sessions/000000").0, 404);
+    assert_eq!(request(addr, "DELETE", "/api/sessions").0, 405);
+
+    // Only as itself, not by a name a web page could have pointed at it.
+    let port = addr.port();
+    assert_eq!(request_to(addr, &addr.to_string(), "GET", "/").0, 200);
+    assert_eq!(
+        request_to(addr, &format!("localhost:{port}"), "GET", "/").0,
+        200
+    );
+    assert_eq!(
+        request_to(addr, &format!("evil.example:{port}"), "GET", "/").0,
+        403
+    );
+    assert_eq!(request_to(addr, "127.0.0.2", "GET", "/").0, 403);
+
+    dashboard.stop();
+    server.join().expect("dashboard stops");
+}
@@ -0,0 +1,65 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{ffi::OsString, fs, path::PathBuf};
+
+use tempfile::TempDir;
+use winlock::devcontainer::{self, Container};
+
+#[test]
+fn finds_devcontainer_config() {
+    let project = TempDir::new().expect("create project dir");
+    assert_eq!(devcontainer::config(project.path()), None);
+
+    fs::write(project.path().join(".devcontainer.json"), "{}").expect("write config");
+    assert_eq!(
+        devcontainer::config(project.path()),
+        Some(project.path().join(".devcontainer.json"))
+    );
+
+    // The directory form takes precedence, as it does for the Dev Container CLI.
+    fs::create_dir(project.path().join(".devcontainer")).expect("create config dir");
+    fs::write(project.path().join(".devcontainer/devcontainer.json"), "{}").expect("write config");
+    assert_eq!(
+        devcontainer::config(project.path()),
+        Some(project.path().join(".devcontainer/devcontainer.json"))
+    );
+}
+
+#[test]
+fn refuses_to_start_without_config() {
+    let project = TempDir::new().expect("create project dir");
+    let err = devcontainer::up(project.path()).expect_err("no config");
+    assert!(
+        err.to_string().contains("no development container"),
+        "{err}"
+    );
+}
+
+#[test]
+fn runs_backend_with_devcontainer_exec() {
+    let container = Container {
+        id: String::from("abc123"),
+        workspace: PathBuf::from("/tmp/workspace"),
+        workspace_in_container: PathBuf::from("/workspaces/workspace"),
+    };
+    let command_line = container.command_line(
+        &[String::from("claude"), String::from("--model=opus")],
+        &[("ANNA_TEST", OsString::from("a b"))],
+    );
+    assert_eq!(
+        command_line,
+        [
+            "devcontainer",
+            "exec",
+            "--workspace-folder",
+            "/tmp/workspace",
+            "--remote-env",
+            "ANNA_TEST=a b",
+            "--",
+            "claude",
+            "--model=opus",
+        ]
+    );
+}
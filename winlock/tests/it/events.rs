@@ -0,0 +1,165 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tempfile::TempDir;
+use winlock::{
+    backend::Restart,
+    config::Profile,
+    events::{Event, Events},
+    session::Sessions,
+    vcs::VcsKind,
+    Agent, CreateOptions, RunOptions,
+};
+
+use crate::git_project;
+
+/// Collects written events in memory.
+#[derive(Debug, Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Buffer {
+    fn events(&self) -> Vec<Event> {
+        let content = self.0.lock().expect("lock buffer").clone();
+        String::from_utf8(content)
+            .expect("events are utf-8")
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("parse event"))
+            .collect()
+    }
+}
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().expect("lock buffer").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn serializes_tagged_events() {
+    let event = Event::BackendExited {
+        code: Some(0),
+        success: true,
+    };
+    assert_eq!(
+        serde_json::to_string(&event).expect("serialize"),
+        r#"{"event":"backend_exited","code":0,"success":true}"#
+    );
+}
+
+#[test]
+fn emits_agent_events() {
+    let project = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let buffer = Buffer::default();
+    let options = CreateOptions {
+        vcs: VcsKind::Git,
+        events: Events::new(buffer.clone()),
+        ..CreateOptions::default()
+    };
+    let mut agent = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &options,
+    )
+    .expect("create agent");
+    let profile =
+        toml::from_str::<Profile>(r#"command = "sh -c 'exit 3'""#).expect("parse profile");
+    let options = RunOptions {
+        verify: Some(String::from("true")),
+        ..RunOptions::default()
+    };
+    agent.run(&profile, &options).expect("run backend");
+
+    let events = buffer
+        .events()
+        .into_iter()
+        .filter(|event| !matches!(event, Event::CopyProgress { .. }))
+        .collect::<Vec<_>>();
+    assert!(matches!(events[0], Event::CopyFinished { failed: 0, .. }));
+    assert_eq!(
+        events[1],
+        Event::SessionCreated {
+            project: agent.project().to_path_buf(),
+            branch: String::from("feature"),
+            workspace: agent.workspace().to_path_buf(),
+        }
+    );
+    assert_eq!(
+        events[2..],
+        [
+            Event::BackendStarted {
+                program: String::from("sh"),
+                resumed: false,
+            },
+            Event::BackendExited {
+                code: Some(3),
+                success: false,
+            },
+            Event::Verified {
+                command: String::from("true"),
+                passed: true,
+            },
+        ]
+    );
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn hands_events_to_functions() {
+    let project = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let handled = Arc::new(Mutex::new(Vec::new()));
+    let options = CreateOptions {
+        vcs: VcsKind::Git,
+        events: Events::from_fn({
+            let handled = Arc::clone(&handled);
+            move |event| handled.lock().expect("lock events").push(event.clone())
+        }),
+        ..CreateOptions::default()
+    };
+    let mut agent = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &options,
+    )
+    .expect("create agent");
+    let profile =
+        toml::from_str::<Profile>(r#"command = "sh -c 'exit 3'""#).expect("parse profile");
+    let options = RunOptions {
+        restart: Restart::OnFailure {
+            max: Some(1),
+            backoff: Duration::from_millis(1),
+        },
+        ..RunOptions::default()
+    };
+    agent.run(&profile, &options).expect("run backend");
+
+    let handled = handled.lock().expect("lock events");
+    assert!(handled.iter().any(|event| matches!(
+        event,
+        Event::BackendRestarting {
+            attempt: 1,
+            max_attempts: Some(1),
+            ..
+        }
+    )));
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
@@ -0,0 +1,370 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    thread,
+};
+
+use winlock::{
+    config::Profile,
+    forge::{
+        Forge, ForgeKind, GitHub, GitLab, Gitea, Issue, IssueReference, PullRequestDraft,
+        Repository,
+    },
+    vcs::VcsKind,
+    RunOptions,
+};
+
+use crate::{agent::create_agent, git_project};
+
+#[test]
+fn parses_remote_urls() {
+    for url in [
+        "https://github.com/jssblck/anna.git",
+        "https://github.com/jssblck/anna",
+        "https://token@github.com/jssblck/anna/",
+        "ssh://git@github.com:22/jssblck/anna.git",
+        "git@github.com:jssblck/anna.git",
+        "git@GitHub.com:jssblck/anna",
+    ] {
+        let repository = url.parse::<Repository>().expect("parse remote url");
+        assert_eq!(repository.host, "github.com", "{url}");
+        assert_eq!(repository.path, "jssblck/anna", "{url}");
+        assert_eq!(repository.owner(), "jssblck", "{url}");
+        assert_eq!(repository.name(), "anna", "{url}");
+    }
+
+    let nested = "https://gitlab.com/group/subgroup/project.git"
+        .parse::<Repository>()
+        .expect("parse nested url");
+    assert_eq!(nested.owner(), "group/subgroup");
+    assert_eq!(nested.name(), "project");
+
+    for url in ["/srv/git/anna.git", "https://github.com/anna", "anna"] {
+        assert!(
+            url.parse::<Repository>().is_err(),
+            "{url} should be rejected"
+        );
+    }
+}
+
+#[test]
+fn drafts_pull_request_from_session() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let profile =
+        toml::from_str::<Profile>(r#"command = "sh -c 'echo fixed > fix.txt'""#).expect("profile");
+    let options = RunOptions {
+        prompt: Some(String::from(
+            "Fix the crash on load\n\nIt happens on startup.",
+        )),
+        auto_commit: true,
+        ..RunOptions::default()
+    };
+    agent.run(&profile, &options).expect("run backend");
+
+    let draft = PullRequestDraft::new(agent.session(), "main").expect("draft");
+    assert_eq!(draft.title, "Fix the crash on load");
+    assert!(draft
+        .body
+        .starts_with("Fix the crash on load\n\nIt happens on startup."));
+    assert!(draft.body.contains("fix.txt"), "{}", draft.body);
+    assert_eq!(draft.head, "feature");
+    assert_eq!(draft.base, "main");
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn drafts_pull_request_without_prompt() {
+    let project = git_project();
+    let (_store, agent) = create_agent(project.path(), VcsKind::Git);
+
+    let draft = PullRequestDraft::new(agent.session(), "main").expect("draft");
+    assert_eq!(draft.title, "feature");
+    assert_eq!(draft.body, "");
+
+    let mut session = agent.session().clone();
+    session.issue = Some(String::from("https://github.com/jssblck/anna/issues/12"));
+    let draft = PullRequestDraft::new(&session, "main").expect("draft");
+    assert_eq!(
+        draft.body,
+        "Closes https://github.com/jssblck/anna/issues/12"
+    );
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+/// The request received by [`serve_once`]: its header lines and body.
+type Received = (Vec<String>, serde_json::Value);
+
+/// Answer a single HTTP request with `response` as JSON.
+fn serve_once(response: &'static str) -> (String, thread::JoinHandle<Received>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let address = listener.local_addr().expect("address");
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut reader = BufReader::new(stream.try_clone().expect("clone stream"));
+
+        let mut head = Vec::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).expect("read header");
+            let line = line.trim_end().to_string();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                length = value.trim().parse().expect("content length");
+            }
+            head.push(line);
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).expect("read body");
+
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+            response.len()
+        )
+        .expect("write response");
+        let body = match body.is_empty() {
+            true => serde_json::Value::Null,
+            false => serde_json::from_slice(&body).expect("json body"),
+        };
+        (head, body)
+    });
+    (format!("http://{address}"), server)
+}
+
+fn draft() -> PullRequestDraft {
+    PullRequestDraft {
+        title: String::from("Fix the crash"),
+        body: String::from("Details"),
+        head: String::from("feature"),
+        base: String::from("main"),
+    }
+}
+
+#[test]
+fn detects_forge_from_host() {
+    for (host, expected) in [
+        ("github.com", Some(ForgeKind::GitHub)),
+        ("github.example.com", Some(ForgeKind::GitHub)),
+        ("gitlab.com", Some(ForgeKind::GitLab)),
+        ("gitlab.gnome.org", Some(ForgeKind::GitLab)),
+        ("codeberg.org", Some(ForgeKind::Gitea)),
+        ("gitea.com", Some(ForgeKind::Gitea)),
+        ("forgejo.example.org", Some(ForgeKind::Gitea)),
+        ("git.example.com", None),
+        ("notgithub.com", None),
+    ] {
+        assert_eq!(ForgeKind::detect(host), expected, "{host}");
+    }
+}
+
+#[test]
+fn creates_github_pull_request() {
+    let (api, server) =
+        serve_once(r#"{"number": 7, "html_url": "https://github.com/jssblck/anna/pull/7"}"#);
+    let repository = "git@github.com:jssblck/anna.git"
+        .parse::<Repository>()
+        .expect("repository");
+    let github = GitHub::new(api, "secret");
+    assert_eq!(github.kind(), ForgeKind::GitHub);
+
+    let pr = github
+        .create_pull_request(&repository, &draft())
+        .expect("create pull request");
+    assert_eq!(pr.number, 7);
+    assert_eq!(pr.url, "https://github.com/jssblck/anna/pull/7");
+
+    let (head, body) = server.join().expect("server");
+    assert!(
+        head[0].starts_with("POST /repos/jssblck/anna/pulls "),
+        "{}",
+        head[0]
+    );
+    assert!(head
+        .iter()
+        .any(|line| line.to_lowercase() == "authorization: bearer secret"));
+    assert_eq!(body["title"], "Fix the crash");
+    assert_eq!(body["head"], "feature");
+    assert_eq!(body["base"], "main");
+}
+
+#[test]
+fn creates_gitlab_merge_request() {
+    let (api, server) = serve_once(
+        r#"{"iid": 3, "web_url": "https://gitlab.com/group/sub/project/-/merge_requests/3"}"#,
+    );
+    let repository = "https://gitlab.com/group/sub/project.git"
+        .parse::<Repository>()
+        .expect("repository");
+    let gitlab = GitLab::new(api, "secret");
+    assert_eq!(gitlab.kind(), ForgeKind::GitLab);
+
+    let pr = gitlab
+        .create_pull_request(&repository, &draft())
+        .expect("create merge request");
+    assert_eq!(pr.number, 3);
+    assert_eq!(
+        pr.url,
+        "https://gitlab.com/group/sub/project/-/merge_requests/3"
+    );
+
+    let (head, body) = server.join().expect("server");
+    assert!(
+        head[0].starts_with("POST /projects/group%2Fsub%2Fproject/merge_requests "),
+        "{}",
+        head[0]
+    );
+    assert!(head
+        .iter()
+        .any(|line| line.to_lowercase() == "private-token: secret"));
+    assert_eq!(body["title"], "Fix the crash");
+    assert_eq!(body["description"], "Details");
+    assert_eq!(body["source_branch"], "feature");
+    assert_eq!(body["target_branch"], "main");
+}
+
+#[test]
+fn creates_gitea_pull_request() {
+    let (api, server) =
+        serve_once(r#"{"number": 12, "html_url": "https://codeberg.org/jssblck/anna/pulls/12"}"#);
+    let repository = "git@codeberg.org:jssblck/anna.git"
+        .parse::<Repository>()
+        .expect("repository");
+    let gitea = Gitea::new(api, "secret");
+    assert_eq!(gitea.kind(), ForgeKind::Gitea);
+
+    let pr = gitea
+        .create_pull_request(&repository, &draft())
+        .expect("create pull request");
+    assert_eq!(pr.number, 12);
+
+    let (head, body) = server.join().expect("server");
+    assert!(
+        head[0].starts_with("POST /repos/jssblck/anna/pulls "),
+        "{}",
+        head[0]
+    );
+    assert!(head
+        .iter()
+        .any(|line| line.to_lowercase() == "authorization: token secret"));
+    assert_eq!(body["head"], "feature");
+    assert_eq!(body["base"], "main");
+}
+
+#[test]
+fn parses_issue_references() {
+    for (reference, repository, number) in [
+        ("12", None, 12),
+        ("#12", None, 12),
+        (
+            "https://github.com/jssblck/anna/issues/12",
+            Some("jssblck/anna"),
+            12,
+        ),
+        (
+            "https://gitlab.com/group/project/-/issues/4/",
+            Some("group/project"),
+            4,
+        ),
+        (
+            "https://codeberg.org/jssblck/anna/issues/9",
+            Some("jssblck/anna"),
+            9,
+        ),
+    ] {
+        let parsed = reference
+            .parse::<IssueReference>()
+            .expect("parse issue reference");
+        assert_eq!(parsed.number, number, "{reference}");
+        assert_eq!(
+            parsed.repository.map(|repository| repository.path),
+            repository.map(String::from),
+            "{reference}"
+        );
+    }
+
+    for reference in ["", "issue", "https://github.com/jssblck/anna/issues/x"] {
+        assert!(
+            reference.parse::<IssueReference>().is_err(),
+            "{reference:?} should be rejected"
+        );
+    }
+}
+
+#[test]
+fn derives_branch_and_prompt_from_issue() {
+    let issue = Issue {
+        number: 12,
+        title: String::from("Crash on load: config with [brackets] can't be parsed at all"),
+        body: String::from("Steps to reproduce...\n"),
+        url: String::from("https://github.com/jssblck/anna/issues/12"),
+    };
+    assert_eq!(
+        issue.branch().expect("branch").as_str(),
+        "issue-12-crash-on-load-config-with-brackets"
+    );
+    assert_eq!(
+        issue.prompt(),
+        "Crash on load: config with [brackets] can't be parsed at all\n\n\
+         Steps to reproduce...\n\n\
+         Resolve issue #12: https://github.com/jssblck/anna/issues/12"
+    );
+}
+
+#[test]
+fn fetches_issues() {
+    let repository = "https://example.com/group/project"
+        .parse::<Repository>()
+        .expect("repository");
+    let github = |api: String| Box::new(GitHub::new(api, "secret")) as Box<dyn Forge>;
+    let gitlab = |api: String| Box::new(GitLab::new(api, "secret")) as Box<dyn Forge>;
+    let gitea = |api: String| Box::new(Gitea::new(api, "secret")) as Box<dyn Forge>;
+    for (connect, response, path) in [
+        (
+            github as fn(String) -> Box<dyn Forge>,
+            r#"{"title": "Crash", "body": "Details", "html_url": "https://example.com/issue"}"#,
+            "/repos/group/project/issues/12",
+        ),
+        (
+            gitlab,
+            r#"{"title": "Crash", "description": "Details", "web_url": "https://example.com/issue"}"#,
+            "/projects/group%2Fproject/issues/12",
+        ),
+        (
+            gitea,
+            r#"{"title": "Crash", "body": "Details", "html_url": "https://example.com/issue"}"#,
+            "/repos/group/project/issues/12",
+        ),
+    ] {
+        let (api, server) = serve_once(response);
+        let forge = connect(api);
+        let issue = forge.issue(&repository, 12).expect("fetch issue");
+        assert_eq!(
+            issue,
+            Issue {
+                number: 12,
+                title: String::from("Crash"),
+                body: String::from("Details"),
+                url: String::from("https://example.com/issue"),
+            }
+        );
+
+        let (head, _) = server.join().expect("server");
+        assert!(
+            head[0].starts_with(&format!("GET {path} ")),
+            "{}: {}",
+            forge.kind(),
+            head[0]
+        );
+    }
+}
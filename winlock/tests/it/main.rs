@@ -0,0 +1,107 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{env, ffi::OsStr, fs, path::Path, process::Command};
+
+use tempfile::TempDir;
+
+mod agent;
+mod backend;
+mod branch;
+mod config;
+mod credentials;
+#[cfg(unix)]
+mod daemon;
+#[cfg(feature = "dashboard")]
+mod dashboard;
+mod devcontainer;
+mod environment;
+mod events;
+#[cfg(feature = "forge")]
+mod forge;
+mod mcp;
+mod multiplexer;
+#[cfg(unix)]
+mod network;
+#[cfg(feature = "notify")]
+mod notify;
+mod prompt;
+mod queue;
+mod remote;
+mod review;
+mod session;
+mod snapshot;
+mod test_util;
+mod toolchain;
+#[cfg(feature = "pty")]
+mod transcript;
+mod usage;
+mod vcs;
+mod workflow;
+mod workspace;
+
+/// Set in the child processes tests are run again in by [`with_env`].
+const CHILD_VARIABLE: &str = "WINLOCK_TEST_CHILD";
+
+/// Run the test called `name` again in a child process with `vars` set, or unset where
+/// they're `None`, returning whether this is the child, which is what does the test.
+///
+/// The environment is shared by every thread of a process, so setting variables while
+/// other tests run would race with them reading it, not least to start programs.
+fn with_env(name: &str, vars: &[(&str, Option<&OsStr>)]) -> bool {
+    if env::var_os(CHILD_VARIABLE).is_some() {
+        return true;
+    }
+    let mut command = Command::new(env::current_exe().expect("find test binary"));
+    command
+        .args([name, "--exact", "--test-threads=1"])
+        .env(CHILD_VARIABLE, "1");
+    for (name, value) in vars {
+        match value {
+            Some(value) => command.env(name, value),
+            None => command.env_remove(name),
+        };
+    }
+    let output = command.output().expect("run test in child process");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success() && stdout.contains("1 passed"),
+        "{name} failed in a child process:\n{stdout}{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    false
+}
+
+/// Run `git` in `dir`, panicking if it fails.
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .expect("run git");
+    assert!(
+        output.status.success(),
+        "git {args:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// Create a git repository with a single commit.
+fn git_project() -> TempDir {
+    let project = TempDir::new().expect("create project dir");
+    git(project.path(), &["init", "--initial-branch=main"]);
+    git(project.path(), &["config", "user.name", "Test"]);
+    git(
+        project.path(),
+        &["config", "user.email", "test@example.com"],
+    );
+    fs::write(project.path().join("README.md"), "hello\n").expect("write readme");
+    fs::write(project.path().join(".gitignore"), "target/\n").expect("write gitignore");
+    fs::create_dir(project.path().join("target")).expect("create target");
+    fs::write(project.path().join("target/artifact"), "built").expect("write artifact");
+    git(project.path(), &["add", "."]);
+    git(project.path(), &["commit", "-m", "initial"]);
+    project
+}
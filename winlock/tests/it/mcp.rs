@@ -0,0 +1,122 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{fs, slice};
+
+use serde_json::{json, Value};
+use winlock::{
+    jsonrpc::{Request, Response},
+    mcp::Server,
+    session::Session,
+    test_util::FakeStore,
+};
+
+use crate::{git, git_project};
+
+/// Call the tool `name` with `arguments`, returning its text and whether it failed.
+fn call_tool(server: &Server, name: &str, arguments: Value) -> (String, bool) {
+    let request = Request {
+        jsonrpc: String::from("2.0"),
+        id: Some(Value::from(1)),
+        method: String::from("tools/call"),
+        params: json!({ "name": name, "arguments": arguments }),
+    };
+    let response = server.handle(request).expect("requests get responses");
+    let result = response.result.expect("tools answer");
+    let text = result["content"][0]["text"].as_str().expect("text content");
+    (String::from(text), result["isError"] == true)
+}
+
+#[test]
+fn initializes_and_lists_tools() {
+    let store = FakeStore::new().expect("open store");
+    let server = Server::new((*store).clone());
+    let input = [
+        r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-03-26","capabilities":{},"clientInfo":{"name":"test","version":"1"}}}"#,
+        r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"tools/list"}"#,
+        r#"{"jsonrpc":"2.0","id":3,"method":"resources/list"}"#,
+    ]
+    .join("\n");
+    let mut output = Vec::new();
+    server
+        .serve(input.as_bytes(), &mut output)
+        .expect("serve requests");
+
+    let responses = String::from_utf8(output)
+        .expect("utf-8 output")
+        .lines()
+        .map(|line| serde_json::from_str::<Response>(line).expect("parse response"))
+        .collect::<Vec<_>>();
+    assert_eq!(responses.len(), 3, "notifications get no response");
+    let initialized = responses[0].result.as_ref().expect("initialized");
+    assert_eq!(initialized["protocolVersion"], "2025-03-26");
+    assert_eq!(initialized["serverInfo"]["name"], "anna");
+
+    let tools = responses[1].result.as_ref().expect("tools listed")["tools"]
+        .as_array()
+        .expect("tools")
+        .iter()
+        .map(|tool| tool["name"].as_str().expect("tool name"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        tools,
+        [
+            "list_sessions",
+            "create_session",
+            "diff_session",
+            "merge_session"
+        ]
+    );
+    assert_eq!(responses[2].id, 3);
+    assert!(responses[2].error.is_some());
+}
+
+#[test]
+fn creates_diffs_and_merges_sessions() {
+    let project = git_project();
+    let store = FakeStore::new().expect("open store");
+    let server = Server::new((*store).clone());
+    let target = json!({ "project": project.path(), "branch": "feature" });
+
+    let (created, failed) = call_tool(&server, "create_session", target.clone());
+    assert!(!failed, "{created}");
+    let session = serde_json::from_str::<Session>(&created).expect("parse session");
+    let (listed, _) = call_tool(
+        &server,
+        "list_sessions",
+        json!({ "project": project.path() }),
+    );
+    assert_eq!(
+        serde_json::from_str::<Vec<Session>>(&listed).expect("parse sessions"),
+        slice::from_ref(&session)
+    );
+
+    fs::write(session.workspace.join("README.md"), "agent\n").expect("write agent work");
+    let (diff, _) = call_tool(&server, "diff_session", target.clone());
+    assert!(diff.contains("+agent"), "{diff}");
+
+    let (refused, failed) = call_tool(
+        &server,
+        "merge_session",
+        json!({ "project": project.path(), "branch": "feature", "remove": true }),
+    );
+    assert!(failed);
+    assert!(refused.contains("uncommitted"), "{refused}");
+
+    git(&session.workspace, &["commit", "-am", "agent work"]);
+    let commit = git(&session.workspace, &["rev-parse", "HEAD"]);
+    let (merged, failed) = call_tool(
+        &server,
+        "merge_session",
+        json!({ "project": project.path(), "branch": "feature", "remove": true }),
+    );
+    assert!(!failed, "{merged}");
+    assert_eq!(git(project.path(), &["rev-parse", "feature"]), commit);
+    assert!(!session.workspace.exists());
+
+    let (missing, failed) = call_tool(&server, "diff_session", target);
+    assert!(failed);
+    assert!(missing.contains("no session"), "{missing}");
+}
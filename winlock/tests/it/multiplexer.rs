@@ -0,0 +1,31 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs;
+
+use tempfile::TempDir;
+use winlock::{config::Config, multiplexer::MultiplexerKind};
+
+#[test]
+fn parses_multiplexers() {
+    for kind in MultiplexerKind::ALL {
+        assert_eq!(
+            kind.to_string().parse::<MultiplexerKind>().expect("parse"),
+            kind
+        );
+        assert_eq!(kind.multiplexer().kind(), kind);
+    }
+    assert!("byobu".parse::<MultiplexerKind>().is_err());
+}
+
+#[test]
+fn multiplexer_is_configurable() {
+    assert_eq!(Config::default().multiplexer, MultiplexerKind::Tmux);
+
+    let dir = TempDir::new().expect("create config dir");
+    let path = dir.path().join("config.toml");
+    fs::write(&path, "multiplexer = \"zellij\"\n").expect("write config");
+    let config = Config::load_from(&path).expect("load config");
+    assert_eq!(config.multiplexer, MultiplexerKind::Zellij);
+}
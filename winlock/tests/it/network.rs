@@ -0,0 +1,164 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    os::unix::net::UnixStream,
+    thread,
+};
+
+use winlock::{
+    backend::ArgsTemplate,
+    config::Profile,
+    network::{self, NetworkPolicy, Proxy},
+    vcs::VcsKind,
+    RunOptions,
+};
+
+use crate::{agent::create_agent, git_project};
+
+fn policy(allow: &[&str]) -> NetworkPolicy {
+    NetworkPolicy {
+        allow: allow.iter().copied().map(String::from).collect(),
+    }
+}
+
+/// Serve `hello` over HTTP on a local port until the test exits, returning the port.
+fn serve_hello() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let port = listener.local_addr().expect("address").port();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            let mut reader = BufReader::new(stream.try_clone().expect("clone stream"));
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                line.clear();
+            }
+            let _ = stream.write_all(
+                b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+            );
+        }
+    });
+    port
+}
+
+#[test]
+fn policy_matches_hosts() {
+    let policy = policy(&["api.anthropic.com", "*.crates.io"]);
+    for host in [
+        "api.anthropic.com",
+        "API.Anthropic.com",
+        "api.anthropic.com.",
+        "static.crates.io",
+        "a.b.crates.io",
+    ] {
+        assert!(policy.allows(host), "{host} should be allowed");
+    }
+    for host in [
+        "anthropic.com",
+        "evil.api.anthropic.com",
+        "crates.io",
+        "evilcrates.io",
+        "example.com",
+    ] {
+        assert!(!policy.allows(host), "{host} should be denied");
+    }
+}
+
+#[test]
+fn proxy_enforces_policy() {
+    let port = serve_hello();
+
+    let proxy = Proxy::start(policy(&["127.0.0.1"])).expect("start proxy");
+    let mut stream = UnixStream::connect(proxy.socket()).expect("connect to proxy");
+    write!(
+        stream,
+        "CONNECT 127.0.0.1:{port} HTTP/1.1\r\nHost: 127.0.0.1:{port}\r\n\r\nGET / HTTP/1.1\r\n\r\n"
+    )
+    .expect("send request");
+    let mut response = String::new();
+    stream.read_to_string(&mut response).expect("read response");
+    assert!(
+        response.starts_with("HTTP/1.1 200 Connection Established\r\n\r\nHTTP/1.1 200 OK"),
+        "{response}"
+    );
+    assert!(response.ends_with("hello"), "{response}");
+    assert!(proxy.denied().is_empty());
+
+    let mut stream = UnixStream::connect(proxy.socket()).expect("connect to proxy");
+    write!(
+        stream,
+        "GET http://localhost:{port}/ HTTP/1.1\r\nHost: localhost\r\n\r\n"
+    )
+    .expect("send request");
+    let mut response = String::new();
+    stream.read_to_string(&mut response).expect("read response");
+    assert!(response.starts_with("HTTP/1.1 403 Forbidden"), "{response}");
+    assert_eq!(proxy.denied(), ["localhost"]);
+}
+
+#[test]
+fn isolates_backend() {
+    // Some systems disable the unprivileged user namespaces isolation needs.
+    if let Err(err) = network::check() {
+        eprintln!("skipping: network isolation is unavailable: {err:#}");
+        return;
+    }
+
+    let port = serve_hello();
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let script = format!(
+        "curl -sf -p http://127.0.0.1:{port}/ > proxied; \
+         curl -sf --noproxy '*' --max-time 2 http://127.0.0.1:{port}/ > direct || echo blocked > direct; \
+         curl -sf -p http://localhost:{port}/; true"
+    );
+    let profile = Profile {
+        command: shell_words::join(["sh", "-c", &script])
+            .parse()
+            .expect("command template"),
+        resume: None,
+        model: None,
+        model_flag: None,
+        hosts: vec![String::from("127.0.0.1")],
+        usage: None,
+        run_args_created: ArgsTemplate::default(),
+        run_args_resumed: ArgsTemplate::default(),
+        install: None,
+        notify: None,
+        name_branch: None,
+        review: None,
+        env: BTreeMap::new(),
+    };
+
+    let ptys = if cfg!(feature = "pty") {
+        &[false, true][..]
+    } else {
+        &[false][..]
+    };
+    for &pty in ptys {
+        let options = RunOptions {
+            pty,
+            network: Some(NetworkPolicy {
+                allow: profile.hosts.clone(),
+            }),
+            ..RunOptions::default()
+        };
+        let outcome = agent.run(&profile, &options).expect("run backend");
+        assert!(outcome.status.success(), "pty: {pty}");
+        assert_eq!(outcome.denied_hosts, ["localhost"], "pty: {pty}");
+
+        let read = |name| fs::read_to_string(agent.workspace().join(name)).expect("read output");
+        assert_eq!(read("proxied"), "hello", "pty: {pty}");
+        assert_eq!(read("direct"), "blocked\n", "pty: {pty}");
+    }
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
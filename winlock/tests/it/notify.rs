@@ -0,0 +1,163 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    path::PathBuf,
+    thread,
+};
+
+use winlock::{
+    branch::BranchName,
+    config::{Config, Profile},
+    notify::{Notification, NotifyConfig},
+    vcs::VcsKind,
+    RunOptions,
+};
+
+use crate::{agent::create_agent, git_project};
+
+#[test]
+fn profile_notify_settings_override_global() {
+    let config = toml::from_str::<Config>(
+        r#"
+        [notify]
+        desktop = true
+        slack = "https://hooks.slack.com/services/global"
+
+        [profiles.quiet]
+        command = "true"
+        notify = { desktop = false, webhook = "https://example.com/hook" }
+        "#,
+    )
+    .expect("parse config");
+
+    let quiet = config.notify(config.profile(Some("quiet")).expect("profile"));
+    assert_eq!(
+        quiet,
+        NotifyConfig {
+            desktop: Some(false),
+            webhook: Some(String::from("https://example.com/hook")),
+            slack: Some(String::from("https://hooks.slack.com/services/global")),
+        }
+    );
+    assert!(quiet.enabled());
+
+    let plain = toml::from_str::<Profile>(r#"command = "true""#).expect("parse profile");
+    assert_eq!(config.notify(&plain), config.notify);
+    assert!(!Config::default().notify.enabled());
+}
+
+#[test]
+fn posts_finished_runs_to_webhooks() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let profile = toml::from_str::<Profile>(r#"command = "sh -c 'echo change > new.txt; exit 3'""#)
+        .expect("parse profile");
+    let options = RunOptions {
+        auto_commit: true,
+        ..RunOptions::default()
+    };
+    let outcome = agent.run(&profile, &options).expect("run backend");
+
+    let notification = Notification::new(agent.session(), &outcome);
+    assert!(!notification.success);
+    assert_eq!(notification.code, Some(3));
+    assert!(notification
+        .diff_stat
+        .as_deref()
+        .is_some_and(|diff_stat| diff_stat.contains("new.txt")));
+    assert!(
+        notification.summary().starts_with("'feature' failed in "),
+        "{}",
+        notification.summary()
+    );
+
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let url = format!("http://{}/hook", listener.local_addr().expect("address"));
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut reader = BufReader::new(stream.try_clone().expect("clone stream"));
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).expect("read header");
+            let line = line.trim_end().to_lowercase();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("content-length:") {
+                length = value.trim().parse().expect("content length");
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).expect("read body");
+        write!(
+            stream,
+            "HTTP/1.1 204 No Content\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        )
+        .expect("respond");
+        serde_json::from_slice::<Notification>(&body).expect("parse notification")
+    });
+
+    let config = NotifyConfig {
+        webhook: Some(url),
+        ..NotifyConfig::default()
+    };
+    let errors = notification.send(&config);
+    assert!(errors.is_empty(), "{errors:?}");
+    assert_eq!(server.join().expect("server"), notification);
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn keeps_webhook_urls_out_of_errors() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let address = listener.local_addr().expect("address");
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("accept");
+        let mut reader = BufReader::new(stream.try_clone().expect("clone stream"));
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).expect("read header");
+            if line.trim_end().is_empty() {
+                break;
+            }
+        }
+        write!(
+            stream,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        )
+        .expect("respond");
+    });
+
+    let notification = Notification {
+        project: PathBuf::from("/project"),
+        branch: "feature".parse::<BranchName>().expect("branch"),
+        workspace: PathBuf::from("/workspace"),
+        success: true,
+        code: Some(0),
+        verified: None,
+        diff_stat: None,
+        duration_ms: 0,
+    };
+    let config = NotifyConfig {
+        webhook: Some(format!(
+            "http://user:password@{address}/services/secret-token"
+        )),
+        ..NotifyConfig::default()
+    };
+    let errors = notification.send(&config);
+    server.join().expect("server");
+    let [error] = errors.as_slice() else {
+        panic!("expected one error: {errors:?}");
+    };
+    let message = format!("{error:#}");
+    assert!(message.contains(&address.to_string()), "{message}");
+    assert!(!message.contains("secret-token"), "{message}");
+    assert!(!message.contains("password"), "{message}");
+}
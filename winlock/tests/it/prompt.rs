@@ -0,0 +1,93 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{collections::BTreeMap, fs};
+
+use tempfile::TempDir;
+use winlock::{
+    branch::BranchName,
+    prompt::{self, PromptTemplate, PROJECT_PROMPTS},
+};
+
+use crate::git_project;
+
+fn branch() -> BranchName {
+    "fix/issue-1234".parse().expect("branch")
+}
+
+#[test]
+fn renders_user_and_builtin_variables() {
+    let project = git_project();
+    fs::write(project.path().join("Cargo.toml"), "[package]").expect("write manifest");
+    let template = PromptTemplate::from(String::from(
+        "Fix issue #{{issue}} on {{ branch }} in this {{project.language}} project.",
+    ));
+    let vars = BTreeMap::from([(String::from("issue"), String::from("1234"))]);
+
+    let rendered = template
+        .render(project.path(), &branch(), &vars)
+        .expect("render");
+    assert_eq!(
+        rendered,
+        "Fix issue #1234 on fix/issue-1234 in this Rust project."
+    );
+}
+
+#[test]
+fn reports_missing_variables() {
+    let project = git_project();
+    let template = PromptTemplate::from(String::from("{{issue}} {{severity}} {{branch}}"));
+
+    let err = template
+        .render(project.path(), &branch(), &BTreeMap::new())
+        .expect_err("variables are missing");
+    let message = format!("{err:#}");
+    assert!(message.contains("{{issue}}") && message.contains("{{severity}}"));
+    assert!(!message.contains("{{branch}}"));
+}
+
+#[test]
+fn rejects_unclosed_variables() {
+    let template = PromptTemplate::from(String::from("Fix {{issue"));
+    assert!(template.variables().is_err());
+}
+
+#[test]
+fn loads_templates_by_name() {
+    let dir = TempDir::new().expect("create prompts dir");
+    fs::write(dir.path().join("bugfix.md"), "Fix {{issue}}").expect("write template");
+
+    let template = PromptTemplate::load_from(dir.path(), "bugfix").expect("load template");
+    assert_eq!(
+        template.variables().expect("variables"),
+        [String::from("issue")].into_iter().collect()
+    );
+    assert!(PromptTemplate::load_from(dir.path(), "missing").is_err());
+}
+
+#[test]
+fn loads_project_templates() {
+    let project = TempDir::new().expect("create project dir");
+    let dir = project.path().join(PROJECT_PROMPTS);
+    fs::create_dir_all(&dir).expect("create prompts dir");
+    fs::write(dir.join("review.md"), "Review {{branch}}").expect("write template");
+
+    let template =
+        PromptTemplate::load_for_project(project.path(), "review").expect("load template");
+    assert_eq!(
+        template.variables().expect("variables"),
+        [String::from("branch")].into_iter().collect()
+    );
+}
+
+#[test]
+fn summarizes_tree_without_ignored_files() {
+    let project = git_project();
+    fs::create_dir(project.path().join("src")).expect("create src");
+    fs::write(project.path().join("src/main.rs"), "").expect("write main");
+
+    let tree = prompt::summarize_tree(project.path()).expect("summarize tree");
+    let lines = tree.lines().collect::<Vec<_>>();
+    assert_eq!(lines, ["README.md", "src/", "src/main.rs"]);
+}
@@ -0,0 +1,97 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    num::NonZero,
+    path::Path,
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+use color_eyre::eyre::eyre;
+use tempfile::TempDir;
+use winlock::queue::{Queue, QueuedRunState};
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| String::from(*arg)).collect()
+}
+
+#[test]
+fn runs_queued_runs_a_few_at_a_time() {
+    let state = TempDir::new().expect("create state dir");
+    let queue = Queue::open_in(state.path()).expect("open queue");
+    let project = Path::new("/src/project");
+    for task in ["one", "two", "three", "four", "five"] {
+        queue
+            .push(project, project, args(&["agent", task]))
+            .expect("queue run");
+    }
+
+    let (running, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    let finished = queue
+        .run(NonZero::new(2).expect("non-zero"), |run| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            running.fetch_sub(1, Ordering::SeqCst);
+            match run.args[1].as_str() {
+                "four" => Err(eyre!("no such program")),
+                // Fails with a usage error.
+                "five" => Ok(Command::new("git").arg("--nonsense").output()?.status),
+                _ => Ok(Command::new("git").arg("--version").output()?.status),
+            }
+        })
+        .expect("run queue");
+
+    assert_eq!(finished.len(), 5);
+    assert_eq!(most.load(Ordering::SeqCst), 2);
+    let listed = queue.list().expect("list");
+    let states = listed
+        .iter()
+        .map(|run| run.state.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        states,
+        [
+            "succeeded",
+            "succeeded",
+            "succeeded",
+            "failed to start",
+            "failed (129)"
+        ]
+    );
+    assert!(matches!(
+        &listed[3].state,
+        QueuedRunState::Finished { error: Some(error), .. } if error.contains("no such program")
+    ));
+
+    // Nothing is left to run.
+    let finished = queue
+        .run(NonZero::new(2).expect("non-zero"), |_| {
+            panic!("nothing is queued")
+        })
+        .expect("run queue");
+    assert!(finished.is_empty());
+}
+
+#[test]
+fn removes_queued_runs() {
+    let state = TempDir::new().expect("create state dir");
+    let queue = Queue::open_in(state.path()).expect("open queue");
+    let project = Path::new("/src/project");
+    let first = queue
+        .push(project, project, args(&["agent", "one"]))
+        .expect("queue run");
+    let second = queue
+        .push(project, project, args(&["agent", "two"]))
+        .expect("queue run");
+    assert_eq!((first.id, second.id), (1, 2));
+    assert_eq!(first.state, QueuedRunState::Pending);
+
+    assert_eq!(queue.remove(first.id).expect("remove"), Some(first));
+    assert_eq!(queue.remove(1).expect("remove"), None);
+    assert_eq!(queue.list().expect("list"), [second]);
+}
@@ -0,0 +1,79 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{ffi::OsString, path::Path};
+
+use tempfile::TempDir;
+use winlock::{remote::Remote, session::Sessions, Agent, CreateOptions, LinkedProject};
+
+use crate::git_project;
+
+#[test]
+fn parses_ssh_destinations() {
+    for destination in ["build", "me@build.example.com", "me@10.0.0.2"] {
+        let remote = destination.parse::<Remote>().expect("parse");
+        assert_eq!(remote.as_str(), destination);
+    }
+    for destination in ["", "-oProxyCommand=true", "me@build server", "build:22"] {
+        assert!(
+            destination.parse::<Remote>().is_err(),
+            "{destination:?} parsed"
+        );
+    }
+}
+
+#[test]
+fn runs_backend_in_remote_mirror() {
+    let remote = "me@build".parse::<Remote>().expect("parse");
+    let workspace = Path::new("/tmp/anna/workspaces/project-feature-1234");
+    assert_eq!(
+        remote.dir(workspace).expect("dir"),
+        Path::new(".anna/workspaces/project-feature-1234")
+    );
+
+    let command_line = remote
+        .command_line(
+            workspace,
+            &[String::from("claude"), String::from("fix the 'bug'")],
+            &[("ANNA_TEST", OsString::from("a b"))],
+            true,
+        )
+        .expect("command line");
+    assert_eq!(
+        command_line,
+        [
+            "ssh",
+            "-t",
+            "--",
+            "me@build",
+            ". .anna/workspaces/project-feature-1234.env && rm -f .anna/workspaces/project-feature-1234.env && cd .anna/workspaces/project-feature-1234 && exec claude 'fix the '\\''bug'\\'''",
+        ]
+    );
+    assert_eq!(
+        remote.env_file(workspace).expect("env file"),
+        Path::new(".anna/workspaces/project-feature-1234.env")
+    );
+}
+
+#[test]
+fn refuses_remote_sessions_spanning_projects() {
+    let project = git_project();
+    let other = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let options = CreateOptions {
+        remote: Some("build".parse().expect("parse")),
+        also: vec![LinkedProject::new(other.path())],
+        ..CreateOptions::default()
+    };
+    let err = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &options,
+    )
+    .expect_err("remote session with linked projects");
+    assert!(err.to_string().contains("remote machine"), "{err}");
+    assert!(sessions.list(project.path()).expect("list").is_empty());
+}
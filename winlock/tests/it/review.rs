@@ -0,0 +1,42 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs;
+
+use winlock::{
+    backend::CommandTemplate,
+    review::{self, REVIEW_FILE},
+    vcs::VcsKind,
+};
+
+use crate::{agent::create_agent, git, git_project};
+
+#[test]
+fn writes_reviews_to_the_workspace() {
+    let project = git_project();
+    let (_store, agent) = create_agent(project.path(), VcsKind::Git);
+    // Records what it was asked, from standard input, and approves.
+    let reviewer = "sh -c 'cat > request.txt; echo \"Looks good.\"'"
+        .parse::<CommandTemplate>()
+        .expect("parse template");
+
+    let err = review::review(agent.session(), &reviewer).expect_err("nothing to review");
+    assert!(err.to_string().contains("no changes"), "{err}");
+
+    fs::write(agent.workspace().join("README.md"), "hello, world\n").expect("write readme");
+    let path = review::review(agent.session(), &reviewer).expect("review");
+    assert_eq!(path, agent.workspace().join(REVIEW_FILE));
+    assert_eq!(
+        fs::read_to_string(&path).expect("read review"),
+        "Looks good.\n"
+    );
+    let request = fs::read_to_string(agent.workspace().join("request.txt")).expect("read request");
+    assert!(request.contains("+hello, world"), "{request}");
+
+    // The review isn't part of the work.
+    let status = git(agent.workspace(), &["status", "--porcelain"]);
+    assert!(!status.contains(REVIEW_FILE), "{status}");
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
@@ -0,0 +1,1276 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    slice,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Barrier, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use color_eyre::eyre::eyre;
+use jiff::Timestamp;
+use tempfile::TempDir;
+use uuid::Uuid;
+use winlock::{
+    branch::BranchName,
+    config::Profile,
+    events::{Event, Events},
+    history::HistoryEvent,
+    session::{
+        project_id, AlreadyRunning, LinkedWorkspace, RunRecord, RunningBackend, Session,
+        SessionIter, Sessions, Verification, WorkspaceMarker, WorkspaceState, MARKER, PROJECT_ID,
+    },
+    test_util::MockBackend,
+    vcs::VcsKind,
+    Agent, AgentSessionStatus, CreateOptions, RunOptions,
+};
+
+use crate::{agent::create_agent, git, git_project};
+
+pub fn session(project: &str, branch: &str) -> Session {
+    Session {
+        id: Uuid::new_v4(),
+        project: PathBuf::from(project),
+        branch: branch.parse().expect("branch"),
+        workspace: PathBuf::from(format!("/nonexistent/{branch}")),
+        vcs: VcsKind::Git,
+        base: Some(String::from("abc123")),
+        created_at: Timestamp::now(),
+        refreshed_at: None,
+        adopted: false,
+        archived: false,
+        resume_tokens: BTreeMap::new(),
+        prompt: None,
+        issue: None,
+        verification: None,
+        environment: None,
+        usage: BTreeMap::new(),
+        model: None,
+        linked: Vec::new(),
+        prompts: Vec::new(),
+        resources: Vec::new(),
+        remote: None,
+        group: None,
+        project_id: None,
+        runs: Vec::new(),
+        toolchains: Vec::new(),
+    }
+}
+
+#[test]
+fn stores_and_gets_sessions() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let one = session("/src/a", "one");
+    let two = session("/src/a", "two");
+
+    sessions.store(&one).expect("store one");
+    sessions.store(&two).expect("store two");
+
+    let got = sessions.get(&one.project, &one.branch).expect("get one");
+    assert_eq!(got, Some(one.clone()));
+
+    let mut listed = sessions.list(&one.project).expect("list");
+    listed.sort_by(|a, b| a.branch.cmp(&b.branch));
+    assert_eq!(listed, [one.clone(), two.clone()]);
+
+    let removed = sessions.remove(&one.project, &one.branch).expect("remove");
+    assert_eq!(removed, Some(one.clone()));
+    assert_eq!(sessions.get(&one.project, &one.branch).expect("get"), None);
+    assert_eq!(sessions.list(&one.project).expect("list"), [two]);
+}
+
+#[test]
+fn store_replaces_existing_session() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let original = session("/src/a", "one");
+    let updated = Session {
+        base: Some(String::from("def456")),
+        ..original.clone()
+    };
+
+    sessions.store(&original).expect("store");
+    sessions.store(&updated).expect("store");
+    assert_eq!(sessions.list(&original.project).expect("list"), [updated]);
+}
+
+#[test]
+fn tells_when_sessions_were_last_active() {
+    let at = |time: &str| time.parse::<Timestamp>().expect("timestamp");
+    let created = Session {
+        created_at: at("2024-01-01T00:00:00Z"),
+        ..session("/src/a", "one")
+    };
+    assert_eq!(created.last_active(), created.created_at);
+
+    let active = Session {
+        refreshed_at: Some(at("2024-01-02T00:00:00Z")),
+        runs: vec![RunRecord {
+            backend: String::from("claude"),
+            started_at: at("2024-01-04T00:00:00Z"),
+            code: Some(0),
+            success: true,
+        }],
+        verification: Some(Verification {
+            command: String::from("true"),
+            passed: true,
+            finished_at: at("2024-01-03T00:00:00Z"),
+        }),
+        ..created
+    };
+    assert_eq!(active.last_active(), at("2024-01-04T00:00:00Z"));
+}
+
+#[test]
+fn groups_sessions() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let one = session("/src/a", "one");
+    let two = session("/src/a", "two");
+    sessions.store(&one).expect("store one");
+    sessions.store(&two).expect("store two");
+
+    let grouped = sessions
+        .set_group(&one.project, &one.branch, Some("payments"))
+        .expect("set group")
+        .expect("session exists");
+    assert_eq!(grouped.group.as_deref(), Some("payments"));
+    assert!(sessions
+        .set_group(&one.project, &one.branch, Some("two\tcolumns"))
+        .is_err());
+
+    assert!(sessions.groups(&one.project).expect("groups").is_empty());
+    sessions
+        .describe_group(&one.project, "payments", "Payments refactor")
+        .expect("describe group");
+    let groups = sessions.groups(&one.project).expect("groups");
+    assert_eq!(
+        groups["payments"].description.as_deref(),
+        Some("Payments refactor")
+    );
+    // The record lives alongside the sessions without being mistaken for one.
+    let mut listed = sessions.list(&one.project).expect("list");
+    listed.sort_by(|a, b| a.branch.cmp(&b.branch));
+    assert_eq!(listed, [grouped, two]);
+    assert_eq!(sessions.iter().expect("iter").count(), 2);
+
+    assert!(sessions
+        .forget_group(&one.project, "payments")
+        .expect("forget group"));
+    assert!(sessions.groups(&one.project).expect("groups").is_empty());
+    assert!(!sessions
+        .forget_group(&one.project, "payments")
+        .expect("forget group"));
+}
+
+#[test]
+fn relinks_moved_projects() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let old = Path::new("/nonexistent/old");
+    let new_dir = TempDir::new().expect("create project dir");
+    let new = new_dir.path();
+    let mut one = session("/nonexistent/old", "one");
+    one.project_id = Some(String::from("abc123"));
+    one.group = Some(String::from("payments"));
+    let two = session("/nonexistent/old", "two");
+    sessions.store(&one).expect("store one");
+    sessions.store(&two).expect("store two");
+    sessions.record(&one, HistoryEvent::Resumed);
+    sessions
+        .describe_group(old, "payments", "Payments refactor")
+        .expect("describe group");
+    assert_eq!(
+        sessions.moved("abc123").expect("moved"),
+        [old.to_path_buf()].into()
+    );
+
+    // Nothing moves if any session would replace one of the new project's.
+    let taken = session(&new.to_string_lossy(), "two");
+    sessions.store(&taken).expect("store taken");
+    assert!(sessions.relink(old, new).is_err());
+    assert_eq!(sessions.list(old).expect("list old").len(), 2);
+    sessions.remove(new, &taken.branch).expect("remove taken");
+
+    let mut relinked = sessions.relink(old, new).expect("relink");
+    relinked.sort_by(|a, b| a.branch.cmp(&b.branch));
+    assert_eq!(relinked.len(), 2);
+    assert!(relinked.iter().all(|session| session.project == new));
+    assert_eq!(relinked[0].id, one.id);
+    assert!(sessions.list(old).expect("list old").is_empty());
+    let mut listed = sessions.list(new).expect("list new");
+    listed.sort_by(|a, b| a.branch.cmp(&b.branch));
+    assert_eq!(listed, relinked);
+    assert_eq!(
+        sessions.groups(new).expect("groups")["payments"]
+            .description
+            .as_deref(),
+        Some("Payments refactor")
+    );
+    assert!(sessions.moved("abc123").expect("moved").is_empty());
+
+    // Histories move with their sessions, after what the new project has of the branch.
+    let events = |branch: &BranchName| {
+        let history = sessions.history(new, branch).expect("history");
+        history
+            .into_iter()
+            .map(|entry| (entry.session, entry.event.name()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        events(&one.branch),
+        [(one.id, "resumed"), (one.id, "relinked")]
+    );
+    assert_eq!(
+        events(&two.branch),
+        [(taken.id, "removed"), (two.id, "relinked")]
+    );
+    assert!(sessions
+        .history(old, &one.branch)
+        .expect("history")
+        .is_empty());
+}
+
+#[test]
+fn relinks_workspace_markers() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let (old, new) = (
+        Path::new("/nonexistent/old"),
+        TempDir::new().expect("create project dir"),
+    );
+    let (workspace, linked) = (
+        TempDir::new().expect("create workspace"),
+        TempDir::new().expect("create linked workspace"),
+    );
+    let session = Session {
+        workspace: workspace.path().to_path_buf(),
+        vcs: VcsKind::None,
+        linked: vec![LinkedWorkspace {
+            project: PathBuf::from("/nonexistent/other"),
+            workspace: linked.path().to_path_buf(),
+            vcs: VcsKind::None,
+            base: None,
+        }],
+        ..session(&old.to_string_lossy(), "one")
+    };
+    for dir in [workspace.path(), linked.path()] {
+        session
+            .marker()
+            .write(dir, VcsKind::None)
+            .expect("write marker");
+    }
+    sessions.store(&session).expect("store session");
+
+    sessions.relink(old, new.path()).expect("relink");
+    for dir in [workspace.path(), linked.path()] {
+        let marker = WorkspaceMarker::read(dir)
+            .expect("read marker")
+            .expect("marker");
+        assert_eq!(marker.project, new.path(), "{}", dir.display());
+    }
+}
+
+#[test]
+fn identifies_projects_stably() {
+    let project = git_project();
+    let root = git(project.path(), &["rev-parse", "HEAD"]);
+    fs::write(project.path().join("README.md"), "changed\n").expect("write readme");
+    git(project.path(), &["commit", "-am", "second"]);
+    assert_eq!(
+        project_id(project.path(), VcsKind::Git).expect("project ID"),
+        Some(root)
+    );
+
+    // The file takes precedence, for projects whose history doesn't identify them.
+    fs::create_dir(project.path().join(".anna")).expect("create .anna");
+    fs::write(project.path().join(PROJECT_ID), "payments-service\n").expect("write ID");
+    assert_eq!(
+        project_id(project.path(), VcsKind::Git).expect("project ID"),
+        Some(String::from("payments-service"))
+    );
+
+    let empty = TempDir::new().expect("create empty dir");
+    git(empty.path(), &["init"]);
+    assert_eq!(
+        project_id(empty.path(), VcsKind::Git).expect("project ID"),
+        None
+    );
+}
+
+#[test]
+fn shards_sessions_by_project() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let a = session("/src/a", "feature");
+    let b = session("/src/b", "feature");
+
+    sessions.store(&a).expect("store a");
+    sessions.store(&b).expect("store b");
+    assert_eq!(
+        sessions.list(&a.project).expect("list a"),
+        slice::from_ref(&a)
+    );
+    assert_eq!(
+        sessions.list(&b.project).expect("list b"),
+        slice::from_ref(&b)
+    );
+
+    let shards = fs::read_dir(store.path().join("sessions"))
+        .expect("read sessions dir")
+        .filter_map(|entry| {
+            let path = entry.expect("entry").path();
+            path.is_dir().then_some(path)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(shards.len(), 2);
+
+    let all = sessions.list_all().expect("list all");
+    assert!(all.contains(&a) && all.contains(&b) && all.len() == 2);
+
+    // A damaged shard only affects its own project.
+    let damaged = shards
+        .iter()
+        .map(|shard| shard.join("feature").join("session.json"))
+        .find(|path| fs::read_to_string(path).expect("read").contains("/src/a"))
+        .expect("find session file for a");
+    fs::write(damaged, "{not json").expect("damage session");
+    assert!(sessions.list(&a.project).is_err());
+    assert_eq!(
+        sessions.list(&b.project).expect("list b"),
+        slice::from_ref(&b)
+    );
+    assert_eq!(
+        sessions.list_all().expect("list all"),
+        [b].into_iter().collect()
+    );
+}
+
+#[test]
+fn gets_session_without_reading_others() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let one = session("/src/a", "one");
+    let two = session("/src/a", "feature/two");
+    sessions.store(&one).expect("store one");
+    sessions.store(&two).expect("store two");
+
+    let path = sessions
+        .data_dir(&two.project, &two.branch)
+        .join("session.json");
+    fs::write(path, "{not json").expect("damage session");
+
+    assert_eq!(
+        sessions.get(&one.project, &one.branch).expect("get one"),
+        Some(one)
+    );
+    assert!(sessions.get(&two.project, &two.branch).is_err());
+}
+
+#[test]
+fn migrates_legacy_shards() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let one = session("/src/a", "one");
+    let two = session("/src/a", "two");
+
+    // Earlier versions stored all of a project's sessions in one file
+    // named for the project, alongside its lock.
+    sessions.store(&one).expect("store one");
+    let shard = sessions
+        .data_dir(&one.project, &one.branch)
+        .parent()
+        .expect("shard dir")
+        .to_path_buf();
+    fs::remove_dir_all(&shard).expect("clear shard");
+    let legacy = shard.with_extension("json");
+    let content = serde_json::to_string(&[&one, &two]).expect("serialize");
+    fs::write(&legacy, content).expect("write legacy shard");
+
+    assert_eq!(
+        sessions.get(&two.project, &two.branch).expect("get two"),
+        Some(two.clone())
+    );
+    assert!(!legacy.exists());
+    let mut listed = sessions.list(&one.project).expect("list");
+    listed.sort_by(|a, b| a.branch.cmp(&b.branch));
+    assert_eq!(listed, [one, two]);
+}
+
+#[test]
+fn finds_sessions_by_id() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let one = Session {
+        id: "a1b2c3d4-0000-4000-8000-000000000001"
+            .parse()
+            .expect("uuid"),
+        ..session("/src/a", "feature")
+    };
+    let two = Session {
+        id: "a1b2c3ff-0000-4000-8000-000000000002"
+            .parse()
+            .expect("uuid"),
+        ..session("/src/b", "feature")
+    };
+    sessions.store(&one).expect("store one");
+    sessions.store(&two).expect("store two");
+
+    assert_eq!(one.short_id(), "a1b2c3");
+    let short_ids = sessions.short_ids().expect("short ids");
+    assert_eq!(short_ids[&one.id], "a1b2c3d");
+    assert_eq!(short_ids[&two.id], "a1b2c3f");
+    assert_eq!(
+        sessions.find_by_id("a1b2c3d").expect("find one"),
+        Some(one.clone())
+    );
+    assert_eq!(
+        sessions.find_by_id(&two.id.to_string()).expect("find two"),
+        Some(two)
+    );
+    assert_eq!(
+        sessions.find_by_id("A1B2C3D4").expect("find one"),
+        Some(one)
+    );
+    assert_eq!(sessions.find_by_id("ffffff").expect("find none"), None);
+    // Too short, or not an ID at all.
+    assert_eq!(sessions.find_by_id("a1b2").expect("find none"), None);
+    assert_eq!(sessions.find_by_id("feature").expect("find none"), None);
+
+    let err = sessions.find_by_id("a1b2c3").expect_err("ambiguous");
+    assert!(err.to_string().contains("ambiguous"), "{err}");
+}
+
+#[test]
+fn reads_without_locking() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let one = session("/src/a", "one");
+    let two = session("/src/b", "two");
+    sessions.store(&one).expect("store one");
+
+    // A project in the legacy format is read as it is rather than converted,
+    // since converting it would take the lock.
+    let shard = sessions
+        .data_dir(&two.project, &two.branch)
+        .parent()
+        .expect("shard dir")
+        .to_path_buf();
+    let legacy = shard.with_extension("json");
+    let content = serde_json::to_string(slice::from_ref(&two)).expect("serialize");
+    fs::write(&legacy, content).expect("write legacy shard");
+
+    // Stands in for another process holding the lock, as in `reports_lock_owner`.
+    let lock = fs::File::options()
+        .write(true)
+        .open(
+            sessions
+                .data_dir(&one.project, &one.branch)
+                .parent()
+                .expect("shard dir")
+                .with_extension("lock"),
+        )
+        .expect("open lock");
+    lock.lock().expect("take lock");
+
+    let reader = Sessions::open_read_only_in(store.path());
+    assert!(reader.is_read_only());
+    assert_eq!(
+        reader.list_all().expect("list all"),
+        [one.clone(), two.clone()].into_iter().collect()
+    );
+    assert_eq!(
+        reader.get(&two.project, &two.branch).expect("get two"),
+        Some(two.clone())
+    );
+    assert_eq!(
+        reader.list(&one.project).expect("list"),
+        slice::from_ref(&one)
+    );
+    assert!(legacy.exists());
+
+    let err = reader.store(&two).expect_err("read-only");
+    assert!(err.to_string().contains("read-only"), "{err}");
+
+    let missing = TempDir::new().expect("create dir");
+    let reader = Sessions::open_read_only_in(&missing.path().join("state"));
+    assert!(reader.list_all().expect("list all").is_empty());
+    assert!(!missing.path().join("state").exists());
+}
+
+#[test]
+fn adopts_existing_worktree() {
+    let project = git_project();
+    let worktrees = TempDir::new().expect("create worktree dir");
+    let worktree = worktrees.path().join("wip");
+    git(
+        project.path(),
+        &["worktree", "add", "-b", "wip", &worktree.to_string_lossy()],
+    );
+
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let branch = "wip".parse().expect("branch");
+
+    let adopted = sessions
+        .adopt(project.path(), &branch, &worktree)
+        .expect("adopt worktree");
+    assert!(adopted.adopted);
+    assert_eq!(adopted.vcs, VcsKind::Git);
+    assert_eq!(
+        sessions.get(project.path(), &branch).expect("get"),
+        Some(adopted)
+    );
+
+    assert!(
+        sessions.adopt(project.path(), &branch, &worktree).is_err(),
+        "adopting twice is an error"
+    );
+
+    sessions.remove(project.path(), &branch).expect("remove");
+    assert!(worktree.exists(), "adopted workspaces are kept");
+}
+
+#[test]
+fn adopt_requires_existing_branch() {
+    let project = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let branch = "missing".parse().expect("branch");
+
+    let err = sessions
+        .adopt(project.path(), &branch, project.path())
+        .expect_err("branch does not exist");
+    assert!(format!("{err:#}").contains("does not exist"));
+}
+
+#[test]
+fn finds_session_containing_directory() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let outer = Session {
+        workspace: PathBuf::from("/work/outer"),
+        ..session("/src/a", "outer")
+    };
+    let inner = Session {
+        workspace: PathBuf::from("/work/outer/inner"),
+        ..session("/src/b", "inner")
+    };
+    sessions.store(&outer).expect("store outer");
+    sessions.store(&inner).expect("store inner");
+
+    let found = |dir: &str| {
+        sessions
+            .containing(&PathBuf::from(dir))
+            .expect("find session")
+            .map(|session| session.branch.to_string())
+    };
+    assert_eq!(found("/work/outer"), Some(String::from("outer")));
+    assert_eq!(found("/work/outer/src"), Some(String::from("outer")));
+    assert_eq!(found("/work/outer/inner/src"), Some(String::from("inner")));
+    assert_eq!(found("/work/outermost"), None);
+    assert_eq!(found("/src/a"), None);
+}
+
+#[test]
+fn get_or_create_is_atomic() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let branch = "feature".parse::<BranchName>().expect("branch");
+    let project = PathBuf::from("/src/a");
+    let barrier = Barrier::new(4);
+    let workspaces = Mutex::new(Vec::new());
+
+    let results = thread::scope(|scope| {
+        let handles = (0..4)
+            .map(|_| {
+                scope.spawn(|| {
+                    // Every thread checks for an existing session before any stores one,
+                    // which is exactly the race being guarded.
+                    barrier.wait();
+                    sessions
+                        .get_or_create(&project, &branch, || {
+                            let workspace = TempDir::new().expect("create workspace").keep();
+                            workspaces
+                                .lock()
+                                .expect("lock workspaces")
+                                .push(workspace.clone());
+                            // Slow enough for the others to be waiting by the time it's stored.
+                            thread::sleep(Duration::from_millis(50));
+                            Ok(Session {
+                                workspace,
+                                ..session("/src/a", "feature")
+                            })
+                        })
+                        .expect("get or create")
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("thread panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    let created = results
+        .iter()
+        .filter(|(_, status)| *status == AgentSessionStatus::Created)
+        .count();
+    assert_eq!(created, 1);
+
+    let stored = sessions
+        .get(&project, &branch)
+        .expect("get")
+        .expect("session is stored");
+    for (session, _) in &results {
+        assert_eq!(session, &stored);
+    }
+
+    // Only one workspace was ever copied.
+    let workspaces = workspaces.into_inner().expect("lock workspaces");
+    assert_eq!(workspaces, slice::from_ref(&stored.workspace));
+
+    fs::remove_dir_all(&stored.workspace).expect("remove workspace");
+}
+
+#[test]
+fn reports_lock_owner() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let one = session("/src/a", "one");
+    sessions.store(&one).expect("store");
+
+    assert!(!sessions.is_locked(&one.project).expect("check lock"));
+    assert_eq!(sessions.lock_owner(&one.project).expect("owner"), None);
+    assert!(sessions.lock_owners().expect("owners").is_empty());
+
+    // Stands in for another process holding the lock: flock-style locks
+    // conflict between separately opened files even within one process.
+    let (lock_path, lock) = fs::read_dir(store.path().join("sessions"))
+        .expect("read store")
+        .map(|entry| entry.expect("entry").path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "lock"))
+        .map(|path| {
+            let file = fs::File::options()
+                .write(true)
+                .open(&path)
+                .expect("open lock");
+            (path, file)
+        })
+        .expect("lock file exists");
+    lock.lock().expect("take lock");
+
+    assert!(sessions.is_locked(&one.project).expect("check lock"));
+    let owner = sessions
+        .lock_owner(&one.project)
+        .expect("owner")
+        .expect("owner is recorded");
+    assert_eq!(owner.pid, std::process::id());
+    let owners = sessions.lock_owners().expect("owners");
+    assert_eq!(owners, [(lock_path, Some(owner))]);
+}
+
+#[test]
+fn gathers_details() {
+    let project = git_project();
+    let (_store, agent) = create_agent(project.path(), VcsKind::Git);
+    let workspace = agent.workspace();
+    fs::write(workspace.join("new.txt"), "change").expect("write file");
+    git(workspace, &["add", "new.txt"]);
+    git(workspace, &["commit", "-m", "change"]);
+
+    let details = agent.session().details();
+    assert!(details.workspace_exists);
+    let diff_stat = details.diff_stat.expect("diff stat");
+    assert!(diff_stat.contains("new.txt"), "{diff_stat}");
+    assert!(details.disk_usage.is_some_and(|usage| usage > 0));
+
+    fs::remove_dir_all(workspace).expect("remove workspace");
+    let details = agent.session().details();
+    assert!(!details.workspace_exists);
+    assert_eq!(details.diff_stat, None);
+    assert_eq!(details.disk_usage, None);
+}
+
+#[test]
+fn summarizes_store() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let workspace = TempDir::new().expect("create workspace dir");
+    fs::write(workspace.path().join("file.txt"), "hello").expect("write file");
+
+    let oldest = "2024-01-01T00:00:00Z".parse().expect("timestamp");
+    let missing = Session {
+        created_at: oldest,
+        ..session("/src/a", "missing")
+    };
+    let archived = Session {
+        archived: true,
+        ..session("/src/a", "archived")
+    };
+    let verified = Session {
+        workspace: workspace.path().to_path_buf(),
+        verification: Some(Verification {
+            command: String::from("true"),
+            passed: true,
+            finished_at: Timestamp::now(),
+        }),
+        ..session("/src/b", "verified")
+    };
+    for session in [&missing, &archived, &verified] {
+        sessions.store(session).expect("store");
+    }
+
+    let stats = sessions.stats().expect("stats");
+    assert_eq!(stats.sessions, 3);
+    assert_eq!(stats.running, 0);
+    assert_eq!(stats.oldest, Some(oldest));
+    assert!(stats.disk_usage >= 5, "{}", stats.disk_usage);
+
+    let a = &stats.projects[&PathBuf::from("/src/a")];
+    assert_eq!(a.sessions(), 2);
+    assert_eq!(
+        a.states,
+        BTreeMap::from([(WorkspaceState::Archived, 1), (WorkspaceState::Missing, 1)])
+    );
+    assert_eq!(a.oldest, Some(oldest));
+
+    let b = &stats.projects[&PathBuf::from("/src/b")];
+    assert_eq!(b.states, BTreeMap::from([(WorkspaceState::Verified, 1)]));
+    assert!(b.disk_usage >= 5, "{}", b.disk_usage);
+}
+
+#[test]
+fn reports_running_backends() {
+    let project = git_project();
+    let (store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+    let (project, branch, workspace) = (
+        agent.project().to_path_buf(),
+        agent.branch().clone(),
+        agent.workspace().to_path_buf(),
+    );
+    assert!(!sessions.is_running(&project, &branch).expect("is running"));
+
+    let profile = toml::from_str::<Profile>(
+        r#"command = "sh -c 'touch started; while [ ! -e stop ]; do sleep 0.01; done'""#,
+    )
+    .expect("parse profile");
+    let backend = thread::spawn(move || {
+        agent
+            .run(&profile, &RunOptions::default())
+            .expect("run backend");
+    });
+    while !workspace.join("started").exists() {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(sessions.is_running(&project, &branch).expect("is running"));
+    assert_eq!(sessions.stats().expect("stats").running, 1);
+
+    fs::write(workspace.join("stop"), "").expect("stop backend");
+    backend.join().expect("join backend");
+    assert!(!sessions.is_running(&project, &branch).expect("is running"));
+
+    fs::remove_dir_all(&workspace).expect("remove workspace");
+}
+
+#[test]
+fn holds_the_run_lock_while_verifying() {
+    let project = git_project();
+    let (store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+    let (project, branch, workspace) = (
+        agent.project().to_path_buf(),
+        agent.branch().clone(),
+        agent.workspace().to_path_buf(),
+    );
+
+    let profile = toml::from_str::<Profile>(r#"command = "true""#).expect("parse profile");
+    let options = RunOptions {
+        verify: Some(String::from(
+            "touch verifying; while [ ! -e stop ]; do sleep 0.01; done",
+        )),
+        ..RunOptions::default()
+    };
+    let backend = thread::spawn(move || {
+        agent.run(&profile, &options).expect("run backend");
+    });
+    while !workspace.join("verifying").exists() {
+        thread::sleep(Duration::from_millis(10));
+    }
+    // Removing takes the run lock, which the run still holds.
+    let err = sessions
+        .remove(&project, &branch)
+        .expect_err("the run is still verifying");
+    assert!(err.to_string().contains("is running"), "{err:#}");
+    assert!(sessions.is_running(&project, &branch).expect("is running"));
+
+    fs::write(workspace.join("stop"), "").expect("stop verification");
+    backend.join().expect("join backend");
+    assert!(!sessions.is_running(&project, &branch).expect("is running"));
+
+    fs::remove_dir_all(&workspace).expect("remove workspace");
+}
+
+#[test]
+fn takes_the_run_lock_while_others_check_for_backends() {
+    let project = git_project();
+    let (store, agent) = create_agent(project.path(), VcsKind::Git);
+    let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+    let (dir, project_dir, branch) = (
+        store.dir().to_path_buf(),
+        agent.project().to_path_buf(),
+        agent.branch().clone(),
+    );
+    let done = Arc::new(AtomicBool::new(false));
+    // Checking takes the run lock for a moment, as the dashboard and daemon do
+    // every time they poll, which mustn't be mistaken for a backend running.
+    let checker = thread::spawn({
+        let (done, branch) = (Arc::clone(&done), branch.clone());
+        move || {
+            let sessions = Sessions::open_in(&dir).expect("open sessions");
+            while !done.load(Ordering::Relaxed) {
+                sessions
+                    .running_backend(&project_dir, &branch)
+                    .expect("check running backend");
+            }
+        }
+    });
+    // Removing takes the run lock before the check, whose error keeps the session.
+    let refusals = (0..2000)
+        .map(|_| {
+            sessions
+                .remove_deferred(agent.project(), &branch, |_| Err(eyre!("kept")))
+                .map(|_| ())
+                .expect_err("the check refuses")
+                .to_string()
+        })
+        .filter(|err| err != "kept")
+        .collect::<Vec<_>>();
+    done.store(true, Ordering::Relaxed);
+    checker.join().expect("join checker");
+    assert!(
+        refusals.is_empty(),
+        "{} refused: {:?}",
+        refusals.len(),
+        refusals.first()
+    );
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[test]
+fn refuses_to_run_two_backends_at_once() {
+    let project = git_project();
+    let (store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let (dir, project_dir) = (store.dir().to_path_buf(), project.path().to_path_buf());
+    let refused = Arc::new(Mutex::new(None));
+    let backend = MockBackend::new().on_run({
+        let refused = Arc::clone(&refused);
+        move |_| {
+            let sessions = Sessions::open_in(&dir)?;
+            let options = CreateOptions {
+                vcs: VcsKind::Git,
+                ..CreateOptions::default()
+            };
+            let mut again = Agent::new(&sessions, &project_dir, "feature".parse()?, &options)?;
+            let other = MockBackend::new();
+            let err = again
+                .run(&other.profile(), &other.run_options())
+                .expect_err("a backend is already running");
+            *refused.lock().expect("lock") = err.downcast::<AlreadyRunning>().ok();
+            assert!(other.runs().is_empty());
+            Ok(())
+        }
+    });
+    agent
+        .run(&backend.profile(), &backend.run_options())
+        .expect("run backend");
+
+    let refused = refused.lock().expect("lock").take().expect("refused");
+    assert_eq!(refused.branch, *agent.branch());
+    assert_eq!(refused.backend.pid, Some(std::process::id()));
+    assert!(refused.backend.is_alive());
+    agent
+        .run(&backend.profile(), &backend.run_options())
+        .expect("run again once the first has exited");
+    assert_eq!(backend.runs().len(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn tells_whether_running_backends_are_alive() {
+    let mut exited = std::process::Command::new("true")
+        .spawn()
+        .expect("spawn process");
+    exited.wait().expect("wait for process");
+    let hostname = gethostname::gethostname().to_string_lossy().into_owned();
+    let backend = |pid: Option<u32>, hostname: &str| RunningBackend {
+        pid,
+        hostname: Some(String::from(hostname)),
+        ..RunningBackend::default()
+    };
+
+    assert!(backend(Some(std::process::id()), &hostname).is_alive());
+    assert!(!backend(Some(exited.id()), &hostname).is_alive());
+    assert!(
+        backend(Some(exited.id()), "elsewhere.invalid").is_alive(),
+        "processes on other hosts can't be checked"
+    );
+    assert!(!backend(None, &hostname).is_alive());
+}
+
+#[test]
+fn defers_deleting_removed_sessions() {
+    let project = git_project();
+    let (store, agent) = create_agent(project.path(), VcsKind::Git);
+    let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+    let (project, branch, workspace) = (
+        agent.project().to_path_buf(),
+        agent.branch().clone(),
+        agent.workspace().to_path_buf(),
+    );
+
+    let (removed, removal) = sessions
+        .remove_deferred(&project, &branch, |_| Ok(()))
+        .expect("remove")
+        .expect("session exists");
+    assert_eq!(removed.workspace, workspace);
+    assert_eq!(sessions.get(&project, &branch).expect("get"), None);
+    assert!(workspace.exists());
+
+    // A new session for the branch doesn't share anything with the pending removal.
+    let replacement = Session {
+        project: project.clone(),
+        ..session("/unused", branch.as_str())
+    };
+    sessions.store(&replacement).expect("store replacement");
+    removal.finish().expect("finish removal");
+    assert!(!workspace.exists());
+    assert_eq!(
+        sessions.get(&project, &branch).expect("get"),
+        Some(replacement)
+    );
+}
+
+#[test]
+fn forgets_removed_sessions_before_they_are_deleted() {
+    let project = git_project();
+    let (store, agent) = create_agent(project.path(), VcsKind::Git);
+    let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+    let (project, branch) = (agent.project().to_path_buf(), agent.branch().clone());
+
+    let (_, removal) = sessions
+        .remove_deferred(&project, &branch, |_| Ok(()))
+        .expect("remove")
+        .expect("session exists");
+    assert_eq!(sessions.list(&project).expect("list"), []);
+    assert_eq!(sessions.iter().expect("iter").count(), 0);
+    let data = removal.dirs().last().expect("data directory").to_path_buf();
+    assert!(data.exists());
+
+    // Such as when the removal was interrupted: reopening the store deletes what's left.
+    Sessions::open_in(store.dir()).expect("reopen sessions");
+    assert!(!data.exists());
+    removal.finish().expect("finish removal");
+}
+
+#[test]
+fn keeps_sessions_whose_removal_check_fails() {
+    let project = git_project();
+    let (store, agent) = create_agent(project.path(), VcsKind::Git);
+    let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+    let (project, branch) = (agent.project().to_path_buf(), agent.branch().clone());
+    fs::write(agent.workspace().join("new.txt"), "work").expect("write file");
+
+    let refused = sessions.remove_deferred(&project, &branch, |session| {
+        match session.unsaved_work()?.is_empty() {
+            true => Ok(()),
+            false => Err(eyre!("unsaved work")),
+        }
+    });
+    assert!(refused.is_err());
+    assert!(sessions.get(&project, &branch).expect("get").is_some());
+    assert!(agent.workspace().exists());
+}
+
+#[test]
+fn refuses_to_remove_or_archive_sessions_while_a_backend_runs() {
+    let project = git_project();
+    let (store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+    let (project, branch, workspace) = (
+        agent.project().to_path_buf(),
+        agent.branch().clone(),
+        agent.workspace().to_path_buf(),
+    );
+    let profile = toml::from_str::<Profile>(
+        r#"command = "sh -c 'touch started; while [ ! -e stop ]; do sleep 0.01; done'""#,
+    )
+    .expect("parse profile");
+    let backend = thread::spawn(move || {
+        agent
+            .run(&profile, &RunOptions::default())
+            .expect("run backend");
+    });
+    while !workspace.join("started").exists() {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(sessions.remove(&project, &branch).is_err());
+    assert!(sessions.get(&project, &branch).expect("get").is_some());
+    #[cfg(feature = "archive")]
+    {
+        assert!(sessions.archive(&project, &branch).is_err());
+        assert!(workspace.join("started").exists());
+    }
+
+    fs::write(workspace.join("stop"), "").expect("stop backend");
+    backend.join().expect("join backend");
+    sessions
+        .remove(&project, &branch)
+        .expect("remove")
+        .expect("session exists");
+    assert!(!workspace.exists());
+}
+
+#[test]
+fn leaves_the_project_unlocked_while_merging() {
+    let project = git_project();
+    let (store, agent) = create_agent(project.path(), VcsKind::Git);
+    let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+    let branch = agent.branch().clone();
+    let dir = store.dir().to_path_buf();
+
+    let (_, removal) = sessions
+        .merge_and_remove(project.path(), &branch, |session| {
+            // Updating a session takes its project's lock, which would be waited on
+            // forever if merging held it.
+            let (project, branch) = (session.project.clone(), session.branch.clone());
+            let (updated, update) = mpsc::channel();
+            thread::spawn(move || {
+                let sessions = Sessions::open_in(&dir).expect("open sessions");
+                let result = sessions.update(&project, &branch, |session| {
+                    session.group = Some(String::from("updated"));
+                });
+                let _ = updated.send(result.map(|_| ()));
+            });
+            update
+                .recv_timeout(Duration::from_secs(10))
+                .map_err(|_| eyre!("the project's sessions stayed locked"))?
+        })
+        .expect("merge and remove")
+        .expect("session exists");
+    removal.finish().expect("delete workspace");
+}
+
+#[cfg(unix)]
+#[test]
+fn deletes_removed_sessions_in_the_background() {
+    let project = git_project();
+    let (store, agent) = create_agent(project.path(), VcsKind::Git);
+    let sessions = Sessions::open_in(store.dir()).expect("open sessions");
+    let workspace = agent.workspace().to_path_buf();
+
+    let (_, removal) = sessions
+        .remove_deferred(agent.project(), agent.branch(), |_| Ok(()))
+        .expect("remove")
+        .expect("session exists");
+    removal.spawn().expect("spawn removal");
+    for _ in 0..500 {
+        if !workspace.exists() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    panic!("workspace was not deleted: {}", workspace.display());
+}
+
+#[test]
+fn dates_unrecorded_first_prompt_to_creation() {
+    let mut session = session("/src/a", "one");
+    assert_eq!(session.prompt_history(), []);
+
+    session.prompt = Some(String::from("start"));
+    let history = session.prompt_history();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].prompt, "start");
+    assert_eq!(history[0].given_at, session.created_at);
+}
+
+#[test]
+fn iterates_sessions_with_filters() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let old = Session {
+        created_at: "2024-01-01T00:00:00Z".parse().expect("timestamp"),
+        ..session("/src/a", "old")
+    };
+    let archived = Session {
+        archived: true,
+        ..session("/src/a", "archived")
+    };
+    let other = session("/src/b", "other");
+    for session in [&old, &archived, &other] {
+        sessions.store(session).expect("store");
+    }
+
+    let branches = |iter: SessionIter| {
+        let mut branches = iter
+            .map(|session| session.expect("read session").branch.to_string())
+            .collect::<Vec<_>>();
+        branches.sort();
+        branches
+    };
+    let iter = || sessions.iter().expect("iterate");
+    assert_eq!(branches(iter()), ["archived", "old", "other"]);
+    assert_eq!(
+        branches(iter().project(Path::new("/src/a"))),
+        ["archived", "old"]
+    );
+    assert_eq!(branches(iter().archived(false)), ["old", "other"]);
+    assert_eq!(
+        branches(
+            iter()
+                .project(Path::new("/src/a"))
+                .created_since("2025-01-01T00:00:00Z".parse().expect("timestamp"))
+        ),
+        ["archived"]
+    );
+    assert_eq!(
+        branches(iter().project(Path::new("/src/c"))),
+        Vec::<String>::new()
+    );
+}
+
+#[test]
+fn iteration_continues_past_damaged_sessions() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let damaged = session("/src/a", "damaged");
+    let fine = session("/src/a", "fine");
+    sessions.store(&damaged).expect("store");
+    sessions.store(&fine).expect("store");
+    let path = sessions
+        .data_dir(&damaged.project, &damaged.branch)
+        .join("session.json");
+    fs::write(path, "{not json").expect("damage session");
+
+    let (read, failed) = sessions
+        .iter()
+        .expect("iterate")
+        .partition::<Vec<_>, _>(Result::is_ok);
+    assert_eq!(failed.len(), 1);
+    assert_eq!(
+        read.into_iter()
+            .map(|session| session.expect("read session"))
+            .collect::<Vec<_>>(),
+        slice::from_ref(&fine)
+    );
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    let sessions = sessions.with_events(Events::from_fn({
+        let warnings = Arc::clone(&warnings);
+        move |event| {
+            if let Event::Warning { message } = event {
+                warnings
+                    .lock()
+                    .expect("lock warnings")
+                    .push(message.clone());
+            }
+        }
+    }));
+    assert_eq!(
+        sessions
+            .iter()
+            .expect("iterate")
+            .skip_unreadable()
+            .collect::<Vec<_>>(),
+        [fine]
+    );
+    let warnings = warnings.lock().expect("lock warnings");
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].contains("session.json"), "{warnings:?}");
+}
+
+#[test]
+fn finds_untracked_workspaces() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let root = TempDir::new().expect("create workspace root");
+    let root_path = fs::canonicalize(root.path()).expect("canonicalize");
+    let marked = |name: &str, session: &Session| {
+        let dir = root_path.join(name);
+        fs::create_dir(&dir).expect("create workspace");
+        session
+            .marker()
+            .write(&dir, VcsKind::None)
+            .expect("write marker");
+        dir
+    };
+
+    let tracked = session("/src/a", "tracked");
+    let tracked = Session {
+        workspace: marked("tracked", &tracked),
+        ..tracked
+    };
+    sessions.store(&tracked).expect("store");
+    let removed = marked("removed", &session("/src/a", "removed"));
+    // An old workspace of a branch whose session was since recreated elsewhere.
+    let replaced = marked("replaced", &session("/src/a", "tracked"));
+    fs::create_dir(root_path.join("unmarked")).expect("create directory");
+    fs::create_dir(root_path.join("garbled")).expect("create directory");
+    fs::write(root_path.join("garbled").join(MARKER), "{").expect("write marker");
+
+    let untracked = sessions
+        .untracked_workspaces(root.path())
+        .expect("find untracked workspaces");
+    assert_eq!(
+        untracked
+            .iter()
+            .map(|(dir, _)| dir.clone())
+            .collect::<Vec<_>>(),
+        [removed, replaced]
+    );
+    assert_eq!(
+        WorkspaceMarker::read(&tracked.workspace).expect("read marker"),
+        Some(tracked.marker())
+    );
+}
+
+#[test]
+fn gives_sessions_without_ids_stable_ones() {
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let legacy = Session {
+        id: Uuid::nil(),
+        ..session("/src/a", "legacy")
+    };
+    sessions.store(&legacy).expect("store");
+    let content = fs::read_to_string(
+        sessions
+            .data_dir(&legacy.project, &legacy.branch)
+            .join("session.json"),
+    )
+    .expect("read session");
+    assert!(!content.contains("\"id\""));
+
+    let get = || {
+        sessions
+            .get(&legacy.project, &legacy.branch)
+            .expect("get")
+            .expect("session exists")
+            .id
+    };
+    let id = get();
+    assert!(!id.is_nil());
+    assert_eq!(get(), id);
+    assert_ne!(id, session("/src/a", "other").id);
+}
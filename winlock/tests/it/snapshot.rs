@@ -0,0 +1,212 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+};
+
+use tempfile::TempDir;
+use winlock::{session::Sessions, snapshot::Snapshots, test_util::MockBackend, vcs::VcsKind};
+
+use crate::{agent::create_agent, git, git_project};
+
+#[test]
+fn rolls_back_git_workspace() {
+    let project = git_project();
+    let (store, agent) = create_agent(project.path(), VcsKind::Git);
+    let workspace = agent.workspace();
+    let data = TempDir::new().expect("create data dir");
+    let snapshots = Snapshots::new(data.path());
+
+    fs::write(workspace.join("README.md"), "staged\n").expect("modify readme");
+    git(workspace, &["add", "README.md"]);
+    fs::write(workspace.join("untracked.txt"), "untracked\n").expect("write file");
+    let snapshot = snapshots
+        .create(
+            &store,
+            agent.project(),
+            agent.branch(),
+            Some("before refactor"),
+        )
+        .expect("create snapshot");
+    assert_eq!(snapshot.id, 1);
+    assert!(snapshot.revision.is_some());
+    let status = git(workspace, &["status", "--porcelain"]);
+    assert_eq!(status, "M  README.md\n?? untracked.txt");
+
+    // The risky change: commits, edits, new files, and deleted files.
+    git(workspace, &["add", "--all"]);
+    git(workspace, &["commit", "-m", "risky"]);
+    fs::write(workspace.join("README.md"), "broken\n").expect("modify readme");
+    fs::write(workspace.join("later.txt"), "later\n").expect("write file");
+    fs::remove_file(workspace.join("untracked.txt")).expect("remove file");
+    fs::create_dir(workspace.join("target")).expect("create target");
+    fs::write(workspace.join("target/artifact"), "built").expect("write artifact");
+
+    snapshots
+        .rollback(&store, agent.project(), agent.branch(), snapshot.id)
+        .expect("roll back");
+    assert_eq!(
+        git(workspace, &["rev-parse", "HEAD"]),
+        agent.base().expect("base")
+    );
+    let readme = fs::read_to_string(workspace.join("README.md")).expect("read readme");
+    assert_eq!(readme, "staged\n");
+    assert!(workspace.join("untracked.txt").exists());
+    assert!(!workspace.join("later.txt").exists());
+    assert!(
+        workspace.join("target/artifact").exists(),
+        "ignored files are kept"
+    );
+    assert_eq!(git(workspace, &["branch", "--show-current"]), "feature");
+
+    fs::remove_dir_all(workspace).expect("remove workspace");
+}
+
+#[test]
+fn rolls_back_git_workspace_keeping_withheld_files_hidden() {
+    let project = git_project();
+    fs::write(project.path().join(".env"), "TOKEN=secret\n").expect("write .env");
+    git(project.path(), &["add", "--force", ".env"]);
+    git(project.path(), &["commit", "-m", "commit a secret"]);
+    let (store, agent) = create_agent(project.path(), VcsKind::Git);
+    let workspace = agent.workspace();
+    let data = TempDir::new().expect("create data dir");
+    let snapshots = Snapshots::new(data.path());
+
+    let snapshot = snapshots
+        .create(&store, agent.project(), agent.branch(), None)
+        .expect("create snapshot");
+    let revision = snapshot.revision.expect("stored in git");
+    assert_eq!(
+        git(workspace, &["diff", "--name-status", "HEAD", &revision]),
+        "",
+        "the withheld .env isn't snapshotted as deleted"
+    );
+    fs::write(workspace.join("later.txt"), "later\n").expect("write file");
+
+    snapshots
+        .rollback(&store, agent.project(), agent.branch(), snapshot.id)
+        .expect("roll back");
+    assert!(!workspace.join(".env").exists());
+    assert_eq!(git(workspace, &["status", "--porcelain"]), "");
+
+    fs::remove_dir_all(workspace).expect("remove workspace");
+}
+
+#[test]
+fn rolls_back_plain_workspace() {
+    let project = TempDir::new().expect("create project dir");
+    fs::write(project.path().join("notes.txt"), "original").expect("write file");
+    let (store, agent) = create_agent(project.path(), VcsKind::None);
+    let workspace = agent.workspace();
+    let data = TempDir::new().expect("create data dir");
+    let snapshots = Snapshots::new(data.path());
+
+    let first = snapshots
+        .create(&store, agent.project(), agent.branch(), None)
+        .expect("create snapshot");
+    assert_eq!(first.revision, None);
+    fs::write(workspace.join("notes.txt"), "changed").expect("modify file");
+    fs::create_dir(workspace.join("nested")).expect("create dir");
+    fs::write(workspace.join("nested/new.txt"), "new").expect("write file");
+    let second = snapshots
+        .create(&store, agent.project(), agent.branch(), Some("changed"))
+        .expect("create snapshot");
+    assert_eq!(second.id, 2);
+
+    snapshots
+        .rollback(&store, agent.project(), agent.branch(), 1)
+        .expect("roll back");
+    let notes = fs::read_to_string(workspace.join("notes.txt")).expect("read file");
+    assert_eq!(notes, "original");
+    assert!(!workspace.join("nested").exists());
+
+    snapshots
+        .rollback(&store, agent.project(), agent.branch(), 2)
+        .expect("roll forward");
+    assert!(workspace.join("nested/new.txt").exists());
+
+    let listed = snapshots.list().expect("list snapshots");
+    assert_eq!(listed, [first, second]);
+    assert!(snapshots
+        .rollback(&store, agent.project(), agent.branch(), 3)
+        .is_err());
+
+    fs::remove_dir_all(workspace).expect("remove workspace");
+}
+
+#[test]
+fn refuses_snapshots_while_backends_run() {
+    let project = git_project();
+    let (store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let data = TempDir::new().expect("create data dir");
+    let snapshots = Snapshots::new(data.path());
+    snapshots
+        .create(&store, agent.project(), agent.branch(), None)
+        .expect("create snapshot");
+
+    let (dir, project_dir, branch) = (
+        store.dir().to_path_buf(),
+        agent.project().to_path_buf(),
+        agent.branch().clone(),
+    );
+    let refused = Arc::new(Mutex::new(Vec::new()));
+    let backend = MockBackend::new().on_run({
+        let refused = Arc::clone(&refused);
+        move |_| {
+            let sessions = Sessions::open_in(&dir)?;
+            let created = snapshots.create(&sessions, &project_dir, &branch, None);
+            let rolled_back = snapshots.rollback(&sessions, &project_dir, &branch, 1);
+            let mut refused = refused.lock().expect("lock");
+            refused.extend(created.err().map(|err| format!("{err:#}")));
+            refused.extend(rolled_back.err().map(|err| format!("{err:#}")));
+            Ok(())
+        }
+    });
+    agent
+        .run(&backend.profile(), &backend.run_options())
+        .expect("run backend");
+
+    let refused = refused.lock().expect("lock");
+    assert_eq!(refused.len(), 2, "{refused:?}");
+    for err in refused.iter() {
+        assert!(err.contains("a backend is running"), "{err}");
+    }
+    assert_eq!(
+        Snapshots::new(data.path())
+            .list()
+            .expect("list snapshots")
+            .len(),
+        1
+    );
+
+    fs::remove_dir_all(agent.workspace()).expect("remove workspace");
+}
+
+#[cfg(feature = "archive")]
+#[test]
+fn refuses_snapshots_of_archived_sessions() {
+    let project = git_project();
+    let (store, agent) = create_agent(project.path(), VcsKind::Git);
+    let data = TempDir::new().expect("create data dir");
+    let snapshots = Snapshots::new(data.path());
+    snapshots
+        .create(&store, agent.project(), agent.branch(), None)
+        .expect("create snapshot");
+    store
+        .archive(agent.project(), agent.branch())
+        .expect("archive")
+        .expect("session exists");
+
+    let err = snapshots
+        .create(&store, agent.project(), agent.branch(), None)
+        .expect_err("archived");
+    assert!(err.to_string().contains("archived"), "{err}");
+    let err = snapshots
+        .rollback(&store, agent.project(), agent.branch(), 1)
+        .expect_err("archived");
+    assert!(err.to_string().contains("archived"), "{err}");
+}
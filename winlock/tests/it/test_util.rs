@@ -0,0 +1,102 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{fs, time::Duration};
+
+use color_eyre::eyre::eyre;
+use winlock::{
+    backend::Restart,
+    test_util::{FakeStore, MockBackend},
+    vcs::VcsKind,
+    Agent, CreateOptions, RunOptions,
+};
+
+use crate::{agent::create_agent, git, git_project};
+
+#[test]
+fn mock_backend_records_runs() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let backend = MockBackend::new().on_run(|run| {
+        fs::write(run.workspace.join("notes.txt"), "agent's work")?;
+        Ok(())
+    });
+
+    let options = RunOptions {
+        prompt: Some(String::from("take notes")),
+        auto_commit: true,
+        ..backend.run_options()
+    };
+    let outcome = agent.run(&backend.profile(), &options).expect("run");
+    assert!(outcome.status.success());
+    assert!(
+        outcome.auto_commit.is_some(),
+        "the mock's work is committed"
+    );
+    assert_eq!(git(agent.workspace(), &["status", "--porcelain"]), "");
+    agent
+        .run(&backend.profile(), &backend.run_options())
+        .expect("run again");
+
+    let runs = backend.runs();
+    assert_eq!(runs.len(), 2);
+    assert_eq!(runs[0].command_line, [MockBackend::PROGRAM, "take notes"]);
+    assert_eq!(runs[0].prompt.as_deref(), Some("take notes"));
+    assert_eq!(runs[0].workspace, agent.workspace());
+    let token = &agent.session().resume_tokens[MockBackend::PROGRAM];
+    assert_eq!(
+        runs[1].command_line,
+        [MockBackend::PROGRAM, "--resume", token.as_str()],
+        "the second run continues the conversation"
+    );
+    assert_eq!(agent.session().runs.len(), 2);
+}
+
+#[test]
+fn mock_backend_exits_as_told() {
+    let project = git_project();
+    let (_store, mut agent) = create_agent(project.path(), VcsKind::Git);
+    let backend = MockBackend::new().exiting_with([1, 2]);
+
+    let options = RunOptions {
+        restart: Restart::OnFailure {
+            max: Some(1),
+            backoff: Duration::ZERO,
+        },
+        ..backend.run_options()
+    };
+    let outcome = agent.run(&backend.profile(), &options).expect("run");
+    assert_eq!(outcome.restarts, 1);
+    assert_eq!(outcome.status.code(), Some(2));
+    let outcome = agent
+        .run(&backend.profile(), &backend.run_options())
+        .expect("run again");
+    assert!(outcome.status.success(), "later runs succeed");
+
+    let failing = MockBackend::new().on_run(|_| Err(eyre!("crashed")));
+    let err = agent
+        .run(&failing.profile(), &failing.run_options())
+        .expect_err("run fails");
+    assert!(format!("{err:#}").contains("crashed"), "{err:#}");
+}
+
+#[test]
+fn fake_store_removes_workspaces_when_dropped() {
+    let project = git_project();
+    let store = FakeStore::new().expect("open store");
+    let agent = Agent::new(
+        &store,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &CreateOptions::default(),
+    )
+    .expect("create agent");
+    assert!(store.dir().join("sessions").is_dir());
+    assert_eq!(store.list(project.path()).expect("list").len(), 1);
+
+    let (dir, workspace) = (store.dir().to_path_buf(), agent.workspace().to_path_buf());
+    drop(store);
+    assert!(!workspace.exists());
+    assert!(!dir.exists());
+}
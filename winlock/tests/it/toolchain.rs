@@ -0,0 +1,92 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{env, ffi::OsString, fs, path::PathBuf};
+
+use tempfile::TempDir;
+use winlock::toolchain::{self, Toolchain};
+
+use crate::with_env;
+
+fn var<'a>(vars: &'a [(&str, OsString)], name: &str) -> Option<&'a OsString> {
+    vars.iter()
+        .find(|(variable, _)| *variable == name)
+        .map(|(_, value)| value)
+}
+
+fn path(vars: &[(&str, OsString)]) -> Vec<PathBuf> {
+    env::split_paths(var(vars, "PATH").expect("PATH set")).collect()
+}
+
+#[test]
+fn parses_toolchains() {
+    for toolchain in Toolchain::ALL {
+        assert_eq!(
+            toolchain.to_string().parse::<Toolchain>().expect("parse"),
+            toolchain
+        );
+    }
+    assert!("ruby".parse::<Toolchain>().is_err());
+}
+
+#[test]
+fn isolates_toolchains_in_workspace() {
+    let workspace = TempDir::new().expect("create workspace dir");
+    let workspace = workspace.path();
+
+    let vars = toolchain::env(&[], workspace).expect("env");
+    assert!(vars.is_empty());
+
+    let vars = toolchain::env(&[Toolchain::Cargo, Toolchain::Python], workspace).expect("env");
+    assert_eq!(
+        var(&vars, "CARGO_TARGET_DIR"),
+        Some(&workspace.join("target").into_os_string())
+    );
+    let venv = workspace.join(".venv");
+    assert_eq!(
+        var(&vars, "VIRTUAL_ENV"),
+        Some(&venv.clone().into_os_string())
+    );
+    let bin = venv.join(if cfg!(windows) { "Scripts" } else { "bin" });
+    let path = path(&vars);
+    assert_eq!(path[0], bin, "the virtualenv's programs come first");
+    assert!(path.len() > 1, "the inherited PATH is kept");
+    assert_eq!(Toolchain::Python.exclude(), ["/.venv/"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn uses_node_version_from_nvmrc() {
+    let nvm = TempDir::new().expect("create nvm dir");
+    let vars = [("NVM_DIR", Some(nvm.path().as_os_str()))];
+    if !with_env("toolchain::uses_node_version_from_nvmrc", &vars) {
+        return;
+    }
+    let nvm = PathBuf::from(env::var_os("NVM_DIR").expect("nvm dir variable"));
+    for version in ["v18.17.0", "v18.20.1", "v20.1.0"] {
+        fs::create_dir_all(nvm.join("versions/node").join(version).join("bin"))
+            .expect("create version");
+    }
+    fs::create_dir_all(nvm.join("alias/lts")).expect("create aliases");
+    fs::write(nvm.join("alias/lts/*"), "lts/hydrogen\n").expect("write alias");
+    fs::write(nvm.join("alias/lts/hydrogen"), "v18.17.0\n").expect("write alias");
+
+    let workspace = TempDir::new().expect("create workspace dir");
+    let node = |nvmrc: &str| {
+        fs::write(workspace.path().join(".nvmrc"), nvmrc).expect("write .nvmrc");
+        toolchain::env(&[Toolchain::Node], workspace.path()).map(|vars| path(&vars)[0].clone())
+    };
+    let bin = |version: &str| nvm.join("versions/node").join(version).join("bin");
+    assert_eq!(node("18\n").expect("node 18"), bin("v18.20.1"));
+    assert_eq!(node("v20").expect("node 20"), bin("v20.1.0"));
+    assert_eq!(node("18.17.0").expect("node 18.17.0"), bin("v18.17.0"));
+    assert_eq!(node("lts/*").expect("lts"), bin("v18.17.0"));
+    assert_eq!(node("node").expect("newest"), bin("v20.1.0"));
+    let err = node("16").expect_err("not installed");
+    assert!(format!("{err:#}").contains("isn't installed"));
+
+    fs::remove_file(workspace.path().join(".nvmrc")).expect("remove .nvmrc");
+    let vars = toolchain::env(&[Toolchain::Node], workspace.path()).expect("env");
+    assert!(vars.is_empty(), "nothing to use without .nvmrc");
+}
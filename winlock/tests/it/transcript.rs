@@ -0,0 +1,142 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{fs, time::Duration};
+
+use tempfile::TempDir;
+use winlock::{
+    config::Profile,
+    session::Sessions,
+    transcript::{self, Recorder, Transcript},
+    Agent, CreateOptions, RunOptions,
+};
+
+use crate::git_project;
+
+#[test]
+fn records_and_replays() {
+    let dir = TempDir::new().expect("create dir");
+    let path = dir.path().join("run.cast");
+
+    let mut recorder = Recorder::create(&path, 100, 30, "claude").expect("create recorder");
+    recorder.output(b"hello ").expect("record");
+    // A multi-byte character split across two reads.
+    let snowman = "☃".as_bytes();
+    recorder.output(&snowman[..1]).expect("record");
+    recorder.output(&snowman[1..]).expect("record");
+    recorder.finish().expect("finish");
+
+    let transcript = Transcript::read(&path).expect("read transcript");
+    assert_eq!(
+        (transcript.header.width, transcript.header.height),
+        (100, 30)
+    );
+    assert_eq!(transcript.header.command.as_deref(), Some("claude"));
+    let output = transcript
+        .events
+        .iter()
+        .map(|(_, data)| data.as_str())
+        .collect::<String>();
+    assert_eq!(output, "hello ☃");
+
+    let mut replayed = Vec::new();
+    transcript
+        .replay(&mut replayed, 0.0, Duration::ZERO)
+        .expect("replay");
+    assert_eq!(String::from_utf8(replayed).expect("utf8"), "hello ☃");
+}
+
+#[test]
+fn agent_records_transcripts() {
+    let project = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let mut agent = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &CreateOptions::default(),
+    )
+    .expect("create agent");
+
+    let profile = toml::from_str::<Profile>(r#"command = "sh -c 'printf recorded; exit 3'""#)
+        .expect("parse profile");
+    let options = RunOptions {
+        pty: true,
+        transcript: true,
+        ..RunOptions::default()
+    };
+    let outcome = agent.run(&profile, &options).expect("run backend");
+    assert_eq!(outcome.status.code(), Some(3));
+
+    let transcripts = transcript::list(&agent.transcript_dir()).expect("list transcripts");
+    assert_eq!(transcripts.len(), 1);
+    let recorded = Transcript::read(&transcripts[0]).expect("read transcript");
+    let output = recorded
+        .events
+        .iter()
+        .map(|(_, data)| data.as_str())
+        .collect::<String>();
+    assert!(output.contains("recorded"), "{output:?}");
+
+    sessions
+        .remove(agent.project(), agent.branch())
+        .expect("remove session");
+    assert!(!agent.data_dir().exists());
+    fs::metadata(store.path()).expect("store remains");
+}
+
+#[test]
+fn backend_sees_a_terminal_without_recording() {
+    let project = git_project();
+    let store = TempDir::new().expect("create store dir");
+    let sessions = Sessions::open_in(store.path()).expect("open sessions");
+    let mut agent = Agent::new(
+        &sessions,
+        project.path(),
+        "feature".parse().expect("branch"),
+        &CreateOptions::default(),
+    )
+    .expect("create agent");
+
+    // The test harness's output is captured, so this only passes under a PTY.
+    let profile = toml::from_str::<Profile>(r#"command = "sh -c 'test -t 0 && test -t 1'""#)
+        .expect("parse profile");
+    let options = RunOptions {
+        pty: true,
+        ..RunOptions::default()
+    };
+    let outcome = agent.run(&profile, &options).expect("run backend");
+    assert!(outcome.status.success());
+    assert!(transcript::list(&agent.transcript_dir())
+        .expect("list transcripts")
+        .is_empty());
+
+    sessions
+        .remove(agent.project(), agent.branch())
+        .expect("remove session");
+}
+
+#[test]
+fn skips_resize_events() {
+    let dir = TempDir::new().expect("create dir");
+    let path = dir.path().join("run.cast");
+
+    let mut recorder = Recorder::create(&path, 80, 24, "claude").expect("create recorder");
+    recorder.output(b"before").expect("record");
+    recorder.resize(120, 40).expect("record resize");
+    recorder.output(b"after").expect("record");
+    recorder.finish().expect("finish");
+
+    let raw = fs::read_to_string(&path).expect("read raw transcript");
+    assert!(raw.contains(r#""r","120x40""#), "{raw}");
+
+    let transcript = Transcript::read(&path).expect("read transcript");
+    let output = transcript
+        .events
+        .iter()
+        .map(|(_, data)| data.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(output, ["before", "after"]);
+}
@@ -0,0 +1,64 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs;
+
+use tempfile::TempDir;
+use winlock::usage::{self, ConversationUsage, TokenUsage, UsageSource};
+
+#[test]
+fn reads_claude_logs() {
+    let dir = TempDir::new().expect("create dir");
+    let project = dir.path().join("projects").join("-src-project");
+    fs::create_dir_all(&project).expect("create project dir");
+    let log = [
+        r#"{"type":"user","message":{"role":"user","content":"fix it"}}"#,
+        // Logged once per part of the message, each with the same usage.
+        r#"{"type":"assistant","message":{"id":"msg_1","model":"claude-sonnet-4-5","usage":{"input_tokens":10,"output_tokens":5,"cache_read_input_tokens":100}}}"#,
+        r#"{"type":"assistant","message":{"id":"msg_1","model":"claude-sonnet-4-5","usage":{"input_tokens":10,"output_tokens":5,"cache_read_input_tokens":100}}}"#,
+        r#"{"type":"assistant","message":{"id":"msg_2","model":"claude-sonnet-4-5","usage":{"input_tokens":20,"output_tokens":7}}}"#,
+        r#"{"type":"assistant","message":{"id":"msg_3","model":"claude-haiku-4-5","usage":{"input_tokens":1,"output_tokens":1}}}"#,
+        "not json",
+    ];
+    fs::write(project.join("conversation.jsonl"), log.join("\n")).expect("write log");
+
+    let usage = UsageSource::Claude
+        .read_in(dir.path(), "conversation")
+        .expect("read usage")
+        .expect("conversation is logged");
+    assert_eq!(
+        usage["claude-sonnet-4-5"],
+        TokenUsage {
+            input_tokens: 30,
+            output_tokens: 12,
+            cache_creation_input_tokens: 0,
+            cache_read_input_tokens: 100,
+        }
+    );
+    assert_eq!(usage["claude-haiku-4-5"].total(), 2);
+
+    let missing = UsageSource::Claude
+        .read_in(dir.path(), "other")
+        .expect("read usage");
+    assert_eq!(missing, None);
+}
+
+#[test]
+fn totals_estimated_costs() {
+    let tokens = TokenUsage {
+        input_tokens: 1_000_000,
+        output_tokens: 1_000_000,
+        cache_creation_input_tokens: 0,
+        cache_read_input_tokens: 0,
+    };
+    let known = ConversationUsage::from([(String::from("claude-sonnet-4-5"), tokens)]);
+    let (total, cost) = usage::total([&known, &known]);
+    assert_eq!(total.total(), 4_000_000);
+    assert_eq!(cost, Some(36.0));
+
+    // An unknown price leaves the total unknown rather than understated.
+    let unknown = ConversationUsage::from([(String::from("mystery-model"), tokens)]);
+    let (_, cost) = usage::total([&known, &unknown]);
+    assert_eq!(cost, None);
+}
@@ -0,0 +1,173 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::fs;
+
+use tempfile::TempDir;
+use winlock::{branch::BranchName, vcs::VcsKind};
+
+use crate::{git, git_project};
+
+#[test]
+fn detects_vcs_from_project_root() {
+    let project = TempDir::new().expect("create project dir");
+    assert_eq!(VcsKind::detect(project.path()), VcsKind::None);
+
+    fs::create_dir(project.path().join(".git")).expect("create .git");
+    assert_eq!(VcsKind::detect(project.path()), VcsKind::Git);
+
+    fs::create_dir(project.path().join(".hg")).expect("create .hg");
+    assert_eq!(VcsKind::detect(project.path()), VcsKind::Mercurial);
+
+    // Colocated jj repositories also contain `.git`.
+    fs::create_dir(project.path().join(".jj")).expect("create .jj");
+    assert_eq!(VcsKind::detect(project.path()), VcsKind::Jujutsu);
+}
+
+#[test]
+fn implementations_report_their_kind() {
+    for kind in [
+        VcsKind::Git,
+        VcsKind::Mercurial,
+        VcsKind::Jujutsu,
+        VcsKind::None,
+    ] {
+        assert_eq!(kind.vcs().kind(), kind);
+    }
+}
+
+#[test]
+fn default_branch_prefers_origin_head() {
+    let upstream = git_project();
+    git(upstream.path(), &["branch", "trunk"]);
+    git(
+        upstream.path(),
+        &["symbolic-ref", "HEAD", "refs/heads/trunk"],
+    );
+
+    let clones = TempDir::new().expect("create clone dir");
+    let clone = clones.path().join("clone");
+    git(
+        clones.path(),
+        &["clone", &upstream.path().to_string_lossy(), "clone"],
+    );
+    git(&clone, &["switch", "--create", "topic"]);
+
+    let default = VcsKind::Git
+        .vcs()
+        .default_branch(&clone)
+        .expect("default branch");
+    assert_eq!(default, "trunk");
+}
+
+#[test]
+fn default_branch_falls_back_to_remote_tracking_branch() {
+    let upstream = git_project();
+    let clones = TempDir::new().expect("create clone dir");
+    let clone = clones.path().join("clone");
+    git(
+        clones.path(),
+        &["clone", &upstream.path().to_string_lossy(), "clone"],
+    );
+    git(&clone, &["switch", "--create", "topic"]);
+    git(&clone, &["branch", "--delete", "main"]);
+
+    let vcs = VcsKind::Git.vcs();
+    let default = vcs.default_branch(&clone).expect("default branch");
+    assert_eq!(default, "origin/main");
+    assert_eq!(
+        vcs.resolve(&clone, &default).expect("resolve"),
+        git(upstream.path(), &["rev-parse", "HEAD"])
+    );
+}
+
+#[test]
+fn git_pushes_branch_to_remote() {
+    let project = git_project();
+    let remotes = TempDir::new().expect("create remote dir");
+    let remote = remotes.path().join("remote.git");
+    git(
+        remotes.path(),
+        &["init", "--bare", "--initial-branch=main", "remote.git"],
+    );
+    let url = remote.to_string_lossy();
+    git(project.path(), &["remote", "add", "origin", &url]);
+
+    let vcs = VcsKind::Git.vcs();
+    assert_eq!(
+        vcs.remote_url(project.path(), "origin")
+            .expect("remote url"),
+        url
+    );
+
+    let branch = "feature".parse::<BranchName>().expect("branch");
+    let base = vcs.head(project.path()).expect("head");
+    vcs.create_branch(project.path(), &branch, &base)
+        .expect("create branch");
+    fs::write(project.path().join("new.txt"), "new\n").expect("write file");
+    vcs.commit_all(project.path(), &branch, "add file")
+        .expect("commit");
+    let stat = vcs
+        .diff_stat(project.path(), &base, &branch)
+        .expect("diff stat");
+    assert!(stat.contains("new.txt"), "{stat}");
+
+    vcs.push(project.path(), "origin", &branch).expect("push");
+    let pushed = git(&remote, &["rev-parse", "refs/heads/feature"]);
+    assert_eq!(pushed, vcs.head(project.path()).expect("head"));
+}
+
+#[test]
+fn git_tracks_branches_on_remotes() {
+    let project = git_project();
+    let remotes = TempDir::new().expect("create remote dir");
+    let remote = remotes.path().join("remote.git");
+    git(
+        remotes.path(),
+        &["init", "--bare", "--initial-branch=main", "remote.git"],
+    );
+    git(
+        project.path(),
+        &["remote", "add", "origin", &remote.to_string_lossy()],
+    );
+
+    let vcs = VcsKind::Git.vcs();
+    let branch = "feature".parse::<BranchName>().expect("branch");
+    let base = vcs.head(project.path()).expect("head");
+    vcs.create_branch(project.path(), &branch, &base)
+        .expect("create branch");
+    assert!(vcs
+        .set_upstream(project.path(), "elsewhere", &branch)
+        .is_err());
+    // The remote doesn't have the branch yet; the first plain push creates it.
+    vcs.set_upstream(project.path(), "origin", &branch)
+        .expect("set upstream");
+    git(project.path(), &["push", "--quiet"]);
+    assert_eq!(
+        git(
+            project.path(),
+            &["rev-parse", "--abbrev-ref", "@{upstream}"]
+        ),
+        "origin/feature"
+    );
+    assert_eq!(git(&remote, &["rev-parse", "refs/heads/feature"]), base);
+}
+
+#[test]
+fn git_ignores_paths_once() {
+    let project = git_project();
+    let vcs = VcsKind::Git.vcs();
+    for _ in 0..2 {
+        vcs.ignore(project.path(), "REVIEW.md".as_ref())
+            .expect("ignore");
+    }
+    let exclude =
+        fs::read_to_string(project.path().join(".git/info/exclude")).expect("read exclude");
+    assert_eq!(
+        exclude.lines().filter(|line| *line == "/REVIEW.md").count(),
+        1
+    );
+    fs::write(project.path().join("REVIEW.md"), "review\n").expect("write review");
+    assert_eq!(git(project.path(), &["status", "--porcelain"]), "");
+}
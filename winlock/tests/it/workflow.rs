@@ -0,0 +1,146 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+};
+
+use winlock::{
+    test_util::{FakeStore, MockBackend},
+    workflow::Workflow,
+    AgentSessionStatus, CreateOptions, RunOptions,
+};
+
+use crate::{git, git_project};
+
+#[test]
+fn runs_sessions_from_creation_to_removal() {
+    let project = git_project();
+    let store = FakeStore::new().expect("open store");
+    let backend = MockBackend::new().on_run(|run| {
+        fs::write(run.workspace.join("notes.txt"), "agent's work")?;
+        Ok(())
+    });
+    let steps = Arc::new(Mutex::new(Vec::new()));
+    let step = |name: &'static str| {
+        let steps = Arc::clone(&steps);
+        move || steps.lock().expect("lock").push(name)
+    };
+    let (on_session, on_outcome, on_merged) = (step("session"), step("outcome"), step("merged"));
+
+    let outcome = Workflow::builder()
+        .sessions(&store)
+        .project(project.path())
+        .profile(backend.profile())
+        .run_options(RunOptions {
+            auto_commit: true,
+            ..backend.run_options()
+        })
+        .prompt("Take notes")
+        .auto_merge(true)
+        .remove_after_merge(true)
+        .on_session(move |agent| {
+            assert!(agent.workspace().exists());
+            on_session();
+            Ok(())
+        })
+        .on_outcome(move |_, outcome| {
+            assert!(outcome.auto_commit.is_some());
+            on_outcome();
+            Ok(())
+        })
+        .on_merged(move |_| {
+            on_merged();
+            Ok(())
+        })
+        .run()
+        .expect("run workflow");
+
+    assert_eq!(
+        *steps.lock().expect("lock"),
+        ["session", "outcome", "merged"]
+    );
+    assert_eq!(outcome.status, AgentSessionStatus::Created);
+    assert_eq!(outcome.session.branch.as_str(), "take-notes");
+    assert!(outcome.merged && outcome.removed);
+    assert_eq!(
+        git(project.path(), &["show", "take-notes:notes.txt"]),
+        "agent's work"
+    );
+    assert!(!outcome.session.workspace.exists());
+    assert!(store.list_all().expect("list sessions").is_empty());
+}
+
+#[test]
+fn keeps_sessions_whose_backend_failed() {
+    let project = git_project();
+    let store = FakeStore::new().expect("open store");
+    let backend = MockBackend::new().exiting_with([1]);
+
+    let outcome = Workflow::builder()
+        .sessions(&store)
+        .project(project.path())
+        .branch("feature".parse().expect("branch"))
+        .profile(backend.profile())
+        .run_options(backend.run_options())
+        .auto_merge(true)
+        .remove_after_merge(true)
+        .on_merged(|_| panic!("the branch isn't merged"))
+        .run()
+        .expect("run workflow");
+
+    assert!(!outcome.run.status.success());
+    assert!(!outcome.merged && !outcome.removed);
+    let session = store
+        .get(&outcome.session.project, &outcome.session.branch)
+        .expect("get session")
+        .expect("the session is kept");
+    assert!(session.workspace.exists());
+}
+
+#[test]
+fn keeps_merged_sessions_with_uncommitted_changes() {
+    let project = git_project();
+    let store = FakeStore::new().expect("open store");
+    let backend = MockBackend::new().on_run(|run| {
+        fs::write(run.workspace.join("notes.txt"), "agent's work")?;
+        Ok(())
+    });
+
+    let outcome = Workflow::builder()
+        .sessions(&store)
+        .project(project.path())
+        .branch("feature".parse().expect("branch"))
+        .profile(backend.profile())
+        .create_options(CreateOptions::default())
+        .run_options(backend.run_options())
+        .auto_merge(true)
+        .remove_after_merge(true)
+        .run()
+        .expect("run workflow");
+
+    assert!(outcome.merged && !outcome.removed);
+    assert!(outcome.session.workspace.join("notes.txt").exists());
+    assert!(store
+        .get(&outcome.session.project, &outcome.session.branch)
+        .expect("get session")
+        .is_some());
+    fs::remove_dir_all(&outcome.session.workspace).expect("remove workspace");
+}
+
+#[test]
+fn needs_a_project_and_branch() {
+    let err = Workflow::builder()
+        .prompt("Take notes")
+        .build()
+        .expect_err("no project");
+    assert!(err.to_string().contains("project"), "{err}");
+
+    let err = Workflow::builder()
+        .project("/src/project")
+        .build()
+        .expect_err("no branch");
+    assert!(err.to_string().contains("branch"), "{err}");
+}